@@ -8,10 +8,10 @@ use hotham::{
         begin_frame, begin_pbr_renderpass, end_frame, end_pbr_renderpass, physics_step,
     },
     systems::{
-        animation_system, collision_system, grabbing_system, hands::add_hand, hands_system,
-        rendering::rendering_system, skinning::skinning_system,
+        animation_system, collision_system, comfort_system, grabbing_system, hands::add_hand,
+        hands_system, rendering::rendering_system, skinning::skinning_system,
         update_parent_transform_matrix_system, update_rigid_body_transforms_system,
-        update_transform_matrix_system, Queries,
+        update_transform_matrix_system, vignette_system, Queries,
     },
     Engine, HothamResult,
 };
@@ -41,13 +41,14 @@ fn init(engine: &mut Engine) -> Result<World, hotham::HothamError> {
     let physics_context = &mut engine.physics_context;
     let mut world = World::default();
 
-    let glb_bufs: Vec<&[u8]> = vec![
+    let glb_bufs: Vec<&'static [u8]> = vec![
         include_bytes!("../../../test_assets/left_hand.glb"),
         include_bytes!("../../../test_assets/right_hand.glb"),
         include_bytes!("../../../test_assets/damaged_helmet.glb"),
     ];
-    let models = gltf_loader::load_models_from_glb(
+    let models = gltf_loader::load_models_from_glb_in_parallel(
         &glb_bufs,
+        &engine.job_context,
         &vulkan_context,
         &render_context.descriptor_set_layouts,
     )?;
@@ -111,7 +112,9 @@ fn tick(engine: &mut Engine, world: &mut World, queries: &mut Queries) {
     let vulkan_context = &engine.vulkan_context;
     let render_context = &mut engine.render_context;
     let physics_context = &mut engine.physics_context;
+    let comfort_context = &mut engine.comfort_context;
 
+    comfort_system(comfort_context, xr_context);
     begin_frame(xr_context, vulkan_context, render_context);
     hands_system(&mut queries.hands_query, world, xr_context, physics_context);
     physics_step(physics_context);
@@ -129,7 +132,13 @@ fn tick(engine: &mut Engine, world: &mut World, queries: &mut Queries) {
         &mut queries.roots_query,
         world,
     );
-    skinning_system(&mut queries.joints_query, &mut queries.meshes_query, world);
+    skinning_system(
+        &mut queries.joints_query,
+        &mut queries.meshes_query,
+        world,
+        vulkan_context,
+        render_context,
+    );
     begin_pbr_renderpass(xr_context, vulkan_context, render_context);
     rendering_system(
         &mut queries.rendering_query,
@@ -138,6 +147,12 @@ fn tick(engine: &mut Engine, world: &mut World, queries: &mut Queries) {
         xr_context.frame_index,
         render_context,
     );
+    vignette_system(
+        comfort_context,
+        vulkan_context,
+        xr_context.frame_index,
+        render_context,
+    );
     end_pbr_renderpass(xr_context, vulkan_context, render_context);
     end_frame(xr_context, vulkan_context, render_context);
 }
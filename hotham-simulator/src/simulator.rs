@@ -1381,7 +1381,10 @@ fn create_multiview_images(
     };
     let format = vk::Format::from_raw(create_info.format as _);
     let tiling = vk::ImageTiling::OPTIMAL;
-    let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+    // Hotham blits to the swapchain when its render scale isn't 1.0.
+    let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | vk::ImageUsageFlags::SAMPLED
+        | vk::ImageUsageFlags::TRANSFER_DST;
     let properties = vk::MemoryPropertyFlags::DEVICE_LOCAL;
 
     let create_info = vk::ImageCreateInfo::builder()
@@ -23,6 +23,8 @@ fn main() {
             compile_shader(path, &mut compiler, ShaderKind::Fragment);
         } else if ext == "vert" {
             compile_shader(path, &mut compiler, ShaderKind::Vertex);
+        } else if ext == "comp" {
+            compile_shader(path, &mut compiler, ShaderKind::Compute);
         }
     }
 
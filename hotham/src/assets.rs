@@ -0,0 +1,278 @@
+use std::{collections::HashMap, fmt, hash::Hash, marker::PhantomData};
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::{
+    components::{Material, Mesh},
+    gltf_loader::{load_models_from_glb_with_textures, Models},
+    resources::{render_context::DescriptorSetLayouts, VulkanContext},
+    texture::{get_texture_format, Texture, TextureColorSpace, TextureRole},
+};
+
+/// A cheap, copyable reference to an asset stored in an `AssetStore`
+pub struct Handle<T> {
+    index: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// Implemented by hand, as deriving would require `T` to implement these traits too.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.index).finish()
+    }
+}
+
+/// Storage for assets of a single type, deduplicated by a source key (eg. a file path)
+#[derive(Debug, Clone)]
+pub struct AssetStore<T> {
+    assets: Vec<T>,
+    keys: HashMap<String, Handle<T>>,
+}
+
+impl<T> Default for AssetStore<T> {
+    fn default() -> Self {
+        Self {
+            assets: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<T> AssetStore<T> {
+    /// Get the handle for the asset with this key, calling `load` to create it if it hasn't been stored yet
+    pub fn get_or_load<F>(&mut self, key: &str, load: F) -> Result<Handle<T>>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        if let Some(handle) = self.keys.get(key) {
+            return Ok(*handle);
+        }
+
+        let asset = load()?;
+        let handle = self.add(asset);
+        self.keys.insert(key.to_string(), handle);
+        Ok(handle)
+    }
+
+    /// Store an asset that has no source key. Every call returns a new handle.
+    pub fn add(&mut self, asset: T) -> Handle<T> {
+        self.assets.push(asset);
+        Handle::new(self.assets.len() - 1)
+    }
+
+    /// Get the handle for the asset with this key, if it has been stored
+    pub fn handle(&self, key: &str) -> Option<Handle<T>> {
+        self.keys.get(key).cloned()
+    }
+
+    /// Get the key an asset was stored with, if it has one
+    pub fn key(&self, handle: Handle<T>) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(_, h)| **h == handle)
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// Resolve a handle to its asset
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.assets.get(handle.index)
+    }
+
+    /// Resolve a handle to its asset, mutably
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.assets.get_mut(handle.index)
+    }
+
+    /// The number of assets stored
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// Are there no assets stored?
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+}
+
+/// Meshes, textures and materials shared between entities, deduplicated by the key they were loaded with
+///
+/// Loading the same asset through `Assets` twice returns the same `Handle`, so it's only uploaded to the GPU once.
+/// Models loaded with `load_models_from_glb` load their textures through `textures` too, so a texture shared between
+/// files, or a file loaded twice, is only uploaded once.
+///
+/// The renderer doesn't resolve handles: entities are drawn from their own `Mesh` component, so add a clone of a
+/// stored `Mesh` to share its GPU buffers.
+#[derive(Debug, Clone, Default)]
+pub struct Assets {
+    /// Textures, keyed by path
+    pub textures: AssetStore<Texture>,
+    /// Meshes, keyed by source (eg. `"models.glb#Helmet"`)
+    pub meshes: AssetStore<Mesh>,
+    /// Materials, keyed by source
+    pub materials: AssetStore<Material>,
+}
+
+impl Assets {
+    /// Load the texture at `path` and upload it to the GPU, unless it's already been loaded
+    pub fn load_texture(
+        &mut self,
+        path: &str,
+        vulkan_context: &VulkanContext,
+    ) -> Result<Handle<Texture>> {
+        self.textures
+            .get_or_load(path, || Texture::from_path(path, vulkan_context))
+    }
+
+    /// Load the texture at `path`, used as `role` and in `color_space`, and upload it to the GPU, unless it's already
+    /// been loaded in the same format. See `get_texture_format`.
+    pub fn load_texture_with_color_space(
+        &mut self,
+        path: &str,
+        vulkan_context: &VulkanContext,
+        role: TextureRole,
+        color_space: TextureColorSpace,
+    ) -> Result<Handle<Texture>> {
+        // The same image may be loaded as sRGB and as linear. sRGB is what `load_texture` loads, so it shares its key.
+        let key = match get_texture_format(role, color_space) {
+            vk::Format::R8G8B8A8_SRGB => path.to_string(),
+            format => format!("{}#{:?}", path, format),
+        };
+        self.textures.get_or_load(&key, || {
+            Texture::from_path_with_color_space(path, vulkan_context, role, color_space)
+        })
+    }
+
+    /// Load glTF models from GLB files, as `gltf_loader::load_models_from_glb` does, with their textures loaded
+    /// through `textures`. Textures that have already been loaded are shared rather than uploaded again.
+    pub fn load_models_from_glb(
+        &mut self,
+        glb_bufs: &Vec<&[u8]>,
+        vulkan_context: &VulkanContext,
+        descriptor_set_layouts: &DescriptorSetLayouts,
+    ) -> Result<Models> {
+        load_models_from_glb_with_textures(
+            glb_bufs,
+            vulkan_context,
+            descriptor_set_layouts,
+            &Default::default(),
+            &mut self.textures,
+        )
+    }
+
+    /// Resolve a texture handle, failing if it doesn't belong to this `Assets`
+    pub fn texture(&self, handle: Handle<Texture>) -> Result<&Texture> {
+        self.textures
+            .get(handle)
+            .ok_or_else(|| anyhow!("Invalid texture handle: {:?}", handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_asset_store_dedupes_by_key() {
+        let mut store = AssetStore::default();
+        let mut loads = 0;
+
+        let a = store
+            .get_or_load("a", || {
+                loads += 1;
+                Ok(1)
+            })
+            .unwrap();
+        let b = store
+            .get_or_load("a", || {
+                loads += 1;
+                Ok(2)
+            })
+            .unwrap();
+        let c = store.get_or_load("c", || Ok(3)).unwrap();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(loads, 1);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(a), Some(&1));
+        assert_eq!(store.handle("c"), Some(c));
+        assert!(store.get_or_load("d", || Err(anyhow!("Nope"))).is_err());
+        assert_eq!(store.handle("d"), None);
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_loading_a_texture_twice_shares_the_image() {
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let mut assets = Assets::default();
+
+        let a = assets
+            .load_texture("render_gui.jpg", &vulkan_context)
+            .unwrap();
+        let b = assets
+            .load_texture("render_gui.jpg", &vulkan_context)
+            .unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(assets.textures.len(), 1);
+        assert_eq!(
+            assets.texture(a).unwrap().image.handle,
+            assets.texture(b).unwrap().image.handle
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_loading_a_model_twice_shares_its_textures() {
+        use crate::resources::render_context::create_descriptor_set_layouts;
+
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let set_layouts = create_descriptor_set_layouts(&vulkan_context).unwrap();
+        let data: Vec<&[u8]> = vec![include_bytes!("../../test_assets/damaged_helmet.glb")];
+        let mut assets = Assets::default();
+
+        let _ = assets
+            .load_models_from_glb(&data, &vulkan_context, &set_layouts)
+            .unwrap();
+        let texture_count = assets.textures.len();
+        assert!(texture_count > 1);
+
+        let models = assets
+            .load_models_from_glb(&data, &vulkan_context, &set_layouts)
+            .unwrap();
+        assert!(models.contains_key("Damaged Helmet"));
+        assert_eq!(assets.textures.len(), texture_count);
+    }
+}
@@ -1,7 +1,7 @@
 use std::marker::PhantomData;
 
 use anyhow::Result;
-use ash::vk;
+use ash::vk::{self, Handle};
 
 use crate::resources::VulkanContext;
 
@@ -20,14 +20,17 @@ impl<T> Buffer<T>
 where
     T: Sized + Copy,
 {
+    /// Create a buffer containing `data`. `name` is shown in validation messages and debugging tools.
     pub fn new(
         vulkan_context: &VulkanContext,
         data: &[T],
         usage: vk::BufferUsageFlags,
+        name: &str,
     ) -> Result<Self> {
         let size = std::mem::size_of_val(data) as vk::DeviceSize;
         let (handle, device_memory, device_memory_size) =
             vulkan_context.create_buffer_with_data(&data, usage, size)?;
+        vulkan_context.set_debug_name(vk::ObjectType::BUFFER, handle.as_raw(), name)?;
 
         Ok(Self {
             handle,
@@ -51,6 +54,68 @@ where
     }
 }
 
+#[cfg(feature = "debug-buffers")]
+impl<T> Buffer<T>
+where
+    T: Sized + Copy,
+{
+    /// Map the buffer and copy its contents back, eg. to check what a shader will read. Requires the `debug-buffers`
+    /// feature.
+    ///
+    /// Buffers are always allocated in host visible, coherent memory, so any buffer can be read back without
+    /// choosing a different memory type when it's created.
+    pub fn read_back(&self, vulkan_context: &VulkanContext) -> Result<Vec<T>> {
+        let len = (self.size / std::mem::size_of::<T>() as vk::DeviceSize) as usize;
+        vulkan_context.read_buffer(self.device_memory, self.device_memory_size, self.usage, len)
+    }
+}
+
+/// A buffer of indices, stored as `u16` when every index fits in 16 bits to save memory.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum IndexBuffer {
+    U16(Buffer<u16>),
+    U32(Buffer<u32>),
+}
+
+impl IndexBuffer {
+    pub fn new(vulkan_context: &VulkanContext, indices: &[u32], name: &str) -> Result<Self> {
+        let usage = vk::BufferUsageFlags::INDEX_BUFFER;
+        let index_buffer = match get_index_type(indices) {
+            vk::IndexType::UINT16 => {
+                let indices = indices.iter().map(|i| *i as u16).collect::<Vec<_>>();
+                IndexBuffer::U16(Buffer::new(vulkan_context, &indices, usage, name)?)
+            }
+            _ => IndexBuffer::U32(Buffer::new(vulkan_context, indices, usage, name)?),
+        };
+
+        Ok(index_buffer)
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        match self {
+            IndexBuffer::U16(b) => b.handle,
+            IndexBuffer::U32(b) => b.handle,
+        }
+    }
+
+    /// The `vk::IndexType` to use when binding this buffer
+    pub fn index_type(&self) -> vk::IndexType {
+        match self {
+            IndexBuffer::U16(_) => vk::IndexType::UINT16,
+            IndexBuffer::U32(_) => vk::IndexType::UINT32,
+        }
+    }
+}
+
+/// Get the smallest index type that can hold every index in `indices`.
+pub fn get_index_type(indices: &[u32]) -> vk::IndexType {
+    if indices.iter().all(|i| *i <= u16::MAX as u32) {
+        vk::IndexType::UINT16
+    } else {
+        vk::IndexType::UINT32
+    }
+}
+
 // TODO: Need to be able to drop Buffers
 // impl<T> Buffer<T> {
 //     pub(crate) fn destroy(&self, vulkan_context: &VulkanContext) -> () {
@@ -61,3 +126,62 @@ where
 //         };
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_index_type() {
+        assert_eq!(get_index_type(&[0, 1, 2, 0, 3, 1]), vk::IndexType::UINT16);
+        assert_eq!(
+            get_index_type(&[0, 1, u16::MAX as u32]),
+            vk::IndexType::UINT16
+        );
+        assert_eq!(
+            get_index_type(&[0, 1, u16::MAX as u32 + 1]),
+            vk::IndexType::UINT32
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_small_index_buffer_is_u16() {
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let index_buffer = IndexBuffer::new(&vulkan_context, &[0, 1, 2, 0, 3, 1], "Test").unwrap();
+        assert_eq!(index_buffer.index_type(), vk::IndexType::UINT16);
+        match index_buffer {
+            IndexBuffer::U16(buffer) => assert_eq!(buffer.size, 12),
+            IndexBuffer::U32(_) => panic!("Expected a u16 index buffer"),
+        }
+    }
+
+    #[cfg(all(target_os = "windows", feature = "debug-buffers"))]
+    #[test]
+    pub fn test_read_back() {
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let vertices = [[0.0f32, 1.0, 2.0], [3.0, 4.0, 5.0]];
+        let buffer = Buffer::new(
+            &vulkan_context,
+            &vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            "Read back",
+        )
+        .unwrap();
+        assert_eq!(buffer.read_back(&vulkan_context).unwrap(), vertices);
+
+        let updated = [[6.0f32, 7.0, 8.0], [9.0, 10.0, 11.0]];
+        buffer.update(&vulkan_context, &updated).unwrap();
+        assert_eq!(buffer.read_back(&vulkan_context).unwrap(), updated);
+
+        let uniforms = [[1.0f32, 2.0, 3.0, 4.0]];
+        let buffer = Buffer::new(
+            &vulkan_context,
+            &uniforms,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            "Read back uniforms",
+        )
+        .unwrap();
+        assert_eq!(buffer.read_back(&vulkan_context).unwrap(), uniforms);
+    }
+}
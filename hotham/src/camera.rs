@@ -4,10 +4,24 @@ use openxr::View;
 
 use crate::util::posef_to_isometry;
 
+/// Default exposure - colours are left unchanged
+pub const DEFAULT_EXPOSURE: f32 = 1.0;
+/// Default gamma - matches the sRGB encoding performed by the swapchain
+pub const DEFAULT_GAMMA: f32 = 2.2;
+
+const MIN_EXPOSURE: f32 = 0.01;
+const MAX_EXPOSURE: f32 = 16.0;
+const MIN_GAMMA: f32 = 1.0;
+const MAX_GAMMA: f32 = 3.0;
+
 #[derive(Debug, Clone)]
 pub struct Camera {
     pub position: Isometry3<f32>,
     pub view_matrix: Matrix4<f32>,
+    /// Multiplier applied to the final colour before it's encoded as sRGB. Defaults to `1.0`.
+    pub exposure: f32,
+    /// Display gamma applied to the final colour. Defaults to `2.2`.
+    pub gamma: f32,
 }
 
 impl Default for Camera {
@@ -16,6 +30,8 @@ impl Default for Camera {
         Self {
             position: Isometry3::from_parts(t, UnitQuaternion::identity()),
             view_matrix: Matrix4::identity(),
+            exposure: DEFAULT_EXPOSURE,
+            gamma: DEFAULT_GAMMA,
         }
     }
 }
@@ -35,6 +51,21 @@ impl Camera {
         vector![p[0], p[1], p[2], 0.]
     }
 
+    /// Set the exposure, clamped to `0.01..=16.0`
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure.clamp(MIN_EXPOSURE, MAX_EXPOSURE);
+    }
+
+    /// Set the gamma, clamped to `1.0..=3.0`
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.gamma = gamma.clamp(MIN_GAMMA, MAX_GAMMA);
+    }
+
+    /// Exposure and gamma, packed to be sent to the fragment shader
+    pub fn tonemapping_params(&self) -> Vector4<f32> {
+        vector![self.exposure, self.gamma, 0., 0.]
+    }
+
     pub fn build_matrix(&self) -> Result<Matrix4<f32>> {
         self.position
             .to_homogeneous()
@@ -42,3 +73,31 @@ impl Camera {
             .ok_or_else(|| anyhow!("Unable to invert view Matrix!"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_exposure_and_gamma_are_clamped() {
+        let mut camera = Camera::default();
+        assert_eq!(camera.exposure, DEFAULT_EXPOSURE);
+        assert_eq!(camera.gamma, DEFAULT_GAMMA);
+
+        camera.set_exposure(2.0);
+        assert_eq!(camera.exposure, 2.0);
+        camera.set_exposure(-1.0);
+        assert_eq!(camera.exposure, MIN_EXPOSURE);
+        camera.set_exposure(1000.0);
+        assert_eq!(camera.exposure, MAX_EXPOSURE);
+
+        camera.set_gamma(0.0);
+        assert_eq!(camera.gamma, MIN_GAMMA);
+        camera.set_gamma(10.0);
+        assert_eq!(camera.gamma, MAX_GAMMA);
+        assert_eq!(
+            camera.tonemapping_params(),
+            vector![MAX_EXPOSURE, MAX_GAMMA, 0., 0.]
+        );
+    }
+}
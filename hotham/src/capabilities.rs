@@ -0,0 +1,111 @@
+use ash::vk;
+use log::warn;
+use openxr as xr;
+
+use crate::resources::VulkanContext;
+
+/// What the current device and OpenXR runtime are able to do
+/// Queried once at startup. Use `Engine::capabilities` to decide which optional features to enable.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// The Vulkan API version negotiated with the loader, OpenXR runtime and device. See `VulkanContext::api_version`.
+    pub vulkan_api_version: u32,
+    /// Highest MSAA sample count supported by both colour and depth framebuffer attachments
+    pub max_sample_count: vk::SampleCountFlags,
+    /// Maximum sampler anisotropy, or 1.0 if anisotropic filtering isn't supported
+    pub max_sampler_anisotropy: f32,
+    /// Maximum size, in bytes, of a push constant block
+    pub max_push_constants_size: u32,
+    /// Maximum number of layers in an image array
+    pub max_image_array_layers: u32,
+    /// The range of supported line widths
+    pub line_width_range: [f32; 2],
+    /// Is `VkPhysicalDeviceFeatures::wideLines` supported?
+    pub wide_lines: bool,
+    /// Is `VkPhysicalDeviceFeatures::fillModeNonSolid` (ie. wireframe) supported?
+    pub fill_mode_non_solid: bool,
+    /// Is `VkPhysicalDeviceFeatures::sampleRateShading` supported?
+    pub sample_rate_shading: bool,
+    /// Is `VkPhysicalDeviceFeatures::depthBiasClamp` supported?
+    pub depth_bias_clamp: bool,
+    /// OpenXR extensions made available by the runtime
+    pub xr_extensions: xr::ExtensionSet,
+}
+
+impl Capabilities {
+    /// Query the capabilities of the physical device and OpenXR runtime
+    pub fn new(vulkan_context: &VulkanContext, xr_instance: &xr::Instance) -> Self {
+        let features = unsafe {
+            vulkan_context
+                .instance
+                .get_physical_device_features(vulkan_context.physical_device)
+        };
+        let limits = &vulkan_context.physical_device_properties.limits;
+        let xr_extensions = xr_instance
+            .entry()
+            .enumerate_extensions()
+            .unwrap_or_else(|e| {
+                warn!(
+                    target: "HOTHAM_CAPABILITIES",
+                    "Unable to enumerate OpenXR extensions: {:?}",
+                    e
+                );
+                Default::default()
+            });
+
+        Self {
+            vulkan_api_version: vulkan_context.api_version,
+            max_sample_count: get_max_sample_count(
+                limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts,
+            ),
+            max_sampler_anisotropy: if features.sampler_anisotropy == vk::TRUE {
+                limits.max_sampler_anisotropy
+            } else {
+                1.0
+            },
+            max_push_constants_size: limits.max_push_constants_size,
+            max_image_array_layers: limits.max_image_array_layers,
+            line_width_range: limits.line_width_range,
+            wide_lines: features.wide_lines == vk::TRUE,
+            fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+            sample_rate_shading: features.sample_rate_shading == vk::TRUE,
+            depth_bias_clamp: features.depth_bias_clamp == vk::TRUE,
+            xr_extensions,
+        }
+    }
+}
+
+fn get_max_sample_count(sample_counts: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .iter()
+    .find(|c| sample_counts.contains(**c))
+    .cloned()
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_max_sample_count() {
+        let sample_counts = vk::SampleCountFlags::TYPE_1
+            | vk::SampleCountFlags::TYPE_2
+            | vk::SampleCountFlags::TYPE_4;
+        assert_eq!(
+            get_max_sample_count(sample_counts),
+            vk::SampleCountFlags::TYPE_4
+        );
+        assert_eq!(
+            get_max_sample_count(vk::SampleCountFlags::empty()),
+            vk::SampleCountFlags::TYPE_1
+        );
+    }
+}
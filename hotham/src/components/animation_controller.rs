@@ -1,7 +1,11 @@
+use hecs::Entity;
+use rand::Rng;
+
 #[derive(Debug, Clone, PartialEq, Default)]
 
 /// Component that controls how an `AnimationTarget` should be animated.
 /// Added by `gltf_loader` to the root node if its children contain animation data.
+/// It keeps some internal state for root motion, so create one with `new` or `default` rather than a struct literal.
 pub struct AnimationController {
     /// The amount to blend from
     pub blend_from: usize,
@@ -9,4 +13,59 @@ pub struct AnimationController {
     pub blend_to: usize,
     /// The total blend amount
     pub blend_amount: f32,
+    /// How far through the animations to sample, in keyframes. Fractional keyframes are interpolated, and the
+    /// animations loop back to the start once they reach their last keyframe. Advance it to play the animations.
+    pub keyframe: f32,
+    /// Added to `keyframe` before the animations are sampled, so entities playing the same animations can be out of
+    /// phase with each other. See `randomize_time_offset`.
+    pub time_offset: f32,
+    /// The bone to extract root motion from, eg. the hips of a walking character. The bone's animated translation
+    /// and rotation are applied to this entity's `Transform` instead, and the bone is held at its first keyframe.
+    /// `None` disables root motion.
+    /// NOTE: Root motion assumes `keyframe` only ever moves forward.
+    pub root_motion: Option<Entity>,
+    /// The (looped) keyframe root motion was last extracted at
+    pub(crate) root_motion_keyframe: Option<f32>,
+}
+
+impl AnimationController {
+    /// Blend `blend_amount` of the way from the `blend_from` animations to the `blend_to` animations, starting at the
+    /// first keyframe
+    pub fn new(blend_from: usize, blend_to: usize, blend_amount: f32) -> Self {
+        Self {
+            blend_from,
+            blend_to,
+            blend_amount,
+            ..Default::default()
+        }
+    }
+
+    /// Offset this controller's animations by `time_offset` keyframes
+    pub fn with_time_offset(self, time_offset: f32) -> Self {
+        Self {
+            time_offset,
+            ..self
+        }
+    }
+
+    /// Extract root motion from `bone`
+    pub fn with_root_motion(self, bone: Entity) -> Self {
+        Self {
+            root_motion: Some(bone),
+            ..self
+        }
+    }
+
+    /// Set `time_offset` to a random number of keyframes up to `max_offset`, eg. the length of the animations, so that
+    /// a crowd of identical entities don't all move in lockstep:
+    /// ```ignore
+    /// controller.randomize_time_offset(&mut rand::thread_rng(), 30.);
+    /// ```
+    pub fn randomize_time_offset(&mut self, rng: &mut impl Rng, max_offset: f32) {
+        self.time_offset = if max_offset > 0. {
+            rng.gen_range(0.0..max_offset)
+        } else {
+            0.
+        };
+    }
 }
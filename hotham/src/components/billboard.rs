@@ -0,0 +1,190 @@
+use anyhow::Result;
+use hecs::{Entity, World};
+use nalgebra::{vector, Matrix4, UnitQuaternion, Vector2, Vector3};
+
+use crate::{
+    assets::{Assets, Handle},
+    resources::{RenderContext, VulkanContext},
+    texture::Texture,
+};
+
+use super::{panel::create_quad_mesh, RenderLayer, Transform, TransformMatrix, Visible};
+
+/// How a `Billboard` turns to face the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BillboardMode {
+    /// Always faces the user's head directly, eg. for UI markers
+    FacingCamera,
+    /// Stays upright in the world, only turning about the vertical axis, eg. for foliage or health bars
+    WorldUp,
+}
+
+/// Component used to draw a textured quad that turns to face the user each frame.
+/// Orientated by `billboard_system` and drawn alpha blended, after opaque objects. Its `Transform`'s rotation is
+/// ignored, but its translation and scale are kept, so billboards can be parented to other entities, eg. health bars.
+///
+/// Basic usage:
+/// ```ignore
+/// let texture = engine.assets.load_texture("marker.png", &engine.vulkan_context)?;
+/// let billboard = Billboard::new(texture, vector![0.2, 0.2], BillboardMode::FacingCamera);
+/// add_billboard_to_world(billboard, translation, &engine.assets, &engine.vulkan_context, &engine.render_context, &mut world)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Billboard {
+    /// The texture to draw
+    pub texture: Handle<Texture>,
+    /// Width and height of the quad, in metres
+    pub size: Vector2<f32>,
+    /// How the quad turns to face the user
+    pub mode: BillboardMode,
+}
+
+impl Billboard {
+    /// Create a new billboard
+    pub fn new(texture: Handle<Texture>, size: Vector2<f32>, mode: BillboardMode) -> Self {
+        Self {
+            texture,
+            size,
+            mode,
+        }
+    }
+}
+
+/// Convenience function to create a billboard's quad and add it to a World
+pub fn add_billboard_to_world(
+    billboard: Billboard,
+    translation: Vector3<f32>,
+    assets: &Assets,
+    vulkan_context: &VulkanContext,
+    render_context: &RenderContext,
+    world: &mut World,
+) -> Result<Entity> {
+    let texture = assets.texture(billboard.texture)?;
+    let mesh = create_quad_mesh(
+        texture,
+        vulkan_context,
+        render_context,
+        billboard.size.x * 0.5,
+        billboard.size.y * 0.5,
+    );
+
+    Ok(world.spawn((
+        billboard,
+        mesh,
+        Transform {
+            translation,
+            ..Default::default()
+        },
+        TransformMatrix::default(),
+        Visible {},
+        RenderLayer::TRANSPARENT,
+    )))
+}
+
+/// Get the rotation that turns a quad at `position`, facing +Z, towards `head_position`.
+/// Returns `None` if the head is directly above, below or on top of the quad, in which case it should keep its rotation.
+pub(crate) fn get_billboard_rotation(
+    mode: BillboardMode,
+    position: &Vector3<f32>,
+    head_position: &Vector3<f32>,
+) -> Option<UnitQuaternion<f32>> {
+    let mut direction = head_position - position;
+    if mode == BillboardMode::WorldUp {
+        direction.y = 0.;
+    }
+
+    // face_towards is undefined when looking straight up or down.
+    let horizontal_length = vector![direction.x, direction.z].norm();
+    if horizontal_length < f32::EPSILON {
+        return None;
+    }
+
+    Some(UnitQuaternion::face_towards(&direction, &Vector3::y()))
+}
+
+/// Turn `transform_matrix`, the world space transform of a quad, towards `head_position`, keeping its position and
+/// scale. Returns `None` if it should keep its rotation: see `get_billboard_rotation`.
+pub(crate) fn get_billboard_matrix(
+    mode: BillboardMode,
+    transform_matrix: &Matrix4<f32>,
+    head_position: &Vector3<f32>,
+) -> Option<Matrix4<f32>> {
+    let position = transform_matrix.column(3).xyz();
+    let scale = Vector3::from_fn(|i, _| transform_matrix.column(i).xyz().norm());
+    let rotation = get_billboard_rotation(mode, &position, head_position)?;
+
+    Some(
+        Matrix4::new_translation(&position)
+            * Matrix4::from(rotation)
+            * Matrix4::new_nonuniform_scaling(&scale),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn test_billboard_rotation() {
+        let position = vector![0., 1., -2.];
+        let head_position = vector![2., 3., -2.];
+
+        // Fully facing: the quad's normal should point straight at the head.
+        let rotation =
+            get_billboard_rotation(BillboardMode::FacingCamera, &position, &head_position).unwrap();
+        let normal = rotation * Vector3::z();
+        assert_relative_eq!(
+            normal,
+            (head_position - position).normalize(),
+            epsilon = 1e-6
+        );
+
+        // World up: the quad stays upright, turning only to face the head horizontally.
+        let rotation =
+            get_billboard_rotation(BillboardMode::WorldUp, &position, &head_position).unwrap();
+        assert_relative_eq!(rotation * Vector3::z(), Vector3::x(), epsilon = 1e-6);
+        assert_relative_eq!(rotation * Vector3::y(), Vector3::y(), epsilon = 1e-6);
+
+        // Directly overhead there's no sensible rotation.
+        assert!(
+            get_billboard_rotation(BillboardMode::WorldUp, &position, &vector![0., 5., -2.])
+                .is_none()
+        );
+    }
+
+    #[test]
+    pub fn test_billboard_matrix() {
+        // A billboard 1m in front of a parent that's been moved, turned and scaled up.
+        let parent = Matrix4::new_translation(&vector![3., 0., 0.])
+            * Matrix4::from(UnitQuaternion::from_euler_angles(0., 1., 0.))
+            * Matrix4::new_scaling(2.);
+        let local = Matrix4::new_translation(&vector![0., 0., 1.]);
+        let transform_matrix = parent * local;
+        let position = transform_matrix.column(3).xyz();
+        let head_position = vector![0., 1.5, 0.];
+
+        // It faces the head from where it is in the world, not where its local translation would put it..
+        let matrix = get_billboard_matrix(
+            BillboardMode::FacingCamera,
+            &transform_matrix,
+            &head_position,
+        )
+        .unwrap();
+        let rotation =
+            get_billboard_rotation(BillboardMode::FacingCamera, &position, &head_position).unwrap();
+        assert_relative_eq!(matrix.column(3).xyz(), position, epsilon = 1e-6);
+        assert_relative_eq!(
+            matrix.transform_vector(&Vector3::z()),
+            rotation * Vector3::z() * 2.,
+            epsilon = 1e-5
+        );
+
+        // ..and keeps its parent's scale.
+        assert_relative_eq!(
+            matrix.transform_vector(&Vector3::y()),
+            rotation * Vector3::y() * 2.,
+            epsilon = 1e-5
+        );
+    }
+}
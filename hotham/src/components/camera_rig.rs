@@ -0,0 +1,213 @@
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+/// How the camera moves between two `CameraKeyframe`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    /// Constant speed
+    Linear,
+    /// Starts slowly, then speeds up
+    EaseIn,
+    /// Starts quickly, then slows down
+    EaseOut,
+    /// Starts and ends slowly
+    EaseInOut,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::EaseInOut
+    }
+}
+
+impl Easing {
+    /// Map `t`, the fraction of the way between two keyframes, to how far along the camera should be
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2. - t),
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+        }
+    }
+}
+
+/// Where the camera should be, and what it should look at, at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraKeyframe {
+    /// Seconds since the start of the rig's path
+    pub time: f32,
+    /// Position of the camera, in the reference space
+    pub position: Vector3<f32>,
+    /// Point the camera looks at, in the reference space
+    pub target: Vector3<f32>,
+    /// How the camera moves from the previous keyframe to this one
+    pub easing: Easing,
+}
+
+impl CameraKeyframe {
+    /// Create a new keyframe, eased into with `Easing::EaseInOut`
+    pub fn new(time: f32, position: Vector3<f32>, target: Vector3<f32>) -> Self {
+        Self {
+            time,
+            position,
+            target,
+            easing: Default::default(),
+        }
+    }
+}
+
+/// Component used to move the camera along a scripted path, eg. for trailers or automated tests.
+/// Driven by `camera_rig_system`, which only moves the camera of flat (handheld) views: a VR headset's camera always
+/// follows the user's head.
+///
+/// Basic usage:
+/// ```ignore
+/// let rig = CameraRig::new(vec![
+///     CameraKeyframe::new(0., vector![0., 1.6, 2.], vector![0., 1., 0.]),
+///     CameraKeyframe::new(5., vector![2., 1.6, 0.], vector![0., 1., 0.]),
+/// ]);
+/// world.spawn((rig,));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraRig {
+    /// The keyframes of the path, sorted by time
+    pub keyframes: Vec<CameraKeyframe>,
+    /// Seconds since the start of the path
+    pub time: f32,
+    /// Whether to go back to the start after the last keyframe, rather than stopping there
+    pub looping: bool,
+}
+
+impl CameraRig {
+    /// Create a new rig, starting at the first keyframe
+    pub fn new(mut keyframes: Vec<CameraKeyframe>) -> Self {
+        keyframes.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Self {
+            keyframes,
+            time: 0.,
+            looping: false,
+        }
+    }
+
+    /// Move `delta_time` seconds along the path
+    pub fn advance(&mut self, delta_time: f32) {
+        self.time += delta_time;
+        let duration = self.duration();
+        if self.looping && duration > 0. {
+            self.time %= duration;
+        }
+    }
+
+    /// The time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.)
+    }
+
+    /// Whether the camera has reached the last keyframe. Looping rigs never finish.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.duration()
+    }
+
+    /// The camera's pose at the rig's current time. `None` if there are no keyframes.
+    pub fn pose(&self) -> Option<Isometry3<f32>> {
+        self.pose_at(self.time)
+    }
+
+    /// The camera's pose `time` seconds along the path. `None` if there are no keyframes.
+    pub fn pose_at(&self, time: f32) -> Option<Isometry3<f32>> {
+        let first = self.keyframes.first()?;
+        let next = match self.keyframes.iter().position(|k| k.time > time) {
+            Some(0) => return Some(look_at(&first.position, &first.target)),
+            Some(next) => next,
+            None => {
+                let last = self.keyframes.last()?;
+                return Some(look_at(&last.position, &last.target));
+            }
+        };
+
+        // The position and target are interpolated separately, so the camera keeps looking at its target as it moves.
+        let from = &self.keyframes[next - 1];
+        let to = &self.keyframes[next];
+        let t = to.easing.apply((time - from.time) / (to.time - from.time));
+        let position = from.position.lerp(&to.position, t);
+        let target = from.target.lerp(&to.target, t);
+        Some(look_at(&position, &target))
+    }
+}
+
+/// A camera pose at `position`, looking down -Z towards `target`
+fn look_at(position: &Vector3<f32>, target: &Vector3<f32>) -> Isometry3<f32> {
+    let rotation = UnitQuaternion::face_towards(&(position - target), &Vector3::y());
+    Isometry3::from_parts(Translation3::from(*position), rotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::vector;
+
+    #[test]
+    pub fn test_camera_rig_pose_at_time() {
+        let target = vector![0., 1., 0.];
+        let mut rig = CameraRig::new(vec![
+            CameraKeyframe {
+                easing: Easing::Linear,
+                ..CameraKeyframe::new(2., vector![0., 1., 4.], target)
+            },
+            CameraKeyframe::new(0., vector![0., 1., 2.], target),
+        ]);
+        assert_eq!(rig.duration(), 2.);
+
+        // Halfway between the keyframes, eased linearly.
+        rig.advance(1.);
+        let pose = rig.pose().unwrap();
+        assert_relative_eq!(pose.translation.vector, vector![0., 1., 3.]);
+
+        // The camera looks down -Z, so it should be facing the target.
+        let forward = pose.rotation * -Vector3::z();
+        assert_relative_eq!(forward, vector![0., 0., -1.], epsilon = 0.0001);
+
+        // Before the first and after the last keyframe, the camera stays put.
+        let start = rig.pose_at(-1.).unwrap();
+        assert_relative_eq!(start.translation.vector, vector![0., 1., 2.]);
+        rig.advance(5.);
+        assert!(rig.is_finished());
+        assert_relative_eq!(rig.pose().unwrap().translation.vector, vector![0., 1., 4.]);
+
+        assert_eq!(CameraRig::new(Vec::new()).pose(), None);
+    }
+
+    #[test]
+    pub fn test_camera_rig_easing() {
+        let rig = CameraRig::new(vec![
+            CameraKeyframe::new(0., vector![0., 0., 0.], vector![0., 0., -1.]),
+            CameraKeyframe::new(1., vector![4., 0., 0.], vector![4., 0., -1.]),
+        ]);
+        let pose = rig.pose_at(0.25).unwrap();
+        assert_relative_eq!(pose.translation.vector, vector![0.625, 0., 0.]);
+        let pose = rig.pose_at(0.5).unwrap();
+        assert_relative_eq!(pose.translation.vector, vector![2., 0., 0.]);
+
+        assert_eq!(Easing::EaseIn.apply(0.5), 0.25);
+        assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+        assert_eq!(Easing::Linear.apply(2.), 1.);
+    }
+
+    #[test]
+    pub fn test_camera_rig_looping() {
+        let mut rig = CameraRig::new(vec![
+            CameraKeyframe::new(0., vector![0., 0., 0.], vector![0., 0., -1.]),
+            CameraKeyframe::new(2., vector![2., 0., 0.], vector![2., 0., -1.]),
+        ]);
+        rig.looping = true;
+        rig.advance(3.);
+        assert_relative_eq!(rig.time, 1.);
+        assert!(!rig.is_finished());
+    }
+}
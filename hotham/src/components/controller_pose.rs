@@ -0,0 +1,21 @@
+use super::hand::Handedness;
+
+/// Which of a controller's poses to follow
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum ControllerPoseKind {
+    /// Where the user's hand grips the controller, eg. for tools and weapons held in the hand
+    Grip,
+    /// A ray pointing forward from the controller, eg. for pointers and guns
+    Aim,
+}
+
+/// A component that's added to an entity to make it follow one of a controller's poses
+/// Used to attach held objects to a controller: see `attach_to_controller`
+/// Requires `controller_pose_system`
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub struct ControllerPose {
+    /// Which controller to follow
+    pub handedness: Handedness,
+    /// Which of the controller's poses to follow
+    pub kind: ControllerPoseKind,
+}
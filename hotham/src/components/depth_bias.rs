@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Component used to push an entity slightly towards (or away from) the camera when it's depth tested, so coplanar
+/// surfaces such as decals and posters don't z-fight with the surface they sit on. Applied by `rendering_system`
+/// with `vkCmdSetDepthBias`. Entities without `DepthBias` aren't biased.
+///
+/// The units are those of Vulkan's depth bias:
+/// - `constant_factor` is a multiple of the smallest depth difference the depth buffer can resolve
+/// - `slope_factor` is a multiple of the surface's depth slope, which keeps the offset working at grazing angles
+///
+/// Depth is 0.0 at the near plane, so **negative** values move the entity towards the camera. Values between
+/// -1.0 and -4.0 for both factors are usually enough for decals; much larger values let surfaces show through
+/// things that are genuinely in front of them.
+///
+/// Basic usage:
+/// ```ignore
+/// use hotham::components::DepthBias;
+/// world.insert_one(decal, DepthBias { constant_factor: -1.0, slope_factor: -1.0 });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct DepthBias {
+    /// Constant depth offset, in units of the minimum resolvable depth difference
+    pub constant_factor: f32,
+    /// Depth offset relative to the surface's depth slope
+    pub slope_factor: f32,
+}
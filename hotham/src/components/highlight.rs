@@ -0,0 +1,23 @@
+use nalgebra::{vector, Vector4};
+
+/// Component used to draw an outline around an entity, eg. to show that it's been grabbed or hovered over.
+/// Consumed by `rendering_system`.
+///
+/// NOTE: The layout of this struct must match the `Highlight` push constant in `outline.vert` and `outline.frag`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Highlight {
+    /// Colour of the outline, in linear RGBA
+    pub color: Vector4<f32>,
+    /// Width of the outline, in metres
+    pub width: f32,
+}
+
+impl Default for Highlight {
+    fn default() -> Self {
+        Self {
+            color: vector![1., 1., 1., 1.],
+            width: 0.005,
+        }
+    }
+}
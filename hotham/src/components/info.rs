@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 /// Component that adds some information about the entity
 /// Useful for debugging - added by default by `gltf_loader`
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
 pub struct Info {
     /// A helpful name
     pub name: String,
@@ -1,9 +1,31 @@
-use anyhow::Result;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
 use ash::vk;
 use gltf::{texture::Info, Material as MaterialData};
-use nalgebra::{vector, Vector4};
+use log::warn;
+use nalgebra::{vector, Vector2, Vector4};
+
+use crate::{
+    assets::AssetStore,
+    resources::VulkanContext,
+    texture::{Texture, TextureColorSpace, TextureRole},
+};
 
-use crate::{resources::VulkanContext, texture::Texture};
+/// The binding of the height map in the textures descriptor set
+const HEIGHT_MAP_BINDING: u32 = 5;
+/// `Material::alpha_mask` for materials whose fragments below the cutoff are discarded
+const ALPHA_MASK_DISCARD: f32 = 1.;
+/// `Material::alpha_mask` for materials whose cutoff is anti-aliased with alpha-to-coverage. Must match `pbr.frag`.
+const ALPHA_MASK_COVERAGE: f32 = 2.;
+/// `Material::alpha_mask` for blended materials drawn in two passes: see `Material::set_depth_prepass`. Below zero, so
+/// `pbr.frag` treats it like any other blended material.
+const ALPHA_MASK_DEPTH_PREPASS: f32 = -1.;
+/// `Material::workflow` for unlit materials, eg. from `KHR_materials_unlit`. Must match `pbr.frag`.
+pub const WORKFLOW_UNLIT: f32 = 2.;
+/// `Material::workflow` for unlit materials drawing a linear, opaque render target, eg. a `Panel`'s. Textures are
+/// decoded according to their format, so only the alpha differs from `WORKFLOW_UNLIT`. Must match `pbr.frag`.
+pub const WORKFLOW_UNLIT_LINEAR_TEXTURE: f32 = 3.;
 
 /// A component that instructs the renderer how an entity should look when rendered
 /// Mostly maps to the [glTF material spec](https://www.khronos.org/registry/glTF/specs/2.0/glTF-2.0.html#materials) and
@@ -13,13 +35,16 @@ use crate::{resources::VulkanContext, texture::Texture};
 pub struct Material {
     /// The base colour of the material
     pub base_colour_factor: Vector4<f32>,
-    /// The color and intensity of the light being emitted by the material
+    /// The color and intensity of the light being emitted by the material, multiplied by the emissive texture if
+    /// there is one. Can be brighter than 1.0 with `KHR_materials_emissive_strength`.
     pub emmissive_factor: Vector4<f32>,
     /// How diffuse is this material?
     pub diffuse_factor: Vector4<f32>,
     /// How specular is this material?
     pub specular_factor: Vector4<f32>,
-    /// What workflow should be used - 0.0 for Metalic Roughness / 1.0 for Specular Glossiness / 2.0 for unlit
+    /// What workflow should be used - 0.0 for Metalic Roughness / 1.0 for Specular Glossiness / 2.0 for unlit /
+    /// 3.0 for unlit with a linear base colour texture. Unlit materials are drawn with their base colour, multiplied
+    /// by the vertex colour, and ignore every light in the scene.
     pub workflow: f32,
     /// The base color texture.
     pub base_color_texture_set: i32,
@@ -35,14 +60,33 @@ pub struct Material {
     pub metallic_factor: f32,
     /// The factor for the roughness of the material.
     pub roughness_factor: f32,
-    /// Alpha mask - see fragment shader
+    /// Alpha mask - see fragment shader. 0.0 if the material is opaque or blended, 1.0 if fragments below
+    /// `alpha_mask_cutoff` are discarded, or 2.0 if the cutoff is anti-aliased with alpha-to-coverage: see
+    /// `Material::set_alpha_to_coverage`. -1.0 if the material is blended, after a depth prepass: see
+    /// `Material::set_depth_prepass`.
     pub alpha_mask: f32,
     /// Alpha mask cutoff - see fragment shader
     pub alpha_mask_cutoff: f32,
+    /// 1.0 if both sides of the material are drawn, 0.0 if back faces are culled.
+    /// Back faces of double-sided materials have their normals flipped towards the viewer, so both sides are lit the same.
+    pub double_sided: f32,
+    /// Height texture set, used for parallax occlusion mapping. -1 disables it. See `Material::set_height_map`.
+    pub height_texture_set: i32,
+    /// How deep the surface described by the height map is, in texture coordinates. A height of 1.0 is the top of the
+    /// surface and 0.0 is `parallax_scale` below it. Usually small, eg. `0.05`: larger values show more depth, but
+    /// distort more at glancing angles.
+    pub parallax_scale: f32,
+    /// How far the top of the surface is raised above the mesh, as a fraction of `parallax_scale`. `0.0` keeps the
+    /// whole surface below the mesh, so it looks pushed in; `0.5` puts the middle of the height range on the mesh.
+    pub parallax_bias: f32,
 }
 
 impl Material {
-    /// Load a material from a glTF document
+    /// Load a material from a glTF document. `emissive_strength` scales its emissive factor, as in
+    /// `KHR_materials_emissive_strength`, which the `gltf` crate doesn't parse.
+    ///
+    /// Textures are loaded through `textures`, so a texture that's already been uploaded is shared rather than uploaded
+    /// again. `KHR_texture_transform` isn't applied here: see `get_texture_transforms`.
     pub fn load(
         mesh_name: &str,
         set_layout: vk::DescriptorSetLayout,
@@ -50,6 +94,8 @@ impl Material {
         vulkan_context: &VulkanContext,
         _buffer: &[u8],
         images: &Vec<gltf::image::Data>,
+        emissive_strength: f32,
+        textures: &mut AssetStore<Texture>,
     ) -> Result<(Self, vk::DescriptorSet)> {
         let material_name = format!(
             "Material {} for mesh {}",
@@ -57,7 +103,9 @@ impl Material {
             mesh_name
         );
 
-        let empty_texture = Texture::empty(vulkan_context)?;
+        let empty_texture =
+            textures.get_or_load("Empty Texture", || Texture::empty(vulkan_context))?;
+        let empty_texture = textures.get(empty_texture).unwrap().clone();
 
         let pbr_metallic_roughness = material.pbr_metallic_roughness();
         let pbr_specular_glossiness = material.pbr_specular_glossiness();
@@ -67,11 +115,13 @@ impl Material {
         let base_color_texture_set = get_texture_set(base_color_texture_info.as_ref());
         let base_color_texture = base_color_texture_info
             .map(|i| {
-                Texture::load(
+                load_texture(
+                    textures,
                     &format!("Base Colour texture for {}", mesh_name),
                     i.texture(),
                     vulkan_context,
                     images,
+                    TextureRole::BaseColor,
                 )
             })
             .flatten()
@@ -84,11 +134,13 @@ impl Material {
             get_texture_set(metallic_roughness_texture_info.as_ref());
         let metallic_roughness_texture = metallic_roughness_texture_info
             .map(|i| {
-                Texture::load(
+                load_texture(
+                    textures,
                     &format!("Metallic Roughness texture for {}", mesh_name),
                     i.texture(),
                     vulkan_context,
                     images,
+                    TextureRole::MetallicRoughness,
                 )
             })
             .flatten()
@@ -102,11 +154,13 @@ impl Material {
             .unwrap_or(-1);
         let normal_texture = normal_texture_info
             .map(|i| {
-                Texture::load(
+                load_texture(
+                    textures,
                     &format!("Normal texture for {}", mesh_name),
                     i.texture(),
                     vulkan_context,
                     images,
+                    TextureRole::Normal,
                 )
             })
             .flatten()
@@ -120,11 +174,13 @@ impl Material {
             .unwrap_or(-1);
         let occlusion_texture = occlusion_texture_info
             .map(|i| {
-                Texture::load(
+                load_texture(
+                    textures,
                     &format!("Occlusion texture for {}", mesh_name),
                     i.texture(),
                     vulkan_context,
                     images,
+                    TextureRole::Occlusion,
                 )
             })
             .flatten()
@@ -135,21 +191,21 @@ impl Material {
         let emissive_texture = emissive_texture_info
             .as_ref()
             .map(|i| {
-                Texture::load(
+                load_texture(
+                    textures,
                     &format!("Occlusion texture for {}", mesh_name),
                     i.texture(),
                     vulkan_context,
                     images,
+                    TextureRole::Emissive,
                 )
             })
             .flatten()
             .unwrap_or(empty_texture.clone());
-        let emissive_texture_set = emissive_texture_info
-            .map(|t| t.tex_coord() as i32)
-            .unwrap_or(-1);
+        let emissive_texture_set = get_texture_set(emissive_texture_info.as_ref());
 
         // Factors
-        let emmissive_factor = vector![0., 0., 0., 0.];
+        let emmissive_factor = get_emissive_factor(&material, emissive_strength);
         let diffuse_factor = pbr_specular_glossiness
             .as_ref()
             .map(|p| Vector4::from(p.diffuse_factor()))
@@ -164,19 +220,24 @@ impl Material {
         // Alpha
         let (alpha_mask, alpha_mask_cutoff) = match (material.alpha_mode(), material.alpha_cutoff())
         {
-            (gltf::material::AlphaMode::Mask, _) => (1., 0.5),
-            (_, Some(alpha_cutoff)) => (1., alpha_cutoff),
+            (gltf::material::AlphaMode::Mask, _) => (ALPHA_MASK_DISCARD, 0.5),
+            (_, Some(alpha_cutoff)) => (ALPHA_MASK_DISCARD, alpha_cutoff),
             _ => (0., 1.),
         };
 
+        // Double sided
+        let double_sided = if material.double_sided() { 1. } else { 0. };
+
         // Workflow
-        let workflow = if pbr_specular_glossiness.is_some() {
+        let workflow = if material.unlit() {
+            WORKFLOW_UNLIT
+        } else if pbr_specular_glossiness.is_some() {
             1.
         } else {
             0.
         };
 
-        // Descriptor set
+        // Descriptor set. glTF has no height maps: use `set_height_map` to add one.
         let descriptor_set = vulkan_context.create_textures_descriptor_sets(
             set_layout,
             &material_name,
@@ -185,6 +246,7 @@ impl Material {
             &normal_texture,
             &occlusion_texture,
             &emissive_texture,
+            &empty_texture,
         )?[0];
 
         Ok((
@@ -203,16 +265,395 @@ impl Material {
                 roughness_factor,
                 alpha_mask,
                 alpha_mask_cutoff,
+                double_sided,
+                height_texture_set: -1,
+                parallax_scale: 0.,
+                parallax_bias: 0.,
             },
             descriptor_set,
         ))
     }
+
+    /// Enable parallax occlusion mapping, which makes a flat surface look like it has depth by offsetting its texture
+    /// coordinates with `height_map`. `texture_descriptor_set` is the descriptor set of the `Primitive` using this
+    /// material, and `tex_coord` the texture coordinate set the height map uses. See `parallax_scale` and
+    /// `parallax_bias`.
+    ///
+    /// Every fragment steps through the height map up to 32 times, so this is much more expensive than normal
+    /// mapping. Only use it on surfaces where the depth is noticeable.
+    pub fn set_height_map(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        texture_descriptor_set: vk::DescriptorSet,
+        height_map: &Texture,
+        tex_coord: u32,
+        parallax_scale: f32,
+        parallax_bias: f32,
+    ) {
+        vulkan_context.update_texture_descriptor_set(
+            texture_descriptor_set,
+            HEIGHT_MAP_BINDING,
+            height_map,
+        );
+        self.height_texture_set = tex_coord as i32;
+        self.parallax_scale = parallax_scale;
+        self.parallax_bias = parallax_bias;
+    }
+
+    /// Anti-alias the edges of a masked (alpha tested) material, eg. foliage, with alpha-to-coverage. Rather than
+    /// discarding fragments below `alpha_mask_cutoff`, alpha around the cutoff is converted into the fraction of MSAA
+    /// samples that are covered, so cutout edges are smoothed like the edges of a mesh.
+    ///
+    /// Returns whether alpha-to-coverage is now enabled: materials that aren't masked are left unchanged. Only has an
+    /// effect when drawing with MSAA; otherwise the cutoff is applied as usual.
+    pub fn set_alpha_to_coverage(&mut self, enabled: bool) -> bool {
+        if self.alpha_mask <= 0. {
+            return false;
+        }
+        self.alpha_mask = if enabled {
+            ALPHA_MASK_COVERAGE
+        } else {
+            ALPHA_MASK_DISCARD
+        };
+        enabled
+    }
+
+    /// Whether the material's cutoff is anti-aliased with alpha-to-coverage: see `set_alpha_to_coverage`
+    pub fn alpha_to_coverage(&self) -> bool {
+        self.alpha_mask == ALPHA_MASK_COVERAGE
+    }
+
+    /// Draw the material in two passes when it's in a transparent `RenderLayer`: first only its depth, then its colour
+    /// wherever its depth equals what the first pass wrote. Only the nearest surface of each pixel is then blended,
+    /// so self-overlapping meshes like hair or glassware don't show their back surfaces through their front ones in
+    /// whatever order their triangles happen to be in. Sorting meshes back-to-front can't fix that, as it only sorts
+    /// whole meshes.
+    ///
+    /// It helps with single, mostly convex or layered meshes that should look like a solid tinted surface. It isn't
+    /// order-independent transparency: the hidden surfaces of the mesh aren't drawn at all, rather than blended in the
+    /// right order, and since the prepass writes depth, transparent meshes drawn after it (ie. in front of it, once
+    /// sorted) are hidden where they're behind it, as if it were opaque. Where the surfaces behind should show
+    /// through, like layered particles or intersecting glass panes, order-independent transparency is still needed.
+    /// Each two-pass primitive also costs a second draw call.
+    ///
+    /// Returns whether the prepass is now enabled: masked materials are left unchanged, as they already write depth.
+    /// Has no effect in opaque, additive or overlay layers.
+    pub fn set_depth_prepass(&mut self, enabled: bool) -> bool {
+        if self.alpha_mask > 0. {
+            return false;
+        }
+        self.alpha_mask = if enabled {
+            ALPHA_MASK_DEPTH_PREPASS
+        } else {
+            0.
+        };
+        enabled
+    }
+
+    /// Whether the material is drawn after a depth prepass when it's transparent: see `set_depth_prepass`
+    pub fn depth_prepass(&self) -> bool {
+        self.alpha_mask == ALPHA_MASK_DEPTH_PREPASS
+    }
+
+    /// Whether the material ignores lighting. Unlit materials are drawn with their own pipeline variant: see
+    /// `RenderContext::get_pipeline`.
+    pub fn is_unlit(&self) -> bool {
+        self.workflow == WORKFLOW_UNLIT || self.workflow == WORKFLOW_UNLIT_LINEAR_TEXTURE
+    }
+
+    /// The faces that should be culled when drawing this material
+    pub fn cull_mode(&self) -> vk::CullModeFlags {
+        if self.double_sided == 1. {
+            vk::CullModeFlags::NONE
+        } else {
+            vk::CullModeFlags::BACK
+        }
+    }
+}
+
+/// Load `texture` as `role` through `textures`, uploading it only if it hasn't been already. `None` if it can't be
+/// loaded.
+fn load_texture(
+    textures: &mut AssetStore<Texture>,
+    name: &str,
+    texture: gltf::texture::Texture,
+    vulkan_context: &VulkanContext,
+    images: &Vec<gltf::image::Data>,
+    role: TextureRole,
+) -> Option<Texture> {
+    let color_space = TextureColorSpace::Auto;
+    let key = Texture::get_gltf_key(&texture, images, role, color_space);
+    let handle = textures
+        .get_or_load(&key, || {
+            Texture::load(name, texture, vulkan_context, images, role, color_space)
+                .ok_or_else(|| anyhow!("Unable to load {}", name))
+        })
+        .ok()?;
+    textures.get(handle).cloned()
 }
 
 fn arr_to_vec4(vec3: [f32; 3]) -> Vector4<f32> {
     vector![vec3[0], vec3[1], vec3[2], 0.]
 }
 
+/// The texture coordinate set used by a texture, which `KHR_texture_transform` can override
 fn get_texture_set(info: Option<&Info>) -> i32 {
-    info.map(|t| t.tex_coord() as i32).unwrap_or(-1)
+    info.map(|t| {
+        t.texture_transform()
+            .and_then(|transform| transform.tex_coord())
+            .unwrap_or(t.tex_coord()) as i32
+    })
+    .unwrap_or(-1)
+}
+
+fn get_emissive_factor(material: &MaterialData, emissive_strength: f32) -> Vector4<f32> {
+    arr_to_vec4(material.emissive_factor()) * emissive_strength
+}
+
+/// An affine transform applied to a texture's UV coordinates, from `KHR_texture_transform`. Usually used to pick
+/// a region of a texture atlas, or to tile a texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTransform {
+    /// Added to the UV coordinates, after they're scaled and rotated
+    pub offset: Vector2<f32>,
+    /// Counter-clockwise rotation of the UV coordinates around the origin, in radians
+    pub rotation: f32,
+    /// Multiplies the UV coordinates, before they're rotated
+    pub scale: Vector2<f32>,
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self {
+            offset: Vector2::zeros(),
+            rotation: 0.,
+            scale: vector![1., 1.],
+        }
+    }
+}
+
+impl TextureTransform {
+    fn load(info: &Info) -> Option<Self> {
+        info.texture_transform().map(|t| Self {
+            offset: t.offset().into(),
+            rotation: t.rotation(),
+            scale: t.scale().into(),
+        })
+    }
+
+    /// Transform `uv`, as in the glTF spec: scale, then rotate, then offset.
+    pub fn apply(&self, uv: Vector2<f32>) -> Vector2<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        let uv = uv.component_mul(&self.scale);
+        self.offset + vector![cos * uv.x + sin * uv.y, cos * uv.y - sin * uv.x]
+    }
+}
+
+/// The `KHR_texture_transform` of each of the two texture coordinate sets used by `material`.
+///
+/// There's no room left in the material's push constants for a transform per texture, so the transforms are baked
+/// into the vertices' texture coordinates when they're loaded. That means textures sharing a texture coordinate set
+/// must share a transform too, which exporters (like Blender's) do anyway. If they don't, the first texture's
+/// transform is used, in the order base colour, metallic roughness, emissive.
+pub(crate) fn get_texture_transforms(
+    material: &MaterialData,
+    mesh_name: &str,
+) -> [Option<TextureTransform>; 2] {
+    let pbr_metallic_roughness = material.pbr_metallic_roughness();
+    let textures = [
+        pbr_metallic_roughness.base_color_texture(),
+        pbr_metallic_roughness.metallic_roughness_texture(),
+        material.emissive_texture(),
+    ];
+
+    let mut transforms = [None, None];
+    for info in textures.iter().flatten() {
+        let transform = TextureTransform::load(info).unwrap_or_default();
+        let set = get_texture_set(Some(info)) as usize;
+        match transforms.get_mut(set) {
+            Some(Some(existing)) if *existing != transform => warn!(
+                target: "HOTHAM_GLTF",
+                "Textures of mesh {} have different transforms for texture coordinates {}. Ignoring {:?}",
+                mesh_name, set, transform
+            ),
+            Some(existing) => *existing = Some(transform),
+            None => {}
+        }
+    }
+
+    // Don't touch texture coordinates that aren't transformed.
+    for transform in transforms.iter_mut() {
+        if *transform == Some(TextureTransform::default()) {
+            *transform = None;
+        }
+    }
+
+    transforms
+}
+
+/// Material extensions that the `gltf` crate doesn't parse, read from the glTF JSON.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MaterialExtensions {
+    /// `KHR_materials_emissive_strength`, by material index
+    emissive_strengths: HashMap<usize, f32>,
+}
+
+impl MaterialExtensions {
+    /// Read the extensions from the JSON of a glTF document. Anything that can't be read is ignored.
+    pub(crate) fn from_json(json: &[u8]) -> Self {
+        let root: serde_json::Value = match serde_json::from_slice(json) {
+            Ok(root) => root,
+            Err(_) => return Default::default(),
+        };
+
+        let emissive_strengths = root["materials"]
+            .as_array()
+            .map(|materials| {
+                materials
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, material)| {
+                        material["extensions"]["KHR_materials_emissive_strength"]
+                            ["emissiveStrength"]
+                            .as_f64()
+                            .map(|strength| (index, strength as f32))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self { emissive_strengths }
+    }
+
+    /// How much the emissive factor of `material` is scaled by. Defaults to 1.0.
+    pub(crate) fn emissive_strength(&self, material: &MaterialData) -> f32 {
+        material
+            .index()
+            .and_then(|index| self.emissive_strengths.get(&index))
+            .copied()
+            .unwrap_or(1.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn test_alpha_to_coverage_only_on_masked_materials() {
+        let mut material = Material::default();
+        assert!(!material.set_alpha_to_coverage(true));
+        assert!(!material.alpha_to_coverage());
+
+        material.alpha_mask = ALPHA_MASK_DISCARD;
+        assert!(material.set_alpha_to_coverage(true));
+        assert!(material.alpha_to_coverage());
+        assert!(!material.set_alpha_to_coverage(false));
+        assert!(!material.alpha_to_coverage());
+        assert_eq!(material.alpha_mask, ALPHA_MASK_DISCARD);
+    }
+
+    #[test]
+    pub fn test_depth_prepass_only_on_blended_materials() {
+        let mut material = Material::default();
+        assert!(!material.depth_prepass());
+        assert!(material.set_depth_prepass(true));
+        assert!(material.depth_prepass());
+        assert!(!material.alpha_to_coverage());
+        assert!(!material.set_depth_prepass(false));
+        assert!(!material.depth_prepass());
+        assert_eq!(material.alpha_mask, 0.);
+
+        // Masked materials already write depth.
+        material.alpha_mask = ALPHA_MASK_DISCARD;
+        assert!(!material.set_depth_prepass(true));
+        assert!(!material.depth_prepass());
+        assert_eq!(material.alpha_mask, ALPHA_MASK_DISCARD);
+    }
+
+    const GLTF: &[u8] = br#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": ["KHR_texture_transform", "KHR_materials_emissive_strength", "EXT_made_up"],
+        "images": [{ "uri": "texture.png" }],
+        "textures": [{ "source": 0 }],
+        "materials": [
+            {
+                "emissiveFactor": [1.0, 0.5, 0.25],
+                "emissiveTexture": { "index": 0 },
+                "pbrMetallicRoughness": {
+                    "baseColorTexture": {
+                        "index": 0,
+                        "extensions": {
+                            "KHR_texture_transform": {
+                                "offset": [0.5, 0.25],
+                                "rotation": 1.5707963,
+                                "scale": [2.0, 4.0],
+                                "texCoord": 1
+                            }
+                        }
+                    }
+                },
+                "extensions": {
+                    "KHR_materials_emissive_strength": { "emissiveStrength": 5.0 },
+                    "EXT_made_up": { "something": true }
+                }
+            },
+            { "emissiveFactor": [1.0, 1.0, 1.0] }
+        ]
+    }"#;
+
+    #[test]
+    pub fn test_texture_transform() {
+        let gltf = gltf::Gltf::from_slice(GLTF).unwrap();
+        let material = gltf.materials().next().unwrap();
+        let info = material.pbr_metallic_roughness().base_color_texture();
+
+        // The transform's texCoord overrides the texture's..
+        assert_eq!(get_texture_set(info.as_ref()), 1);
+        let transform = TextureTransform::load(&info.unwrap()).unwrap();
+        assert_eq!(transform.offset, vector![0.5, 0.25]);
+        assert_eq!(transform.rotation, 1.5707963);
+        assert_eq!(transform.scale, vector![2., 4.]);
+
+        // ..and the emissive texture, which isn't transformed, uses the first set.
+        assert_eq!(
+            get_texture_transforms(&material, "Test"),
+            [None, Some(transform)]
+        );
+
+        // (1, 1) is scaled to (2, 4), rotated a quarter turn to (4, -2), then offset.
+        assert_relative_eq!(
+            transform.apply(vector![1., 1.]),
+            vector![4.5, -1.75],
+            epsilon = 1e-5
+        );
+        assert_eq!(
+            TextureTransform::default().apply(vector![0.3, 0.7]),
+            vector![0.3, 0.7]
+        );
+    }
+
+    #[test]
+    pub fn test_emissive_strength() {
+        let gltf = gltf::Gltf::from_slice(GLTF).unwrap();
+        let extensions = MaterialExtensions::from_json(GLTF);
+        let mut materials = gltf.materials();
+        let (first, second) = (materials.next().unwrap(), materials.next().unwrap());
+
+        assert_eq!(extensions.emissive_strength(&first), 5.);
+        assert_eq!(extensions.emissive_strength(&second), 1.);
+        assert_eq!(
+            get_emissive_factor(&first, extensions.emissive_strength(&first)),
+            vector![5., 2.5, 1.25, 0.]
+        );
+        assert_eq!(
+            get_emissive_factor(&second, extensions.emissive_strength(&second)),
+            vector![1., 1., 1., 0.]
+        );
+
+        // Anything unreadable has no extensions.
+        let extensions = MaterialExtensions::from_json(b"not json");
+        assert_eq!(extensions.emissive_strength(&first), 1.);
+    }
 }
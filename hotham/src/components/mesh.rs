@@ -1,40 +1,36 @@
 use anyhow::Result;
 use ash::vk;
+use log::debug;
 use nalgebra::Matrix4;
 
-use super::primitive::Primitive;
+use super::{
+    material::MaterialExtensions,
+    primitive::{BoundingSphere, Primitive},
+};
 use crate::{
+    assets::AssetStore,
     buffer::Buffer,
-    resources::{render_context::DescriptorSetLayouts, VulkanContext},
+    gltf_loader::{MeshOptimisation, VertexColorSpace},
+    resources::{
+        deletion_queue::GpuResource, render_context::DescriptorSetLayouts, RenderContext,
+        VulkanContext,
+    },
+    texture::Texture,
 };
-use std::mem::MaybeUninit;
+
+/// The binding of the joint matrices storage buffer in the mesh descriptor set
+const JOINT_MATRICES_BINDING: usize = 1;
 
 /// Uniform buffer used by the vertex shader for each entity
 #[repr(C)]
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Default, Clone, PartialEq, Copy)]
 pub struct MeshUBO {
     /// The transform of the entity, in world space
     pub transform: Matrix4<f32>,
-    /// Joint matrices for skinning
-    pub joint_matrices: [Matrix4<f32>; 128],
     /// The number of joints
     pub joint_count: f32,
 }
 
-impl Default for MeshUBO {
-    fn default() -> Self {
-        // Most meshes don't have joints, so no point allocating.
-        let mut joint_matrices: [Matrix4<f32>; 128] =
-            unsafe { MaybeUninit::uninit().assume_init() };
-        joint_matrices[0] = Matrix4::identity();
-        Self {
-            transform: Default::default(),
-            joint_count: Default::default(),
-            joint_matrices,
-        }
-    }
-}
-
 /// Component that encapsulates an Entity's geometry. Maps closesly to the [glTF spec](https://www.khronos.org/registry/glTF/specs/2.0/glTF-2.0.html#meshes)
 /// Usually automatically added by `gltf_loader`.
 #[derive(Debug, Clone, PartialEq)]
@@ -45,6 +41,11 @@ pub struct Mesh {
     pub ubo_buffer: Buffer<MeshUBO>,
     /// The actual contents of the UBO
     pub ubo_data: MeshUBO,
+    /// Storage buffer of joint matrices for skinning, sent to the vertex shader. Grows to fit `joint_matrices`: see
+    /// `Mesh::update_joint_buffer`.
+    pub joint_buffer: Buffer<Matrix4<f32>>,
+    /// Joint matrices for skinning, written by `skinning_system`. Empty if the mesh isn't skinned.
+    pub joint_matrices: Vec<Matrix4<f32>>,
     /// The primitives in this mesh (eg. actual geometry)
     pub primitives: Vec<Primitive>,
 }
@@ -56,6 +57,12 @@ impl Mesh {
         vulkan_context: &VulkanContext,
         descriptor_set_layouts: &DescriptorSetLayouts,
         images: &Vec<gltf::image::Data>,
+        optimisation: MeshOptimisation,
+        vertex_color_space: VertexColorSpace,
+        normal_smoothing_angle: f32,
+        import_scale: f32,
+        material_extensions: &MaterialExtensions,
+        textures: &mut AssetStore<Texture>,
     ) -> Result<Mesh> {
         let name = mesh_data.name().unwrap_or("");
         let primitives = mesh_data
@@ -68,12 +75,18 @@ impl Mesh {
                     buffer,
                     vulkan_context,
                     images,
+                    optimisation,
+                    vertex_color_space,
+                    normal_smoothing_angle,
+                    import_scale,
+                    material_extensions,
+                    textures,
                 )
             })
             .collect::<Result<Vec<_>>>()?;
 
         // Create descriptor sets
-        println!("[HOTHAM_MODEL] Creating descriptor sets for {}", name);
+        debug!(target: "HOTHAM_MODEL", "Creating descriptor sets for {}", name);
         let descriptor_sets = vulkan_context.create_mesh_descriptor_sets(
             descriptor_set_layouts.mesh_layout,
             mesh_data
@@ -81,13 +94,14 @@ impl Mesh {
                 .unwrap_or(&format!("Mesh {}", mesh_data.index())),
         )?;
         let descriptor_sets = [descriptor_sets[0]];
-        println!("[HOTHAM_MODEL] ..done!");
+        debug!(target: "HOTHAM_MODEL", "..done!");
 
         let mesh_ubo = MeshUBO::default();
         let ubo_buffer = Buffer::new(
             vulkan_context,
             &[mesh_ubo],
             vk::BufferUsageFlags::UNIFORM_BUFFER,
+            &format!("Mesh UBO for {}", name),
         )?;
         vulkan_context.update_buffer_descriptor_set(
             &ubo_buffer,
@@ -95,12 +109,118 @@ impl Mesh {
             0,
             vk::DescriptorType::UNIFORM_BUFFER,
         );
+        let joint_buffer = create_joint_buffer(vulkan_context, 1, descriptor_sets[0], name)?;
 
         Ok(Mesh {
             ubo_buffer,
             ubo_data: mesh_ubo,
+            joint_buffer,
+            joint_matrices: Vec::new(),
             descriptor_sets,
             primitives,
         })
     }
+
+    /// The number of joint matrices `joint_buffer` has room for
+    pub fn joint_capacity(&self) -> usize {
+        self.joint_buffer.size as usize / std::mem::size_of::<Matrix4<f32>>()
+    }
+
+    /// Upload `joint_matrices` to `joint_buffer`, growing it to the next power of two if they don't fit, eg. after
+    /// retargeting an animation to a skeleton with more joints.
+    pub fn update_joint_buffer(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        render_context: &mut RenderContext,
+    ) -> Result<()> {
+        if self.joint_matrices.len() > self.joint_capacity() {
+            let capacity = self.joint_matrices.len().next_power_of_two();
+            self.reserve_joints(vulkan_context, render_context, capacity)?;
+        }
+        if self.joint_matrices.is_empty() {
+            return Ok(());
+        }
+        self.joint_buffer
+            .update(vulkan_context, &self.joint_matrices)
+    }
+
+    /// Make sure `joint_buffer` has room for at least `capacity` joint matrices, eg. before swapping to a more
+    /// detailed skeleton, so the buffer is only reallocated once.
+    ///
+    /// Frames still in flight may be using the old buffer and descriptor set, so a new descriptor set is allocated
+    /// and the old ones are released to the `RenderContext`'s `DeletionQueue`.
+    pub fn reserve_joints(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        render_context: &mut RenderContext,
+        capacity: usize,
+    ) -> Result<()> {
+        if capacity <= self.joint_capacity() {
+            return Ok(());
+        }
+        debug!(
+            target: "HOTHAM_MODEL",
+            "Growing joint buffer from {} to {} joints",
+            self.joint_capacity(),
+            capacity
+        );
+
+        let descriptor_set = vulkan_context.create_mesh_descriptor_sets(
+            render_context.descriptor_set_layouts.mesh_layout,
+            "Skinned Mesh",
+        )?[0];
+        vulkan_context.update_buffer_descriptor_set(
+            &self.ubo_buffer,
+            descriptor_set,
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+        );
+        let joint_buffer =
+            create_joint_buffer(vulkan_context, capacity, descriptor_set, "Skinned Mesh")?;
+
+        let frame_index = render_context.frame_index;
+        let old_buffer = std::mem::replace(&mut self.joint_buffer, joint_buffer);
+        let old_descriptor_set = std::mem::replace(&mut self.descriptor_sets, [descriptor_set]);
+        let deletion_queue = &mut render_context.deletion_queue;
+        deletion_queue.push(
+            frame_index,
+            GpuResource::Buffer(old_buffer.handle, old_buffer.device_memory),
+        );
+        deletion_queue.push(
+            frame_index,
+            GpuResource::DescriptorSet(old_descriptor_set[0]),
+        );
+        Ok(())
+    }
+
+    /// A sphere around all of the mesh's primitives, in the mesh's space. `None` if the mesh has no primitives.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.primitives
+            .iter()
+            .map(|p| p.bounding_sphere)
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
+/// Create a storage buffer with room for `capacity` joint matrices, and bind it to `descriptor_set`. The matrices
+/// start out as the identity, so a skinned mesh is drawn in its bind pose until `skinning_system` runs.
+pub(crate) fn create_joint_buffer(
+    vulkan_context: &VulkanContext,
+    capacity: usize,
+    descriptor_set: vk::DescriptorSet,
+    name: &str,
+) -> Result<Buffer<Matrix4<f32>>> {
+    let joint_buffer = Buffer::new(
+        vulkan_context,
+        &vec![Matrix4::identity(); capacity.max(1)],
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        &format!("Joint Matrices for {}", name),
+    )?;
+    vulkan_context.update_buffer_descriptor_set(
+        &joint_buffer,
+        descriptor_set,
+        JOINT_MATRICES_BINDING,
+        vk::DescriptorType::STORAGE_BUFFER,
+    );
+    Ok(joint_buffer)
 }
@@ -0,0 +1,193 @@
+use anyhow::Result;
+use ash::vk;
+use nalgebra::{vector, Matrix3, Matrix4, Vector3, Vector4};
+
+use crate::{
+    buffer::Buffer,
+    resources::{RenderContext, VulkanContext},
+    scene_data::SceneData,
+};
+
+/// Component added to an entity with a `Mesh` to turn the mesh into a planar mirror, reflecting everything in front
+/// of it. Drawn by `mirror_system`.
+///
+/// The mesh is only used for the mirror's shape, so the entity shouldn't be `Visible`, or the mesh would be drawn
+/// over its own reflection. It should be flat, lying in the plane through the entity's origin with `normal`, and facing
+/// the same way as `normal`. Only the first mirror in the world is drawn, and only opaque and background meshes are
+/// reflected.
+///
+/// Mirrors are masked with the stencil buffer, so they aren't drawn if the depth format has no stencil component. See
+/// `DEPTH_FORMATS`.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    /// Colour the reflection is multiplied by, in linear RGB. Defaults to a slightly darkened white, as real mirrors
+    /// don't reflect all of the light that hits them.
+    pub tint: Vector4<f32>,
+    /// The direction the mirror faces, in the entity's local space. Defaults to +Z.
+    pub normal: Vector3<f32>,
+    pub(crate) scene_data_buffers: Vec<Buffer<SceneData>>,
+    pub(crate) scene_data_descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl Mirror {
+    pub fn new(vulkan_context: &VulkanContext, render_context: &RenderContext) -> Result<Self> {
+        // One buffer per swapchain image, so a frame still in flight never sees the next frame's reflection.
+        let mut scene_data_buffers = Vec::new();
+        let mut scene_data_descriptor_sets = Vec::new();
+        for i in 0..render_context.frames.len() {
+            let scene_data_buffer = Buffer::new(
+                vulkan_context,
+                &[SceneData::default()],
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                &format!("Mirror Scene Data Buffer {}", i),
+            )?;
+            // Reflections on materials seen in the mirror come from the default IBL maps.
+            let scene_data_descriptor_set = vulkan_context.create_scene_data_descriptor_sets(
+                render_context.descriptor_set_layouts.scene_data_layout,
+                &scene_data_buffer,
+                &render_context.scene_params_buffer,
+                &render_context.diffuse_ibl,
+                &render_context.specular_ibl,
+                &render_context.brdf_lut,
+            )?[0];
+            scene_data_buffers.push(scene_data_buffer);
+            scene_data_descriptor_sets.push(scene_data_descriptor_set);
+        }
+
+        Ok(Self {
+            tint: vector![0.9, 0.9, 0.9, 1.],
+            normal: Vector3::z(),
+            scene_data_buffers,
+            scene_data_descriptor_sets,
+        })
+    }
+
+    /// The plane the mirror reflects in, in world space, for an entity with `transform`: `(n, -n·p)` for a point `p`
+    /// on the plane and its unit normal `n`. Points in front of the mirror are on the positive side.
+    pub fn get_plane(&self, transform: &Matrix4<f32>) -> Vector4<f32> {
+        get_plane(transform, &self.normal)
+    }
+}
+
+fn get_plane(transform: &Matrix4<f32>, normal: &Vector3<f32>) -> Vector4<f32> {
+    // Normals are transformed by the inverse transpose, so they stay perpendicular to non-uniformly scaled surfaces.
+    let basis = transform.fixed_slice::<3, 3>(0, 0);
+    let normal = basis
+        .try_inverse()
+        .map(|inverse| inverse.transpose() * normal)
+        .unwrap_or(*normal)
+        .normalize();
+    let point = transform.column(3).xyz();
+    normal.push(-normal.dot(&point))
+}
+
+/// The matrix that reflects points through `plane`, as returned by `Mirror::get_plane`.
+///
+/// A point `x` is reflected by moving it twice its distance from the plane, back along the normal:
+///
+/// `x' = x - 2(n·x + d)n = (I - 2nnᵀ)x - 2dn`
+///
+/// which is the affine matrix `[I - 2nnᵀ, -2dn]`. It's its own inverse, and its determinant is -1, so it reverses the
+/// winding of every triangle it transforms.
+///
+/// Each eye sees the reflection as if it were looking at the reflected world through the mirror, so the reflected
+/// view matrix for an eye with view matrix `V` is `V * R`. Points are reflected first, then viewed from the real eye.
+/// Equivalently, it's the view from the eye reflected behind the mirror, `R * eye`, with a mirrored basis. Each eye
+/// gets its own reflected view, so the reflection has the correct parallax in stereo. Shading uses that reflected
+/// eye position, as the world positions the shaders see aren't reflected.
+pub(crate) fn get_reflection_matrix(plane: &Vector4<f32>) -> Matrix4<f32> {
+    let normal = plane.xyz();
+    let mut reflection = (Matrix3::identity() - normal * normal.transpose() * 2.).to_homogeneous();
+    reflection
+        .fixed_slice_mut::<3, 1>(0, 3)
+        .copy_from(&(normal * -2. * plane.w));
+    reflection
+}
+
+/// Replace the near plane of `projection` with `plane`, in world space, for a camera with `view`, so that nothing
+/// behind the mirror shows up in its reflection. The far plane is tilted to keep as much depth precision as possible.
+///
+/// This is Eric Lengyel's "oblique near-plane clipping", adapted for Vulkan's 0 to 1 depth range: row 2 of the
+/// projection (which gives depth) is replaced with the plane in view space, scaled so that the far plane, where
+/// depth equals w, passes through the corner of the view frustum furthest from it.
+pub(crate) fn get_oblique_projection(
+    projection: &Matrix4<f32>,
+    view: &Matrix4<f32>,
+    plane: &Vector4<f32>,
+) -> Matrix4<f32> {
+    let (view_inverse, projection_inverse) = match (view.try_inverse(), projection.try_inverse()) {
+        (Some(v), Some(p)) => (v, p),
+        _ => return *projection,
+    };
+
+    // Planes are transformed by the inverse transpose of the matrix that transforms points.
+    let plane = view_inverse.transpose() * plane;
+    let clip_plane = projection_inverse.transpose() * plane;
+
+    // The corner of the frustum on the far side of the plane, in view space.
+    let corner = projection_inverse * vector![clip_plane.x.signum(), clip_plane.y.signum(), 1., 1.];
+    let scale = projection.row(3).transpose().dot(&corner) / plane.dot(&corner);
+
+    let mut oblique = *projection;
+    oblique.set_row(2, &(plane * scale).transpose());
+    oblique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::render_context::get_projection;
+    use approx::assert_relative_eq;
+    use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion};
+    use openxr as xr;
+
+    #[test]
+    pub fn test_reflection_matrix() {
+        // A mirror at z = -2, rotated to face +X, with a normal that's been scaled along with it.
+        let transform = Translation3::new(0., 0., -2.).to_homogeneous()
+            * UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2)
+                .to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&vector![3., 1., 1.]);
+        let plane = get_plane(&transform, &Vector3::z());
+        assert_relative_eq!(plane, vector![1., 0., 0., 0.], epsilon = 1e-5);
+
+        let reflection = get_reflection_matrix(&vector![0., 0., 1., 2.]);
+        let reflect = |p: Point3<f32>| reflection.transform_point(&p);
+        assert_relative_eq!(reflect(Point3::new(1., 2., 3.)), Point3::new(1., 2., -7.));
+        assert_relative_eq!(reflect(Point3::new(1., 2., -2.)), Point3::new(1., 2., -2.));
+        assert_relative_eq!(reflection * reflection, Matrix4::identity());
+        assert_relative_eq!(reflection.determinant(), -1.);
+    }
+
+    #[test]
+    pub fn test_oblique_projection() {
+        let fov = xr::Fovf {
+            angle_left: -0.8,
+            angle_right: 0.7,
+            angle_up: 0.75,
+            angle_down: -0.85,
+        };
+        let projection = get_projection(fov, 0.05, 100.);
+
+        // The eye is 2m in front of a mirror at the origin, facing +Z.
+        let plane = vector![0., 0., 1., 0.];
+        let eye =
+            Isometry3::look_at_rh(&Point3::new(0.2, 0., 2.), &Point3::origin(), &Vector3::y());
+        let view = eye.to_homogeneous() * get_reflection_matrix(&plane);
+        let oblique = get_oblique_projection(&projection, &view, &plane);
+        let ndc = |p: Point3<f32>| oblique.transform_point(&(view.transform_point(&p)));
+
+        // Points in front of the mirror are visible in it, where they'd be without the oblique near plane..
+        let point = Point3::new(0.3, -0.2, 1.);
+        let depth = ndc(point).z;
+        assert!(depth > 0. && depth < 1.);
+        let unclipped = (projection * view).transform_point(&point);
+        assert_relative_eq!(ndc(point).xy(), unclipped.xy(), epsilon = 1e-5);
+
+        // ..points on the mirror are on the near plane..
+        assert_relative_eq!(ndc(Point3::new(-0.4, 0.3, 0.)).z, 0., epsilon = 1e-4);
+
+        // ..and points behind it are clipped.
+        assert!(ndc(Point3::new(0.1, 0.1, -0.5)).z < 0.);
+    }
+}
@@ -1,40 +1,72 @@
 #![allow(missing_docs)]
 pub mod animation_controller;
 pub mod animation_target;
+pub mod billboard;
+pub mod camera_rig;
 pub mod collider;
+pub mod controller_pose;
+pub mod depth_bias;
 pub mod hand;
+pub mod highlight;
 pub mod info;
 pub mod joint;
 pub mod material;
 pub mod mesh;
+pub mod mirror;
 pub mod panel;
 pub mod parent;
 pub mod pointer;
+pub mod pointer_ray;
 pub mod primitive;
+pub mod reflection_probe;
+pub mod render_flags;
+pub mod render_layer;
 pub mod rigid_body;
 pub mod root;
 pub mod skin;
 pub mod sound_emitter;
+pub mod stencil_tag;
 pub mod transform;
 pub mod transform_matrix;
+pub mod ui_overlay;
+pub mod ui_panel;
+pub mod update_rate;
 pub mod visible;
+pub mod voxel_volume;
+pub mod wireframe_overlay;
 
 pub use animation_controller::AnimationController;
 pub use animation_target::AnimationTarget;
+pub use billboard::{Billboard, BillboardMode};
+pub use camera_rig::{CameraKeyframe, CameraRig, Easing};
 pub use collider::Collider;
+pub use controller_pose::{ControllerPose, ControllerPoseKind};
+pub use depth_bias::DepthBias;
 pub use hand::Hand;
+pub use highlight::Highlight;
 pub use info::Info;
 pub use joint::Joint;
-pub use material::Material;
+pub use material::{Material, TextureTransform};
 pub use mesh::Mesh;
+pub use mirror::Mirror;
 pub use panel::Panel;
 pub use parent::Parent;
 pub use pointer::Pointer;
+pub use pointer_ray::{PointerRay, PointerRayState};
 pub use primitive::Primitive;
+pub use reflection_probe::{CaptureMode, ReflectionProbe};
+pub use render_flags::RenderFlags;
+pub use render_layer::RenderLayer;
 pub use rigid_body::RigidBody;
 pub use root::Root;
 pub use skin::Skin;
 pub use sound_emitter::SoundEmitter;
+pub use stencil_tag::StencilTag;
 pub use transform::Transform;
 pub use transform_matrix::TransformMatrix;
+pub use ui_overlay::UiOverlay;
+pub use ui_panel::{UiEvent, UiPanel};
+pub use update_rate::UpdateRate;
 pub use visible::Visible;
+pub use voxel_volume::VoxelVolume;
+pub use wireframe_overlay::WireframeOverlay;
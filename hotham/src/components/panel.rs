@@ -4,13 +4,16 @@ use egui::epaint::Vertex as EguiVertex;
 use egui::{CtxRef, Pos2};
 use hecs::{Entity, World};
 use itertools::izip;
+use log::debug;
 use nalgebra::{vector, Vector3, Vector4};
 use rapier3d::prelude::{ColliderBuilder, InteractionGroups};
 
 const BUFFER_SIZE: usize = 1024;
 
-use crate::buffer::Buffer;
-use crate::components::mesh::MeshUBO;
+use crate::buffer::{Buffer, IndexBuffer};
+use crate::components::material::WORKFLOW_UNLIT_LINEAR_TEXTURE;
+use crate::components::mesh::{create_joint_buffer, MeshUBO};
+use crate::components::primitive::BoundingSphere;
 use crate::components::{Material, Mesh, Primitive};
 use crate::resources::gui_context::SCALE_FACTOR;
 use crate::resources::physics_context::PANEL_COLLISION_GROUP;
@@ -88,7 +91,7 @@ pub fn add_panel_to_world(
     physics_context: &mut PhysicsContext,
     world: &mut World,
 ) -> Entity {
-    println!("[PANEL] Adding panel with text {}", text);
+    debug!(target: "PANEL", "Adding panel with text {}", text);
     let extent = vk::Extent2D { width, height };
     let output_image = vulkan_context
         .create_image(
@@ -97,10 +100,11 @@ pub fn add_panel_to_world(
             vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
             1,
             1,
+            &format!("Panel Image for {}", text),
         )
         .unwrap();
     let sampler = vulkan_context
-        .create_texture_sampler(vk::SamplerAddressMode::REPEAT, 1)
+        .get_texture_sampler(&Default::default())
         .unwrap();
     let descriptor = vk::DescriptorImageInfo::builder()
         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
@@ -182,7 +186,7 @@ pub fn add_panel_to_world(
         handle,
     };
     world.insert_one(panel_entity, collider).unwrap();
-    println!("[PANEL] ..done! {:?}", panel_entity);
+    debug!(target: "PANEL", "..done! {:?}", panel_entity);
     panel_entity
 }
 
@@ -192,9 +196,25 @@ fn create_mesh(
     render_context: &RenderContext,
     extent: &vk::Extent2D,
 ) -> Mesh {
-    let (material, descriptor_set) =
-        get_material(&output_texture, &vulkan_context, &render_context);
     let (half_width, half_height) = get_panel_dimensions(&extent);
+    create_quad_mesh(
+        output_texture,
+        vulkan_context,
+        render_context,
+        half_width,
+        half_height,
+    )
+}
+
+/// Create an unlit quad in the XY plane, facing +Z, textured with `texture`
+pub(crate) fn create_quad_mesh(
+    texture: &Texture,
+    vulkan_context: &VulkanContext,
+    render_context: &RenderContext,
+    half_width: f32,
+    half_height: f32,
+) -> Mesh {
+    let (material, descriptor_set) = get_material(&texture, &vulkan_context, &render_context);
 
     let positions = [
         vector![-half_width, half_height, 0.],  // v0
@@ -221,22 +241,25 @@ fn create_mesh(
         vulkan_context,
         &vertices,
         vk::BufferUsageFlags::VERTEX_BUFFER,
+        "Quad Vertex Buffer",
     )
     .unwrap();
 
-    let index_buffer = Buffer::new(
-        vulkan_context,
-        &[0, 1, 2, 0, 3, 1],
-        vk::BufferUsageFlags::INDEX_BUFFER,
-    )
-    .unwrap();
+    let indices = vec![0, 1, 2, 0, 3, 1];
+    let index_buffer = IndexBuffer::new(vulkan_context, &indices, "Quad Index Buffer").unwrap();
 
     let primitive = Primitive {
         index_buffer,
         vertex_buffer,
-        indicies_count: 6,
+        indicies_count: indices.len() as _,
         material,
         texture_descriptor_set: descriptor_set,
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        bounding_sphere: BoundingSphere::from_vertices(&vertices),
+        vertices,
+        indices,
+        indirect_buffer: None,
+        custom_shader: None,
     };
 
     // Create descriptor sets
@@ -250,6 +273,7 @@ fn create_mesh(
         vulkan_context,
         &[mesh_ubo],
         vk::BufferUsageFlags::UNIFORM_BUFFER,
+        "Quad Mesh UBO",
     )
     .unwrap();
     vulkan_context.update_buffer_descriptor_set(
@@ -258,11 +282,15 @@ fn create_mesh(
         0,
         vk::DescriptorType::UNIFORM_BUFFER,
     );
+    let joint_buffer =
+        create_joint_buffer(vulkan_context, 1, descriptor_sets[0], "Quad Mesh").unwrap();
 
     Mesh {
         descriptor_sets,
         ubo_buffer,
         ubo_data: mesh_ubo,
+        joint_buffer,
+        joint_matrices: Vec::new(),
         primitives: vec![primitive],
     }
 }
@@ -296,6 +324,7 @@ fn get_material(
             &empty_texture,
             &empty_texture,
             &empty_texture,
+            &empty_texture,
         )
         .unwrap()[0];
 
@@ -304,7 +333,7 @@ fn get_material(
         emmissive_factor: Vector4::zeros(),
         diffuse_factor: Vector4::zeros(),
         specular_factor: Vector4::zeros(),
-        workflow: 2.,
+        workflow: WORKFLOW_UNLIT_LINEAR_TEXTURE,
         base_color_texture_set: 0,
         metallic_roughness_texture_set: -1,
         normal_texture_set: -1,
@@ -314,13 +343,17 @@ fn get_material(
         roughness_factor: 0.,
         alpha_mask: 0.,
         alpha_mask_cutoff: 1.,
+        double_sided: 0.,
+        height_texture_set: -1,
+        parallax_scale: 0.,
+        parallax_bias: 0.,
     };
 
     (material, descriptor_set)
 }
 
 fn create_mesh_buffers(vulkan_context: &VulkanContext) -> (Buffer<EguiVertex>, Buffer<u32>) {
-    println!("[HOTHAM_DRAW_GUI] Creating mesh buffers..");
+    debug!(target: "HOTHAM_DRAW_GUI", "Creating mesh buffers..");
     let vertices = (0..BUFFER_SIZE)
         .map(|_| Default::default())
         .collect::<Vec<_>>();
@@ -330,6 +363,7 @@ fn create_mesh_buffers(vulkan_context: &VulkanContext) -> (Buffer<EguiVertex>, B
         &vulkan_context,
         &vertices,
         vk::BufferUsageFlags::VERTEX_BUFFER,
+        "GUI Vertex Buffer",
     )
     .expect("Unable to create font index buffer");
 
@@ -337,10 +371,11 @@ fn create_mesh_buffers(vulkan_context: &VulkanContext) -> (Buffer<EguiVertex>, B
         &vulkan_context,
         &empty_index_buffer,
         vk::BufferUsageFlags::INDEX_BUFFER,
+        "GUI Index Buffer",
     )
     .expect("Unable to create font index buffer");
 
-    println!("[HOTHAM_DRAW_GUI] ..done!");
+    debug!(target: "HOTHAM_DRAW_GUI", "..done!");
 
     (vertex_buffer, index_buffer)
 }
@@ -0,0 +1,185 @@
+use nalgebra::{vector, Vector3, Vector4};
+
+use crate::raycast::RaycastHit;
+
+/// How many lines make up the reticle's ring
+const RETICLE_SEGMENTS: usize = 16;
+
+/// What a `PointerRay` is doing, which decides its colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerRayState {
+    /// The ray isn't pointing at anything
+    Idle,
+    /// The ray is pointing at something, but the trigger isn't pulled
+    Hover,
+    /// The ray is pointing at something and the trigger is pulled
+    Active,
+}
+
+/// Component added to an entity with a `Pointer` to draw its aim ray, so users can see what they're pointing at.
+/// Each frame `pointer_ray_system` casts a ray from the pointer with `raycast`, then draws a line to the first mesh it
+/// hits, ending in a ring-shaped reticle lying on the surface. If nothing is hit, a line `miss_length` long is drawn
+/// instead. The ray and reticle are drawn with debug lines, in the colour for the pointer's `PointerRayState`.
+///
+/// Basic usage:
+/// ```ignore
+/// use hotham::components::PointerRay;
+/// world.insert_one(right_pointer, PointerRay {
+///     hover_color: vector![0., 1., 0., 1.],
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointerRay {
+    /// Colour of the ray when it isn't pointing at anything
+    pub color: Vector4<f32>,
+    /// Colour of the ray and reticle when pointing at something
+    pub hover_color: Vector4<f32>,
+    /// Colour of the ray and reticle when pointing at something with the trigger pulled
+    pub active_color: Vector4<f32>,
+    /// How far away, in metres, meshes can be hit
+    pub max_distance: f32,
+    /// How long, in metres, the ray is drawn when it doesn't hit anything
+    pub miss_length: f32,
+    /// Radius of the reticle, in metres
+    pub reticle_radius: f32,
+    /// How far the trigger must be pulled for the ray to be active
+    pub trigger_threshold: f32,
+    /// What the ray hit this frame, if anything. Written by `pointer_ray_system`.
+    pub hit: Option<RaycastHit>,
+}
+
+impl Default for PointerRay {
+    fn default() -> Self {
+        Self {
+            color: vector![1., 1., 1., 0.5],
+            hover_color: vector![0.2, 0.6, 1., 1.],
+            active_color: vector![1., 0.8, 0.2, 1.],
+            max_distance: 40.,
+            miss_length: 2.,
+            reticle_radius: 0.01,
+            trigger_threshold: 0.5,
+            hit: None,
+        }
+    }
+}
+
+impl PointerRay {
+    /// What the ray is doing, given how far the pointer's trigger is pulled
+    pub fn state(&self, trigger_value: f32) -> PointerRayState {
+        match self.hit {
+            None => PointerRayState::Idle,
+            Some(_) if trigger_value >= self.trigger_threshold => PointerRayState::Active,
+            Some(_) => PointerRayState::Hover,
+        }
+    }
+
+    /// The colour the ray is drawn in when in `state`
+    pub fn get_color(&self, state: PointerRayState) -> Vector4<f32> {
+        match state {
+            PointerRayState::Idle => self.color,
+            PointerRayState::Hover => self.hover_color,
+            PointerRayState::Active => self.active_color,
+        }
+    }
+
+    /// The lines to draw for a ray cast from `origin` along `direction`, in world space: the ray itself first, then
+    /// the reticle if something was hit.
+    pub fn get_lines(
+        &self,
+        origin: &Vector3<f32>,
+        direction: &Vector3<f32>,
+    ) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        let direction = direction.normalize();
+        let hit = match self.hit {
+            Some(hit) => hit,
+            None => return vec![(*origin, origin + direction * self.miss_length)],
+        };
+
+        let end = origin + direction * hit.distance;
+        let mut lines = vec![(*origin, end)];
+
+        // Lay the reticle on the surface, lifted off it slightly so it isn't hidden by it.
+        let normal = if hit.normal.norm_squared() > 0. {
+            hit.normal.normalize()
+        } else {
+            -direction
+        };
+        let (u, v) = get_perpendicular_axes(&normal);
+        let center = end + normal * self.reticle_radius * 0.1;
+        lines.extend((0..RETICLE_SEGMENTS).map(|i| {
+            let point = |i: usize| {
+                let angle = i as f32 / RETICLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (u * angle.cos() + v * angle.sin()) * self.reticle_radius
+            };
+            (point(i), point(i + 1))
+        }));
+        lines
+    }
+}
+
+/// Two unit vectors perpendicular to `normal` and each other
+fn get_perpendicular_axes(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let other = if normal.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let u = normal.cross(&other).normalize();
+    (u, normal.cross(&u))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use hecs::World;
+    use nalgebra::{Point3, Vector2};
+
+    #[test]
+    pub fn test_pointer_ray_lines() {
+        let mut pointer_ray = PointerRay::default();
+        let origin = vector![1., 1., 0.];
+        let direction = vector![0., 0., -2.];
+
+        // Nothing hit: a ray `miss_length` long.
+        let lines = pointer_ray.get_lines(&origin, &direction);
+        assert_eq!(lines.len(), 1);
+        assert_relative_eq!(lines[0].1, vector![1., 1., -pointer_ray.miss_length]);
+        assert_eq!(pointer_ray.state(1.), PointerRayState::Idle);
+
+        // A wall three metres away: the ray ends where it was hit..
+        let entity = World::new().spawn(());
+        pointer_ray.hit = Some(RaycastHit {
+            entity,
+            primitive_index: 0,
+            distance: 3.,
+            point: Point3::new(1., 1., -3.),
+            triangle: [0, 1, 2],
+            barycentric: vector![1., 0., 0.],
+            uv: Vector2::zeros(),
+            normal: vector![0., 0., 1.],
+        });
+        let lines = pointer_ray.get_lines(&origin, &direction);
+        assert_eq!(lines.len(), 1 + RETICLE_SEGMENTS);
+        assert_relative_eq!((lines[0].1 - lines[0].0).norm(), 3.);
+        assert_relative_eq!(lines[0].1, pointer_ray.hit.unwrap().point.coords);
+
+        // ..with the reticle lying on the wall around the hit.
+        for (from, _) in &lines[1..] {
+            assert_relative_eq!(from.z, -3. + pointer_ray.reticle_radius * 0.1);
+            assert_relative_eq!(
+                (from.xy() - vector![1., 1.]).norm(),
+                pointer_ray.reticle_radius,
+                epsilon = 1e-6
+            );
+        }
+
+        assert_eq!(pointer_ray.state(0.2), PointerRayState::Hover);
+        assert_eq!(pointer_ray.state(0.8), PointerRayState::Active);
+        assert_eq!(
+            pointer_ray.get_color(PointerRayState::Active),
+            pointer_ray.active_color
+        );
+    }
+}
@@ -1,17 +1,28 @@
-use crate::{buffer::Buffer, resources::VulkanContext, vertex::Vertex};
+use crate::{
+    assets::AssetStore,
+    buffer::{Buffer, IndexBuffer},
+    gltf_loader::{MeshOptimisation, VertexColorSpace},
+    mesh_optimisation::{generate_normals, optimise_mesh},
+    resources::{marching_cubes::IndirectDraw, CustomShader, VulkanContext},
+    texture::Texture,
+    vertex::Vertex,
+};
 use anyhow::{anyhow, Result};
 use ash::vk;
 use itertools::izip;
-use nalgebra::vector;
+use nalgebra::{vector, Matrix4, Vector3};
 
-use super::Material;
+use super::{
+    material::{get_texture_transforms, MaterialExtensions},
+    Material,
+};
 
 /// Geometry for a mesh
 /// Automatically generated by `gltf_loader`
 #[derive(Debug, Clone, PartialEq)]
 pub struct Primitive {
-    /// Buffer for the indices
-    pub index_buffer: Buffer<u32>,
+    /// Buffer for the indices, stored as `u16` if possible
+    pub index_buffer: IndexBuffer,
     /// Buffer for the vertices
     pub vertex_buffer: Buffer<Vertex>,
     /// Number of vertices
@@ -20,9 +31,111 @@ pub struct Primitive {
     pub material: Material,
     /// Texture descriptor set
     pub texture_descriptor_set: vk::DescriptorSet,
+    /// How the vertices are assembled into primitives, taken from the glTF primitive's mode
+    pub topology: vk::PrimitiveTopology,
+    /// CPU-side copy of the vertices, used for raycasting
+    pub vertices: Vec<Vertex>,
+    /// CPU-side copy of the indices, used for raycasting
+    pub indices: Vec<u32>,
+    /// A sphere around the vertices, in the mesh's space. Used for culling.
+    pub bounding_sphere: BoundingSphere,
+    /// For geometry generated on the GPU, eg. by `MarchingCubes`, the draw parameters written alongside it. The
+    /// primitive is drawn with `cmd_draw_indexed_indirect`, so `indicies_count` is only the capacity of the index
+    /// buffer, and `vertices` and `indices` are empty.
+    pub indirect_buffer: Option<Buffer<IndirectDraw>>,
+    /// Shaders to draw the primitive with instead of Hotham's PBR shaders, eg. for water or toon shading. `None` by
+    /// default.
+    pub custom_shader: Option<CustomShader>,
+}
+
+/// A sphere enclosing some geometry. Used by `rendering_system` to skip meshes that can't be seen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    /// The centre of the sphere
+    pub center: Vector3<f32>,
+    /// The radius of the sphere
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Create a sphere around `vertices`, centred on their bounding box
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        if vertices.is_empty() {
+            return Self {
+                center: Vector3::zeros(),
+                radius: 0.,
+            };
+        }
+
+        let (min, max) = vertices.iter().fold(
+            (
+                Vector3::repeat(f32::INFINITY),
+                Vector3::repeat(f32::NEG_INFINITY),
+            ),
+            |(min, max), v| (min.inf(&v.position), max.sup(&v.position)),
+        );
+        let center = (min + max) * 0.5;
+        let radius = vertices
+            .iter()
+            .map(|v| (v.position - center).norm())
+            .fold(0., f32::max);
+        Self { center, radius }
+    }
+
+    /// The smallest sphere enclosing both `self` and `other`
+    pub fn union(&self, other: &BoundingSphere) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.norm();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        Self { center, radius }
+    }
+
+    /// Grow the sphere by `margin` in every direction
+    pub fn expand(&self, margin: f32) -> Self {
+        Self {
+            center: self.center,
+            radius: self.radius + margin,
+        }
+    }
+
+    /// Move the sphere by `transform`. The radius is scaled by the largest scale in `transform`, so the sphere
+    /// still encloses the geometry.
+    pub fn transform(&self, transform: &Matrix4<f32>) -> Self {
+        let scale = (0..3)
+            .map(|i| transform.fixed_slice::<3, 1>(0, i).norm())
+            .fold(0., f32::max);
+        Self {
+            center: transform.transform_point(&self.center.into()).coords,
+            radius: self.radius * scale,
+        }
+    }
 }
 
 impl Primitive {
+    /// Record a draw of this primitive into `command_buffer`. Its vertex and index buffers must already be bound.
+    pub(crate) fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            match &self.indirect_buffer {
+                Some(indirect_buffer) => device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    indirect_buffer.handle,
+                    0,
+                    1,
+                    std::mem::size_of::<IndirectDraw>() as _,
+                ),
+                None => device.cmd_draw_indexed(command_buffer, self.indicies_count, 1, 0, 0, 1),
+            }
+        }
+    }
+
     pub(crate) fn load(
         textures_layout: vk::DescriptorSetLayout,
         mesh_name: &str,
@@ -30,6 +143,12 @@ impl Primitive {
         buffer: &[u8],
         vulkan_context: &VulkanContext,
         images: &Vec<gltf::image::Data>,
+        optimisation: MeshOptimisation,
+        vertex_color_space: VertexColorSpace,
+        normal_smoothing_angle: f32,
+        import_scale: f32,
+        material_extensions: &MaterialExtensions,
+        textures: &mut AssetStore<Texture>,
     ) -> Result<Self> {
         let mut indices = Vec::new();
         let mut positions = Vec::new();
@@ -41,12 +160,12 @@ impl Primitive {
 
         let reader = primitive_data.reader(|_| Some(buffer));
 
-        // Positions
+        // Positions, scaled into metres before anything is computed from them
         for v in reader
             .read_positions()
             .ok_or(anyhow!("Mesh {} has no positions!", mesh_name))?
         {
-            positions.push(vector![v[0], v[1], v[2]]);
+            positions.push(vector![v[0], v[1], v[2]] * import_scale);
         }
 
         // Indices
@@ -55,8 +174,11 @@ impl Primitive {
                 indices.push(i);
             }
         }
+        let mode = primitive_data.mode();
+        let indices = get_indices(indices, positions.len(), mode);
 
-        // Normals
+        // Normals. Meshes without them have them generated below, once the vertices are built.
+        let has_normals = reader.read_normals().is_some();
         if let Some(iter) = reader.read_normals() {
             for v in iter {
                 normals.push(vector![v[0], v[1], v[2]]);
@@ -87,6 +209,20 @@ impl Primitive {
             }
         }
 
+        // Bake any KHR_texture_transforms into the texture coordinates.
+        let [transform_0, transform_1] =
+            get_texture_transforms(&primitive_data.material(), mesh_name);
+        for (tex_coords, transform) in [
+            (&mut tex_coords_0, transform_0),
+            (&mut tex_coords_1, transform_1),
+        ] {
+            if let Some(transform) = transform {
+                for uv in tex_coords.iter_mut() {
+                    *uv = transform.apply(*uv);
+                }
+            }
+        }
+
         if let Some(iter) = reader.read_joints(0) {
             for t in iter.into_u16() {
                 joint_indices.push(vector![t[0] as f32, t[1] as f32, t[2] as f32, t[3] as f32]);
@@ -114,9 +250,11 @@ impl Primitive {
             vulkan_context,
             buffer,
             images,
+            material_extensions.emissive_strength(&primitive_data.material()),
+            textures,
         )?;
 
-        let vertices: Vec<Vertex> = izip!(
+        let mut vertices: Vec<Vertex> = izip!(
             positions,
             normals,
             tex_coords_0,
@@ -128,24 +266,180 @@ impl Primitive {
         .map(Vertex::from_zip)
         .collect();
 
+        // Vertex colours. Vertices without them are white, so they don't tint the material.
+        if let Some(iter) = reader.read_colors(0) {
+            for (vertex, c) in vertices.iter_mut().zip(iter.into_rgba_f32()) {
+                vertex.color = match vertex_color_space {
+                    VertexColorSpace::Linear => vector![c[0], c[1], c[2], c[3]],
+                    VertexColorSpace::Srgb => vector![
+                        srgb_to_linear(c[0]),
+                        srgb_to_linear(c[1]),
+                        srgb_to_linear(c[2]),
+                        c[3]
+                    ],
+                };
+            }
+        }
+
+        // Without normals, lighting is impossible.
+        let (vertices, indices) = if !has_normals && mode == gltf::mesh::Mode::Triangles {
+            generate_normals(&vertices, &indices, normal_smoothing_angle)
+        } else {
+            (vertices, indices)
+        };
+        let (vertices, indices) =
+            optimise_mesh(vertices, indices, get_topology(mode), optimisation);
+
         // Create buffers
         let vertex_buffer = Buffer::new(
             &vulkan_context,
             &vertices,
             vk::BufferUsageFlags::VERTEX_BUFFER,
+            &format!("Vertex Buffer for {}", mesh_name),
         )?;
-        let index_buffer = Buffer::new(
+        let index_buffer = IndexBuffer::new(
             &vulkan_context,
             &indices,
-            vk::BufferUsageFlags::INDEX_BUFFER,
+            &format!("Index Buffer for {}", mesh_name),
         )?;
 
+        let bounding_sphere = BoundingSphere::from_vertices(&vertices);
+
         Ok(Primitive {
             material,
             index_buffer,
             vertex_buffer,
             indicies_count: indices.len() as _,
             texture_descriptor_set,
+            topology: get_topology(mode),
+            vertices,
+            indices,
+            bounding_sphere,
+            indirect_buffer: None,
+            custom_shader: None,
         })
     }
 }
+
+/// Get the Vulkan topology for a glTF primitive mode. Line loops must have their first index repeated at the end.
+pub(crate) fn get_topology(mode: gltf::mesh::Mode) -> vk::PrimitiveTopology {
+    match mode {
+        gltf::mesh::Mode::Points => vk::PrimitiveTopology::POINT_LIST,
+        gltf::mesh::Mode::Lines => vk::PrimitiveTopology::LINE_LIST,
+        gltf::mesh::Mode::LineLoop => vk::PrimitiveTopology::LINE_STRIP,
+        gltf::mesh::Mode::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+        gltf::mesh::Mode::Triangles => vk::PrimitiveTopology::TRIANGLE_LIST,
+        gltf::mesh::Mode::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+        gltf::mesh::Mode::TriangleFan => vk::PrimitiveTopology::TRIANGLE_FAN,
+    }
+}
+
+/// The indices a primitive with `vertex_count` vertices is drawn with in `mode`. Primitives are always drawn indexed, so
+/// unindexed primitives get an index for each vertex. Vulkan has no line loops, so they're closed and drawn as strips.
+fn get_indices(mut indices: Vec<u32>, vertex_count: usize, mode: gltf::mesh::Mode) -> Vec<u32> {
+    if indices.is_empty() {
+        indices = (0..vertex_count as u32).collect();
+    }
+    if mode == gltf::mesh::Mode::LineLoop {
+        if let Some(first) = indices.first().cloned() {
+            indices.push(first);
+        }
+    }
+    indices
+}
+
+/// Convert an sRGB encoded colour channel to linear, as the sRGB transfer function in the fragment shader does
+fn srgb_to_linear(c: f32) -> f32 {
+    if c < 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_topology_for_line_strip() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 36 }],
+                "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+                "accessors": [{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0, 0, 0],
+                    "max": [1, 1, 0]
+                }],
+                "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "mode": 3 }] }]
+            }"#,
+        )
+        .unwrap();
+        let primitive = gltf.meshes().next().unwrap().primitives().next().unwrap();
+        assert_eq!(primitive.mode(), gltf::mesh::Mode::LineStrip);
+        assert_eq!(
+            get_topology(primitive.mode()),
+            vk::PrimitiveTopology::LINE_STRIP
+        );
+        assert_eq!(
+            get_topology(gltf::mesh::Mode::Triangles),
+            vk::PrimitiveTopology::TRIANGLE_LIST
+        );
+    }
+
+    #[test]
+    pub fn test_unindexed_primitives_are_indexed() {
+        use gltf::mesh::Mode;
+
+        assert_eq!(get_indices(vec![], 3, Mode::LineStrip), [0, 1, 2]);
+        assert_eq!(get_indices(vec![], 3, Mode::Triangles), [0, 1, 2]);
+        assert_eq!(get_indices(vec![2, 1, 0], 3, Mode::Triangles), [2, 1, 0]);
+
+        // Line loops are closed, whether they're indexed or not.
+        assert_eq!(get_indices(vec![], 3, Mode::LineLoop), [0, 1, 2, 0]);
+        assert_eq!(get_indices(vec![2, 0, 1], 3, Mode::LineLoop), [2, 0, 1, 2]);
+        assert!(get_indices(vec![], 0, Mode::LineLoop).is_empty());
+    }
+
+    #[test]
+    pub fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert_eq!(srgb_to_linear(1.), 1.);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 0.001);
+        assert!((srgb_to_linear(0.02) - 0.02 / 12.92).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    pub fn test_bounding_sphere() {
+        let vertex = |x, y, z| Vertex {
+            position: vector![x, y, z],
+            ..Default::default()
+        };
+        let sphere = BoundingSphere::from_vertices(&[
+            vertex(-1., 0., 0.),
+            vertex(1., 0., 0.),
+            vertex(0., 0.5, 0.),
+        ]);
+        assert_eq!(sphere.center, vector![0., 0.25, 0.]);
+        assert!(sphere.radius >= (1.0_f32 + 0.25 * 0.25).sqrt());
+
+        let other = BoundingSphere {
+            center: vector![4., 0.25, 0.],
+            radius: 1.,
+        };
+        let union = sphere.union(&other);
+        assert!((union.center - sphere.center).norm() + sphere.radius <= union.radius + 1e-5);
+        assert!((union.center - other.center).norm() + other.radius <= union.radius + 1e-5);
+        assert_eq!(sphere.union(&sphere.expand(1.)), sphere.expand(1.));
+
+        let transform = Matrix4::new_translation(&vector![0., 1., 0.]) * Matrix4::new_scaling(2.);
+        let moved = other.transform(&transform);
+        assert_eq!(moved.center, vector![8., 1.5, 0.]);
+        assert_eq!(moved.radius, 2.);
+    }
+}
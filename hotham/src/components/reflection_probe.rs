@@ -0,0 +1,339 @@
+use std::f32::consts::FRAC_PI_4;
+
+use anyhow::Result;
+use ash::vk;
+use hecs::{Entity, World};
+use nalgebra::{vector, Isometry3, Matrix4, Point3, Vector3};
+use openxr as xr;
+
+use crate::{
+    buffer::Buffer,
+    image::Image,
+    resources::{render_context::get_projection, RenderContext, VulkanContext},
+    scene_data::SceneData,
+    texture::{SamplerInfo, Texture},
+    DEPTH_ATTACHMENT_USAGE_FLAGS,
+};
+
+use super::{Transform, TransformMatrix};
+
+/// Width and height of each face of a reflection probe's cubemap, in pixels
+pub const REFLECTION_PROBE_RESOLUTION: u32 = 256;
+/// Faces are captured two at a time, one per multiview layer
+pub(crate) const FACE_PAIRS: usize = 3;
+const NEAR: f32 = 0.05;
+const FAR: f32 = 100.0;
+
+/// When a `ReflectionProbe` captures its surroundings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// Capture once, when the probe is added. Use `ReflectionProbe::request_capture` to capture again.
+    Once,
+    /// Capture again every `frames` frames, so moving objects show up in reflections
+    Periodic { frames: usize },
+}
+
+/// Component added to an entity to capture its surroundings into a cubemap, used for specular reflections on nearby
+/// materials instead of the default image based lighting.
+///
+/// Materials sample the probe nearest the user, with rougher materials sampling lower (blurrier) mip levels.
+/// Probes without a capture yet aren't used. Only opaque and background meshes are captured, and at most one probe is
+/// captured each frame. See `reflection_probe_system`.
+///
+/// NOTE: The capture is the PBR pass's tonemapped output, not linear radiance, so reflections are slightly flatter than
+/// they are with the default IBL maps.
+#[derive(Debug, Clone)]
+pub struct ReflectionProbe {
+    pub capture_mode: CaptureMode,
+    pub(crate) cubemap: Texture,
+    pub(crate) mip_levels: u32,
+    pub(crate) targets: CaptureTargets,
+    /// The frame the probe was last captured on
+    pub(crate) last_capture: Option<usize>,
+}
+
+/// The render targets and scene data used to capture a probe's cubemap, two faces at a time
+#[derive(Debug, Clone)]
+pub(crate) struct CaptureTargets {
+    pub colour_image: Image,
+    pub depth_image: Image,
+    pub framebuffers: [vk::Framebuffer; FACE_PAIRS],
+    pub scene_data_buffers: Vec<Buffer<SceneData>>,
+    pub scene_data_descriptor_sets: [vk::DescriptorSet; FACE_PAIRS],
+}
+
+impl ReflectionProbe {
+    pub(crate) fn new(
+        capture_mode: CaptureMode,
+        vulkan_context: &VulkanContext,
+        render_context: &RenderContext,
+    ) -> Result<Self> {
+        let extent = vk::Extent2D {
+            width: REFLECTION_PROBE_RESOLUTION,
+            height: REFLECTION_PROBE_RESOLUTION,
+        };
+        let mip_levels = get_mip_levels(REFLECTION_PROBE_RESOLUTION);
+        let image = vulkan_context.create_image(
+            render_context.colour_format,
+            &extent,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            6,
+            mip_levels,
+            "Reflection Probe Cubemap",
+        )?;
+        let sampler = vulkan_context.get_texture_sampler(&SamplerInfo::new(
+            vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            mip_levels,
+        ))?;
+        let descriptor = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image.view)
+            .sampler(sampler)
+            .build();
+
+        let targets = CaptureTargets::new(&image, vulkan_context, render_context)?;
+
+        Ok(Self {
+            capture_mode,
+            cubemap: Texture {
+                image,
+                sampler,
+                descriptor,
+            },
+            mip_levels,
+            targets,
+            last_capture: None,
+        })
+    }
+
+    /// Capture the probe's surroundings again on the next frame, eg. after it's moved
+    pub fn request_capture(&mut self) {
+        self.last_capture = None;
+    }
+
+    /// Has the probe been captured yet?
+    pub fn is_captured(&self) -> bool {
+        self.last_capture.is_some()
+    }
+
+    /// Should the probe be captured on `frame_index`?
+    pub(crate) fn is_capture_due(&self, frame_index: usize) -> bool {
+        is_capture_due(self.capture_mode, self.last_capture, frame_index)
+    }
+}
+
+fn is_capture_due(
+    capture_mode: CaptureMode,
+    last_capture: Option<usize>,
+    frame_index: usize,
+) -> bool {
+    match (last_capture, capture_mode) {
+        (None, _) => true,
+        (Some(_), CaptureMode::Once) => false,
+        (Some(last_capture), CaptureMode::Periodic { frames }) => {
+            frame_index >= last_capture + frames.max(1)
+        }
+    }
+}
+
+impl CaptureTargets {
+    fn new(
+        cubemap: &Image,
+        vulkan_context: &VulkanContext,
+        render_context: &RenderContext,
+    ) -> Result<Self> {
+        let extent = cubemap.extent;
+        let colour_image = vulkan_context.create_image(
+            render_context.colour_format,
+            &extent,
+            vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            2,
+            1,
+            "Reflection Probe Colour Image",
+        )?;
+        let depth_image = vulkan_context.create_image(
+            render_context.depth_format,
+            &extent,
+            DEPTH_ATTACHMENT_USAGE_FLAGS,
+            2,
+            1,
+            "Reflection Probe Depth Image",
+        )?;
+
+        let mut framebuffers = [vk::Framebuffer::null(); FACE_PAIRS];
+        let mut scene_data_buffers = Vec::with_capacity(FACE_PAIRS);
+        let mut scene_data_descriptor_sets = [vk::DescriptorSet::null(); FACE_PAIRS];
+        for pair in 0..FACE_PAIRS {
+            // Each pair of faces is resolved into its two layers of the cubemap.
+            let face_view = create_face_pair_view(vulkan_context, cubemap, pair)?;
+            let attachments = [colour_image.view, depth_image.view, face_view];
+            framebuffers[pair] = unsafe {
+                vulkan_context.device.create_framebuffer(
+                    &vk::FramebufferCreateInfo::builder()
+                        .render_pass(render_context.render_pass)
+                        .attachments(&attachments)
+                        .width(extent.width)
+                        .height(extent.height)
+                        .layers(1),
+                    None,
+                )
+            }?;
+
+            // Filled in by `reflection_probe_system` as it records each capture.
+            let scene_data_buffer = Buffer::new(
+                vulkan_context,
+                &[SceneData::default()],
+                vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                &format!("Reflection Probe Scene Data Buffer {}", pair),
+            )?;
+            // Reflections in the capture itself come from the default IBL maps.
+            scene_data_descriptor_sets[pair] = vulkan_context.create_scene_data_descriptor_sets(
+                render_context.descriptor_set_layouts.scene_data_layout,
+                &scene_data_buffer,
+                &render_context.scene_params_buffer,
+                &render_context.diffuse_ibl,
+                &render_context.specular_ibl,
+                &render_context.brdf_lut,
+            )?[0];
+            scene_data_buffers.push(scene_data_buffer);
+        }
+
+        Ok(Self {
+            colour_image,
+            depth_image,
+            framebuffers,
+            scene_data_buffers,
+            scene_data_descriptor_sets,
+        })
+    }
+}
+
+/// Add a `ReflectionProbe` at `translation` to the world. It's captured on the next frame.
+pub fn add_reflection_probe_to_world(
+    translation: Vector3<f32>,
+    capture_mode: CaptureMode,
+    vulkan_context: &VulkanContext,
+    render_context: &RenderContext,
+    world: &mut World,
+) -> Result<Entity> {
+    let reflection_probe = ReflectionProbe::new(capture_mode, vulkan_context, render_context)?;
+    let transform = Transform {
+        translation,
+        ..Default::default()
+    };
+    Ok(world.spawn((reflection_probe, transform, TransformMatrix::default())))
+}
+
+/// The view matrix of each face of a cubemap captured at `position`, in the order of the cubemap's layers
+///
+/// The PBR shader flips the Y axis of reflection vectors before sampling a cubemap (to match the default IBL maps),
+/// so the faces are captured flipped in Y too: the +Y layer looks down, and the -Y layer looks up. Every face is still
+/// a rotation, so front faces aren't mirrored.
+pub(crate) fn get_face_views(position: &Vector3<f32>) -> [Matrix4<f32>; 6] {
+    let eye = Point3::from(*position);
+    let faces = [
+        (vector![1., 0., 0.], vector![0., -1., 0.]),
+        (vector![-1., 0., 0.], vector![0., -1., 0.]),
+        (vector![0., -1., 0.], vector![0., 0., -1.]),
+        (vector![0., 1., 0.], vector![0., 0., 1.]),
+        (vector![0., 0., 1.], vector![0., -1., 0.]),
+        (vector![0., 0., -1.], vector![0., -1., 0.]),
+    ];
+    faces.map(|(forward, up): (Vector3<f32>, Vector3<f32>)| {
+        Isometry3::look_at_rh(&eye, &(eye + forward), &up).to_homogeneous()
+    })
+}
+
+/// The projection used for each face of a cubemap: a square, 90 degree field of view
+pub(crate) fn get_face_projection() -> Matrix4<f32> {
+    let fov = xr::Fovf {
+        angle_left: -FRAC_PI_4,
+        angle_right: FRAC_PI_4,
+        angle_up: FRAC_PI_4,
+        angle_down: -FRAC_PI_4,
+    };
+    get_projection(fov, NEAR, FAR)
+}
+
+/// The number of mip levels needed to shrink a `resolution` image down to a single pixel
+fn get_mip_levels(resolution: u32) -> u32 {
+    32 - resolution.leading_zeros()
+}
+
+/// A view of the first mip level of two layers of `cubemap`, used to resolve a pair of faces into
+fn create_face_pair_view(
+    vulkan_context: &VulkanContext,
+    cubemap: &Image,
+    pair: usize,
+) -> Result<vk::ImageView> {
+    let create_info = vk::ImageViewCreateInfo::builder()
+        .image(cubemap.handle)
+        .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+        .format(cubemap.format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: (pair * 2) as u32,
+            layer_count: 2,
+        });
+    unsafe { vulkan_context.device.create_image_view(&create_info, None) }.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn test_capture_due() {
+        // Probes that haven't been captured yet are always due.
+        assert!(is_capture_due(CaptureMode::Once, None, 0));
+        assert!(!is_capture_due(CaptureMode::Once, Some(0), 100));
+
+        let periodic = CaptureMode::Periodic { frames: 10 };
+        assert!(is_capture_due(periodic, None, 5));
+        assert!(!is_capture_due(periodic, Some(5), 14));
+        assert!(is_capture_due(periodic, Some(5), 15));
+
+        // Capturing every 0 frames is treated as every frame.
+        let every_frame = CaptureMode::Periodic { frames: 0 };
+        assert!(!is_capture_due(every_frame, Some(5), 5));
+        assert!(is_capture_due(every_frame, Some(5), 6));
+    }
+
+    #[test]
+    pub fn test_face_views() {
+        let position = vector![1., 2., 3.];
+        let views = get_face_views(&position);
+        let projection = get_face_projection();
+
+        // Each point on a face of the capture must be where the shader samples it from: the direction given by the
+        // cube map face selection table in the Vulkan spec, flipped in Y.
+        for (face, view) in views.iter().enumerate() {
+            for (s, t) in [(0., 0.), (0.5, -0.25), (-0.75, 0.5), (0.9, 0.9)] {
+                let direction = get_cube_direction(face, s, t);
+                let direction = vector![direction.x, -direction.y, direction.z];
+                let point = Point3::from(position + direction);
+                let ndc = (projection * view).transform_point(&point);
+                assert_relative_eq!(ndc.x, s, epsilon = 1e-5);
+                assert_relative_eq!(ndc.y, t, epsilon = 1e-5);
+            }
+        }
+    }
+
+    /// The direction of the point (`s`, `t`) on `face` of a cubemap, with `s` and `t` from -1 to 1
+    fn get_cube_direction(face: usize, s: f32, t: f32) -> Vector3<f32> {
+        match face {
+            0 => vector![1., -t, -s],
+            1 => vector![-1., -t, s],
+            2 => vector![s, 1., t],
+            3 => vector![s, -1., -t],
+            4 => vector![s, -t, 1.],
+            _ => vector![-s, -t, -1.],
+        }
+    }
+}
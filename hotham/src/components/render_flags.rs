@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Component used to control how an entity is rendered without removing it from the world.
+/// Entities without `RenderFlags` are rendered as if they had `RenderFlags::default()`.
+///
+/// - `visible`: if false, the entity is skipped entirely by `rendering_system`
+///
+/// Basic usage:
+/// ```ignore
+/// use hotham::components::RenderFlags;
+/// world.insert_one(entity, RenderFlags { visible: false });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenderFlags {
+    pub visible: bool,
+}
+
+impl Default for RenderFlags {
+    fn default() -> Self {
+        Self { visible: true }
+    }
+}
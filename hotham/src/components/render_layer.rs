@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// Component used to group entities into draw-order buckets.
+///
+/// Layers are drawn in ascending order. Entities without a `RenderLayer` are drawn in `RenderLayer::OPAQUE`.
+/// - Entities in a transparent layer are sorted back-to-front and alpha blended
+/// - Entities in an additive layer (`ADDITIVE` up to `OVERLAY`) are transparent, but their colour is added to what's
+///   behind them instead of blended with it, eg. for fire or magic effects
+/// - Entities in an overlay layer (`OVERLAY`, `UI` and above) are drawn without depth testing
+///
+/// Basic usage:
+/// ```ignore
+/// use hotham::components::RenderLayer;
+/// world.insert_one(entity, RenderLayer::UI);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RenderLayer(pub u32);
+
+impl RenderLayer {
+    pub const BACKGROUND: RenderLayer = RenderLayer(0);
+    pub const OPAQUE: RenderLayer = RenderLayer(1000);
+    pub const TRANSPARENT: RenderLayer = RenderLayer(2000);
+    pub const ADDITIVE: RenderLayer = RenderLayer(2500);
+    pub const OVERLAY: RenderLayer = RenderLayer(3000);
+    pub const UI: RenderLayer = RenderLayer(4000);
+
+    /// Should entities in this layer be sorted back-to-front and blended?
+    pub fn is_transparent(&self) -> bool {
+        *self >= Self::TRANSPARENT && *self < Self::OVERLAY
+    }
+
+    /// Should entities in this layer be added to what's behind them, rather than blended with it?
+    pub fn is_additive(&self) -> bool {
+        *self >= Self::ADDITIVE && *self < Self::OVERLAY
+    }
+
+    /// Should entities in this layer be drawn on top of everything, ignoring depth?
+    pub fn is_overlay(&self) -> bool {
+        *self >= Self::OVERLAY
+    }
+}
+
+impl Default for RenderLayer {
+    fn default() -> Self {
+        Self::OPAQUE
+    }
+}
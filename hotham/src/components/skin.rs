@@ -4,4 +4,28 @@
 pub struct Skin {
     /// List of joints, represented by their node ID
     pub joint_ids: Vec<usize>,
+    /// How far the animated mesh can reach outside its bind pose, in the mesh's space. The mesh's bounds are expanded
+    /// by this much when culling, so animated limbs don't disappear. `None` (the default) calculates it from the
+    /// furthest any joint moves in the animations the `AnimationController` is blending between.
+    pub culling_margin: Option<f32>,
+    /// The margin calculated by `skinning_system`, and the (`blend_from`, `blend_to`) it was calculated for
+    pub(crate) animated_margin: Option<((usize, usize), f32)>,
+}
+
+impl Skin {
+    /// Create a skin for `joint_ids`, with its culling margin calculated from its animations
+    pub fn new(joint_ids: Vec<usize>) -> Self {
+        Self {
+            joint_ids,
+            culling_margin: None,
+            animated_margin: None,
+        }
+    }
+
+    /// How much the mesh's bounds are expanded by when culling
+    pub fn margin(&self) -> f32 {
+        self.culling_margin
+            .or_else(|| self.animated_margin.map(|(_, margin)| margin))
+            .unwrap_or(0.)
+    }
 }
@@ -0,0 +1,72 @@
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+/// How far up the stencil buffer tags are written. The bits below are Hotham's own: bit 0 marks highlighted objects
+/// for their outline, and bit 1 marks where `Mirror`s are visible.
+pub const STENCIL_TAG_SHIFT: u32 = 2;
+/// The bits of the stencil buffer that hold tags
+pub const STENCIL_TAG_MASK: u32 = 0xFC;
+
+/// Component used to tag an entity in the stencil buffer wherever it's drawn, so a later pass can draw only where
+/// (or only where not) entities with that tag were drawn, eg. for x-ray vision or masked rendering. Written by
+/// `rendering_system`, alongside the bit that marks highlighted objects. Entities without `StencilTag` aren't tagged.
+///
+/// Tags go in the top six bits of the stencil buffer, so they're 1 to 63. Pipelines for the later pass can test for
+/// them with `get_stencil_test_state`. Nothing is written if the depth format has no stencil component: see
+/// `has_stencil_component`.
+///
+/// Basic usage:
+/// ```ignore
+/// use hotham::components::StencilTag;
+/// world.insert_one(enemy, StencilTag(1));
+/// // Later, in a pipeline that draws the x-ray effect:
+/// let stencil_state = StencilTag(1).get_stencil_test_state(vk::CompareOp::EQUAL);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct StencilTag(pub u8);
+
+impl StencilTag {
+    /// The largest tag that fits in the stencil buffer
+    pub const MAX: u8 = (STENCIL_TAG_MASK >> STENCIL_TAG_SHIFT) as u8;
+
+    /// The bits written to the stencil buffer for this tag. Tags above `MAX` lose their top bits.
+    pub fn stencil_bits(self) -> u32 {
+        ((self.0 as u32) << STENCIL_TAG_SHIFT) & STENCIL_TAG_MASK
+    }
+
+    /// Stencil state for a pipeline that tests against this tag, without writing to the stencil buffer. With
+    /// `CompareOp::EQUAL` it only draws where an entity with this tag was drawn; with `CompareOp::NOT_EQUAL`,
+    /// everywhere else. Hotham's own bits are masked out, so highlighted entities still match.
+    pub fn get_stencil_test_state(self, compare_op: vk::CompareOp) -> vk::StencilOpState {
+        vk::StencilOpState::builder()
+            .fail_op(vk::StencilOp::KEEP)
+            .pass_op(vk::StencilOp::KEEP)
+            .depth_fail_op(vk::StencilOp::KEEP)
+            .compare_op(compare_op)
+            .compare_mask(STENCIL_TAG_MASK)
+            .write_mask(0x00)
+            .reference(self.stencil_bits())
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_stencil_tag_bits() {
+        assert_eq!(StencilTag::MAX, 63);
+        assert_eq!(StencilTag(0).stencil_bits(), 0);
+        assert_eq!(StencilTag(1).stencil_bits(), 0b100);
+        assert_eq!(StencilTag(StencilTag::MAX).stencil_bits(), STENCIL_TAG_MASK);
+
+        // Tags never touch Hotham's own bits.
+        assert_eq!(StencilTag(u8::MAX).stencil_bits() & !STENCIL_TAG_MASK, 0);
+
+        let state = StencilTag(5).get_stencil_test_state(vk::CompareOp::EQUAL);
+        assert_eq!(state.reference, 5 << STENCIL_TAG_SHIFT);
+        assert_eq!(state.compare_mask, STENCIL_TAG_MASK);
+        assert_eq!(state.write_mask, 0);
+    }
+}
@@ -0,0 +1,125 @@
+use nalgebra::{vector, Isometry3, Matrix4, Point3, Translation3, Vector2, Vector3};
+
+/// How far in front of the user UI overlays are placed by default, in metres. Comfortable to focus on for long
+/// periods, and close enough to sit in front of most of the scene.
+pub const DEFAULT_UI_DISTANCE: f32 = 2.;
+
+/// Component that keeps an entity in front of the user's eyes, like a HUD. Positioned by `ui_overlay_system`.
+///
+/// Painting UI straight into clip space puts it at the same place in both eyes, so the eyes converge at infinity and
+/// the UI is seen double against anything nearer. Instead, the overlay is placed in the world at `distance` from the
+/// head, so each eye sees it from its own position with the right disparity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiOverlay {
+    /// Where the overlay sits in the user's view, as the tangent of the angle right and up from straight ahead. The
+    /// overlay stays at the same place in view whatever its `distance`.
+    pub offset: Vector2<f32>,
+    /// How far in front of the user the overlay is, in metres. Defaults to `DEFAULT_UI_DISTANCE`.
+    pub distance: f32,
+}
+
+impl Default for UiOverlay {
+    fn default() -> Self {
+        Self {
+            offset: Vector2::zeros(),
+            distance: DEFAULT_UI_DISTANCE,
+        }
+    }
+}
+
+impl UiOverlay {
+    /// Create an overlay at `offset` in the user's view, `DEFAULT_UI_DISTANCE` away
+    pub fn new(offset: Vector2<f32>) -> Self {
+        Self {
+            offset,
+            ..Default::default()
+        }
+    }
+
+    /// The pose of the overlay in world space, given the pose of the user's head. The overlay faces the head.
+    pub fn get_pose(&self, head_pose: &Isometry3<f32>) -> Isometry3<f32> {
+        let position = vector![self.offset.x, self.offset.y, -1.] * self.distance;
+        head_pose * Translation3::from(position)
+    }
+}
+
+/// Where each eye sees `position`, in normalized device coordinates, given the view projection matrix of each eye.
+/// The horizontal difference between them is the disparity the eyes converge on: zero for a point at infinity, and
+/// growing as it gets closer.
+pub fn get_eye_offsets(
+    position: &Vector3<f32>,
+    view_projections: &[Matrix4<f32>; 2],
+) -> [Vector2<f32>; 2] {
+    let position = Point3::from(*position);
+    [0, 1].map(|eye| view_projections[eye].transform_point(&position).coords.xy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::render_context::get_projection;
+    use approx::assert_relative_eq;
+    use nalgebra::UnitQuaternion;
+    use openxr::Fovf;
+
+    #[test]
+    pub fn test_ui_overlay_disparity() {
+        let ipd = 0.064;
+        let head_pose = Isometry3::from_parts(
+            Translation3::new(0.5, 1.6, 0.),
+            UnitQuaternion::from_euler_angles(0., 0.3, 0.),
+        );
+
+        // Eyes either side of the head, with symmetric 90 degree fields of view.
+        let fov = Fovf {
+            angle_left: -std::f32::consts::FRAC_PI_4,
+            angle_right: std::f32::consts::FRAC_PI_4,
+            angle_up: std::f32::consts::FRAC_PI_4,
+            angle_down: -std::f32::consts::FRAC_PI_4,
+        };
+        let projection = get_projection(fov, 0.05, 100.);
+        let view_projections = [-0.5, 0.5].map(|side| {
+            let eye = head_pose * Translation3::new(side * ipd, 0., 0.);
+            projection * eye.inverse().to_homogeneous()
+        });
+
+        // Straight ahead, each eye sees the overlay half the disparity from the centre, towards the nose..
+        let overlay = UiOverlay::default();
+        let position = overlay.get_pose(&head_pose).translation.vector;
+        let [left, right] = get_eye_offsets(&position, &view_projections);
+        let disparity = ipd / DEFAULT_UI_DISTANCE * projection[(0, 0)];
+        assert_relative_eq!(left.x - right.x, disparity, epsilon = 1e-5);
+        assert_relative_eq!(left.x, -right.x, epsilon = 1e-5);
+        assert_relative_eq!(left.y, right.y, epsilon = 1e-5);
+
+        // ..the disparity halves when the overlay is twice as far away..
+        let overlay = UiOverlay {
+            distance: DEFAULT_UI_DISTANCE * 2.,
+            ..overlay
+        };
+        let position = overlay.get_pose(&head_pose).translation.vector;
+        let [left, right] = get_eye_offsets(&position, &view_projections);
+        assert_relative_eq!(left.x - right.x, disparity * 0.5, epsilon = 1e-5);
+
+        // ..and an offset overlay stays at the same place in view whatever its distance.
+        let overlay = UiOverlay::new(vector![0.2, -0.1]);
+        let near = get_eye_offsets(
+            &overlay.get_pose(&head_pose).translation.vector,
+            &view_projections,
+        );
+        let overlay = UiOverlay {
+            distance: 10.,
+            ..overlay
+        };
+        let far = get_eye_offsets(
+            &overlay.get_pose(&head_pose).translation.vector,
+            &view_projections,
+        );
+        assert_relative_eq!(near[0].y, far[0].y, epsilon = 1e-5);
+        assert_relative_eq!(
+            (near[0].x + near[1].x) * 0.5,
+            (far[0].x + far[1].x) * 0.5,
+            epsilon = 1e-5
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::Result;
+use ash::vk;
+use hecs::{Entity, World};
+use nalgebra::{vector, Matrix4, Point3, Vector2, Vector3};
+
+use crate::{
+    resources::{RenderContext, VulkanContext},
+    texture::Texture,
+    COLOR_FORMAT,
+};
+
+use super::{hand::Handedness, panel::create_quad_mesh, Transform, TransformMatrix, Visible};
+
+/// Rays closer to parallel with a panel than this are treated as missing it
+const EPSILON: f32 = 1e-6;
+
+/// Something a pointer did to a `UiPanel` this frame. Positions are UV coordinates, with (0, 0) at the top left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiEvent {
+    /// A pointer is over the panel
+    Hover(Vector2<f32>),
+    /// The trigger was pulled while pointing at the panel
+    Click(Vector2<f32>),
+    /// The thumbstick was pushed while pointing at the panel, from -1.0 to 1.0 on each axis.
+    /// +Y is pushing the thumbstick forward.
+    Scroll(Vector2<f32>),
+}
+
+/// A component added to an entity to display an in-world menu: a quad showing a texture that you render into.
+/// Pointers are raycast against it by `ui_panel_system`, which fills `events` each frame.
+///
+/// Basic usage:
+/// ```ignore
+/// let entity = add_ui_panel_to_world(vector![0.5, 0.3], resolution, translation, &engine.vulkan_context, &engine.render_context, &mut world)?;
+/// for event in &world.get::<UiPanel>(entity)?.events {
+///     if let UiEvent::Click(uv) = event {
+///         click_menu_item_at(uv);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct UiPanel {
+    /// The texture drawn on the panel. Render your UI into it.
+    pub texture: Texture,
+    /// Width and height of the panel, in metres
+    pub size: Vector2<f32>,
+    /// What pointers did to the panel this frame
+    pub events: Vec<UiEvent>,
+    /// The pointers holding their trigger down over the panel. Used so each pointer only clicks once per pull.
+    pub(crate) pressed: Vec<Handedness>,
+}
+
+impl UiPanel {
+    /// Where the first pointer over the panel is this frame, if any
+    pub fn hover_uv(&self) -> Option<Vector2<f32>> {
+        self.events.iter().find_map(|e| match e {
+            UiEvent::Hover(uv) => Some(*uv),
+            _ => None,
+        })
+    }
+}
+
+/// Convenience function to create a UI panel and add it to a World.
+/// `resolution` is the size of the texture to render into.
+pub fn add_ui_panel_to_world(
+    size: Vector2<f32>,
+    resolution: vk::Extent2D,
+    translation: Vector3<f32>,
+    vulkan_context: &VulkanContext,
+    render_context: &RenderContext,
+    world: &mut World,
+) -> Result<Entity> {
+    let image = vulkan_context.create_image(
+        COLOR_FORMAT,
+        &resolution,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        1,
+        1,
+        "UI Panel Image",
+    )?;
+    let sampler = vulkan_context.get_texture_sampler(&Default::default())?;
+    let descriptor = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image.view)
+        .sampler(sampler)
+        .build();
+    let texture = Texture {
+        image,
+        sampler,
+        descriptor,
+    };
+    let mesh = create_quad_mesh(
+        &texture,
+        vulkan_context,
+        render_context,
+        size.x * 0.5,
+        size.y * 0.5,
+    );
+
+    Ok(world.spawn((
+        UiPanel {
+            texture,
+            size,
+            events: Vec::new(),
+            pressed: Vec::new(),
+        },
+        mesh,
+        Transform {
+            translation,
+            ..Default::default()
+        },
+        TransformMatrix::default(),
+        Visible {},
+    )))
+}
+
+/// Cast a ray against a panel of `size` drawn with `transform`. Returns the distance to the hit, in world space, and
+/// the UV coordinates that were hit. Panels can be hit from either side.
+pub(crate) fn raycast_ui_panel(
+    size: &Vector2<f32>,
+    transform: &Matrix4<f32>,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+) -> Option<(f32, Vector2<f32>)> {
+    let inverse_transform = transform.try_inverse()?;
+    let direction = direction.normalize();
+
+    // The panel is a quad in its XY plane: see `create_quad_mesh`.
+    let model_origin = inverse_transform.transform_point(origin);
+    let model_direction = inverse_transform.transform_vector(&direction);
+    if model_direction.z.abs() < EPSILON {
+        return None;
+    }
+
+    // The model space direction is unnormalised, so this is the distance in world units.
+    let distance = -model_origin.z / model_direction.z;
+    if distance < 0. {
+        return None;
+    }
+
+    let hit_point = model_origin + model_direction * distance;
+    let uv = vector![hit_point.x / size.x + 0.5, 0.5 - hit_point.y / size.y];
+    if !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y) {
+        return None;
+    }
+
+    Some((distance, uv))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::{Isometry3, Translation3, UnitQuaternion};
+
+    #[test]
+    pub fn test_raycast_ui_panel() {
+        // A 2m x 1m panel, 3m in front of the origin.
+        let size = vector![2., 1.];
+        let transform = Isometry3::translation(0., 1., -3.).to_homogeneous();
+        let origin = Point3::new(0., 1., 0.);
+
+        // Straight ahead is the middle of the panel..
+        let (distance, uv) =
+            raycast_ui_panel(&size, &transform, &origin, &vector![0., 0., -1.]).unwrap();
+        assert_relative_eq!(distance, 3.);
+        assert_relative_eq!(uv, vector![0.5, 0.5]);
+
+        // ..and a point up and to the left is towards the top left.
+        let hit_point = Point3::new(-0.5, 1.25, -3.);
+        let (_, uv) = raycast_ui_panel(&size, &transform, &origin, &(hit_point - origin)).unwrap();
+        assert_relative_eq!(uv, vector![0.25, 0.25], epsilon = 1e-5);
+
+        // Scaling and turning the panel is taken into account.
+        let transform = Isometry3::from_parts(
+            Translation3::new(3., 1., 0.),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -std::f32::consts::FRAC_PI_2),
+        )
+        .to_homogeneous()
+            * Matrix4::new_scaling(2.);
+        let hit_point = Point3::new(3., 1.5, 1.);
+        let (distance, uv) =
+            raycast_ui_panel(&size, &transform, &origin, &(hit_point - origin)).unwrap();
+        assert_relative_eq!(distance, (hit_point - origin).norm(), epsilon = 1e-5);
+        assert_relative_eq!(uv, vector![0.75, 0.25], epsilon = 1e-5);
+
+        // Missing the panel, or pointing away from it.
+        assert!(raycast_ui_panel(&size, &transform, &origin, &vector![0., 0., -1.]).is_none());
+        assert!(raycast_ui_panel(&size, &transform, &origin, &vector![-1., 0., 0.]).is_none());
+    }
+}
@@ -0,0 +1,74 @@
+/// Component that throttles how often an entity is updated, so distant or numerous entities, like the members of a
+/// crowd, don't cost CPU time every frame. Add it to an entity with an `AnimationController` and `animation_system`
+/// only samples that controller's animations every `every` frames, holding its bones where they were in between.
+///
+/// Held bones keep their last `Transform`, so their transform matrices are still valid every frame: they just move
+/// less smoothly. Root motion is still applied every frame, so the entity itself keeps moving smoothly.
+///
+/// Entities with the same `every` are all updated on the same frames unless they're given different `offset`s, which
+/// spreads the cost of a crowd evenly over the frames:
+/// ```ignore
+/// for (i, entity) in crowd.iter().enumerate() {
+///     world.insert_one(*entity, UpdateRate::new(4).with_offset(i as u32));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateRate {
+    /// Update the entity once every this many frames. `1`, or `0`, updates it every frame.
+    pub every: u32,
+    /// Which of the `every` frames the entity is updated on
+    pub offset: u32,
+    /// Frames counted since the component was added
+    frame: u32,
+}
+
+impl UpdateRate {
+    /// Update the entity once every `every` frames, starting with the first
+    pub fn new(every: u32) -> Self {
+        Self {
+            every,
+            offset: 0,
+            frame: 0,
+        }
+    }
+
+    /// Update the entity `offset` frames later than other entities with the same `every`
+    pub fn with_offset(self, offset: u32) -> Self {
+        Self { offset, ..self }
+    }
+
+    /// Count a frame, returning whether the entity should be updated on it. Call it exactly once per frame.
+    pub fn tick(&mut self) -> bool {
+        let every = self.every.max(1);
+        let is_due = self.frame % every == self.offset % every;
+        self.frame = (self.frame + 1) % every;
+        is_due
+    }
+}
+
+impl Default for UpdateRate {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_update_rate_tick() {
+        let ticks = |mut rate: UpdateRate| (0..6).map(|_| rate.tick()).collect::<Vec<_>>();
+
+        assert_eq!(ticks(UpdateRate::default()), [true; 6]);
+        assert_eq!(ticks(UpdateRate::new(0)), [true; 6]);
+        assert_eq!(
+            ticks(UpdateRate::new(3)),
+            [true, false, false, true, false, false]
+        );
+        assert_eq!(
+            ticks(UpdateRate::new(3).with_offset(4)),
+            [false, true, false, false, true, false]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use nalgebra::{vector, Vector3, Vector4};
+
+use super::primitive::BoundingSphere;
+
+/// Component holding a density field sampled on a regular grid, eg. for procedural terrain or sculpting. Turned into
+/// a `Mesh` on the GPU by `MarchingCubes::create_mesh`: the surface lies where the density crosses `iso_level`, with
+/// denser voxels inside.
+///
+/// The volume's origin is the first voxel, and it extends `(resolution - 1) * voxel_size` along each axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelVolume {
+    /// The number of voxels along each axis. Each must be at least 2.
+    pub resolution: [u32; 3],
+    /// The distance between neighbouring voxels, in metres
+    pub voxel_size: f32,
+    /// The density of each voxel. x varies fastest, then y, then z: see `VoxelVolume::index`.
+    pub density: Vec<f32>,
+    /// Density at the surface. Voxels denser than this are inside. Defaults to 0.
+    pub iso_level: f32,
+    /// Linear RGBA colour of the generated vertices. Defaults to white.
+    pub color: Vector4<f32>,
+    /// The most triangles the generated mesh can hold. Any more are dropped. Defaults to enough for a few closed
+    /// surfaces spanning the volume: raise it for noisy density fields.
+    pub max_triangles: u32,
+}
+
+impl VoxelVolume {
+    /// Create a volume from `density`, which must have a value for every voxel
+    pub fn new(resolution: [u32; 3], voxel_size: f32, density: Vec<f32>) -> Self {
+        assert!(
+            resolution.iter().all(|r| *r >= 2),
+            "A voxel volume needs at least 2 voxels along each axis"
+        );
+        assert_eq!(
+            density.len(),
+            resolution.iter().product::<u32>() as usize,
+            "Density must have a value for every voxel"
+        );
+        let [x, y, z] = resolution;
+        Self {
+            resolution,
+            voxel_size,
+            density,
+            iso_level: 0.,
+            color: Vector4::repeat(1.),
+            max_triangles: 4 * (x * y + y * z + z * x),
+        }
+    }
+
+    /// Create a volume by evaluating `density` at the position of each voxel, in the volume's space
+    pub fn from_fn(
+        resolution: [u32; 3],
+        voxel_size: f32,
+        density: impl Fn(Vector3<f32>) -> f32,
+    ) -> Self {
+        let [rx, ry, rz] = resolution;
+        let mut values = Vec::with_capacity((rx * ry * rz) as usize);
+        for z in 0..rz {
+            for y in 0..ry {
+                for x in 0..rx {
+                    values.push(density(vector![x as f32, y as f32, z as f32] * voxel_size));
+                }
+            }
+        }
+        Self::new(resolution, voxel_size, values)
+    }
+
+    /// The index of the voxel at `(x, y, z)` in `density`
+    pub fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        let [rx, ry, _] = self.resolution;
+        (x + y * rx + z * rx * ry) as usize
+    }
+
+    /// The size of the volume along each axis, in metres
+    pub fn extent(&self) -> Vector3<f32> {
+        Vector3::from(self.resolution).map(|r| (r - 1) as f32) * self.voxel_size
+    }
+
+    /// A sphere around the whole volume, which encloses any surface generated from it
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let extent = self.extent();
+        BoundingSphere {
+            center: extent * 0.5,
+            radius: extent.norm() * 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_voxel_volume() {
+        let volume = VoxelVolume::from_fn([2, 3, 4], 0.5, |p| p.x + p.y * 10. + p.z * 100.);
+        assert_eq!(volume.density.len(), 24);
+        assert_eq!(volume.density[volume.index(1, 2, 3)], 0.5 + 10. + 150.);
+        assert_eq!(volume.extent(), vector![0.5, 1., 1.5]);
+        assert_eq!(volume.bounding_sphere().center, vector![0.25, 0.5, 0.75]);
+        assert_eq!(volume.max_triangles, 4 * (6 + 12 + 8));
+    }
+}
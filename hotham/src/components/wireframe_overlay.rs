@@ -0,0 +1,20 @@
+use nalgebra::{vector, Vector4};
+
+/// Component used to draw the edges of an entity's triangles over it in a solid colour, eg. to show which object is
+/// selected in an editor. Other entities are still only drawn filled. Consumed by `rendering_system`.
+///
+/// Remove the component to stop drawing the overlay. Needs the `fillModeNonSolid` device feature: without it, the
+/// overlay isn't drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WireframeOverlay {
+    /// Colour of the wireframe, in linear RGBA
+    pub color: Vector4<f32>,
+}
+
+impl Default for WireframeOverlay {
+    fn default() -> Self {
+        Self {
+            color: vector![1., 0.5, 0., 1.],
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use ash::vk;
+use nalgebra::{Vector3, Vector4};
+
+/// The maximum number of line vertices that can be drawn each frame
+pub const MAX_DEBUG_LINE_VERTICES: usize = 4096;
+
+/// A vertex used to draw debug lines
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq, Default)]
+pub struct DebugLineVertex {
+    pub position: Vector3<f32>,
+    pub color: Vector4<f32>,
+}
+
+impl DebugLineVertex {
+    pub fn new(position: Vector3<f32>, color: Vector4<f32>) -> Self {
+        Self { position, color }
+    }
+
+    pub fn attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
+        let position = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(memoffset::offset_of!(DebugLineVertex, position) as _)
+            .build();
+
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(DebugLineVertex, color) as _)
+            .build();
+
+        vec![position, color]
+    }
+}
@@ -1,10 +1,15 @@
 use crate::{
+    assets::Assets,
+    capabilities::Capabilities,
     resources::{
-        AudioContext, GuiContext, HapticContext, PhysicsContext, RenderContext, VulkanContext,
-        XrContext,
+        xr_context::SwapchainColorSpace, AudioContext, ComfortContext, EntityCommands, GazeContext,
+        GuiContext, HapticContext, InputBindings, JobContext, MemoryStats, PhysicsContext,
+        RenderContext, TextureStreaming, VulkanContext, VulkanExtensions, XrContext,
     },
-    HothamError, HothamResult, VIEW_TYPE,
+    HothamError, HothamResult,
 };
+use ash::vk;
+use log::info;
 use openxr as xr;
 
 use std::{
@@ -33,6 +38,7 @@ pub struct Engine {
     #[allow(dead_code)]
     resumed: bool,
     event_data_buffer: EventDataBuffer,
+    capabilities: Capabilities,
     /// OpenXR context
     pub xr_context: XrContext,
     /// Vulkan context
@@ -47,12 +53,94 @@ pub struct Engine {
     pub gui_context: GuiContext,
     /// Haptics context
     pub haptic_context: HapticContext,
+    /// Comfort context
+    pub comfort_context: ComfortContext,
+    /// Gaze context
+    pub gaze_context: GazeContext,
+    /// Streams mips of large textures in and out of GPU memory
+    pub texture_streaming: TextureStreaming,
+    /// GPU memory usage and budget of each memory heap. Updated by `update`.
+    pub memory_stats: MemoryStats,
+    /// Thread pool for CPU-bound work, eg. loading models
+    pub job_context: JobContext,
+    /// Spawns, despawns and other changes to the world, deferred until they're applied between the Update and
+    /// PostUpdate stages
+    pub entity_commands: EntityCommands,
+    /// Meshes, textures and materials loaded through it, shared between entities. See `Assets`.
+    pub assets: Assets,
 }
 
 impl Engine {
     /// Create a new instance of the engine
     /// NOTE: only one instance may be running at any one time
     pub fn new() -> Self {
+        Self::new_with_form_factor(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+    }
+
+    /// Create a new instance of the engine for the given OpenXR form factor, eg. `HANDHELD_DISPLAY` for phone AR.
+    /// Falls back to `HEAD_MOUNTED_DISPLAY` if the runtime doesn't support the requested form factor.
+    /// NOTE: only one instance may be running at any one time
+    pub fn new_with_form_factor(form_factor: xr::FormFactor) -> Self {
+        Self::new_with_vulkan_extensions(form_factor, Default::default())
+    }
+
+    /// Create a new instance of the engine, enabling extra Vulkan extensions, eg. vendor extensions Hotham doesn't use.
+    /// Panics with a clear error if any of them aren't available. See `Engine::new_with_form_factor`.
+    /// NOTE: only one instance may be running at any one time
+    pub fn new_with_vulkan_extensions(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: VulkanExtensions,
+    ) -> Self {
+        Self::new_with_color_space(form_factor, vulkan_extensions, Default::default())
+    }
+
+    /// Create a new instance of the engine, rendering in `color_space`, eg. `SwapchainColorSpace::Hdr` for
+    /// HDR-capable headsets. Falls back to SDR with a warning if the runtime doesn't support it - check
+    /// `xr_context.swapchain_color_space` for the colour space in use. See `Engine::new_with_vulkan_extensions`.
+    /// NOTE: only one instance may be running at any one time
+    pub fn new_with_color_space(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: VulkanExtensions,
+        color_space: SwapchainColorSpace,
+    ) -> Self {
+        Self::new_with_input_bindings(
+            form_factor,
+            vulkan_extensions,
+            color_space,
+            &Default::default(),
+        )
+    }
+
+    /// Create a new instance of the engine, binding controller actions to `input_bindings`, eg. controls the player
+    /// remapped and saved with a `Scene`. Panics if the runtime rejects the bindings. See `InputBindings` and
+    /// `Engine::new_with_color_space`.
+    /// NOTE: only one instance may be running at any one time
+    pub fn new_with_input_bindings(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: VulkanExtensions,
+        color_space: SwapchainColorSpace,
+        input_bindings: &InputBindings,
+    ) -> Self {
+        Self::new_with_image_rect(
+            form_factor,
+            vulkan_extensions,
+            color_space,
+            input_bindings,
+            None,
+        )
+    }
+
+    /// Create a new instance of the engine that renders views to `image_rect` of each swapchain image, eg. for
+    /// runtimes that expect views in a sub-rect of a shared image. `None` uses the whole image. Panics if it doesn't
+    /// fit in the swapchain images. See `Engine::new_with_input_bindings`.
+    /// NOTE: only one instance may be running at any one time
+    pub fn new_with_image_rect(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: VulkanExtensions,
+        color_space: SwapchainColorSpace,
+        input_bindings: &InputBindings,
+        image_rect: Option<vk::Rect2D>,
+    ) -> Self {
         #[allow(unused_mut)] // Only Android mutates this.
         let mut resumed = false;
         let should_quit = Arc::new(AtomicBool::from(false));
@@ -69,16 +157,25 @@ impl Engine {
         }
 
         // Now initialise the engine.
-        let (xr_context, vulkan_context) =
-            XrContext::new().expect("!!FATAL ERROR - Unable to initialise OpenXR!!");
+        let (xr_context, vulkan_context) = XrContext::new_with_image_rect(
+            form_factor,
+            &vulkan_extensions,
+            color_space,
+            input_bindings,
+            image_rect,
+        )
+        .expect("!!FATAL ERROR - Unable to initialise OpenXR!!");
         let render_context = RenderContext::new(&vulkan_context, &xr_context)
             .expect("!!FATAL ERROR - Unable to initialise renderer!");
         let gui_context = GuiContext::new(&vulkan_context);
+        let capabilities = Capabilities::new(&vulkan_context, &xr_context.instance);
+        info!(target: "HOTHAM_ENGINE", "Capabilities: {:?}", capabilities);
 
         let mut engine = Self {
             should_quit,
             resumed,
             event_data_buffer: Default::default(),
+            capabilities,
             xr_context,
             vulkan_context,
             render_context,
@@ -86,17 +183,77 @@ impl Engine {
             audio_context: Default::default(),
             gui_context,
             haptic_context: Default::default(),
+            comfort_context: Default::default(),
+            gaze_context: Default::default(),
+            texture_streaming: Default::default(),
+            memory_stats: Default::default(),
+            job_context: Default::default(),
+            entity_commands: Default::default(),
+            assets: Default::default(),
         };
 
         engine.update().unwrap();
         engine
     }
 
+    /// Get the capabilities of the current device and OpenXR runtime
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Get the underlying OpenXR instance, eg. to call extension functions that Hotham doesn't wrap.
+    ///
+    /// The instance is owned by the engine: don't destroy it or any of the objects the engine created with it.
+    pub fn xr_instance(&self) -> &xr::Instance {
+        &self.xr_context.instance
+    }
+
+    /// Get the underlying OpenXR session.
+    ///
+    /// The session is owned by the engine. Don't begin, end or request to exit the session, and only call
+    /// functions that must happen inside a frame between the engine's `begin_frame` and `end_frame`.
+    pub fn xr_session(&self) -> &xr::Session<xr::Vulkan> {
+        &self.xr_context.session
+    }
+
+    /// Get the reference space that all poses in Hotham are relative to.
+    pub fn xr_reference_space(&self) -> &xr::Space {
+        &self.xr_context.reference_space
+    }
+
+    /// Get the underlying Vulkan device.
+    ///
+    /// The device is owned by OpenXR and the engine. Don't destroy it, and make sure any objects you create with it
+    /// are destroyed before the engine is dropped. Commands must not be recorded into the engine's command buffers
+    /// outside of the engine's render pass.
+    pub fn vulkan_device(&self) -> &ash::Device {
+        &self.vulkan_context.device
+    }
+
+    /// Get the queue used for graphics, along with its queue family index.
+    ///
+    /// The queue is shared with the engine. Submissions must be externally synchronised with the render loop,
+    /// ie. don't submit to it from another thread while the engine is rendering.
+    pub fn vulkan_graphics_queue(&self) -> (vk::Queue, u32) {
+        (
+            self.vulkan_context.graphics_queue,
+            self.vulkan_context.queue_family_index,
+        )
+    }
+
+    /// Set the flags used when submitting the projection layer to the OpenXR compositor, eg.
+    /// `BLEND_TEXTURE_SOURCE_ALPHA` to composite over passthrough. Defaults to no flags.
+    pub fn set_composition_layer_flags(&mut self, flags: xr::CompositionLayerFlags) {
+        self.xr_context.set_composition_layer_flags(flags);
+    }
+
     /// IMPORTANT: Call this function each tick to update the engine's running state with the underlying OS
     pub fn update(&mut self) -> HothamResult<(xr::SessionState, xr::SessionState)> {
         #[cfg(target_os = "android")]
         process_android_events(&mut self.resumed, &self.should_quit);
 
+        self.memory_stats.update(&self.vulkan_context);
+
         let (previous_state, current_state) = {
             let previous_state = self.xr_context.session_state.clone();
             let current_state = self.xr_context.poll_xr_event(&mut self.event_data_buffer)?;
@@ -111,11 +268,11 @@ impl Engine {
                 sleep(Duration::from_millis(100)); // Sleep to avoid thrasing the CPU
             }
             (SessionState::IDLE, SessionState::READY) => {
-                self.xr_context.session.begin(VIEW_TYPE)?;
+                self.xr_context.session.begin(self.xr_context.view_type)?;
             }
             (_, SessionState::EXITING) => {
                 // Show's over
-                println!("[HOTHAM_ENGINE] State is now exiting!");
+                info!(target: "HOTHAM_ENGINE", "State is now exiting!");
                 return Err(HothamError::ShuttingDown);
             }
             (_, SessionState::STOPPING) => {
@@ -135,7 +292,7 @@ impl Engine {
 #[cfg(target_os = "android")]
 pub fn process_android_events(resumed: &mut bool, should_quit: &Arc<AtomicBool>) -> bool {
     while let Some(event) = poll_android_events(*resumed) {
-        println!("[HOTHAM_ANDROID] Received event {:?}", event);
+        info!(target: "HOTHAM_ANDROID", "Received event {:?}", event);
         match event {
             ndk_glue::Event::Resume => *resumed = true,
             ndk_glue::Event::Destroy => {
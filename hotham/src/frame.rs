@@ -6,9 +6,12 @@ use anyhow::Result;
 /// A container for all the resources necessary to render a single frame.
 #[derive(Debug, Clone)]
 pub struct Frame {
+    /// Signalled when the GPU has finished with this frame's command buffer, so it can be recorded again.
+    /// NOTE: This is the only GPU -> CPU synchronisation a frame needs: see `RenderContext::end_frame`.
     pub fence: vk::Fence,
     pub command_buffer: vk::CommandBuffer,
     pub framebuffer: vk::Framebuffer,
+    pub swapchain_image: vk::Image,
     pub swapchain_image_view: vk::ImageView,
 }
 
@@ -17,9 +20,11 @@ impl Frame {
         vulkan_context: &VulkanContext,
         render_pass: vk::RenderPass,
         swapchain_resolution: vk::Extent2D,
+        swapchain_image: vk::Image,
         swapchain_image_view: vk::ImageView,
         depth_image_view: vk::ImageView,
         colour_image_view: vk::ImageView,
+        depth_resolve_image_view: Option<vk::ImageView>,
     ) -> Result<Self> {
         let device = &vulkan_context.device;
         let command_pool = vulkan_context.command_pool;
@@ -42,7 +47,9 @@ impl Frame {
         .pop()
         .ok_or(HothamError::EmptyListError)?;
 
-        let attachments = [colour_image_view, depth_image_view, swapchain_image_view];
+        // The depth resolve attachment is only there if the render pass resolves depth: see `create_render_pass`.
+        let mut attachments = vec![colour_image_view, depth_image_view, swapchain_image_view];
+        attachments.extend(depth_resolve_image_view);
 
         let frame_buffer_create_info = vk::FramebufferCreateInfo::builder()
             .render_pass(render_pass)
@@ -57,6 +64,7 @@ impl Frame {
             fence,
             command_buffer,
             framebuffer: frame_buffer,
+            swapchain_image,
             swapchain_image_view,
         })
     }
@@ -1,39 +1,204 @@
 use crate::{
+    assets::AssetStore,
     buffer::Buffer,
     components::{
-        animation_controller::AnimationController, AnimationTarget, Info, Joint, Mesh, Parent,
-        Root, Skin, Transform, TransformMatrix, Visible,
+        animation_controller::AnimationController, material::MaterialExtensions,
+        mesh::create_joint_buffer, AnimationTarget, Info, Joint, Mesh, Parent, Root, Skin,
+        Transform, TransformMatrix, Visible,
     },
-    resources::{render_context::DescriptorSetLayouts, VulkanContext},
+    resources::{render_context::DescriptorSetLayouts, JobContext, VulkanContext},
+    texture::Texture,
 };
 use anyhow::Result;
 use ash::vk;
 use gltf::animation::util::ReadOutputs;
 use hecs::{Entity, World};
 use itertools::{izip, Itertools};
-use nalgebra::{vector, Matrix4, Quaternion, UnitQuaternion};
-use std::collections::HashMap;
+use log::{info, warn};
+use nalgebra::{vector, Matrix4, Quaternion, UnitQuaternion, Vector3};
+use std::{collections::HashMap, f32::consts::FRAC_PI_2, time::Instant};
 
 /// Convenience type for models
 pub type Models = HashMap<String, World>;
 
+/// glTF extensions that affect how models are loaded. Any others are ignored.
+const SUPPORTED_EXTENSIONS: [&str; 4] = [
+    "KHR_materials_emissive_strength",
+    "KHR_materials_pbrSpecularGlossiness",
+    "KHR_materials_unlit",
+    "KHR_texture_transform",
+];
+
+/// How a model's meshes are optimised when they're loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshOptimisation {
+    /// Meshes are loaded as they are. The default.
+    None,
+    /// Identical vertices are merged, and unindexed meshes are given an index buffer.
+    DeduplicateVertices,
+    /// As `DeduplicateVertices`, and triangle lists are reordered to make better use of the GPU's vertex cache.
+    OptimiseVertexCache,
+}
+
+impl Default for MeshOptimisation {
+    fn default() -> Self {
+        MeshOptimisation::None
+    }
+}
+
+/// The colour space a model's vertex colours (`COLOR_0`) were authored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VertexColorSpace {
+    /// Linear, as the glTF spec requires. The default.
+    Linear,
+    /// sRGB, as some tools export them. They're converted to linear when they're loaded.
+    Srgb,
+}
+
+impl Default for VertexColorSpace {
+    fn default() -> Self {
+        VertexColorSpace::Linear
+    }
+}
+
+/// The axis a model was authored with pointing up. Hotham's world is Y-up, as glTF and OpenXR are, so models authored
+/// with another axis up are turned when they're loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpAxis {
+    /// Y is up, as the glTF spec requires. The default.
+    Y,
+    /// Z is up, as in Blender and many CAD tools. Models are assumed to face +Y, and are turned to face -Z.
+    Z,
+}
+
+impl Default for UpAxis {
+    fn default() -> Self {
+        UpAxis::Y
+    }
+}
+
+impl UpAxis {
+    /// The rotation that turns a model authored with this axis up into Hotham's Y-up world
+    pub fn to_y_up(&self) -> UnitQuaternion<f32> {
+        match self {
+            UpAxis::Y => UnitQuaternion::identity(),
+            UpAxis::Z => UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2),
+        }
+    }
+}
+
+/// Faces that meet at less than 60 degrees are smoothed over when normals are generated
+pub const DEFAULT_NORMAL_SMOOTHING_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+/// Options for loading glTF models
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// How the meshes of each model are optimised, keyed by model name. Models that aren't listed aren't optimised.
+    pub mesh_optimisation: HashMap<String, MeshOptimisation>,
+    /// The colour space of each model's vertex colours, keyed by model name. Models that aren't listed are linear.
+    pub vertex_color_space: HashMap<String, VertexColorSpace>,
+    /// For models whose meshes have no normals, the largest angle in radians between two faces that's smoothed
+    /// over when normals are generated, keyed by model name. Sharper edges are kept hard. Models that aren't listed
+    /// use `DEFAULT_NORMAL_SMOOTHING_ANGLE`.
+    pub normal_smoothing_angle: HashMap<String, f32>,
+    /// The axis each model was authored with pointing up, keyed by model name. Models that aren't listed are Y-up.
+    pub up_axis: HashMap<String, UpAxis>,
+    /// The factor each model is scaled by to convert it to metres, keyed by model name, eg. `0.01` for a model
+    /// authored in centimetres. Models that aren't listed aren't scaled.
+    pub import_scale: HashMap<String, f32>,
+}
+
+impl LoadOptions {
+    /// How the meshes of the model named `name` should be optimised
+    pub fn mesh_optimisation(&self, name: &str) -> MeshOptimisation {
+        self.mesh_optimisation
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The colour space of the vertex colours of the model named `name`
+    pub fn vertex_color_space(&self, name: &str) -> VertexColorSpace {
+        self.vertex_color_space
+            .get(name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The angle normals are smoothed over when they're generated for the model named `name`
+    pub fn normal_smoothing_angle(&self, name: &str) -> f32 {
+        self.normal_smoothing_angle
+            .get(name)
+            .copied()
+            .unwrap_or(DEFAULT_NORMAL_SMOOTHING_ANGLE)
+    }
+
+    /// The axis the model named `name` was authored with pointing up
+    pub fn up_axis(&self, name: &str) -> UpAxis {
+        self.up_axis.get(name).copied().unwrap_or_default()
+    }
+
+    /// The factor the model named `name` is scaled by when it's loaded
+    pub fn import_scale(&self, name: &str) -> f32 {
+        self.import_scale.get(name).copied().unwrap_or(1.)
+    }
+}
+
 /// Load glTF models from a GLB file
 pub fn load_models_from_glb(
     glb_bufs: &Vec<&[u8]>,
     vulkan_context: &VulkanContext,
     descriptor_set_layouts: &DescriptorSetLayouts,
+) -> Result<Models> {
+    load_models_from_glb_with_options(
+        glb_bufs,
+        vulkan_context,
+        descriptor_set_layouts,
+        &Default::default(),
+    )
+}
+
+/// Load glTF models from a GLB file, with `options`. See `LoadOptions`.
+///
+/// Textures used by several materials are only uploaded once. Use `Assets::load_models_from_glb` to also share them
+/// with models loaded earlier.
+pub fn load_models_from_glb_with_options(
+    glb_bufs: &Vec<&[u8]>,
+    vulkan_context: &VulkanContext,
+    descriptor_set_layouts: &DescriptorSetLayouts,
+    options: &LoadOptions,
+) -> Result<Models> {
+    load_models_from_glb_with_textures(
+        glb_bufs,
+        vulkan_context,
+        descriptor_set_layouts,
+        options,
+        &mut AssetStore::default(),
+    )
+}
+
+/// Load glTF models from a GLB file, loading their textures through `textures`
+pub(crate) fn load_models_from_glb_with_textures(
+    glb_bufs: &Vec<&[u8]>,
+    vulkan_context: &VulkanContext,
+    descriptor_set_layouts: &DescriptorSetLayouts,
+    options: &LoadOptions,
+    textures: &mut AssetStore<Texture>,
 ) -> Result<Models> {
     let mut models = HashMap::new();
 
     for glb_buf in glb_bufs {
         let (document, buffers, images) = gltf::import_slice(glb_buf).unwrap();
-        load_models_from_gltf_data(
+        load_models(
             &document,
             &buffers[0],
             &images,
             &vulkan_context,
             descriptor_set_layouts,
             &mut models,
+            options,
+            &get_material_extensions(glb_buf),
+            textures,
         )
         .unwrap();
     }
@@ -41,7 +206,61 @@ pub fn load_models_from_glb(
     Ok(models)
 }
 
-/// Load glTF models from a glTF document
+/// Load glTF models from several GLB files, parsing and decoding them in parallel on `job_context`.
+/// Uploads to the GPU still happen one file at a time on the calling thread.
+pub fn load_models_from_glb_in_parallel(
+    glb_bufs: &[&'static [u8]],
+    job_context: &JobContext,
+    vulkan_context: &VulkanContext,
+    descriptor_set_layouts: &DescriptorSetLayouts,
+) -> Result<Models> {
+    let start = Instant::now();
+    let handles = glb_bufs
+        .iter()
+        .map(|glb_buf| {
+            let glb_buf: &'static [u8] = glb_buf;
+            job_context.spawn(move || {
+                let (document, buffers, images) = gltf::import_slice(glb_buf)?;
+                Ok::<_, anyhow::Error>((
+                    document,
+                    buffers,
+                    images,
+                    get_material_extensions(glb_buf),
+                ))
+            })
+        })
+        .collect_vec();
+
+    let mut models = HashMap::new();
+    let mut textures = AssetStore::default();
+    for handle in handles {
+        let (document, buffers, images, material_extensions) = handle.wait()??;
+        load_models(
+            &document,
+            &buffers[0],
+            &images,
+            &vulkan_context,
+            descriptor_set_layouts,
+            &mut models,
+            &Default::default(),
+            &material_extensions,
+            &mut textures,
+        )?;
+    }
+
+    info!(
+        target: "HOTHAM_GLTF",
+        "Loaded {} models from {} files in {:?}",
+        models.len(), glb_bufs.len(), start.elapsed()
+    );
+
+    Ok(models)
+}
+
+/// Load glTF models from a glTF document.
+///
+/// `gltf::Document` drops extensions the `gltf` crate doesn't know, so `KHR_materials_emissive_strength` is only
+/// applied when loading from a GLB file.
 pub fn load_models_from_gltf_data(
     document: &gltf::Document,
     buffer: &[u8],
@@ -50,12 +269,72 @@ pub fn load_models_from_gltf_data(
     descriptor_set_layouts: &DescriptorSetLayouts,
     models: &mut Models,
 ) -> Result<()> {
+    load_models_from_gltf_data_with_options(
+        document,
+        buffer,
+        images,
+        vulkan_context,
+        descriptor_set_layouts,
+        models,
+        &Default::default(),
+    )
+}
+
+/// Load glTF models from a glTF document, with `options`. See `LoadOptions`, and `load_models_from_gltf_data` for
+/// the extensions that can't be loaded from a document.
+pub fn load_models_from_gltf_data_with_options(
+    document: &gltf::Document,
+    buffer: &[u8],
+    images: &Vec<gltf::image::Data>,
+    vulkan_context: &VulkanContext,
+    descriptor_set_layouts: &DescriptorSetLayouts,
+    models: &mut Models,
+    options: &LoadOptions,
+) -> Result<()> {
+    load_models(
+        document,
+        buffer,
+        images,
+        vulkan_context,
+        descriptor_set_layouts,
+        models,
+        options,
+        &Default::default(),
+        &mut AssetStore::default(),
+    )
+}
+
+fn load_models(
+    document: &gltf::Document,
+    buffer: &[u8],
+    images: &Vec<gltf::image::Data>,
+    vulkan_context: &VulkanContext,
+    descriptor_set_layouts: &DescriptorSetLayouts,
+    models: &mut Models,
+    options: &LoadOptions,
+    material_extensions: &MaterialExtensions,
+    textures: &mut AssetStore<Texture>,
+) -> Result<()> {
+    for extension in document.extensions_used() {
+        if !SUPPORTED_EXTENSIONS.contains(&extension) {
+            warn!(
+                target: "HOTHAM_GLTF",
+                "Ignoring unsupported glTF extension {}", extension
+            );
+        }
+    }
+
     let root_scene = document.scenes().next().unwrap(); // safe as there is always one scene
     let mut node_entity_map = HashMap::new();
     let animations = document.animations().collect_vec();
 
     for node_data in root_scene.nodes() {
         let mut world = World::default();
+        let name = node_data.name().unwrap_or_default();
+        let optimisation = options.mesh_optimisation(name);
+        let vertex_color_space = options.vertex_color_space(name);
+        let normal_smoothing_angle = options.normal_smoothing_angle(name);
+        let import_scale = options.import_scale(name);
         load_node(
             &node_data,
             buffer,
@@ -65,6 +344,12 @@ pub fn load_models_from_gltf_data(
             &mut node_entity_map,
             true,
             images,
+            optimisation,
+            vertex_color_space,
+            normal_smoothing_angle,
+            import_scale,
+            material_extensions,
+            textures,
         )?;
         add_parents(&node_data, &mut world, &mut node_entity_map);
         add_skins_and_joints(
@@ -75,6 +360,10 @@ pub fn load_models_from_gltf_data(
             &mut node_entity_map,
         );
         add_animations(&animations, &buffer, &mut world, &mut node_entity_map);
+        apply_import_scale(&mut world, import_scale);
+        if let Some(root) = node_entity_map.get(&node_data.index()) {
+            apply_up_axis(&mut world, *root, options.up_axis(name));
+        }
 
         models.insert(
             node_data.name().expect("Node has no name!").to_string(),
@@ -85,6 +374,70 @@ pub fn load_models_from_gltf_data(
     Ok(())
 }
 
+/// Turn the model rooted at `root` so the axis it was authored with pointing up points up in Hotham's world. The rest
+/// of the model is relative to the root, so its bounds, skins and anything built from its transforms, eg. colliders,
+/// follow along.
+fn apply_up_axis(world: &mut World, root: Entity, up_axis: UpAxis) {
+    if up_axis == UpAxis::Y {
+        return;
+    }
+
+    let rotation = up_axis.to_y_up();
+    let rotate = |transform: &mut Transform| {
+        transform.translation = rotation * transform.translation;
+        transform.rotation = rotation * transform.rotation;
+    };
+
+    if let Ok(mut transform) = world.get_mut::<Transform>(root) {
+        rotate(&mut transform);
+    }
+    if let Ok(mut transform_matrix) = world.get_mut::<TransformMatrix>(root) {
+        transform_matrix.0 = rotation.to_homogeneous() * transform_matrix.0;
+    }
+
+    // An animated root has its transform overwritten by `animation_system`, so its keyframes must be turned too.
+    if let Ok(mut animation_target) = world.get_mut::<AnimationTarget>(root) {
+        animation_target
+            .animations
+            .iter_mut()
+            .flatten()
+            .for_each(|t| rotate(t));
+    }
+}
+
+/// Scale the offsets between the nodes of a model loaded with `import_scale`, to match its vertices, which are scaled
+/// as they're loaded. Every translation is scaled: node transforms, joints' inverse bind matrices and animation
+/// keyframes. Rotations and scales are left alone.
+fn apply_import_scale(world: &mut World, import_scale: f32) {
+    if import_scale == 1. {
+        return;
+    }
+
+    for (_, transform) in world.query_mut::<&mut Transform>() {
+        transform.translation *= import_scale;
+    }
+    for (_, transform_matrix) in world.query_mut::<&mut TransformMatrix>() {
+        scale_translation(&mut transform_matrix.0, import_scale);
+    }
+    for (_, joint) in world.query_mut::<&mut Joint>() {
+        scale_translation(&mut joint.inverse_bind_matrix, import_scale);
+    }
+    for (_, animation_target) in world.query_mut::<&mut AnimationTarget>() {
+        animation_target
+            .animations
+            .iter_mut()
+            .flatten()
+            .for_each(|t| t.translation *= import_scale);
+    }
+}
+
+/// Scale the translation of an affine transform. With a uniform scale, this is the same as scaling the space the
+/// transform is in.
+fn scale_translation(matrix: &mut Matrix4<f32>, scale: f32) {
+    let mut translation = matrix.fixed_slice_mut::<3, 1>(0, 3);
+    translation *= scale;
+}
+
 fn load_node(
     node_data: &gltf::Node,
     gltf_buffer: &[u8],
@@ -94,6 +447,12 @@ fn load_node(
     node_entity_map: &mut HashMap<usize, Entity>,
     is_root: bool,
     images: &Vec<gltf::image::Data>,
+    optimisation: MeshOptimisation,
+    vertex_color_space: VertexColorSpace,
+    normal_smoothing_angle: f32,
+    import_scale: f32,
+    material_extensions: &MaterialExtensions,
+    textures: &mut AssetStore<Texture>,
 ) -> Result<()> {
     let transform = Transform::load(node_data.transform());
     let transform_matrix = TransformMatrix(node_data.transform().matrix().into());
@@ -114,6 +473,12 @@ fn load_node(
             vulkan_context,
             descriptor_set_layouts,
             images,
+            optimisation,
+            vertex_color_space,
+            normal_smoothing_angle,
+            import_scale,
+            material_extensions,
+            textures,
         )?;
 
         world.insert(this_entity, (mesh, Visible {})).unwrap();
@@ -133,12 +498,25 @@ fn load_node(
             node_entity_map,
             false,
             images,
+            optimisation,
+            vertex_color_space,
+            normal_smoothing_angle,
+            import_scale,
+            material_extensions,
+            textures,
         )?;
     }
 
     Ok(())
 }
 
+/// Read the material extensions the `gltf` crate doesn't parse from the JSON chunk of a GLB file
+fn get_material_extensions(glb_buf: &[u8]) -> MaterialExtensions {
+    gltf::Glb::from_slice(glb_buf)
+        .map(|glb| MaterialExtensions::from_json(&glb.json))
+        .unwrap_or_default()
+}
+
 fn add_parents(
     node_data: &gltf::Node,
     world: &mut World,
@@ -164,7 +542,7 @@ fn add_skins_and_joints(
     // Do we need to add a Skin?
     // TODO: Extract this to components::Skin
     if let Some(node_skin_data) = node_data.skin() {
-        println!("[HOTHAM_GLTF] Adding a skin to {}", node_data.index());
+        info!(target: "HOTHAM_GLTF", "Adding a skin to {}", node_data.index());
         let this_entity = *node_entity_map.get(&node_data.index()).unwrap();
         let mut joint_matrices = Vec::new();
         let reader = node_skin_data.reader(|_| Some(buffer));
@@ -187,7 +565,7 @@ fn add_skins_and_joints(
         }
 
         // Add a Skin to the entity.
-        world.insert_one(this_entity, Skin { joint_ids }).unwrap();
+        world.insert_one(this_entity, Skin::new(joint_ids)).unwrap();
 
         // Tell the vertex shader how many joints we have
         let mut mesh = world.get_mut::<Mesh>(this_entity).unwrap();
@@ -245,7 +623,11 @@ fn add_animations(
 
             let target_entity = *node_entity_map.get(&target).unwrap();
             if !world.contains(target_entity) {
-                println!("[HOTHAM_GLTF] - Error importing animation {:?}. No target, probably due to malformed file. Ignoring", animation.name());
+                warn!(
+                    target: "HOTHAM_GLTF",
+                    "Error importing animation {:?}. No target, probably due to malformed file. Ignoring",
+                    animation.name()
+                );
                 return;
             }
 
@@ -347,19 +729,31 @@ pub fn add_model_to_world(
                 vulkan_context,
                 &[mesh.ubo_data],
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
+                &format!("Mesh UBO for {}", info.name),
             )
             .unwrap();
             vulkan_context.update_buffer_descriptor_set(
                 &ubo_buffer,
-                mesh.descriptor_sets[0],
+                descriptor_sets[0],
                 0,
                 vk::DescriptorType::UNIFORM_BUFFER,
             );
 
+            // Skinned meshes get room for all of their joints up front.
+            let joint_buffer = create_joint_buffer(
+                vulkan_context,
+                mesh.ubo_data.joint_count as usize,
+                descriptor_sets[0],
+                &info.name,
+            )
+            .unwrap();
+
             let new_mesh = Mesh {
                 descriptor_sets: [descriptor_sets[0]],
                 ubo_buffer,
                 ubo_data: mesh.ubo_data.clone(),
+                joint_buffer,
+                joint_matrices: mesh.joint_matrices.clone(),
                 primitives: mesh.primitives.clone(),
             };
             destination_world
@@ -439,31 +833,91 @@ pub fn add_model_to_world(
     // Get the new root entity.
     let new_root_entity = entity_map.get(&root_entity).cloned().unwrap();
 
-    // We'll also need to fix up any meshes
-    for (_, (info, mesh)) in destination_world.query_mut::<(&Info, &mut Mesh)>() {
-        // Create new description sets
-        let new_descriptor_sets = vulkan_context
-            .create_mesh_descriptor_sets(descriptor_set_layouts.mesh_layout, &info.name)
+    Some(new_root_entity)
+}
+
+/// Apply the animations from the model `source_name` to the hierarchy under `destination_root`, matching nodes by
+/// name rather than by glTF node index. This lets a clip authored for one rig drive any structurally similar rig.
+///
+/// Nodes that exist in the source animation but can't be found under `destination_root` are skipped with a warning.
+/// Returns the number of nodes that were retargeted.
+pub fn retarget_animations(
+    source_name: &str,
+    models: &Models,
+    destination_world: &mut World,
+    destination_root: Entity,
+) -> usize {
+    let source_world = match models.get(source_name) {
+        Some(w) => w,
+        None => {
+            warn!(
+                target: "HOTHAM_GLTF",
+                "Unable to retarget animations: no model named {}",
+                source_name
+            );
+            return 0;
+        }
+    };
+
+    // Find every named node in the destination hierarchy.
+    let destination_nodes = destination_world
+        .query::<&Info>()
+        .iter()
+        .filter(|(e, _)| is_descendant_of(&*destination_world, *e, destination_root))
+        .map(|(e, info)| (info.name.clone(), e))
+        .collect::<HashMap<_, _>>();
+
+    let mut retargeted = 0;
+    for (_, (info, animation_target)) in source_world.query::<(&Info, &AnimationTarget)>().iter() {
+        let destination_entity = match destination_nodes.get(&info.name) {
+            Some(e) => *e,
+            None => {
+                warn!(
+                    target: "HOTHAM_GLTF",
+                    "Unable to retarget animation for node {} - no node with that name exists. Skipping",
+                    info.name
+                );
+                continue;
+            }
+        };
+
+        destination_world
+            .insert_one(
+                destination_entity,
+                AnimationTarget {
+                    controller: destination_root,
+                    animations: animation_target.animations.clone(),
+                },
+            )
             .unwrap();
-        mesh.descriptor_sets = [new_descriptor_sets[0]];
+        retargeted += 1;
+    }
 
-        // Create a new buffer
-        let new_ubo_buffer = Buffer::new(
-            vulkan_context,
-            &[mesh.ubo_data],
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-        )
-        .unwrap();
-        vulkan_context.update_buffer_descriptor_set(
-            &new_ubo_buffer,
-            mesh.descriptor_sets[0],
-            0,
-            vk::DescriptorType::UNIFORM_BUFFER,
-        );
-        mesh.ubo_buffer = new_ubo_buffer;
+    // Make sure the destination has a controller to drive the new targets.
+    if retargeted > 0
+        && destination_world
+            .get::<AnimationController>(destination_root)
+            .is_err()
+    {
+        destination_world
+            .insert_one(destination_root, AnimationController::default())
+            .unwrap();
     }
 
-    Some(new_root_entity)
+    retargeted
+}
+
+fn is_descendant_of(world: &World, entity: Entity, root: Entity) -> bool {
+    let mut current = entity;
+    loop {
+        if current == root {
+            return true;
+        }
+        match world.get::<Parent>(current) {
+            Ok(parent) => current = parent.0,
+            Err(_) => return false,
+        }
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -624,3 +1078,244 @@ mod tests {
         }
     }
 }
+
+#[cfg(test)]
+mod retargeting_tests {
+    use super::*;
+    use crate::systems::animation_system;
+    use hecs::PreparedQuery;
+
+    #[test]
+    pub fn test_retarget_animations() {
+        // The source rig: the node indices differ from the destination rig, but the names match.
+        let mut source_world = World::new();
+        let source_root = source_world.spawn((
+            Root {},
+            Info {
+                name: "Root".to_string(),
+                node_id: 0,
+            },
+        ));
+        let hip_animation = vec![Transform {
+            translation: vector![0., 1., 0.],
+            ..Default::default()
+        }];
+        let arm_animation = vec![Transform {
+            translation: vector![0., 0., 2.],
+            ..Default::default()
+        }];
+        source_world.spawn((
+            Info {
+                name: "Hip".to_string(),
+                node_id: 1,
+            },
+            AnimationTarget {
+                controller: source_root,
+                animations: vec![hip_animation.clone()],
+            },
+        ));
+        source_world.spawn((
+            Info {
+                name: "Arm".to_string(),
+                node_id: 2,
+            },
+            AnimationTarget {
+                controller: source_root,
+                animations: vec![arm_animation.clone()],
+            },
+        ));
+        source_world.spawn((
+            Info {
+                name: "Tail".to_string(),
+                node_id: 3,
+            },
+            AnimationTarget {
+                controller: source_root,
+                animations: vec![arm_animation.clone()],
+            },
+        ));
+        let mut models = Models::new();
+        models.insert("Source".to_string(), source_world);
+
+        // The destination rig, with its nodes in a different order.
+        let mut world = World::new();
+        let root = world.spawn((
+            Root {},
+            Info {
+                name: "Root".to_string(),
+                node_id: 0,
+            },
+        ));
+        let arm = world.spawn((
+            Info {
+                name: "Arm".to_string(),
+                node_id: 1,
+            },
+            Transform::default(),
+            Parent(root),
+        ));
+        let hip = world.spawn((
+            Info {
+                name: "Hip".to_string(),
+                node_id: 2,
+            },
+            Transform::default(),
+            Parent(arm),
+        ));
+
+        // A node with a matching name that *isn't* part of the destination hierarchy.
+        let other_arm = world.spawn((
+            Info {
+                name: "Arm".to_string(),
+                node_id: 1,
+            },
+            Transform::default(),
+        ));
+
+        // "Tail" has no match, so it should be skipped.
+        let retargeted = retarget_animations("Source", &models, &mut world, root);
+        assert_eq!(retargeted, 2);
+        assert!(world.get::<AnimationController>(root).is_ok());
+        assert!(world.get::<AnimationTarget>(other_arm).is_err());
+
+        animation_system(&mut PreparedQuery::default(), &mut world);
+        assert_eq!(*world.get::<Transform>(hip).unwrap(), hip_animation[0]);
+        assert_eq!(*world.get::<Transform>(arm).unwrap(), arm_animation[0]);
+        assert_eq!(
+            *world.get::<Transform>(other_arm).unwrap(),
+            Transform::default()
+        );
+    }
+}
+
+#[cfg(test)]
+mod up_axis_tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::Point3;
+
+    #[test]
+    pub fn test_z_up_model_is_turned_y_up() {
+        let mut world = World::new();
+        let transform = Transform {
+            translation: vector![0., 0., 1.],
+            ..Default::default()
+        };
+        let root = world.spawn((
+            Root {},
+            transform,
+            TransformMatrix(transform.position().to_homogeneous()),
+        ));
+        apply_up_axis(&mut world, root, UpAxis::Z);
+
+        // A vertex 2m in front (+Y) of and 3m above (+Z) the root of a Z-up model..
+        let transform_matrix = world.get::<TransformMatrix>(root).unwrap().0;
+        let vertex = transform_matrix.transform_point(&Point3::new(1., 2., 3.));
+        // ..should end up 2m in front (-Z) of and 3m above (+Y) it, and the root's own offset turned the same way.
+        assert_relative_eq!(vertex, Point3::new(1., 4., -2.), epsilon = 1e-6);
+
+        // The transform should match, or it would undo the turn once the transform matrices are updated.
+        let transform = *world.get::<Transform>(root).unwrap();
+        assert_relative_eq!(
+            transform.position().to_homogeneous(),
+            transform_matrix,
+            epsilon = 1e-6
+        );
+
+        // Y-up models are left alone.
+        let root = world.spawn((Root {}, Transform::default()));
+        apply_up_axis(&mut world, root, UpAxis::Y);
+        assert_eq!(*world.get::<Transform>(root).unwrap(), Transform::default());
+    }
+
+    #[test]
+    pub fn test_animated_root_keyframes_are_turned() {
+        let mut world = World::new();
+        let root = world.spawn((Root {}, Transform::default()));
+        let keyframe = Transform {
+            translation: vector![0., 0., 1.],
+            ..Default::default()
+        };
+        world
+            .insert_one(
+                root,
+                AnimationTarget {
+                    controller: root,
+                    animations: vec![vec![keyframe, keyframe]],
+                },
+            )
+            .unwrap();
+        apply_up_axis(&mut world, root, UpAxis::Z);
+
+        let animation_target = world.get::<AnimationTarget>(root).unwrap();
+        for keyframe in &animation_target.animations[0] {
+            assert_relative_eq!(keyframe.translation, vector![0., 1., 0.], epsilon = 1e-6);
+        }
+    }
+}
+
+#[cfg(test)]
+mod import_scale_tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::Point3;
+
+    #[test]
+    pub fn test_centimetre_model_is_scaled_to_metres() {
+        // A model authored in centimetres: a 1.8m tall figure whose hips are 100cm above its root..
+        let mut world = World::new();
+        let root = world.spawn((Root {}, Transform::default(), TransformMatrix::default()));
+        let hips = Transform {
+            translation: vector![0., 100., 0.],
+            ..Default::default()
+        };
+        let hips = world.spawn((
+            hips,
+            TransformMatrix(hips.position().to_homogeneous()),
+            Parent(root),
+            Joint {
+                skeleton_root: root,
+                inverse_bind_matrix: Matrix4::new_translation(&vector![0., -100., 0.]),
+            },
+            AnimationTarget {
+                controller: root,
+                animations: vec![vec![Transform {
+                    translation: vector![0., 90., 0.],
+                    ..Default::default()
+                }]],
+            },
+        ));
+
+        // ..whose vertices have been scaled as they were loaded, with the top of the head 80cm above the hips.
+        let import_scale = 0.01;
+        let head = Point3::new(0., 80., 0.) * import_scale;
+        apply_import_scale(&mut world, import_scale);
+
+        // The whole model should now be 1.8m tall..
+        let transform_matrix = world.get::<TransformMatrix>(hips).unwrap().0;
+        assert_relative_eq!(
+            transform_matrix.transform_point(&head),
+            Point3::new(0., 1.8, 0.),
+            epsilon = 1e-6
+        );
+        let transform = *world.get::<Transform>(hips).unwrap();
+        assert_relative_eq!(transform.translation, vector![0., 1., 0.], epsilon = 1e-6);
+
+        // ..with its skin and animations scaled to match.
+        let joint = world.get::<Joint>(hips).unwrap();
+        assert_relative_eq!(
+            transform_matrix * joint.inverse_bind_matrix,
+            Matrix4::identity(),
+            epsilon = 1e-6
+        );
+        let animation_target = world.get::<AnimationTarget>(hips).unwrap();
+        assert_relative_eq!(
+            animation_target.animations[0][0].translation,
+            vector![0., 0.9, 0.],
+            epsilon = 1e-6
+        );
+
+        // Rotations and scales are left alone.
+        assert_eq!(transform.scale, vector![1., 1., 1.]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use ash::vk::Result as VulkanResult;
 use openxr::sys::Result as OpenXRResult;
 use thiserror::Error;
@@ -26,6 +28,20 @@ pub enum HothamError {
     /// Engine shutting down
     #[error("The engine is shutting down")]
     ShuttingDown,
+    /// Vulkan extensions that were requested aren't available
+    #[error("The Vulkan extensions {0:?} were requested, but aren't available on this device")]
+    UnsupportedExtensions(Vec<String>),
+    /// The GPU didn't finish a frame within `RenderContext::fence_timeout`
+    #[error("Timed out after {timeout:?} waiting for the GPU to finish frame {frame}")]
+    FenceTimeout {
+        /// The index of the frame that timed out
+        frame: usize,
+        /// How long was waited
+        timeout: Duration,
+    },
+    /// A job run by `JobContext` panicked
+    #[error("A job panicked: {0}")]
+    JobPanicked(String),
     /// IO error
     #[error(transparent)]
     IO(#[from] std::io::Error),
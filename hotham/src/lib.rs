@@ -13,16 +13,22 @@
 pub use ash::vk;
 pub use openxr as xr;
 
+pub use capabilities::Capabilities;
 pub use engine::Engine;
 pub use hecs;
 pub use hotham_error::HothamError;
 pub use nalgebra;
 pub use rapier3d;
+pub use texture::{get_texture_format, TextureColorSpace, TextureRole};
 
+/// Meshes, textures and materials that can be shared between entities
+pub mod assets;
 mod buffer;
 mod camera;
+mod capabilities;
 /// Components are data that are used to update the simulation and interact with the external world
 pub mod components;
+mod debug_lines;
 mod engine;
 mod frame;
 
@@ -30,8 +36,13 @@ mod frame;
 pub mod gltf_loader;
 mod hotham_error;
 mod image;
+mod mesh_optimisation;
+/// Triangle-accurate raycasting against meshes
+pub mod raycast;
 /// Resources are wrappers around some external state that the engine will interact with
 pub mod resources;
+/// Save and load entities to and from JSON
+pub mod scene;
 /// Data used in the fragment shader
 pub mod scene_data;
 pub mod schedule_functions;
@@ -48,19 +59,33 @@ pub type HothamResult<T> = std::result::Result<T, HothamError>;
 
 /// Format used for colour textures
 pub const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
-/// Format used for depth textures
-pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+/// Format used for the swapchain when HDR is requested and supported: linear scRGB, with values above 1.0 brighter
+/// than SDR white. See `SwapchainColorSpace`.
+pub const HDR_COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Formats that can be used for depth textures, in order of preference. The first one supported by the device is used.
+/// Formats with a stencil component are preferred, as the stencil is used to draw outlines and mirrors.
+pub const DEPTH_FORMATS: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT,
+];
 
 /// Number of views
 pub const VIEW_COUNT: u32 = 2;
 
+/// Distance to the near clipping plane of each view, in metres
+pub const NEAR_PLANE: f32 = 0.05;
+
+/// Distance to the far clipping plane of each view, in metres
+pub const FAR_PLANE: f32 = 100.0;
+
 /// Swapchain length
 pub const SWAPCHAIN_LENGTH: usize = 3;
 
-/// OpenXR view type
+/// OpenXR view type used by head mounted displays. See `XrContext::view_type` for the view type in use.
 pub const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
 
-/// OpenXR blend mode
+/// Preferred OpenXR blend mode for head mounted displays. See `XrContext::blend_mode` for the blend mode in use.
 pub const BLEND_MODE: xr::EnvironmentBlendMode = xr::EnvironmentBlendMode::OPAQUE;
 
 /// Vulkan depth attachment usage flags
@@ -0,0 +1,399 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use ash::vk;
+use nalgebra::Vector3;
+
+use crate::{gltf_loader::MeshOptimisation, vertex::Vertex};
+
+/// Size of the simulated post-transform vertex cache. Larger than most real caches, which still works well for them.
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+/// Score of the vertices of the last triangle added, which are deliberately scored lower than the next few in the
+/// cache so the next triangle doesn't just reuse an edge of the last one
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Apply `optimisation` to a primitive's `vertices` and `indices`. An empty `indices` means the primitive is
+/// unindexed. The vertex cache is only optimised for triangle lists.
+pub(crate) fn optimise_mesh(
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    topology: vk::PrimitiveTopology,
+    optimisation: MeshOptimisation,
+) -> (Vec<Vertex>, Vec<u32>) {
+    if optimisation == MeshOptimisation::None {
+        return (vertices, indices);
+    }
+
+    let (vertices, indices) = deduplicate_vertices(&vertices, &indices);
+    if optimisation == MeshOptimisation::OptimiseVertexCache
+        && topology == vk::PrimitiveTopology::TRIANGLE_LIST
+    {
+        let indices = optimise_vertex_cache(&indices, vertices.len());
+        return (vertices, indices);
+    }
+
+    (vertices, indices)
+}
+
+/// Merge identical vertices, returning the unique vertices, in the order they're first used, and indices into them.
+/// An empty `indices` means `vertices` are unindexed, and drawn in order.
+pub(crate) fn deduplicate_vertices(
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique_vertices = Vec::new();
+    let mut unique_indices = HashMap::new();
+
+    let mut remap = |vertex: &Vertex| {
+        *unique_indices
+            .entry(get_vertex_key(vertex))
+            .or_insert_with(|| {
+                unique_vertices.push(*vertex);
+                unique_vertices.len() as u32 - 1
+            })
+    };
+
+    let indices = if indices.is_empty() {
+        vertices.iter().map(&mut remap).collect()
+    } else {
+        indices
+            .iter()
+            .map(|i| remap(&vertices[*i as usize]))
+            .collect()
+    };
+
+    (unique_vertices, indices)
+}
+
+/// The bits of every component of `vertex`, so vertices can be compared exactly and hashed
+fn get_vertex_key(vertex: &Vertex) -> Vec<u32> {
+    vertex
+        .position
+        .iter()
+        .chain(vertex.normal.iter())
+        .chain(vertex.texture_coords_0.iter())
+        .chain(vertex.texture_coords_1.iter())
+        .chain(vertex.joint_indices.iter())
+        .chain(vertex.joint_weights.iter())
+        .chain(vertex.color.iter())
+        .map(|f| f.to_bits())
+        .collect()
+}
+
+/// Compute normals for a triangle list that has none. Each corner's normal is the area weighted average of the faces
+/// around its position that are within `smoothing_angle` radians of its own face, so sharper edges stay hard: their
+/// vertices are split, one per side. An empty `indices` means `vertices` are unindexed.
+pub(crate) fn generate_normals(
+    vertices: &[Vertex],
+    indices: &[u32],
+    smoothing_angle: f32,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let indices = if indices.is_empty() {
+        (0..vertices.len() as u32).collect()
+    } else {
+        indices.to_vec()
+    };
+    let triangles = indices.chunks_exact(3).collect::<Vec<_>>();
+
+    // Faces are smoothed across every vertex at the same position, eg. either side of a UV seam.
+    let mut faces_at_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    // The length of each cross product is twice the triangle's area, which weights the average.
+    let mut face_normals = Vec::with_capacity(triangles.len());
+    for (face, triangle) in triangles.iter().enumerate() {
+        let a = vertices[triangle[0] as usize].position;
+        let b = vertices[triangle[1] as usize].position;
+        let c = vertices[triangle[2] as usize].position;
+        face_normals.push((b - a).cross(&(c - a)));
+
+        for index in triangle.iter() {
+            let faces = faces_at_position
+                .entry(get_position_key(&vertices[*index as usize]))
+                .or_default();
+            if faces.last() != Some(&face) {
+                faces.push(face);
+            }
+        }
+    }
+
+    let min_cos = smoothing_angle.cos();
+    let mut new_vertices = Vec::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut unique_indices = HashMap::new();
+    for (face, triangle) in triangles.iter().enumerate() {
+        let face_normal = face_normals[face]
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros);
+
+        for index in triangle.iter() {
+            let vertex = vertices[*index as usize];
+            let normal = faces_at_position[&get_position_key(&vertex)]
+                .iter()
+                .map(|f| face_normals[*f])
+                .filter(|n| {
+                    n.try_normalize(f32::EPSILON)
+                        .map_or(false, |n| n.dot(&face_normal) >= min_cos)
+                })
+                .fold(Vector3::zeros(), |sum, n| sum + n)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or(face_normal);
+
+            let normal_key: [u32; 3] = normal.map(f32::to_bits).into();
+            let new_index = *unique_indices
+                .entry((*index, normal_key))
+                .or_insert_with(|| {
+                    new_vertices.push(Vertex { normal, ..vertex });
+                    new_vertices.len() as u32 - 1
+                });
+            new_indices.push(new_index);
+        }
+    }
+
+    (new_vertices, new_indices)
+}
+
+fn get_position_key(vertex: &Vertex) -> [u32; 3] {
+    vertex.position.map(f32::to_bits).into()
+}
+
+/// Reorder the triangles in a triangle list so that their vertices are reused while they're still in the GPU's
+/// post-transform cache, using Tom Forsyth's "Linear-Speed Vertex Cache Optimisation". The winding of each triangle
+/// is kept.
+pub(crate) fn optimise_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangles = indices.chunks_exact(3).collect::<Vec<_>>();
+
+    // The triangles each vertex is used by that haven't been added yet
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (t, triangle) in triangles.iter().enumerate() {
+        for v in triangle.iter() {
+            vertex_triangles[*v as usize].push(t);
+        }
+    }
+
+    let mut vertex_scores = vertex_triangles
+        .iter()
+        .map(|t| get_vertex_score(None, t.len()))
+        .collect::<Vec<_>>();
+    let get_triangle_score = |vertex_scores: &[f32], triangle: &[u32]| -> f32 {
+        triangle.iter().map(|v| vertex_scores[*v as usize]).sum()
+    };
+    let mut triangle_scores = triangles
+        .iter()
+        .map(|t| get_triangle_score(&vertex_scores, t))
+        .collect::<Vec<_>>();
+    let mut added = vec![false; triangles.len()];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut optimised = Vec::with_capacity(triangles.len() * 3);
+    let mut best_triangle = None;
+
+    for _ in 0..triangles.len() {
+        // If none of the triangles around the cache are any good, find the best one left anywhere.
+        let triangle = match best_triangle {
+            Some(t) => t,
+            None => (0..triangles.len())
+                .filter(|t| !added[*t])
+                .max_by(|a, b| {
+                    triangle_scores[*a]
+                        .partial_cmp(&triangle_scores[*b])
+                        .unwrap_or(Ordering::Equal)
+                })
+                .unwrap(),
+        };
+        added[triangle] = true;
+        optimised.extend_from_slice(triangles[triangle]);
+
+        // Move the triangle's vertices to the front of the cache.
+        let mut new_cache = triangles[triangle].to_vec();
+        for v in triangles[triangle] {
+            let remaining = &mut vertex_triangles[*v as usize];
+            if let Some(p) = remaining.iter().position(|t| *t == triangle) {
+                remaining.swap_remove(p);
+            }
+        }
+        new_cache.extend(cache.iter().filter(|v| !triangles[triangle].contains(*v)));
+
+        // Rescore every vertex that was in the cache, including any that have just fallen out of it..
+        for (position, v) in new_cache.iter().enumerate() {
+            let position = if position < CACHE_SIZE {
+                Some(position)
+            } else {
+                None
+            };
+            vertex_scores[*v as usize] =
+                get_vertex_score(position, vertex_triangles[*v as usize].len());
+        }
+
+        // ..then the triangles using them, picking the best to add next.
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+        for v in &new_cache {
+            for t in &vertex_triangles[*v as usize] {
+                let score = get_triangle_score(&vertex_scores, triangles[*t]);
+                triangle_scores[*t] = score;
+                if score > best_score {
+                    best_score = score;
+                    best_triangle = Some(*t);
+                }
+            }
+        }
+
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+
+    optimised
+}
+
+/// Score a vertex at `cache_position` with `remaining_triangles` left to add. Vertices recently used score higher,
+/// as do vertices with few triangles left, so that they're finished off rather than leaving lone triangles behind.
+fn get_vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        Some(p) if p < 3 => LAST_TRIANGLE_SCORE,
+        Some(p) => {
+            let scaler = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (p - 3) as f32 * scaler).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+    let valence_boost =
+        VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+
+    cache_score + valence_boost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::vector;
+
+    #[test]
+    pub fn test_deduplicate_unindexed_triangles() {
+        let vertex = |x, y| Vertex {
+            position: vector![x, y, 0.],
+            normal: vector![0., 0., 1.],
+            ..Default::default()
+        };
+
+        // An unindexed quad: two triangles sharing an edge.
+        let vertices = vec![
+            vertex(0., 0.),
+            vertex(1., 0.),
+            vertex(1., 1.),
+            vertex(0., 0.),
+            vertex(1., 1.),
+            vertex(0., 1.),
+        ];
+        let (unique_vertices, indices) = deduplicate_vertices(&vertices, &[]);
+        assert_eq!(unique_vertices.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+        for (i, index) in indices.iter().enumerate() {
+            assert_eq!(unique_vertices[*index as usize], vertices[i]);
+        }
+
+        // Vertices that only differ in one attribute are kept apart.
+        let mut vertices = vertices;
+        vertices[3].texture_coords_0 = vector![0.5, 0.5];
+        let (unique_vertices, _) = deduplicate_vertices(&vertices, &[]);
+        assert_eq!(unique_vertices.len(), 5);
+
+        // Existing indices are remapped.
+        let (unique_vertices, indices) = deduplicate_vertices(&vertices, &[5, 4, 0]);
+        assert_eq!(unique_vertices, vec![vertices[5], vertices[4], vertices[0]]);
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    pub fn test_generate_cube_normals() {
+        // Each face of a unit cube as its outward normal and two edges, wound anti-clockwise seen from outside.
+        let faces = [
+            (Vector3::x(), Vector3::y(), Vector3::z()),
+            (-Vector3::x(), Vector3::z(), Vector3::y()),
+            (Vector3::y(), Vector3::z(), Vector3::x()),
+            (-Vector3::y(), Vector3::x(), Vector3::z()),
+            (Vector3::z(), Vector3::x(), Vector3::y()),
+            (-Vector3::z(), Vector3::y(), Vector3::x()),
+        ];
+
+        // Share the 8 corners between faces, as a mesh without normals would.
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices = Vec::new();
+        for (normal, u, v) in faces.iter() {
+            let mut corners = Vec::new();
+            for (s, t) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)].iter() {
+                let position: Vector3<f32> = (normal + u * *s + v * *t) * 0.5;
+                match vertices.iter().position(|v| v.position == position) {
+                    Some(index) => corners.push(index as u32),
+                    None => {
+                        vertices.push(Vertex {
+                            position,
+                            ..Default::default()
+                        });
+                        corners.push(vertices.len() as u32 - 1);
+                    }
+                }
+            }
+            indices.extend_from_slice(&[corners[0], corners[1], corners[2]]);
+            indices.extend_from_slice(&[corners[0], corners[2], corners[3]]);
+        }
+        assert_eq!(vertices.len(), 8);
+
+        // The cube's edges are 90 degrees, so they're hard: every corner is split into one vertex per face.
+        let (hard_vertices, hard_indices) =
+            generate_normals(&vertices, &indices, std::f32::consts::FRAC_PI_3);
+        assert_eq!(hard_vertices.len(), 24);
+        assert_eq!(hard_indices.len(), indices.len());
+        for (triangle, (normal, _, _)) in hard_indices.chunks_exact(6).zip(faces.iter()) {
+            for index in triangle {
+                let vertex = &hard_vertices[*index as usize];
+                assert_eq!(vertex.normal, *normal);
+                assert!(vertex.position.dot(&vertex.normal) > 0.);
+            }
+        }
+
+        // Smoothed across every edge, each corner has a single normal pointing out of the cube.
+        let (smooth_vertices, smooth_indices) =
+            generate_normals(&vertices, &indices, std::f32::consts::PI);
+        assert_eq!(smooth_vertices.len(), 8);
+        assert_eq!(smooth_indices, indices);
+        for vertex in &smooth_vertices {
+            assert!((vertex.normal.norm() - 1.).abs() < 0.0001);
+            for i in 0..3 {
+                assert!(vertex.position[i] * vertex.normal[i] > 0.);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_optimise_vertex_cache() {
+        // A strip of quads, with its triangles in a scrambled order.
+        let mut indices = Vec::new();
+        for quad in [3, 0, 4, 1, 2] {
+            let v = quad * 2;
+            indices.extend_from_slice(&[v, v + 1, v + 2, v + 2, v + 1, v + 3]);
+        }
+        let optimised = optimise_vertex_cache(&indices, 12);
+
+        // Every triangle is still there, with the same winding..
+        let normalise = |t: &[u32]| {
+            let first = (0..3).min_by_key(|i| t[*i]).unwrap();
+            [t[first], t[(first + 1) % 3], t[(first + 2) % 3]]
+        };
+        let mut before = indices.chunks(3).map(normalise).collect::<Vec<_>>();
+        let mut after = optimised.chunks(3).map(normalise).collect::<Vec<_>>();
+        before.sort_unstable();
+        after.sort_unstable();
+        assert_eq!(before, after);
+
+        // ..and each triangle after the first shares an edge with the one before it.
+        for pair in optimised.chunks(3).collect::<Vec<_>>().windows(2) {
+            let shared = pair[1].iter().filter(|v| pair[0].contains(v)).count();
+            assert_eq!(shared, 2);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::borrow::Cow;
+
+use ash::vk;
+use hecs::{Entity, World};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+
+use crate::{
+    components::{Mesh, TransformMatrix},
+    vertex::Vertex,
+};
+
+/// Rays closer to parallel with a triangle than this are treated as missing it
+const EPSILON: f32 = 1e-7;
+
+/// Information about where a ray hit a mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    /// The entity whose mesh was hit
+    pub entity: Entity,
+    /// Index of the primitive that was hit within the entity's `Mesh`
+    pub primitive_index: usize,
+    /// Distance along the ray to the hit, in world space
+    pub distance: f32,
+    /// The point that was hit, in world space
+    pub point: Point3<f32>,
+    /// The indices of the three vertices of the triangle that was hit
+    pub triangle: [u32; 3],
+    /// Barycentric weights of the hit point for each vertex in `triangle`
+    pub barycentric: Vector3<f32>,
+    /// The interpolated first set of texture coordinates at the hit point
+    pub uv: Vector2<f32>,
+    /// The interpolated surface normal at the hit point, in world space
+    pub normal: Vector3<f32>,
+}
+
+/// Cast a ray from `origin` along `direction` (both in world space) against the triangles of every `Mesh`,
+/// returning the closest hit within `max_distance`. Primitives drawn as points or lines can't be hit.
+///
+/// NOTE: Meshes are tested in their bind pose, so skinned meshes may not match what is drawn.
+pub fn raycast(
+    world: &World,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+    max_distance: f32,
+) -> Option<RaycastHit> {
+    raycast_filtered(world, origin, direction, max_distance, |_| true)
+}
+
+/// Like `raycast`, but only tests the meshes of entities for which `filter` returns true, eg. to ignore the mesh of
+/// whatever is casting the ray.
+pub fn raycast_filtered(
+    world: &World,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+    max_distance: f32,
+    filter: impl Fn(Entity) -> bool,
+) -> Option<RaycastHit> {
+    let mut closest: Option<RaycastHit> = None;
+    for (entity, (mesh, transform_matrix)) in world.query::<(&Mesh, &TransformMatrix)>().iter() {
+        if !filter(entity) {
+            continue;
+        }
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            let hit = raycast_triangles(
+                &primitive.vertices,
+                &get_triangle_list(&primitive.indices, primitive.topology),
+                &transform_matrix.0,
+                origin,
+                direction,
+            );
+            if let Some(hit) = hit {
+                let is_closer = closest.map_or(true, |c| hit.distance < c.distance);
+                if hit.distance <= max_distance && is_closer {
+                    closest = Some(RaycastHit {
+                        entity,
+                        primitive_index,
+                        distance: hit.distance,
+                        point: hit.point,
+                        triangle: hit.triangle,
+                        barycentric: hit.barycentric,
+                        uv: hit.uv,
+                        normal: hit.normal,
+                    });
+                }
+            }
+        }
+    }
+
+    closest
+}
+
+/// A hit against a single primitive, before it's known which entity it belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TriangleHit {
+    distance: f32,
+    point: Point3<f32>,
+    triangle: [u32; 3],
+    barycentric: Vector3<f32>,
+    uv: Vector2<f32>,
+    normal: Vector3<f32>,
+}
+
+/// Find the closest triangle hit by the ray. The ray is transformed into model space with the inverse of `transform`
+/// so the vertices don't need to be transformed.
+fn raycast_triangles(
+    vertices: &[Vertex],
+    indices: &[u32],
+    transform: &Matrix4<f32>,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+) -> Option<TriangleHit> {
+    let inverse_transform = transform.try_inverse()?;
+    let direction = direction.normalize();
+
+    // The model space direction is deliberately left unnormalised, so distances along it are in world units.
+    let model_origin = inverse_transform.transform_point(origin);
+    let model_direction = inverse_transform.transform_vector(&direction);
+
+    let mut closest: Option<(f32, [u32; 3], Vector3<f32>)> = None;
+    for triangle in indices.chunks_exact(3) {
+        let triangle = [triangle[0], triangle[1], triangle[2]];
+        let [a, b, c] = triangle;
+        let hit = intersect_triangle(
+            &model_origin,
+            &model_direction,
+            &vertices.get(a as usize)?.position,
+            &vertices.get(b as usize)?.position,
+            &vertices.get(c as usize)?.position,
+        );
+        if let Some((distance, barycentric)) = hit {
+            if closest.map_or(true, |(d, _, _)| distance < d) {
+                closest = Some((distance, triangle, barycentric));
+            }
+        }
+    }
+
+    let (distance, triangle, barycentric) = closest?;
+    let [a, b, c] = [
+        &vertices[triangle[0] as usize],
+        &vertices[triangle[1] as usize],
+        &vertices[triangle[2] as usize],
+    ];
+    let uv = a.texture_coords_0 * barycentric.x
+        + b.texture_coords_0 * barycentric.y
+        + c.texture_coords_0 * barycentric.z;
+
+    // Fall back to the face normal if the mesh doesn't have vertex normals.
+    let mut normal = a.normal * barycentric.x + b.normal * barycentric.y + c.normal * barycentric.z;
+    if normal.norm_squared() < EPSILON {
+        normal = (b.position - a.position).cross(&(c.position - a.position));
+    }
+    let normal = inverse_transform
+        .transpose()
+        .transform_vector(&normal)
+        .normalize();
+
+    Some(TriangleHit {
+        distance,
+        point: origin + direction * distance,
+        triangle,
+        barycentric,
+        uv,
+        normal,
+    })
+}
+
+/// `indices` as a triangle list, with their triangles assembled as `topology` would assemble them. Points and lines
+/// have no triangles, so are empty.
+fn get_triangle_list(indices: &[u32], topology: vk::PrimitiveTopology) -> Cow<[u32]> {
+    match topology {
+        vk::PrimitiveTopology::TRIANGLE_LIST => Cow::Borrowed(indices),
+        // Every other triangle of a strip is flipped, so they all wind the same way.
+        vk::PrimitiveTopology::TRIANGLE_STRIP => indices
+            .windows(3)
+            .enumerate()
+            .flat_map(|(i, t)| {
+                if i % 2 == 0 {
+                    [t[0], t[1], t[2]]
+                } else {
+                    [t[1], t[0], t[2]]
+                }
+            })
+            .collect(),
+        vk::PrimitiveTopology::TRIANGLE_FAN => indices
+            .get(1..)
+            .unwrap_or_default()
+            .windows(2)
+            .flat_map(|t| [indices[0], t[0], t[1]])
+            .collect(),
+        _ => Cow::Borrowed(&[]),
+    }
+}
+
+/// Möller–Trumbore ray/triangle intersection. Returns the distance along `direction` to the hit and the barycentric
+/// weights of `a`, `b` and `c` at the hit point. Both sides of the triangle can be hit.
+fn intersect_triangle(
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+    a: &Vector3<f32>,
+    b: &Vector3<f32>,
+    c: &Vector3<f32>,
+) -> Option<(f32, Vector3<f32>)> {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+    let p = direction.cross(&edge_2);
+    let determinant = edge_1.dot(&p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let s = origin.coords - a;
+    let u = s.dot(&p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge_1);
+    let v = direction.dot(&q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge_2.dot(&q) * inverse_determinant;
+    if distance < 0.0 {
+        return None;
+    }
+
+    Some((distance, Vector3::new(1.0 - u - v, u, v)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::{vector, Isometry3, Translation3, UnitQuaternion};
+
+    fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+        Vertex {
+            position: vector![x, y, 0.],
+            normal: vector![0., 0., 1.],
+            texture_coords_0: vector![u, v],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_raycast_triangle() {
+        // A triangle in the XY plane, with UVs matching its positions.
+        let vertices = [
+            vertex(0., 0., 0., 0.),
+            vertex(1., 0., 1., 0.),
+            vertex(0., 1., 0., 1.),
+        ];
+        let indices = [0, 1, 2];
+        let origin = Point3::new(0.25, 0.5, 2.);
+        let direction = vector![0., 0., -1.];
+
+        let hit = raycast_triangles(
+            &vertices,
+            &indices,
+            &Matrix4::identity(),
+            &origin,
+            &direction,
+        )
+        .unwrap();
+        assert_eq!(hit.triangle, [0, 1, 2]);
+        assert_relative_eq!(hit.distance, 2.);
+        assert_relative_eq!(hit.barycentric, vector![0.25, 0.25, 0.5]);
+        assert_relative_eq!(hit.barycentric.sum(), 1.);
+        assert_relative_eq!(hit.uv, vector![0.25, 0.5]);
+        assert_relative_eq!(hit.normal, vector![0., 0., 1.]);
+        assert_relative_eq!(hit.point, Point3::new(0.25, 0.5, 0.));
+
+        // Move the triangle and turn it to face along +X: the ray must be transformed into model space to hit it.
+        let transform = Isometry3::from_parts(
+            Translation3::new(5., 0., 0.),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2),
+        )
+        .to_homogeneous();
+        let origin = Point3::new(10., 0.5, -0.25);
+        let direction = vector![-2., 0., 0.];
+        let hit = raycast_triangles(&vertices, &indices, &transform, &origin, &direction).unwrap();
+        assert_relative_eq!(hit.distance, 5., epsilon = 1e-5);
+        assert_relative_eq!(hit.barycentric, vector![0.25, 0.25, 0.5], epsilon = 1e-5);
+        assert_relative_eq!(hit.normal, vector![1., 0., 0.], epsilon = 1e-5);
+
+        // Missing the triangle entirely.
+        let origin = Point3::new(2., 2., 2.);
+        let direction = vector![0., 0., -1.];
+        assert!(raycast_triangles(
+            &vertices,
+            &indices,
+            &Matrix4::identity(),
+            &origin,
+            &direction
+        )
+        .is_none());
+    }
+
+    #[test]
+    pub fn test_triangle_list_for_topology() {
+        let indices = [0, 1, 2, 3, 4];
+        let list = |topology| get_triangle_list(&indices, topology).into_owned();
+        assert_eq!(list(vk::PrimitiveTopology::TRIANGLE_LIST), indices);
+        assert_eq!(
+            list(vk::PrimitiveTopology::TRIANGLE_STRIP),
+            [0, 1, 2, 2, 1, 3, 2, 3, 4]
+        );
+        assert_eq!(
+            list(vk::PrimitiveTopology::TRIANGLE_FAN),
+            [0, 1, 2, 0, 2, 3, 0, 3, 4]
+        );
+        assert!(list(vk::PrimitiveTopology::LINE_STRIP).is_empty());
+        assert!(list(vk::PrimitiveTopology::POINT_LIST).is_empty());
+        assert!(get_triangle_list(&[], vk::PrimitiveTopology::TRIANGLE_FAN).is_empty());
+
+        // A quad drawn as a strip can be hit in either of its triangles.
+        let vertices = [
+            vertex(0., 0., 0., 0.),
+            vertex(1., 0., 1., 0.),
+            vertex(0., 1., 0., 1.),
+            vertex(1., 1., 1., 1.),
+        ];
+        let strip = get_triangle_list(&[0, 1, 2, 3], vk::PrimitiveTopology::TRIANGLE_STRIP);
+        let direction = vector![0., 0., -1.];
+        for (x, y) in [(0.25, 0.25), (0.75, 0.75)] {
+            let origin = Point3::new(x, y, 1.);
+            let hit =
+                raycast_triangles(&vertices, &strip, &Matrix4::identity(), &origin, &direction)
+                    .unwrap();
+            assert_relative_eq!(hit.uv, vector![x, y], epsilon = 1e-5);
+        }
+
+        // Lines have no surface to hit, even if their vertices would make triangles under the ray.
+        let lines = get_triangle_list(&[0, 1, 2, 3], vk::PrimitiveTopology::LINE_LIST);
+        let origin = Point3::new(0., 0., 1.);
+        assert!(
+            raycast_triangles(&vertices, &lines, &Matrix4::identity(), &origin, &direction)
+                .is_none()
+        );
+    }
+}
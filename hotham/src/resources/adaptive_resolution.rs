@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+/// Measurements ignored after the render scale changes, as frames already in flight were rendered at the old scale
+const SETTLE_FRAMES: u32 = 5;
+
+/// Automatically raises and lowers `RenderContext`'s render scale to keep the GPU's frame time within budget.
+/// Disabled by default.
+///
+/// The budget is `target_utilisation` of the display period the runtime predicts for each frame, leaving headroom
+/// for the compositor. When the GPU is over budget for `frames_to_decrease` frames in a row, the scale is lowered by
+/// `step`. It's only raised again once the GPU has been under `increase_threshold` of the budget for
+/// `frames_to_increase` frames in a row, so the scale doesn't oscillate between two values that sit either side of the
+/// budget. Between the two, the scale is left alone.
+///
+/// GPU time is measured with timestamp queries. If the device doesn't support them, the scale is never changed.
+/// The current scale is available from `RenderContext::render_scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveResolution {
+    /// Is the render scale adjusted automatically? Defaults to `false`.
+    pub enabled: bool,
+    /// The fraction of the display period the GPU should take at most. Defaults to `0.9`.
+    pub target_utilisation: f32,
+    /// The fraction of the budget the GPU must be under before the scale is raised. Defaults to `0.75`.
+    pub increase_threshold: f32,
+    /// The lowest render scale that will be used. Defaults to `0.6`.
+    pub min_scale: f32,
+    /// The highest render scale that will be used. Defaults to `1.0`.
+    pub max_scale: f32,
+    /// How much the render scale changes by at a time. Defaults to `0.05`.
+    pub step: f32,
+    /// Frames in a row over budget before the scale is lowered. Defaults to `5`.
+    pub frames_to_decrease: u32,
+    /// Frames in a row well under budget before the scale is raised. Defaults to `90`.
+    pub frames_to_increase: u32,
+    budget: Duration,
+    over_budget_frames: u32,
+    under_budget_frames: u32,
+    settle_frames: u32,
+}
+
+impl Default for AdaptiveResolution {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_utilisation: 0.9,
+            increase_threshold: 0.75,
+            min_scale: 0.6,
+            max_scale: 1.0,
+            step: 0.05,
+            frames_to_decrease: 5,
+            frames_to_increase: 90,
+            budget: Duration::ZERO,
+            over_budget_frames: 0,
+            under_budget_frames: 0,
+            settle_frames: 0,
+        }
+    }
+}
+
+impl AdaptiveResolution {
+    /// The GPU time each frame is aiming for, from the most recent display period. Zero until a frame has been timed.
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// Record that the GPU took `gpu_time` to render a frame at `render_scale`, to be displayed for `display_period`.
+    /// Returns the scale to render at from now on, if it should change.
+    pub(crate) fn update(
+        &mut self,
+        gpu_time: Duration,
+        display_period: Duration,
+        render_scale: f32,
+    ) -> Option<f32> {
+        if !self.enabled || display_period.is_zero() {
+            return None;
+        }
+
+        self.budget = display_period.mul_f32(self.target_utilisation);
+        if self.settle_frames > 0 {
+            self.settle_frames -= 1;
+            return None;
+        }
+
+        if gpu_time > self.budget {
+            self.over_budget_frames += 1;
+            self.under_budget_frames = 0;
+        } else if gpu_time < self.budget.mul_f32(self.increase_threshold) {
+            self.under_budget_frames += 1;
+            self.over_budget_frames = 0;
+        } else {
+            self.over_budget_frames = 0;
+            self.under_budget_frames = 0;
+        }
+
+        let new_scale = if self.over_budget_frames >= self.frames_to_decrease {
+            render_scale - self.step
+        } else if self.under_budget_frames >= self.frames_to_increase {
+            render_scale + self.step
+        } else {
+            return None;
+        };
+        let new_scale = new_scale.clamp(self.min_scale, self.max_scale);
+
+        self.over_budget_frames = 0;
+        self.under_budget_frames = 0;
+        if (new_scale - render_scale).abs() < f32::EPSILON {
+            return None;
+        }
+
+        self.settle_frames = SETTLE_FRAMES;
+        Some(new_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn test_over_budget_lowers_scale() {
+        let mut adaptive_resolution = AdaptiveResolution {
+            enabled: true,
+            ..Default::default()
+        };
+
+        // 72Hz, with the GPU taking 16ms a frame.
+        let display_period = Duration::from_micros(13_889);
+        let gpu_time = Duration::from_millis(16);
+        let mut render_scale = 1.0;
+        let mut changes = 0;
+        for _ in 0..200 {
+            if let Some(s) = adaptive_resolution.update(gpu_time, display_period, render_scale) {
+                assert!(s < render_scale);
+                render_scale = s;
+                changes += 1;
+            }
+        }
+        assert_relative_eq!(
+            adaptive_resolution.budget().as_secs_f32(),
+            0.0125,
+            epsilon = 1e-5
+        );
+
+        // The scale is lowered one step at a time until it reaches the minimum, then stays there.
+        assert_relative_eq!(render_scale, adaptive_resolution.min_scale);
+        assert_eq!(changes, 8);
+
+        // Somewhere between the budget and the increase threshold, the scale is left alone..
+        let gpu_time = Duration::from_millis(11);
+        for _ in 0..200 {
+            assert_eq!(
+                adaptive_resolution.update(gpu_time, display_period, render_scale),
+                None
+            );
+        }
+
+        // ..but once the GPU is well under budget for long enough it's raised again.
+        let gpu_time = Duration::from_millis(8);
+        let raised = (0..200)
+            .find_map(|_| adaptive_resolution.update(gpu_time, display_period, render_scale));
+        assert_relative_eq!(raised.unwrap(), render_scale + adaptive_resolution.step);
+
+        // A single slow frame isn't enough to lower it.
+        let mut adaptive_resolution = AdaptiveResolution {
+            enabled: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            adaptive_resolution.update(Duration::from_millis(30), display_period, 1.0),
+            None
+        );
+        for _ in 0..100 {
+            assert_eq!(
+                adaptive_resolution.update(Duration::from_millis(11), display_period, 1.0),
+                None
+            );
+        }
+
+        // Nothing changes when it's disabled.
+        adaptive_resolution.enabled = false;
+        for _ in 0..100 {
+            assert_eq!(
+                adaptive_resolution.update(Duration::from_millis(30), display_period, 1.0),
+                None
+            );
+        }
+    }
+}
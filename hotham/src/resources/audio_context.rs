@@ -5,6 +5,7 @@ use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Stream,
 };
+use log::{debug, error, info};
 use oddio::{Frames, FramesSignal, Handle, Mixer, SpatialBuffered, SpatialScene, Stop};
 use symphonia::core::{audio::SampleBuffer, io::MediaSourceStream, probe::Hint};
 
@@ -39,8 +40,9 @@ impl Default for AudioContext {
         let device = host
             .default_output_device()
             .expect("no output device available");
-        println!(
-            "[HOTHAM_AUDIO_CONTEXT] Using default audio device: {}",
+        info!(
+            target: "HOTHAM_AUDIO_CONTEXT",
+            "Using default audio device: {}",
             device.name().unwrap()
         );
         let sample_rate = device.default_output_config().unwrap().sample_rate();
@@ -49,7 +51,7 @@ impl Default for AudioContext {
             sample_rate,
             buffer_size: cpal::BufferSize::Default,
         };
-        println!("[HOTHAM_AUDIO_CONTEXT] cpal AudioConfig: {:?}", config);
+        info!(target: "HOTHAM_AUDIO_CONTEXT", "cpal AudioConfig: {:?}", config);
 
         // Create a spatialised audio scene
         let (scene_handle, scene) = oddio::split(oddio::SpatialScene::new(sample_rate.0, 0.1));
@@ -69,8 +71,9 @@ impl Default for AudioContext {
                     oddio::run(&mixer, sample_rate.0, out_stereo);
                 },
                 |err| {
-                    eprintln!(
-                        "[HOTHAM_AUDIO_CONTEXT] An error occurred playing the audio stream: {}",
+                    error!(
+                        target: "HOTHAM_AUDIO_CONTEXT",
+                        "An error occurred playing the audio stream: {}",
                         err
                     )
                 },
@@ -163,9 +166,9 @@ impl AudioContext {
 
     /// Add a music track
     pub fn add_music_track(&mut self, mp3_bytes: Vec<u8>) -> MusicTrack {
-        println!("[AUDIO_CONTEXT] Decoding MP3..");
+        debug!(target: "AUDIO_CONTEXT", "Decoding MP3..");
         let frames = get_stereo_frames_from_mp3(mp3_bytes);
-        println!("[AUDIO_CONTEXT] ..done!");
+        debug!(target: "AUDIO_CONTEXT", "..done!");
         let track = MusicTrack {
             index: self.music_tracks_inner.insert(frames),
         };
@@ -266,7 +269,7 @@ fn decode_mp3(mp3_bytes: Vec<u8>) -> (Vec<f32>, u32) {
         let packet = match reader.next_packet() {
             Ok(packet) => packet,
             Err(err) => {
-                eprintln!("Error reading packet: {:?}", err);
+                error!("Error reading packet: {:?}", err);
                 break;
             }
         };
@@ -287,7 +290,7 @@ fn decode_mp3(mp3_bytes: Vec<u8>) -> (Vec<f32>, u32) {
                 }
             }
             Err(err) => {
-                eprintln!("Error while decoding: {:?}", err);
+                error!("Error while decoding: {:?}", err);
                 break;
             }
         }
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use nalgebra::Vector3;
+
+/// How far the thumbstick must be pushed before a snap turn is triggered
+const SNAP_TURN_THRESHOLD: f32 = 0.7;
+/// Radius of the vignette when the player is stationary, in normalised screen units. Large enough to be invisible.
+const VIGNETTE_MAX_RADIUS: f32 = 1.5;
+/// Radius of the vignette when the player is moving at `vignette_max_speed` with full intensity.
+const VIGNETTE_MIN_RADIUS: f32 = 0.4;
+
+/// Settings and state used to reduce motion sickness
+/// - Snap turning rotates the player in fixed increments rather than smoothly
+/// - The tunneling vignette darkens the periphery of the player's vision while they're moving
+#[derive(Clone, Debug)]
+pub struct ComfortContext {
+    /// Should the player be able to snap turn with the right thumbstick? Defaults to `true`.
+    pub snap_turn_enabled: bool,
+    /// How far to turn with each snap, in radians. Defaults to 30 degrees.
+    pub snap_turn_angle: f32,
+    /// Minimum time between two snap turns. Defaults to 250ms.
+    pub snap_turn_interval: Duration,
+    /// Should the periphery be darkened while the player is moving? Defaults to `true`.
+    pub vignette_enabled: bool,
+    /// How strong the vignette is, from `0.0` to `1.0`. Defaults to `0.8`.
+    pub vignette_intensity: f32,
+    /// Speed, in metres per second, at which the vignette is at its strongest. Defaults to `3.0`.
+    pub vignette_max_speed: f32,
+    /// Velocity of the player, in metres per second. Locomotion systems should update this each frame.
+    pub player_velocity: Vector3<f32>,
+    last_snap_turn: Option<Instant>,
+}
+
+/// Parameters sent to the vignette shader
+///
+/// NOTE: The layout of this struct must match the `Vignette` push constant in `vignette.frag`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vignette {
+    /// Distance from the centre of the view at which the vignette starts, in normalised screen units
+    pub radius: f32,
+    /// How dark the vignette is at the edges, from `0.0` to `1.0`
+    pub intensity: f32,
+}
+
+impl Default for ComfortContext {
+    fn default() -> Self {
+        Self {
+            snap_turn_enabled: true,
+            snap_turn_angle: 30_f32.to_radians(),
+            snap_turn_interval: Duration::from_millis(250),
+            vignette_enabled: true,
+            vignette_intensity: 0.8,
+            vignette_max_speed: 3.0,
+            player_velocity: Vector3::zeros(),
+            last_snap_turn: None,
+        }
+    }
+}
+
+impl ComfortContext {
+    /// Given the horizontal position of the thumbstick, work out whether the player should snap turn.
+    /// Returns the angle to turn by, in radians, or `None` if no turn should happen this frame.
+    pub fn snap_turn(&mut self, thumbstick_x: f32, now: Instant) -> Option<f32> {
+        if !self.snap_turn_enabled || thumbstick_x.abs() < SNAP_TURN_THRESHOLD {
+            return None;
+        }
+
+        if let Some(last_snap_turn) = self.last_snap_turn {
+            if now.saturating_duration_since(last_snap_turn) < self.snap_turn_interval {
+                return None;
+            }
+        }
+
+        self.last_snap_turn = Some(now);
+        Some(thumbstick_x.signum() * self.snap_turn_angle)
+    }
+
+    /// The vignette to draw this frame, based on how fast the player is moving.
+    /// Returns `None` if the vignette is disabled or the player is stationary.
+    pub fn vignette(&self) -> Option<Vignette> {
+        if !self.vignette_enabled || self.vignette_max_speed <= 0. {
+            return None;
+        }
+
+        let intensity = self.vignette_intensity.clamp(0., 1.);
+        let speed = (self.player_velocity.norm() / self.vignette_max_speed).clamp(0., 1.);
+        if speed == 0. || intensity == 0. {
+            return None;
+        }
+
+        let radius =
+            VIGNETTE_MAX_RADIUS - (VIGNETTE_MAX_RADIUS - VIGNETTE_MIN_RADIUS) * speed * intensity;
+        Some(Vignette { radius, intensity })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::vector;
+
+    #[test]
+    pub fn test_snap_turn() {
+        let mut comfort_context = ComfortContext::default();
+        let now = Instant::now();
+        let angle = comfort_context.snap_turn_angle;
+
+        assert_eq!(comfort_context.snap_turn(0.2, now), None);
+        assert_eq!(comfort_context.snap_turn(1.0, now), Some(angle));
+
+        // Too soon
+        let soon = now + Duration::from_millis(100);
+        assert_eq!(comfort_context.snap_turn(-1.0, soon), None);
+
+        let later = now + Duration::from_millis(300);
+        assert_eq!(comfort_context.snap_turn(-1.0, later), Some(-angle));
+
+        comfort_context.snap_turn_enabled = false;
+        assert_eq!(
+            comfort_context.snap_turn(1.0, later + Duration::from_secs(1)),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_vignette_scales_with_speed() {
+        let mut comfort_context = ComfortContext::default();
+        assert_eq!(comfort_context.vignette(), None);
+
+        comfort_context.player_velocity = vector![1., 0., 0.];
+        let slow = comfort_context.vignette().unwrap();
+
+        comfort_context.player_velocity = vector![0., 0., 10.];
+        let fast = comfort_context.vignette().unwrap();
+        assert!(fast.radius < slow.radius);
+        assert_eq!(fast.intensity, comfort_context.vignette_intensity);
+
+        comfort_context.vignette_enabled = false;
+        assert_eq!(comfort_context.vignette(), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+/// The refresh rate the budget is derived from when the runtime doesn't report one, in Hz
+const DEFAULT_REFRESH_RATE: f32 = 72.;
+
+/// A part of the frame whose CPU time is broken down by `CpuTimer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuPhase {
+    Animation,
+    Skinning,
+    /// Gathering, culling and sorting draw calls in `rendering_system`
+    Culling,
+    /// Recording draw calls in `rendering_system`
+    Recording,
+    /// Any other part of the frame, eg. an application's own systems
+    Other(&'static str),
+}
+
+/// Measures the CPU time of each frame, from when `begin_frame` has finished waiting for the runtime until
+/// `end_frame`, and logs a warning with a breakdown by `CpuPhase` when it goes over budget. Catches performance
+/// regressions during development, alongside `RenderContext::gpu_frame_time`.
+///
+/// `skinning_system` and `rendering_system` time their own phases. Time anything else with `CpuTimer::time`:
+/// ```ignore
+/// render_context.cpu_timer.time(CpuPhase::Animation, || {
+///     animation_system(&mut queries.animation_query, world)
+/// });
+/// ```
+///
+/// Only enabled in debug builds by default.
+#[derive(Debug, Clone)]
+pub struct CpuTimer {
+    /// Are frames timed? Defaults to `true` in debug builds and `false` in release builds.
+    pub enabled: bool,
+    /// The most CPU time a frame can take before a warning is logged. If `None`, one display refresh at
+    /// `XrContext::display_refresh_rate` is used.
+    pub budget: Option<Duration>,
+    frame_start: Option<Instant>,
+    phases: RefCell<Vec<(CpuPhase, Duration)>>,
+    last_frame_time: Option<Duration>,
+}
+
+impl Default for CpuTimer {
+    fn default() -> Self {
+        Self {
+            enabled: cfg!(debug_assertions),
+            budget: None,
+            frame_start: None,
+            phases: Default::default(),
+            last_frame_time: None,
+        }
+    }
+}
+
+impl CpuTimer {
+    /// Start timing a frame. Called by `begin_frame`.
+    pub(crate) fn begin_frame(&mut self) {
+        self.phases.get_mut().clear();
+        self.frame_start = if self.enabled {
+            Some(Instant::now())
+        } else {
+            None
+        };
+    }
+
+    /// Run `f`, adding the time it takes to `phase`
+    pub fn time<R>(&self, phase: CpuPhase, f: impl FnOnce() -> R) -> R {
+        if self.frame_start.is_none() {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    /// Add `duration` to the time spent in `phase` this frame
+    pub fn record(&self, phase: CpuPhase, duration: Duration) {
+        if self.frame_start.is_none() {
+            return;
+        }
+
+        let mut phases = self.phases.borrow_mut();
+        match phases.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += duration,
+            None => phases.push((phase, duration)),
+        }
+    }
+
+    /// Finish timing the frame, warning if it went over budget. Called by `end_frame`.
+    pub(crate) fn end_frame(&mut self, display_refresh_rate: Option<f32>) {
+        let frame_start = match self.frame_start.take() {
+            Some(frame_start) => frame_start,
+            None => return,
+        };
+
+        let frame_time = frame_start.elapsed();
+        self.last_frame_time = Some(frame_time);
+        let budget = get_budget(self.budget, display_refresh_rate);
+        if frame_time > budget {
+            warn!(
+                target: "HOTHAM_CPU_TIMER",
+                "CPU frame time of {:?} is over the budget of {:?} - {}",
+                frame_time,
+                budget,
+                get_breakdown(frame_time, &self.phases.borrow())
+            );
+        }
+    }
+
+    /// The CPU time of the last frame that was timed
+    pub fn last_frame_time(&self) -> Option<Duration> {
+        self.last_frame_time
+    }
+}
+
+fn get_budget(budget: Option<Duration>, display_refresh_rate: Option<f32>) -> Duration {
+    budget.unwrap_or_else(|| {
+        Duration::from_secs_f64(1. / display_refresh_rate.unwrap_or(DEFAULT_REFRESH_RATE) as f64)
+    })
+}
+
+/// Describe where the time in a frame went, eg. `skinning: 1ms, culling: 2ms, untimed: 5ms`
+fn get_breakdown(frame_time: Duration, phases: &[(CpuPhase, Duration)]) -> String {
+    let timed = phases.iter().map(|(_, d)| *d).sum::<Duration>();
+    phases
+        .iter()
+        .map(|(phase, duration)| {
+            let name = match phase {
+                CpuPhase::Other(name) => name.to_string(),
+                phase => format!("{:?}", phase).to_lowercase(),
+            };
+            format!("{}: {:?}", name, duration)
+        })
+        .chain(std::iter::once(format!(
+            "untimed: {:?}",
+            frame_time.saturating_sub(timed)
+        )))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_cpu_timer() {
+        let mut cpu_timer = CpuTimer {
+            enabled: true,
+            ..Default::default()
+        };
+        cpu_timer.begin_frame();
+        cpu_timer.record(CpuPhase::Skinning, Duration::from_millis(1));
+        cpu_timer.record(CpuPhase::Skinning, Duration::from_millis(2));
+        assert_eq!(cpu_timer.time(CpuPhase::Other("physics"), || 42), 42);
+        assert_eq!(
+            cpu_timer.phases.borrow()[0],
+            (CpuPhase::Skinning, Duration::from_millis(3))
+        );
+        assert_eq!(cpu_timer.phases.borrow()[1].0, CpuPhase::Other("physics"));
+        cpu_timer.end_frame(Some(90.));
+        assert!(cpu_timer.last_frame_time().is_some());
+
+        let breakdown = get_breakdown(
+            Duration::from_millis(10),
+            &[
+                (CpuPhase::Skinning, Duration::from_millis(1)),
+                (CpuPhase::Other("physics"), Duration::from_millis(2)),
+            ],
+        );
+        assert_eq!(breakdown, "skinning: 1ms, physics: 2ms, untimed: 7ms");
+
+        assert_eq!(get_budget(None, Some(100.)), Duration::from_millis(10));
+        assert_eq!(
+            get_budget(Some(Duration::from_millis(5)), Some(100.)),
+            Duration::from_millis(5)
+        );
+
+        // Nothing is timed while disabled.
+        cpu_timer.enabled = false;
+        cpu_timer.begin_frame();
+        cpu_timer.record(CpuPhase::Culling, Duration::from_millis(1));
+        assert!(cpu_timer.phases.borrow().is_empty());
+    }
+}
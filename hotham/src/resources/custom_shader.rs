@@ -0,0 +1,222 @@
+use std::{collections::HashMap, io::Cursor};
+
+use anyhow::{anyhow, Result};
+use ash::{util::read_spv, vk};
+
+use crate::{resources::VulkanContext, vertex::Vertex};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const DECORATION_LOCATION: u32 = 30;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+
+/// Vertex and fragment shaders to draw a `Primitive` with, in place of Hotham's PBR shaders, for custom effects like
+/// water or toon shading. Set `Primitive::custom_shader` to use them: `rendering_system` then creates a pipeline for
+/// them the first time the primitive is drawn. Primitives without one use the PBR shaders.
+///
+/// The shaders are used with the PBR pipeline layout, so they get the same descriptor sets, and the `Material` as a
+/// push constant, as `pbr.vert` and `pbr.frag`. Both must have a `main` entry point. Reflections in a `Mirror` are
+/// still drawn with the PBR shaders.
+///
+/// The shader modules aren't destroyed by Hotham: call `destroy` once every primitive using them has been despawned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomShader {
+    /// The vertex shader
+    pub vertex: vk::ShaderModule,
+    /// The fragment shader
+    pub fragment: vk::ShaderModule,
+}
+
+impl CustomShader {
+    /// Create shader modules from SPIR-V. Fails if the vertex shader reads any input that `Vertex` doesn't provide:
+    /// see `validate_vertex_inputs`.
+    pub fn new(
+        vulkan_context: &VulkanContext,
+        vertex_spirv: &[u8],
+        fragment_spirv: &[u8],
+    ) -> Result<Self> {
+        let vertex_code = read_spv(&mut Cursor::new(vertex_spirv))?;
+        let fragment_code = read_spv(&mut Cursor::new(fragment_spirv))?;
+        validate_vertex_inputs(&vertex_code)?;
+
+        let create = |code: &[u32]| unsafe {
+            vulkan_context
+                .device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(code), None)
+        };
+        let vertex = create(&vertex_code)?;
+        let fragment = create(&fragment_code)?;
+        Ok(Self { vertex, fragment })
+    }
+
+    /// Destroy the shader modules. Pipelines already created from them keep working.
+    ///
+    /// # Safety
+    /// No pipeline may be created from them afterwards, so no primitive still using them may be drawn again.
+    pub unsafe fn destroy(&self, vulkan_context: &VulkanContext) {
+        vulkan_context
+            .device
+            .destroy_shader_module(self.vertex, None);
+        vulkan_context
+            .device
+            .destroy_shader_module(self.fragment, None);
+    }
+}
+
+/// Check that every input of the vertex shader in `spirv` matches an attribute of `Vertex`: it must be a float scalar
+/// or vector, at one of `Vertex::attribute_descriptions`' locations, with no more components than the attribute.
+pub fn validate_vertex_inputs(spirv: &[u32]) -> Result<()> {
+    if spirv.len() < 5 || spirv[0] != SPIRV_MAGIC {
+        return Err(anyhow!("The vertex shader isn't valid SPIR-V"));
+    }
+
+    let mut is_vertex_shader = false;
+    let mut locations = HashMap::new();
+    // Types, by ID: (is float, component count) for scalars and vectors, and the type pointed to for pointers.
+    let mut scalar_types = HashMap::new();
+    let mut pointer_types = HashMap::new();
+    let mut inputs = Vec::new();
+
+    let mut words = &spirv[5..];
+    while !words.is_empty() {
+        let word_count = (words[0] >> 16) as usize;
+        if word_count == 0 || word_count > words.len() {
+            return Err(anyhow!("The vertex shader's SPIR-V is truncated"));
+        }
+        let (instruction, rest) = words.split_at(word_count);
+        words = rest;
+        match (instruction[0] & 0xFFFF, instruction) {
+            (OP_ENTRY_POINT, [_, model, ..]) => {
+                is_vertex_shader |= *model == EXECUTION_MODEL_VERTEX;
+            }
+            (OP_DECORATE, [_, target, DECORATION_LOCATION, location]) => {
+                locations.insert(*target, *location);
+            }
+            (OP_TYPE_INT, [_, id, ..]) => {
+                scalar_types.insert(*id, (false, 1));
+            }
+            (OP_TYPE_FLOAT, [_, id, ..]) => {
+                scalar_types.insert(*id, (true, 1));
+            }
+            (OP_TYPE_VECTOR, [_, id, component_type, count]) => {
+                if let Some((is_float, _)) = scalar_types.get(component_type).copied() {
+                    scalar_types.insert(*id, (is_float, *count));
+                }
+            }
+            (OP_TYPE_POINTER, [_, id, _, pointee]) => {
+                pointer_types.insert(*id, *pointee);
+            }
+            (OP_VARIABLE, [_, pointer_type, id, STORAGE_CLASS_INPUT, ..]) => {
+                inputs.push((*id, *pointer_type));
+            }
+            _ => {}
+        }
+    }
+
+    if !is_vertex_shader {
+        return Err(anyhow!("The vertex shader has no vertex entry point"));
+    }
+
+    let attributes = Vertex::attribute_descriptions();
+    for (id, pointer_type) in inputs {
+        // Built-ins like gl_VertexIndex have no location.
+        let location = match locations.get(&id) {
+            Some(location) => *location,
+            None => continue,
+        };
+        let attribute = attributes
+            .iter()
+            .find(|a| a.location == location)
+            .ok_or_else(|| anyhow!("Vertex has no attribute at input location {}", location))?;
+        let input_type = pointer_types
+            .get(&pointer_type)
+            .and_then(|t| scalar_types.get(t));
+        match input_type {
+            Some((true, count)) if *count <= get_component_count(attribute.format) => {}
+            _ => {
+                return Err(anyhow!(
+                    "The vertex shader's input at location {} doesn't match Vertex's {:?}",
+                    location,
+                    attribute.format
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get_component_count(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R32_SFLOAT => 1,
+        vk::Format::R32G32_SFLOAT => 2,
+        vk::Format::R32G32B32_SFLOAT => 3,
+        vk::Format::R32G32B32A32_SFLOAT => 4,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal vertex shader that reads a `vecN` (or, if `is_float` is false, an `ivecN`) input at `location`
+    fn get_vertex_shader(location: u32, is_float: bool, component_count: u32) -> Vec<u32> {
+        let op = |opcode: u32, operands: &[u32]| {
+            let mut instruction = vec![((operands.len() as u32 + 1) << 16) | opcode];
+            instruction.extend_from_slice(operands);
+            instruction
+        };
+        // IDs: 1 main, 2 scalar type, 3 vector type, 4 pointer type, 5 input
+        let mut spirv = vec![SPIRV_MAGIC, 0x0001_0000, 0, 6, 0];
+        spirv.extend(op(
+            OP_ENTRY_POINT,
+            &[
+                EXECUTION_MODEL_VERTEX,
+                1,
+                u32::from_le_bytes(*b"main"),
+                0,
+                5,
+            ],
+        ));
+        spirv.extend(op(OP_DECORATE, &[5, DECORATION_LOCATION, location]));
+        if is_float {
+            spirv.extend(op(OP_TYPE_FLOAT, &[2, 32]));
+        } else {
+            spirv.extend(op(OP_TYPE_INT, &[2, 32, 1]));
+        }
+        spirv.extend(op(OP_TYPE_VECTOR, &[3, 2, component_count]));
+        spirv.extend(op(OP_TYPE_POINTER, &[4, STORAGE_CLASS_INPUT, 3]));
+        spirv.extend(op(OP_VARIABLE, &[4, 5, STORAGE_CLASS_INPUT]));
+        spirv
+    }
+
+    #[test]
+    pub fn test_validate_vertex_inputs() {
+        // Hotham's own vertex shader matches, of course..
+        let pbr_vert = include_bytes!("../../shaders/pbr.vert.spv");
+        let pbr_vert = read_spv(&mut Cursor::new(&pbr_vert[..])).unwrap();
+        validate_vertex_inputs(&pbr_vert).unwrap();
+
+        // ..as does a shader that only reads some of an attribute, like the xy of the position..
+        validate_vertex_inputs(&get_vertex_shader(0, true, 2)).unwrap();
+        validate_vertex_inputs(&get_vertex_shader(6, true, 4)).unwrap();
+
+        // ..but not one that reads more components than there are, integers, or a location Vertex doesn't have.
+        assert!(validate_vertex_inputs(&get_vertex_shader(2, true, 4)).is_err());
+        assert!(validate_vertex_inputs(&get_vertex_shader(0, false, 3)).is_err());
+        assert!(validate_vertex_inputs(&get_vertex_shader(7, true, 4)).is_err());
+
+        // A fragment shader isn't a vertex shader.
+        let pbr_frag = include_bytes!("../../shaders/pbr.frag.spv");
+        let pbr_frag = read_spv(&mut Cursor::new(&pbr_frag[..])).unwrap();
+        assert!(validate_vertex_inputs(&pbr_frag).is_err());
+    }
+}
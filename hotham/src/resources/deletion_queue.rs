@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+
+use ash::vk;
+use hecs::{Entity, NoSuchEntity, World};
+
+use crate::{
+    components::{Mesh, Parent, UiPanel},
+    image::Image,
+    resources::VulkanContext,
+};
+
+/// A GPU resource waiting to be destroyed
+#[derive(Debug, Clone)]
+pub enum GpuResource {
+    Buffer(vk::Buffer, vk::DeviceMemory),
+    Image(Image),
+    DescriptorSet(vk::DescriptorSet),
+    Framebuffer(vk::Framebuffer),
+    Pipeline(vk::Pipeline),
+}
+
+impl GpuResource {
+    /// The number of Vulkan objects destroyed along with this resource, eg. a buffer and its memory
+    fn object_count(&self) -> usize {
+        match self {
+            GpuResource::Buffer(..) => 2,
+            GpuResource::Image(..) => 3,
+            GpuResource::DescriptorSet(..)
+            | GpuResource::Framebuffer(..)
+            | GpuResource::Pipeline(..) => 1,
+        }
+    }
+}
+
+/// GPU resources that are no longer used, waiting for the frames that might still be using them to finish.
+///
+/// Resources are keyed by the frame they were released in, and destroyed once every frame that was in flight at the
+/// time has been waited on. Use `RenderContext::despawn` to remove an entity and release its resources.
+#[derive(Debug, Clone, Default)]
+pub struct DeletionQueue {
+    pending: BTreeMap<usize, Vec<GpuResource>>,
+}
+
+impl DeletionQueue {
+    /// Queue `resource` to be destroyed once `frame_index` is no longer in flight
+    pub fn push(&mut self, frame_index: usize, resource: GpuResource) {
+        self.pending.entry(frame_index).or_default().push(resource);
+    }
+
+    /// The number of resources waiting to be destroyed
+    pub fn len(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+
+    /// Are there no resources waiting to be destroyed?
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Despawn `entity` and its descendants (every entity whose `Parent` chain leads back to it), queueing the GPU
+    /// resources they own to be destroyed. These are each `Mesh`'s uniform buffer, joint buffer and descriptor set,
+    /// and each `UiPanel`'s texture.
+    ///
+    /// NOTE: A mesh's primitives are shared by every entity added from the same model (see `add_model_to_world`),
+    /// so they're left alone.
+    pub fn despawn(
+        &mut self,
+        world: &mut World,
+        entity: Entity,
+        frame_index: usize,
+    ) -> Result<(), NoSuchEntity> {
+        world.entity(entity)?;
+        let parents = world
+            .query::<&Parent>()
+            .iter()
+            .map(|(child, parent)| (child, parent.0))
+            .collect::<Vec<_>>();
+
+        // Walk down the hierarchy, a level at a time.
+        let mut entities = vec![entity];
+        let mut next = 0;
+        while let Some(&parent) = entities.get(next) {
+            entities.extend(
+                parents
+                    .iter()
+                    .filter(|(_, p)| *p == parent)
+                    .map(|(child, _)| *child),
+            );
+            next += 1;
+        }
+
+        for entity in entities {
+            self.release(world, entity, frame_index);
+            world.despawn(entity)?;
+        }
+        Ok(())
+    }
+
+    /// Queue the GPU resources `entity` owns to be destroyed
+    fn release(&mut self, world: &World, entity: Entity, frame_index: usize) {
+        if let Ok(mesh) = world.get::<Mesh>(entity) {
+            self.push(
+                frame_index,
+                GpuResource::Buffer(mesh.ubo_buffer.handle, mesh.ubo_buffer.device_memory),
+            );
+            self.push(
+                frame_index,
+                GpuResource::Buffer(mesh.joint_buffer.handle, mesh.joint_buffer.device_memory),
+            );
+            for descriptor_set in mesh.descriptor_sets.iter() {
+                self.push(frame_index, GpuResource::DescriptorSet(*descriptor_set));
+            }
+        }
+
+        if let Ok(ui_panel) = world.get::<UiPanel>(entity) {
+            self.push(
+                frame_index,
+                GpuResource::Image(ui_panel.texture.image.clone()),
+            );
+        }
+    }
+
+    /// Destroy any resources released at least `frames_in_flight` frames before `frame_index`. Must only be called
+    /// after waiting on the fence for `frame_index`. Returns the number of Vulkan objects destroyed.
+    pub(crate) fn flush(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        frame_index: usize,
+        frames_in_flight: usize,
+    ) -> usize {
+        let mut destroyed = 0;
+        for resource in self.take_expired(frame_index, frames_in_flight) {
+            destroyed += resource.object_count();
+            destroy(vulkan_context, resource);
+        }
+        destroyed
+    }
+
+    fn take_expired(&mut self, frame_index: usize, frames_in_flight: usize) -> Vec<GpuResource> {
+        let oldest_in_flight = match frame_index.checked_sub(frames_in_flight) {
+            Some(i) => i + 1,
+            None => return Vec::new(),
+        };
+
+        let still_pending = self.pending.split_off(&oldest_in_flight);
+        std::mem::replace(&mut self.pending, still_pending)
+            .into_iter()
+            .flat_map(|(_, resources)| resources)
+            .collect()
+    }
+}
+
+fn destroy(vulkan_context: &VulkanContext, resource: GpuResource) {
+    let device = &vulkan_context.device;
+    unsafe {
+        match resource {
+            GpuResource::Buffer(buffer, device_memory) => {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(device_memory, None);
+            }
+            GpuResource::Image(image) => {
+                device.destroy_image_view(image.view, None);
+                device.destroy_image(image.handle, None);
+                device.free_memory(image.device_memory, None);
+            }
+            GpuResource::DescriptorSet(descriptor_set) => {
+                device.free_descriptor_sets(vulkan_context.descriptor_pool, &[descriptor_set]);
+            }
+            GpuResource::Framebuffer(framebuffer) => {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            GpuResource::Pipeline(pipeline) => {
+                device.destroy_pipeline(pipeline, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_take_expired() {
+        let mut deletion_queue = DeletionQueue::default();
+        let resource = GpuResource::DescriptorSet(vk::DescriptorSet::null());
+        deletion_queue.push(0, resource.clone());
+        deletion_queue.push(1, resource.clone());
+        deletion_queue.push(1, resource);
+        assert_eq!(deletion_queue.len(), 3);
+
+        // With 3 frames in flight, frame 0 may still be in use until frame 3 has been waited on..
+        assert!(deletion_queue.take_expired(2, 3).is_empty());
+        assert_eq!(deletion_queue.take_expired(3, 3).len(), 1);
+        assert_eq!(deletion_queue.len(), 2);
+
+        // ..and skipping frames releases everything older.
+        assert_eq!(deletion_queue.take_expired(10, 3).len(), 2);
+        assert!(deletion_queue.is_empty());
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_despawning_meshes_frees_gpu_resources() {
+        use crate::{
+            gltf_loader::{add_model_to_world, load_models_from_glb},
+            resources::render_context::create_descriptor_set_layouts,
+        };
+
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let set_layouts = create_descriptor_set_layouts(&vulkan_context).unwrap();
+        let data: Vec<&[u8]> = vec![include_bytes!("../../../test_assets/left_hand.glb")];
+        let models = load_models_from_glb(&data, &vulkan_context, &set_layouts).unwrap();
+
+        // The hand's meshes are children of its root, so they're only freed if the whole hierarchy is despawned. The
+        // descriptor pool only has room for 100 uniform buffers, so leaking meshes would run out long before this.
+        let mut world = World::new();
+        let mut deletion_queue = DeletionQueue::default();
+        let frames_in_flight = 3;
+        let frame_count = 500;
+        let mut created = 0;
+        let mut destroyed = 0;
+        for frame_index in 0..frame_count {
+            let hand = add_model_to_world(
+                "Left Hand",
+                &models,
+                &mut world,
+                None,
+                &vulkan_context,
+                &set_layouts,
+            )
+            .unwrap();
+            assert!(world.query::<&Parent>().iter().any(|(_, p)| p.0 == hand));
+            created += count_vulkan_objects(&world);
+
+            deletion_queue
+                .despawn(&mut world, hand, frame_index)
+                .unwrap();
+            assert_eq!(world.len(), 0);
+            destroyed += deletion_queue.flush(&vulkan_context, frame_index, frames_in_flight);
+        }
+
+        // Once the last frames are finished with, every object the hands created has been destroyed.
+        destroyed += deletion_queue.flush(
+            &vulkan_context,
+            frame_count + frames_in_flight,
+            frames_in_flight,
+        );
+        assert!(created > 0);
+        assert_eq!(destroyed, created);
+        assert!(deletion_queue.is_empty());
+    }
+
+    /// Count the Vulkan objects owned by the meshes and panels in `world`
+    #[cfg(target_os = "windows")]
+    fn count_vulkan_objects(world: &World) -> usize {
+        // A uniform buffer and a joint buffer, each with its memory, and the descriptor sets.
+        let meshes = world
+            .query::<&Mesh>()
+            .iter()
+            .map(|(_, mesh)| 4 + mesh.descriptor_sets.len())
+            .sum::<usize>();
+        // An image, its view and its memory.
+        let panels = world.query::<&UiPanel>().iter().count() * 3;
+        meshes + panels
+    }
+}
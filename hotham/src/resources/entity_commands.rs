@@ -0,0 +1,203 @@
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use hecs::{Bundle, Component, DynamicBundle, Entity, NoSuchEntity, World};
+use log::warn;
+
+use crate::{components::Parent, resources::RenderContext};
+
+/// A structural change to the world, waiting for `EntityCommands::apply`
+enum Command {
+    /// Run against the world, eg. to insert or remove components
+    Apply(Box<dyn FnOnce(&mut World) + Send>),
+    /// Despawn the entity, releasing its GPU resources
+    Despawn(Entity),
+}
+
+/// A queue of structural changes to the world - spawning and despawning entities, adding and removing components, and
+/// re-parenting - that are deferred until `apply` is called. Systems and jobs can queue changes while queries over the
+/// world are in progress, or from other threads, without invalidating those queries or racing each other.
+///
+/// Clones share the same queue, so a clone can be moved into a job on `JobContext` and its changes are applied with
+/// everyone else's. Call `apply` once per frame, at the sync point between the Update and PostUpdate stages, so the
+/// changes are picked up by `update_transform_matrix_system` in the same frame.
+///
+/// Changes are applied in the order they were queued, so a component inserted and then removed is gone afterwards.
+/// Changes to an entity that no longer exists by the time they're applied, eg. because an earlier change despawned
+/// it, are skipped with a warning.
+///
+/// Basic usage:
+/// ```ignore
+/// let bullet = engine.entity_commands.spawn(&world, (Transform::default(), Visible {}));
+/// engine.entity_commands.set_parent(bullet, Some(gun));
+/// engine.entity_commands.despawn(target);
+/// // ..later, after the Update stage:
+/// engine.entity_commands.apply(&mut world, &mut engine.render_context);
+/// ```
+#[derive(Clone)]
+pub struct EntityCommands {
+    sender: Sender<Command>,
+    receiver: Receiver<Command>,
+}
+
+impl Default for EntityCommands {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded();
+        Self { sender, receiver }
+    }
+}
+
+impl EntityCommands {
+    /// Queue an entity to be spawned with `components`. The entity is reserved straight away, so it can be used in
+    /// other commands, but has no components until `apply` is called.
+    pub fn spawn(&self, world: &World, components: impl DynamicBundle + Send + 'static) -> Entity {
+        let entity = world.reserve_entity();
+        self.insert(entity, components);
+        entity
+    }
+
+    /// Queue `components` to be added to `entity`, replacing any it already has of the same type
+    pub fn insert(&self, entity: Entity, components: impl DynamicBundle + Send + 'static) {
+        self.push(move |world| {
+            if world.insert(entity, components).is_err() {
+                warn_missing(entity);
+            }
+        });
+    }
+
+    /// Queue `component` to be added to `entity`, replacing any it already has of the same type
+    pub fn insert_one(&self, entity: Entity, component: impl Component) {
+        self.insert(entity, (component,));
+    }
+
+    /// Queue the components in the bundle `T` to be removed from `entity`. Nothing is removed unless `entity` has
+    /// every one of them.
+    pub fn remove<T: Bundle + 'static>(&self, entity: Entity) {
+        self.push(move |world| {
+            let _ = world.remove::<T>(entity);
+        });
+    }
+
+    /// Queue the component `T` to be removed from `entity`
+    pub fn remove_one<T: Component>(&self, entity: Entity) {
+        self.push(move |world| {
+            let _ = world.remove_one::<T>(entity);
+        });
+    }
+
+    /// Queue `entity` and its descendants to be despawned. Their GPU resources are released as with
+    /// `RenderContext::despawn`.
+    pub fn despawn(&self, entity: Entity) {
+        // The receiver lives as long as we do, so this can't fail.
+        let _ = self.sender.send(Command::Despawn(entity));
+    }
+
+    /// Queue `entity` to be attached to `parent`, or detached from its parent with `None`. Its `Transform` is kept,
+    /// so it's now relative to its new parent.
+    pub fn set_parent(&self, entity: Entity, parent: Option<Entity>) {
+        match parent {
+            Some(parent) => self.insert_one(entity, Parent(parent)),
+            None => self.remove_one::<Parent>(entity),
+        }
+    }
+
+    /// The number of changes waiting to be applied
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    /// Are there no changes waiting to be applied?
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    /// Apply every change queued so far to `world`, in the order they were queued. Changes queued on other threads
+    /// while this runs wait for the next call.
+    pub fn apply(&self, world: &mut World, render_context: &mut RenderContext) {
+        self.apply_with(world, |world, entity| render_context.despawn(world, entity));
+    }
+
+    fn apply_with(
+        &self,
+        world: &mut World,
+        mut despawn: impl FnMut(&mut World, Entity) -> Result<(), NoSuchEntity>,
+    ) {
+        let commands = self.receiver.try_iter().collect::<Vec<_>>();
+        for command in commands {
+            match command {
+                Command::Apply(f) => f(world),
+                Command::Despawn(entity) => {
+                    if despawn(world, entity).is_err() {
+                        warn_missing(entity);
+                    }
+                }
+            }
+        }
+    }
+
+    fn push(&self, f: impl FnOnce(&mut World) + Send + 'static) {
+        // The receiver lives as long as we do, so this can't fail.
+        let _ = self.sender.send(Command::Apply(Box::new(f)));
+    }
+}
+
+fn warn_missing(entity: Entity) {
+    warn!(
+        target: "HOTHAM_ENTITY_COMMANDS",
+        "{:?} no longer exists - skipping the change queued for it", entity
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Info, Transform};
+
+    fn apply(commands: &EntityCommands, world: &mut World) {
+        commands.apply_with(world, |world, entity| world.despawn(entity));
+    }
+
+    fn info(name: &str) -> Info {
+        Info {
+            name: name.to_string(),
+            node_id: 0,
+        }
+    }
+
+    #[test]
+    pub fn test_queued_spawns_appear_after_apply() {
+        let mut world = World::new();
+        let commands = EntityCommands::default();
+        let parent = world.spawn((Transform::default(),));
+
+        // Queue changes while a query is in progress..
+        for (entity, _) in world.query::<&Transform>().iter() {
+            let child = commands.spawn(&world, (Transform::default(), info("child")));
+            commands.set_parent(child, Some(entity));
+            commands.insert_one(entity, info("parent"));
+        }
+        assert_eq!(commands.len(), 3);
+        assert_eq!(world.query::<&Info>().iter().count(), 0);
+
+        // ..and they appear once applied.
+        apply(&commands, &mut world);
+        assert!(commands.is_empty());
+        let children = world
+            .query::<(&Info, &Parent)>()
+            .iter()
+            .map(|(_, (info, parent))| (info.name.clone(), parent.0))
+            .collect::<Vec<_>>();
+        assert_eq!(children, [("child".to_string(), parent)]);
+        assert_eq!(world.get::<Info>(parent).unwrap().name, "parent");
+
+        // Changes are applied in order, and changes to entities that are gone are skipped.
+        let child = world.query::<&Parent>().iter().next().unwrap().0;
+        commands.set_parent(child, None);
+        commands.despawn(parent);
+        commands.insert_one(parent, info("gone"));
+        commands.remove_one::<Info>(child);
+        commands.insert_one(child, info("orphan"));
+        apply(&commands, &mut world);
+        assert!(!world.contains(parent));
+        assert!(world.get::<Parent>(child).is_err());
+        assert_eq!(world.get::<Info>(child).unwrap().name, "orphan");
+    }
+}
@@ -0,0 +1,426 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use anyhow::Result;
+use ash::vk;
+use log::{debug, warn};
+
+use crate::{
+    buffer::Buffer,
+    hotham_error::HothamError,
+    resources::{
+        deletion_queue::{DeletionQueue, GpuResource},
+        VulkanContext,
+    },
+};
+
+/// The number of eyes in each swapchain image
+const LAYER_COUNT: u32 = 2;
+
+/// A rendered frame, copied back to the CPU by `FrameReadback`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadbackFrame {
+    /// The `RenderContext::frame_index` of the frame
+    pub frame_index: usize,
+    /// The size of each eye's image, in pixels
+    pub extent: vk::Extent2D,
+    /// RGBA pixels, 8 bits per channel: the left eye then the right, each row by row from the top. Encoded the same
+    /// way as the swapchain, so usually sRGB.
+    pub pixels: Vec<u8>,
+}
+
+/// Copies rendered frames back to the CPU without stalling the render loop, eg. to record video or feed an ML
+/// pipeline. Enable it with `RenderContext::enable_frame_readback`, which returns the channel frames are delivered on.
+///
+/// At the end of the PBR render pass, the swapchain image is copied into one of a ring of host visible buffers, one
+/// per swapchain image. The copy is picked up when `begin_frame` next waits on that image's fence, so frames arrive
+/// a couple of frames after they were rendered, without any extra waiting.
+#[derive(Debug)]
+pub struct FrameReadback {
+    /// Only every `interval`th frame is read back. Defaults to 1, every frame.
+    pub interval: usize,
+    format: vk::Format,
+    render_area: vk::Rect2D,
+    buffers: Vec<Buffer<u8>>,
+    /// The index of the frame waiting in each buffer, if there is one
+    pending: Vec<Option<usize>>,
+    sender: Sender<ReadbackFrame>,
+}
+
+impl FrameReadback {
+    /// Create buffers to read back `render_area` of `frame_count` swapchain images. Fails if frames in `format` can't
+    /// be converted to RGBA.
+    pub(crate) fn new(
+        vulkan_context: &VulkanContext,
+        format: vk::Format,
+        render_area: vk::Rect2D,
+        frame_count: usize,
+    ) -> Result<(Self, Receiver<ReadbackFrame>)> {
+        let bytes_per_pixel =
+            get_bytes_per_pixel(format).ok_or_else(|| HothamError::InvalidFormatError {
+                format: format!("{:?}", format),
+            })?;
+        let extent = render_area.extent;
+        let size = (extent.width * extent.height * LAYER_COUNT) as usize * bytes_per_pixel;
+        let buffers = (0..frame_count)
+            .map(|i| {
+                Buffer::new(
+                    vulkan_context,
+                    &vec![0u8; size],
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                    &format!("Frame Readback Buffer {}", i),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        debug!(
+            target: "HOTHAM_RENDERER",
+            "Reading back frames as {:?}, {} bytes per frame", format, size
+        );
+
+        let (sender, receiver) = channel();
+        Ok((
+            Self {
+                interval: 1,
+                format,
+                render_area,
+                buffers,
+                pending: vec![None; frame_count],
+                sender,
+            },
+            receiver,
+        ))
+    }
+
+    /// Record a copy of `swapchain_image` into the buffer for `frame`, if `frame_index` is due to be read back. Must
+    /// be recorded after the PBR render pass has ended. Leaves the image in `COLOR_ATTACHMENT_OPTIMAL`.
+    pub(crate) fn record(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+        frame: usize,
+        frame_index: usize,
+    ) {
+        if frame_index % self.interval.max(1) != 0 {
+            return;
+        }
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: LAYER_COUNT,
+        };
+        let before = [vk::ImageMemoryBarrier::builder()
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            )
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .image(swapchain_image)
+            .subresource_range(subresource_range)
+            .build()];
+        let after = [vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .image(swapchain_image)
+            .subresource_range(subresource_range)
+            .build()];
+        // Make the copy visible to the host once the frame's fence is signalled.
+        let host_read = [vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .buffer(self.buffers[frame].handle)
+            .size(vk::WHOLE_SIZE)
+            .build()];
+
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: LAYER_COUNT,
+            })
+            .image_offset(vk::Offset3D {
+                x: self.render_area.offset.x,
+                y: self.render_area.offset.y,
+                z: 0,
+            })
+            .image_extent(vk::Extent3D {
+                width: self.render_area.extent.width,
+                height: self.render_area.extent.height,
+                depth: 1,
+            })
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &before,
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.buffers[frame].handle,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &host_read,
+                &after,
+            );
+        }
+        self.pending[frame] = Some(frame_index);
+    }
+
+    /// Deliver the frame waiting in the buffer for `frame`, if there is one. Must only be called after waiting on the
+    /// frame's fence. Returns `false` if the receiver has been dropped, so there's no point reading back any more.
+    pub(crate) fn collect(&mut self, vulkan_context: &VulkanContext, frame: usize) -> Result<bool> {
+        let frame_index = match self.pending[frame].take() {
+            Some(frame_index) => frame_index,
+            None => return Ok(true),
+        };
+
+        let buffer = &self.buffers[frame];
+        let data = unsafe {
+            let src = vulkan_context.device.map_memory(
+                buffer.device_memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            let data = std::slice::from_raw_parts(src as *const u8, buffer.size as usize).to_vec();
+            vulkan_context.device.unmap_memory(buffer.device_memory);
+            data
+        };
+
+        let readback_frame = ReadbackFrame {
+            frame_index,
+            extent: self.render_area.extent,
+            pixels: convert_to_rgba8(self.format, &data)?,
+        };
+        if self.sender.send(readback_frame).is_err() {
+            warn!(
+                target: "HOTHAM_RENDERER",
+                "Frame readback receiver was dropped - no longer reading back frames"
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Queue the readback buffers to be destroyed once `frame_index` is no longer in flight. Frames still waiting in
+    /// them aren't delivered.
+    pub(crate) fn release(self, deletion_queue: &mut DeletionQueue, frame_index: usize) {
+        for buffer in self.buffers {
+            deletion_queue.push(
+                frame_index,
+                GpuResource::Buffer(buffer.handle, buffer.device_memory),
+            );
+        }
+    }
+}
+
+/// The size of a pixel in `format`, or `None` if `convert_to_rgba8` can't convert it.
+fn get_bytes_per_pixel(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32 => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        _ => None,
+    }
+}
+
+/// Convert pixels in `format` to RGBA, 8 bits per channel. Packed 10 bit and half float formats are reduced to 8 bits
+/// per channel, with floats clamped to `0.0..=1.0`. Block compressed formats can't be rendered to, so they aren't
+/// supported.
+pub(crate) fn convert_to_rgba8(format: vk::Format, data: &[u8]) -> Result<Vec<u8>> {
+    let pixels = match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => data.to_vec(),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect(),
+        vk::Format::A2B10G10R10_UNORM_PACK32 => data
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let p = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                let channel = |shift: u32| ((p >> shift) & 0x3ff) as f32 / 1023.;
+                [
+                    to_unorm8(channel(0)),
+                    to_unorm8(channel(10)),
+                    to_unorm8(channel(20)),
+                    ((p >> 30) * 85) as u8,
+                ]
+            })
+            .collect(),
+        vk::Format::R16G16B16A16_SFLOAT => data
+            .chunks_exact(2)
+            .map(|c| to_unorm8(f16_to_f32(u16::from_le_bytes([c[0], c[1]]))))
+            .collect(),
+        _ => {
+            return Err(HothamError::InvalidFormatError {
+                format: format!("{:?}", format),
+            }
+            .into())
+        }
+    };
+
+    Ok(pixels)
+}
+
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// Decode an IEEE 754 half precision float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0. => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_convert_to_rgba8() {
+        let rgba = [10, 20, 30, 255, 40, 50, 60, 128];
+        assert_eq!(
+            convert_to_rgba8(vk::Format::R8G8B8A8_SRGB, &rgba).unwrap(),
+            rgba
+        );
+        assert_eq!(
+            convert_to_rgba8(vk::Format::B8G8R8A8_UNORM, &[30, 20, 10, 255]).unwrap(),
+            [10, 20, 30, 255]
+        );
+
+        // Red at full, green at zero, blue at half and alpha at full.
+        let packed: u32 = 1023 | (512 << 20) | (3 << 30);
+        assert_eq!(
+            convert_to_rgba8(vk::Format::A2B10G10R10_UNORM_PACK32, &packed.to_le_bytes()).unwrap(),
+            [255, 0, 128, 255]
+        );
+
+        // 1.0, 0.5, 2.0 (clamped) and -1.0 (clamped) as half floats.
+        let halves = [0x3c00u16, 0x3800, 0x4000, 0xbc00]
+            .iter()
+            .flat_map(|h| h.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            convert_to_rgba8(vk::Format::R16G16B16A16_SFLOAT, &halves).unwrap(),
+            [255, 128, 255, 0]
+        );
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+
+        // Block compressed formats can't be rendered to.
+        assert!(convert_to_rgba8(vk::Format::ASTC_4X4_SRGB_BLOCK, &[0; 16]).is_err());
+        assert!(get_bytes_per_pixel(vk::Format::ASTC_4X4_SRGB_BLOCK).is_none());
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_drain_readback_frames() {
+        use crate::{
+            resources::{RenderContext, XrContext},
+            schedule_functions::{
+                begin_frame, begin_pbr_renderpass, end_frame, end_pbr_renderpass,
+            },
+        };
+
+        let (mut xr_context, vulkan_context) = XrContext::new().unwrap();
+        let mut render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
+        let receiver = render_context
+            .enable_frame_readback(&vulkan_context)
+            .unwrap();
+
+        // Each frame is delivered once its swapchain image comes around again.
+        let frame_count = render_context.frames.len();
+        for _ in 0..frame_count + 3 {
+            begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
+            begin_pbr_renderpass(&mut xr_context, &vulkan_context, &mut render_context);
+            end_pbr_renderpass(&mut xr_context, &vulkan_context, &mut render_context);
+            end_frame(&mut xr_context, &vulkan_context, &mut render_context);
+        }
+
+        let frames = receiver.try_iter().collect::<Vec<_>>();
+        assert!(!frames.is_empty());
+        let extent = render_context.render_area.extent;
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.extent, extent);
+            assert_eq!(
+                frame.pixels.len(),
+                (extent.width * extent.height * LAYER_COUNT * 4) as usize
+            );
+            if i > 0 {
+                assert!(frame.frame_index > frames[i - 1].frame_index);
+            }
+        }
+
+        // Nothing more is read back once disabled, and the buffers are queued to be destroyed.
+        let queued = render_context.deletion_queue.len();
+        render_context.disable_frame_readback();
+        assert_eq!(render_context.deletion_queue.len(), queued + frame_count);
+        begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
+        end_frame(&mut xr_context, &vulkan_context, &mut render_context);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_replaced_readback_buffers_are_released() {
+        use crate::{
+            resources::{RenderContext, XrContext},
+            schedule_functions::{
+                begin_frame, begin_pbr_renderpass, end_frame, end_pbr_renderpass,
+            },
+        };
+
+        let (mut xr_context, vulkan_context) = XrContext::new().unwrap();
+        let mut render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
+        let frame_count = render_context.frames.len();
+
+        // Enabling readback again replaces the old buffers..
+        let _first = render_context
+            .enable_frame_readback(&vulkan_context)
+            .unwrap();
+        let queued = render_context.deletion_queue.len();
+        let receiver = render_context
+            .enable_frame_readback(&vulkan_context)
+            .unwrap();
+        assert_eq!(render_context.deletion_queue.len(), queued + frame_count);
+
+        // ..and dropping the receiver releases them once a frame comes back with nowhere to go.
+        drop(receiver);
+        for _ in 0..frame_count + 1 {
+            begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
+            begin_pbr_renderpass(&mut xr_context, &vulkan_context, &mut render_context);
+            end_pbr_renderpass(&mut xr_context, &vulkan_context, &mut render_context);
+            end_frame(&mut xr_context, &vulkan_context, &mut render_context);
+        }
+        assert!(render_context.frame_readback.is_none());
+    }
+}
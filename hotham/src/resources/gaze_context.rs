@@ -0,0 +1,168 @@
+use std::time::{Duration, Instant};
+
+use hecs::Entity;
+use nalgebra::{vector, Isometry3, Point3, Vector3};
+
+/// Where the gaze ray is coming from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GazeSource {
+    /// The combined gaze of both eyes, from `XR_EXT_eye_gaze_interaction`
+    Eyes,
+    /// The direction the user's head is facing
+    Head,
+}
+
+/// Where the user is looking, updated each frame by `gaze_system`. Use it as a ray for picking, eg. with `raycast`.
+///
+/// Eye tracking is only used if `eye_tracking_enabled` is set and the runtime supports `XR_EXT_eye_gaze_interaction`.
+/// Otherwise, the gaze falls back to the direction the user's head is facing.
+///
+/// **Privacy**: eye gaze is sensitive biometric data. It can reveal what a user is interested in, their emotional
+/// state and even some medical conditions. For that reason eye tracking is disabled by default:
+/// - Only enable it if your application needs it, and tell the user why
+/// - Use the gaze for interaction in the current frame; don't record, log or transmit it
+/// - Some platforms (eg. Quest Pro) also require the user to grant a runtime permission before eye tracking works
+#[derive(Debug, Clone)]
+pub struct GazeContext {
+    /// Should eye tracking be used, if it's available? Defaults to `false`.
+    pub eye_tracking_enabled: bool,
+    /// Pose of the gaze this frame, in the active reference space, `XrContext::reference_space`, which isn't always
+    /// the stage: see `XrContext::set_reference_space_type`. Looks down -Z. `None` if it isn't being tracked.
+    pub pose: Option<Isometry3<f32>>,
+    /// Where `pose` came from this frame
+    pub source: GazeSource,
+}
+
+impl Default for GazeContext {
+    fn default() -> Self {
+        Self {
+            eye_tracking_enabled: false,
+            pose: None,
+            source: GazeSource::Head,
+        }
+    }
+}
+
+impl GazeContext {
+    /// The origin and (normalised) direction of the gaze ray, if the gaze is being tracked
+    pub fn ray(&self) -> Option<(Point3<f32>, Vector3<f32>)> {
+        self.pose.map(|pose| {
+            let origin = Point3::from(pose.translation.vector);
+            let direction = pose.rotation.transform_vector(&vector![0., 0., -1.]);
+            (origin, direction)
+        })
+    }
+}
+
+/// Selects whatever the user has looked at for `dwell_time`, eg. to activate buttons with gaze alone.
+///
+/// Basic usage:
+/// ```ignore
+/// let target = raycast(&world, &origin, &direction, 10.).map(|hit| hit.entity);
+/// if let Some(entity) = dwell_selector.update(target, Instant::now()) {
+///     activate(entity);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DwellSelector {
+    /// How long the user must look at something before it's selected
+    pub dwell_time: Duration,
+    target: Option<(Entity, Instant)>,
+    selected: bool,
+}
+
+impl DwellSelector {
+    /// Create a selector that activates after looking at something for `dwell_time`
+    pub fn new(dwell_time: Duration) -> Self {
+        Self {
+            dwell_time,
+            target: None,
+            selected: false,
+        }
+    }
+
+    /// Update with what the user is looking at now. Returns the target once, when it has been looked at for
+    /// `dwell_time`. The user must look away before it can be selected again.
+    pub fn update(&mut self, target: Option<Entity>, now: Instant) -> Option<Entity> {
+        let target = match target {
+            Some(target) => target,
+            None => {
+                self.target = None;
+                return None;
+            }
+        };
+
+        match self.target {
+            Some((current, _)) if current == target => {}
+            _ => {
+                self.target = Some((target, now));
+                self.selected = false;
+            }
+        }
+
+        let (_, started) = self.target.unwrap();
+        if !self.selected && now.saturating_duration_since(started) >= self.dwell_time {
+            self.selected = true;
+            return Some(target);
+        }
+
+        None
+    }
+
+    /// How far through the dwell the current target is, from `0.0` to `1.0`, eg. to draw a progress indicator.
+    pub fn progress(&self, now: Instant) -> f32 {
+        match self.target {
+            Some(_) if self.selected => 1.0,
+            Some((_, started)) => {
+                let elapsed = now.saturating_duration_since(started).as_secs_f32();
+                (elapsed / self.dwell_time.as_secs_f32().max(f32::EPSILON)).min(1.0)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use hecs::World;
+
+    #[test]
+    pub fn test_dwell_selector() {
+        let mut world = World::new();
+        let button = world.spawn(());
+        let other_button = world.spawn(());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut dwell_selector = DwellSelector::new(Duration::from_millis(500));
+        assert_eq!(dwell_selector.update(Some(button), at(0)), None);
+        assert_eq!(dwell_selector.update(Some(button), at(250)), None);
+        assert_relative_eq!(dwell_selector.progress(at(250)), 0.5);
+
+        // Selected once..
+        assert_eq!(dwell_selector.update(Some(button), at(500)), Some(button));
+        assert_eq!(dwell_selector.update(Some(button), at(1000)), None);
+
+        // ..looking at something else restarts the timer..
+        assert_eq!(dwell_selector.update(Some(other_button), at(1100)), None);
+        assert_eq!(dwell_selector.update(Some(other_button), at(1400)), None);
+
+        // ..as does looking away.
+        assert_eq!(dwell_selector.update(None, at(1500)), None);
+        assert_eq!(dwell_selector.update(Some(button), at(1600)), None);
+        assert_eq!(dwell_selector.update(Some(button), at(2100)), Some(button));
+    }
+
+    #[test]
+    pub fn test_gaze_ray() {
+        let mut gaze_context = GazeContext::default();
+        assert!(gaze_context.ray().is_none());
+
+        gaze_context.pose = Some(Isometry3::translation(0., 1.5, 0.));
+        let (origin, direction) = gaze_context.ray().unwrap();
+        assert_relative_eq!(origin, Point3::new(0., 1.5, 0.));
+        assert_relative_eq!(direction, vector![0., 0., -1.]);
+    }
+}
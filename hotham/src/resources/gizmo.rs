@@ -0,0 +1,480 @@
+use hecs::{Entity, World};
+use nalgebra::{vector, Point3, UnitQuaternion, Vector2, Vector3, Vector4};
+use openxr::View;
+
+use crate::{
+    components::Transform, raycast::raycast, resources::RenderContext, util::posef_to_isometry,
+};
+
+/// How far away entities can be selected by clicking on them, in metres
+const MAX_SELECT_DISTANCE: f32 = 100.;
+/// The number of line segments in each ring of the rotation gizmo
+const RING_SEGMENTS: usize = 32;
+/// The smallest scale the gizmo will shrink an axis to
+const MIN_SCALE: f32 = 0.01;
+/// Colour of the handle under the cursor, or being dragged
+const ACTIVE_COLOR: Vector4<f32> = Vector4::new(1., 0.9, 0.1, 1.);
+
+/// What dragging a `Gizmo`'s handles does to the selected entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    /// Move along the handle's axis
+    Translate,
+    /// Turn about the handle's axis
+    Rotate,
+    /// Stretch along the handle's axis
+    Scale,
+}
+
+/// The axis a `Gizmo` handle constrains changes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl GizmoAxis {
+    const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    /// The index of the axis in a vector
+    fn index(self) -> usize {
+        match self {
+            GizmoAxis::X => 0,
+            GizmoAxis::Y => 1,
+            GizmoAxis::Z => 2,
+        }
+    }
+
+    /// The direction of the axis, in world space
+    pub fn direction(self) -> Vector3<f32> {
+        Vector3::ith(self.index(), 1.)
+    }
+
+    /// The colour the axis' handle is drawn in: red for X, green for Y and blue for Z
+    pub fn color(self) -> Vector4<f32> {
+        let mut color = Vector4::new(0.2, 0.2, 0.2, 1.);
+        color[self.index()] = 1.;
+        color
+    }
+}
+
+/// A handle being dragged, and where the drag started
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Drag {
+    axis: GizmoAxis,
+    start_transform: Transform,
+    /// How far along the axis, or how far around it, the cursor was when the drag started
+    start_value: f32,
+}
+
+/// A translate, rotate or scale manipulator for editing scenes in flat (handheld or spectator) mode. Updated and drawn
+/// each frame by `gizmo_system`.
+///
+/// Clicking on an entity's mesh selects it, using `raycast`. The gizmo is then drawn at the selected entity with a
+/// handle for each axis: red for X, green for Y and blue for Z. Dragging a handle changes the entity's `Transform`
+/// along that axis only.
+///
+/// Handles are aligned with the world's axes, and drawn at the entity's `Transform`, so the gizmo is only accurate for
+/// entities without a `Parent`.
+#[derive(Debug, Clone)]
+pub struct Gizmo {
+    /// What dragging a handle does. Defaults to `Translate`.
+    pub mode: GizmoMode,
+    /// The entity being edited
+    pub selected: Option<Entity>,
+    /// The length of each handle, in metres. Defaults to 0.25.
+    pub size: f32,
+    /// How close the cursor has to pass to a handle to grab it, in metres. Defaults to 0.02.
+    pub handle_radius: f32,
+    hovered: Option<GizmoAxis>,
+    drag: Option<Drag>,
+    was_pressed: bool,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            selected: None,
+            size: 0.25,
+            handle_radius: 0.02,
+            hovered: None,
+            drag: None,
+            was_pressed: false,
+        }
+    }
+}
+
+impl Gizmo {
+    /// The axis whose handle is being dragged, if any
+    pub fn dragging(&self) -> Option<GizmoAxis> {
+        self.drag.map(|d| d.axis)
+    }
+
+    /// Update the gizmo with the cursor's ray, in world space, and whether the button is pressed. Pressing on a handle
+    /// starts dragging it; pressing anywhere else selects the entity under the cursor, if there is one.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+        pressed: bool,
+    ) {
+        let direction = direction.normalize();
+        let just_pressed = pressed && !self.was_pressed;
+        self.was_pressed = pressed;
+
+        // Forget the selection if the entity has gone.
+        let transform = match self.selected.map(|e| world.get_mut::<Transform>(e)) {
+            Some(Ok(transform)) => Some(transform),
+            _ => {
+                self.selected = None;
+                self.drag = None;
+                None
+            }
+        };
+
+        if let Some(mut transform) = transform {
+            let center = Point3::from(transform.translation);
+            if !pressed {
+                self.drag = None;
+                self.hovered = self.hit_test(&center, origin, &direction);
+                return;
+            }
+
+            if just_pressed {
+                let axis = self.hit_test(&center, origin, &direction);
+                self.drag = axis.and_then(|axis| {
+                    Some(Drag {
+                        axis,
+                        start_transform: *transform,
+                        start_value: self.get_drag_value(axis, &center, origin, &direction)?,
+                    })
+                });
+            }
+
+            if let Some(drag) = self.drag {
+                let start_center = Point3::from(drag.start_transform.translation);
+                if let Some(value) =
+                    self.get_drag_value(drag.axis, &start_center, origin, &direction)
+                {
+                    *transform = self.apply_drag(&drag, value);
+                }
+                return;
+            }
+        }
+
+        if just_pressed {
+            self.selected =
+                raycast(world, origin, &direction, MAX_SELECT_DISTANCE).map(|h| h.entity);
+            self.hovered = None;
+        }
+    }
+
+    /// Queue the gizmo's handles to be drawn at the selected entity with `RenderContext::draw_debug_line`
+    pub fn draw(&self, world: &World, render_context: &mut RenderContext) {
+        let center = match self.selected.map(|e| world.get::<Transform>(e)) {
+            Some(Ok(transform)) => transform.translation,
+            _ => return,
+        };
+
+        for axis in GizmoAxis::ALL {
+            let color = if self.dragging().or(self.hovered) == Some(axis) {
+                ACTIVE_COLOR
+            } else {
+                axis.color()
+            };
+            for (from, to) in self.get_handle_lines(axis, &center) {
+                render_context.draw_debug_line(from, to, color);
+            }
+        }
+    }
+
+    /// The lines that make up the handle for `axis`
+    fn get_handle_lines(
+        &self,
+        axis: GizmoAxis,
+        center: &Vector3<f32>,
+    ) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        let direction = axis.direction();
+        let (u, v) = get_perpendicular_axes(axis);
+        let end = center + direction * self.size;
+        let tip = self.size * 0.1;
+        match self.mode {
+            // A line with an arrowhead..
+            GizmoMode::Translate => vec![
+                (*center, end),
+                (end, end - direction * tip + u * tip * 0.5),
+                (end, end - direction * tip - u * tip * 0.5),
+            ],
+            // ..a ring around the axis..
+            GizmoMode::Rotate => (0..RING_SEGMENTS)
+                .map(|i| {
+                    let point = |i: usize| {
+                        let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+                        center + (u * angle.cos() + v * angle.sin()) * self.size
+                    };
+                    (point(i), point(i + 1))
+                })
+                .collect(),
+            // ..or a line ending in a square.
+            GizmoMode::Scale => {
+                let corners = [u + v, u - v, -u - v, -u + v].map(|c| end + c * tip * 0.5);
+                let mut lines = vec![(*center, end)];
+                lines.extend((0..4).map(|i| (corners[i], corners[(i + 1) % 4])));
+                lines
+            }
+        }
+    }
+
+    /// The handle the ray passes closest to, if it's within `handle_radius` of any
+    fn hit_test(
+        &self,
+        center: &Point3<f32>,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+    ) -> Option<GizmoAxis> {
+        GizmoAxis::ALL
+            .iter()
+            .filter_map(|axis| {
+                let distance = match self.mode {
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let t = get_closest_on_axis(center, &axis.direction(), origin, direction)?;
+                        let point = center + axis.direction() * t.clamp(0., self.size);
+                        get_distance_to_ray(&point, origin, direction)
+                    }
+                    GizmoMode::Rotate => {
+                        let point = intersect_plane(center, &axis.direction(), origin, direction)?;
+                        ((point - center).norm() - self.size).abs()
+                    }
+                };
+                Some((*axis, distance))
+            })
+            .filter(|(_, distance)| *distance <= self.handle_radius)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(axis, _)| axis)
+    }
+
+    /// How far along `axis` the ray passes, or for rotation, the angle around it the ray points at
+    fn get_drag_value(
+        &self,
+        axis: GizmoAxis,
+        center: &Point3<f32>,
+        origin: &Point3<f32>,
+        direction: &Vector3<f32>,
+    ) -> Option<f32> {
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                get_closest_on_axis(center, &axis.direction(), origin, direction)
+            }
+            GizmoMode::Rotate => {
+                let offset =
+                    intersect_plane(center, &axis.direction(), origin, direction)? - center;
+                let (u, v) = get_perpendicular_axes(axis);
+                Some(offset.dot(&v).atan2(offset.dot(&u)))
+            }
+        }
+    }
+
+    /// The transform `drag` produces when the cursor is at `value`
+    fn apply_drag(&self, drag: &Drag, value: f32) -> Transform {
+        let mut transform = drag.start_transform;
+        let delta = value - drag.start_value;
+        let direction = drag.axis.direction();
+        match self.mode {
+            GizmoMode::Translate => transform.translation += direction * delta,
+            GizmoMode::Rotate => {
+                transform.rotation =
+                    UnitQuaternion::from_axis_angle(&Vector3::ith_axis(drag.axis.index()), delta)
+                        * transform.rotation
+            }
+            GizmoMode::Scale => {
+                let i = drag.axis.index();
+                transform.scale[i] = (transform.scale[i] * (1. + delta / self.size)).max(MIN_SCALE)
+            }
+        }
+        transform
+    }
+}
+
+/// The ray through `cursor` on a flat view, in world space. `cursor` is in normalised device coordinates: -1 to 1
+/// from left to right and from top to bottom.
+pub fn get_cursor_ray(view: &View, cursor: Vector2<f32>) -> (Point3<f32>, Vector3<f32>) {
+    let pose = posef_to_isometry(view.pose);
+    let (x, y) = ((cursor.x + 1.) * 0.5, (cursor.y + 1.) * 0.5);
+    let fov = view.fov;
+    let direction = vector![
+        lerp(fov.angle_left.tan(), fov.angle_right.tan(), x),
+        lerp(fov.angle_up.tan(), fov.angle_down.tan(), y),
+        -1.
+    ];
+    (
+        Point3::from(pose.translation.vector),
+        pose.rotation.transform_vector(&direction).normalize(),
+    )
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Two axes perpendicular to `axis` and each other, in the order that makes positive angles turn about `axis`
+fn get_perpendicular_axes(axis: GizmoAxis) -> (Vector3<f32>, Vector3<f32>) {
+    let i = axis.index();
+    (Vector3::ith((i + 1) % 3, 1.), Vector3::ith((i + 2) % 3, 1.))
+}
+
+/// How far along the line through `center` in `axis` is the point closest to the ray, or `None` if they're parallel.
+/// `axis` and `direction` must be normalised.
+fn get_closest_on_axis(
+    center: &Point3<f32>,
+    axis: &Vector3<f32>,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+) -> Option<f32> {
+    let b = axis.dot(direction);
+    let denominator = 1. - b * b;
+    if denominator < 1e-6 {
+        return None;
+    }
+    let w = center - origin;
+    Some((b * direction.dot(&w) - axis.dot(&w)) / denominator)
+}
+
+fn get_distance_to_ray(point: &Point3<f32>, origin: &Point3<f32>, direction: &Vector3<f32>) -> f32 {
+    let offset = point - origin;
+    (offset - direction * offset.dot(direction).max(0.)).norm()
+}
+
+/// Where the ray crosses the plane through `center` facing `normal`, if it does
+fn intersect_plane(
+    center: &Point3<f32>,
+    normal: &Vector3<f32>,
+    origin: &Point3<f32>,
+    direction: &Vector3<f32>,
+) -> Option<Point3<f32>> {
+    let facing = normal.dot(direction);
+    if facing.abs() < 1e-6 {
+        return None;
+    }
+    let distance = normal.dot(&(center - origin)) / facing;
+    if distance < 0. {
+        return None;
+    }
+    Some(origin + direction * distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Cast a ray from the origin through `target`
+    fn ray_to(target: Vector3<f32>) -> (Point3<f32>, Vector3<f32>) {
+        (Point3::origin(), target.normalize())
+    }
+
+    #[test]
+    pub fn test_drag_x_handle_translates_along_x() {
+        let mut world = World::new();
+        let start = vector![0., 0., -2.];
+        let entity = world.spawn((Transform {
+            translation: start,
+            ..Default::default()
+        },));
+        let mut gizmo = Gizmo {
+            selected: Some(entity),
+            ..Default::default()
+        };
+
+        // Hovering over the X handle highlights it..
+        let (origin, direction) = ray_to(start + vector![0.15, 0., 0.]);
+        gizmo.update(&mut world, &origin, &direction, false);
+        assert_eq!(gizmo.hovered, Some(GizmoAxis::X));
+
+        // ..and pressing grabs it.
+        gizmo.update(&mut world, &origin, &direction, true);
+        assert_eq!(gizmo.dragging(), Some(GizmoAxis::X));
+
+        // Dragging along X moves the entity along X..
+        let (origin, direction) = ray_to(start + vector![0.45, 0., 0.]);
+        gizmo.update(&mut world, &origin, &direction, true);
+        let translation = world.get::<Transform>(entity).unwrap().translation;
+        assert_relative_eq!(translation, start + vector![0.3, 0., 0.], epsilon = 1e-5);
+
+        // ..and dragging off the axis still only moves it along X.
+        let (origin, direction) = ray_to(start + vector![0.45, 0.3, 0.2]);
+        gizmo.update(&mut world, &origin, &direction, true);
+        let translation = world.get::<Transform>(entity).unwrap().translation;
+        assert!(translation.x > start.x);
+        assert_eq!(translation.y, start.y);
+        assert_eq!(translation.z, start.z);
+
+        // Releasing ends the drag, and the entity stays where it was left.
+        gizmo.update(&mut world, &origin, &direction, false);
+        assert_eq!(gizmo.dragging(), None);
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            translation
+        );
+    }
+
+    #[test]
+    pub fn test_rotate_and_scale_handles() {
+        let mut world = World::new();
+        let start = vector![0., 0., -2.];
+        let entity = world.spawn((Transform {
+            translation: start,
+            ..Default::default()
+        },));
+
+        // Dragging around the Z ring turns the entity about Z.
+        let mut gizmo = Gizmo {
+            mode: GizmoMode::Rotate,
+            selected: Some(entity),
+            ..Default::default()
+        };
+        let (origin, direction) = ray_to(start + vector![0.25, 0., 0.]);
+        gizmo.update(&mut world, &origin, &direction, true);
+        assert_eq!(gizmo.dragging(), Some(GizmoAxis::Z));
+        let (origin, direction) = ray_to(start + vector![0., 0.25, 0.]);
+        gizmo.update(&mut world, &origin, &direction, true);
+        let rotation = world.get::<Transform>(entity).unwrap().rotation;
+        assert_relative_eq!(rotation * Vector3::x(), Vector3::y(), epsilon = 1e-5);
+
+        // Dragging the Y scale handle out to twice its length doubles the scale along Y.
+        let mut gizmo = Gizmo {
+            mode: GizmoMode::Scale,
+            selected: Some(entity),
+            ..Default::default()
+        };
+        let (origin, direction) = ray_to(start + vector![0., 0.25, 0.]);
+        gizmo.update(&mut world, &origin, &direction, true);
+        assert_eq!(gizmo.dragging(), Some(GizmoAxis::Y));
+        let (origin, direction) = ray_to(start + vector![0., 0.5, 0.]);
+        gizmo.update(&mut world, &origin, &direction, true);
+        let scale = world.get::<Transform>(entity).unwrap().scale;
+        assert_relative_eq!(scale, vector![1., 2., 1.], epsilon = 1e-5);
+    }
+
+    #[test]
+    pub fn test_get_cursor_ray() {
+        let view = View {
+            pose: openxr::Posef::IDENTITY,
+            fov: openxr::Fovf {
+                angle_left: -std::f32::consts::FRAC_PI_4,
+                angle_right: std::f32::consts::FRAC_PI_4,
+                angle_up: std::f32::consts::FRAC_PI_4,
+                angle_down: -std::f32::consts::FRAC_PI_4,
+            },
+        };
+        let (origin, direction) = get_cursor_ray(&view, vector![0., 0.]);
+        assert_eq!(origin, Point3::origin());
+        assert_relative_eq!(direction, vector![0., 0., -1.]);
+
+        // The top right corner of the view.
+        let (_, direction) = get_cursor_ray(&view, vector![1., -1.]);
+        assert_relative_eq!(direction, vector![1., 1., -1.].normalize(), epsilon = 1e-6);
+    }
+}
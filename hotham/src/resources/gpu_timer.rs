@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use ash::vk;
+use log::warn;
+
+use crate::resources::VulkanContext;
+
+/// Measures how long the GPU takes to execute each frame's command buffer, with a pair of timestamp queries per frame.
+#[derive(Debug, Clone)]
+pub struct GpuTimer {
+    query_pool: vk::QueryPool,
+    /// Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    /// The bits of a timestamp the graphics queue actually writes
+    valid_bits_mask: u64,
+    /// Have each frame's timestamps been written since they were last read?
+    written: Vec<bool>,
+}
+
+impl GpuTimer {
+    /// Create a timer for `frame_count` frames, or `None` if the graphics queue doesn't support timestamps.
+    pub(crate) fn new(vulkan_context: &VulkanContext, frame_count: usize) -> Result<Option<Self>> {
+        let queue_family_properties = unsafe {
+            vulkan_context
+                .instance
+                .get_physical_device_queue_family_properties(vulkan_context.physical_device)
+        };
+        let valid_bits = queue_family_properties
+            .get(vulkan_context.queue_family_index as usize)
+            .map(|q| q.timestamp_valid_bits)
+            .unwrap_or(0);
+        if valid_bits == 0 {
+            warn!(
+                target: "HOTHAM_RENDERER",
+                "The graphics queue doesn't support timestamps - GPU frame times won't be measured"
+            );
+            return Ok(None);
+        }
+
+        let query_pool = unsafe {
+            vulkan_context.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(frame_count as u32 * 2),
+                None,
+            )
+        }?;
+
+        Ok(Some(Self {
+            query_pool,
+            timestamp_period: vulkan_context
+                .physical_device_properties
+                .limits
+                .timestamp_period,
+            valid_bits_mask: get_valid_bits_mask(valid_bits),
+            written: vec![false; frame_count],
+        }))
+    }
+
+    /// Record the timestamp at the start of `frame`. Must be recorded outside of a render pass.
+    pub(crate) fn begin(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        let first_query = frame as u32 * 2;
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, self.query_pool, first_query, 2);
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                first_query,
+            );
+        }
+        self.written[frame] = true;
+    }
+
+    /// Record the timestamp at the end of `frame`, once all of its commands have completed.
+    pub(crate) fn end(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                frame as u32 * 2 + 1,
+            );
+        }
+    }
+
+    /// How long the GPU took to execute `frame` the last time it was submitted, or `None` if it hasn't been timed
+    /// since this was last called. Must only be called after waiting on the frame's fence.
+    pub(crate) fn get_frame_time(
+        &mut self,
+        device: &ash::Device,
+        frame: usize,
+    ) -> Option<Duration> {
+        if !std::mem::replace(&mut self.written[frame], false) {
+            return None;
+        }
+
+        let mut timestamps = [0u64; 2];
+        unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                frame as u32 * 2,
+                2,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .ok()?;
+
+        Some(get_duration(
+            timestamps,
+            self.valid_bits_mask,
+            self.timestamp_period,
+        ))
+    }
+}
+
+fn get_valid_bits_mask(valid_bits: u32) -> u64 {
+    if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << valid_bits) - 1
+    }
+}
+
+/// The time between a pair of timestamps, allowing for the counter wrapping around between them
+fn get_duration(timestamps: [u64; 2], valid_bits_mask: u64, timestamp_period: f32) -> Duration {
+    let [begin, end] = timestamps;
+    let ticks = end.wrapping_sub(begin) & valid_bits_mask;
+    Duration::from_nanos((ticks as f64 * timestamp_period as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_duration() {
+        let mask = get_valid_bits_mask(64);
+        assert_eq!(mask, u64::MAX);
+        assert_eq!(
+            get_duration([1_000, 11_000], mask, 1.0),
+            Duration::from_micros(10)
+        );
+        assert_eq!(
+            get_duration([1_000, 11_000], mask, 52.08),
+            Duration::from_nanos(520_800)
+        );
+
+        // A 36 bit counter that wraps around between the two timestamps.
+        let mask = get_valid_bits_mask(36);
+        assert_eq!(mask, 0xF_FFFF_FFFF);
+        assert_eq!(
+            get_duration([0xF_FFFF_FF00, 0x100], mask, 1.0),
+            Duration::from_nanos(0x200)
+        );
+    }
+}
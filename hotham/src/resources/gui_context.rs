@@ -506,6 +506,7 @@ fn update_font_texture(
         texture.width as u32,
         texture.height as u32,
         COLOR_FORMAT,
+        Default::default(),
     )
     .expect("Unable to create font texture");
 
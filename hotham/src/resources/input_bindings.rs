@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// The interaction profile Hotham's default bindings are suggested for
+pub const DEFAULT_INTERACTION_PROFILE: &str = "/interaction_profiles/oculus/touch_controller";
+
+/// A logical action that Hotham reads from the controllers. Each is driven by the physical inputs it's bound to in
+/// `InputBindings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum InputAction {
+    /// The pose of each hand. Read from `XrContext::left_hand_space` and `XrContext::right_hand_space`.
+    HandPose,
+    /// The pose each hand points with. Read from `XrContext::left_pointer_space` and `XrContext::right_pointer_space`.
+    PointerPose,
+    /// `XrContext::grab_action`
+    Grab,
+    /// `XrContext::trigger_action`
+    Trigger,
+    /// `XrContext::haptic_feedback_action`
+    HapticFeedback,
+    /// `XrContext::thumbstick_action`
+    Thumbstick,
+}
+
+/// Which physical inputs drive each `InputAction`, as OpenXR input paths for `interaction_profile`, eg.
+/// `/user/hand/left/input/squeeze/value`. Can be saved with a `Scene` so players' remapped controls persist.
+///
+/// **Limitation**: OpenXR only accepts suggested bindings before an action set is attached to the session, and action
+/// sets can only be attached once per session. Hotham does this when the `XrContext` is created, so changing the
+/// bindings of a running session isn't possible. To remap controls, check the new bindings with
+/// `XrContext::check_input_bindings`, save them, and pass them to `Engine::new_with_input_bindings` the next time the
+/// engine is created. The runtime may also let players remap controls itself, which takes precedence.
+///
+/// Basic usage:
+/// ```ignore
+/// let mut bindings = engine.xr_context.input_bindings.clone();
+/// bindings.set_paths(InputAction::Grab, vec![
+///     "/user/hand/left/input/trigger/value".to_string(),
+///     "/user/hand/right/input/trigger/value".to_string(),
+/// ]);
+/// engine.xr_context.check_input_bindings(&bindings)?;
+/// scene.input_bindings = Some(bindings);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputBindings {
+    /// The interaction profile the bindings are suggested for
+    pub interaction_profile: String,
+    bindings: BTreeMap<InputAction, Vec<String>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let both_hands = |input: &str| {
+            vec![
+                format!("/user/hand/left/{}", input),
+                format!("/user/hand/right/{}", input),
+            ]
+        };
+        let bindings = vec![
+            (InputAction::HandPose, both_hands("input/grip/pose")),
+            (InputAction::PointerPose, both_hands("input/aim/pose")),
+            (InputAction::Grab, both_hands("input/squeeze/value")),
+            (InputAction::Trigger, both_hands("input/trigger/value")),
+            (InputAction::HapticFeedback, both_hands("output/haptic")),
+            (InputAction::Thumbstick, both_hands("input/thumbstick")),
+        ];
+
+        Self {
+            interaction_profile: DEFAULT_INTERACTION_PROFILE.to_string(),
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl InputBindings {
+    /// The physical inputs that drive `action`. Empty if it's unbound.
+    pub fn paths(&self, action: InputAction) -> &[String] {
+        self.bindings
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Override the physical inputs that drive `action`. An empty list leaves it unbound.
+    pub fn set_paths(&mut self, action: InputAction, paths: Vec<String>) {
+        self.bindings.insert(action, paths);
+    }
+
+    /// Every action and the physical inputs that drive it, in a stable order
+    pub fn iter(&self) -> impl Iterator<Item = (InputAction, &[String])> {
+        self.bindings
+            .iter()
+            .map(|(action, paths)| (*action, paths.as_slice()))
+    }
+
+    /// Save the bindings as JSON
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Load bindings from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_remap_input_bindings() {
+        let mut bindings = InputBindings::default();
+        assert_eq!(
+            bindings.paths(InputAction::Grab),
+            [
+                "/user/hand/left/input/squeeze/value",
+                "/user/hand/right/input/squeeze/value"
+            ]
+        );
+
+        let trigger = vec!["/user/hand/right/input/trigger/value".to_string()];
+        bindings.set_paths(InputAction::Grab, trigger.clone());
+        assert_eq!(bindings.paths(InputAction::Grab), trigger.as_slice());
+        bindings.set_paths(InputAction::Thumbstick, Vec::new());
+        assert!(bindings.paths(InputAction::Thumbstick).is_empty());
+        assert_eq!(bindings.iter().count(), 6);
+
+        let json = bindings.to_json().unwrap();
+        assert_eq!(InputBindings::from_json(&json).unwrap(), bindings);
+        assert_ne!(bindings, InputBindings::default());
+    }
+}
@@ -0,0 +1,189 @@
+use crossbeam::channel::{unbounded, Receiver, Sender};
+
+use crate::components::hand::Handedness;
+
+/// Interaction profiles of the runtime's hand tracking, when the user puts the controllers down
+const HAND_TRACKING_PROFILES: [&str; 2] = [
+    "/interaction_profiles/ext/hand_interaction_ext",
+    "/interaction_profiles/microsoft/hand_interaction",
+];
+
+/// What kind of device is driving a hand, going by its interaction profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerKind {
+    /// No device is driving the hand, eg. the controller is switched off or hasn't been picked up yet
+    None,
+    /// An Oculus or Meta Touch controller
+    TouchController,
+    /// The user's tracked hand, without a controller
+    HandTracking,
+    /// Any other controller: check `InteractionProfiles::get` for which
+    Other,
+}
+
+impl ControllerKind {
+    /// The kind of device with the interaction profile at `profile`, or none if there isn't one
+    pub fn from_profile(profile: Option<&str>) -> Self {
+        match profile {
+            None => ControllerKind::None,
+            Some(profile) if HAND_TRACKING_PROFILES.contains(&profile) => {
+                ControllerKind::HandTracking
+            }
+            Some(profile)
+                if profile.starts_with("/interaction_profiles/oculus/touch_controller")
+                    || profile.starts_with("/interaction_profiles/meta/touch_controller") =>
+            {
+                ControllerKind::TouchController
+            }
+            Some(_) => ControllerKind::Other,
+        }
+    }
+}
+
+/// Sent on `InteractionProfiles::changes` when the device driving a hand changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InteractionProfileChanged {
+    /// The hand that changed
+    pub handedness: Handedness,
+    /// The hand's interaction profile before the change
+    pub previous: Option<String>,
+    /// The hand's interaction profile now, or `None` if no device is driving it
+    pub profile: Option<String>,
+}
+
+/// The interaction profile currently driving each hand, eg. `/interaction_profiles/oculus/touch_controller`, so UI can
+/// show the right controller hints and applications can adapt to the device in use. Hotham bindings are only
+/// suggested for `InputBindings::interaction_profile`: the runtime maps them onto whatever the user is holding.
+///
+/// Updated by `XrContext` when the runtime reports an `InteractionProfileChanged` event, eg. when the user puts the
+/// controllers down and switches to hand tracking. Each change is also sent on `changes`.
+pub struct InteractionProfiles {
+    left: Option<String>,
+    right: Option<String>,
+    sender: Sender<InteractionProfileChanged>,
+    /// Receives an `InteractionProfileChanged` for every change to a hand's profile
+    pub changes: Receiver<InteractionProfileChanged>,
+}
+
+impl Default for InteractionProfiles {
+    fn default() -> Self {
+        let (sender, changes) = unbounded();
+        Self {
+            left: None,
+            right: None,
+            sender,
+            changes,
+        }
+    }
+}
+
+impl InteractionProfiles {
+    /// The interaction profile driving the hand on `handedness`, or `None` if there's no device driving it
+    pub fn get(&self, handedness: Handedness) -> Option<&str> {
+        match handedness {
+            Handedness::Left => self.left.as_deref(),
+            Handedness::Right => self.right.as_deref(),
+        }
+    }
+
+    /// The kind of device driving the hand on `handedness`
+    pub fn controller_kind(&self, handedness: Handedness) -> ControllerKind {
+        ControllerKind::from_profile(self.get(handedness))
+    }
+
+    /// Set the interaction profile of the hand on `handedness`, sending an `InteractionProfileChanged` if it's changed.
+    /// Returns whether it changed.
+    pub(crate) fn set(&mut self, handedness: Handedness, profile: Option<String>) -> bool {
+        let current = match handedness {
+            Handedness::Left => &mut self.left,
+            Handedness::Right => &mut self.right,
+        };
+        if *current == profile {
+            return false;
+        }
+
+        let previous = std::mem::replace(current, profile.clone());
+        // The receiver lives as long as we do, so this can't fail.
+        let _ = self.sender.send(InteractionProfileChanged {
+            handedness,
+            previous,
+            profile,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_interaction_profile_changes() {
+        let touch = "/interaction_profiles/oculus/touch_controller".to_string();
+        let hands = HAND_TRACKING_PROFILES[0].to_string();
+        let mut profiles = InteractionProfiles::default();
+        assert_eq!(
+            profiles.controller_kind(Handedness::Left),
+            ControllerKind::None
+        );
+
+        // Picking up the controllers..
+        assert!(profiles.set(Handedness::Left, Some(touch.clone())));
+        assert!(profiles.set(Handedness::Right, Some(touch.clone())));
+        assert_eq!(profiles.get(Handedness::Left), Some(touch.as_str()));
+        assert_eq!(
+            profiles.controller_kind(Handedness::Right),
+            ControllerKind::TouchController
+        );
+
+        // ..then putting the right one down and switching to hand tracking..
+        assert!(profiles.set(Handedness::Right, Some(hands.clone())));
+        assert_eq!(
+            profiles.controller_kind(Handedness::Right),
+            ControllerKind::HandTracking
+        );
+        assert_eq!(
+            profiles.controller_kind(Handedness::Left),
+            ControllerKind::TouchController
+        );
+
+        // ..then losing it altogether. The runtime may report a profile that hasn't changed, which isn't a change.
+        assert!(!profiles.set(Handedness::Left, Some(touch.clone())));
+        assert!(profiles.set(Handedness::Right, None));
+        assert_eq!(
+            profiles.controller_kind(Handedness::Right),
+            ControllerKind::None
+        );
+
+        let changes: Vec<_> = profiles.changes.try_iter().collect();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(
+            changes[2],
+            InteractionProfileChanged {
+                handedness: Handedness::Right,
+                previous: Some(touch),
+                profile: Some(hands.clone()),
+            }
+        );
+        assert_eq!(changes[3].previous, Some(hands));
+        assert_eq!(changes[3].profile, None);
+    }
+
+    #[test]
+    pub fn test_controller_kind() {
+        let kind = |profile| ControllerKind::from_profile(Some(profile));
+        assert_eq!(
+            kind("/interaction_profiles/meta/touch_controller_pro"),
+            ControllerKind::TouchController
+        );
+        assert_eq!(
+            kind("/interaction_profiles/microsoft/hand_interaction"),
+            ControllerKind::HandTracking
+        );
+        assert_eq!(
+            kind("/interaction_profiles/valve/index_controller"),
+            ControllerKind::Other
+        );
+        assert_eq!(ControllerKind::from_profile(None), ControllerKind::None);
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    any::Any,
+    panic::{catch_unwind, AssertUnwindSafe},
+    thread::JoinHandle,
+};
+
+use crossbeam::channel::{Receiver, Sender, TryRecvError};
+
+use crate::{HothamError, HothamResult};
+
+/// Number of worker threads used if the number of CPUs can't be determined
+const DEFAULT_THREAD_COUNT: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A small pool of worker threads for CPU-bound work, eg. parsing models while the engine starts.
+///
+/// A job that panics doesn't take its worker down with it: the panic is returned as an error from `JobHandle::wait`.
+///
+/// Basic usage:
+/// ```ignore
+/// let handle = engine.job_context.spawn(|| expensive_calculation());
+/// let result = handle.wait()?;
+/// ```
+pub struct JobContext {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobContext {
+    /// Create a pool with `thread_count` worker threads
+    pub fn new(thread_count: usize) -> Self {
+        let (sender, receiver) = crossbeam::channel::unbounded::<Job>();
+        let workers = (0..thread_count.max(1))
+            .map(|n| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("hotham-job-{}", n))
+                    .spawn(move || {
+                        // Exits once the pool has been dropped and the queue is empty.
+                        for job in receiver.iter() {
+                            job();
+                        }
+                    })
+                    .expect("[HOTHAM_JOBS] Unable to spawn worker thread")
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Run `job` on a worker thread. Use the returned handle to get its result.
+    pub fn spawn<F, T>(&self, job: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_sender, result_receiver) = crossbeam::channel::bounded(1);
+        let job: Job = Box::new(move || {
+            let result = catch_unwind(AssertUnwindSafe(job))
+                .map_err(|panic| HothamError::JobPanicked(get_panic_message(panic)));
+            // The handle may have been dropped, in which case nobody cares about the result.
+            let _ = result_sender.send(result);
+        });
+
+        // The sender is only taken when the pool is dropped, so this can't fail.
+        self.sender.as_ref().unwrap().send(job).unwrap();
+
+        JobHandle {
+            receiver: result_receiver,
+        }
+    }
+
+    /// The number of worker threads in the pool
+    pub fn thread_count(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+impl Default for JobContext {
+    fn default() -> Self {
+        let thread_count = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(DEFAULT_THREAD_COUNT);
+        Self::new(thread_count)
+    }
+}
+
+impl Drop for JobContext {
+    fn drop(&mut self) {
+        // Closing the queue lets each worker finish its remaining jobs and exit.
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A handle to the result of a job spawned with `JobContext::spawn`
+pub struct JobHandle<T> {
+    receiver: Receiver<HothamResult<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Block until the job has finished and return its result, or an error if it panicked.
+    pub fn wait(self) -> HothamResult<T> {
+        self.receiver
+            .recv()
+            .unwrap_or_else(|_| Err(HothamError::JobPanicked("Job was never run".to_string())))
+    }
+
+    /// Get the result of the job if it has finished, without blocking.
+    pub fn try_wait(&self) -> Option<HothamResult<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(HothamError::JobPanicked(
+                "Job was never run".to_string(),
+            ))),
+        }
+    }
+}
+
+fn get_panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_spawn_jobs() {
+        let job_context = JobContext::new(2);
+        let handles = (0..8)
+            .map(|n| job_context.spawn(move || n * 2))
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|h| h.wait().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results, vec![0, 2, 4, 6, 8, 10, 12, 14]);
+    }
+
+    #[test]
+    pub fn test_panicking_job_returns_error() {
+        let job_context = JobContext::new(1);
+        let result = job_context.spawn(|| -> usize { panic!("Oh no") }).wait();
+        match result {
+            Err(HothamError::JobPanicked(message)) => assert_eq!(message, "Oh no"),
+            _ => panic!("Expected the panic to be returned as an error"),
+        }
+
+        // The worker should still be alive to run more jobs.
+        assert_eq!(job_context.spawn(|| 42).wait().unwrap(), 42);
+    }
+}
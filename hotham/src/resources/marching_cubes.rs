@@ -0,0 +1,661 @@
+use std::mem::size_of;
+
+use anyhow::Result;
+use ash::vk::{self, Handle};
+use log::debug;
+use nalgebra::{vector, Vector4};
+
+use crate::{
+    buffer::{Buffer, IndexBuffer},
+    components::{
+        material::Material,
+        mesh::{create_joint_buffer, MeshUBO},
+        Mesh, Primitive, VoxelVolume,
+    },
+    resources::{render_context::create_shader, RenderContext, VulkanContext},
+    texture::Texture,
+    vertex::Vertex,
+};
+
+/// The corners at either end of each edge of a cell. Corner `i` is offset from the cell's first voxel by
+/// `(i & 1, (i >> 1) & 1, (i >> 2) & 1)`. Must match `marching_cubes.comp`.
+const EDGE_CORNERS: [[usize; 2]; 12] = [
+    [0, 1],
+    [2, 3],
+    [4, 5],
+    [6, 7],
+    [0, 2],
+    [1, 3],
+    [4, 6],
+    [5, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+/// The number of edge indices stored for each case in the triangle table: up to 5 triangles, then -1.
+const TRIANGLE_TABLE_STRIDE: usize = 16;
+
+/// The number of voxels along each axis handled by one compute workgroup. Must match `marching_cubes.comp`.
+const WORKGROUP_SIZE: u32 = 4;
+
+/// Draw parameters written by a compute shader, read by `cmd_draw_indexed_indirect`. Starts with a
+/// `vk::DrawIndexedIndirectCommand`, followed by the number of triangles the shader tried to write.
+/// Must match `marching_cubes.comp`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct IndirectDraw {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+    /// Every triangle in the surface, including any that didn't fit in the buffers
+    pub triangle_count: u32,
+}
+
+/// Push constant used by `marching_cubes.comp`. Must match the shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct MarchingCubesParams {
+    resolution: [u32; 4],
+    color: Vector4<f32>,
+    voxel_size: f32,
+    iso_level: f32,
+    max_triangles: u32,
+}
+
+/// Generates meshes from `VoxelVolume`s on the GPU with [marching cubes](https://en.wikipedia.org/wiki/Marching_cubes).
+///
+/// The vertices and indices are written straight into the buffers the mesh is drawn from, along with the number of
+/// indices to draw, so nothing is read back to the CPU. The generated primitives have no CPU-side copy of their
+/// geometry, so they can't be hit by raycasts.
+pub struct MarchingCubes {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    triangle_table: Buffer<[i32; TRIANGLE_TABLE_STRIDE]>,
+}
+
+impl MarchingCubes {
+    pub fn new(vulkan_context: &VulkanContext) -> Result<Self> {
+        debug!(target: "HOTHAM_INIT", "Creating marching cubes pipeline..");
+        let device = &vulkan_context.device;
+
+        // Density, triangle table, vertices, indices and draw parameters, in that order.
+        let bindings = (0..5)
+            .map(|binding| {
+                *vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect::<Vec<_>>();
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings),
+                None,
+            )
+        }?;
+
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange {
+                        stage_flags: vk::ShaderStageFlags::COMPUTE,
+                        offset: 0,
+                        size: size_of::<MarchingCubesParams>() as _,
+                    }]),
+                None,
+            )
+        }?;
+
+        let (shader, stage) = create_shader(
+            include_bytes!("../../shaders/marching_cubes.comp.spv"),
+            vk::ShaderStageFlags::COMPUTE,
+            vulkan_context,
+        )?;
+        let create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(pipeline_layout)
+            .build();
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        }
+        .map_err(|(_, r)| r)?;
+        unsafe { device.destroy_shader_module(shader, None) };
+        vulkan_context.set_debug_name(
+            vk::ObjectType::PIPELINE,
+            pipelines[0].as_raw(),
+            "Marching Cubes Pipeline",
+        )?;
+
+        let triangle_table = Buffer::new(
+            vulkan_context,
+            &get_triangle_table(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "Marching Cubes Triangle Table",
+        )?;
+        debug!(target: "HOTHAM_INIT", "..done!");
+
+        Ok(Self {
+            pipeline: pipelines[0],
+            pipeline_layout,
+            descriptor_set_layout,
+            triangle_table,
+        })
+    }
+
+    /// Generate a mesh from `volume`, to be added to the same entity. Waits for the GPU to finish, so call it while
+    /// loading rather than every frame.
+    pub fn create_mesh(
+        &self,
+        vulkan_context: &VulkanContext,
+        render_context: &RenderContext,
+        volume: &VoxelVolume,
+    ) -> Result<Mesh> {
+        let primitive = self.create_primitive(
+            vulkan_context,
+            render_context.descriptor_set_layouts.textures_layout,
+            volume,
+        )?;
+
+        let descriptor_sets = vulkan_context.create_mesh_descriptor_sets(
+            render_context.descriptor_set_layouts.mesh_layout,
+            "Voxel Volume",
+        )?;
+        let descriptor_sets = [descriptor_sets[0]];
+
+        let mesh_ubo = MeshUBO::default();
+        let ubo_buffer = Buffer::new(
+            vulkan_context,
+            &[mesh_ubo],
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            "Voxel Volume Mesh UBO",
+        )?;
+        vulkan_context.update_buffer_descriptor_set(
+            &ubo_buffer,
+            descriptor_sets[0],
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+        );
+        let joint_buffer =
+            create_joint_buffer(vulkan_context, 1, descriptor_sets[0], "Voxel Volume Mesh")?;
+
+        Ok(Mesh {
+            descriptor_sets,
+            ubo_buffer,
+            ubo_data: mesh_ubo,
+            joint_buffer,
+            joint_matrices: Vec::new(),
+            primitives: vec![primitive],
+        })
+    }
+
+    /// Generate a primitive from `volume`, drawn with `cmd_draw_indexed_indirect`. Waits for the GPU to finish.
+    pub fn create_primitive(
+        &self,
+        vulkan_context: &VulkanContext,
+        textures_layout: vk::DescriptorSetLayout,
+        volume: &VoxelVolume,
+    ) -> Result<Primitive> {
+        let device = &vulkan_context.device;
+        let max_vertices = volume.max_triangles as usize * 3;
+
+        let density_buffer = Buffer::new(
+            vulkan_context,
+            &volume.density,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "Voxel Volume Density",
+        )?;
+        let vertex_buffer = Buffer::new(
+            vulkan_context,
+            &vec![Vertex::default(); max_vertices],
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            "Voxel Volume Vertex Buffer",
+        )?;
+        let index_buffer = Buffer::new(
+            vulkan_context,
+            &vec![0u32; max_vertices],
+            vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            "Voxel Volume Index Buffer",
+        )?;
+        let indirect_buffer = Buffer::new(
+            vulkan_context,
+            &[IndirectDraw {
+                instance_count: 1,
+                ..Default::default()
+            }],
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            "Voxel Volume Indirect Buffer",
+        )?;
+
+        let descriptor_set = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .set_layouts(&[self.descriptor_set_layout])
+                    .descriptor_pool(vulkan_context.descriptor_pool),
+            )
+        }?[0];
+        let storage_buffer = vk::DescriptorType::STORAGE_BUFFER;
+        vulkan_context.update_buffer_descriptor_set(
+            &density_buffer,
+            descriptor_set,
+            0,
+            storage_buffer,
+        );
+        vulkan_context.update_buffer_descriptor_set(
+            &self.triangle_table,
+            descriptor_set,
+            1,
+            storage_buffer,
+        );
+        vulkan_context.update_buffer_descriptor_set(
+            &vertex_buffer,
+            descriptor_set,
+            2,
+            storage_buffer,
+        );
+        vulkan_context.update_buffer_descriptor_set(
+            &index_buffer,
+            descriptor_set,
+            3,
+            storage_buffer,
+        );
+        vulkan_context.update_buffer_descriptor_set(
+            &indirect_buffer,
+            descriptor_set,
+            4,
+            storage_buffer,
+        );
+
+        let [x, y, z] = volume.resolution;
+        let params = MarchingCubesParams {
+            resolution: [x, y, z, 0],
+            color: volume.color,
+            voxel_size: volume.voxel_size,
+            iso_level: volume.iso_level,
+            max_triangles: volume.max_triangles,
+        };
+
+        // Generate on the compute queue. If it's from a different queue family to the graphics queue, the geometry and
+        // draw parameters are released by the compute queue here, then acquired by the graphics queue below.
+        let ownership_transfer =
+            vulkan_context.compute_queue_family_index != vulkan_context.queue_family_index;
+        let buffer_barriers = |src_access_mask, dst_access_mask| {
+            [
+                (vertex_buffer.handle, vertex_buffer.size),
+                (index_buffer.handle, index_buffer.size),
+                (indirect_buffer.handle, indirect_buffer.size),
+            ]
+            .iter()
+            .map(|&(buffer, size)| {
+                *vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(src_access_mask)
+                    .dst_access_mask(dst_access_mask)
+                    .src_queue_family_index(vulkan_context.compute_queue_family_index)
+                    .dst_queue_family_index(vulkan_context.queue_family_index)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(size)
+            })
+            .collect::<Vec<_>>()
+        };
+        let draw_stages =
+            vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT;
+        let draw_access = vk::AccessFlags::INDIRECT_COMMAND_READ
+            | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+            | vk::AccessFlags::INDEX_READ;
+
+        let command_buffer =
+            vulkan_context.begin_single_time_commands_in(vulkan_context.compute_command_pool);
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(
+                    &params as *const _ as *const u8,
+                    size_of::<MarchingCubesParams>(),
+                ),
+            );
+            // One invocation per cell, between each pair of voxels.
+            device.cmd_dispatch(
+                command_buffer,
+                get_workgroup_count(x),
+                get_workgroup_count(y),
+                get_workgroup_count(z),
+            );
+            if ownership_transfer {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &buffer_barriers(vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::empty()),
+                    &[],
+                );
+            } else {
+                // Make the geometry and draw parameters visible to the draws that read them.
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    draw_stages,
+                    vk::DependencyFlags::empty(),
+                    &[*vk::MemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                        .dst_access_mask(draw_access)],
+                    &[],
+                    &[],
+                );
+            }
+        }
+        vulkan_context.end_single_time_commands_in(
+            command_buffer,
+            vulkan_context.compute_queue,
+            vulkan_context.compute_command_pool,
+        );
+
+        if ownership_transfer {
+            let command_buffer = vulkan_context.begin_single_time_commands();
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    draw_stages,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &buffer_barriers(vk::AccessFlags::empty(), draw_access),
+                    &[],
+                );
+            }
+            vulkan_context.end_single_time_commands(command_buffer);
+        }
+
+        // The density is only needed while generating.
+        unsafe {
+            device.free_descriptor_sets(vulkan_context.descriptor_pool, &[descriptor_set])?;
+            device.destroy_buffer(density_buffer.handle, None);
+            device.free_memory(density_buffer.device_memory, None);
+        }
+
+        let (material, texture_descriptor_set) = get_material(vulkan_context, textures_layout)?;
+
+        Ok(Primitive {
+            index_buffer: IndexBuffer::U32(index_buffer),
+            vertex_buffer,
+            indicies_count: max_vertices as _,
+            material,
+            texture_descriptor_set,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            bounding_sphere: volume.bounding_sphere(),
+            indirect_buffer: Some(indirect_buffer),
+            custom_shader: None,
+        })
+    }
+}
+
+/// A rough, untextured material. The colour comes from `VoxelVolume::color`, through the vertex colours.
+fn get_material(
+    vulkan_context: &VulkanContext,
+    textures_layout: vk::DescriptorSetLayout,
+) -> Result<(Material, vk::DescriptorSet)> {
+    let empty_texture = Texture::empty(vulkan_context)?;
+    let descriptor_set = vulkan_context.create_textures_descriptor_sets(
+        textures_layout,
+        "Voxel Volume Material",
+        &empty_texture,
+        &empty_texture,
+        &empty_texture,
+        &empty_texture,
+        &empty_texture,
+        &empty_texture,
+    )?[0];
+
+    let material = Material {
+        base_colour_factor: vector![1., 1., 1., 1.],
+        base_color_texture_set: -1,
+        metallic_roughness_texture_set: -1,
+        normal_texture_set: -1,
+        occlusion_texture_set: -1,
+        emissive_texture_set: -1,
+        height_texture_set: -1,
+        roughness_factor: 1.,
+        alpha_mask_cutoff: 1.,
+        ..Default::default()
+    };
+
+    Ok((material, descriptor_set))
+}
+
+/// The number of workgroups needed to cover the cells between `resolution` voxels
+fn get_workgroup_count(resolution: u32) -> u32 {
+    (resolution.saturating_sub(1) + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+}
+
+/// The faces of a cell, each as its four corners in counter-clockwise order seen from outside the cell
+fn get_faces() -> Vec<[usize; 4]> {
+    let mut faces = Vec::new();
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for side in 0..2 {
+            // Going from u to v is counter-clockwise seen from the positive side of `axis`.
+            let mut corners = [(0, 0), (1, 0), (1, 1), (0, 1)]
+                .map(|(cu, cv)| (side << axis) | (cu << u) | (cv << v));
+            if side == 0 {
+                corners.reverse();
+            }
+            faces.push(corners);
+        }
+    }
+    faces
+}
+
+/// Build the marching cubes triangle table. For each of the 256 ways a cell's corners can be inside the surface
+/// (bit `i` set if corner `i` is), up to 5 triangles as indices into `EDGE_CORNERS`, followed by -1.
+///
+/// Rather than the usual hand-written table, the surface is traced around the faces of the cell: each face is walked
+/// counter-clockwise, and the point where the walk enters the inside is joined to the point where it next leaves.
+/// These segments chain into closed loops around the inside corners, which are fanned into triangles facing out.
+/// Neighbouring cells walk a shared face in opposite directions and so join its points the same way, which keeps the
+/// surface watertight.
+pub(crate) fn get_triangle_table() -> Vec<[i32; TRIANGLE_TABLE_STRIDE]> {
+    let faces = get_faces();
+    let edge_between = |a: usize, b: usize| {
+        EDGE_CORNERS
+            .iter()
+            .position(|e| *e == [a, b] || *e == [b, a])
+            .unwrap()
+    };
+
+    (0..256usize)
+        .map(|case| {
+            let inside = |corner: usize| case & (1 << corner) != 0;
+
+            // For each edge the surface crosses, the next edge along the loop it's part of.
+            let mut next_edge = [None; 12];
+            for face in &faces {
+                let crossings = (0..4)
+                    .map(|i| (face[i], face[(i + 1) % 4]))
+                    .filter(|(a, b)| inside(*a) != inside(*b))
+                    .map(|(a, b)| (edge_between(a, b), inside(b)))
+                    .collect::<Vec<_>>();
+                for (i, (edge, entering)) in crossings.iter().enumerate() {
+                    if *entering {
+                        next_edge[*edge] = Some(crossings[(i + 1) % crossings.len()].0);
+                    }
+                }
+            }
+
+            let mut triangles = [-1; TRIANGLE_TABLE_STRIDE];
+            let mut count = 0;
+            let mut visited = [false; 12];
+            for start in 0..12 {
+                if visited[start] || next_edge[start].is_none() {
+                    continue;
+                }
+
+                let mut polygon = vec![start];
+                visited[start] = true;
+                let mut edge = next_edge[start].unwrap();
+                while edge != start {
+                    visited[edge] = true;
+                    polygon.push(edge);
+                    edge = next_edge[edge].unwrap();
+                }
+
+                for i in 1..polygon.len() - 1 {
+                    for edge in [polygon[0], polygon[i], polygon[i + 1]] {
+                        triangles[count] = edge as i32;
+                        count += 1;
+                    }
+                }
+            }
+
+            triangles
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use nalgebra::Vector3;
+
+    /// Run marching cubes on the CPU, returning the corners of each triangle in voxels
+    fn march(volume: &VoxelVolume) -> Vec<[Vector3<f32>; 3]> {
+        let table = get_triangle_table();
+        let corner = |c: usize| vector![c & 1, (c >> 1) & 1, (c >> 2) & 1].map(|o| o as u32);
+        let [rx, ry, rz] = volume.resolution;
+        let mut triangles = Vec::new();
+        for z in 0..rz - 1 {
+            for y in 0..ry - 1 {
+                for x in 0..rx - 1 {
+                    let positions = (0..8)
+                        .map(|c| vector![x, y, z] + corner(c))
+                        .collect::<Vec<_>>();
+                    let density = positions
+                        .iter()
+                        .map(|p| volume.density[volume.index(p.x, p.y, p.z)])
+                        .collect::<Vec<_>>();
+                    let case = (0..8)
+                        .filter(|c| density[*c] > volume.iso_level)
+                        .fold(0, |case, c| case | 1 << c);
+                    for triangle in table[case].chunks(3).take_while(|t| t[0] >= 0) {
+                        let point = |edge: i32| {
+                            let [a, b] = EDGE_CORNERS[edge as usize];
+                            let s = (volume.iso_level - density[a]) / (density[b] - density[a]);
+                            positions[a]
+                                .cast::<f32>()
+                                .lerp(&positions[b].cast::<f32>(), s)
+                        };
+                        triangles.push([
+                            point(triangle[0]),
+                            point(triangle[1]),
+                            point(triangle[2]),
+                        ]);
+                    }
+                }
+            }
+        }
+        triangles
+    }
+
+    fn sphere(resolution: u32, radius: f32) -> VoxelVolume {
+        let center = Vector3::repeat((resolution - 1) as f32 * 0.5);
+        VoxelVolume::from_fn([resolution; 3], 1., |p| radius - (p - center).norm())
+    }
+
+    #[test]
+    pub fn test_triangle_table() {
+        let table = get_triangle_table();
+        assert_eq!(table.len(), 256);
+        // Nothing is generated for cells that are entirely inside or outside..
+        assert_eq!(table[0], [-1; TRIANGLE_TABLE_STRIDE]);
+        assert_eq!(table[255], [-1; TRIANGLE_TABLE_STRIDE]);
+        // ..a single corner is cut off by one triangle..
+        assert_eq!(&table[1][..4], &[0, 4, 8, -1]);
+        // ..and no case needs more than 5 triangles.
+        assert!(table.iter().all(|t| t[15] == -1));
+    }
+
+    #[test]
+    pub fn test_sphere_isosurface() {
+        let volume = sphere(16, 5.3);
+        let center = Vector3::repeat(7.5);
+        let triangles = march(&volume);
+        assert_ne!(triangles.len(), 0);
+
+        // Every triangle faces out of the sphere..
+        for [a, b, c] in &triangles {
+            let normal = (b - a).cross(&(c - a));
+            assert!(normal.dot(&((a + b + c) / 3. - center)) > 0.);
+        }
+
+        // ..and every edge is shared with exactly one other triangle, running the other way.
+        let key = |p: &Vector3<f32>| p.map(|c| (c * 1000.).round() as i64);
+        let mut edges = HashMap::new();
+        for triangle in &triangles {
+            for i in 0..3 {
+                let edge = (key(&triangle[i]), key(&triangle[(i + 1) % 3]));
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        for ((a, b), count) in &edges {
+            assert_eq!(edges.get(&(*b, *a)), Some(count));
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_generate_sphere_on_gpu() {
+        use crate::{
+            resources::render_context::create_descriptor_set_layouts, util::get_from_device_memory,
+        };
+
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let descriptor_set_layouts = create_descriptor_set_layouts(&vulkan_context).unwrap();
+        let marching_cubes = MarchingCubes::new(&vulkan_context).unwrap();
+
+        let volume = sphere(16, 5.3);
+        let primitive = marching_cubes
+            .create_primitive(
+                &vulkan_context,
+                descriptor_set_layouts.textures_layout,
+                &volume,
+            )
+            .unwrap();
+        let indirect_buffer = primitive.indirect_buffer.unwrap();
+        let draw = unsafe { get_from_device_memory(&vulkan_context, &indirect_buffer) }[0];
+
+        // The GPU generates the same surface as the CPU, in some order.
+        let triangle_count = march(&volume).len() as u32;
+        assert_ne!(draw.triangle_count, 0);
+        assert_eq!(draw.triangle_count, triangle_count);
+        assert_eq!(draw.index_count, triangle_count * 3);
+        assert_eq!(draw.instance_count, 1);
+    }
+}
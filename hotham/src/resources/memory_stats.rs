@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use ash::vk;
+
+use crate::resources::VulkanContext;
+
+/// How often `MemoryStats` is updated by default
+const DEFAULT_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The GPU memory in use and available in each of the device's memory heaps. Overcommitting GPU memory crashes mobile
+/// devices, so use it to decide how much to load, eg. to set `TextureStreaming::memory_budget`.
+///
+/// Updated by `Engine::update` every `update_interval`. Usage and budgets need `VK_EXT_memory_budget`: without it,
+/// only the size of each heap is reported.
+#[derive(Debug, Clone)]
+pub struct MemoryStats {
+    /// Each of the device's memory heaps, in the order Vulkan reports them
+    pub heaps: Vec<HeapStats>,
+    /// How often `heaps` is updated. Defaults to once a second.
+    pub update_interval: Duration,
+    last_update: Option<Instant>,
+}
+
+impl Default for MemoryStats {
+    fn default() -> Self {
+        Self {
+            heaps: Vec::new(),
+            update_interval: DEFAULT_UPDATE_INTERVAL,
+            last_update: None,
+        }
+    }
+}
+
+impl MemoryStats {
+    /// Query the heaps again, if it's been at least `update_interval` since they were last queried
+    pub fn update(&mut self, vulkan_context: &VulkanContext) {
+        if self
+            .last_update
+            .map_or(false, |t| t.elapsed() < self.update_interval)
+        {
+            return;
+        }
+
+        self.heaps = vulkan_context.get_memory_heap_stats();
+        self.last_update = Some(Instant::now());
+    }
+
+    /// The device local memory still available to this process across all heaps, in bytes, or `None` if the budget
+    /// isn't known
+    pub fn device_local_available(&self) -> Option<u64> {
+        self.heaps
+            .iter()
+            .filter(|h| h.device_local)
+            .map(HeapStats::available)
+            .sum()
+    }
+}
+
+/// The size of a memory heap, and how much of it is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Total size of the heap, in bytes
+    pub size: u64,
+    /// Is this heap's memory local to the GPU?
+    pub device_local: bool,
+    /// How much of the heap this process can use before allocations may fail or hurt performance, in bytes.
+    /// `None` without `VK_EXT_memory_budget`.
+    pub budget: Option<u64>,
+    /// How much of the heap this process is using, in bytes. `None` without `VK_EXT_memory_budget`.
+    pub usage: Option<u64>,
+}
+
+impl HeapStats {
+    /// How much more of the heap this process can use, in bytes, or `None` if the budget isn't known
+    pub fn available(&self) -> Option<u64> {
+        Some(self.budget?.saturating_sub(self.usage?))
+    }
+}
+
+/// Combine the heaps in `memory_properties` with their usage and budget from `VK_EXT_memory_budget`, if there is any
+pub(crate) fn get_heap_stats(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_budget: Option<&vk::PhysicalDeviceMemoryBudgetPropertiesEXT>,
+) -> Vec<HeapStats> {
+    memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(i, heap)| HeapStats {
+            size: heap.size,
+            device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            budget: memory_budget.map(|b| b.heap_budget[i]),
+            usage: memory_budget.map(|b| b.heap_usage[i]),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_heap_stats() {
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: 2,
+            ..Default::default()
+        };
+        memory_properties.memory_heaps[0] = vk::MemoryHeap {
+            size: 4096,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        memory_properties.memory_heaps[1] = vk::MemoryHeap {
+            size: 1024,
+            flags: vk::MemoryHeapFlags::empty(),
+        };
+
+        // Without the extension, only the sizes are known..
+        let heaps = get_heap_stats(&memory_properties, None);
+        assert_eq!(
+            heaps,
+            vec![
+                HeapStats {
+                    size: 4096,
+                    device_local: true,
+                    budget: None,
+                    usage: None,
+                },
+                HeapStats {
+                    size: 1024,
+                    device_local: false,
+                    budget: None,
+                    usage: None,
+                },
+            ]
+        );
+        let memory_stats = MemoryStats {
+            heaps,
+            ..Default::default()
+        };
+        assert_eq!(memory_stats.device_local_available(), None);
+
+        // ..while with it, the usage and budget of each heap are too.
+        let mut memory_budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        memory_budget.heap_budget[0] = 3000;
+        memory_budget.heap_usage[0] = 1000;
+        memory_budget.heap_budget[1] = 512;
+        memory_budget.heap_usage[1] = 600;
+        let heaps = get_heap_stats(&memory_properties, Some(&memory_budget));
+        assert_eq!(heaps[0].budget, Some(3000));
+        assert_eq!(heaps[0].usage, Some(1000));
+        assert_eq!(heaps[0].available(), Some(2000));
+        // Usage can go over budget.
+        assert_eq!(heaps[1].available(), Some(0));
+
+        let memory_stats = MemoryStats {
+            heaps,
+            ..Default::default()
+        };
+        assert_eq!(memory_stats.device_local_available(), Some(2000));
+    }
+}
@@ -1,16 +1,53 @@
 #![allow(missing_docs)]
+pub mod adaptive_resolution;
 pub mod audio_context;
+pub mod comfort_context;
+pub mod cpu_timer;
+pub mod custom_shader;
+pub mod deletion_queue;
+pub mod entity_commands;
+pub mod frame_readback;
+pub mod gaze_context;
+pub mod gizmo;
+pub mod gpu_timer;
 pub mod gui_context;
 pub mod haptic_context;
+pub mod input_bindings;
+pub mod interaction_profiles;
+pub mod job_context;
+pub mod marching_cubes;
+pub mod memory_stats;
 pub mod physics_context;
 pub mod render_context;
+pub mod render_graph;
+pub mod render_scale;
+pub mod specialization_constants;
+pub mod texture_streaming;
 pub mod vulkan_context;
 pub mod xr_context;
 
+pub use adaptive_resolution::AdaptiveResolution;
 pub use audio_context::AudioContext;
+pub use comfort_context::ComfortContext;
+pub use cpu_timer::{CpuPhase, CpuTimer};
+pub use custom_shader::CustomShader;
+pub use deletion_queue::DeletionQueue;
+pub use entity_commands::EntityCommands;
+pub use frame_readback::{FrameReadback, ReadbackFrame};
+pub use gaze_context::{DwellSelector, GazeContext};
+pub use gizmo::{Gizmo, GizmoAxis, GizmoMode};
 pub use gui_context::GuiContext;
 pub use haptic_context::HapticContext;
+pub use input_bindings::{InputAction, InputBindings};
+pub use interaction_profiles::{ControllerKind, InteractionProfileChanged, InteractionProfiles};
+pub use job_context::{JobContext, JobHandle};
+pub use marching_cubes::MarchingCubes;
+pub use memory_stats::{HeapStats, MemoryStats};
 pub use physics_context::PhysicsContext;
 pub use render_context::RenderContext;
+pub use render_graph::RenderGraph;
+pub use specialization_constants::SpecializationConstants;
+pub use texture_streaming::{StreamedTextureId, TextureStreaming};
 pub(crate) use vulkan_context::VulkanContext;
+pub use vulkan_context::VulkanExtensions;
 pub use xr_context::XrContext;
@@ -1,4 +1,11 @@
-use std::{ffi::CStr, io::Cursor, mem::size_of, time::Instant};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::CStr,
+    io::Cursor,
+    mem::size_of,
+    time::{Duration, Instant},
+};
 
 pub static CLEAR_VALUES: [vk::ClearValue; 2] = [
     vk::ClearValue {
@@ -17,22 +24,76 @@ pub static CLEAR_VALUES: [vk::ClearValue; 2] = [
 use crate::{
     buffer::Buffer,
     camera::Camera,
-    components::Material,
+    components::{Highlight, Material, RenderLayer},
+    debug_lines::{DebugLineVertex, MAX_DEBUG_LINE_VERTICES},
     frame::Frame,
+    hotham_error::HothamError,
     image::Image,
-    resources::{VulkanContext, XrContext},
-    scene_data::{SceneData, SceneParams},
+    resources::{
+        adaptive_resolution::AdaptiveResolution,
+        comfort_context::Vignette,
+        cpu_timer::CpuTimer,
+        custom_shader::CustomShader,
+        deletion_queue::{DeletionQueue, GpuResource},
+        frame_readback::{FrameReadback, ReadbackFrame},
+        gpu_timer::GpuTimer,
+        render_graph::{AttachmentHandle, PassHandle, RenderGraph},
+        render_scale::{clamp_render_scale, get_scaled_extent, ScaledTarget},
+        specialization_constants::SpecializationConstants,
+        vulkan_context::has_stencil_component,
+        VulkanContext, XrContext,
+    },
+    scene_data::{get_environment_matrix, AmbientLight, NearFade, SceneData, SceneParams},
     swapchain::Swapchain,
     texture::Texture,
-    vertex::Vertex, COLOR_FORMAT, DEPTH_ATTACHMENT_USAGE_FLAGS, DEPTH_FORMAT, VIEW_COUNT,
+    vertex::Vertex, DEPTH_ATTACHMENT_USAGE_FLAGS, DEPTH_FORMATS, FAR_PLANE, NEAR_PLANE, VIEW_COUNT,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use ash::{
     prelude::VkResult,
     vk::{self, Handle},
 };
-use nalgebra::Matrix4;
+use hecs::{Entity, NoSuchEntity, World};
+use log::{debug, error, info, warn};
+use nalgebra::{Matrix4, UnitQuaternion, Vector3, Vector4};
 use openxr as xr;
+use std::sync::mpsc::Receiver;
+
+/// What the PBR render pass does with the contents of its attachments when it begins. Defaults to clearing both. The
+/// colour and depth attachments can be cleared independently: see `LoadOps::CLEAR_DEPTH` and `LoadOps::CLEAR_COLOUR`.
+///
+/// Use `LOAD` to draw on top of what the previous PBR render pass drew. `LOAD` keeps what was rendered into the
+/// multisampled attachments, not the contents of the swapchain image, so it can't be used to draw over passthrough:
+/// clear to a transparent colour for that. Until the PBR pass has rendered into its attachments at least once, eg. on
+/// the first frame or after the render scale changes, they're cleared instead. Use `RenderContext::set_load_ops`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LoadOps {
+    pub colour: vk::AttachmentLoadOp,
+    pub depth: vk::AttachmentLoadOp,
+}
+
+impl LoadOps {
+    /// Keep the colour and clear depth, eg. for UI drawn in a later render pass that should always be on top of the
+    /// world, without clearing the world away.
+    pub const CLEAR_DEPTH: LoadOps = LoadOps {
+        colour: vk::AttachmentLoadOp::LOAD,
+        depth: vk::AttachmentLoadOp::CLEAR,
+    };
+    /// Clear the colour and keep depth, eg. to draw a different background behind what's already been drawn.
+    pub const CLEAR_COLOUR: LoadOps = LoadOps {
+        colour: vk::AttachmentLoadOp::CLEAR,
+        depth: vk::AttachmentLoadOp::LOAD,
+    };
+}
+
+impl Default for LoadOps {
+    fn default() -> Self {
+        Self {
+            colour: vk::AttachmentLoadOp::CLEAR,
+            depth: vk::AttachmentLoadOp::CLEAR,
+        }
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 pub struct DescriptorSetLayouts {
@@ -41,25 +102,185 @@ pub struct DescriptorSetLayouts {
     pub mesh_layout: vk::DescriptorSetLayout,
 }
 
+/// Default for how long to wait for the GPU to finish with a frame before dropping it
+const DEFAULT_FENCE_TIMEOUT: Duration = Duration::from_millis(300);
+/// Default for how many frames in a row can time out before the device is treated as lost
+const DEFAULT_MAX_FENCE_TIMEOUTS: u32 = 5;
+/// Default width of debug lines and line meshes, in pixels
+const DEFAULT_LINE_WIDTH: f32 = 2.0;
+/// The bit of the stencil buffer highlighted objects write to, so they can be outlined
+pub(crate) const HIGHLIGHT_STENCIL_MASK: u32 = 1;
+/// Written to the stencil buffer wherever a `Mirror` is visible, so its reflection is only drawn there.
+/// Highlighted objects write 1, so mirrors use the next bit up.
+pub(crate) const MIRROR_STENCIL_REFERENCE: u32 = 2;
+/// The `constant_id` of `UNLIT` in `pbr.frag`, set for the pipelines of unlit materials. Hotham's own constants start
+/// at 100, so they don't clash with those passed to `create_specialized_pipeline`.
+pub const UNLIT_CONSTANT_ID: u32 = 100;
+
+/// The render layer, topology, front face, cull mode, whether alpha-to-coverage is enabled, whether the material is
+/// unlit, the custom shaders, if any, and the depth pass that a PBR pipeline was created for
+pub type PipelineVariant = (
+    RenderLayer,
+    vk::PrimitiveTopology,
+    vk::FrontFace,
+    vk::CullModeFlags,
+    bool,
+    bool,
+    Option<CustomShader>,
+    DepthPass,
+);
+
+/// Which pass of a transparent material's drawing a PBR pipeline is for: see `Material::set_depth_prepass`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DepthPass {
+    /// Draw the material in a single pass, as usual
+    Single,
+    /// Write only the material's depth, without drawing any colour
+    Prepass,
+    /// Draw the material's colour only where its depth equals what `Prepass` wrote, without writing depth
+    Equal,
+}
+
+/// How a PBR pipeline uses the stencil buffer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum StencilMode {
+    /// Write the (dynamic) stencil reference wherever an object is drawn, so highlighted objects can be outlined
+    Write,
+    /// Only draw where the stencil buffer equals the (dynamic) stencil reference, without writing to it. Used to draw
+    /// reflections inside a `Mirror`.
+    Test,
+}
+
+/// Which part of a `Mirror` a mirror pipeline draws: see `mirror_system`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MirrorStage {
+    /// Mark where the mirror is visible in the stencil buffer, without drawing anything
+    Mask,
+    /// Tint the reflection and write the mirror's depth over it, so nothing behind the mirror is drawn over it
+    Composite,
+}
+
 #[derive(Clone)]
 pub struct RenderContext {
     pub frames: Vec<Frame>,
     pub descriptor_set_layouts: DescriptorSetLayouts,
     pub pipeline_layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
+    pub transparent_pipeline: vk::Pipeline,
+    pub additive_pipeline: vk::Pipeline,
+    pub overlay_pipeline: vk::Pipeline,
+    /// Pipelines for topologies other than `TRIANGLE_LIST`, mirrored meshes or double-sided materials, created the
+    /// first time they're needed
+    pub pipeline_variants: RefCell<HashMap<PipelineVariant, vk::Pipeline>>,
+    pub outline_pipeline_layout: vk::PipelineLayout,
+    pub outline_pipeline: vk::Pipeline,
+    /// Pipelines used to mask and composite a `Mirror`'s reflection. They use the outline pipeline layout.
+    pub mirror_mask_pipeline: vk::Pipeline,
+    pub mirror_composite_pipeline: vk::Pipeline,
+    /// Pipeline used to draw a `WireframeOverlay`. Uses the outline pipeline layout. `None` if the device doesn't
+    /// support `fillModeNonSolid`.
+    pub wireframe_pipeline: Option<vk::Pipeline>,
+    /// Opaque PBR pipelines that only draw inside a `Mirror`, for each topology, front face and cull mode, created the
+    /// first time they're needed
+    pub(crate) mirror_pipelines:
+        RefCell<HashMap<(vk::PrimitiveTopology, vk::FrontFace, vk::CullModeFlags), vk::Pipeline>>,
+    pub vignette_pipeline_layout: vk::PipelineLayout,
+    pub vignette_pipeline: vk::Pipeline,
+    pub debug_line_pipeline_layout: vk::PipelineLayout,
+    pub debug_line_pipeline: vk::Pipeline,
+    /// The debug lines drawn by each frame, indexed by swapchain image
+    pub debug_line_buffers: Vec<Buffer<DebugLineVertex>>,
+    /// Debug lines queued to be drawn at the end of the PBR render pass this frame
+    pub debug_line_vertices: Vec<DebugLineVertex>,
+    /// Should `skeleton_debug_system` draw the skeletons of skinned meshes?
+    pub draw_skeletons: bool,
+    /// Width of debug lines and meshes with line topologies, in pixels. Always 1.0 if the device doesn't support
+    /// `wideLines`. Use `set_line_width` to change it.
+    pub line_width: f32,
+    /// The fraction of MSAA samples each PBR fragment is shaded for, or `None` if it's shaded once per pixel. Use
+    /// `set_min_sample_shading` to change it.
+    pub(crate) min_sample_shading: Option<f32>,
     pub render_pass: vk::RenderPass,
+    /// What the PBR render pass does with its attachments when it begins. Use `set_load_ops` to change it.
+    pub(crate) load_ops: LoadOps,
+    /// Whether the PBR pass has rendered into its current attachments, so they have contents to load
+    pub(crate) attachments_rendered: bool,
+    /// Render passes for `LoadOps` other than the default, created the first time they're needed.
+    /// They're compatible with `render_pass`, so share its pipelines and framebuffers.
+    pub render_pass_variants: RefCell<HashMap<LoadOps, vk::RenderPass>>,
     pub depth_image: Image,
+    /// Format of the depth image, chosen from `DEPTH_FORMATS`
+    pub depth_format: vk::Format,
     pub colour_image: Image,
+    /// Format of the colour image and swapchain. See `XrContext::swapchain_format`.
+    pub colour_format: vk::Format,
+    /// Orders the passes in a frame that render into or copy between attachments, and records the barriers between
+    /// them. Anything drawn inside the PBR render pass, like mirrors or the depth prepass, is part of `pbr_pass`.
+    pub render_graph: RenderGraph,
+    /// The pass everything in the world is drawn in
+    pub pbr_pass: PassHandle,
+    /// The pass that blits the PBR pass's output to the swapchain image, when the render scale isn't 1.0
+    pub render_scale_blit_pass: PassHandle,
+    /// The image the PBR pass resolves colour into when the render scale isn't 1.0: see `ScaledTarget`
+    pub(crate) scaled_resolve_attachment: AttachmentHandle,
+    /// This frame's swapchain image, as written by `render_scale_blit_pass`
+    pub(crate) swapchain_attachment: AttachmentHandle,
     pub render_area: vk::Rect2D,
+    /// The resolution the PBR pass renders at, relative to `render_area`. Use `set_render_scale` to change it.
+    pub(crate) render_scale: f32,
+    /// Where the PBR pass renders when `render_scale` isn't 1.0
+    pub(crate) scaled_target: Option<ScaledTarget>,
+    /// Whether the PBR pass resolves depth into the images of `XrContext::depth_swapchain`, for the compositor
+    pub(crate) resolve_depth: bool,
+    /// Adjusts the render scale to keep the GPU within its frame budget. Disabled by default.
+    pub adaptive_resolution: AdaptiveResolution,
+    /// How long the GPU took to render the last frame that used the current swapchain image, if it was measured
+    pub gpu_frame_time: Option<Duration>,
+    /// Timestamp queries used to measure `gpu_frame_time`. `None` if the device doesn't support them.
+    pub(crate) gpu_timer: Option<GpuTimer>,
+    /// Warns when the CPU takes longer than the frame budget. Only enabled in debug builds by default.
+    pub cpu_timer: CpuTimer,
+    /// Copies rendered frames back to the CPU. `None` unless enabled with `enable_frame_readback`.
+    pub frame_readback: Option<FrameReadback>,
     pub scene_data: SceneData,
-    pub scene_data_buffer: Buffer<SceneData>,
+    /// Constant light added to the diffuse term of every PBR material, independent of any other lights
+    pub ambient_light: AmbientLight,
+    /// Fades out geometry very close to the eyes. Disabled by default.
+    pub near_fade: NearFade,
+    /// Scene data for each swapchain image, so that updating one frame's scene data can't race the GPU reading an
+    /// earlier frame's
+    pub scene_data_buffers: Vec<Buffer<SceneData>>,
     pub scene_params_buffer: Buffer<SceneParams>,
+    /// A descriptor set for each swapchain image, pointing at its scene data buffer. Use `scene_data_descriptor_set`
+    /// to get the one for the current frame.
     pub scene_data_descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Image based lighting maps, used for reflections when there are no `ReflectionProbe`s
+    pub(crate) diffuse_ibl: Texture,
+    pub(crate) specular_ibl: Texture,
+    pub(crate) brdf_lut: Texture,
+    /// The cubemap bound for specular reflections in each scene data descriptor set: either `specular_ibl` or a
+    /// `ReflectionProbe`'s
+    pub(crate) reflection_cubemaps: Vec<vk::ImageView>,
+    /// How the image based lighting maps are rotated. See `set_environment_rotation`
+    environment_rotation: UnitQuaternion<f32>,
     pub render_start_time: Instant,
     pub cameras: Vec<Camera>,
     pub views: Vec<xr::View>,
     pub last_frame_time: Instant,
     pub frame_index: usize,
+    /// Set when the current frame is being skipped, eg. because the GPU was still busy. No commands are recorded.
+    pub skip_frame: bool,
+    /// The number of frames that have been dropped since the engine started
+    pub dropped_frames: usize,
+    /// How long to wait for the GPU to finish with a frame before dropping it. Defaults to 300ms.
+    pub fence_timeout: Duration,
+    /// How many frames in a row can time out before the GPU is assumed to be hung, and `begin_frame` returns
+    /// `ERROR_DEVICE_LOST`. Defaults to 5.
+    pub max_fence_timeouts: u32,
+    /// The number of frames in a row that have timed out
+    pub(crate) fence_timeouts: u32,
+    /// GPU resources waiting for the frames using them to finish before they're destroyed
+    pub deletion_queue: DeletionQueue,
 }
 
 impl Drop for RenderContext {
@@ -97,13 +318,32 @@ impl Drop for RenderContext {
 
 impl RenderContext {
     pub fn new(vulkan_context: &VulkanContext, xr_context: &XrContext) -> Result<Self> {
-        println!("[HOTHAM_RENDERER] Creating renderer..");
+        debug!(target: "HOTHAM_RENDERER", "Creating renderer..");
         let xr_swapchain = &xr_context.swapchain;
         let swapchain_resolution = xr_context.swapchain_resolution;
 
         // Build swapchain
-        let swapchain = Swapchain::new(xr_swapchain, swapchain_resolution)?;
-        Self::new_from_swapchain(vulkan_context, &swapchain)
+        let swapchain = Swapchain::new(
+            xr_swapchain,
+            swapchain_resolution,
+            xr_context.swapchain_format,
+        )?;
+
+        // The depth swapchain is created with the same depth format the renderer picks.
+        let depth_swapchain = match &xr_context.depth_swapchain {
+            Some(handle) => Some(Swapchain::new(
+                handle,
+                swapchain_resolution,
+                vulkan_context.get_depth_format(&DEPTH_FORMATS)?,
+            )?),
+            None => None,
+        };
+        Self::new_with_render_area(
+            vulkan_context,
+            &swapchain,
+            xr_context.image_rect,
+            depth_swapchain.as_ref(),
+        )
     }
 
     pub(crate) fn new_from_swapchain(
@@ -114,11 +354,44 @@ impl RenderContext {
             extent: swapchain.resolution,
             offset: vk::Offset2D::default(),
         };
+        Self::new_with_render_area(vulkan_context, swapchain, render_area, None)
+    }
+
+    /// Create a renderer that only draws to `render_area` of each swapchain image, eg. when the runtime hands back a
+    /// sub-rect of a shared image. The viewport, scissor and render pass are all limited to it.
+    ///
+    /// If there's a `depth_swapchain`, depth is resolved into its images at the end of the PBR pass, so it can be
+    /// submitted to the compositor. It must have as many images as `swapchain`, in the renderer's depth format.
+    pub(crate) fn new_with_render_area(
+        vulkan_context: &VulkanContext,
+        swapchain: &Swapchain,
+        render_area: vk::Rect2D,
+        depth_swapchain: Option<&Swapchain>,
+    ) -> Result<Self> {
+        if !image_rect_fits(&render_area, swapchain.resolution) {
+            return Err(anyhow!(
+                "Render area {:?} doesn't fit in swapchain images of {:?}",
+                render_area,
+                swapchain.resolution
+            ));
+        }
 
         let descriptor_set_layouts = create_descriptor_set_layouts(&vulkan_context)?;
 
+        // Pick the best depth format this device supports
+        let depth_format = vulkan_context.get_depth_format(&DEPTH_FORMATS)?;
+        info!(target: "HOTHAM_RENDERER", "Using depth format {:?}", depth_format);
+
         // Pipeline, render pass
-        let render_pass = create_render_pass(&vulkan_context)?;
+        let colour_format = swapchain.format;
+        let resolve_depth = depth_swapchain.is_some();
+        let render_pass = create_render_pass(
+            &vulkan_context,
+            depth_format,
+            colour_format,
+            LoadOps::default(),
+            resolve_depth,
+        )?;
         let pipeline_layout = create_pipeline_layout(
             &vulkan_context,
             &[
@@ -127,27 +400,170 @@ impl RenderContext {
                 descriptor_set_layouts.mesh_layout,
             ],
         )?;
-        let pipeline =
-            create_pipeline(&vulkan_context, pipeline_layout, &render_area, render_pass)?;
+        let pipeline = create_pipeline(
+            &vulkan_context,
+            pipeline_layout,
+            render_pass,
+            RenderLayer::OPAQUE,
+            depth_format,
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            vk::CullModeFlags::BACK,
+            false,
+            None,
+            &Default::default(),
+            StencilMode::Write,
+            DepthPass::Single,
+            vk::Pipeline::null(),
+            None,
+        )?;
+        let transparent_pipeline = create_pipeline(
+            &vulkan_context,
+            pipeline_layout,
+            render_pass,
+            RenderLayer::TRANSPARENT,
+            depth_format,
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            vk::CullModeFlags::BACK,
+            false,
+            None,
+            &Default::default(),
+            StencilMode::Write,
+            DepthPass::Single,
+            vk::Pipeline::null(),
+            None,
+        )?;
+        let additive_pipeline = create_pipeline(
+            &vulkan_context,
+            pipeline_layout,
+            render_pass,
+            RenderLayer::ADDITIVE,
+            depth_format,
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            vk::CullModeFlags::BACK,
+            false,
+            None,
+            &Default::default(),
+            StencilMode::Write,
+            DepthPass::Single,
+            vk::Pipeline::null(),
+            None,
+        )?;
+        let overlay_pipeline = create_pipeline(
+            &vulkan_context,
+            pipeline_layout,
+            render_pass,
+            RenderLayer::OVERLAY,
+            depth_format,
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            vk::CullModeFlags::BACK,
+            false,
+            None,
+            &Default::default(),
+            StencilMode::Write,
+            DepthPass::Single,
+            vk::Pipeline::null(),
+            None,
+        )?;
+        let outline_pipeline_layout = create_outline_pipeline_layout(
+            &vulkan_context,
+            &[
+                descriptor_set_layouts.scene_data_layout,
+                descriptor_set_layouts.textures_layout,
+                descriptor_set_layouts.mesh_layout,
+            ],
+        )?;
+        let outline_pipeline = create_outline_pipeline(
+            &vulkan_context,
+            outline_pipeline_layout,
+            render_pass,
+            depth_format,
+        )?;
+        let mirror_mask_pipeline = create_mirror_pipeline(
+            &vulkan_context,
+            outline_pipeline_layout,
+            render_pass,
+            depth_format,
+            MirrorStage::Mask,
+        )?;
+        let mirror_composite_pipeline = create_mirror_pipeline(
+            &vulkan_context,
+            outline_pipeline_layout,
+            render_pass,
+            depth_format,
+            MirrorStage::Composite,
+        )?;
+        let wireframe_pipeline = if vulkan_context.enabled_features.fill_mode_non_solid == vk::TRUE
+        {
+            Some(create_wireframe_pipeline(
+                &vulkan_context,
+                outline_pipeline_layout,
+                render_pass,
+            )?)
+        } else {
+            None
+        };
+        let vignette_pipeline_layout = create_vignette_pipeline_layout(&vulkan_context)?;
+        let vignette_pipeline =
+            create_vignette_pipeline(&vulkan_context, vignette_pipeline_layout, render_pass)?;
+
+        let debug_line_pipeline_layout = create_pipeline_layout_without_push_constants(
+            &vulkan_context,
+            &[descriptor_set_layouts.scene_data_layout],
+        )?;
+        let debug_line_pipeline =
+            create_debug_line_pipeline(&vulkan_context, debug_line_pipeline_layout, render_pass)?;
 
         // Depth image, shared between frames
         let depth_image = vulkan_context.create_image(
-            DEPTH_FORMAT,
+            depth_format,
             &swapchain.resolution,
             DEPTH_ATTACHMENT_USAGE_FLAGS,
             2,
             1,
+            "Depth Image",
         )?;
 
         // Colour image, used for MSAA.
         let colour_image = vulkan_context.create_image(
-            COLOR_FORMAT,
+            colour_format,
             &swapchain.resolution,
             vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
             2,
             1,
+            "MSAA Colour Image",
         )?;
 
+        // The PBR pass, then the blit to the swapchain when the render scale isn't 1.0. The scaled resolve image and
+        // the swapchain image are set when they're known: see `set_render_scale` and `end_pbr_render_pass`.
+        let mut render_graph = RenderGraph::default();
+        let colour_attachment =
+            render_graph.add_attachment("Colour", colour_image.handle, colour_format);
+        let depth_attachment =
+            render_graph.add_attachment("Depth", depth_image.handle, depth_format);
+        let scaled_resolve_attachment =
+            render_graph.add_attachment("Scaled Resolve", vk::Image::null(), colour_format);
+        let swapchain_attachment =
+            render_graph.add_attachment("Swapchain", vk::Image::null(), colour_format);
+        let pbr_pass = render_graph.add_pass(
+            "PBR",
+            &[],
+            &[
+                colour_attachment,
+                depth_attachment,
+                scaled_resolve_attachment,
+            ],
+        );
+        let render_scale_blit_pass = render_graph.add_transfer_pass(
+            "Render Scale Blit",
+            &[scaled_resolve_attachment],
+            &[swapchain_attachment],
+        );
+        render_graph.compile()?;
+
         // Create all the per-frame resources we need
         let frames = create_frames(
             &vulkan_context,
@@ -155,21 +571,43 @@ impl RenderContext {
             &swapchain,
             &depth_image,
             &colour_image,
+            depth_swapchain,
         )?;
 
-        println!("[HOTHAM_RENDERER] Creating UBO..");
+        let gpu_timer = GpuTimer::new(vulkan_context, frames.len())?;
+
+        debug!(target: "HOTHAM_RENDERER", "Creating UBOs..");
         let scene_data = SceneData::default();
-        let scene_data_buffer = Buffer::new(
-            &vulkan_context,
-            &[scene_data],
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-        )?;
+        let scene_data_buffers = (0..frames.len())
+            .map(|i| {
+                Buffer::new(
+                    &vulkan_context,
+                    &[scene_data],
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    &format!("Scene Data Buffer {}", i),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // As with scene data, each frame gets its own debug lines, so a frame still in flight never has its lines
+        // overwritten.
+        let debug_line_buffers = (0..frames.len())
+            .map(|i| {
+                Buffer::new(
+                    &vulkan_context,
+                    &vec![DebugLineVertex::default(); MAX_DEBUG_LINE_VERTICES],
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    &format!("Debug Line Vertex Buffer {}", i),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let scene_params = SceneParams::default();
         let scene_params_buffer = Buffer::new(
             &vulkan_context,
             &[scene_params],
             vk::BufferUsageFlags::UNIFORM_BUFFER,
+            "Scene Params Buffer",
         )?;
 
         let diffuse_ibl = Texture::from_ktx2(
@@ -188,33 +626,87 @@ impl RenderContext {
             vulkan_context,
         )?;
 
-        let scene_data_descriptor_sets = vulkan_context.create_scene_data_descriptor_sets(
-            descriptor_set_layouts.scene_data_layout,
-            &scene_data_buffer,
-            &scene_params_buffer,
-            &diffuse_ibl,
-            &specular_ibl,
-            &brdf_lut,
-        )?;
+        // Each frame gets its own descriptor set, so none of them are updated while the GPU may be using them.
+        let mut scene_data_descriptor_sets = Vec::with_capacity(frames.len());
+        for scene_data_buffer in &scene_data_buffers {
+            scene_data_descriptor_sets.extend(vulkan_context.create_scene_data_descriptor_sets(
+                descriptor_set_layouts.scene_data_layout,
+                scene_data_buffer,
+                &scene_params_buffer,
+                &diffuse_ibl,
+                &specular_ibl,
+                &brdf_lut,
+            )?);
+        }
 
-        println!("[HOTHAM_RENDERER] ..done! {:?}", scene_data_buffer);
+        debug!(target: "HOTHAM_RENDERER", "..done! {:?}", scene_data_buffers);
 
-        println!("[HOTHAM_RENDERER] Done! Renderer initialised!");
+        info!(target: "HOTHAM_RENDERER", "Done! Renderer initialised!");
 
         Ok(Self {
             frames,
             descriptor_set_layouts,
             pipeline,
+            transparent_pipeline,
+            additive_pipeline,
+            overlay_pipeline,
+            pipeline_variants: Default::default(),
+            outline_pipeline_layout,
+            outline_pipeline,
+            mirror_mask_pipeline,
+            mirror_composite_pipeline,
+            wireframe_pipeline,
+            mirror_pipelines: Default::default(),
+            vignette_pipeline_layout,
+            vignette_pipeline,
+            debug_line_pipeline_layout,
+            debug_line_pipeline,
+            debug_line_buffers,
+            debug_line_vertices: Vec::new(),
+            draw_skeletons: false,
+            line_width: clamp_line_width(DEFAULT_LINE_WIDTH, get_line_width_range(vulkan_context)),
+            min_sample_shading: None,
+            ambient_light: Default::default(),
+            near_fade: Default::default(),
             pipeline_layout,
             render_pass,
+            load_ops: Default::default(),
+            attachments_rendered: false,
+            render_pass_variants: Default::default(),
             frame_index: 0,
+            skip_frame: false,
+            dropped_frames: 0,
+            fence_timeout: DEFAULT_FENCE_TIMEOUT,
+            max_fence_timeouts: DEFAULT_MAX_FENCE_TIMEOUTS,
+            fence_timeouts: 0,
+            deletion_queue: Default::default(),
             depth_image,
+            depth_format,
             colour_image,
+            colour_format,
+            render_graph,
+            pbr_pass,
+            render_scale_blit_pass,
+            scaled_resolve_attachment,
+            swapchain_attachment,
             render_area,
+            render_scale: 1.0,
+            scaled_target: None,
+            resolve_depth,
+            adaptive_resolution: Default::default(),
+            gpu_frame_time: None,
+            cpu_timer: Default::default(),
+            frame_readback: None,
+            gpu_timer,
             scene_data,
-            scene_data_buffer,
+            scene_data_buffers,
             scene_params_buffer,
+            reflection_cubemaps: vec![specular_ibl.image.view; scene_data_descriptor_sets.len()],
+            environment_rotation: UnitQuaternion::identity(),
             scene_data_descriptor_sets,
+            diffuse_ibl,
+            specular_ibl,
+            brdf_lut,
             render_start_time: Instant::now(),
             cameras: vec![Default::default(); 2],
             views: Vec::new(),
@@ -222,11 +714,239 @@ impl RenderContext {
         })
     }
 
+    /// Set the exposure of both cameras. See `Camera::set_exposure`
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.cameras
+            .iter_mut()
+            .for_each(|c| c.set_exposure(exposure));
+    }
+
+    /// Set the gamma of both cameras. See `Camera::set_gamma`
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.cameras.iter_mut().for_each(|c| c.set_gamma(gamma));
+    }
+
+    /// Rotate the image based lighting maps by `rotation`, eg. to line the environment up with the scene's north
+    /// without baking the maps again. Reflections from `ReflectionProbe`s aren't affected, as they're already captured
+    /// in world space.
+    pub fn set_environment_rotation(&mut self, rotation: UnitQuaternion<f32>) {
+        self.environment_rotation = rotation;
+    }
+
+    /// How the image based lighting maps are rotated. See `set_environment_rotation`
+    pub fn environment_rotation(&self) -> UnitQuaternion<f32> {
+        self.environment_rotation
+    }
+
+    /// Set the width of debug lines and meshes with line topologies, in pixels.
+    /// The width is clamped to the range the device supports, or 1.0 if it doesn't support `wideLines`.
+    pub fn set_line_width(&mut self, vulkan_context: &VulkanContext, line_width: f32) {
+        self.line_width = clamp_line_width(line_width, get_line_width_range(vulkan_context));
+    }
+
+    /// The fraction of MSAA samples each PBR fragment is shaded for, or `None` if sample shading is off. See
+    /// `set_min_sample_shading`.
+    pub fn min_sample_shading(&self) -> Option<f32> {
+        self.min_sample_shading
+    }
+
+    /// Shade each fragment drawn with a PBR pipeline for at least `min_sample_shading` times the number of MSAA
+    /// samples, rather than once per pixel. This reduces specular aliasing ("sparkles") on high-frequency normal maps,
+    /// which MSAA alone doesn't help with. `None` turns it off, which is the default. Values are clamped to (0, 1]:
+    /// 1.0 shades every sample.
+    ///
+    /// Returns the value in use, which is `None` if the device doesn't support the `sampleRateShading` feature.
+    ///
+    /// NOTE: This is expensive. With 4x MSAA, a `min_sample_shading` of 1.0 runs the fragment shader up to four times
+    /// as often, and mobile GPUs like the Quest's are usually limited by fragment shading already. Try 0.25 or 0.5
+    /// first, and check `gpu_frame_time`.
+    ///
+    /// Changing it recreates the PBR pipelines, so only call it between frames. Pipelines created with
+    /// `create_specialized_pipeline` keep the setting they were created with.
+    pub fn set_min_sample_shading(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        min_sample_shading: Option<f32>,
+    ) -> Result<Option<f32>> {
+        let mut min_sample_shading = clamp_min_sample_shading(min_sample_shading);
+        if min_sample_shading.is_some()
+            && vulkan_context.enabled_features.sample_rate_shading != vk::TRUE
+        {
+            warn!(
+                target: "HOTHAM_RENDERER",
+                "sampleRateShading isn't supported, so sample shading can't be enabled"
+            );
+            min_sample_shading = None;
+        }
+        if min_sample_shading == self.min_sample_shading {
+            return Ok(min_sample_shading);
+        }
+
+        let create = |render_layer| {
+            create_pipeline(
+                vulkan_context,
+                self.pipeline_layout,
+                self.render_pass,
+                render_layer,
+                self.depth_format,
+                vk::PrimitiveTopology::TRIANGLE_LIST,
+                vk::FrontFace::COUNTER_CLOCKWISE,
+                vk::CullModeFlags::BACK,
+                false,
+                min_sample_shading,
+                &Default::default(),
+                StencilMode::Write,
+                DepthPass::Single,
+                vk::Pipeline::null(),
+                None,
+            )
+        };
+        let pipeline = create(RenderLayer::OPAQUE)?;
+        let transparent_pipeline = create(RenderLayer::TRANSPARENT)?;
+        let additive_pipeline = create(RenderLayer::ADDITIVE)?;
+        let overlay_pipeline = create(RenderLayer::OVERLAY)?;
+
+        // The old pipelines may still be in use by frames in flight. Variants are recreated when they're next needed.
+        let mut old_pipelines = vec![
+            std::mem::replace(&mut self.pipeline, pipeline),
+            std::mem::replace(&mut self.transparent_pipeline, transparent_pipeline),
+            std::mem::replace(&mut self.additive_pipeline, additive_pipeline),
+            std::mem::replace(&mut self.overlay_pipeline, overlay_pipeline),
+        ];
+        old_pipelines.extend(self.pipeline_variants.get_mut().drain().map(|(_, p)| p));
+        old_pipelines.extend(self.mirror_pipelines.get_mut().drain().map(|(_, p)| p));
+        for pipeline in old_pipelines {
+            self.deletion_queue
+                .push(self.frame_index, GpuResource::Pipeline(pipeline));
+        }
+
+        self.min_sample_shading = min_sample_shading;
+        info!(
+            target: "HOTHAM_RENDERER",
+            "Min sample shading set to {:?}",
+            min_sample_shading
+        );
+        Ok(min_sample_shading)
+    }
+
+    /// Set the line width for the pipeline that was just bound, if it has a dynamic line width
+    pub(crate) fn bind_line_width(
+        &self,
+        vulkan_context: &VulkanContext,
+        command_buffer: vk::CommandBuffer,
+        topology: vk::PrimitiveTopology,
+    ) {
+        if has_dynamic_line_width(vulkan_context, topology) {
+            unsafe {
+                vulkan_context
+                    .device
+                    .cmd_set_line_width(command_buffer, self.line_width);
+            }
+        }
+    }
+
+    /// The resolution the PBR pass renders at, relative to the swapchain's. See `set_render_scale`.
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Render the scene at `render_scale` times the swapchain's resolution in each dimension, then upscale or
+    /// downsample it into the swapchain image. Below 1.0 trades sharpness for GPU time, above 1.0 supersamples.
+    /// Returns the scale that was set, which is clamped to between `MIN_RENDER_SCALE` and `MAX_RENDER_SCALE`.
+    ///
+    /// Can be called between frames, but not between `begin_pbr_render_pass` and `end_pbr_render_pass`.
+    /// NOTE: Changing the scale changes the attachments, so the next frame clears them, whatever the `LoadOps`.
+    pub fn set_render_scale(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        render_scale: f32,
+    ) -> Result<f32> {
+        let render_scale = clamp_render_scale(render_scale);
+        let max_dimension = vulkan_context
+            .physical_device_properties
+            .limits
+            .max_image_dimension2_d;
+        let extent = get_scaled_extent(self.render_area.extent, render_scale, max_dimension);
+        self.render_scale = render_scale;
+        if extent == self.get_pbr_render_area().extent {
+            return Ok(render_scale);
+        }
+
+        let scaled_target = if extent == self.render_area.extent {
+            None
+        } else {
+            Some(ScaledTarget::new(
+                vulkan_context,
+                self.render_pass,
+                self.colour_format,
+                self.depth_format,
+                extent,
+                self.resolve_depth,
+            )?)
+        };
+        if let Some(old_target) = std::mem::replace(&mut self.scaled_target, scaled_target) {
+            old_target.release(&mut self.deletion_queue, self.frame_index);
+        }
+        self.attachments_rendered = false;
+        let resolve_image = self
+            .scaled_target
+            .as_ref()
+            .map_or(vk::Image::null(), |t| t.resolve_image.handle);
+        self.render_graph
+            .set_attachment_image(self.scaled_resolve_attachment, resolve_image);
+
+        info!(
+            target: "HOTHAM_RENDERER",
+            "Render scale set to {}: rendering at {}x{}",
+            render_scale, extent.width, extent.height
+        );
+        Ok(render_scale)
+    }
+
+    /// Let `adaptive_resolution` adjust the render scale, based on `gpu_frame_time` and the `display_period` the
+    /// runtime predicts for this frame. Called by `begin_frame`, before any render passes have begun.
+    pub(crate) fn update_adaptive_resolution(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        display_period: Duration,
+    ) -> Result<()> {
+        let gpu_time = match self.gpu_frame_time {
+            Some(gpu_time) => gpu_time,
+            None => return Ok(()),
+        };
+
+        if let Some(render_scale) =
+            self.adaptive_resolution
+                .update(gpu_time, display_period, self.render_scale)
+        {
+            self.set_render_scale(vulkan_context, render_scale)?;
+        }
+        Ok(())
+    }
+
+    /// The area the PBR pass renders into: `render_area`, or an offscreen area of a different size if the render
+    /// scale isn't 1.0
+    pub fn get_pbr_render_area(&self) -> vk::Rect2D {
+        self.scaled_target
+            .as_ref()
+            .map(|t| t.render_area)
+            .unwrap_or(self.render_area)
+    }
+
+    /// Whether the PBR pass resolves depth into this frame's image of `XrContext::depth_swapchain`. Depth isn't
+    /// scaled, so it's only resolved there when the render scale is 1.0.
+    pub(crate) fn has_resolved_depth(&self) -> bool {
+        self.resolve_depth && self.scaled_target.is_none()
+    }
+
     // TODO: Make this update the scene data rather than creating a new one
+    /// Update the scene data used to render to the swapchain image at `swapchain_image_index`.
+    /// Must be called after `begin_frame` has waited for the GPU to finish with that image's last frame.
     pub(crate) fn update_scene_data(
         &mut self,
         views: &Vec<xr::View>,
         vulkan_context: &VulkanContext,
+        swapchain_image_index: usize,
     ) -> Result<()> {
         self.views = views.clone();
 
@@ -239,19 +959,21 @@ impl RenderContext {
             .collect::<Result<Vec<_>>>()?;
 
         // Projection
-        let near = 0.05;
-        let far = 100.0;
+        let near = NEAR_PLANE;
+        let far = FAR_PLANE;
 
         let view = [view_matrices[0], view_matrices[1]];
         let fov_left = views[0].fov;
         let fov_right = views[1].fov;
         if self.frame_index == 1 {
-            println!(
-                "[FOV Left]: up: {} down: {}, left: {}, right: {}",
+            debug!(
+                target: "HOTHAM_RENDERER",
+                "FOV Left: up: {} down: {}, left: {}, right: {}",
                 fov_left.angle_up, fov_left.angle_down, fov_left.angle_left, fov_left.angle_right
             );
-            println!(
-                "[FOV Right]: up: {} down: {}, left: {}, right: {}",
+            debug!(
+                target: "HOTHAM_RENDERER",
+                "FOV Right: up: {} down: {}, left: {}, right: {}",
                 fov_right.angle_up,
                 fov_right.angle_down,
                 fov_right.angle_left,
@@ -266,48 +988,109 @@ impl RenderContext {
 
         let camera_position = [self.cameras[0].position(), self.cameras[1].position()];
         if self.frame_index == 0 {
-            println!("Camera position: {:?}", camera_position);
+            debug!(target: "HOTHAM_RENDERER", "Camera position: {:?}", camera_position);
         }
 
+        let tonemapping = [
+            self.cameras[0].tonemapping_params(),
+            self.cameras[1].tonemapping_params(),
+        ];
+
         self.scene_data = SceneData {
             view,
             projection,
             camera_position,
+            tonemapping,
+            ambient_light: self.ambient_light.to_shader(),
+            near_fade: self.near_fade.to_shader(),
+            environment_rotation: get_environment_matrix(&self.environment_rotation),
+            reflection_rotation: self.get_reflection_rotation(swapchain_image_index),
         };
 
-        self.scene_data_buffer
+        self.scene_data_buffers[swapchain_image_index]
             .update(&vulkan_context, &[self.scene_data])?;
 
         Ok(())
     }
 
+    /// Wait for the GPU to finish with this frame's resources and begin recording its command buffer.
+    /// Returns `HothamError::FenceTimeout` if the GPU is still busy after `fence_timeout`: the frame should be
+    /// skipped. After `max_fence_timeouts` timeouts in a row, returns `ERROR_DEVICE_LOST` instead.
     pub(crate) fn begin_frame(
-        &self,
+        &mut self,
         vulkan_context: &VulkanContext,
         swapchain_image_index: usize,
-    ) {
+    ) -> Result<(), HothamError> {
         // Get the values we need to start the frame..
         let device = &vulkan_context.device;
         let frame = &self.frames[swapchain_image_index];
         let command_buffer = frame.command_buffer;
 
+        // Throw away any debug lines from a frame that was never drawn.
+        self.debug_line_vertices.clear();
+
         // Wait for the GPU to be ready.
-        self.wait(device, frame);
+        match self.wait(device, frame, swapchain_image_index) {
+            Ok(()) => self.fence_timeouts = 0,
+            Err(e @ HothamError::FenceTimeout { .. }) => {
+                self.fence_timeouts += 1;
+                self.dropped_frames += 1;
+                self.skip_frame = true;
+                warn!(
+                    target: "HOTHAM_RENDERER",
+                    "{} - dropping frame. {} frames dropped so far.",
+                    e, self.dropped_frames
+                );
+
+                if self.fence_timeouts >= self.max_fence_timeouts {
+                    error!(
+                        target: "HOTHAM_RENDERER",
+                        "GPU has timed out on {} frames in a row - treating the device as lost",
+                        self.fence_timeouts
+                    );
+                    return Err(vk::Result::ERROR_DEVICE_LOST.into());
+                }
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+        self.skip_frame = false;
+
+        // Destroy anything the GPU has finished with.
+        self.deletion_queue
+            .flush(vulkan_context, self.frame_index, self.frames.len());
+
+        // The GPU has finished with the last frame to use this image, so its timestamps are ready.
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            self.gpu_frame_time = gpu_timer.get_frame_time(device, swapchain_image_index);
+        }
+
+        // ..and so is any frame it copied back.
+        if let Some(frame_readback) = self.frame_readback.as_mut() {
+            match frame_readback.collect(vulkan_context, swapchain_image_index) {
+                Ok(true) => {}
+                Ok(false) => self.release_frame_readback(),
+                Err(e) => error!(target: "HOTHAM_RENDERER", "Unable to read back frame: {:?}", e),
+            }
+        }
 
         // Begin recording the command buffer.
         unsafe {
-            device
-                .begin_command_buffer(
-                    command_buffer,
-                    &vk::CommandBufferBeginInfo::builder()
-                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                )
-                .unwrap();
+            device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
         }
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            gpu_timer.begin(device, command_buffer, swapchain_image_index);
+        }
+
+        Ok(())
     }
 
     pub(crate) fn begin_pbr_render_pass(
-        &self,
+        &mut self,
         vulkan_context: &VulkanContext,
         swapchain_image_index: usize,
     ) {
@@ -315,14 +1098,32 @@ impl RenderContext {
         let device = &vulkan_context.device;
         let frame = &self.frames[swapchain_image_index];
         let command_buffer = frame.command_buffer;
-        let framebuffer = frame.framebuffer;
 
-        // Begin the renderpass.
+        // Render offscreen if the render scale isn't 1.0: see `set_render_scale`.
+        let (framebuffer, render_area) = match &self.scaled_target {
+            Some(scaled_target) => (scaled_target.framebuffer, scaled_target.render_area),
+            None => (frame.framebuffer, self.render_area),
+        };
+
+        // Wait for any passes the PBR pass depends on.
+        self.render_graph
+            .record_barriers(device, command_buffer, self.pbr_pass);
+
+        // Begin the renderpass. `set_load_ops` has already created its render pass.
+        let load_ops = get_load_ops(self.load_ops, self.attachments_rendered);
+        let render_pass = self
+            .render_pass_variants
+            .borrow()
+            .get(&load_ops)
+            .copied()
+            .unwrap_or(self.render_pass);
+        let clear_values = get_clear_values(load_ops);
+        self.attachments_rendered = true;
         let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.render_pass)
+            .render_pass(render_pass)
             .framebuffer(framebuffer)
-            .render_area(self.render_area)
-            .clear_values(&CLEAR_VALUES);
+            .render_area(render_area)
+            .clear_values(clear_values);
 
         unsafe {
             device.cmd_begin_render_pass(
@@ -330,6 +1131,55 @@ impl RenderContext {
                 &render_pass_begin_info,
                 vk::SubpassContents::INLINE,
             );
+            set_viewport(device, command_buffer, &render_area);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_set_stencil_reference(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                0,
+            );
+            device.cmd_set_depth_bias(command_buffer, 0.0, 0.0, 0.0);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.scene_data_descriptor_set(swapchain_image_index)],
+                &[],
+            );
+        }
+    }
+
+    /// Darken the periphery of the view. Must be called inside the PBR render pass, after everything else has been drawn.
+    /// Leaves the default pipeline bound.
+    pub(crate) fn draw_vignette(
+        &self,
+        vulkan_context: &VulkanContext,
+        swapchain_image_index: usize,
+        vignette: &Vignette,
+    ) {
+        let device = &vulkan_context.device;
+        let command_buffer = self.frames[swapchain_image_index].command_buffer;
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.vignette_pipeline,
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.vignette_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                create_push_constant(vignette),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+            // The vignette pipeline layout isn't compatible with the PBR one, so the scene data must be bound again.
             device.cmd_bind_pipeline(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
@@ -340,7 +1190,7 @@ impl RenderContext {
                 vk::PipelineBindPoint::GRAPHICS,
                 self.pipeline_layout,
                 0,
-                &self.scene_data_descriptor_sets,
+                &[self.scene_data_descriptor_set(swapchain_image_index)],
                 &[],
             );
         }
@@ -354,46 +1204,601 @@ impl RenderContext {
         let device = &vulkan_context.device;
         let frame = &self.frames[swapchain_image_index];
         let command_buffer = frame.command_buffer;
+        self.draw_debug_lines(vulkan_context, swapchain_image_index);
         unsafe {
             device.cmd_end_render_pass(command_buffer);
         }
+
+        if let Some(scaled_target) = &self.scaled_target {
+            let swapchain_image = self.frames[swapchain_image_index].swapchain_image;
+            self.render_graph
+                .set_attachment_image(self.swapchain_attachment, swapchain_image);
+            self.render_graph
+                .record_barriers(device, command_buffer, self.render_scale_blit_pass);
+            scaled_target.blit_to_swapchain(
+                device,
+                command_buffer,
+                swapchain_image,
+                &self.render_area,
+            );
+        }
+
+        if let Some(frame_readback) = self.frame_readback.as_mut() {
+            frame_readback.record(
+                device,
+                command_buffer,
+                self.frames[swapchain_image_index].swapchain_image,
+                swapchain_image_index,
+                self.frame_index,
+            );
+        }
+    }
+
+    /// Start copying every rendered frame back to the CPU. Frames are delivered on the returned channel a couple of
+    /// frames after they're rendered, without stalling the render loop: see `FrameReadback`. Set
+    /// `FrameReadback::interval` to read back fewer frames.
+    ///
+    /// Fails if the swapchain's format can't be converted to RGBA.
+    pub fn enable_frame_readback(
+        &mut self,
+        vulkan_context: &VulkanContext,
+    ) -> Result<Receiver<ReadbackFrame>> {
+        let (frame_readback, receiver) = FrameReadback::new(
+            vulkan_context,
+            self.colour_format,
+            self.render_area,
+            self.frames.len(),
+        )?;
+        self.release_frame_readback();
+        self.frame_readback = Some(frame_readback);
+        Ok(receiver)
+    }
+
+    /// Stop copying frames back to the CPU. Frames that are still in flight aren't delivered.
+    pub fn disable_frame_readback(&mut self) {
+        self.release_frame_readback();
+    }
+
+    /// Drop the current `FrameReadback`, if there is one, queueing its buffers to be destroyed once the frames that
+    /// may still be copying into them are done.
+    fn release_frame_readback(&mut self) {
+        if let Some(frame_readback) = self.frame_readback.take() {
+            frame_readback.release(&mut self.deletion_queue, self.frame_index);
+        }
+    }
+
+    /// Queue a line to be drawn, in world space, at the end of the PBR render pass.
+    /// Lines are drawn on top of everything else. Lines past `MAX_DEBUG_LINE_VERTICES` are ignored.
+    pub fn draw_debug_line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: Vector4<f32>) {
+        if self.debug_line_vertices.len() + 2 > MAX_DEBUG_LINE_VERTICES {
+            return;
+        }
+        self.debug_line_vertices
+            .push(DebugLineVertex::new(from, color));
+        self.debug_line_vertices
+            .push(DebugLineVertex::new(to, color));
+    }
+
+    fn draw_debug_lines(&mut self, vulkan_context: &VulkanContext, swapchain_image_index: usize) {
+        if self.debug_line_vertices.is_empty() {
+            return;
+        }
+
+        let device = &vulkan_context.device;
+        let command_buffer = self.frames[swapchain_image_index].command_buffer;
+        let debug_line_buffer = &self.debug_line_buffers[swapchain_image_index];
+        debug_line_buffer
+            .update(vulkan_context, &self.debug_line_vertices)
+            .unwrap();
+        let debug_line_buffer = debug_line_buffer.handle;
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.debug_line_pipeline,
+            );
+        }
+        self.bind_line_width(
+            vulkan_context,
+            command_buffer,
+            vk::PrimitiveTopology::LINE_LIST,
+        );
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.debug_line_pipeline_layout,
+                0,
+                &[self.scene_data_descriptor_set(swapchain_image_index)],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[debug_line_buffer], &[0]);
+            device.cmd_draw(command_buffer, self.debug_line_vertices.len() as _, 1, 0, 0);
+        }
+
+        self.debug_line_vertices.clear();
     }
 
+    /// Finish recording this frame's command buffer and submit it to the GPU.
+    /// If the submission fails with a recoverable error the frame is dropped; `DEVICE_LOST` and other fatal errors are returned.
+    ///
+    /// NOTE: There are deliberately no image-available or render-finished semaphores here. OpenXR owns presentation:
+    /// `xrWaitSwapchainImage` guarantees the image is ready to be written before the frame is recorded, and the runtime
+    /// orders its own reads after work submitted to the queue before `xrReleaseSwapchainImage`. A binary semaphore
+    /// signalled here would never be waited on, which is invalid. The frame's fence only guards its command buffer.
     pub(crate) fn end_frame(
         &mut self,
         vulkan_context: &VulkanContext,
         swapchain_image_index: usize,
-    ) {
+    ) -> Result<(), HothamError> {
         // Get the values we need to end the renderpass
         let device = &vulkan_context.device;
         let frame = &self.frames[swapchain_image_index];
         let command_buffer = frame.command_buffer;
         let graphics_queue = vulkan_context.graphics_queue;
 
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.end(device, command_buffer, swapchain_image_index);
+        }
+
         // End the render pass and submit.
         unsafe {
-            device.end_command_buffer(command_buffer).unwrap();
+            device.end_command_buffer(command_buffer)?;
             let fence = frame.fence;
             let submit_info = vk::SubmitInfo::builder()
                 .command_buffers(&[command_buffer])
                 .build();
-            device
-                .queue_submit(graphics_queue, &[submit_info], fence)
-                .unwrap();
+            if let Err(e) = device.queue_submit(graphics_queue, &[submit_info], fence) {
+                if is_fatal(e) {
+                    return Err(e.into());
+                }
+
+                // The fence was reset in `wait`, so make sure it's signalled again or we'll never see this frame again.
+                device.queue_submit(graphics_queue, &[], fence)?;
+                self.dropped_frames += 1;
+                warn!(
+                    target: "HOTHAM_RENDERER",
+                    "Unable to submit frame {}: {:?} - dropping frame. {} frames dropped so far.",
+                    swapchain_image_index, e, self.dropped_frames
+                );
+            }
         }
 
         self.last_frame_time = Instant::now();
         self.frame_index += 1;
+        Ok(())
     }
 
-    pub(crate) fn wait(&self, device: &ash::Device, frame: &Frame) {
-        let fence = frame.fence;
+    /// Despawn `entity` and its descendants from `world`, destroying the GPU resources they own once no frame in
+    /// flight is using them.
+    /// See `DeletionQueue::despawn`.
+    pub fn despawn(&mut self, world: &mut World, entity: Entity) -> Result<(), NoSuchEntity> {
+        self.deletion_queue.despawn(world, entity, self.frame_index)
+    }
 
-        unsafe {
-            device.wait_for_fences(&[fence], true, u64::MAX).unwrap();
-            device.reset_fences(&[fence]).unwrap();
+    /// Get the PBR pipeline used to draw entities in `render_layer`
+    pub fn get_pipeline_for_layer(&self, render_layer: RenderLayer) -> vk::Pipeline {
+        if render_layer.is_overlay() {
+            self.overlay_pipeline
+        } else if render_layer.is_additive() {
+            self.additive_pipeline
+        } else if render_layer.is_transparent() {
+            self.transparent_pipeline
+        } else {
+            self.pipeline
+        }
+    }
+
+    /// Create a PBR pipeline for `render_layer`, with the specialization constants in its shaders set to
+    /// `specialization_constants`. Unlike `get_pipeline`, a new pipeline is created on every call, so keep hold of it.
+    pub fn create_specialized_pipeline(
+        &self,
+        vulkan_context: &VulkanContext,
+        render_layer: RenderLayer,
+        specialization_constants: &SpecializationConstants,
+    ) -> Result<vk::Pipeline> {
+        create_pipeline(
+            vulkan_context,
+            self.pipeline_layout,
+            self.render_pass,
+            render_layer,
+            self.depth_format,
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            vk::FrontFace::COUNTER_CLOCKWISE,
+            vk::CullModeFlags::BACK,
+            false,
+            self.min_sample_shading,
+            specialization_constants,
+            StencilMode::Write,
+            DepthPass::Single,
+            self.get_pipeline_for_layer(render_layer),
+            None,
+        )
+    }
+
+    /// Get the pipeline used to draw primitives with `topology` in `render_layer`, creating it if it doesn't exist yet.
+    /// `front_face` should come from `get_front_face`, so that mirrored meshes aren't culled inside out, and
+    /// `cull_mode`, `alpha_to_coverage` and `unlit` from the primitive's `Material`. Unlit materials get a variant
+    /// with the lighting compiled out of the fragment shader. Primitives with a `custom_shader` get a variant using
+    /// its shaders instead of the PBR ones. `depth_pass` is `DepthPass::Single` unless the material has a depth
+    /// prepass: see `Material::set_depth_prepass`.
+    pub fn get_pipeline(
+        &self,
+        vulkan_context: &VulkanContext,
+        render_layer: RenderLayer,
+        topology: vk::PrimitiveTopology,
+        front_face: vk::FrontFace,
+        cull_mode: vk::CullModeFlags,
+        alpha_to_coverage: bool,
+        unlit: bool,
+        custom_shader: Option<CustomShader>,
+        depth_pass: DepthPass,
+    ) -> Result<vk::Pipeline> {
+        if topology == vk::PrimitiveTopology::TRIANGLE_LIST
+            && front_face == vk::FrontFace::COUNTER_CLOCKWISE
+            && cull_mode == vk::CullModeFlags::BACK
+            && !alpha_to_coverage
+            && !unlit
+            && custom_shader.is_none()
+            && depth_pass == DepthPass::Single
+        {
+            return Ok(self.get_pipeline_for_layer(render_layer));
+        }
+
+        // Layers share pipelines in the same way as they do in `get_pipeline_for_layer`.
+        let render_layer = if render_layer.is_overlay() {
+            RenderLayer::OVERLAY
+        } else if render_layer.is_additive() {
+            RenderLayer::ADDITIVE
+        } else if render_layer.is_transparent() {
+            RenderLayer::TRANSPARENT
+        } else {
+            RenderLayer::OPAQUE
+        };
+
+        let variant = (
+            render_layer,
+            topology,
+            front_face,
+            cull_mode,
+            alpha_to_coverage,
+            unlit,
+            custom_shader,
+            depth_pass,
+        );
+        let mut pipeline_variants = self.pipeline_variants.borrow_mut();
+        if let Some(pipeline) = pipeline_variants.get(&variant) {
+            return Ok(*pipeline);
+        }
+
+        let mut specialization_constants = SpecializationConstants::default();
+        if unlit {
+            specialization_constants.set("UNLIT", UNLIT_CONSTANT_ID, true);
+        }
+        let pipeline = create_pipeline(
+            vulkan_context,
+            self.pipeline_layout,
+            self.render_pass,
+            render_layer,
+            self.depth_format,
+            topology,
+            front_face,
+            cull_mode,
+            alpha_to_coverage,
+            self.min_sample_shading,
+            &specialization_constants,
+            StencilMode::Write,
+            depth_pass,
+            self.get_pipeline_for_layer(render_layer),
+            custom_shader,
+        )?;
+        pipeline_variants.insert(variant, pipeline);
+        Ok(pipeline)
+    }
+
+    /// Get the opaque pipeline used to draw primitives with `topology` inside a `Mirror`, creating it if it doesn't
+    /// exist yet. Only draws where the stencil buffer equals the stencil reference. Reflected meshes are mirrored, so
+    /// `front_face` should come from `get_front_face` with the reflection applied to the mesh's transform.
+    pub(crate) fn get_mirror_pipeline(
+        &self,
+        vulkan_context: &VulkanContext,
+        topology: vk::PrimitiveTopology,
+        front_face: vk::FrontFace,
+        cull_mode: vk::CullModeFlags,
+    ) -> Result<vk::Pipeline> {
+        let variant = (topology, front_face, cull_mode);
+        let mut mirror_pipelines = self.mirror_pipelines.borrow_mut();
+        if let Some(pipeline) = mirror_pipelines.get(&variant) {
+            return Ok(*pipeline);
+        }
+
+        let pipeline = create_pipeline(
+            vulkan_context,
+            self.pipeline_layout,
+            self.render_pass,
+            RenderLayer::OPAQUE,
+            self.depth_format,
+            topology,
+            front_face,
+            cull_mode,
+            false,
+            self.min_sample_shading,
+            &Default::default(),
+            StencilMode::Test,
+            DepthPass::Single,
+            self.pipeline,
+            None,
+        )?;
+        mirror_pipelines.insert(variant, pipeline);
+        Ok(pipeline)
+    }
+
+    /// The scene data descriptor set to bind when rendering to the swapchain image at `swapchain_image_index`
+    pub fn scene_data_descriptor_set(&self, swapchain_image_index: usize) -> vk::DescriptorSet {
+        self.scene_data_descriptor_sets[swapchain_image_index]
+    }
+
+    /// Use `cubemap` for specular reflections, or the default specular IBL map if it's `None`, when rendering to the
+    /// swapchain image at `swapchain_image_index`.
+    ///
+    /// Only that image's descriptor set is updated, as the GPU has finished with it: the others catch up when their
+    /// images come around again.
+    pub(crate) fn set_reflection_cubemap(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        swapchain_image_index: usize,
+        cubemap: Option<&Texture>,
+    ) {
+        let cubemap = cubemap.unwrap_or(&self.specular_ibl);
+        let image_view = cubemap.image.view;
+        if image_view == self.reflection_cubemaps[swapchain_image_index] {
+            return;
+        }
+
+        // Binding 3 is the prefiltered map: see `create_descriptor_set_layouts`.
+        let image_info = [cubemap.descriptor];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.scene_data_descriptor_set(swapchain_image_index))
+            .dst_binding(3)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+        unsafe { vulkan_context.device.update_descriptor_sets(&[*write], &[]) };
+        self.reflection_cubemaps[swapchain_image_index] = image_view;
+
+        // Probes aren't rotated along with the image based lighting maps, so the scene data must be updated to match.
+        self.scene_data.reflection_rotation = self.get_reflection_rotation(swapchain_image_index);
+        if let Err(e) = self.scene_data_buffers[swapchain_image_index]
+            .update(vulkan_context, &[self.scene_data])
+        {
+            error!("Unable to update scene data: {:?}", e);
+        }
+    }
+
+    /// The rotation of the cubemap bound for specular reflections when rendering to the swapchain image at
+    /// `swapchain_image_index`. See `set_reflection_cubemap`
+    fn get_reflection_rotation(&self, swapchain_image_index: usize) -> Matrix4<f32> {
+        if self.reflection_cubemaps[swapchain_image_index] == self.specular_ibl.image.view {
+            get_environment_matrix(&self.environment_rotation)
+        } else {
+            Matrix4::identity()
+        }
+    }
+
+    /// What the PBR render pass does with its attachments when it begins. See `LoadOps`.
+    pub fn load_ops(&self) -> LoadOps {
+        self.load_ops
+    }
+
+    /// Change what the PBR render pass does with its attachments when it begins, from the next frame. Its render pass
+    /// is created now, so fails if it can't be.
+    pub fn set_load_ops(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        load_ops: LoadOps,
+    ) -> Result<()> {
+        self.get_render_pass(vulkan_context, load_ops)?;
+        self.load_ops = load_ops;
+        Ok(())
+    }
+
+    /// Get the PBR render pass that begins with `load_ops`, creating it if it doesn't exist yet.
+    pub fn get_render_pass(
+        &self,
+        vulkan_context: &VulkanContext,
+        load_ops: LoadOps,
+    ) -> Result<vk::RenderPass> {
+        if load_ops == LoadOps::default() {
+            return Ok(self.render_pass);
+        }
+
+        let mut render_pass_variants = self.render_pass_variants.borrow_mut();
+        if let Some(render_pass) = render_pass_variants.get(&load_ops) {
+            return Ok(*render_pass);
+        }
+
+        let render_pass = create_render_pass(
+            vulkan_context,
+            self.depth_format,
+            self.colour_format,
+            load_ops,
+            self.resolve_depth,
+        )?;
+        render_pass_variants.insert(load_ops, render_pass);
+        Ok(render_pass)
+    }
+
+    /// Wait up to `fence_timeout` for the GPU to signal this frame's fence.
+    /// Returns `HothamError::FenceTimeout` if it wasn't signalled in time.
+    pub(crate) fn wait(
+        &self,
+        device: &ash::Device,
+        frame: &Frame,
+        swapchain_image_index: usize,
+    ) -> Result<(), HothamError> {
+        let fence = frame.fence;
+        let timeout = self.fence_timeout.as_nanos() as u64;
+
+        match unsafe { device.wait_for_fences(&[fence], true, timeout) } {
+            Ok(_) => {
+                unsafe { device.reset_fences(&[fence])? };
+                Ok(())
+            }
+            Err(e) if is_fatal(e) => Err(e.into()),
+            Err(_) => Err(HothamError::FenceTimeout {
+                frame: swapchain_image_index,
+                timeout: self.fence_timeout,
+            }),
         }
+    }
+}
+
+/// Get the range of line widths the device supports, or `None` if `wideLines` isn't enabled.
+fn get_line_width_range(vulkan_context: &VulkanContext) -> Option<[f32; 2]> {
+    let limits = &vulkan_context.physical_device_properties.limits;
+    if vulkan_context.enabled_features.wide_lines == vk::TRUE {
+        Some(limits.line_width_range)
+    } else {
+        None
+    }
+}
+
+/// Clamp `line_width` to `line_width_range`, warning if it's out of range. Lines are always 1.0 wide without `wideLines`.
+fn clamp_line_width(line_width: f32, line_width_range: Option<[f32; 2]>) -> f32 {
+    let [min, max] = line_width_range.unwrap_or([1.0, 1.0]);
+    let clamped = line_width.max(min).min(max);
+    if (clamped - line_width).abs() > f32::EPSILON {
+        warn!(
+            target: "HOTHAM_RENDERER",
+            "Line width {} is not supported, using {} instead",
+            line_width, clamped
+        );
+    }
+    clamped
+}
+
+/// How the PBR pipeline for `render_layer` blends. Opaque layers don't blend at all.
+///
+/// - Additive layers add the fragment's colour to what's behind it: `src * ONE + dst * ONE`. The fragment's alpha is
+///   ignored, so fade additive effects out by darkening their colour. The destination's alpha is kept.
+/// - Transparent and overlay layers alpha blend: `src * SRC_ALPHA + dst * ONE_MINUS_SRC_ALPHA`
+fn get_color_blend_attachment(render_layer: RenderLayer) -> vk::PipelineColorBlendAttachmentState {
+    let builder = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(render_layer.is_transparent() || render_layer.is_overlay())
+        .color_blend_op(vk::BlendOp::ADD)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    if render_layer.is_additive() {
+        builder
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .build()
+    } else {
+        builder
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .build()
+    }
+}
+
+/// Pipelines that draw lines have a dynamic line width, if the device supports `wideLines`.
+fn has_dynamic_line_width(vulkan_context: &VulkanContext, topology: vk::PrimitiveTopology) -> bool {
+    let is_line = matches!(
+        topology,
+        vk::PrimitiveTopology::LINE_LIST | vk::PrimitiveTopology::LINE_STRIP
+    );
+    is_line && vulkan_context.enabled_features.wide_lines == vk::TRUE
+}
+
+/// Get the viewport covering `render_area`, which may be offset within the swapchain image.
+fn get_viewport(render_area: &vk::Rect2D) -> vk::Viewport {
+    vk::Viewport {
+        x: render_area.offset.x as _,
+        y: render_area.offset.y as _,
+        width: render_area.extent.width as _,
+        height: render_area.extent.height as _,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
+/// Set the dynamic viewport and scissor to `render_area`. Every pipeline used in the PBR render pass has a dynamic
+/// viewport and scissor, so this must be called after the pass begins.
+pub(crate) fn set_viewport(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    render_area: &vk::Rect2D,
+) {
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, &[get_viewport(render_area)]);
+        device.cmd_set_scissor(command_buffer, 0, &[*render_area]);
+    }
+}
+
+/// Does `image_rect` lie entirely within an image of size `resolution`?
+fn image_rect_fits(image_rect: &vk::Rect2D, resolution: vk::Extent2D) -> bool {
+    let vk::Rect2D { offset, extent } = *image_rect;
+    offset.x >= 0
+        && offset.y >= 0
+        && extent.width > 0
+        && extent.height > 0
+        && offset.x as u32 + extent.width <= resolution.width
+        && offset.y as u32 + extent.height <= resolution.height
+}
+
+/// Get the winding that faces the camera for a mesh drawn with `transform`.
+///
+/// A transform with a negative scale on an odd number of axes mirrors the mesh, which reverses the winding of its
+/// triangles. Those meshes need `CLOCKWISE` front faces, or back face culling would show them inside out.
+/// Normals don't need flipping, as the vertex shader transforms them by the inverse transpose.
+pub fn get_front_face(transform: &Matrix4<f32>) -> vk::FrontFace {
+    if transform.fixed_slice::<3, 3>(0, 0).determinant() < 0. {
+        vk::FrontFace::CLOCKWISE
+    } else {
+        vk::FrontFace::COUNTER_CLOCKWISE
+    }
+}
+
+/// The clear values to begin the PBR render pass with. Vulkan only needs values up to the last attachment that is
+/// cleared, so nothing is recorded for attachments that are loaded or don't care.
+fn get_clear_values(load_ops: LoadOps) -> &'static [vk::ClearValue] {
+    // Attachments are ordered colour, depth, resolve: see `create_render_pass`.
+    if load_ops.depth == vk::AttachmentLoadOp::CLEAR {
+        &CLEAR_VALUES
+    } else if load_ops.colour == vk::AttachmentLoadOp::CLEAR {
+        &CLEAR_VALUES[..1]
+    } else {
+        &[]
+    }
+}
+
+/// The `LoadOps` to begin the PBR render pass with. Attachments that haven't been rendered into yet have nothing to
+/// load, so they're cleared.
+fn get_load_ops(load_ops: LoadOps, attachments_rendered: bool) -> LoadOps {
+    if attachments_rendered {
+        load_ops
+    } else {
+        LoadOps::default()
+    }
+}
 
+/// Attachments that are loaded must start in the layout the previous render pass left them in.
+fn get_initial_layout(load_op: vk::AttachmentLoadOp, layout: vk::ImageLayout) -> vk::ImageLayout {
+    if load_op == vk::AttachmentLoadOp::LOAD {
+        layout
+    } else {
+        vk::ImageLayout::UNDEFINED
     }
 }
 
@@ -401,7 +1806,7 @@ pub fn create_push_constant<T: Sized>(p: &T) -> &[u8] {
     unsafe { std::slice::from_raw_parts(std::mem::transmute(p), size_of::<T>()) }
 }
 
-fn get_projection(fov: xr::Fovf, near: f32, far: f32) -> Matrix4<f32> {
+pub(crate) fn get_projection(fov: xr::Fovf, near: f32, far: f32) -> Matrix4<f32> {
     let tan_left = f32::tan(fov.angle_left);
     let tan_right = f32::tan(fov.angle_right);
 
@@ -522,6 +1927,12 @@ pub fn create_descriptor_set_layouts(
         .descriptor_count(1)
         .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
         .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+    // set = 1 binding = 5
+    let height_map = vk::DescriptorSetLayoutBinding::builder()
+        .binding(5)
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
     let material_layout = unsafe {
         vulkan_context.device.create_descriptor_set_layout(
             &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
@@ -530,6 +1941,7 @@ pub fn create_descriptor_set_layouts(
                 *normal_map,
                 *ao_map,
                 *emissive_map,
+                *height_map,
             ]),
             None,
         )
@@ -547,9 +1959,15 @@ pub fn create_descriptor_set_layouts(
         .descriptor_count(1)
         .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
         .stage_flags(vk::ShaderStageFlags::VERTEX);
+    // set = 2, binding = 1
+    let joint_matrices = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_count(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .stage_flags(vk::ShaderStageFlags::VERTEX);
     let mesh_data_layout = unsafe {
         vulkan_context.device.create_descriptor_set_layout(
-            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[*mesh_data]),
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[*mesh_data, *joint_matrices]),
             None,
         )
     }?;
@@ -572,51 +1990,72 @@ fn create_frames(
     swapchain: &Swapchain,
     depth_image: &Image,
     colour_image: &Image,
+    depth_swapchain: Option<&Swapchain>,
 ) -> Result<Vec<Frame>> {
-    print!("[HOTHAM_INIT] Creating frames..");
+    debug!(target: "HOTHAM_INIT", "Creating frames..");
     let frames = swapchain
         .images
         .iter()
-        .flat_map(|i| {
-            vulkan_context.create_image_view(
+        .enumerate()
+        .map(|(index, i)| {
+            let v = vulkan_context.create_image_view(
                 i,
-                COLOR_FORMAT,
+                swapchain.format,
                 vk::ImageViewType::TYPE_2D_ARRAY,
                 2,
                 1,
-            )
-        })
-        .map(|i| {
+            )?;
+            let depth_resolve_image_view = match depth_swapchain {
+                Some(depth_swapchain) => Some(vulkan_context.create_image_view(
+                    &depth_swapchain.images[index],
+                    depth_swapchain.format,
+                    vk::ImageViewType::TYPE_2D_ARRAY,
+                    2,
+                    1,
+                )?),
+                None => None,
+            };
             Frame::new(
                 vulkan_context,
                 *render_pass,
                 swapchain.resolution,
-                i,
+                *i,
+                v,
                 depth_image.view,
                 colour_image.view,
+                depth_resolve_image_view,
             )
         })
         .collect::<Result<Vec<Frame>>>()?;
-    println!(" ..done!");
+    debug!(target: "HOTHAM_INIT", "..done!");
     Ok(frames)
 }
 
-fn create_render_pass(vulkan_context: &VulkanContext) -> Result<vk::RenderPass> {
-    print!("[HOTHAM_INIT] Creating render pass..");
+fn create_render_pass(
+    vulkan_context: &VulkanContext,
+    depth_format: vk::Format,
+    colour_format: vk::Format,
+    load_ops: LoadOps,
+    resolve_depth: bool,
+) -> Result<vk::RenderPass> {
+    debug!(target: "HOTHAM_INIT", "Creating render pass with {:?}..", load_ops);
     // Attachment used for MSAA
     let colour_attachment = vk::AttachmentDescription::builder()
-        .format(COLOR_FORMAT)
+        .format(colour_format)
         .samples(vk::SampleCountFlags::TYPE_4)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .load_op(load_ops.colour)
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .initial_layout(get_initial_layout(
+            load_ops.colour,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        ))
         .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
     // Final attachment to be presented
     let colour_attachment_resolve = vk::AttachmentDescription::builder()
-        .format(COLOR_FORMAT)
+        .format(colour_format)
         .samples(vk::SampleCountFlags::TYPE_1)
         .load_op(vk::AttachmentLoadOp::DONT_CARE)
         .store_op(vk::AttachmentStoreOp::STORE)
@@ -625,14 +2064,19 @@ fn create_render_pass(vulkan_context: &VulkanContext) -> Result<vk::RenderPass>
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
+    // Depth is always stored, as `LoadOps` can be changed between frames, so a later render pass may load it.
+    let depth_store_op = vk::AttachmentStoreOp::STORE;
     let depth_attachment = vk::AttachmentDescription::builder()
-        .format(DEPTH_FORMAT)
+        .format(depth_format)
         .samples(vk::SampleCountFlags::TYPE_4)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .load_op(load_ops.depth)
+        .store_op(depth_store_op)
+        .stencil_load_op(load_ops.depth)
+        .stencil_store_op(depth_store_op)
+        .initial_layout(get_initial_layout(
+            load_ops.depth,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        ))
         .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
     let color_attachment_reference = vk::AttachmentReference::builder()
@@ -688,45 +2132,214 @@ fn create_render_pass(vulkan_context: &VulkanContext) -> Result<vk::RenderPass>
         *colour_attachment_resolve,
     ];
 
-    let render_pass = unsafe {
-        vulkan_context.device.create_render_pass(
-            &vk::RenderPassCreateInfo::builder()
+    let render_pass = if resolve_depth {
+        create_depth_resolve_render_pass(
+            vulkan_context,
+            &attachments,
+            depth_format,
+            &dependency,
+            view_masks[0],
+        )?
+    } else {
+        unsafe {
+            vulkan_context.device.create_render_pass(
+                &vk::RenderPassCreateInfo::builder()
+                    .attachments(&attachments)
+                    .subpasses(&[*subpass])
+                    .dependencies(&[*dependency])
+                    .push_next(&mut multiview),
+                None,
+            )
+        }?
+    };
+    vulkan_context.set_debug_name(
+        vk::ObjectType::RENDER_PASS,
+        render_pass.as_raw(),
+        &format!("PBR Render Pass ({:?})", load_ops),
+    )?;
+    debug!(target: "HOTHAM_INIT", "..done!");
+
+    Ok(render_pass)
+}
+
+/// Create the PBR render pass with a fourth attachment, that the multisampled depth attachment is resolved into so it
+/// can be submitted to the compositor. `attachments`, `dependency` and `view_mask` are those of `create_render_pass`,
+/// which are translated to their Vulkan 1.2 equivalents: depth can only be resolved with `vkCreateRenderPass2`.
+///
+/// Depth is resolved from the first sample, rather than averaged, so edges don't get depths that aren't on any
+/// surface. The resolved attachment ends up in `DEPTH_STENCIL_ATTACHMENT_OPTIMAL`, which OpenXR expects depth
+/// swapchain images to be in when they're released.
+fn create_depth_resolve_render_pass(
+    vulkan_context: &VulkanContext,
+    attachments: &[vk::AttachmentDescription],
+    depth_format: vk::Format,
+    dependency: &vk::SubpassDependency,
+    view_mask: u32,
+) -> Result<vk::RenderPass> {
+    let mut attachments = attachments
+        .iter()
+        .map(|a| {
+            vk::AttachmentDescription2::builder()
+                .flags(a.flags)
+                .format(a.format)
+                .samples(a.samples)
+                .load_op(a.load_op)
+                .store_op(a.store_op)
+                .stencil_load_op(a.stencil_load_op)
+                .stencil_store_op(a.stencil_store_op)
+                .initial_layout(a.initial_layout)
+                .final_layout(a.final_layout)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    attachments.push(
+        vk::AttachmentDescription2::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build(),
+    );
+
+    let reference = |attachment, layout| {
+        vk::AttachmentReference2::builder()
+            .attachment(attachment)
+            .layout(layout)
+            .build()
+    };
+    let color_attachment_reference = [reference(0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+    let depth_stencil_reference = reference(1, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    let color_attachment_resolve_reference =
+        [reference(2, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+    let depth_stencil_resolve_reference =
+        reference(3, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    // Every device that can resolve depth supports SAMPLE_ZERO for both depth and stencil.
+    let mut depth_stencil_resolve = vk::SubpassDescriptionDepthStencilResolve::builder()
+        .depth_resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+        .stencil_resolve_mode(vk::ResolveModeFlags::SAMPLE_ZERO)
+        .depth_stencil_resolve_attachment(&depth_stencil_resolve_reference);
+    let subpass = vk::SubpassDescription2::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .view_mask(view_mask)
+        .color_attachments(&color_attachment_reference)
+        .resolve_attachments(&color_attachment_resolve_reference)
+        .depth_stencil_attachment(&depth_stencil_reference)
+        .push_next(&mut depth_stencil_resolve);
+
+    let dependency = vk::SubpassDependency2::builder()
+        .src_subpass(dependency.src_subpass)
+        .dst_subpass(dependency.dst_subpass)
+        .src_stage_mask(dependency.src_stage_mask)
+        .dst_stage_mask(dependency.dst_stage_mask)
+        .src_access_mask(dependency.src_access_mask)
+        .dst_access_mask(dependency.dst_access_mask)
+        .dependency_flags(dependency.dependency_flags);
+
+    let correlated_view_masks = [view_mask];
+    unsafe {
+        vulkan_context.device.create_render_pass2(
+            &vk::RenderPassCreateInfo2::builder()
                 .attachments(&attachments)
                 .subpasses(&[*subpass])
                 .dependencies(&[*dependency])
-                .push_next(&mut multiview),
+                .correlated_view_masks(&correlated_view_masks),
             None,
         )
-    }?;
-    println!("..done!");
+    }
+    .map_err(Into::into)
+}
 
-    Ok(render_pass)
+/// PBR pipelines without a base allow derivatives, so that variants of them, eg. for other topologies or cull modes,
+/// can be created from them more cheaply. Pipelines with a base are derived from it. The base must have been created
+/// without one itself: layers' pipelines always are, and are created before any of their variants.
+fn get_pipeline_create_flags(base_pipeline: vk::Pipeline) -> vk::PipelineCreateFlags {
+    if base_pipeline == vk::Pipeline::null() {
+        vk::PipelineCreateFlags::ALLOW_DERIVATIVES
+    } else {
+        vk::PipelineCreateFlags::DERIVATIVE
+    }
+}
+
+/// Alpha-to-coverage only makes sense with MSAA: with a single sample, coverage is all or nothing.
+fn use_alpha_to_coverage(alpha_to_coverage: bool, samples: vk::SampleCountFlags) -> bool {
+    alpha_to_coverage && samples != vk::SampleCountFlags::TYPE_1
+}
+
+/// Sample shading only makes sense with MSAA: with a single sample, every fragment is already shaded once per sample.
+fn use_sample_shading(
+    min_sample_shading: Option<f32>,
+    samples: vk::SampleCountFlags,
+) -> Option<f32> {
+    min_sample_shading.filter(|_| samples != vk::SampleCountFlags::TYPE_1)
+}
+
+/// Clamp `min_sample_shading` to (0, 1]. Anything that isn't above 0 turns sample shading off.
+fn clamp_min_sample_shading(min_sample_shading: Option<f32>) -> Option<f32> {
+    min_sample_shading.filter(|m| *m > 0.).map(|m| m.min(1.))
 }
 
 fn create_pipeline(
     vulkan_context: &VulkanContext,
     pipeline_layout: vk::PipelineLayout,
-    render_area: &vk::Rect2D,
     render_pass: vk::RenderPass,
+    render_layer: RenderLayer,
+    depth_format: vk::Format,
+    topology: vk::PrimitiveTopology,
+    front_face: vk::FrontFace,
+    cull_mode: vk::CullModeFlags,
+    alpha_to_coverage: bool,
+    min_sample_shading: Option<f32>,
+    specialization_constants: &SpecializationConstants,
+    stencil_mode: StencilMode,
+    depth_pass: DepthPass,
+    base_pipeline: vk::Pipeline,
+    custom_shader: Option<CustomShader>,
 ) -> Result<vk::Pipeline> {
-    print!("[HOTHAM_INIT] Creating pipeline..");
+    debug!(
+        target: "HOTHAM_INIT",
+        "Creating pipeline for render layer {:?}, topology {:?}, front face {:?}, cull mode {:?}, alpha to coverage {}, min sample shading {:?}, stencil mode {:?} and depth pass {:?}..",
+        render_layer, topology, front_face, cull_mode, alpha_to_coverage, min_sample_shading, stencil_mode, depth_pass
+    );
     // Build up the state of the pipeline
 
-    // Vertex shader stage
-    let (vertex_shader, vertex_stage) = create_shader(
-        include_bytes!("../../shaders/pbr.vert.spv"),
-        vk::ShaderStageFlags::VERTEX,
-        vulkan_context,
-    )?;
-
-    // Fragment shader stage
-    let (fragment_shader, fragment_stage) = create_shader(
-        include_bytes!("../../shaders/pbr.frag.spv"),
-        vk::ShaderStageFlags::FRAGMENT,
-        vulkan_context,
-    )?;
+    // Vertex and fragment shader stages
+    // A `CustomShader`'s modules belong to the application, so they're left null here to keep them from being
+    // destroyed below: destroying a null module does nothing.
+    let (vertex_shader, vertex_stage, fragment_shader, fragment_stage) = match custom_shader {
+        Some(custom_shader) => (
+            vk::ShaderModule::null(),
+            get_shader_stage(custom_shader.vertex, vk::ShaderStageFlags::VERTEX),
+            vk::ShaderModule::null(),
+            get_shader_stage(custom_shader.fragment, vk::ShaderStageFlags::FRAGMENT),
+        ),
+        None => {
+            let (vertex_shader, vertex_stage) = create_shader(
+                include_bytes!("../../shaders/pbr.vert.spv"),
+                vk::ShaderStageFlags::VERTEX,
+                vulkan_context,
+            )?;
+            let (fragment_shader, fragment_stage) = create_shader(
+                include_bytes!("../../shaders/pbr.frag.spv"),
+                vk::ShaderStageFlags::FRAGMENT,
+                vulkan_context,
+            )?;
+            (vertex_shader, vertex_stage, fragment_shader, fragment_stage)
+        }
+    };
 
-    let stages = [vertex_stage, fragment_stage];
+    // Both stages share the same constants: see `SpecializationConstants`.
+    let specialization_info = specialization_constants.info();
+    let mut stages = [vertex_stage, fragment_stage];
+    if !specialization_constants.is_empty() {
+        for stage in stages.iter_mut() {
+            stage.p_specialization_info = &specialization_info;
+        }
+    }
 
     // Vertex input state
     let vertex_binding_description = vk::VertexInputBindingDescription::builder()
@@ -742,64 +2355,83 @@ fn create_pipeline(
         .vertex_binding_descriptions(&vertex_binding_descriptions);
 
     // Input assembly state
-    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
-
-    // Viewport State
-    let viewport = vk::Viewport {
-        x: 0.0,
-        y: 0.0,
-        width: render_area.extent.width as _,
-        height: render_area.extent.height as _,
-        min_depth: 0.0,
-        max_depth: 1.0,
-    };
-    let viewports = [viewport];
-
-    // Scissors
-    let scissors = [*render_area];
+    let input_assembly_state =
+        vk::PipelineInputAssemblyStateCreateInfo::builder().topology(topology);
 
+    // Viewport state
+    // The viewport and scissor are dynamic, so the pipeline can render at any resolution: see `set_viewport`.
     let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-        .viewports(&viewports)
-        .scissors(&scissors);
+        .viewport_count(1)
+        .scissor_count(1);
 
     // Rasterization state
     let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
         .polygon_mode(vk::PolygonMode::FILL)
-        .cull_mode(vk::CullModeFlags::BACK)
-        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .cull_mode(cull_mode)
+        .front_face(front_face)
         .rasterizer_discard_enable(false)
         .depth_clamp_enable(false)
-        .depth_bias_enable(false)
+        .depth_bias_enable(true)
         .depth_bias_constant_factor(0.0)
         .depth_bias_clamp(0.0)
         .depth_bias_slope_factor(0.0)
         .line_width(1.0);
 
     // Multisample state
+    let samples = vk::SampleCountFlags::TYPE_4;
     let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
-        .rasterization_samples(vk::SampleCountFlags::TYPE_4);
+        .rasterization_samples(samples)
+        .alpha_to_coverage_enable(use_alpha_to_coverage(alpha_to_coverage, samples));
+    let multisample_state = match use_sample_shading(min_sample_shading, samples) {
+        Some(min_sample_shading) => multisample_state
+            .sample_shading_enable(true)
+            .min_sample_shading(min_sample_shading),
+        None => multisample_state,
+    };
+
+    // Stencil state
+    // See `StencilMode`.
+    let (compare_op, write_mask) = match stencil_mode {
+        StencilMode::Write => (vk::CompareOp::ALWAYS, 0xFF),
+        StencilMode::Test => (vk::CompareOp::EQUAL, 0x00),
+    };
+    let stencil_op_state = vk::StencilOpState::builder()
+        .fail_op(vk::StencilOp::KEEP)
+        .pass_op(vk::StencilOp::REPLACE)
+        .depth_fail_op(vk::StencilOp::KEEP)
+        .compare_op(compare_op)
+        .compare_mask(0xFF)
+        .write_mask(write_mask)
+        .reference(0)
+        .build();
 
     // Depth stencil state
+    // Transparent objects are tested against, but don't write to, the depth buffer. Overlays ignore it entirely.
+    // Transparent objects with a depth prepass write their depth first, then draw only their nearest surface.
+    let (depth_write_enable, depth_compare_op) = match depth_pass {
+        DepthPass::Single => (
+            !render_layer.is_overlay() && !render_layer.is_transparent(),
+            vk::CompareOp::LESS,
+        ),
+        DepthPass::Prepass => (true, vk::CompareOp::LESS),
+        DepthPass::Equal => (false, vk::CompareOp::EQUAL),
+    };
     let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_test_enable(!render_layer.is_overlay())
+        .depth_write_enable(depth_write_enable)
+        .depth_compare_op(depth_compare_op)
         .depth_bounds_test_enable(false)
         .min_depth_bounds(0.0)
         .max_depth_bounds(1.0)
-        .stencil_test_enable(false);
+        .stencil_test_enable(has_stencil_component(depth_format))
+        .front(stencil_op_state)
+        .back(stencil_op_state);
 
     // Color blend state
-    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
-        .color_write_mask(
-            vk::ColorComponentFlags::R
-                | vk::ColorComponentFlags::G
-                | vk::ColorComponentFlags::B
-                | vk::ColorComponentFlags::A,
-        )
-        .blend_enable(false)
-        .build();
+    let mut color_blend_attachment = get_color_blend_attachment(render_layer);
+    if depth_pass == DepthPass::Prepass {
+        color_blend_attachment.color_write_mask = vk::ColorComponentFlags::empty();
+    }
 
     let color_blend_attachments = [color_blend_attachment];
 
@@ -807,6 +2439,20 @@ fn create_pipeline(
         .logic_op_enable(false)
         .attachments(&color_blend_attachments);
 
+    // Dynamic state
+    // The depth bias is set per object, from its `DepthBias` component.
+    let mut dynamic_states = vec![
+        vk::DynamicState::VIEWPORT,
+        vk::DynamicState::SCISSOR,
+        vk::DynamicState::STENCIL_REFERENCE,
+        vk::DynamicState::DEPTH_BIAS,
+    ];
+    if has_dynamic_line_width(vulkan_context, topology) {
+        dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+    }
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
     let create_info = vk::GraphicsPipelineCreateInfo::builder()
         .stages(&stages)
         .vertex_input_state(&vertex_input_state)
@@ -816,13 +2462,18 @@ fn create_pipeline(
         .multisample_state(&multisample_state)
         .depth_stencil_state(&depth_stencil_state)
         .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
         .layout(pipeline_layout)
         .render_pass(render_pass)
         .subpass(0)
+        .flags(get_pipeline_create_flags(base_pipeline))
+        .base_pipeline_handle(base_pipeline)
+        .base_pipeline_index(-1)
         .build();
 
     let create_infos = [create_info];
 
+    let start = Instant::now();
     let pipelines = unsafe {
         vulkan_context.device.create_graphics_pipelines(
             vk::PipelineCache::null(),
@@ -831,6 +2482,16 @@ fn create_pipeline(
         )
     }
     .map_err(|(_, r)| r)?;
+    debug!(
+        target: "HOTHAM_INIT",
+        "..done in {:?}{}",
+        start.elapsed(),
+        if base_pipeline == vk::Pipeline::null() {
+            ""
+        } else {
+            ", derived from a base pipeline"
+        }
+    );
 
     unsafe {
         vulkan_context
@@ -842,49 +2503,1060 @@ fn create_pipeline(
     }
 
     let primary_pipeline = pipelines[0];
+    vulkan_context.set_debug_name(
+        vk::ObjectType::PIPELINE,
+        primary_pipeline.as_raw(),
+        &format!(
+            "PBR Pipeline ({:?}, {:?}, {:?}, {:?}, {:?})",
+            render_layer, topology, front_face, cull_mode, stencil_mode
+        ),
+    )?;
 
     Ok(primary_pipeline)
 }
 
-pub fn create_shader(
-    shader_code: &[u8],
-    stage: vk::ShaderStageFlags,
+/// Creates the pipeline used to draw outlines around highlighted objects.
+/// Draws each mesh extruded along its normals, but only where the stencil buffer hasn't been written to by the object itself.
+fn create_outline_pipeline(
     vulkan_context: &VulkanContext,
-) -> Result<(vk::ShaderModule, vk::PipelineShaderStageCreateInfo)> {
-    let mut cursor = Cursor::new(shader_code);
-    let shader_code = ash::util::read_spv(&mut cursor)?;
-    let main = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
-    let create_info = vk::ShaderModuleCreateInfo::builder().code(&shader_code);
-    let shader_module = unsafe {
-        vulkan_context
-            .device
-            .create_shader_module(&create_info, None)
-    }?;
-    let shader_stage = vk::PipelineShaderStageCreateInfo::builder()
-        .stage(stage)
-        .name(main.clone())
-        .module(shader_module)
-        .build();
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    depth_format: vk::Format,
+) -> Result<vk::Pipeline> {
+    debug!(target: "HOTHAM_INIT", "Creating outline pipeline..");
 
-    Ok((shader_module, shader_stage))
-}
+    // Vertex shader stage
+    let (vertex_shader, vertex_stage) = create_shader(
+        include_bytes!("../../shaders/outline.vert.spv"),
+        vk::ShaderStageFlags::VERTEX,
+        vulkan_context,
+    )?;
 
-fn create_pipeline_layout(
-    vulkan_context: &VulkanContext,
-    set_layouts: &[vk::DescriptorSetLayout],
-) -> Result<vk::PipelineLayout> {
-    let push_constant_ranges = [vk::PushConstantRange {
-        stage_flags: vk::ShaderStageFlags::FRAGMENT,
-        offset: 0,
-        size: size_of::<Material>() as _,
-    }];
-    let create_info = &vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(set_layouts)
-        .push_constant_ranges(&push_constant_ranges);
-    unsafe {
-        vulkan_context
-            .device
-            .create_pipeline_layout(&create_info, None)
-    }
-    .map_err(|e| e.into())
+    // Fragment shader stage
+    let (fragment_shader, fragment_stage) = create_shader(
+        include_bytes!("../../shaders/outline.frag.spv"),
+        vk::ShaderStageFlags::FRAGMENT,
+        vulkan_context,
+    )?;
+
+    let stages = [vertex_stage, fragment_stage];
+
+    // Vertex input state
+    let vertex_binding_description = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<Vertex>() as _)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build();
+    let vertex_binding_descriptions = [vertex_binding_description];
+    let vertex_attribute_descriptions = Vertex::attribute_descriptions();
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions)
+        .vertex_binding_descriptions(&vertex_binding_descriptions);
+
+    // Input assembly state
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+    // Viewport state
+    // The viewport and scissor are dynamic, so the pipeline can render at any resolution: see `set_viewport`.
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    // Rasterization state
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::BACK)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .rasterizer_discard_enable(false)
+        .depth_clamp_enable(false)
+        .depth_bias_enable(false)
+        .line_width(1.0);
+
+    // Multisample state
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_4);
+
+    // Stencil state
+    // Only draw where the highlighted object *didn't* write to the stencil buffer. Other bits may hold a `StencilTag`.
+    let stencil_op_state = vk::StencilOpState::builder()
+        .fail_op(vk::StencilOp::KEEP)
+        .pass_op(vk::StencilOp::KEEP)
+        .depth_fail_op(vk::StencilOp::KEEP)
+        .compare_op(vk::CompareOp::NOT_EQUAL)
+        .compare_mask(HIGHLIGHT_STENCIL_MASK)
+        .write_mask(0x00)
+        .reference(1)
+        .build();
+
+    // Depth stencil state
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(has_stencil_component(depth_format))
+        .front(stencil_op_state)
+        .back(stencil_op_state);
+
+    // Color blend state
+    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(false)
+        .build();
+
+    let color_blend_attachments = [color_blend_attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&color_blend_attachments);
+
+    // Dynamic state
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    let create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+
+    let create_infos = [create_info];
+
+    let pipelines = unsafe {
+        vulkan_context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &create_infos,
+            None,
+        )
+    }
+    .map_err(|(_, r)| r)?;
+
+    unsafe {
+        vulkan_context
+            .device
+            .destroy_shader_module(vertex_shader, None);
+        vulkan_context
+            .device
+            .destroy_shader_module(fragment_shader, None);
+    }
+    vulkan_context.set_debug_name(
+        vk::ObjectType::PIPELINE,
+        pipelines[0].as_raw(),
+        "Outline Pipeline",
+    )?;
+    debug!(target: "HOTHAM_INIT", "..done!");
+
+    Ok(pipelines[0])
+}
+
+/// Creates a pipeline used to draw a `Mirror`, for `stage`.
+/// The mirror's mesh is drawn with the outline shaders, unextruded, so the pipeline uses the outline pipeline layout.
+fn create_mirror_pipeline(
+    vulkan_context: &VulkanContext,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    depth_format: vk::Format,
+    stage: MirrorStage,
+) -> Result<vk::Pipeline> {
+    debug!(target: "HOTHAM_INIT", "Creating mirror {:?} pipeline..", stage);
+
+    // Vertex shader stage
+    let (vertex_shader, vertex_stage) = create_shader(
+        include_bytes!("../../shaders/outline.vert.spv"),
+        vk::ShaderStageFlags::VERTEX,
+        vulkan_context,
+    )?;
+
+    // Fragment shader stage
+    let (fragment_shader, fragment_stage) = create_shader(
+        include_bytes!("../../shaders/outline.frag.spv"),
+        vk::ShaderStageFlags::FRAGMENT,
+        vulkan_context,
+    )?;
+
+    let stages = [vertex_stage, fragment_stage];
+
+    // Vertex input state
+    let vertex_binding_description = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<Vertex>() as _)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build();
+    let vertex_binding_descriptions = [vertex_binding_description];
+    let vertex_attribute_descriptions = Vertex::attribute_descriptions();
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions)
+        .vertex_binding_descriptions(&vertex_binding_descriptions);
+
+    // Input assembly state
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+    // Viewport state
+    // The viewport and scissor are dynamic, so the pipeline can render at any resolution: see `set_viewport`.
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    // Rasterization state
+    // Mirrors are one sided: nothing is reflected when they're seen from behind.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::BACK)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .rasterizer_discard_enable(false)
+        .depth_clamp_enable(false)
+        .depth_bias_enable(false)
+        .line_width(1.0);
+
+    // Multisample state
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_4);
+
+    // Stencil and depth state
+    // The mask marks wherever the mirror is visible. The composite then only draws there, replacing the reflection's
+    // depth with the mirror's own.
+    let (stencil_compare_op, stencil_pass_op, depth_compare_op) = match stage {
+        MirrorStage::Mask => (
+            vk::CompareOp::ALWAYS,
+            vk::StencilOp::REPLACE,
+            vk::CompareOp::LESS,
+        ),
+        MirrorStage::Composite => (
+            vk::CompareOp::EQUAL,
+            vk::StencilOp::KEEP,
+            vk::CompareOp::ALWAYS,
+        ),
+    };
+    let stencil_op_state = vk::StencilOpState::builder()
+        .fail_op(vk::StencilOp::KEEP)
+        .pass_op(stencil_pass_op)
+        .depth_fail_op(vk::StencilOp::KEEP)
+        .compare_op(stencil_compare_op)
+        .compare_mask(0xFF)
+        .write_mask(0xFF)
+        .reference(MIRROR_STENCIL_REFERENCE)
+        .build();
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(stage == MirrorStage::Composite)
+        .depth_compare_op(depth_compare_op)
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(has_stencil_component(depth_format))
+        .front(stencil_op_state)
+        .back(stencil_op_state);
+
+    // Color blend state
+    // The mask doesn't draw anything. The composite multiplies the reflection by the mirror's tint.
+    let color_write_mask = match stage {
+        MirrorStage::Mask => vk::ColorComponentFlags::empty(),
+        MirrorStage::Composite => {
+            vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B
+        }
+    };
+    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(color_write_mask)
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::ZERO)
+        .dst_color_blend_factor(vk::BlendFactor::SRC_COLOR)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build();
+
+    let color_blend_attachments = [color_blend_attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&color_blend_attachments);
+
+    // Dynamic state
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    let create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+
+    let create_infos = [create_info];
+
+    let pipelines = unsafe {
+        vulkan_context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &create_infos,
+            None,
+        )
+    }
+    .map_err(|(_, r)| r)?;
+
+    unsafe {
+        vulkan_context
+            .device
+            .destroy_shader_module(vertex_shader, None);
+        vulkan_context
+            .device
+            .destroy_shader_module(fragment_shader, None);
+    }
+    vulkan_context.set_debug_name(
+        vk::ObjectType::PIPELINE,
+        pipelines[0].as_raw(),
+        &format!("Mirror {:?} Pipeline", stage),
+    )?;
+    debug!(target: "HOTHAM_INIT", "..done!");
+
+    Ok(pipelines[0])
+}
+
+/// Creates the pipeline used to draw a `WireframeOverlay`.
+/// The mesh is drawn with the outline shaders, unextruded, with its triangles rasterized as lines.
+fn create_wireframe_pipeline(
+    vulkan_context: &VulkanContext,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+) -> Result<vk::Pipeline> {
+    debug!(target: "HOTHAM_INIT", "Creating wireframe pipeline..");
+
+    // Vertex shader stage
+    let (vertex_shader, vertex_stage) = create_shader(
+        include_bytes!("../../shaders/outline.vert.spv"),
+        vk::ShaderStageFlags::VERTEX,
+        vulkan_context,
+    )?;
+
+    // Fragment shader stage
+    let (fragment_shader, fragment_stage) = create_shader(
+        include_bytes!("../../shaders/outline.frag.spv"),
+        vk::ShaderStageFlags::FRAGMENT,
+        vulkan_context,
+    )?;
+
+    let stages = [vertex_stage, fragment_stage];
+
+    // Vertex input state
+    let vertex_binding_description = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<Vertex>() as _)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build();
+    let vertex_binding_descriptions = [vertex_binding_description];
+    let vertex_attribute_descriptions = Vertex::attribute_descriptions();
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions)
+        .vertex_binding_descriptions(&vertex_binding_descriptions);
+
+    // Input assembly state
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+    // Viewport state
+    // The viewport and scissor are dynamic, so the pipeline can render at any resolution: see `set_viewport`.
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    // Rasterization state
+    // Nothing is culled, as hidden edges fail the depth test anyway and mirrored meshes have the opposite winding.
+    // The edges are pulled towards the camera so they aren't hidden by the filled triangles they were drawn with.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::LINE)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .rasterizer_discard_enable(false)
+        .depth_clamp_enable(false)
+        .depth_bias_enable(true)
+        .depth_bias_constant_factor(-1.0)
+        .depth_bias_slope_factor(-1.0)
+        .line_width(1.0);
+
+    // Multisample state
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_4);
+
+    // Depth stencil state
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .depth_bounds_test_enable(false)
+        .min_depth_bounds(0.0)
+        .max_depth_bounds(1.0)
+        .stencil_test_enable(false);
+
+    // Color blend state
+    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(false)
+        .build();
+
+    let color_blend_attachments = [color_blend_attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&color_blend_attachments);
+
+    // Dynamic state
+    let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    if has_dynamic_line_width(vulkan_context, vk::PrimitiveTopology::LINE_LIST) {
+        dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+    }
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    let create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+
+    let create_infos = [create_info];
+
+    let pipelines = unsafe {
+        vulkan_context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &create_infos,
+            None,
+        )
+    }
+    .map_err(|(_, r)| r)?;
+
+    unsafe {
+        vulkan_context
+            .device
+            .destroy_shader_module(vertex_shader, None);
+        vulkan_context
+            .device
+            .destroy_shader_module(fragment_shader, None);
+    }
+    vulkan_context.set_debug_name(
+        vk::ObjectType::PIPELINE,
+        pipelines[0].as_raw(),
+        "Wireframe Pipeline",
+    )?;
+    debug!(target: "HOTHAM_INIT", "..done!");
+
+    Ok(pipelines[0])
+}
+
+pub fn create_shader(
+    shader_code: &[u8],
+    stage: vk::ShaderStageFlags,
+    vulkan_context: &VulkanContext,
+) -> Result<(vk::ShaderModule, vk::PipelineShaderStageCreateInfo)> {
+    let mut cursor = Cursor::new(shader_code);
+    let shader_code = ash::util::read_spv(&mut cursor)?;
+    let create_info = vk::ShaderModuleCreateInfo::builder().code(&shader_code);
+    let shader_module = unsafe {
+        vulkan_context
+            .device
+            .create_shader_module(&create_info, None)
+    }?;
+    Ok((shader_module, get_shader_stage(shader_module, stage)))
+}
+
+/// A shader stage running the `main` entry point of `shader_module`
+fn get_shader_stage(
+    shader_module: vk::ShaderModule,
+    stage: vk::ShaderStageFlags,
+) -> vk::PipelineShaderStageCreateInfo {
+    let main = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+    vk::PipelineShaderStageCreateInfo::builder()
+        .stage(stage)
+        .name(main)
+        .module(shader_module)
+        .build()
+}
+
+fn create_pipeline_layout(
+    vulkan_context: &VulkanContext,
+    set_layouts: &[vk::DescriptorSetLayout],
+) -> Result<vk::PipelineLayout> {
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Material>() as _,
+    }];
+    let create_info = &vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe {
+        vulkan_context
+            .device
+            .create_pipeline_layout(&create_info, None)
+    }
+    .map_err(|e| e.into())
+}
+
+fn create_outline_pipeline_layout(
+    vulkan_context: &VulkanContext,
+    set_layouts: &[vk::DescriptorSetLayout],
+) -> Result<vk::PipelineLayout> {
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Highlight>() as _,
+    }];
+    let create_info = &vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe {
+        vulkan_context
+            .device
+            .create_pipeline_layout(&create_info, None)
+    }
+    .map_err(|e| e.into())
+}
+
+/// Creates the pipeline used to draw the comfort vignette over the whole view.
+fn create_vignette_pipeline(
+    vulkan_context: &VulkanContext,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+) -> Result<vk::Pipeline> {
+    debug!(target: "HOTHAM_INIT", "Creating vignette pipeline..");
+
+    // Vertex shader stage
+    let (vertex_shader, vertex_stage) = create_shader(
+        include_bytes!("../../shaders/vignette.vert.spv"),
+        vk::ShaderStageFlags::VERTEX,
+        vulkan_context,
+    )?;
+
+    // Fragment shader stage
+    let (fragment_shader, fragment_stage) = create_shader(
+        include_bytes!("../../shaders/vignette.frag.spv"),
+        vk::ShaderStageFlags::FRAGMENT,
+        vulkan_context,
+    )?;
+
+    let stages = [vertex_stage, fragment_stage];
+
+    // Vertex input state - the vertices are generated in the vertex shader
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    // Input assembly state
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+    // Viewport state
+    // The viewport and scissor are dynamic, so the pipeline can render at any resolution: see `set_viewport`.
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    // Rasterization state
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .rasterizer_discard_enable(false)
+        .depth_clamp_enable(false)
+        .depth_bias_enable(false)
+        .line_width(1.0);
+
+    // Multisample state
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_4);
+
+    // Depth stencil state
+    // The vignette is drawn over everything.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color blend state
+    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build();
+
+    let color_blend_attachments = [color_blend_attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&color_blend_attachments);
+
+    // Dynamic state
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    let create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+
+    let create_infos = [create_info];
+
+    let pipelines = unsafe {
+        vulkan_context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &create_infos,
+            None,
+        )
+    }
+    .map_err(|(_, r)| r)?;
+
+    unsafe {
+        vulkan_context
+            .device
+            .destroy_shader_module(vertex_shader, None);
+        vulkan_context
+            .device
+            .destroy_shader_module(fragment_shader, None);
+    }
+    vulkan_context.set_debug_name(
+        vk::ObjectType::PIPELINE,
+        pipelines[0].as_raw(),
+        "Vignette Pipeline",
+    )?;
+    debug!(target: "HOTHAM_INIT", "..done!");
+
+    Ok(pipelines[0])
+}
+
+fn create_vignette_pipeline_layout(vulkan_context: &VulkanContext) -> Result<vk::PipelineLayout> {
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<Vignette>() as _,
+    }];
+    let create_info =
+        &vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&push_constant_ranges);
+    unsafe {
+        vulkan_context
+            .device
+            .create_pipeline_layout(&create_info, None)
+    }
+    .map_err(|e| e.into())
+}
+
+/// Creates the pipeline used to draw debug lines, on top of everything else.
+fn create_debug_line_pipeline(
+    vulkan_context: &VulkanContext,
+    pipeline_layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+) -> Result<vk::Pipeline> {
+    debug!(target: "HOTHAM_INIT", "Creating debug line pipeline..");
+
+    // Vertex shader stage
+    let (vertex_shader, vertex_stage) = create_shader(
+        include_bytes!("../../shaders/debug_line.vert.spv"),
+        vk::ShaderStageFlags::VERTEX,
+        vulkan_context,
+    )?;
+
+    // Fragment shader stage
+    let (fragment_shader, fragment_stage) = create_shader(
+        include_bytes!("../../shaders/debug_line.frag.spv"),
+        vk::ShaderStageFlags::FRAGMENT,
+        vulkan_context,
+    )?;
+
+    let stages = [vertex_stage, fragment_stage];
+
+    // Vertex input state
+    let vertex_binding_description = vk::VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(size_of::<DebugLineVertex>() as _)
+        .input_rate(vk::VertexInputRate::VERTEX)
+        .build();
+    let vertex_binding_descriptions = [vertex_binding_description];
+    let vertex_attribute_descriptions = DebugLineVertex::attribute_descriptions();
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_attribute_descriptions(&vertex_attribute_descriptions)
+        .vertex_binding_descriptions(&vertex_binding_descriptions);
+
+    // Input assembly state
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::LINE_LIST);
+
+    // Viewport state
+    // The viewport and scissor are dynamic, so the pipeline can render at any resolution: see `set_viewport`.
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    // Rasterization state
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .rasterizer_discard_enable(false)
+        .depth_clamp_enable(false)
+        .depth_bias_enable(false)
+        .line_width(1.0);
+
+    // Multisample state
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_4);
+
+    // Depth stencil state
+    // Debug lines are usually inside a mesh (eg. a skeleton), so they're drawn over everything.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Color blend state
+    let color_blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        )
+        .blend_enable(false)
+        .build();
+
+    let color_blend_attachments = [color_blend_attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&color_blend_attachments);
+
+    // Dynamic state
+    let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    if has_dynamic_line_width(vulkan_context, vk::PrimitiveTopology::LINE_LIST) {
+        dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+    }
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    let create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+
+    let create_infos = [create_info];
+
+    let pipelines = unsafe {
+        vulkan_context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &create_infos,
+            None,
+        )
+    }
+    .map_err(|(_, r)| r)?;
+
+    unsafe {
+        vulkan_context
+            .device
+            .destroy_shader_module(vertex_shader, None);
+        vulkan_context
+            .device
+            .destroy_shader_module(fragment_shader, None);
+    }
+    vulkan_context.set_debug_name(
+        vk::ObjectType::PIPELINE,
+        pipelines[0].as_raw(),
+        "Debug Line Pipeline",
+    )?;
+    debug!(target: "HOTHAM_INIT", "..done!");
+
+    Ok(pipelines[0])
+}
+
+fn create_pipeline_layout_without_push_constants(
+    vulkan_context: &VulkanContext,
+    set_layouts: &[vk::DescriptorSetLayout],
+) -> Result<vk::PipelineLayout> {
+    let create_info = &vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    unsafe {
+        vulkan_context
+            .device
+            .create_pipeline_layout(&create_info, None)
+    }
+    .map_err(|e| e.into())
+}
+
+/// Is this result fatal to the session? Timeouts and out of memory errors are transient, so the frame can
+/// just be dropped. Anything else - in particular `DEVICE_LOST` - means the device can no longer be used.
+fn is_fatal(result: vk::Result) -> bool {
+    !matches!(
+        result,
+        vk::Result::TIMEOUT
+            | vk::Result::NOT_READY
+            | vk::Result::ERROR_OUT_OF_HOST_MEMORY
+            | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_is_fatal() {
+        assert!(!is_fatal(vk::Result::TIMEOUT));
+        assert!(!is_fatal(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY));
+        assert!(is_fatal(vk::Result::ERROR_DEVICE_LOST));
+        assert!(is_fatal(vk::Result::ERROR_SURFACE_LOST_KHR));
+    }
+
+    #[test]
+    pub fn test_pipeline_derivatives() {
+        // Pipelines without a base can be derived from..
+        assert_eq!(
+            get_pipeline_create_flags(vk::Pipeline::null()),
+            vk::PipelineCreateFlags::ALLOW_DERIVATIVES
+        );
+        // ..and variants are derived from them.
+        assert_eq!(
+            get_pipeline_create_flags(vk::Pipeline::from_raw(1)),
+            vk::PipelineCreateFlags::DERIVATIVE
+        );
+    }
+
+    #[test]
+    pub fn test_additive_layers_add_colour() {
+        let additive = get_color_blend_attachment(RenderLayer::ADDITIVE);
+        assert_eq!(additive.blend_enable, vk::TRUE);
+        assert_eq!(additive.src_color_blend_factor, vk::BlendFactor::ONE);
+        assert_eq!(additive.dst_color_blend_factor, vk::BlendFactor::ONE);
+        assert_eq!(additive.dst_alpha_blend_factor, vk::BlendFactor::ONE);
+
+        let transparent = get_color_blend_attachment(RenderLayer::TRANSPARENT);
+        assert_eq!(
+            transparent.src_color_blend_factor,
+            vk::BlendFactor::SRC_ALPHA
+        );
+        assert_eq!(
+            transparent.dst_color_blend_factor,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+        );
+
+        let opaque = get_color_blend_attachment(RenderLayer::OPAQUE);
+        assert_eq!(opaque.blend_enable, vk::FALSE);
+
+        // Additive layers are drawn after the transparent layers they're a part of, and before overlays.
+        assert!(RenderLayer::ADDITIVE.is_transparent());
+        assert!(!RenderLayer::TRANSPARENT.is_additive());
+        assert!(!RenderLayer::OVERLAY.is_additive());
+    }
+
+    #[test]
+    pub fn test_viewport_with_image_rect_offset() {
+        // The right half of an image shared by both eyes.
+        let resolution = vk::Extent2D {
+            width: 2048,
+            height: 1024,
+        };
+        let image_rect = vk::Rect2D {
+            offset: vk::Offset2D { x: 1024, y: 0 },
+            extent: vk::Extent2D {
+                width: 1024,
+                height: 1024,
+            },
+        };
+        assert!(image_rect_fits(&image_rect, resolution));
+
+        let viewport = get_viewport(&image_rect);
+        assert_eq!(viewport.x, 1024.);
+        assert_eq!(viewport.y, 0.);
+        assert_eq!(viewport.width, 1024.);
+        assert_eq!(viewport.height, 1024.);
+
+        // Running off the edge of the image isn't allowed.
+        let image_rect = vk::Rect2D {
+            offset: vk::Offset2D { x: 1025, y: 0 },
+            ..image_rect
+        };
+        assert!(!image_rect_fits(&image_rect, resolution));
+    }
+
+    #[test]
+    pub fn test_clamp_line_width() {
+        assert_eq!(clamp_line_width(2.0, Some([1.0, 8.0])), 2.0);
+        assert_eq!(clamp_line_width(16.0, Some([1.0, 8.0])), 8.0);
+        assert_eq!(clamp_line_width(0.5, Some([1.0, 8.0])), 1.0);
+
+        // Without wideLines, lines can only be 1.0 wide.
+        assert_eq!(clamp_line_width(2.0, None), 1.0);
+    }
+
+    #[test]
+    pub fn test_alpha_to_coverage_needs_msaa() {
+        assert!(use_alpha_to_coverage(true, vk::SampleCountFlags::TYPE_4));
+        assert!(!use_alpha_to_coverage(false, vk::SampleCountFlags::TYPE_4));
+        assert!(!use_alpha_to_coverage(true, vk::SampleCountFlags::TYPE_1));
+    }
+
+    #[test]
+    pub fn test_min_sample_shading() {
+        assert_eq!(clamp_min_sample_shading(None), None);
+        assert_eq!(clamp_min_sample_shading(Some(0.5)), Some(0.5));
+        assert_eq!(clamp_min_sample_shading(Some(2.)), Some(1.));
+        assert_eq!(clamp_min_sample_shading(Some(0.)), None);
+        assert_eq!(clamp_min_sample_shading(Some(f32::NAN)), None);
+
+        // Without MSAA, every sample is already shaded.
+        assert_eq!(
+            use_sample_shading(Some(0.5), vk::SampleCountFlags::TYPE_4),
+            Some(0.5)
+        );
+        assert_eq!(
+            use_sample_shading(Some(0.5), vk::SampleCountFlags::TYPE_1),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_load_doesnt_record_clear_values() {
+        assert_eq!(get_clear_values(LoadOps::default()).len(), 2);
+
+        let load_ops = LoadOps {
+            colour: vk::AttachmentLoadOp::LOAD,
+            depth: vk::AttachmentLoadOp::LOAD,
+        };
+        assert!(get_clear_values(load_ops).is_empty());
+
+        // Only the colour attachment is cleared, so the depth clear value can be left out.
+        let load_ops = LoadOps {
+            colour: vk::AttachmentLoadOp::CLEAR,
+            depth: vk::AttachmentLoadOp::DONT_CARE,
+        };
+        assert_eq!(get_clear_values(load_ops).len(), 1);
+
+        // Loaded attachments must keep the layout the last render pass left them in.
+        assert_eq!(
+            get_initial_layout(
+                vk::AttachmentLoadOp::LOAD,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            ),
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            get_initial_layout(
+                vk::AttachmentLoadOp::CLEAR,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            ),
+            vk::ImageLayout::UNDEFINED
+        );
+    }
+
+    #[test]
+    pub fn test_clear_depth_only_loads_colour() {
+        let load_ops = LoadOps::CLEAR_DEPTH;
+
+        // The colour attachment keeps what was drawn before..
+        assert_eq!(load_ops.colour, vk::AttachmentLoadOp::LOAD);
+        assert_eq!(
+            get_initial_layout(load_ops.colour, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+
+        // ..while depth is cleared, which needs a clear value for each attachment up to it.
+        assert_eq!(
+            get_initial_layout(
+                load_ops.depth,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            ),
+            vk::ImageLayout::UNDEFINED
+        );
+        assert_eq!(get_clear_values(load_ops).len(), 2);
+
+        // And the other way around.
+        let load_ops = LoadOps::CLEAR_COLOUR;
+        assert_eq!(
+            get_initial_layout(
+                load_ops.depth,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            ),
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(get_clear_values(load_ops).len(), 1);
+    }
+
+    #[test]
+    pub fn test_attachments_are_cleared_until_rendered() {
+        let load_ops = LoadOps::CLEAR_DEPTH;
+        assert_eq!(get_load_ops(load_ops, false), LoadOps::default());
+        assert_eq!(get_load_ops(load_ops, true), load_ops);
+    }
 }
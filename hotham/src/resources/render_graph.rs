@@ -0,0 +1,474 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::resources::vulkan_context::get_aspect_mask;
+
+/// Handle to an attachment added to a `RenderGraph`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AttachmentHandle(usize);
+
+/// Handle to a pass added to a `RenderGraph`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PassHandle(usize);
+
+/// An image that passes render into or read from
+#[derive(Debug, Clone)]
+struct Attachment {
+    name: String,
+    image: vk::Image,
+    format: vk::Format,
+}
+
+impl Attachment {
+    fn is_depth(&self) -> bool {
+        get_aspect_mask(self.format).contains(vk::ImageAspectFlags::DEPTH)
+    }
+}
+
+/// What a pass does with its attachments, which decides the stages and layouts they're used in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PassKind {
+    /// Draws into its attachments in a Vulkan render pass
+    Graphics,
+    /// Copies or blits the attachments it reads into the attachments it writes, outside of a render pass
+    Transfer,
+}
+
+/// A pass, and the attachments it reads from and writes to
+#[derive(Debug, Clone)]
+struct Pass {
+    name: String,
+    kind: PassKind,
+    reads: Vec<AttachmentHandle>,
+    writes: Vec<AttachmentHandle>,
+}
+
+/// A barrier that must be recorded before a pass, so it sees the results of the passes before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttachmentBarrier {
+    pub attachment: AttachmentHandle,
+    pub src_stage_mask: vk::PipelineStageFlags,
+    pub dst_stage_mask: vk::PipelineStageFlags,
+    pub src_access_mask: vk::AccessFlags,
+    pub dst_access_mask: vk::AccessFlags,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+}
+
+/// How a pass uses an attachment
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Usage {
+    stage_mask: vk::PipelineStageFlags,
+    access_mask: vk::AccessFlags,
+    layout: vk::ImageLayout,
+    writes: bool,
+}
+
+/// A lightweight render graph. Passes declare which attachments they read and write, and the graph works out the
+/// order to run them in and the barriers needed between them, so new passes can be added without hand-ordering them.
+///
+/// Passes that write an attachment run before the passes that only read it. Passes that write the same attachment
+/// run in the order they were added. Layout transitions within a pass are still handled by its Vulkan render pass.
+/// Transfer passes don't have one, so the graph transitions the attachments they write the first time they're used,
+/// discarding their contents.
+///
+/// Basic usage:
+/// ```ignore
+/// let shadow_map = render_graph.add_attachment("Shadow Map", shadow_image, depth_format);
+/// let shadow_pass = render_graph.add_pass("Shadow", &[], &[shadow_map]);
+/// let main_pass = render_graph.add_pass("Main", &[shadow_map], &[colour, depth]);
+/// render_graph.compile()?;
+///
+/// render_graph.record_barriers(device, command_buffer, shadow_pass);
+/// // ..record the shadow pass..
+/// render_graph.record_barriers(device, command_buffer, main_pass);
+/// // ..record the main pass..
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RenderGraph {
+    attachments: Vec<Attachment>,
+    passes: Vec<Pass>,
+    order: Vec<PassHandle>,
+    barriers: Vec<Vec<AttachmentBarrier>>,
+}
+
+impl RenderGraph {
+    /// Add an attachment. `format` decides whether it's used as a colour or depth attachment.
+    pub fn add_attachment(
+        &mut self,
+        name: &str,
+        image: vk::Image,
+        format: vk::Format,
+    ) -> AttachmentHandle {
+        self.attachments.push(Attachment {
+            name: name.to_string(),
+            image,
+            format,
+        });
+        AttachmentHandle(self.attachments.len() - 1)
+    }
+
+    /// Change the image behind `attachment`, eg. to this frame's swapchain image. The graph doesn't need to be
+    /// compiled again.
+    pub fn set_attachment_image(&mut self, attachment: AttachmentHandle, image: vk::Image) {
+        self.attachments[attachment.0].image = image;
+    }
+
+    /// Add a pass that samples `reads` in its shaders and renders into `writes`.
+    /// The graph must be compiled again before it's used.
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[AttachmentHandle],
+        writes: &[AttachmentHandle],
+    ) -> PassHandle {
+        self.push_pass(name, PassKind::Graphics, reads, writes)
+    }
+
+    /// Add a pass that copies or blits `reads` into `writes` with transfer commands.
+    /// The graph must be compiled again before it's used.
+    pub fn add_transfer_pass(
+        &mut self,
+        name: &str,
+        reads: &[AttachmentHandle],
+        writes: &[AttachmentHandle],
+    ) -> PassHandle {
+        self.push_pass(name, PassKind::Transfer, reads, writes)
+    }
+
+    fn push_pass(
+        &mut self,
+        name: &str,
+        kind: PassKind,
+        reads: &[AttachmentHandle],
+        writes: &[AttachmentHandle],
+    ) -> PassHandle {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            kind,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        });
+        PassHandle(self.passes.len() - 1)
+    }
+
+    /// Order the passes and work out the barriers needed between them.
+    /// Fails if the passes depend on each other in a cycle.
+    pub fn compile(&mut self) -> Result<()> {
+        self.order = self.get_order()?;
+        self.barriers = self.get_barriers(&self.order);
+        Ok(())
+    }
+
+    /// The passes in the order they should be recorded. Empty until the graph has been compiled.
+    pub fn order(&self) -> &[PassHandle] {
+        &self.order
+    }
+
+    /// The name of `attachment`
+    pub fn attachment_name(&self, attachment: AttachmentHandle) -> &str {
+        &self.attachments[attachment.0].name
+    }
+
+    /// The name of `pass`
+    pub fn pass_name(&self, pass: PassHandle) -> &str {
+        &self.passes[pass.0].name
+    }
+
+    /// The barriers that must be recorded before `pass`
+    pub fn barriers(&self, pass: PassHandle) -> &[AttachmentBarrier] {
+        self.barriers.get(pass.0).map_or(&[], |b| b.as_slice())
+    }
+
+    /// Record the barriers needed before `pass`. Must be called outside of a render pass.
+    pub fn record_barriers(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        pass: PassHandle,
+    ) {
+        for barrier in self.barriers(pass) {
+            let attachment = &self.attachments[barrier.attachment.0];
+            let image_barrier = vk::ImageMemoryBarrier::builder()
+                .image(attachment.image)
+                .src_access_mask(barrier.src_access_mask)
+                .dst_access_mask(barrier.dst_access_mask)
+                .old_layout(barrier.old_layout)
+                .new_layout(barrier.new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: get_aspect_mask(attachment.format),
+                    base_mip_level: 0,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    base_array_layer: 0,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
+                })
+                .build();
+
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    barrier.src_stage_mask,
+                    barrier.dst_stage_mask,
+                    vk::DependencyFlags::BY_REGION,
+                    &[],
+                    &[],
+                    &[image_barrier],
+                );
+            }
+        }
+    }
+
+    /// Sort the passes so every pass runs after the passes it depends on, keeping the order they were added in
+    /// where there's no dependency.
+    fn get_order(&self) -> Result<Vec<PassHandle>> {
+        let pass_count = self.passes.len();
+        let mut dependencies = vec![Vec::new(); pass_count];
+        for attachment in 0..self.attachments.len() {
+            let attachment = AttachmentHandle(attachment);
+            let writers = (0..pass_count)
+                .filter(|p| self.passes[*p].writes.contains(&attachment))
+                .collect::<Vec<_>>();
+
+            // Writers run in the order they were added..
+            for pair in writers.windows(2) {
+                dependencies[pair[1]].push(pair[0]);
+            }
+
+            // ..and passes that only read an attachment see the result of all of them.
+            for (pass_index, pass) in self.passes.iter().enumerate() {
+                if pass.reads.contains(&attachment) && !pass.writes.contains(&attachment) {
+                    dependencies[pass_index].extend(writers.iter().copied());
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(pass_count);
+        let mut scheduled = vec![false; pass_count];
+        while order.len() < pass_count {
+            let next = (0..pass_count)
+                .find(|p| !scheduled[*p] && dependencies[*p].iter().all(|d| scheduled[*d]));
+            match next {
+                Some(p) => {
+                    scheduled[p] = true;
+                    order.push(PassHandle(p));
+                }
+                None => {
+                    let remaining = (0..pass_count)
+                        .filter(|p| !scheduled[*p])
+                        .map(|p| self.passes[p].name.as_str())
+                        .collect::<Vec<_>>();
+                    return Err(anyhow!(
+                        "Render graph has a cycle between passes {:?}",
+                        remaining
+                    ));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Walk the passes in order, tracking how each attachment was last used, and add a barrier wherever a pass
+    /// needs to wait for or transition an attachment.
+    fn get_barriers(&self, order: &[PassHandle]) -> Vec<Vec<AttachmentBarrier>> {
+        let mut barriers = vec![Vec::new(); self.passes.len()];
+        let mut last_usages: Vec<Option<Usage>> = vec![None; self.attachments.len()];
+
+        for pass_handle in order {
+            let pass = &self.passes[pass_handle.0];
+            let reads = pass.reads.iter().filter(|a| !pass.writes.contains(a));
+            for attachment in pass.writes.iter().chain(reads) {
+                let last_usage = &mut last_usages[attachment.0];
+                let writes = pass.writes.contains(attachment);
+                let usage = get_usage(&self.attachments[attachment.0], pass.kind, writes);
+
+                match *last_usage {
+                    // Read-after-read in the same layout doesn't need a barrier.
+                    Some(last) => {
+                        if last.writes || usage.writes || last.layout != usage.layout {
+                            barriers[pass_handle.0].push(AttachmentBarrier {
+                                attachment: *attachment,
+                                src_stage_mask: last.stage_mask,
+                                dst_stage_mask: usage.stage_mask,
+                                src_access_mask: last.access_mask,
+                                dst_access_mask: usage.access_mask,
+                                old_layout: last.layout,
+                                new_layout: usage.layout,
+                            });
+                        }
+                    }
+                    // There's no render pass to transition what a transfer pass writes, and it's overwritten anyway.
+                    None if pass.kind == PassKind::Transfer && writes => {
+                        barriers[pass_handle.0].push(AttachmentBarrier {
+                            attachment: *attachment,
+                            src_stage_mask: vk::PipelineStageFlags::TOP_OF_PIPE,
+                            dst_stage_mask: usage.stage_mask,
+                            src_access_mask: vk::AccessFlags::empty(),
+                            dst_access_mask: usage.access_mask,
+                            old_layout: vk::ImageLayout::UNDEFINED,
+                            new_layout: usage.layout,
+                        });
+                    }
+                    None => {}
+                }
+                *last_usage = Some(usage);
+            }
+        }
+
+        barriers
+    }
+}
+
+fn get_usage(attachment: &Attachment, kind: PassKind, writes: bool) -> Usage {
+    if kind == PassKind::Transfer {
+        let (access_mask, layout) = if writes {
+            (
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            )
+        } else {
+            (
+                vk::AccessFlags::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+        };
+        return Usage {
+            stage_mask: vk::PipelineStageFlags::TRANSFER,
+            access_mask,
+            layout,
+            writes,
+        };
+    }
+
+    match (attachment.is_depth(), writes) {
+        (true, true) => Usage {
+            stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            writes,
+        },
+        (true, false) => Usage {
+            stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            access_mask: vk::AccessFlags::SHADER_READ,
+            layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            writes,
+        },
+        (false, true) => Usage {
+            stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            writes,
+        },
+        (false, false) => Usage {
+            stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            access_mask: vk::AccessFlags::SHADER_READ,
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            writes,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_render_graph_orders_passes() {
+        let mut render_graph = RenderGraph::default();
+        let colour =
+            render_graph.add_attachment("Colour", vk::Image::null(), vk::Format::R8G8B8A8_SRGB);
+        let depth = render_graph.add_attachment("Depth", vk::Image::null(), vk::Format::D32_SFLOAT);
+        let shadow_map =
+            render_graph.add_attachment("Shadow Map", vk::Image::null(), vk::Format::D32_SFLOAT);
+
+        // Add the main pass first: it must still run after the shadow pass it reads from.
+        let main_pass = render_graph.add_pass("Main", &[shadow_map], &[colour, depth]);
+        let shadow_pass = render_graph.add_pass("Shadow", &[], &[shadow_map]);
+        let ui_pass = render_graph.add_pass("UI", &[], &[colour]);
+        render_graph.compile().unwrap();
+
+        assert_eq!(render_graph.order(), &[shadow_pass, main_pass, ui_pass]);
+        assert!(render_graph.barriers(shadow_pass).is_empty());
+
+        // The shadow map is transitioned from a depth attachment to something that can be sampled..
+        assert_eq!(
+            render_graph.barriers(main_pass),
+            &[AttachmentBarrier {
+                attachment: shadow_map,
+                src_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                old_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            }]
+        );
+
+        // ..and the UI waits for the main pass to finish writing colour.
+        let ui_barriers = render_graph.barriers(ui_pass);
+        assert_eq!(ui_barriers.len(), 1);
+        assert_eq!(ui_barriers[0].attachment, colour);
+        assert_eq!(
+            ui_barriers[0].new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+    }
+
+    #[test]
+    pub fn test_render_graph_transfer_pass() {
+        let mut render_graph = RenderGraph::default();
+        let colour =
+            render_graph.add_attachment("Colour", vk::Image::null(), vk::Format::R8G8B8A8_SRGB);
+        let swapchain =
+            render_graph.add_attachment("Swapchain", vk::Image::null(), vk::Format::R8G8B8A8_SRGB);
+        let blit_pass = render_graph.add_transfer_pass("Blit", &[colour], &[swapchain]);
+        let main_pass = render_graph.add_pass("Main", &[], &[colour]);
+        render_graph.compile().unwrap();
+
+        assert_eq!(render_graph.order(), &[main_pass, blit_pass]);
+        assert!(render_graph.barriers(main_pass).is_empty());
+
+        // The blit waits for the main pass to finish writing colour, and discards the swapchain image's contents.
+        assert_eq!(
+            render_graph.barriers(blit_pass),
+            &[
+                AttachmentBarrier {
+                    attachment: swapchain,
+                    src_stage_mask: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                },
+                AttachmentBarrier {
+                    attachment: colour,
+                    src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+                    src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                    old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_render_graph_cycle_is_an_error() {
+        let mut render_graph = RenderGraph::default();
+        let a = render_graph.add_attachment("A", vk::Image::null(), vk::Format::R8G8B8A8_SRGB);
+        let b = render_graph.add_attachment("B", vk::Image::null(), vk::Format::R8G8B8A8_SRGB);
+        render_graph.add_pass("First", &[a], &[b]);
+        render_graph.add_pass("Second", &[b], &[a]);
+        assert!(render_graph.compile().is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    image::Image,
+    resources::{
+        deletion_queue::{DeletionQueue, GpuResource},
+        VulkanContext,
+    },
+    DEPTH_ATTACHMENT_USAGE_FLAGS,
+};
+
+/// The smallest render scale: half the recommended resolution in each dimension
+pub const MIN_RENDER_SCALE: f32 = 0.5;
+/// The largest render scale: twice the recommended resolution in each dimension
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+/// Offscreen attachments the PBR pass renders into when the render scale isn't 1.0.
+///
+/// The multisampled colour image is resolved into `resolve_image` at the end of the pass, which is then blitted to the
+/// swapchain image, upscaling or downsampling it to the swapchain's resolution.
+///
+/// Depth isn't scaled, so if the render pass resolves depth for the compositor it's resolved into
+/// `depth_resolve_image` and not submitted. See `RenderContext::has_resolved_depth`.
+#[derive(Debug, Clone)]
+pub struct ScaledTarget {
+    pub colour_image: Image,
+    pub depth_image: Image,
+    pub resolve_image: Image,
+    pub depth_resolve_image: Option<Image>,
+    pub framebuffer: vk::Framebuffer,
+    /// The area of the attachments that is rendered to
+    pub render_area: vk::Rect2D,
+}
+
+impl ScaledTarget {
+    pub(crate) fn new(
+        vulkan_context: &VulkanContext,
+        render_pass: vk::RenderPass,
+        colour_format: vk::Format,
+        depth_format: vk::Format,
+        extent: vk::Extent2D,
+        resolve_depth: bool,
+    ) -> Result<Self> {
+        let colour_image = vulkan_context.create_image(
+            colour_format,
+            &extent,
+            vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            2,
+            1,
+            "Scaled MSAA Colour Image",
+        )?;
+        let depth_image = vulkan_context.create_image(
+            depth_format,
+            &extent,
+            DEPTH_ATTACHMENT_USAGE_FLAGS,
+            2,
+            1,
+            "Scaled Depth Image",
+        )?;
+        let resolve_image = vulkan_context.create_image(
+            colour_format,
+            &extent,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            2,
+            1,
+            "Scaled Resolve Image",
+        )?;
+
+        let depth_resolve_image = if resolve_depth {
+            Some(vulkan_context.create_image_with_samples(
+                depth_format,
+                &extent,
+                DEPTH_ATTACHMENT_USAGE_FLAGS,
+                vk::SampleCountFlags::TYPE_1,
+                2,
+                1,
+                "Scaled Depth Resolve Image",
+            )?)
+        } else {
+            None
+        };
+
+        let mut attachments = vec![colour_image.view, depth_image.view, resolve_image.view];
+        attachments.extend(depth_resolve_image.as_ref().map(|i| i.view));
+        let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe {
+            vulkan_context
+                .device
+                .create_framebuffer(&framebuffer_create_info, None)
+        }?;
+
+        Ok(Self {
+            colour_image,
+            depth_image,
+            resolve_image,
+            depth_resolve_image,
+            framebuffer,
+            render_area: vk::Rect2D {
+                offset: Default::default(),
+                extent,
+            },
+        })
+    }
+
+    /// Record a blit of both eyes from `resolve_image` to `dst_area` of `swapchain_image`. Must be recorded after the
+    /// PBR render pass has ended and the render graph's barriers for `RenderContext::render_scale_blit_pass`, which
+    /// transition both images for the blit. Leaves the swapchain image in `COLOR_ATTACHMENT_OPTIMAL`, as the render
+    /// pass would.
+    pub(crate) fn blit_to_swapchain(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+        dst_area: &vk::Rect2D,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 2,
+        };
+        let subresource_layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 2,
+        };
+
+        // The graph only records barriers before passes, so the swapchain image is handed back here.
+        let after = [vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .image(swapchain_image)
+            .subresource_range(subresource_range)
+            .build()];
+
+        let blit = vk::ImageBlit {
+            src_subresource: subresource_layers,
+            src_offsets: get_blit_offsets(&self.render_area),
+            dst_subresource: subresource_layers,
+            dst_offsets: get_blit_offsets(dst_area),
+        };
+
+        unsafe {
+            device.cmd_blit_image(
+                command_buffer,
+                self.resolve_image.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &after,
+            );
+        }
+    }
+
+    /// Queue the target's images and framebuffer to be destroyed once `frame_index` is no longer in flight
+    pub(crate) fn release(self, deletion_queue: &mut DeletionQueue, frame_index: usize) {
+        deletion_queue.push(frame_index, GpuResource::Framebuffer(self.framebuffer));
+        for image in [self.colour_image, self.depth_image, self.resolve_image] {
+            deletion_queue.push(frame_index, GpuResource::Image(image));
+        }
+        if let Some(image) = self.depth_resolve_image {
+            deletion_queue.push(frame_index, GpuResource::Image(image));
+        }
+    }
+}
+
+/// Clamp `render_scale` to between `MIN_RENDER_SCALE` and `MAX_RENDER_SCALE`. NaN is treated as 1.0.
+pub(crate) fn clamp_render_scale(render_scale: f32) -> f32 {
+    if render_scale.is_nan() {
+        return 1.0;
+    }
+    render_scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE)
+}
+
+/// The size of `extent` at `render_scale`, rounded to the nearest pixel. Each dimension is at least 1 and at most
+/// `max_dimension`, the largest image the device supports.
+pub(crate) fn get_scaled_extent(
+    extent: vk::Extent2D,
+    render_scale: f32,
+    max_dimension: u32,
+) -> vk::Extent2D {
+    let scale = |d: u32| ((d as f32 * render_scale).round() as u32).clamp(1, max_dimension);
+    vk::Extent2D {
+        width: scale(extent.width),
+        height: scale(extent.height),
+    }
+}
+
+fn get_blit_offsets(area: &vk::Rect2D) -> [vk::Offset3D; 2] {
+    [
+        vk::Offset3D {
+            x: area.offset.x,
+            y: area.offset.y,
+            z: 0,
+        },
+        vk::Offset3D {
+            x: area.offset.x + area.extent.width as i32,
+            y: area.offset.y + area.extent.height as i32,
+            z: 1,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_scaled_extent() {
+        assert_eq!(clamp_render_scale(0.1), MIN_RENDER_SCALE);
+        assert_eq!(clamp_render_scale(4.0), MAX_RENDER_SCALE);
+        assert_eq!(clamp_render_scale(0.75), 0.75);
+        assert_eq!(clamp_render_scale(f32::NAN), 1.0);
+
+        let extent = vk::Extent2D {
+            width: 1832,
+            height: 1921,
+        };
+        assert_eq!(get_scaled_extent(extent, 1.0, 16384), extent);
+        assert_eq!(
+            get_scaled_extent(extent, 0.5, 16384),
+            vk::Extent2D {
+                width: 916,
+                height: 961,
+            }
+        );
+        assert_eq!(
+            get_scaled_extent(extent, 1.3, 16384),
+            vk::Extent2D {
+                width: 2382,
+                height: 2497,
+            }
+        );
+
+        // Extents never exceed what the device supports, and never collapse to nothing.
+        assert_eq!(
+            get_scaled_extent(extent, 2.0, 2048),
+            vk::Extent2D {
+                width: 2048,
+                height: 2048,
+            }
+        );
+        let tiny = vk::Extent2D {
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(get_scaled_extent(tiny, 0.5, 16384), tiny);
+    }
+}
@@ -0,0 +1,138 @@
+use std::mem::size_of;
+
+use ash::vk;
+
+/// The value of a single specialization constant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecializationValue {
+    U32(u32),
+    F32(f32),
+    Bool(bool),
+}
+
+impl SpecializationValue {
+    fn to_bytes(self) -> [u8; 4] {
+        match self {
+            SpecializationValue::U32(v) => v.to_ne_bytes(),
+            SpecializationValue::F32(v) => v.to_ne_bytes(),
+            // Booleans are 32 bits wide in SPIR-V.
+            SpecializationValue::Bool(v) => (v as vk::Bool32).to_ne_bytes(),
+        }
+    }
+}
+
+impl From<u32> for SpecializationValue {
+    fn from(v: u32) -> Self {
+        SpecializationValue::U32(v)
+    }
+}
+
+impl From<f32> for SpecializationValue {
+    fn from(v: f32) -> Self {
+        SpecializationValue::F32(v)
+    }
+}
+
+impl From<bool> for SpecializationValue {
+    fn from(v: bool) -> Self {
+        SpecializationValue::Bool(v)
+    }
+}
+
+/// Values for the specialization constants in a pipeline's shaders, so that one SPIR-V module can be compiled into
+/// several optimised variants (eg. with a different number of lights) when the pipeline is created.
+///
+/// Each constant has a name, used to replace its value and in logs, and the `constant_id` it was declared with in
+/// the shader, eg. `layout (constant_id = 0) const uint LIGHT_COUNT = 1;`. The same constants are given to every
+/// stage: stages that don't declare a constant ignore it.
+///
+/// Basic usage:
+/// ```ignore
+/// let mut constants = SpecializationConstants::default();
+/// constants.set("LIGHT_COUNT", 0, 4_u32).set("USE_FOG", 1, true);
+/// let pipeline = render_context.create_specialized_pipeline(&vulkan_context, RenderLayer::OPAQUE, &constants)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SpecializationConstants {
+    names: Vec<String>,
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+    /// Set the constant called `name`, with `constant_id` in the shader, to `value`. Replaces any value already set.
+    pub fn set(
+        &mut self,
+        name: &str,
+        constant_id: u32,
+        value: impl Into<SpecializationValue>,
+    ) -> &mut Self {
+        let bytes = value.into().to_bytes();
+        match self.names.iter().position(|n| n == name) {
+            Some(index) => {
+                let offset = self.entries[index].offset as usize;
+                self.entries[index].constant_id = constant_id;
+                self.data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            }
+            None => {
+                self.names.push(name.to_string());
+                self.entries.push(vk::SpecializationMapEntry {
+                    constant_id,
+                    offset: self.data.len() as _,
+                    size: size_of::<u32>(),
+                });
+                self.data.extend_from_slice(&bytes);
+            }
+        }
+        self
+    }
+
+    /// The names of the constants that have been set
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Are there no constants set?
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the `vk::SpecializationInfo` for these constants. It points into `self`, so must not outlive it.
+    pub(crate) fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_specialization_constants() {
+        let mut constants = SpecializationConstants::default();
+        assert!(constants.is_empty());
+
+        constants
+            .set("LIGHT_COUNT", 0, 4_u32)
+            .set("FOG_DENSITY", 1, 0.5_f32)
+            .set("USE_FOG", 2, true);
+        assert_eq!(constants.names(), ["LIGHT_COUNT", "FOG_DENSITY", "USE_FOG"]);
+        assert_eq!(constants.entries[1].constant_id, 1);
+        assert_eq!(constants.entries[1].offset, 4);
+        assert_eq!(constants.entries[1].size, 4);
+        assert_eq!(&constants.data[4..8], &0.5_f32.to_ne_bytes());
+        assert_eq!(&constants.data[8..12], &1_u32.to_ne_bytes());
+
+        // Setting a constant again replaces its value, rather than adding another entry.
+        constants.set("LIGHT_COUNT", 0, 8_u32);
+        assert_eq!(constants.entries.len(), 3);
+        assert_eq!(&constants.data[0..4], &8_u32.to_ne_bytes());
+
+        let info = constants.info();
+        assert_eq!(info.map_entry_count, 3);
+        assert_eq!(info.data_size, 12);
+    }
+}
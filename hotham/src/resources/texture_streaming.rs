@@ -0,0 +1,578 @@
+use std::{cmp::Ordering, collections::HashMap, io::Cursor};
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::{
+    resources::{
+        deletion_queue::{DeletionQueue, GpuResource},
+        VulkanContext,
+    },
+    texture::{parse_ktx, SamplerInfo, Texture},
+};
+
+/// Streamed textures are first uploaded with only the mips that are at most this wide and high
+pub const INITIAL_RESIDENT_SIZE: u32 = 64;
+/// The default `TextureStreaming::memory_budget`, in bytes
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+/// A texture added to `TextureStreaming`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamedTextureId(usize);
+
+#[derive(Debug, Clone)]
+struct MipLevel {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Clone)]
+struct StreamedTexture {
+    name: String,
+    format: vk::Format,
+    sampler_info: SamplerInfo,
+    /// Every mip, most detailed first, kept in memory so they can be uploaded again
+    mips: Vec<MipLevel>,
+    /// The most detailed mip on the GPU
+    resident_mip: usize,
+    texture: Texture,
+    /// The descriptor sets and bindings to update when the texture is uploaded again
+    bindings: Vec<(vk::DescriptorSet, u32)>,
+    /// The nearest distance the texture was requested at since the last `update`
+    distance: Option<f32>,
+}
+
+/// Streams mips of large textures in and out of GPU memory, so scenes with more texture data than the headset can
+/// hold can still be drawn.
+///
+/// A streamed texture starts with only its low detail mips (see `INITIAL_RESIDENT_SIZE`) on the GPU. Every frame,
+/// `texture_streaming_system` requests each texture at the distance of the nearest primitive using it, and `update`
+/// uploads the mips that distance calls for. If they don't fit in `memory_budget`, high detail mips are evicted:
+/// first from textures that weren't requested, then from the farthest ones.
+///
+/// Textures are streamed by uploading them again with a different number of mips, rather than with sparse binding,
+/// so every mip is also kept in CPU memory.
+///
+/// Basic usage:
+/// ```ignore
+/// let texture_streaming = &mut engine.texture_streaming;
+/// let id = texture_streaming.add_rgba8("Terrain", &pixels, 4096, 4096, Default::default(), vulkan_context)?;
+/// texture_streaming.bind(id, primitive.texture_descriptor_set, 0, vulkan_context);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TextureStreaming {
+    /// How much GPU memory the resident mips of streamed textures may use, in bytes
+    pub memory_budget: usize,
+    /// Textures nearer than this are streamed in at full detail. Each doubling of the distance beyond it drops a mip.
+    pub full_detail_distance: f32,
+    /// The most textures uploaded by a single `update`, spreading the cost of streaming over several frames
+    pub max_uploads_per_update: usize,
+    textures: Vec<StreamedTexture>,
+    descriptor_sets: HashMap<vk::DescriptorSet, Vec<StreamedTextureId>>,
+}
+
+impl Default for TextureStreaming {
+    fn default() -> Self {
+        Self {
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            full_detail_distance: 1.,
+            max_uploads_per_update: 2,
+            textures: Vec::new(),
+            descriptor_sets: HashMap::new(),
+        }
+    }
+}
+
+impl TextureStreaming {
+    /// Stream an sRGB encoded RGBA8 image, eg. a base colour texture, as `R8G8B8A8_SRGB`. Its mips are generated with a
+    /// box filter.
+    pub fn add_rgba8(
+        &mut self,
+        name: &str,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        sampler_info: SamplerInfo,
+        vulkan_context: &VulkanContext,
+    ) -> Result<StreamedTextureId> {
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Unable to stream empty texture {}", name));
+        }
+        if pixels.len() != (width * height * 4) as usize {
+            return Err(anyhow!(
+                "Texture {} should have {} bytes of RGBA pixels, but has {}",
+                name,
+                width * height * 4,
+                pixels.len()
+            ));
+        }
+
+        let mips = generate_mips(pixels, width, height);
+        self.add(
+            name,
+            vk::Format::R8G8B8A8_SRGB,
+            sampler_info,
+            mips,
+            vulkan_context,
+        )
+    }
+
+    /// Stream a KTX2 texture, using the mips in the file. Cubemaps and texture arrays aren't supported.
+    pub fn add_ktx2(
+        &mut self,
+        name: &str,
+        buf: &[u8],
+        vulkan_context: &VulkanContext,
+    ) -> Result<StreamedTextureId> {
+        let buf = Box::new(Cursor::new(buf.to_vec()));
+        let (buf, width, height, format, array_layers, mip_levels, offsets) = parse_ktx(buf)?;
+        if mip_levels == 0 || width == 0 || height == 0 {
+            return Err(anyhow!(
+                "Unable to stream texture {}: it's {}x{} with {} mip levels",
+                name,
+                width,
+                height,
+                mip_levels
+            ));
+        }
+        if array_layers != 1 {
+            return Err(anyhow!(
+                "Unable to stream texture {} with {} layers",
+                name,
+                array_layers
+            ));
+        }
+
+        // Levels may not be stored in order, so each one ends where the next one after it in the buffer starts.
+        let mut starts = offsets.iter().map(|o| *o as usize).collect::<Vec<_>>();
+        starts.sort_unstable();
+        let mip_count = (mip_levels as usize)
+            .min(get_mip_count(width, height))
+            .min(offsets.len());
+        let mips = (0..mip_count)
+            .map(|level| {
+                let start = offsets[level] as usize;
+                let end = starts
+                    .iter()
+                    .find(|s| **s > start)
+                    .copied()
+                    .unwrap_or(buf.len());
+                MipLevel {
+                    data: buf[start..end].to_vec(),
+                    width: width >> level,
+                    height: height >> level,
+                }
+            })
+            .collect();
+
+        self.add(name, format, SamplerInfo::default(), mips, vulkan_context)
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        format: vk::Format,
+        sampler_info: SamplerInfo,
+        mips: Vec<MipLevel>,
+        vulkan_context: &VulkanContext,
+    ) -> Result<StreamedTextureId> {
+        let sampler_info = SamplerInfo {
+            mip_count: mips.len() as _,
+            ..sampler_info
+        };
+        let resident_mip = get_initial_resident_mip(name, &mips)?;
+        let texture = upload(
+            name,
+            format,
+            &sampler_info,
+            &mips[resident_mip..],
+            vulkan_context,
+        )?;
+
+        self.textures.push(StreamedTexture {
+            name: name.to_string(),
+            format,
+            sampler_info,
+            mips,
+            resident_mip,
+            texture,
+            bindings: Vec::new(),
+            distance: None,
+        });
+        Ok(StreamedTextureId(self.textures.len() - 1))
+    }
+
+    /// Use the streamed texture `id` at `binding` of a descriptor set created by
+    /// `VulkanContext::create_textures_descriptor_sets`, eg. a `Primitive`'s `texture_descriptor_set`. The descriptor
+    /// set is updated whenever the texture is uploaded again. It must not be in use by the GPU.
+    pub fn bind(
+        &mut self,
+        id: StreamedTextureId,
+        descriptor_set: vk::DescriptorSet,
+        binding: u32,
+        vulkan_context: &VulkanContext,
+    ) {
+        let streamed = &mut self.textures[id.0];
+        vulkan_context.update_texture_descriptor_set(descriptor_set, binding, &streamed.texture);
+        streamed.bindings.push((descriptor_set, binding));
+        self.descriptor_sets
+            .entry(descriptor_set)
+            .or_default()
+            .push(id);
+    }
+
+    /// Ask for `id` to be streamed in with enough detail to be seen from `distance` metres away
+    pub fn request(&mut self, id: StreamedTextureId, distance: f32) {
+        let streamed = &mut self.textures[id.0];
+        streamed.distance = Some(streamed.distance.map_or(distance, |d| d.min(distance)));
+    }
+
+    /// Request every streamed texture bound to `descriptor_set`
+    pub(crate) fn request_descriptor_set(
+        &mut self,
+        descriptor_set: vk::DescriptorSet,
+        distance: f32,
+    ) {
+        let ids = match self.descriptor_sets.get(&descriptor_set) {
+            Some(ids) => ids.clone(),
+            None => return,
+        };
+        for id in ids {
+            self.request(id, distance);
+        }
+    }
+
+    /// The texture currently on the GPU for `id`
+    pub fn texture(&self, id: StreamedTextureId) -> &Texture {
+        &self.textures[id.0].texture
+    }
+
+    /// The most detailed mip of `id` on the GPU. `0` is full detail.
+    pub fn resident_mip(&self, id: StreamedTextureId) -> usize {
+        self.textures[id.0].resident_mip
+    }
+
+    /// The number of mips `id` has in total
+    pub fn mip_count(&self, id: StreamedTextureId) -> usize {
+        self.textures[id.0].mips.len()
+    }
+
+    /// How much GPU memory the resident mips of every streamed texture use, in bytes
+    pub fn resident_bytes(&self) -> usize {
+        self.textures
+            .iter()
+            .map(|t| get_resident_bytes(&t.mips, t.resident_mip))
+            .sum()
+    }
+
+    /// Upload or evict mips for the distances textures were requested at since the last update, then clear the
+    /// requests. Evictions go first, then the nearest textures, up to `max_uploads_per_update` in total.
+    ///
+    /// Uploading waits for the GPU to go idle, so this must be called before anything is recorded for the frame.
+    pub fn update(
+        &mut self,
+        vulkan_context: &VulkanContext,
+        deletion_queue: &mut DeletionQueue,
+        frame_index: usize,
+    ) -> Result<()> {
+        let requests = self
+            .textures
+            .iter()
+            .map(|t| MipRequest {
+                mip_sizes: t.mips.iter().map(|m| m.data.len()).collect(),
+                resident_mip: t.resident_mip,
+                distance: t.distance,
+            })
+            .collect::<Vec<_>>();
+        let targets = plan_resident_mips(&requests, self.full_detail_distance, self.memory_budget);
+
+        let mut changes = targets
+            .into_iter()
+            .enumerate()
+            .filter(|(index, mip)| *mip != requests[*index].resident_mip)
+            .collect::<Vec<_>>();
+        changes.sort_by(|(a, a_mip), (b, b_mip)| {
+            let a_evicted = *a_mip > requests[*a].resident_mip;
+            let b_evicted = *b_mip > requests[*b].resident_mip;
+            let a_distance = requests[*a].distance.unwrap_or(f32::MAX);
+            let b_distance = requests[*b].distance.unwrap_or(f32::MAX);
+            b_evicted.cmp(&a_evicted).then(
+                a_distance
+                    .partial_cmp(&b_distance)
+                    .unwrap_or(Ordering::Equal),
+            )
+        });
+
+        for (index, mip) in changes.into_iter().take(self.max_uploads_per_update) {
+            let streamed = &mut self.textures[index];
+            let texture = upload(
+                &streamed.name,
+                streamed.format,
+                &streamed.sampler_info,
+                &streamed.mips[mip..],
+                vulkan_context,
+            )?;
+
+            // The upload waited for the GPU to go idle, so no frame in flight is using these descriptor sets.
+            for (descriptor_set, binding) in &streamed.bindings {
+                vulkan_context.update_texture_descriptor_set(*descriptor_set, *binding, &texture);
+            }
+            let old_texture = std::mem::replace(&mut streamed.texture, texture);
+            deletion_queue.push(frame_index, GpuResource::Image(old_texture.image));
+            streamed.resident_mip = mip;
+        }
+
+        for streamed in &mut self.textures {
+            streamed.distance = None;
+        }
+
+        Ok(())
+    }
+}
+
+/// Upload `mips` as a new texture, with the first of them as its most detailed mip
+fn upload(
+    name: &str,
+    format: vk::Format,
+    sampler_info: &SamplerInfo,
+    mips: &[MipLevel],
+    vulkan_context: &VulkanContext,
+) -> Result<Texture> {
+    let mut buf = Vec::new();
+    let mut offsets = Vec::new();
+    for mip in mips {
+        offsets.push(buf.len() as vk::DeviceSize);
+        buf.extend_from_slice(&mip.data);
+    }
+
+    let image = vulkan_context.create_texture_image(
+        name,
+        &buf,
+        mips[0].width,
+        mips[0].height,
+        format,
+        1,
+        mips.len() as _,
+        offsets,
+    )?;
+    let sampler = vulkan_context.get_texture_sampler(sampler_info)?;
+    let descriptor = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image.view)
+        .sampler(sampler)
+        .build();
+
+    Ok(Texture {
+        image,
+        sampler,
+        descriptor,
+    })
+}
+
+/// The number of mips in a chain that halves `width` and `height` until either reaches 1
+fn get_mip_count(width: u32, height: u32) -> usize {
+    (32 - width.min(height).max(1).leading_zeros()) as _
+}
+
+/// Build a mip chain from RGBA pixels, averaging each 2x2 block of a mip into a pixel of the next
+fn generate_mips(pixels: &[u8], width: u32, height: u32) -> Vec<MipLevel> {
+    let mut mips = vec![MipLevel {
+        data: pixels.to_vec(),
+        width,
+        height,
+    }];
+
+    for _ in 1..get_mip_count(width, height) {
+        let previous = mips.last().unwrap();
+        let (width, height) = (previous.width / 2, previous.height / 2);
+        let mut data = Vec::with_capacity((width * height * 4) as _);
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..4 {
+                    let sum: u32 = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .map(|(dx, dy)| {
+                            let index = ((y * 2 + dy) * previous.width + x * 2 + dx) * 4 + channel;
+                            previous.data[index as usize] as u32
+                        })
+                        .sum();
+                    data.push(((sum + 2) / 4) as u8);
+                }
+            }
+        }
+        mips.push(MipLevel {
+            data,
+            width,
+            height,
+        });
+    }
+
+    mips
+}
+
+fn get_resident_bytes(mips: &[MipLevel], resident_mip: usize) -> usize {
+    mips[resident_mip..].iter().map(|m| m.data.len()).sum()
+}
+
+struct MipRequest {
+    /// The size of each mip in bytes, most detailed first
+    mip_sizes: Vec<usize>,
+    resident_mip: usize,
+    distance: Option<f32>,
+}
+
+/// The mip a texture seen from `distance` metres away needs
+fn get_desired_mip(distance: f32, full_detail_distance: f32, lowest_mip: usize) -> usize {
+    let ratio = (distance / full_detail_distance).max(1.);
+    (ratio.log2().floor() as usize).min(lowest_mip)
+}
+
+/// The most detailed mip that's at most `INITIAL_RESIDENT_SIZE` wide and high, or the least detailed one if they're
+/// all bigger. Fails if there are no mips.
+fn get_initial_resident_mip(name: &str, mips: &[MipLevel]) -> Result<usize> {
+    if mips.is_empty() {
+        return Err(anyhow!("Unable to stream texture {} with no mips", name));
+    }
+    Ok(mips
+        .iter()
+        .position(|m| m.width.max(m.height) <= INITIAL_RESIDENT_SIZE)
+        .unwrap_or(mips.len() - 1))
+}
+
+/// Pick the most detailed mip each texture should have resident. Requested textures get the mip their distance
+/// calls for, and the rest keep what they have. While that's over `memory_budget`, textures that weren't requested
+/// are evicted down to their lowest mip, then the farthest requested textures drop a mip at a time.
+fn plan_resident_mips(
+    requests: &[MipRequest],
+    full_detail_distance: f32,
+    memory_budget: usize,
+) -> Vec<usize> {
+    let resident_bytes =
+        |request: &MipRequest, mip: usize| -> usize { request.mip_sizes[mip..].iter().sum() };
+    let mut targets = requests
+        .iter()
+        .map(|r| match r.distance {
+            Some(distance) => {
+                get_desired_mip(distance, full_detail_distance, r.mip_sizes.len() - 1)
+            }
+            None => r.resident_mip,
+        })
+        .collect::<Vec<_>>();
+    let mut total = requests
+        .iter()
+        .zip(&targets)
+        .map(|(r, mip)| resident_bytes(r, *mip))
+        .sum::<usize>();
+
+    for (index, request) in requests.iter().enumerate() {
+        if total <= memory_budget {
+            return targets;
+        }
+        if request.distance.is_none() {
+            let lowest_mip = request.mip_sizes.len() - 1;
+            total -= resident_bytes(request, targets[index]) - resident_bytes(request, lowest_mip);
+            targets[index] = lowest_mip;
+        }
+    }
+
+    let mut farthest_first = (0..requests.len())
+        .filter(|i| requests[*i].distance.is_some())
+        .collect::<Vec<_>>();
+    farthest_first.sort_by(|a, b| {
+        requests[*b]
+            .distance
+            .partial_cmp(&requests[*a].distance)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    while total > memory_budget {
+        let mut evicted = false;
+        for index in &farthest_first {
+            if total <= memory_budget {
+                break;
+            }
+            let mip = targets[*index];
+            if mip + 1 < requests[*index].mip_sizes.len() {
+                total -= requests[*index].mip_sizes[mip];
+                targets[*index] = mip + 1;
+                evicted = true;
+            }
+        }
+        if !evicted {
+            break;
+        }
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_generate_mips() {
+        let pixels = [
+            [0, 0, 0, 255],
+            [4, 8, 12, 255],
+            [8, 16, 24, 255],
+            [0, 0, 0, 255],
+            [4, 8, 12, 255],
+            [0, 0, 0, 255],
+            [0, 0, 0, 255],
+            [8, 16, 24, 255],
+        ]
+        .concat();
+        let mips = generate_mips(&pixels, 4, 2);
+        assert_eq!(mips.len(), 2);
+        assert_eq!((mips[1].width, mips[1].height), (2, 1));
+        assert_eq!(mips[1].data, vec![2, 4, 6, 255, 4, 8, 12, 255]);
+
+        assert_eq!(get_mip_count(1024, 256), 9);
+        assert_eq!(get_mip_count(1, 1), 1);
+    }
+
+    #[test]
+    pub fn test_get_initial_resident_mip() {
+        let pixels = vec![0; 256 * 256 * 4];
+        let mips = generate_mips(&pixels, 256, 256);
+        assert_eq!(get_initial_resident_mip("Big", &mips).unwrap(), 2);
+        assert_eq!(get_initial_resident_mip("Big", &mips[..1]).unwrap(), 0);
+
+        // Eg. a KTX2 file with no levels.
+        assert!(get_initial_resident_mip("Empty", &[]).is_err());
+    }
+
+    #[test]
+    pub fn test_plan_resident_mips() {
+        // Textures with mips of 64, 16, 4 and 1 bytes.
+        let request = |resident_mip, distance| MipRequest {
+            mip_sizes: vec![64, 16, 4, 1],
+            resident_mip,
+            distance,
+        };
+
+        // Within budget, requested textures get the mip their distance calls for and the rest are left alone.
+        let requests = [
+            request(2, Some(0.5)),
+            request(2, Some(3.)),
+            request(1, None),
+        ];
+        assert_eq!(plan_resident_mips(&requests, 1., 1000), vec![0, 1, 1]);
+
+        // Textures that weren't requested are evicted first..
+        assert_eq!(plan_resident_mips(&requests, 1., 110), vec![0, 1, 3]);
+
+        // ..then mips are dropped from the farthest textures.
+        assert_eq!(plan_resident_mips(&requests, 1., 100), vec![0, 2, 3]);
+        assert_eq!(plan_resident_mips(&requests, 1., 20), vec![2, 3, 3]);
+
+        // Every texture keeps at least its lowest mip.
+        assert_eq!(plan_resident_mips(&requests, 1., 0), vec![3, 3, 3]);
+
+        // Far away textures don't need their detailed mips.
+        assert_eq!(get_desired_mip(8., 2., 3), 2);
+        assert_eq!(get_desired_mip(1000., 2., 3), 3);
+    }
+}
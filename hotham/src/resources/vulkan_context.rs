@@ -2,9 +2,10 @@ use crate::{
     buffer::Buffer,
     hotham_error::HothamError,
     image::Image,
+    resources::memory_stats::{get_heap_stats, HeapStats},
     scene_data::{SceneData, SceneParams},
-    texture::Texture,
-    DEPTH_ATTACHMENT_USAGE_FLAGS, DEPTH_FORMAT,
+    texture::{SamplerInfo, Texture},
+    DEPTH_ATTACHMENT_USAGE_FLAGS,
 };
 use anyhow::{anyhow, Result};
 use ash::{
@@ -14,11 +15,49 @@ use ash::{
     vk::{self, DebugUtilsObjectNameInfoEXT, Handle, ObjectType},
     Device, Entry, Instance as AshInstance,
 };
+use log::{debug, error};
 use openxr as xr;
-use std::{cmp::max, ffi::CString, fmt::Debug, ptr::copy};
+use std::{
+    cmp::max,
+    collections::HashMap,
+    ffi::{CStr, CString},
+    fmt::Debug,
+    ptr::copy,
+    sync::{Arc, Mutex},
+};
 
 type XrVulkan = xr::Vulkan;
 
+/// Extra Vulkan extensions to enable, eg. vendor specific ones Hotham doesn't use itself.
+/// They're enabled alongside the extensions required by Hotham and the OpenXR runtime. Creating the `VulkanContext`
+/// fails with `HothamError::UnsupportedExtensions` if any of them aren't available.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VulkanExtensions {
+    /// Extra instance extensions, eg. `VK_EXT_debug_report`
+    pub extra_instance_extensions: Vec<String>,
+    /// Extra device extensions, eg. `VK_EXT_debug_marker`
+    pub extra_device_extensions: Vec<String>,
+}
+
+/// The Vulkan API versions Hotham can use, newest first. The newest one supported by the Vulkan loader, the OpenXR
+/// runtime and the device is used: see `VulkanContext::api_version`.
+pub const SUPPORTED_API_VERSIONS: [u32; 3] = [
+    vk::make_api_version(0, 1, 2, 0),
+    vk::make_api_version(0, 1, 1, 0),
+    vk::make_api_version(0, 1, 0, 0),
+];
+
+/// How multiview, which Hotham needs to render both eyes in one pass, is enabled on a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiviewSupport {
+    /// Through `VkPhysicalDeviceVulkan11Features`, on Vulkan 1.2 and later
+    Vulkan11Features,
+    /// Through `VkPhysicalDeviceMultiviewFeatures`, on Vulkan 1.1
+    Core,
+    /// Through the `VK_KHR_multiview` extension, on Vulkan 1.0
+    Extension,
+}
+
 #[derive(Clone)]
 pub struct VulkanContext {
     pub entry: Entry,
@@ -28,9 +67,34 @@ pub struct VulkanContext {
     pub command_pool: vk::CommandPool,
     pub queue_family_index: u32,
     pub graphics_queue: vk::Queue,
+    /// Queue for uploads, eg. `create_texture_image`. The same as `graphics_queue` if the device has no dedicated
+    /// transfer queue family.
+    /// NOTE: Submissions to a queue must be externally synchronised, so don't submit from several threads at once
+    /// when it's shared. Resources with `EXCLUSIVE` sharing used by both families need a queue family ownership
+    /// transfer when the families differ.
+    pub transfer_queue: vk::Queue,
+    pub transfer_queue_family_index: u32,
+    /// Command pool for `transfer_queue`. The same as `command_pool` if the queue is shared.
+    pub transfer_command_pool: vk::CommandPool,
+    /// Queue for compute work, eg. `MarchingCubes::create_primitive`. The same as `graphics_queue` if the device has
+    /// no dedicated compute queue family.
+    /// The same synchronisation rules as `transfer_queue` apply.
+    pub compute_queue: vk::Queue,
+    pub compute_queue_family_index: u32,
+    /// Command pool for `compute_queue`. The same as `command_pool` if the queue is shared.
+    pub compute_command_pool: vk::CommandPool,
     pub descriptor_pool: vk::DescriptorPool,
     pub debug_utils: DebugUtils,
     pub physical_device_properties: vk::PhysicalDeviceProperties,
+    /// The Vulkan API version in use: the newest of `SUPPORTED_API_VERSIONS` supported by the Vulkan loader, the
+    /// OpenXR runtime and the device. Use `vk::api_version_major` and `vk::api_version_minor` to decode it.
+    pub api_version: u32,
+    /// The optional device features that were enabled, ie. those Hotham uses that the device supports
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    /// Was `VK_EXT_memory_budget` enabled? If not, `get_memory_heap_stats` can only report the size of each heap.
+    pub memory_budget_supported: bool,
+    /// Samplers are shared between all textures with the same sampler state
+    pub samplers: Arc<Mutex<HashMap<SamplerInfo, vk::Sampler>>>,
 }
 
 // NOTE: OpenXR created the instance / device etc. and is therefore the owner. We'll let it do the cleanup.
@@ -52,22 +116,16 @@ impl VulkanContext {
         xr_instance: &xr::Instance,
         system: xr::SystemId,
     ) -> Result<Self> {
-        println!("[HOTHAM_VULKAN] Creating VulkanContext..");
-        let vk_target_version_xr = xr::Version::new(1, 2, 0);
-
+        debug!(target: "HOTHAM_VULKAN", "Creating VulkanContext..");
         let requirements = xr_instance.graphics_requirements::<XrVulkan>(system)?;
-        if vk_target_version_xr < requirements.min_api_version_supported
-            || vk_target_version_xr.major() > requirements.max_api_version_supported.major()
-        {
-            return Err(HothamError::UnsupportedVersionError.into());
-        }
 
         let entry = unsafe { Entry::new() }?;
         let get_instance_proc_addr =
             unsafe { std::mem::transmute(entry.static_fn().get_instance_proc_addr) };
+        let instance_api_version = select_api_version(&entry, Some(&requirements))?;
 
         let app_info = vk::ApplicationInfo::builder()
-            .api_version(vk::make_api_version(0, 1, 2, 0))
+            .api_version(instance_api_version)
             .build();
 
         let create_info = vk::InstanceCreateInfo::builder().application_info(&app_info);
@@ -92,27 +150,33 @@ impl VulkanContext {
             xr_instance.vulkan_graphics_device(system, instance_handle)? as _,
         );
 
-        let queue_family_index = unsafe {
-            instance
-                .get_physical_device_queue_family_properties(physical_device)
-                .into_iter()
-                .enumerate()
-                .find_map(|(queue_family_index, info)| {
-                    if info.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                        Some(queue_family_index as u32)
-                    } else {
-                        None
-                    }
-                })
-                .ok_or(HothamError::EmptyListError)?
+        let queue_family_indices = unsafe {
+            select_queue_families(
+                &instance.get_physical_device_queue_family_properties(physical_device),
+            )?
         };
+        let queue_priorities = [1.0];
+        let queue_create_infos = queue_family_indices.create_infos(&queue_priorities);
+        let enabled_features = get_physical_device_features(unsafe {
+            &instance.get_physical_device_features(physical_device)
+        });
+        let physical_device_properties =
+            unsafe { instance.get_physical_device_properties(physical_device) };
+        let api_version = instance_api_version.min(physical_device_properties.api_version);
+        let available_extensions =
+            unsafe { instance.enumerate_device_extension_properties(physical_device) }?;
+        let multiview_support = get_multiview_support(api_version, &available_extensions)?;
+        let memory_budget_supported = get_memory_budget_support(api_version, &available_extensions);
+        let mut extension_names = get_multiview_extension_names(multiview_support);
+        if memory_budget_supported {
+            extension_names.push(vk::ExtMemoryBudgetFn::name().to_owned());
+        }
+        let extension_names = extension_names
+            .iter()
+            .map(|e| e.as_ptr())
+            .collect::<Vec<_>>();
 
-        let graphics_queue_create_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(queue_family_index)
-            .queue_priorities(&[1.0])
-            .build();
-        let queue_create_infos = [graphics_queue_create_info];
-        let multiview = &mut vk::PhysicalDeviceVulkan11Features {
+        let vulkan_11_features = &mut vk::PhysicalDeviceVulkan11Features {
             multiview: vk::TRUE,
             ..Default::default()
         };
@@ -120,11 +184,22 @@ impl VulkanContext {
             separate_depth_stencil_layouts: vk::TRUE,
             ..Default::default()
         };
+        let multiview_features = &mut vk::PhysicalDeviceMultiviewFeatures {
+            multiview: vk::TRUE,
+            ..Default::default()
+        };
 
         let device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
-            .push_next(separate_depth_stencil_layouts)
-            .push_next(multiview);
+            .enabled_extension_names(&extension_names)
+            .enabled_features(&enabled_features);
+        let device_create_info = if multiview_support == MultiviewSupport::Vulkan11Features {
+            device_create_info
+                .push_next(separate_depth_stencil_layouts)
+                .push_next(vulkan_11_features)
+        } else {
+            device_create_info.push_next(multiview_features)
+        };
 
         let device_handle = unsafe {
             xr_instance.create_vulkan_device(
@@ -139,100 +214,142 @@ impl VulkanContext {
         let device =
             unsafe { Device::load(instance.fp_v1_0(), vk::Device::from_raw(device_handle as _)) };
 
-        let graphics_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
-
-        let command_pool = create_command_pool(&device, queue_family_index)?;
+        let queues = Queues::new(&device, queue_family_indices)?;
 
         let descriptor_pool = create_descriptor_pool(&device)?;
         let debug_utils = DebugUtils::new(&entry, &instance);
-        let physical_device_properties =
-            unsafe { instance.get_physical_device_properties(physical_device) };
 
-        println!(" ..done!");
+        debug!(target: "HOTHAM_VULKAN", "..done!");
 
         Ok(Self {
             entry,
             instance,
             device,
             physical_device,
-            command_pool,
-            queue_family_index,
-            graphics_queue,
+            command_pool: queues.command_pool,
+            queue_family_index: queue_family_indices.graphics,
+            graphics_queue: queues.graphics_queue,
+            transfer_queue: queues.transfer_queue,
+            transfer_queue_family_index: queue_family_indices.transfer,
+            transfer_command_pool: queues.transfer_command_pool,
+            compute_queue: queues.compute_queue,
+            compute_queue_family_index: queue_family_indices.compute,
+            compute_command_pool: queues.compute_command_pool,
             descriptor_pool,
             debug_utils,
             physical_device_properties,
+            api_version,
+            enabled_features,
+            memory_budget_supported,
+            samplers: Default::default(),
         })
     }
 
     pub fn create_from_xr_instance_legacy(
         xr_instance: &xr::Instance,
         system: xr::SystemId,
+        vulkan_extensions: &VulkanExtensions,
     ) -> Result<Self> {
-        let vk_target_version_xr = xr::Version::new(1, 2, 0);
-
         let requirements = xr_instance.graphics_requirements::<XrVulkan>(system)?;
-        if vk_target_version_xr < requirements.min_api_version_supported
-            || vk_target_version_xr.major() > requirements.max_api_version_supported.major()
-        {
-            return Err(HothamError::UnsupportedVersionError.into());
-        }
 
-        let (vulkan_instance, vulkan_entry) = vulkan_init_legacy(xr_instance, system)?;
+        let (vulkan_instance, vulkan_entry, instance_api_version) = vulkan_init_legacy(
+            xr_instance,
+            system,
+            &requirements,
+            &vulkan_extensions.extra_instance_extensions,
+        )?;
         let physical_device = vk::PhysicalDevice::from_raw(
             xr_instance
                 .vulkan_graphics_device(system, vulkan_instance.handle().as_raw() as _)
                 .unwrap() as _,
         );
-        let (device, graphics_queue, queue_family_index) =
-            create_vulkan_device_legacy(xr_instance, system, &vulkan_instance, physical_device)?;
+        let (device, queue_family_indices, enabled_features, api_version) =
+            create_vulkan_device_legacy(
+                xr_instance,
+                system,
+                &vulkan_instance,
+                physical_device,
+                instance_api_version,
+                &vulkan_extensions.extra_device_extensions,
+            )?;
 
-        let command_pool = create_command_pool(&device, queue_family_index)?;
+        let queues = Queues::new(&device, queue_family_indices)?;
 
         let descriptor_pool = create_descriptor_pool(&device)?;
         let debug_utils = DebugUtils::new(&vulkan_entry, &vulkan_instance);
         let physical_device_properties =
             unsafe { vulkan_instance.get_physical_device_properties(physical_device) };
+        let memory_budget_supported = get_memory_budget_support(api_version, unsafe {
+            &vulkan_instance.enumerate_device_extension_properties(physical_device)?
+        });
 
         Ok(Self {
             entry: vulkan_entry,
             instance: vulkan_instance,
             physical_device,
             device,
-            graphics_queue,
-            queue_family_index,
-            command_pool,
+            graphics_queue: queues.graphics_queue,
+            queue_family_index: queue_family_indices.graphics,
+            command_pool: queues.command_pool,
+            transfer_queue: queues.transfer_queue,
+            transfer_queue_family_index: queue_family_indices.transfer,
+            transfer_command_pool: queues.transfer_command_pool,
+            compute_queue: queues.compute_queue,
+            compute_queue_family_index: queue_family_indices.compute,
+            compute_command_pool: queues.compute_command_pool,
             descriptor_pool,
             debug_utils,
             physical_device_properties,
+            api_version,
+            enabled_features,
+            memory_budget_supported,
+            samplers: Default::default(),
         })
     }
 
     pub fn testing() -> Result<Self> {
-        let (instance, entry) = vulkan_init_test()?;
+        let (instance, entry, instance_api_version) = vulkan_init_test()?;
         let physical_device = get_test_physical_device(&instance);
         let mut extension_names = Vec::new();
         add_device_extension_names(&mut extension_names);
 
-        let (device, graphics_queue, queue_family_index) =
-            create_vulkan_device(&extension_names, &instance, physical_device)?;
+        let (device, queue_family_indices, enabled_features, api_version) = create_vulkan_device(
+            &extension_names,
+            &instance,
+            physical_device,
+            instance_api_version,
+        )?;
 
-        let command_pool = create_command_pool(&device, queue_family_index)?;
+        let queues = Queues::new(&device, queue_family_indices)?;
         let descriptor_pool = create_descriptor_pool(&device)?;
         let debug_utils = DebugUtils::new(&entry, &instance);
         let physical_device_properties =
             unsafe { instance.get_physical_device_properties(physical_device) };
+        let memory_budget_supported = get_memory_budget_support(api_version, unsafe {
+            &instance.enumerate_device_extension_properties(physical_device)?
+        });
 
         Ok(Self {
             entry,
             instance,
             physical_device,
             device,
-            graphics_queue,
-            queue_family_index,
-            command_pool,
+            graphics_queue: queues.graphics_queue,
+            queue_family_index: queue_family_indices.graphics,
+            command_pool: queues.command_pool,
+            transfer_queue: queues.transfer_queue,
+            transfer_queue_family_index: queue_family_indices.transfer,
+            transfer_command_pool: queues.transfer_command_pool,
+            compute_queue: queues.compute_queue,
+            compute_queue_family_index: queue_family_indices.compute,
+            compute_command_pool: queues.compute_command_pool,
             descriptor_pool,
             debug_utils,
             physical_device_properties,
+            api_version,
+            enabled_features,
+            memory_budget_supported,
+            samplers: Default::default(),
         })
     }
 
@@ -272,6 +389,37 @@ impl VulkanContext {
         usage: vk::ImageUsageFlags,
         array_layers: u32,
         mip_levels: u32,
+        name: &str,
+    ) -> Result<Image> {
+        let samples = if usage.contains(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+            || usage.contains(DEPTH_ATTACHMENT_USAGE_FLAGS)
+        {
+            vk::SampleCountFlags::TYPE_4
+        } else {
+            vk::SampleCountFlags::TYPE_1
+        };
+        self.create_image_with_samples(
+            format,
+            extent,
+            usage,
+            samples,
+            array_layers,
+            mip_levels,
+            name,
+        )
+    }
+
+    /// Create an image with `samples`, rather than the sample count `create_image` picks from `usage`. Eg. for a
+    /// single sampled image that a multisampled depth attachment is resolved into.
+    pub fn create_image_with_samples(
+        &self,
+        format: vk::Format,
+        extent: &vk::Extent2D,
+        usage: vk::ImageUsageFlags,
+        samples: vk::SampleCountFlags,
+        array_layers: u32,
+        mip_levels: u32,
+        name: &str,
     ) -> Result<Image> {
         let tiling = vk::ImageTiling::OPTIMAL;
         let (flags, image_view_type) = if array_layers == 1 {
@@ -287,13 +435,6 @@ impl VulkanContext {
                 vk::ImageViewType::TYPE_2D_ARRAY,
             )
         };
-        let samples = if usage.contains(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
-            || usage.contains(DEPTH_ATTACHMENT_USAGE_FLAGS)
-        {
-            vk::SampleCountFlags::TYPE_4
-        } else {
-            vk::SampleCountFlags::TYPE_1
-        };
 
         let create_info = vk::ImageCreateInfo::builder()
             .format(format)
@@ -312,6 +453,7 @@ impl VulkanContext {
             .samples(samples)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
         let image = unsafe { self.device.create_image(&create_info, None) }?;
+        self.set_debug_name(vk::ObjectType::IMAGE, image.as_raw(), name)?;
 
         let (_, device_memory) = self.allocate_image_memory(image)?;
 
@@ -332,6 +474,35 @@ impl VulkanContext {
         ))
     }
 
+    /// Check that `format` can be used as a depth/stencil attachment on this device
+    pub fn check_depth_format_support(&self, format: vk::Format) -> Result<(), HothamError> {
+        let format_properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        };
+
+        if format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        {
+            Ok(())
+        } else {
+            Err(HothamError::InvalidFormatError {
+                format: format!("{:?}", format),
+            })
+        }
+    }
+
+    /// Pick the first format in `preferred_formats` that can be used as a depth/stencil attachment on this device
+    pub fn get_depth_format(
+        &self,
+        preferred_formats: &[vk::Format],
+    ) -> Result<vk::Format, HothamError> {
+        select_depth_format(preferred_formats, |format| {
+            self.check_depth_format_support(format).is_ok()
+        })
+    }
+
     /// Create a Vukan buffer filled with the contents of `data`.
     pub fn create_buffer_with_data<T: Sized + Copy>(
         &self,
@@ -348,8 +519,9 @@ impl VulkanContext {
         let buffer = unsafe { device.create_buffer(&buffer_create_info, None) }?;
         let (device_memory_size, device_memory) = self.allocate_buffer_memory(buffer)?;
 
-        println!(
-            "[HOTHAM_VULKAN] Allocated {} bits of buffer memory: {:?}",
+        debug!(
+            target: "HOTHAM_VULKAN",
+            "Allocated {} bits of buffer memory: {:?}",
             device_memory_size, device_memory
         );
         unsafe { device.bind_buffer_memory(buffer, device_memory, 0) }?;
@@ -386,6 +558,36 @@ impl VulkanContext {
         Ok(())
     }
 
+    /// Copy `len` elements back out of `device_memory`, laid out as `update_buffer` wrote them
+    #[cfg(feature = "debug-buffers")]
+    pub fn read_buffer<T: Sized + Copy>(
+        &self,
+        device_memory: vk::DeviceMemory,
+        device_memory_size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        len: usize,
+    ) -> Result<Vec<T>> {
+        unsafe {
+            let src = self.device.map_memory(
+                device_memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )?;
+
+            let data = if usage == vk::BufferUsageFlags::UNIFORM_BUFFER {
+                let (alignment, aligned_size) = self.get_alignment_info::<T>(device_memory_size);
+                let mut align = Align::<T>::new(src, alignment, aligned_size);
+                align.iter_mut().take(len).map(|v| *v).collect()
+            } else {
+                std::slice::from_raw_parts(src as *const T, len).to_vec()
+            };
+            self.device.unmap_memory(device_memory);
+
+            Ok(data)
+        }
+    }
+
     pub fn get_alignment_info<T: Sized>(
         &self,
         original_size: vk::DeviceSize,
@@ -426,6 +628,33 @@ impl VulkanContext {
         ))
     }
 
+    /// Get the size of each memory heap and, if `memory_budget_supported`, how much of it this process is using and
+    /// can use. The budget changes as other processes allocate memory, so don't hold on to it for long: see
+    /// `MemoryStats`.
+    pub fn get_memory_heap_stats(&self) -> Vec<HeapStats> {
+        if !self.memory_budget_supported {
+            let memory_properties = unsafe {
+                self.instance
+                    .get_physical_device_memory_properties(self.physical_device)
+            };
+            return get_heap_stats(&memory_properties, None);
+        }
+
+        let mut memory_budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let memory_properties = {
+            let mut memory_properties =
+                vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut memory_budget);
+            unsafe {
+                self.instance.get_physical_device_memory_properties2(
+                    self.physical_device,
+                    &mut memory_properties,
+                )
+            };
+            memory_properties.memory_properties
+        };
+        get_heap_stats(&memory_properties, Some(&memory_budget))
+    }
+
     fn allocate_buffer_memory(
         &self,
         buffer: vk::Buffer,
@@ -474,7 +703,7 @@ impl VulkanContext {
         layer_count: u32,
         mip_count: u32,
         offsets: Vec<vk::DeviceSize>,
-    ) -> Result<(Image, vk::Sampler)> {
+    ) -> Result<Image> {
         // Get the image's properties
         let image_extent = vk::Extent2D { width, height };
 
@@ -485,56 +714,134 @@ impl VulkanContext {
             vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
             layer_count,
             mip_count,
+            name,
         )?;
-        self.set_debug_name(vk::ObjectType::IMAGE, texture_image.handle.as_raw(), name)?;
 
         // Create a staging buffer.
-        println!("[HOTHAM_VULKAN] Creating staging buffer..");
+        debug!(target: "HOTHAM_VULKAN", "Creating staging buffer..");
         let usage = vk::BufferUsageFlags::TRANSFER_SRC;
         let size = 8 * image_buf.len();
         let (staging_buffer, staging_memory, _) =
             self.create_buffer_with_data(image_buf, usage, size as _)?;
-        println!("[HOTHAM_VULKAN] ..done!");
-
-        // Copy the buffer into the image
-        let initial_layout = vk::ImageLayout::UNDEFINED;
+        debug!(target: "HOTHAM_VULKAN", "..done!");
+
+        // Copy the buffer into the image on the transfer queue, then hand it over to the graphics queue to be sampled.
+        // If they're from different queue families, that's a queue family ownership transfer: the image is released
+        // by the transfer queue, then acquired by the graphics queue with a matching barrier.
+        let ownership_transfer = self.transfer_queue_family_index != self.queue_family_index;
+        let (src_queue_family_index, dst_queue_family_index) = if ownership_transfer {
+            (self.transfer_queue_family_index, self.queue_family_index)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+        let barrier = |old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(src_queue_family_index)
+                .dst_queue_family_index(dst_queue_family_index)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: mip_count,
+                    base_array_layer: 0,
+                    layer_count,
+                })
+                .image(texture_image.handle)
+                .build()
+        };
         let transfer_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-        self.transition_image_layout(
-            texture_image.handle,
-            initial_layout,
-            transfer_layout,
-            layer_count,
-            mip_count,
-        );
-
-        println!("[HOTHAM_VULKAN] Copying buffer to image..");
-        self.copy_buffer_to_image(
-            staging_buffer,
-            &texture_image,
-            layer_count,
-            mip_count,
-            offsets,
-        );
-
-        // Now transition the image
-        println!("[HOTHAM_VULKAN] ..done! Transitioning image layout..");
         let final_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-        self.transition_image_layout(
-            texture_image.handle,
+
+        debug!(target: "HOTHAM_VULKAN", "Copying buffer to image..");
+        let command_buffer = self.begin_single_time_commands_in(self.transfer_command_pool);
+        let mut to_transfer = barrier(
+            vk::ImageLayout::UNDEFINED,
             transfer_layout,
-            final_layout,
-            layer_count,
-            mip_count,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
         );
-        println!("[HOTHAM_VULKAN] ..done! Freeing staging buffer..");
-        let sampler_address_mode = if format == vk::Format::R16G16_SFLOAT || layer_count == 6 {
-            vk::SamplerAddressMode::CLAMP_TO_EDGE
+        to_transfer.src_queue_family_index = vk::QUEUE_FAMILY_IGNORED;
+        to_transfer.dst_queue_family_index = vk::QUEUE_FAMILY_IGNORED;
+        let (release, release_stage) = if ownership_transfer {
+            (
+                barrier(
+                    transfer_layout,
+                    final_layout,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::empty(),
+                ),
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            )
         } else {
-            vk::SamplerAddressMode::REPEAT
+            (
+                barrier(
+                    transfer_layout,
+                    final_layout,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            )
         };
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            self.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer,
+                texture_image.handle,
+                transfer_layout,
+                &get_buffer_image_copies(&texture_image, layer_count, mip_count, &offsets),
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                release_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[release],
+            );
+        }
+        self.end_single_time_commands_in(
+            command_buffer,
+            self.transfer_queue,
+            self.transfer_command_pool,
+        );
 
-        let sampler = self.create_texture_sampler(sampler_address_mode, mip_count)?;
-        self.set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), name)?;
+        if ownership_transfer {
+            debug!(target: "HOTHAM_VULKAN", "..done! Acquiring image on the graphics queue..");
+            let command_buffer = self.begin_single_time_commands();
+            let acquire = barrier(
+                transfer_layout,
+                final_layout,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::SHADER_READ,
+            );
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[acquire],
+                );
+            }
+            self.end_single_time_commands(command_buffer);
+        }
+        debug!(target: "HOTHAM_VULKAN", "..done! Freeing staging buffer..");
 
         // Free the staging buffer
         unsafe {
@@ -542,12 +849,9 @@ impl VulkanContext {
             self.device.free_memory(staging_memory, None);
         }
 
-        println!(
-            "[HOTHAM_VULKAN] ..done! Texture {} created successfully.",
-            name
-        );
+        debug!(target: "HOTHAM_VULKAN", "..done! Texture {} created successfully.", name);
 
-        Ok((texture_image, sampler))
+        Ok(texture_image)
     }
 
     pub fn transition_image_layout(
@@ -598,10 +902,19 @@ impl VulkanContext {
         self.end_single_time_commands(command_buffer);
     }
     pub fn begin_single_time_commands(&self) -> vk::CommandBuffer {
+        self.begin_single_time_commands_in(self.command_pool)
+    }
+
+    /// Begin recording a command buffer from `command_pool`, eg. `transfer_command_pool`, to be submitted once with
+    /// `end_single_time_commands_in`
+    pub fn begin_single_time_commands_in(
+        &self,
+        command_pool: vk::CommandPool,
+    ) -> vk::CommandBuffer {
         let alloc_info = vk::CommandBufferAllocateInfo::builder()
             .command_buffer_count(1)
             .level(vk::CommandBufferLevel::PRIMARY)
-            .command_pool(self.command_pool);
+            .command_pool(command_pool);
 
         let command_buffer = unsafe {
             self.device
@@ -623,6 +936,17 @@ impl VulkanContext {
     }
 
     pub fn end_single_time_commands(&self, command_buffer: vk::CommandBuffer) {
+        self.end_single_time_commands_in(command_buffer, self.graphics_queue, self.command_pool);
+    }
+
+    /// Submit a command buffer begun with `begin_single_time_commands_in` to `queue`, which must be from the same
+    /// queue family as `command_pool`, and wait for it to finish
+    pub fn end_single_time_commands_in(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+    ) {
         unsafe {
             self.device
                 .end_command_buffer(command_buffer)
@@ -639,41 +963,65 @@ impl VulkanContext {
 
         unsafe {
             self.device
-                .queue_submit(self.graphics_queue, submit_info, vk::Fence::null())
+                .queue_submit(queue, submit_info, vk::Fence::null())
                 .expect("Unable to submit to queue");
             self.device
-                .queue_wait_idle(self.graphics_queue)
+                .queue_wait_idle(queue)
                 .expect("Unable to wait idle");
             self.device
-                .free_command_buffers(self.command_pool, command_buffers)
+                .free_command_buffers(command_pool, command_buffers)
         }
     }
 
-    pub fn create_texture_sampler(
-        &self,
-        address_mode: vk::SamplerAddressMode,
-        mip_count: u32,
-    ) -> Result<vk::Sampler> {
+    /// Get a sampler matching `sampler_info`, creating it if an identical one doesn't already exist.
+    pub fn get_texture_sampler(&self, sampler_info: &SamplerInfo) -> Result<vk::Sampler> {
+        let mut samplers = self.samplers.lock().unwrap();
+        if let Some(sampler) = samplers.get(sampler_info) {
+            return Ok(*sampler);
+        }
+
+        let sampler = self.create_texture_sampler(sampler_info)?;
+        self.set_debug_name(
+            vk::ObjectType::SAMPLER,
+            sampler.as_raw(),
+            &format!("Sampler {:?}", sampler_info),
+        )?;
+        samplers.insert(*sampler_info, sampler);
+
+        Ok(sampler)
+    }
+
+    pub fn create_texture_sampler(&self, sampler_info: &SamplerInfo) -> Result<vk::Sampler> {
         let max_anisotropy = self
             .physical_device_properties
             .limits
             .max_sampler_anisotropy;
+        // Anisotropic filtering would blur nearest filtered textures, eg. pixel art.
+        let anisotropy_enable = self.enabled_features.sampler_anisotropy == vk::TRUE
+            && sampler_info.mag_filter == vk::Filter::LINEAR
+            && sampler_info.min_filter == vk::Filter::LINEAR;
+        // Without a mipmap mode, only the first mip level is sampled. Vulkan's recommended way to do that is to
+        // clamp the LOD to 0.25 with NEAREST mipmapping.
+        let (mipmap_mode, max_lod) = match sampler_info.mipmap_mode {
+            Some(mipmap_mode) => (mipmap_mode, sampler_info.mip_count as f32),
+            None => (vk::SamplerMipmapMode::NEAREST, 0.25),
+        };
         let create_info = vk::SamplerCreateInfo::builder()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .address_mode_u(address_mode)
-            .address_mode_v(address_mode)
-            .address_mode_w(address_mode)
-            .anisotropy_enable(true)
+            .mag_filter(sampler_info.mag_filter)
+            .min_filter(sampler_info.min_filter)
+            .address_mode_u(sampler_info.address_mode_u)
+            .address_mode_v(sampler_info.address_mode_v)
+            .address_mode_w(sampler_info.address_mode_w)
+            .anisotropy_enable(anisotropy_enable)
             .max_anisotropy(max_anisotropy)
             .border_color(vk::BorderColor::INT_OPAQUE_WHITE)
             .unnormalized_coordinates(false)
             .compare_enable(false)
             .compare_op(vk::CompareOp::NEVER)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .mipmap_mode(mipmap_mode)
             .mip_lod_bias(0.0)
             .min_lod(0.0)
-            .max_lod(mip_count as _)
+            .max_lod(max_lod)
             .build();
 
         unsafe {
@@ -692,33 +1040,7 @@ impl VulkanContext {
         offsets: Vec<vk::DeviceSize>,
     ) {
         let command_buffer = self.begin_single_time_commands();
-
-        let mut regions = Vec::new();
-        for layer in 0..layer_count {
-            for mip_level in 0..mip_count {
-                let image_subresource = vk::ImageSubresourceLayers::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .mip_level(mip_level)
-                    .base_array_layer(layer)
-                    .layer_count(1);
-
-                let image_extent = vk::Extent3D {
-                    width: dst_image.extent.width >> mip_level,
-                    height: dst_image.extent.height >> mip_level,
-                    depth: 1,
-                };
-                let offset_index = (layer * mip_count) + mip_level;
-
-                let region = vk::BufferImageCopy::builder()
-                    .buffer_offset(offsets[offset_index as usize])
-                    .buffer_row_length(0)
-                    .buffer_image_height(0)
-                    .image_subresource(*image_subresource)
-                    .image_extent(image_extent)
-                    .build();
-                regions.push(region);
-            }
-        }
+        let regions = get_buffer_image_copies(dst_image, layer_count, mip_count, &offsets);
 
         let dst_image_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
 
@@ -781,7 +1103,7 @@ impl VulkanContext {
         set_layout: vk::DescriptorSetLayout,
         mesh_name: &str,
     ) -> VkResult<Vec<vk::DescriptorSet>> {
-        println!("[HOTHAM_VULKAN] Allocating mesh descriptor sets..");
+        debug!(target: "HOTHAM_VULKAN", "Allocating mesh descriptor sets..");
         let descriptor_sets = unsafe {
             self.device.allocate_descriptor_sets(
                 &vk::DescriptorSetAllocateInfo::builder()
@@ -794,7 +1116,7 @@ impl VulkanContext {
             descriptor_sets[0].as_raw(),
             &format!("Mesh {}", mesh_name),
         )?;
-        println!("[HOTHAM_VULKAN] ..done! {:?}", descriptor_sets);
+        debug!(target: "HOTHAM_VULKAN", "..done! {:?}", descriptor_sets);
 
         Ok(descriptor_sets)
     }
@@ -808,8 +1130,9 @@ impl VulkanContext {
         normal_map: &Texture,
         ao_map: &Texture,
         emissive_map: &Texture,
+        height_map: &Texture,
     ) -> VkResult<Vec<vk::DescriptorSet>> {
-        println!("[HOTHAM_VULKAN] Allocating textures descriptor sets..");
+        debug!(target: "HOTHAM_VULKAN", "Allocating textures descriptor sets..");
         let descriptor_sets = unsafe {
             self.device.allocate_descriptor_sets(
                 &vk::DescriptorSetAllocateInfo::builder()
@@ -823,7 +1146,7 @@ impl VulkanContext {
             descriptor_sets[0].as_raw(),
             &format!("Material {}", material_name),
         )?;
-        println!("[HOTHAM_VULKAN] ..done! {:?}", descriptor_sets);
+        debug!(target: "HOTHAM_VULKAN", "..done! {:?}", descriptor_sets);
 
         unsafe {
             self.device.update_descriptor_sets(
@@ -873,6 +1196,15 @@ impl VulkanContext {
                             .image_view(emissive_map.image.view)
                             .sampler(emissive_map.sampler)
                             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]),
+                    *vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_sets[0])
+                        .dst_binding(5)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&[*vk::DescriptorImageInfo::builder()
+                            .image_view(height_map.image.view)
+                            .sampler(height_map.sampler)
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]),
                 ],
                 &[],
             )
@@ -880,6 +1212,30 @@ impl VulkanContext {
         Ok(descriptor_sets)
     }
 
+    /// Replace the texture at `binding` of a descriptor set created by `create_textures_descriptor_sets`.
+    /// The descriptor set must not be in use by the GPU.
+    pub fn update_texture_descriptor_set(
+        &self,
+        descriptor_set: vk::DescriptorSet,
+        binding: u32,
+        texture: &Texture,
+    ) {
+        unsafe {
+            self.device.update_descriptor_sets(
+                &[*vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[*vk::DescriptorImageInfo::builder()
+                        .image_view(texture.image.view)
+                        .sampler(texture.sampler)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            )
+        }
+    }
+
     pub fn create_scene_data_descriptor_sets(
         &self,
         set_layout: vk::DescriptorSetLayout,
@@ -889,7 +1245,7 @@ impl VulkanContext {
         prefiltered_map: &Texture,
         brdflut: &Texture,
     ) -> VkResult<Vec<vk::DescriptorSet>> {
-        println!("[HOTHAM_VULKAN] Allocating scene data sets..");
+        debug!(target: "HOTHAM_VULKAN", "Allocating scene data sets..");
         let descriptor_sets = unsafe {
             self.device.allocate_descriptor_sets(
                 &vk::DescriptorSetAllocateInfo::builder()
@@ -1040,6 +1396,8 @@ fn create_descriptor_pool(device: &Device) -> Result<vk::DescriptorPool, anyhow:
     let descriptor_pool = unsafe {
         device.create_descriptor_pool(
             &vk::DescriptorPoolCreateInfo::builder()
+                // Sets are freed when the entities that own them are despawned: see `DeletionQueue`.
+                .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
                 .pool_sizes(&[
                     vk::DescriptorPoolSize {
                         ty: vk::DescriptorType::UNIFORM_BUFFER,
@@ -1061,11 +1419,35 @@ fn create_descriptor_pool(device: &Device) -> Result<vk::DescriptorPool, anyhow:
     Ok(descriptor_pool)
 }
 
-fn get_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
-    if format == DEPTH_FORMAT {
-        return vk::ImageAspectFlags::DEPTH;
-    } else {
-        return vk::ImageAspectFlags::COLOR;
+fn select_depth_format(
+    preferred_formats: &[vk::Format],
+    is_supported: impl Fn(vk::Format) -> bool,
+) -> Result<vk::Format, HothamError> {
+    preferred_formats
+        .iter()
+        .cloned()
+        .find(|format| is_supported(*format))
+        .ok_or_else(|| HothamError::InvalidFormatError {
+            format: format!("{:?}", preferred_formats),
+        })
+}
+
+/// Does this depth format have a stencil component?
+pub(crate) fn has_stencil_component(format: vk::Format) -> bool {
+    get_aspect_mask(format).contains(vk::ImageAspectFlags::STENCIL)
+}
+
+pub(crate) fn get_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
     }
 }
 
@@ -1081,10 +1463,12 @@ impl Debug for VulkanContext {
 fn vulkan_init_legacy(
     xr_instance: &xr::Instance,
     system: xr::SystemId,
-) -> Result<(AshInstance, Entry)> {
+    requirements: &xr::vulkan::Requirements,
+    extra_instance_extensions: &[String],
+) -> Result<(AshInstance, Entry, u32)> {
     use crate::util::get_raw_strings;
 
-    println!("[HOTHAM_VULKAN] Initialising Vulkan..");
+    debug!(target: "HOTHAM_VULKAN", "Initialising Vulkan..");
     unsafe {
         let app_name = CString::new("Hotham Asteroid")?;
         let entry = Entry::new()?;
@@ -1100,7 +1484,15 @@ fn vulkan_init_legacy(
         #[cfg(debug_assertions)]
         vk_instance_exts.push(vk::ExtDebugUtilsFn::name().to_owned());
 
-        println!(
+        let available_extensions = entry.enumerate_instance_extension_properties()?;
+        add_extra_extension_names(
+            &mut vk_instance_exts,
+            extra_instance_extensions,
+            &available_extensions,
+        )?;
+
+        debug!(
+            target: "HOTHAM_VULKAN",
             "Required Vulkan instance extensions: {:?}",
             vk_instance_exts
         );
@@ -1109,9 +1501,10 @@ fn vulkan_init_legacy(
             .map(|x| x.as_ptr())
             .collect::<Vec<_>>();
 
+        let api_version = select_api_version(&entry, Some(requirements))?;
         let app_info = vk::ApplicationInfo::builder()
             .application_name(&app_name)
-            .api_version(vk::make_api_version(0, 1, 2, 0));
+            .api_version(api_version);
 
         let validation_features_enables = [
             // vk::ValidationFeatureEnableEXT::BEST_PRACTICES,
@@ -1133,19 +1526,19 @@ fn vulkan_init_legacy(
             )
             .expect("Vulkan error creating Vulkan instance");
 
-        Ok((instance, entry))
+        Ok((instance, entry, api_version))
     }
 }
 
-fn vulkan_init_test() -> Result<(AshInstance, Entry)> {
+fn vulkan_init_test() -> Result<(AshInstance, Entry, u32)> {
     use crate::util::{get_raw_strings, parse_raw_strings};
 
-    println!("[HOTHAM_VULKAN] Initialising Vulkan..");
+    debug!(target: "HOTHAM_VULKAN", "Initialising Vulkan..");
     let app_name = CString::new("Hotham Testing")?;
     let entry = unsafe { Entry::new()? };
     let layers = vec!["VK_LAYER_KHRONOS_validation\0"];
     let layer_names = unsafe { get_raw_strings(layers) };
-    println!("[HOTHAM_VULKAN] Trying to use layers: {:?}", unsafe {
+    debug!(target: "HOTHAM_VULKAN", "Trying to use layers: {:?}", unsafe {
         parse_raw_strings(&layer_names)
     });
     let mut extensions = Vec::new();
@@ -1153,9 +1546,10 @@ fn vulkan_init_test() -> Result<(AshInstance, Entry)> {
 
     let extension_names = extensions.iter().map(|e| e.as_ptr()).collect::<Vec<_>>();
 
+    let api_version = select_api_version(&entry, None)?;
     let app_info = vk::ApplicationInfo::builder()
         .application_name(&app_name)
-        .api_version(vk::make_api_version(0, 1, 2, 0));
+        .api_version(api_version);
     let create_info = vk::InstanceCreateInfo::builder()
         .application_info(&app_info)
         .enabled_extension_names(&extension_names)
@@ -1163,9 +1557,9 @@ fn vulkan_init_test() -> Result<(AshInstance, Entry)> {
 
     let instance = unsafe { entry.create_instance(&create_info, None) }?;
 
-    println!("[HOTHAM_VULKAN] ..done");
+    debug!(target: "HOTHAM_VULKAN", "..done");
 
-    Ok((instance, entry))
+    Ok((instance, entry, api_version))
 }
 
 pub fn create_vulkan_device_legacy(
@@ -1173,8 +1567,10 @@ pub fn create_vulkan_device_legacy(
     system: xr::SystemId,
     vulkan_instance: &AshInstance,
     physical_device: vk::PhysicalDevice,
-) -> Result<(Device, vk::Queue, u32)> {
-    println!("[HOTHAM_VULKAN] Creating logical device.. ");
+    instance_api_version: u32,
+    extra_device_extensions: &[String],
+) -> Result<(Device, QueueFamilyIndices, vk::PhysicalDeviceFeatures, u32)> {
+    debug!(target: "HOTHAM_VULKAN", "Creating logical device..");
 
     let extension_names = xr_instance.vulkan_legacy_device_extensions(system)?;
     let mut extension_names = extension_names
@@ -1184,50 +1580,112 @@ pub fn create_vulkan_device_legacy(
 
     add_device_extension_names(&mut extension_names);
 
-    create_vulkan_device(&extension_names, vulkan_instance, physical_device)
+    let available_extensions =
+        unsafe { vulkan_instance.enumerate_device_extension_properties(physical_device) }?;
+    add_extra_extension_names(
+        &mut extension_names,
+        extra_device_extensions,
+        &available_extensions,
+    )?;
+
+    create_vulkan_device(
+        &extension_names,
+        vulkan_instance,
+        physical_device,
+        instance_api_version,
+    )
+}
+
+/// Add the user's extra extensions to `extension_names`, skipping any that are already there.
+/// Fails if any of them aren't in `available_extensions`.
+fn add_extra_extension_names(
+    extension_names: &mut Vec<CString>,
+    extra_extensions: &[String],
+    available_extensions: &[vk::ExtensionProperties],
+) -> Result<(), HothamError> {
+    let available_extensions = available_extensions
+        .iter()
+        .map(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) })
+        .collect::<Vec<_>>();
+    let missing_extensions = extra_extensions
+        .iter()
+        .filter(|e| {
+            !available_extensions
+                .iter()
+                .any(|a| a.to_bytes() == e.as_bytes())
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing_extensions.is_empty() {
+        return Err(HothamError::UnsupportedExtensions(missing_extensions));
+    }
+
+    for extension in extra_extensions {
+        let extension = CString::new(extension.as_str()).map_err(anyhow::Error::from)?;
+        if !extension_names.contains(&extension) {
+            extension_names.push(extension);
+        }
+    }
+
+    Ok(())
 }
 
+/// Create the logical device. Returns the API version the device supports, up to `instance_api_version`.
 fn create_vulkan_device(
     extension_names: &Vec<std::ffi::CString>,
     vulkan_instance: &AshInstance,
     physical_device: vk::PhysicalDevice,
-) -> Result<(Device, vk::Queue, u32)> {
-    println!(
-        "[HOTHAM_VULKAN] Using device extensions: {:?}",
-        extension_names
+    instance_api_version: u32,
+) -> Result<(Device, QueueFamilyIndices, vk::PhysicalDeviceFeatures, u32)> {
+    let device_api_version =
+        unsafe { vulkan_instance.get_physical_device_properties(physical_device) }.api_version;
+    let api_version = instance_api_version.min(device_api_version);
+    let available_extensions =
+        unsafe { vulkan_instance.enumerate_device_extension_properties(physical_device) }?;
+    let multiview_support = get_multiview_support(api_version, &available_extensions)?;
+    debug!(
+        target: "HOTHAM_VULKAN",
+        "Using Vulkan {}.{}, with multiview through {:?}",
+        vk::api_version_major(api_version),
+        vk::api_version_minor(api_version),
+        multiview_support
     );
+
+    let mut extension_names = extension_names.clone();
+    let mut optional_extension_names = get_multiview_extension_names(multiview_support);
+    if get_memory_budget_support(api_version, &available_extensions) {
+        optional_extension_names.push(vk::ExtMemoryBudgetFn::name().to_owned());
+    }
+    for extension in optional_extension_names {
+        if !extension_names.contains(&extension) {
+            extension_names.push(extension);
+        }
+    }
+
+    debug!(target: "HOTHAM_VULKAN", "Using device extensions: {:?}", extension_names);
     let extension_names = extension_names
         .iter()
         .map(|e| e.as_ptr())
         .collect::<Vec<_>>();
     let queue_priorities = [1.0];
-    let graphics_family_index = unsafe {
-        vulkan_instance
-            .get_physical_device_queue_family_properties(physical_device)
-            .into_iter()
-            .enumerate()
-            .find_map(|(queue_family_index, info)| {
-                if info.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                    Some(queue_family_index as u32)
-                } else {
-                    None
-                }
-            })
-            .ok_or(HothamError::EmptyListError)?
+    let queue_family_indices = unsafe {
+        select_queue_families(
+            &vulkan_instance.get_physical_device_queue_family_properties(physical_device),
+        )?
     };
+    let queue_create_infos = queue_family_indices.create_infos(&queue_priorities);
 
-    let graphics_queue_create_info = vk::DeviceQueueCreateInfo::builder()
-        .queue_priorities(&queue_priorities)
-        .queue_family_index(graphics_family_index)
-        .build();
-
-    let queue_create_infos = [graphics_queue_create_info];
-
-    let physical_device_features = get_physical_device_features();
+    let physical_device_features = get_physical_device_features(unsafe {
+        &vulkan_instance.get_physical_device_features(physical_device)
+    });
     // TODO: Quest 2?
     // physical_device_features.shader_storage_image_multisample(true);
 
-    let multiview = &mut vk::PhysicalDeviceVulkan11Features {
+    let vulkan_11_features = &mut vk::PhysicalDeviceVulkan11Features {
+        multiview: vk::TRUE,
+        ..Default::default()
+    };
+    let multiview_features = &mut vk::PhysicalDeviceMultiviewFeatures {
         multiview: vk::TRUE,
         ..Default::default()
     };
@@ -1235,25 +1693,272 @@ fn create_vulkan_device(
     let device_create_info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
         .enabled_extension_names(&extension_names)
-        .enabled_features(&physical_device_features)
-        .push_next(multiview);
+        .enabled_features(&physical_device_features);
+    let device_create_info = if multiview_support == MultiviewSupport::Vulkan11Features {
+        device_create_info.push_next(vulkan_11_features)
+    } else {
+        device_create_info.push_next(multiview_features)
+    };
 
     let device =
         unsafe { vulkan_instance.create_device(physical_device, &device_create_info, None) }?;
 
-    let graphics_queue = unsafe { device.get_device_queue(graphics_family_index, 0) };
+    debug!(target: "HOTHAM_VULKAN", "..done");
 
-    println!("[HOTHAM_VULKAN] ..done");
+    Ok((
+        device,
+        queue_family_indices,
+        physical_device_features,
+        api_version,
+    ))
+}
 
-    Ok((device, graphics_queue, graphics_family_index))
+/// Pick the newest of `SUPPORTED_API_VERSIONS` supported by the Vulkan loader and, if there is one, the OpenXR
+/// runtime. Fails with `HothamError::UnsupportedVersionError` if none of them are.
+fn select_api_version(
+    entry: &Entry,
+    requirements: Option<&xr::vulkan::Requirements>,
+) -> Result<u32, HothamError> {
+    // Vulkan 1.0 loaders can't report their version.
+    let instance_version = entry
+        .try_enumerate_instance_version()?
+        .unwrap_or_else(|| vk::make_api_version(0, 1, 0, 0));
+    let api_version = get_newest_api_version(instance_version, requirements).ok_or_else(|| {
+        error!(
+            target: "HOTHAM_VULKAN",
+            "None of the supported Vulkan versions are available. Loader: {}.{}, OpenXR requirements: {:?}",
+            vk::api_version_major(instance_version),
+            vk::api_version_minor(instance_version),
+            requirements.map(|r| (r.min_api_version_supported, r.max_api_version_supported))
+        );
+        HothamError::UnsupportedVersionError
+    })?;
+    Ok(api_version)
 }
 
-fn get_physical_device_features() -> vk::PhysicalDeviceFeatures {
+fn get_newest_api_version(
+    instance_version: u32,
+    requirements: Option<&xr::vulkan::Requirements>,
+) -> Option<u32> {
+    SUPPORTED_API_VERSIONS.iter().copied().find(|api_version| {
+        let xr_version = xr::Version::new(
+            vk::api_version_major(*api_version) as _,
+            vk::api_version_minor(*api_version) as _,
+            vk::api_version_patch(*api_version),
+        );
+        *api_version <= instance_version
+            && requirements.map_or(true, |r| {
+                xr_version >= r.min_api_version_supported
+                    && xr_version.major() <= r.max_api_version_supported.major()
+            })
+    })
+}
+
+/// Multiview is core from Vulkan 1.1, and needs `VK_KHR_multiview` on 1.0.
+fn get_multiview_support(
+    api_version: u32,
+    available_extensions: &[vk::ExtensionProperties],
+) -> Result<MultiviewSupport, HothamError> {
+    if api_version >= vk::make_api_version(0, 1, 2, 0) {
+        return Ok(MultiviewSupport::Vulkan11Features);
+    }
+    if api_version >= vk::make_api_version(0, 1, 1, 0) {
+        return Ok(MultiviewSupport::Core);
+    }
+
+    let multiview = vk::KhrMultiviewFn::name();
+    if available_extensions
+        .iter()
+        .any(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) } == multiview)
+    {
+        Ok(MultiviewSupport::Extension)
+    } else {
+        error!(
+            target: "HOTHAM_VULKAN",
+            "Multiview needs Vulkan 1.1 or {:?}, but neither is available",
+            multiview
+        );
+        Err(HothamError::UnsupportedVersionError)
+    }
+}
+
+fn get_multiview_extension_names(multiview_support: MultiviewSupport) -> Vec<CString> {
+    if multiview_support == MultiviewSupport::Extension {
+        vec![vk::KhrMultiviewFn::name().to_owned()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// `VK_EXT_memory_budget` is used if it's available, but reading it needs Vulkan 1.1.
+fn get_memory_budget_support(
+    api_version: u32,
+    available_extensions: &[vk::ExtensionProperties],
+) -> bool {
+    let memory_budget = vk::ExtMemoryBudgetFn::name();
+    api_version >= vk::make_api_version(0, 1, 1, 0)
+        && available_extensions
+            .iter()
+            .any(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) } == memory_budget)
+}
+
+/// The queue families used for each kind of work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QueueFamilyIndices {
+    pub graphics: u32,
+    pub transfer: u32,
+    pub compute: u32,
+}
+
+impl QueueFamilyIndices {
+    /// One queue for each distinct family
+    fn create_infos(&self, queue_priorities: &[f32]) -> Vec<vk::DeviceQueueCreateInfo> {
+        let mut families = vec![self.graphics, self.transfer, self.compute];
+        families.sort_unstable();
+        families.dedup();
+        families
+            .into_iter()
+            .map(|queue_family_index| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(queue_family_index)
+                    .queue_priorities(queue_priorities)
+                    .build()
+            })
+            .collect()
+    }
+}
+
+/// Pick a graphics queue family, and dedicated transfer and compute families if the device has them.
+/// Falls back to the graphics family for transfer and compute work.
+fn select_queue_families(
+    queue_families: &[vk::QueueFamilyProperties],
+) -> Result<QueueFamilyIndices, HothamError> {
+    let find_family = |wanted: vk::QueueFlags, unwanted: vk::QueueFlags| {
+        queue_families
+            .iter()
+            .position(|f| {
+                f.queue_count > 0
+                    && f.queue_flags.contains(wanted)
+                    && !f.queue_flags.intersects(unwanted)
+            })
+            .map(|i| i as u32)
+    };
+
+    let graphics = find_family(vk::QueueFlags::GRAPHICS, vk::QueueFlags::empty())
+        .ok_or(HothamError::EmptyListError)?;
+
+    // Prefer a pure transfer family (usually backed by a DMA engine), then anything that isn't graphics.
+    let transfer = find_family(
+        vk::QueueFlags::TRANSFER,
+        vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+    )
+    .or_else(|| find_family(vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS))
+    .unwrap_or(graphics);
+
+    let compute =
+        find_family(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS).unwrap_or(graphics);
+
+    Ok(QueueFamilyIndices {
+        graphics,
+        transfer,
+        compute,
+    })
+}
+
+/// The queues and command pools for each of the `QueueFamilyIndices`. Families that are shared share their queue and pool.
+struct Queues {
+    graphics_queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    transfer_queue: vk::Queue,
+    transfer_command_pool: vk::CommandPool,
+    compute_queue: vk::Queue,
+    compute_command_pool: vk::CommandPool,
+}
+
+impl Queues {
+    fn new(device: &Device, queue_family_indices: QueueFamilyIndices) -> Result<Self> {
+        debug!(target: "HOTHAM_VULKAN", "Using queue families {:?}", queue_family_indices);
+        let graphics_queue = unsafe { device.get_device_queue(queue_family_indices.graphics, 0) };
+        let command_pool = create_command_pool(device, queue_family_indices.graphics)?;
+
+        let get_queue_and_pool = |queue_family_index: u32| -> Result<_> {
+            if queue_family_index == queue_family_indices.graphics {
+                return Ok((graphics_queue, command_pool));
+            }
+            let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+            let pool = create_command_pool(device, queue_family_index)?;
+            Ok((queue, pool))
+        };
+
+        let (transfer_queue, transfer_command_pool) =
+            get_queue_and_pool(queue_family_indices.transfer)?;
+        let (compute_queue, compute_command_pool) =
+            if queue_family_indices.compute == queue_family_indices.transfer {
+                (transfer_queue, transfer_command_pool)
+            } else {
+                get_queue_and_pool(queue_family_indices.compute)?
+            };
+
+        Ok(Self {
+            graphics_queue,
+            command_pool,
+            transfer_queue,
+            transfer_command_pool,
+            compute_queue,
+            compute_command_pool,
+        })
+    }
+}
+
+/// Enable the optional features Hotham uses, if `supported_features` has them
+fn get_physical_device_features(
+    supported_features: &vk::PhysicalDeviceFeatures,
+) -> vk::PhysicalDeviceFeatures {
     vk::PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true)
+        .sampler_anisotropy(supported_features.sampler_anisotropy == vk::TRUE)
+        .wide_lines(supported_features.wide_lines == vk::TRUE)
+        .sample_rate_shading(supported_features.sample_rate_shading == vk::TRUE)
+        .fill_mode_non_solid(supported_features.fill_mode_non_solid == vk::TRUE)
         .build()
 }
 
+/// A copy into each mip level of each layer of `dst_image`, from the buffer at `offsets`, which are ordered by layer
+/// then mip level
+fn get_buffer_image_copies(
+    dst_image: &Image,
+    layer_count: u32,
+    mip_count: u32,
+    offsets: &[vk::DeviceSize],
+) -> Vec<vk::BufferImageCopy> {
+    let mut regions = Vec::new();
+    for layer in 0..layer_count {
+        for mip_level in 0..mip_count {
+            let image_subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(mip_level)
+                .base_array_layer(layer)
+                .layer_count(1);
+
+            let image_extent = vk::Extent3D {
+                width: dst_image.extent.width >> mip_level,
+                height: dst_image.extent.height >> mip_level,
+                depth: 1,
+            };
+            let offset_index = (layer * mip_count) + mip_level;
+
+            let region = vk::BufferImageCopy::builder()
+                .buffer_offset(offsets[offset_index as usize])
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(*image_subresource)
+                .image_extent(image_extent)
+                .build();
+            regions.push(region);
+        }
+    }
+    regions
+}
+
 fn get_stage(
     old_layout: vk::ImageLayout,
     new_layout: vk::ImageLayout,
@@ -1297,7 +2002,7 @@ fn get_stage(
 
 pub fn get_test_physical_device(instance: &AshInstance) -> vk::PhysicalDevice {
     unsafe {
-        println!("[HOTHAM_VULKAN] Getting physical device..");
+        debug!(target: "HOTHAM_VULKAN", "Getting physical device..");
         let devices = instance.enumerate_physical_devices().unwrap();
         devices[0]
     }
@@ -1312,3 +2017,197 @@ pub const EMPTY_KTX: [u8; 104] = [
     0x6F, 0x6E, 0x00, 0x53, 0x3D, 0x72, 0x2C, 0x54, 0x3D, 0x64, 0x2C, 0x52, 0x3D, 0x69, 0x00, 0x00,
     0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extension_properties(name: &str) -> vk::ExtensionProperties {
+        let mut properties = vk::ExtensionProperties::default();
+        for (c, b) in properties.extension_name.iter_mut().zip(name.bytes()) {
+            *c = b as _;
+        }
+        properties
+    }
+
+    #[test]
+    pub fn test_add_extra_extension_names() {
+        let available_extensions = [
+            extension_properties("VK_KHR_multiview"),
+            extension_properties("VK_EXT_debug_marker"),
+        ];
+        let mut extension_names = vec![CString::new("VK_KHR_multiview").unwrap()];
+
+        // Extensions that are already required aren't added twice.
+        let extra_extensions = [
+            "VK_EXT_debug_marker".to_string(),
+            "VK_KHR_multiview".to_string(),
+        ];
+        add_extra_extension_names(
+            &mut extension_names,
+            &extra_extensions,
+            &available_extensions,
+        )
+        .unwrap();
+        assert_eq!(
+            extension_names,
+            vec![
+                CString::new("VK_KHR_multiview").unwrap(),
+                CString::new("VK_EXT_debug_marker").unwrap()
+            ]
+        );
+
+        // Missing extensions are named in the error.
+        let extra_extensions = ["VK_NV_imaginary".to_string()];
+        match add_extra_extension_names(
+            &mut extension_names,
+            &extra_extensions,
+            &available_extensions,
+        ) {
+            Err(HothamError::UnsupportedExtensions(missing)) => {
+                assert_eq!(missing, vec!["VK_NV_imaginary".to_string()])
+            }
+            _ => panic!("Expected an UnsupportedExtensions error"),
+        }
+        assert_eq!(extension_names.len(), 2);
+    }
+
+    #[test]
+    pub fn test_get_newest_api_version() {
+        let v1_0 = vk::make_api_version(0, 1, 0, 0);
+        let v1_1 = vk::make_api_version(0, 1, 1, 0);
+        let v1_2 = vk::make_api_version(0, 1, 2, 0);
+
+        // Newer loaders are limited to the newest version Hotham supports, and older ones are used as they are.
+        let v1_3 = vk::make_api_version(0, 1, 3, 204);
+        assert_eq!(get_newest_api_version(v1_3, None), Some(v1_2));
+        assert_eq!(
+            get_newest_api_version(vk::make_api_version(0, 1, 1, 100), None),
+            Some(v1_1)
+        );
+
+        // The OpenXR runtime can rule versions out.
+        let requirements = |min: (u16, u16), max: (u16, u16)| xr::vulkan::Requirements {
+            min_api_version_supported: xr::Version::new(min.0, min.1, 0),
+            max_api_version_supported: xr::Version::new(max.0, max.1, 0),
+        };
+        assert_eq!(
+            get_newest_api_version(v1_2, Some(&requirements((1, 0), (1, 2)))),
+            Some(v1_2)
+        );
+        assert_eq!(
+            get_newest_api_version(v1_1, Some(&requirements((1, 0), (1, 2)))),
+            Some(v1_1)
+        );
+        assert_eq!(
+            get_newest_api_version(v1_0, Some(&requirements((1, 1), (1, 2)))),
+            None
+        );
+        assert_eq!(
+            get_newest_api_version(v1_2, Some(&requirements((2, 0), (2, 0)))),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_get_multiview_support() {
+        let v1_0 = vk::make_api_version(0, 1, 0, 0);
+        let with_extension = [extension_properties("VK_KHR_multiview")];
+
+        assert_eq!(
+            get_multiview_support(vk::make_api_version(0, 1, 2, 0), &[]).unwrap(),
+            MultiviewSupport::Vulkan11Features
+        );
+        assert_eq!(
+            get_multiview_support(vk::make_api_version(0, 1, 1, 0), &[]).unwrap(),
+            MultiviewSupport::Core
+        );
+        assert_eq!(
+            get_multiview_support(v1_0, &with_extension).unwrap(),
+            MultiviewSupport::Extension
+        );
+        assert!(matches!(
+            get_multiview_support(v1_0, &[]),
+            Err(HothamError::UnsupportedVersionError)
+        ));
+        assert_eq!(
+            get_multiview_extension_names(MultiviewSupport::Extension),
+            vec![CString::new("VK_KHR_multiview").unwrap()]
+        );
+    }
+
+    #[test]
+    pub fn test_get_memory_budget_support() {
+        let with_extension = [extension_properties("VK_EXT_memory_budget")];
+        let v1_1 = vk::make_api_version(0, 1, 1, 0);
+
+        assert!(get_memory_budget_support(v1_1, &with_extension));
+        assert!(!get_memory_budget_support(v1_1, &[]));
+        // The budget can't be read on Vulkan 1.0.
+        assert!(!get_memory_budget_support(
+            vk::make_api_version(0, 1, 0, 0),
+            &with_extension
+        ));
+    }
+
+    #[test]
+    pub fn test_select_queue_families() {
+        let family = |queue_flags| vk::QueueFamilyProperties {
+            queue_flags,
+            queue_count: 1,
+            ..Default::default()
+        };
+        let all = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+
+        // Dedicated families are preferred..
+        let families = [
+            family(all),
+            family(vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+            family(vk::QueueFlags::TRANSFER),
+        ];
+        let indices = select_queue_families(&families).unwrap();
+        assert_eq!(indices.graphics, 0);
+        assert_eq!(indices.transfer, 2);
+        assert_eq!(indices.compute, 1);
+        assert_eq!(indices.create_infos(&[1.0]).len(), 3);
+
+        // ..then anything that isn't graphics..
+        let families = [
+            family(all),
+            family(vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+        ];
+        let indices = select_queue_families(&families).unwrap();
+        assert_eq!(indices.transfer, 1);
+        assert_eq!(indices.compute, 1);
+        assert_eq!(indices.create_infos(&[1.0]).len(), 2);
+
+        // ..and finally, the graphics family.
+        let indices = select_queue_families(&[family(all)]).unwrap();
+        assert_eq!(
+            indices,
+            QueueFamilyIndices {
+                graphics: 0,
+                transfer: 0,
+                compute: 0
+            }
+        );
+        assert_eq!(indices.create_infos(&[1.0]).len(), 1);
+
+        assert!(select_queue_families(&[family(vk::QueueFlags::TRANSFER)]).is_err());
+    }
+
+    #[test]
+    pub fn test_select_depth_format() {
+        let preferred = crate::DEPTH_FORMATS;
+        let format = select_depth_format(&preferred, |f| f != vk::Format::D32_SFLOAT_S8_UINT);
+        assert_eq!(format.unwrap(), vk::Format::D24_UNORM_S8_UINT);
+        assert!(has_stencil_component(vk::Format::D24_UNORM_S8_UINT));
+        assert!(!has_stencil_component(vk::Format::D32_SFLOAT));
+
+        let format = select_depth_format(&preferred, |_| false);
+        assert!(matches!(
+            format,
+            Err(HothamError::InvalidFormatError { .. })
+        ));
+    }
+}
@@ -1,5 +1,6 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use ash::vk::{self, Handle};
+use log::{debug, info, warn};
 use openxr::{
     self as xr, Action, ActionSet, EventDataBuffer, FrameStream, FrameWaiter, Path, Posef, Session,
     SessionState, Space, Swapchain, Vulkan,
@@ -9,91 +10,239 @@ use xr::{
     SwapchainCreateFlags, SwapchainCreateInfo, SwapchainUsageFlags, Time, View, ViewStateFlags,
 };
 
-use crate::{resources::VulkanContext, BLEND_MODE, COLOR_FORMAT, VIEW_COUNT, VIEW_TYPE};
+use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector3};
+
+use crate::{
+    components::hand::Handedness,
+    resources::{
+        input_bindings::{InputAction, InputBindings},
+        interaction_profiles::InteractionProfiles,
+        VulkanContext, VulkanExtensions,
+    },
+    util::{is_view_valid, isometry_to_posef, posef_to_isometry},
+    BLEND_MODE, COLOR_FORMAT, DEPTH_FORMATS, FAR_PLANE, HDR_COLOR_FORMAT, NEAR_PLANE, VIEW_COUNT,
+    VIEW_TYPE,
+};
+
+/// The colour space to render the swapchain in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapchainColorSpace {
+    /// Standard dynamic range, in `COLOR_FORMAT`. Supported everywhere.
+    Sdr,
+    /// High dynamic range, in `HDR_COLOR_FORMAT` (linear scRGB). Colours brighter than 1.0 aren't clamped, so
+    /// highlights can go past SDR white on headsets that can display them.
+    /// NOTE: HDR10 (PQ encoded `A2B10G10R10`) isn't supported, as it would need the shaders to encode their output.
+    Hdr,
+}
+
+impl Default for SwapchainColorSpace {
+    fn default() -> Self {
+        SwapchainColorSpace::Sdr
+    }
+}
 
 pub struct XrContext {
     pub instance: openxr::Instance,
     pub session: Session<Vulkan>,
     pub session_state: SessionState,
     pub swapchain: Swapchain<Vulkan>,
+    /// A swapchain the renderer resolves depth into, so the compositor can use it to reproject more accurately.
+    /// Only created if the runtime supports `XR_KHR_composition_layer_depth` and the device can resolve depth.
+    pub depth_swapchain: Option<Swapchain<Vulkan>>,
+    /// Whether depth is submitted to the compositor, if there's a `depth_swapchain`. Defaults to `true`.
+    pub submit_depth: bool,
+    /// Whether the renderer resolved depth into the current `depth_swapchain` image: see `RenderContext::has_resolved_depth`.
+    pub(crate) depth_resolved: bool,
     pub reference_space: Space,
+    /// Offset of `reference_space` from the runtime's space of `reference_space_type`, eg. after the player has snap
+    /// turned
+    pub reference_space_offset: Isometry3<f32>,
+    /// The runtime space `reference_space` is based on. See `set_reference_space_type`.
+    reference_space_type: ReferenceSpaceType,
+    /// How far `reference_space` is raised above the `LOCAL` space. See `set_seated_height_offset`.
+    seated_height_offset: f32,
     pub action_set: ActionSet,
     pub pose_action: Action<Posef>,
     pub grab_action: Action<f32>,
     pub trigger_action: Action<f32>,
     pub haptic_feedback_action: Action<Haptic>,
+    pub thumbstick_action: Action<xr::Vector2f>,
     pub left_hand_space: Space,
     pub left_hand_subaction_path: Path,
     pub left_pointer_space: Space,
     pub right_hand_space: Space,
     pub right_hand_subaction_path: Path,
     pub right_pointer_space: Space,
+    /// The physical inputs the actions were bound to when the session was created. See `InputBindings`.
+    pub input_bindings: InputBindings,
+    /// The interaction profile currently driving each hand. Updated when the runtime reports a change, eg. from
+    /// controllers to hand tracking.
+    pub interaction_profiles: InteractionProfiles,
+    /// Action and space for the combined gaze of both eyes, if `XR_EXT_eye_gaze_interaction` is available.
+    /// See `GazeContext`
+    pub eye_gaze: Option<(Action<Posef>, Space)>,
+    /// Space tracking the user's head
+    pub view_space: Space,
     pub swapchain_resolution: vk::Extent2D,
+    /// Format of the swapchain images. `HDR_COLOR_FORMAT` if HDR was requested and is supported, otherwise
+    /// `COLOR_FORMAT`. See `XrContext::new_with_color_space`.
+    pub swapchain_format: vk::Format,
+    /// The colour space the swapchain is rendered in. May differ from the one requested.
+    pub swapchain_color_space: SwapchainColorSpace,
+    /// The area of each swapchain image that views are rendered to and submitted from. Defaults to the whole image.
+    /// Set it with `XrContext::new_with_image_rect`, eg. for runtimes that expect views in a sub-rect of a shared image:
+    /// the `RenderContext` is created to fit it, so changing it afterwards breaks rendering.
+    /// NOTE: Views are rendered with multiview, so both eyes share the same rect on their own array layer.
+    pub image_rect: vk::Rect2D,
     pub frame_waiter: FrameWaiter,
     pub frame_stream: FrameStream<Vulkan>,
     pub frame_state: FrameState,
     pub views: Vec<View>,
     pub view_state_flags: ViewStateFlags,
     pub frame_index: usize,
+    pub composition_layer_flags: xr::CompositionLayerFlags,
+    /// The form factor the system was created with. May differ from the one requested - see `XrContext::new_with_form_factor`
+    pub form_factor: xr::FormFactor,
+    /// The view configuration implied by `form_factor`
+    pub view_type: xr::ViewConfigurationType,
+    /// The environment blend mode used when submitting frames
+    pub blend_mode: xr::EnvironmentBlendMode,
+    /// The display's refresh rate in Hz, if the runtime supports `XR_FB_display_refresh_rate`. Updated when the
+    /// runtime reports that it has changed, so use it rather than assuming a fixed rate. See
+    /// `XrContext::request_display_refresh_rate`.
+    pub display_refresh_rate: Option<f32>,
+    /// Whether the views are located again just before the frame is submitted, as late as possible, rather than only
+    /// in `begin_frame`. Defaults to `false`. See `end_frame`.
+    pub late_latching: bool,
 }
 
 impl XrContext {
     pub fn new() -> Result<(XrContext, VulkanContext)> {
-        let (instance, system) = create_xr_instance()?;
-        XrContext::_new(instance, system)
+        Self::new_with_form_factor(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+    }
+
+    /// Create an `XrContext` for the given form factor, eg. `HANDHELD_DISPLAY` for phone or tablet AR.
+    /// If the runtime doesn't support the requested form factor, falls back to `HEAD_MOUNTED_DISPLAY`.
+    pub fn new_with_form_factor(form_factor: xr::FormFactor) -> Result<(XrContext, VulkanContext)> {
+        Self::new_with_vulkan_extensions(form_factor, &Default::default())
+    }
+
+    /// Create an `XrContext` for the given form factor, enabling `vulkan_extensions` as well as those Hotham and the
+    /// OpenXR runtime require. See `XrContext::new_with_form_factor`.
+    pub fn new_with_vulkan_extensions(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: &VulkanExtensions,
+    ) -> Result<(XrContext, VulkanContext)> {
+        Self::new_with_color_space(form_factor, vulkan_extensions, Default::default())
+    }
+
+    /// Create an `XrContext`, rendering the swapchain in `color_space`. If HDR is requested but the runtime doesn't
+    /// offer `HDR_COLOR_FORMAT`, falls back to SDR with a warning. See `XrContext::new_with_vulkan_extensions`.
+    pub fn new_with_color_space(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: &VulkanExtensions,
+        color_space: SwapchainColorSpace,
+    ) -> Result<(XrContext, VulkanContext)> {
+        Self::new_with_input_bindings(
+            form_factor,
+            vulkan_extensions,
+            color_space,
+            &Default::default(),
+        )
+    }
+
+    /// Create an `XrContext`, binding its actions to the physical inputs in `input_bindings` rather than the defaults.
+    /// Bindings can't be changed once the session is created. See `InputBindings`.
+    pub fn new_with_input_bindings(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: &VulkanExtensions,
+        color_space: SwapchainColorSpace,
+        input_bindings: &InputBindings,
+    ) -> Result<(XrContext, VulkanContext)> {
+        Self::new_with_image_rect(
+            form_factor,
+            vulkan_extensions,
+            color_space,
+            input_bindings,
+            None,
+        )
+    }
+
+    /// Create an `XrContext` that renders views to, and submits them from, `image_rect` of each swapchain image rather
+    /// than the whole image. `None` uses the whole image. See `XrContext::new_with_input_bindings`.
+    pub fn new_with_image_rect(
+        form_factor: xr::FormFactor,
+        vulkan_extensions: &VulkanExtensions,
+        color_space: SwapchainColorSpace,
+        input_bindings: &InputBindings,
+        image_rect: Option<vk::Rect2D>,
+    ) -> Result<(XrContext, VulkanContext)> {
+        let instance = create_xr_instance()?;
+        XrContext::_new(
+            instance,
+            form_factor,
+            vulkan_extensions,
+            color_space,
+            input_bindings,
+            image_rect,
+        )
     }
 
     pub fn new_from_path(path: &std::path::Path) -> Result<(XrContext, VulkanContext)> {
-        let (instance, system) = create_xr_instance_from_path(path)?;
-        XrContext::_new(instance, system)
+        let instance = create_xr_instance_from_path(path)?;
+        XrContext::_new(
+            instance,
+            xr::FormFactor::HEAD_MOUNTED_DISPLAY,
+            &Default::default(),
+            Default::default(),
+            &Default::default(),
+            None,
+        )
     }
 
-    fn _new(instance: xr::Instance, system: xr::SystemId) -> Result<(XrContext, VulkanContext)> {
-        let vulkan_context = create_vulkan_context(&instance, system)?;
+    fn _new(
+        instance: xr::Instance,
+        requested_form_factor: xr::FormFactor,
+        vulkan_extensions: &VulkanExtensions,
+        requested_color_space: SwapchainColorSpace,
+        input_bindings: &InputBindings,
+        image_rect: Option<vk::Rect2D>,
+    ) -> Result<(XrContext, VulkanContext)> {
+        let (system, form_factor) = get_system(&instance, requested_form_factor)?;
+        let view_type = get_view_type(form_factor);
+        let blend_mode = get_blend_mode(&instance, system, view_type, form_factor)?;
+        info!(
+            target: "HOTHAM_XR",
+            "Using form factor {:?}, view type {:?} and blend mode {:?}",
+            form_factor, view_type, blend_mode
+        );
+        let vulkan_context = create_vulkan_context(&instance, system, vulkan_extensions)?;
         let (session, frame_waiter, frame_stream) =
             create_xr_session(&instance, system, &vulkan_context)?;
         let reference_space =
             session.create_reference_space(ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)?;
-        let swapchain_resolution = get_swapchain_resolution(&instance, system)?;
-        let swapchain = create_xr_swapchain(&session, &swapchain_resolution, VIEW_COUNT)?;
+        let swapchain_resolution = get_swapchain_resolution(&instance, system, view_type)?;
+        let (swapchain_format, swapchain_color_space) =
+            get_swapchain_format(&instance, &session, requested_color_space)?;
+        info!(
+            target: "HOTHAM_XR",
+            "Using swapchain format {:?} ({:?})",
+            swapchain_format, swapchain_color_space
+        );
+        let swapchain = create_xr_swapchain(
+            &session,
+            &swapchain_resolution,
+            VIEW_COUNT,
+            swapchain_format,
+        )?;
+        let depth_swapchain =
+            create_xr_depth_swapchain(&instance, &session, &vulkan_context, &swapchain_resolution)?;
 
         // Create an action set to encapsulate our actions
         let action_set = instance.create_action_set("input", "input pose information", 0)?;
 
         let left_hand_subaction_path = instance.string_to_path("/user/hand/left").unwrap();
         let right_hand_subaction_path = instance.string_to_path("/user/hand/right").unwrap();
-        let left_hand_pose_path = instance
-            .string_to_path("/user/hand/left/input/grip/pose")
-            .unwrap();
-        let left_pointer_path = instance
-            .string_to_path("/user/hand/left/input/aim/pose")
-            .unwrap();
-        let right_hand_pose_path = instance
-            .string_to_path("/user/hand/right/input/grip/pose")
-            .unwrap();
-        let right_pointer_path = instance
-            .string_to_path("/user/hand/right/input/aim/pose")
-            .unwrap();
-
-        let left_hand_grip_squeeze_path = instance
-            .string_to_path("/user/hand/left/input/squeeze/value")
-            .unwrap();
-        let left_hand_grip_trigger_path = instance
-            .string_to_path("/user/hand/left/input/trigger/value")
-            .unwrap();
-        let left_hand_haptic_feedback_path = instance
-            .string_to_path("/user/hand/left/output/haptic")
-            .unwrap();
-
-        let right_hand_grip_squeeze_path = instance
-            .string_to_path("/user/hand/right/input/squeeze/value")
-            .unwrap();
-        let right_hand_grip_trigger_path = instance
-            .string_to_path("/user/hand/right/input/trigger/value")
-            .unwrap();
-        let right_hand_haptic_feedback_path = instance
-            .string_to_path("/user/hand/right/output/haptic")
-            .unwrap();
 
         let pose_action = action_set.create_action::<xr::Posef>(
             "hand_pose",
@@ -125,24 +274,30 @@ impl XrContext {
             &[left_hand_subaction_path, right_hand_subaction_path],
         )?;
 
+        let thumbstick_action = action_set.create_action::<xr::Vector2f>(
+            "thumbstick",
+            "Thumbstick",
+            &[left_hand_subaction_path, right_hand_subaction_path],
+        )?;
+
         // Bind our actions to input devices using the given profile
+        let mut bindings = Vec::new();
+        for (action, paths) in input_bindings.iter() {
+            for path in paths {
+                let path = instance.string_to_path(path)?;
+                bindings.push(match action {
+                    InputAction::HandPose => xr::Binding::new(&pose_action, path),
+                    InputAction::PointerPose => xr::Binding::new(&aim_action, path),
+                    InputAction::Grab => xr::Binding::new(&grab_action, path),
+                    InputAction::Trigger => xr::Binding::new(&trigger_action, path),
+                    InputAction::HapticFeedback => xr::Binding::new(&haptic_feedback_action, path),
+                    InputAction::Thumbstick => xr::Binding::new(&thumbstick_action, path),
+                });
+            }
+        }
         instance.suggest_interaction_profile_bindings(
-            instance
-                .string_to_path("/interaction_profiles/oculus/touch_controller")
-                .unwrap(),
-            &[
-                xr::Binding::new(&pose_action, left_hand_pose_path),
-                xr::Binding::new(&pose_action, right_hand_pose_path),
-                xr::Binding::new(&aim_action, left_pointer_path),
-                xr::Binding::new(&aim_action, right_pointer_path),
-                xr::Binding::new(&grab_action, left_hand_grip_squeeze_path),
-                xr::Binding::new(&grab_action, right_hand_grip_squeeze_path),
-                xr::Binding::new(&trigger_action, left_hand_grip_trigger_path),
-                xr::Binding::new(&trigger_action, right_hand_grip_trigger_path),
-                xr::Binding::new(&grab_action, right_hand_grip_squeeze_path),
-                xr::Binding::new(&haptic_feedback_action, left_hand_haptic_feedback_path),
-                xr::Binding::new(&haptic_feedback_action, right_hand_haptic_feedback_path),
-            ],
+            instance.string_to_path(&input_bindings.interaction_profile)?,
+            &bindings,
         )?;
 
         let left_hand_space =
@@ -158,12 +313,26 @@ impl XrContext {
         let right_pointer_space =
             aim_action.create_space(session.clone(), left_hand_subaction_path, Posef::IDENTITY)?;
 
+        // Eye gaze has its own interaction profile, so it only needs to be bound if the extension is enabled.
+        let eye_gaze = if instance.exts().ext_eye_gaze_interaction.is_some() {
+            Some(create_eye_gaze_action(&instance, &session, &action_set)?)
+        } else {
+            None
+        };
+        let view_space =
+            session.create_reference_space(ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)?;
+
         let frame_state = FrameState {
             predicted_display_time: Time::from_nanos(0),
             predicted_display_period: Duration::from_nanos(0),
             should_render: false,
         };
 
+        let display_refresh_rate = get_display_refresh_rate(&instance, &session);
+        if let Some(rate) = display_refresh_rate {
+            info!(target: "HOTHAM_XR", "Display refresh rate is {}Hz", rate);
+        }
+
         // Attach the action set to the session
         session.attach_action_sets(&[&action_set])?;
         let xr_context = XrContext {
@@ -171,25 +340,48 @@ impl XrContext {
             session,
             session_state: SessionState::IDLE,
             swapchain,
+            depth_swapchain,
+            submit_depth: true,
+            depth_resolved: false,
             reference_space,
+            reference_space_offset: Isometry3::identity(),
+            reference_space_type: ReferenceSpaceType::STAGE,
+            seated_height_offset: 0.,
             action_set,
             pose_action,
             trigger_action,
             grab_action,
             haptic_feedback_action,
+            thumbstick_action,
             left_hand_space,
             left_pointer_space,
             left_hand_subaction_path,
             right_hand_space,
             right_pointer_space,
             right_hand_subaction_path,
+            input_bindings: input_bindings.clone(),
+            eye_gaze,
+            view_space,
             swapchain_resolution,
+            swapchain_format,
+            swapchain_color_space,
+            image_rect: image_rect.unwrap_or(vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent: swapchain_resolution,
+            }),
             frame_waiter,
             frame_stream,
             frame_state,
             views: Vec::new(),
             view_state_flags: ViewStateFlags::EMPTY,
             frame_index: 0,
+            composition_layer_flags: xr::CompositionLayerFlags::EMPTY,
+            form_factor,
+            view_type,
+            blend_mode,
+            display_refresh_rate,
+            interaction_profiles: Default::default(),
+            late_latching: false,
         };
 
         Ok((xr_context, vulkan_context))
@@ -203,14 +395,27 @@ impl XrContext {
             match self.instance.poll_event(event_buffer)? {
                 Some(xr::Event::SessionStateChanged(session_changed)) => {
                     let new_state = session_changed.state();
-                    println!("[HOTHAM_POLL_EVENT] State is now {:?}", new_state);
+                    info!(target: "HOTHAM_POLL_EVENT", "State is now {:?}", new_state);
                     self.session_state = new_state;
                 }
+                Some(xr::Event::DisplayRefreshRateChangedFB(changed)) => {
+                    let rate = changed.to_display_refresh_rate();
+                    info!(
+                        target: "HOTHAM_POLL_EVENT",
+                        "Display refresh rate changed from {}Hz to {}Hz",
+                        changed.from_display_refresh_rate(),
+                        rate
+                    );
+                    self.display_refresh_rate = Some(rate);
+                }
+                Some(xr::Event::InteractionProfileChanged(_)) => {
+                    self.update_interaction_profiles()?;
+                }
                 Some(xr::Event::InstanceLossPending(_)) => {
-                    println!("[HOTHAM_POLL_EVENT] Instance loss pending!");
+                    warn!(target: "HOTHAM_POLL_EVENT", "Instance loss pending!");
                     break;
                 }
-                Some(_) => println!("[HOTHAM_POLL_EVENT] Received some other event"),
+                Some(_) => debug!(target: "HOTHAM_POLL_EVENT", "Received some other event"),
                 None => break,
             }
         }
@@ -218,6 +423,58 @@ impl XrContext {
         Ok(self.session_state)
     }
 
+    /// Re-read the interaction profile of each hand from the runtime. A hand with no device driving it has no profile.
+    fn update_interaction_profiles(&mut self) -> Result<()> {
+        let hands = [
+            (Handedness::Left, self.left_hand_subaction_path),
+            (Handedness::Right, self.right_hand_subaction_path),
+        ];
+        for (handedness, subaction_path) in hands {
+            let path = self.session.current_interaction_profile(subaction_path)?;
+            let profile = if path == Path::NULL {
+                None
+            } else {
+                Some(self.instance.path_to_string(path)?)
+            };
+            if self.interaction_profiles.set(handedness, profile.clone()) {
+                info!(
+                    target: "HOTHAM_POLL_EVENT",
+                    "{:?} hand's interaction profile is now {:?}",
+                    handedness,
+                    profile
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Locate the views at the frame's predicted display time. Handheld (mono) displays only have a single view, which
+    /// is rendered to both layers of the multiview swapchain.
+    pub(crate) fn locate_views(&self) -> Result<(ViewStateFlags, Vec<View>)> {
+        let (view_state_flags, mut views) = self.session.locate_views(
+            self.view_type,
+            self.frame_state.predicted_display_time,
+            &self.reference_space,
+        )?;
+        if views.len() == 1 {
+            views.push(views[0]);
+        }
+        Ok((view_state_flags, views))
+    }
+
+    /// Locate the views again, with the latest tracking data the runtime has for the predicted display time, and use
+    /// them from now on. Views that aren't valid are ignored, so the ones located earlier in the frame are kept.
+    /// Returns whether the views were updated.
+    pub(crate) fn late_latch_views(&mut self) -> Result<bool> {
+        let (view_state_flags, views) = self.locate_views()?;
+        if !is_view_valid(&view_state_flags) {
+            return Ok(false);
+        }
+        self.views = views;
+        self.view_state_flags = view_state_flags;
+        Ok(true)
+    }
+
     pub(crate) fn begin_frame(&mut self) -> Result<()> {
         self.frame_state = self.frame_waiter.wait()?;
         self.frame_stream.begin()?;
@@ -225,23 +482,40 @@ impl XrContext {
         self.frame_index = self.swapchain.acquire_image()? as _;
         self.swapchain.wait_image(openxr::Duration::INFINITE)?;
 
+        // The renderer always resolves into the depth image with the same index as the colour image.
+        if let Some(depth_swapchain) = &mut self.depth_swapchain {
+            depth_swapchain.acquire_image()?;
+            depth_swapchain.wait_image(openxr::Duration::INFINITE)?;
+        }
+
         Ok(())
     }
 
     pub fn end_frame(&mut self) -> std::result::Result<(), openxr::sys::Result> {
         // Submit the image to OpenXR
         self.swapchain.release_image().unwrap();
+        if let Some(depth_swapchain) = &mut self.depth_swapchain {
+            depth_swapchain.release_image().unwrap();
+        }
 
         let rect = xr::Rect2Di {
-            offset: xr::Offset2Di { x: 0, y: 0 },
+            offset: xr::Offset2Di {
+                x: self.image_rect.offset.x,
+                y: self.image_rect.offset.y,
+            },
             extent: xr::Extent2Di {
-                width: self.swapchain_resolution.width as _,
-                height: self.swapchain_resolution.height as _,
+                width: self.image_rect.extent.width as _,
+                height: self.image_rect.extent.height as _,
             },
         };
 
         let display_time = self.frame_state.predicted_display_time;
 
+        // Nothing was rendered this frame (eg. it was dropped), so don't submit a stale image.
+        if !self.frame_state.should_render {
+            return self.frame_stream.end(display_time, self.blend_mode, &[]);
+        }
+
         let views = [
             xr::CompositionLayerProjectionView::new()
                 .pose(self.views[0].pose)
@@ -263,29 +537,211 @@ impl XrContext {
                 ),
         ];
 
+        // Depth is chained onto each view. The depth infos have to outlive the views, as only pointers to them are kept.
+        let depth_infos = match &self.depth_swapchain {
+            Some(depth_swapchain) if self.submit_depth && self.depth_resolved => Some([
+                get_depth_info(depth_swapchain, 0, rect),
+                get_depth_info(depth_swapchain, 1, rect),
+            ]),
+            _ => None,
+        };
+        let views = match &depth_infos {
+            Some(depth_infos) => {
+                let [left, right] = views;
+                [
+                    chain_depth_info(left, &depth_infos[0]),
+                    chain_depth_info(right, &depth_infos[1]),
+                ]
+            }
+            None => views,
+        };
+
+        // Handheld displays only have a single view.
+        let view_count = get_view_count(self.view_type);
         let layer_projection = xr::CompositionLayerProjection::new()
+            .layer_flags(self.composition_layer_flags)
             .space(&self.reference_space)
-            .views(&views);
+            .views(&views[..view_count]);
 
         let layers = [&*layer_projection];
-        self.frame_stream.end(display_time, BLEND_MODE, &layers)
+        self.frame_stream
+            .end(display_time, self.blend_mode, &layers)
+    }
+
+    /// Rotate the reference space about the vertical axis through `pivot`, eg. to snap turn the player.
+    /// `pivot` is in the current reference space - typically the position of the player's head.
+    pub fn rotate_reference_space(&mut self, angle: f32, pivot: Vector3<f32>) -> Result<()> {
+        let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle);
+        self.reference_space_offset *= Isometry3::rotation_wrt_point(rotation, Point3::from(pivot));
+        self.update_reference_space()
+    }
+
+    /// Base `reference_space` on the runtime's `reference_space_type`: `STAGE`, the default, for standing or room-scale
+    /// experiences, with its origin on the floor, or `LOCAL` for seated ones, with its origin at the user's head when
+    /// they were seated. Any offset from snap turning is kept.
+    pub fn set_reference_space_type(
+        &mut self,
+        reference_space_type: ReferenceSpaceType,
+    ) -> Result<()> {
+        self.reference_space_type = reference_space_type;
+        self.update_reference_space()
+    }
+
+    /// The runtime space `reference_space` is based on
+    pub fn reference_space_type(&self) -> ReferenceSpaceType {
+        self.reference_space_type
+    }
+
+    /// Raise the origin of a seated (`LOCAL`) reference space by `offset` metres, so content authored with its floor
+    /// at zero sits at the right height for the user's eyes. Calibrate it per user, eg. to their seated eye height.
+    /// Runtimes disagree on where the seated origin is, so there's no sensible default: it's `0.0` until set.
+    ///
+    /// Every pose located in `reference_space` moves up by `offset`. Has no effect in `STAGE`, whose floor is known.
+    pub fn set_seated_height_offset(&mut self, offset: f32) -> Result<()> {
+        self.seated_height_offset = offset;
+        self.update_reference_space()
+    }
+
+    /// How far the origin of a seated reference space is raised, in metres. See `set_seated_height_offset`.
+    pub fn seated_height_offset(&self) -> f32 {
+        self.seated_height_offset
+    }
+
+    /// Recreate `reference_space` with the current type and offsets
+    fn update_reference_space(&mut self) -> Result<()> {
+        let pose = get_reference_space_pose(
+            self.reference_space_offset,
+            self.reference_space_type,
+            self.seated_height_offset,
+        );
+        self.reference_space = self
+            .session
+            .create_reference_space(self.reference_space_type, isometry_to_posef(pose))?;
+        Ok(())
+    }
+
+    /// The pose of the user's head: midway between the eyes, as located by `begin_frame` for this frame's predicted
+    /// display time. Like every pose Hotham locates, it's in the active reference space, `reference_space`. Use it to
+    /// turn UI to face the user, position an audio listener or check how close something is.
+    ///
+    /// With a flat (handheld) form factor, this is the pose of the camera.
+    pub fn head_pose(&self) -> Posef {
+        get_head_pose(&self.views)
+    }
+
+    /// The display refresh rates the runtime supports, in Hz. Empty if it doesn't support `XR_FB_display_refresh_rate`.
+    pub fn supported_display_refresh_rates(&self) -> Vec<f32> {
+        let ext = match self.instance.exts().fb_display_refresh_rate {
+            Some(ext) => ext,
+            None => return Vec::new(),
+        };
+
+        let session = self.session.as_raw();
+        let mut count = 0;
+        let result = unsafe {
+            (ext.enumerate_display_refresh_rates)(session, 0, &mut count, std::ptr::null_mut())
+        };
+        if result != xr::sys::Result::SUCCESS {
+            warn!(target: "HOTHAM_XR", "Unable to get display refresh rates: {:?}", result);
+            return Vec::new();
+        }
+
+        let mut rates = vec![0.0; count as usize];
+        let result = unsafe {
+            (ext.enumerate_display_refresh_rates)(session, count, &mut count, rates.as_mut_ptr())
+        };
+        if result != xr::sys::Result::SUCCESS {
+            warn!(target: "HOTHAM_XR", "Unable to get display refresh rates: {:?}", result);
+            return Vec::new();
+        }
+        rates.truncate(count as usize);
+        rates
+    }
+
+    /// Ask the runtime to change the display's refresh rate to the supported rate closest to `rate`, in Hz (eg. 72,
+    /// 90 or 120 on a Quest). Returns the rate requested, or `None` if the runtime doesn't support changing it.
+    ///
+    /// The change isn't immediate: `display_refresh_rate` is updated when the runtime reports that it has happened.
+    pub fn request_display_refresh_rate(&self, rate: f32) -> Option<f32> {
+        let ext = self.instance.exts().fb_display_refresh_rate?;
+        let rate = get_closest_refresh_rate(&self.supported_display_refresh_rates(), rate)?;
+
+        let result = unsafe { (ext.request_display_refresh_rate)(self.session.as_raw(), rate) };
+        if result != xr::sys::Result::SUCCESS {
+            warn!(
+                target: "HOTHAM_XR",
+                "Unable to request a display refresh rate of {}Hz: {:?}",
+                rate, result
+            );
+            return None;
+        }
+
+        info!(target: "HOTHAM_XR", "Requested a display refresh rate of {}Hz", rate);
+        Some(rate)
+    }
+
+    /// Set the flags used for the projection layer submitted in `end_frame`.
+    /// Combinations that the compositor will ignore are still applied, but a warning is logged.
+    pub fn set_composition_layer_flags(&mut self, flags: xr::CompositionLayerFlags) {
+        for warning in validate_composition_layer_flags(flags, self.blend_mode) {
+            warn!(target: "HOTHAM_XR", "{}", warning);
+        }
+        self.composition_layer_flags = flags;
+    }
+
+    /// Check that `input_bindings` only uses well-formed OpenXR paths, eg. before saving remapped controls. This can't
+    /// tell whether the interaction profile actually has the inputs: the runtime only checks that when the bindings are
+    /// suggested, in `XrContext::new_with_input_bindings`.
+    pub fn check_input_bindings(&self, input_bindings: &InputBindings) -> Result<()> {
+        let profile = &input_bindings.interaction_profile;
+        self.instance
+            .string_to_path(profile)
+            .map_err(|e| anyhow!("Invalid interaction profile {}: {:?}", profile, e))?;
+        for (action, paths) in input_bindings.iter() {
+            for path in paths {
+                self.instance
+                    .string_to_path(path)
+                    .map_err(|e| anyhow!("Invalid path {} for {:?}: {:?}", path, action, e))?;
+            }
+        }
+        Ok(())
     }
 
     pub(crate) fn end_session(&mut self) -> anyhow::Result<()> {
-        println!("[HOTHAM_XR] - Ending session..");
+        info!(target: "HOTHAM_XR", "Ending session..");
         self.session.end()?;
-        println!("[HOTHAM_XR] - ..done!");
+        debug!(target: "HOTHAM_XR", "..done!");
         Ok(())
     }
 }
 
+fn validate_composition_layer_flags(
+    flags: xr::CompositionLayerFlags,
+    blend_mode: xr::EnvironmentBlendMode,
+) -> Vec<&'static str> {
+    let mut warnings = Vec::new();
+    let source_alpha = flags.contains(xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA);
+
+    if flags.contains(xr::CompositionLayerFlags::UNPREMULTIPLIED_ALPHA) && !source_alpha {
+        warnings.push("UNPREMULTIPLIED_ALPHA has no effect without BLEND_TEXTURE_SOURCE_ALPHA");
+    }
+
+    if source_alpha && blend_mode == xr::EnvironmentBlendMode::OPAQUE {
+        warnings.push("BLEND_TEXTURE_SOURCE_ALPHA has no effect on the only layer when the environment blend mode is OPAQUE");
+    }
+
+    warnings
+}
+
 #[cfg(not(target_os = "android"))]
 pub(crate) fn create_vulkan_context(
     xr_instance: &xr::Instance,
     system: xr::SystemId,
+    vulkan_extensions: &VulkanExtensions,
 ) -> Result<VulkanContext, crate::hotham_error::HothamError> {
-    let vulkan_context = VulkanContext::create_from_xr_instance_legacy(xr_instance, system)?;
-    println!("[HOTHAM_VULKAN] - Vulkan Context created successfully");
+    let vulkan_context =
+        VulkanContext::create_from_xr_instance_legacy(xr_instance, system, vulkan_extensions)?;
+    debug!(target: "HOTHAM_VULKAN", "Vulkan Context created successfully");
     Ok(vulkan_context)
 }
 
@@ -293,18 +749,21 @@ pub(crate) fn create_vulkan_context(
 fn create_vulkan_context(
     xr_instance: &xr::Instance,
     system: xr::SystemId,
+    vulkan_extensions: &VulkanExtensions,
 ) -> Result<VulkanContext, crate::hotham_error::HothamError> {
-    let vulkan_context = VulkanContext::create_from_xr_instance_legacy(xr_instance, system)?;
-    println!("[HOTHAM_VULKAN] - Vulkan Context created successfully");
+    let vulkan_context =
+        VulkanContext::create_from_xr_instance_legacy(xr_instance, system, vulkan_extensions)?;
+    debug!(target: "HOTHAM_VULKAN", "Vulkan Context created successfully");
     Ok(vulkan_context)
 }
 
 pub(crate) fn get_swapchain_resolution(
     xr_instance: &xr::Instance,
     system: xr::SystemId,
+    view_type: xr::ViewConfigurationType,
 ) -> Result<vk::Extent2D> {
-    let views = xr_instance.enumerate_view_configuration_views(system, VIEW_TYPE)?;
-    println!("[HOTHAM_VULKAN] Views: {:?}", views);
+    let views = xr_instance.enumerate_view_configuration_views(system, view_type)?;
+    debug!(target: "HOTHAM_VULKAN", "Views: {:?}", views);
     let resolution = vk::Extent2D {
         width: views[0].recommended_image_rect_width,
         height: views[0].recommended_image_rect_height,
@@ -317,12 +776,16 @@ pub(crate) fn create_xr_swapchain(
     xr_session: &Session<Vulkan>,
     resolution: &vk::Extent2D,
     array_size: u32,
+    format: vk::Format,
 ) -> Result<Swapchain<Vulkan>> {
     xr_session
         .create_swapchain(&SwapchainCreateInfo {
             create_flags: SwapchainCreateFlags::EMPTY,
-            usage_flags: SwapchainUsageFlags::COLOR_ATTACHMENT,
-            format: COLOR_FORMAT.as_raw() as u32,
+            // Images are blitted to when the render scale isn't 1.0, and copied from by `FrameReadback`.
+            usage_flags: SwapchainUsageFlags::COLOR_ATTACHMENT
+                | SwapchainUsageFlags::TRANSFER_DST
+                | SwapchainUsageFlags::TRANSFER_SRC,
+            format: format.as_raw() as u32,
             sample_count: 1,
             width: resolution.width,
             height: resolution.height,
@@ -333,12 +796,92 @@ pub(crate) fn create_xr_swapchain(
         .map_err(Into::into)
 }
 
+/// Create a swapchain for the renderer to resolve depth into, if the runtime supports `XR_KHR_composition_layer_depth`.
+/// Depth can only be resolved on Vulkan 1.2, and the swapchain has to use the renderer's depth format.
+pub(crate) fn create_xr_depth_swapchain(
+    instance: &xr::Instance,
+    session: &Session<Vulkan>,
+    vulkan_context: &VulkanContext,
+    resolution: &vk::Extent2D,
+) -> Result<Option<Swapchain<Vulkan>>> {
+    if instance.exts().khr_composition_layer_depth.is_none() {
+        info!(target: "HOTHAM_XR", "XR_KHR_composition_layer_depth is unavailable, not submitting depth");
+        return Ok(None);
+    }
+
+    if !can_resolve_depth(vulkan_context.api_version) {
+        info!(target: "HOTHAM_XR", "Depth can't be resolved before Vulkan 1.2, not submitting depth");
+        return Ok(None);
+    }
+
+    let depth_format = vulkan_context.get_depth_format(&DEPTH_FORMATS)?;
+    let available = session.enumerate_swapchain_formats()?;
+    if !available.contains(&(depth_format.as_raw() as u32)) {
+        info!(
+            target: "HOTHAM_XR",
+            "The runtime doesn't support depth format {:?}, not submitting depth",
+            depth_format
+        );
+        return Ok(None);
+    }
+
+    let swapchain = session.create_swapchain(&SwapchainCreateInfo {
+        create_flags: SwapchainCreateFlags::EMPTY,
+        usage_flags: SwapchainUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        format: depth_format.as_raw() as u32,
+        sample_count: 1,
+        width: resolution.width,
+        height: resolution.height,
+        face_count: 1,
+        array_size: VIEW_COUNT,
+        mip_count: 1,
+    })?;
+    Ok(Some(swapchain))
+}
+
+/// Depth resolve attachments are core in Vulkan 1.2
+fn can_resolve_depth(api_version: u32) -> bool {
+    api_version >= vk::make_api_version(0, 1, 2, 0)
+}
+
+/// Describe layer `image_array_index` of the current image in `depth_swapchain` to the compositor
+fn get_depth_info(
+    depth_swapchain: &Swapchain<Vulkan>,
+    image_array_index: u32,
+    image_rect: xr::Rect2Di,
+) -> xr::sys::CompositionLayerDepthInfoKHR {
+    xr::sys::CompositionLayerDepthInfoKHR {
+        ty: xr::sys::CompositionLayerDepthInfoKHR::TYPE,
+        next: std::ptr::null(),
+        sub_image: xr::sys::SwapchainSubImage {
+            swapchain: depth_swapchain.as_raw(),
+            image_rect,
+            image_array_index,
+        },
+        min_depth: 0.0,
+        max_depth: 1.0,
+        near_z: NEAR_PLANE,
+        far_z: FAR_PLANE,
+    }
+}
+
+/// Chain `depth_info` onto `view`, which the compositor will then use to reproject it
+fn chain_depth_info<'a>(
+    view: xr::CompositionLayerProjectionView<'a, Vulkan>,
+    depth_info: &'a xr::sys::CompositionLayerDepthInfoKHR,
+) -> xr::CompositionLayerProjectionView<'a, Vulkan> {
+    let mut raw = view.into_raw();
+    raw.next = depth_info as *const _ as *const _;
+    // SAFETY: `depth_info` is borrowed for as long as the view, and its swapchain is owned by the `XrContext`.
+    unsafe { xr::CompositionLayerProjectionView::from_raw(raw) }
+}
+
 pub(crate) fn create_xr_session(
     xr_instance: &xr::Instance,
     system: xr::SystemId,
     vulkan_context: &VulkanContext,
 ) -> Result<(Session<Vulkan>, FrameWaiter, FrameStream<Vulkan>)> {
-    println!("[HOTHAM] Creating session..");
+    debug!(target: "HOTHAM_XR", "Creating session..");
     Ok(unsafe {
         xr_instance.create_session(
             system,
@@ -354,7 +897,7 @@ pub(crate) fn create_xr_session(
     .unwrap())
 }
 
-pub(crate) fn create_xr_instance() -> anyhow::Result<(xr::Instance, xr::SystemId)> {
+pub(crate) fn create_xr_instance() -> anyhow::Result<xr::Instance> {
     let xr_entry = xr::Entry::load()?;
     let xr_app_info = openxr::ApplicationInfo {
         application_name: "Hotham Asteroid",
@@ -372,19 +915,24 @@ pub(crate) fn create_xr_instance() -> anyhow::Result<(xr::Instance, xr::SystemId
         xr_entry.initialize_android_loader()?;
     }
 
-    println!(
-        "Available extensions: {:?}",
-        xr_entry.enumerate_extensions()?
-    );
+    let available_extensions = xr_entry.enumerate_extensions()?;
+    debug!(target: "HOTHAM_XR", "Available extensions: {:?}", available_extensions);
+
+    // Eye tracking is optional: see `GazeContext`.
+    required_extensions.ext_eye_gaze_interaction = available_extensions.ext_eye_gaze_interaction;
+    // Used to tell the compositor which primaries HDR swapchains use, where available.
+    required_extensions.fb_color_space = available_extensions.fb_color_space;
+    // Lets the refresh rate be changed: see `XrContext::request_display_refresh_rate`.
+    required_extensions.fb_display_refresh_rate = available_extensions.fb_display_refresh_rate;
+    // Improves reprojection where available: see `XrContext::depth_swapchain`.
+    required_extensions.khr_composition_layer_depth =
+        available_extensions.khr_composition_layer_depth;
 
     let instance = xr_entry.create_instance(&xr_app_info, &required_extensions, &[])?;
-    let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
-    Ok((instance, system))
+    Ok(instance)
 }
 
-pub(crate) fn create_xr_instance_from_path(
-    path: &std::path::Path,
-) -> anyhow::Result<(xr::Instance, xr::SystemId)> {
+pub(crate) fn create_xr_instance_from_path(path: &std::path::Path) -> anyhow::Result<xr::Instance> {
     let xr_entry = xr::Entry::load_from(path)?;
     let xr_app_info = openxr::ApplicationInfo {
         application_name: "Hotham Asteroid",
@@ -402,23 +950,381 @@ pub(crate) fn create_xr_instance_from_path(
         xr_entry.initialize_android_loader()?;
     }
 
-    println!(
-        "Available extensions: {:?}",
-        xr_entry.enumerate_extensions()?
-    );
+    let available_extensions = xr_entry.enumerate_extensions()?;
+    debug!(target: "HOTHAM_XR", "Available extensions: {:?}", available_extensions);
+
+    // Eye tracking is optional: see `GazeContext`.
+    required_extensions.ext_eye_gaze_interaction = available_extensions.ext_eye_gaze_interaction;
+    // Used to tell the compositor which primaries HDR swapchains use, where available.
+    required_extensions.fb_color_space = available_extensions.fb_color_space;
+    // Lets the refresh rate be changed: see `XrContext::request_display_refresh_rate`.
+    required_extensions.fb_display_refresh_rate = available_extensions.fb_display_refresh_rate;
+    // Improves reprojection where available: see `XrContext::depth_swapchain`.
+    required_extensions.khr_composition_layer_depth =
+        available_extensions.khr_composition_layer_depth;
 
     let instance = xr_entry.create_instance(&xr_app_info, &required_extensions, &[])?;
-    let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
-    Ok((instance, system))
+    Ok(instance)
+}
+
+/// The display's current refresh rate, if the runtime supports `XR_FB_display_refresh_rate`
+fn get_display_refresh_rate(instance: &xr::Instance, session: &Session<Vulkan>) -> Option<f32> {
+    let ext = instance.exts().fb_display_refresh_rate?;
+    let mut rate = 0.0;
+    let result = unsafe { (ext.get_display_refresh_rate)(session.as_raw(), &mut rate) };
+    if result != xr::sys::Result::SUCCESS {
+        warn!(target: "HOTHAM_XR", "Unable to get the display refresh rate: {:?}", result);
+        return None;
+    }
+    Some(rate)
+}
+
+/// The rate in `supported` closest to `requested`
+fn get_closest_refresh_rate(supported: &[f32], requested: f32) -> Option<f32> {
+    supported.iter().copied().min_by(|a, b| {
+        (a - requested)
+            .abs()
+            .partial_cmp(&(b - requested).abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    })
+}
+
+/// Create an action and space tracking the combined gaze of both eyes, using `XR_EXT_eye_gaze_interaction`.
+/// Must be called before the action set is attached to the session.
+fn create_eye_gaze_action(
+    instance: &xr::Instance,
+    session: &Session<Vulkan>,
+    action_set: &ActionSet,
+) -> Result<(Action<Posef>, Space)> {
+    let eye_gaze_action = action_set.create_action::<Posef>("eye_gaze", "Eye Gaze", &[])?;
+    instance.suggest_interaction_profile_bindings(
+        instance.string_to_path("/interaction_profiles/ext/eye_gaze_interaction")?,
+        &[xr::Binding::new(
+            &eye_gaze_action,
+            instance.string_to_path("/user/eyes_ext/input/gaze_ext/pose")?,
+        )],
+    )?;
+    let space = eye_gaze_action.create_space(session.clone(), Path::NULL, Posef::IDENTITY)?;
+    Ok((eye_gaze_action, space))
+}
+
+/// Get the system for `form_factor`, falling back to a head mounted display if it isn't available.
+fn get_system(
+    instance: &xr::Instance,
+    form_factor: xr::FormFactor,
+) -> Result<(xr::SystemId, xr::FormFactor)> {
+    let error = match instance.system(form_factor) {
+        Ok(system) => return Ok((system, form_factor)),
+        Err(e) => e,
+    };
+
+    if form_factor == xr::FormFactor::HEAD_MOUNTED_DISPLAY {
+        return Err(anyhow!(
+            "Unable to get a head mounted display system: {:?}",
+            error
+        ));
+    }
+
+    warn!(
+        target: "HOTHAM_XR",
+        "Form factor {:?} is unavailable ({:?}), falling back to a head mounted display",
+        form_factor, error
+    );
+    match instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY) {
+        Ok(system) => Ok((system, xr::FormFactor::HEAD_MOUNTED_DISPLAY)),
+        Err(fallback_error) => Err(anyhow!(
+            "Neither form factor {:?} ({:?}) nor a head mounted display ({:?}) are available",
+            form_factor,
+            error,
+            fallback_error
+        )),
+    }
+}
+
+/// The view configuration implied by each form factor:
+/// - `HEAD_MOUNTED_DISPLAY` uses `PRIMARY_STEREO` (ie. `VIEW_TYPE`), with one view per eye
+/// - `HANDHELD_DISPLAY` uses `PRIMARY_MONO`, with a single view
+fn get_view_type(form_factor: xr::FormFactor) -> xr::ViewConfigurationType {
+    match form_factor {
+        xr::FormFactor::HANDHELD_DISPLAY => xr::ViewConfigurationType::PRIMARY_MONO,
+        _ => VIEW_TYPE,
+    }
+}
+
+/// The pose of a reference space within the runtime's space of `reference_space_type`. In `LOCAL`, the space is
+/// lowered by `seated_height_offset`, which raises everything located in it by the same amount.
+fn get_reference_space_pose(
+    offset: Isometry3<f32>,
+    reference_space_type: ReferenceSpaceType,
+    seated_height_offset: f32,
+) -> Isometry3<f32> {
+    if reference_space_type == ReferenceSpaceType::LOCAL {
+        offset * Isometry3::translation(0., -seated_height_offset, 0.)
+    } else {
+        offset
+    }
+}
+
+/// The pose midway between the first two views, which are the left and right eyes for every stereo view
+/// configuration. A mono view is the head itself.
+fn get_head_pose(views: &[View]) -> Posef {
+    match views {
+        [left, right, ..] => isometry_to_posef(
+            posef_to_isometry(left.pose).lerp_slerp(&posef_to_isometry(right.pose), 0.5),
+        ),
+        [view] => view.pose,
+        [] => Posef::IDENTITY,
+    }
+}
+
+/// The number of views submitted each frame for a view configuration
+pub(crate) fn get_view_count(view_type: xr::ViewConfigurationType) -> usize {
+    if view_type == xr::ViewConfigurationType::PRIMARY_MONO {
+        1
+    } else {
+        2
+    }
+}
+
+/// Pick a blend mode: head mounted displays prefer `OPAQUE` (ie. `BLEND_MODE`), handheld displays prefer blending
+/// with the camera feed. Falls back to whatever the runtime supports.
+fn get_blend_mode(
+    instance: &xr::Instance,
+    system: xr::SystemId,
+    view_type: xr::ViewConfigurationType,
+    form_factor: xr::FormFactor,
+) -> Result<xr::EnvironmentBlendMode> {
+    let supported = instance.enumerate_environment_blend_modes(system, view_type)?;
+    select_blend_mode(form_factor, &supported)
+        .ok_or_else(|| anyhow!("The runtime doesn't support any environment blend modes"))
+}
+
+fn select_blend_mode(
+    form_factor: xr::FormFactor,
+    supported: &[xr::EnvironmentBlendMode],
+) -> Option<xr::EnvironmentBlendMode> {
+    let preferred: &[xr::EnvironmentBlendMode] = match form_factor {
+        xr::FormFactor::HANDHELD_DISPLAY => &[
+            xr::EnvironmentBlendMode::ALPHA_BLEND,
+            xr::EnvironmentBlendMode::ADDITIVE,
+            BLEND_MODE,
+        ],
+        _ => &[BLEND_MODE],
+    };
+
+    preferred
+        .iter()
+        .find(|b| supported.contains(b))
+        .or_else(|| supported.first())
+        .cloned()
+}
+
+/// Pick a swapchain format for `color_space` from the formats the runtime offers, falling back to SDR if HDR isn't
+/// available. If the runtime supports `XR_FB_color_space`, tells the compositor that HDR images use Rec. 709
+/// primaries, as scRGB does.
+fn get_swapchain_format(
+    instance: &xr::Instance,
+    session: &Session<Vulkan>,
+    color_space: SwapchainColorSpace,
+) -> Result<(vk::Format, SwapchainColorSpace)> {
+    let available = session
+        .enumerate_swapchain_formats()?
+        .into_iter()
+        .map(|f| vk::Format::from_raw(f as _))
+        .collect::<Vec<_>>();
+    let (format, color_space) = select_swapchain_format(&available, color_space);
+
+    if color_space == SwapchainColorSpace::Hdr {
+        if let Some(fb_color_space) = instance.exts().fb_color_space {
+            let result = unsafe {
+                (fb_color_space.set_color_space)(session.as_raw(), xr::sys::ColorSpaceFB::REC709)
+            };
+            if result != xr::sys::Result::SUCCESS {
+                warn!(
+                    target: "HOTHAM_XR",
+                    "Unable to set the compositor's colour space: {:?}",
+                    result
+                );
+            }
+        }
+    }
+
+    Ok((format, color_space))
+}
+
+fn select_swapchain_format(
+    available: &[vk::Format],
+    color_space: SwapchainColorSpace,
+) -> (vk::Format, SwapchainColorSpace) {
+    if color_space == SwapchainColorSpace::Hdr {
+        if available.contains(&HDR_COLOR_FORMAT) {
+            return (HDR_COLOR_FORMAT, SwapchainColorSpace::Hdr);
+        }
+        warn!(
+            target: "HOTHAM_XR",
+            "HDR was requested, but the runtime doesn't support {:?} - falling back to SDR",
+            HDR_COLOR_FORMAT
+        );
+    }
+
+    (COLOR_FORMAT, SwapchainColorSpace::Sdr)
 }
 
-#[cfg(target_os = "windows")]
 #[cfg(test)]
 mod tests {
-    use super::XrContext;
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::vector;
 
+    #[cfg(target_os = "windows")]
     #[test]
     pub fn test_xr_context_smoke_test() {
         XrContext::new().unwrap();
     }
+
+    #[test]
+    pub fn test_get_closest_refresh_rate() {
+        let supported = [72.0, 80.0, 90.0, 120.0];
+        assert_eq!(get_closest_refresh_rate(&supported, 90.0), Some(90.0));
+        assert_eq!(get_closest_refresh_rate(&supported, 60.0), Some(72.0));
+        assert_eq!(get_closest_refresh_rate(&supported, 100.0), Some(90.0));
+        assert_eq!(get_closest_refresh_rate(&supported, 144.0), Some(120.0));
+        assert_eq!(get_closest_refresh_rate(&[], 90.0), None);
+    }
+
+    #[test]
+    pub fn test_can_resolve_depth() {
+        assert!(can_resolve_depth(vk::make_api_version(0, 1, 2, 0)));
+        assert!(can_resolve_depth(vk::make_api_version(0, 1, 3, 0)));
+        assert!(!can_resolve_depth(vk::make_api_version(0, 1, 1, 0)));
+        assert!(!can_resolve_depth(vk::make_api_version(0, 1, 0, 0)));
+    }
+
+    #[test]
+    pub fn test_form_factor_defaults() {
+        let hmd = xr::FormFactor::HEAD_MOUNTED_DISPLAY;
+        let handheld = xr::FormFactor::HANDHELD_DISPLAY;
+        assert_eq!(get_view_type(hmd), VIEW_TYPE);
+        assert_eq!(get_view_count(get_view_type(hmd)), 2);
+        assert_eq!(
+            get_view_type(handheld),
+            xr::ViewConfigurationType::PRIMARY_MONO
+        );
+        assert_eq!(get_view_count(get_view_type(handheld)), 1);
+
+        let supported = [
+            xr::EnvironmentBlendMode::OPAQUE,
+            xr::EnvironmentBlendMode::ALPHA_BLEND,
+        ];
+        assert_eq!(select_blend_mode(hmd, &supported), Some(BLEND_MODE));
+        assert_eq!(
+            select_blend_mode(handheld, &supported),
+            Some(xr::EnvironmentBlendMode::ALPHA_BLEND)
+        );
+        assert_eq!(
+            select_blend_mode(handheld, &[xr::EnvironmentBlendMode::ADDITIVE]),
+            Some(xr::EnvironmentBlendMode::ADDITIVE)
+        );
+        assert_eq!(select_blend_mode(hmd, &[]), None);
+    }
+
+    #[test]
+    pub fn test_select_swapchain_format() {
+        let sdr_only = [COLOR_FORMAT, vk::Format::B8G8R8A8_SRGB];
+        let hdr = [COLOR_FORMAT, HDR_COLOR_FORMAT];
+
+        assert_eq!(
+            select_swapchain_format(&hdr, SwapchainColorSpace::Hdr),
+            (HDR_COLOR_FORMAT, SwapchainColorSpace::Hdr)
+        );
+        assert_eq!(
+            select_swapchain_format(&hdr, SwapchainColorSpace::Sdr),
+            (COLOR_FORMAT, SwapchainColorSpace::Sdr)
+        );
+        assert_eq!(
+            select_swapchain_format(&sdr_only, SwapchainColorSpace::Hdr),
+            (COLOR_FORMAT, SwapchainColorSpace::Sdr)
+        );
+    }
+
+    #[test]
+    pub fn test_validate_composition_layer_flags() {
+        let opaque = xr::EnvironmentBlendMode::OPAQUE;
+        let alpha_blend = xr::EnvironmentBlendMode::ALPHA_BLEND;
+
+        assert!(
+            validate_composition_layer_flags(xr::CompositionLayerFlags::EMPTY, opaque).is_empty()
+        );
+        assert!(validate_composition_layer_flags(
+            xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA
+                | xr::CompositionLayerFlags::UNPREMULTIPLIED_ALPHA,
+            alpha_blend
+        )
+        .is_empty());
+        assert_eq!(
+            validate_composition_layer_flags(
+                xr::CompositionLayerFlags::UNPREMULTIPLIED_ALPHA,
+                alpha_blend
+            )
+            .len(),
+            1
+        );
+        assert_eq!(
+            validate_composition_layer_flags(
+                xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA,
+                opaque
+            )
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    pub fn test_seated_height_offset() {
+        // The user's head, 0.1m above and 0.3m behind the origin of the runtime's seated space.
+        let head = Point3::new(0., 0.1, 0.3);
+        let locate = |reference_space_pose: Isometry3<f32>| reference_space_pose.inverse() * head;
+
+        // Seated, the head should be raised by the offset..
+        let pose = get_reference_space_pose(Isometry3::identity(), ReferenceSpaceType::LOCAL, 1.2);
+        assert_relative_eq!(locate(pose), Point3::new(0., 1.3, 0.3), epsilon = 1e-6);
+
+        // ..even after snap turning about the head, which stays where it is.
+        let turn = Isometry3::rotation_wrt_point(
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 1.),
+            Point3::new(0., 1.3, 0.3),
+        );
+        let pose = get_reference_space_pose(turn, ReferenceSpaceType::LOCAL, 1.2);
+        assert_relative_eq!(locate(pose), Point3::new(0., 1.3, 0.3), epsilon = 1e-6);
+
+        // Standing, the floor is known, so the offset does nothing.
+        let pose = get_reference_space_pose(Isometry3::identity(), ReferenceSpaceType::STAGE, 1.2);
+        assert_relative_eq!(locate(pose), head);
+    }
+
+    #[test]
+    pub fn test_head_pose() {
+        let view = |x: f32| View {
+            pose: isometry_to_posef(Isometry3::translation(x, 1.6, 0.)),
+            fov: xr::Fovf {
+                angle_left: -0.5,
+                angle_right: 0.5,
+                angle_up: 0.5,
+                angle_down: -0.5,
+            },
+        };
+
+        // In flat mode, the single view is the camera..
+        let camera = view(2.);
+        let head_pose = posef_to_isometry(get_head_pose(&[camera]));
+        assert_relative_eq!(head_pose, posef_to_isometry(camera.pose));
+
+        // ..even once it's been duplicated to fill both layers of the swapchain.
+        let head_pose = posef_to_isometry(get_head_pose(&[camera, camera]));
+        assert_relative_eq!(head_pose, posef_to_isometry(camera.pose));
+
+        // In stereo, the head is between the eyes.
+        let head_pose = posef_to_isometry(get_head_pose(&[view(-0.032), view(0.032)]));
+        assert_relative_eq!(head_pose.translation.vector, vector![0., 1.6, 0.]);
+    }
 }
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use hecs::{Entity, EntityBuilder, World};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::{AssetStore, Assets, Handle},
+    components::{
+        Info, Material, Mesh, Parent, RenderFlags, RenderLayer, Transform, TransformMatrix, Visible,
+    },
+    resources::InputBindings,
+    scene_data::AmbientLight,
+};
+
+/// A snapshot of the entities in a `World`, that can be saved to and loaded from JSON, eg. for save states or tools.
+///
+/// Only these components are stored: `Transform`, `Info`, `Parent`, `Visible`, `RenderLayer`, `RenderFlags`,
+/// and `Handle<Mesh>` / `Handle<Material>`. GPU resources are never stored: asset handles are saved as the key they
+/// were loaded with in `Assets`, and resolved against `Assets` again when the scene is spawned.
+///
+/// Basic usage:
+/// ```ignore
+/// let json = Scene::from_world(&world, &engine.assets, &engine.render_context.ambient_light)?.to_json()?;
+/// let scene = Scene::from_json(&json)?;
+/// scene.spawn(&mut world, &engine.assets)?;
+/// engine.render_context.ambient_light = scene.ambient_light;
+/// ```
+///
+/// A scene can also carry the player's remapped controls in `Scene::input_bindings`. These can't be applied to a
+/// running engine: pass them to `Engine::new_with_input_bindings` when the game next starts. See `InputBindings`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    /// The entities in the scene
+    pub entities: Vec<SceneEntity>,
+    /// The scene's ambient light
+    pub ambient_light: AmbientLight,
+    /// The player's remapped controls, if any. Never set by `Scene::from_world`.
+    #[serde(default)]
+    pub input_bindings: Option<InputBindings>,
+}
+
+/// The stored components of a single entity in a `Scene`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SceneEntity {
+    /// The entity's `Info`
+    pub info: Option<Info>,
+    /// The entity's `Transform`
+    pub transform: Option<Transform>,
+    /// Index of the entity's parent within `Scene::entities`
+    pub parent: Option<usize>,
+    /// Does the entity have the `Visible` component?
+    pub visible: bool,
+    /// The entity's `RenderLayer`
+    pub render_layer: Option<RenderLayer>,
+    /// The entity's `RenderFlags`
+    pub render_flags: Option<RenderFlags>,
+    /// Key of the entity's mesh in `Assets::meshes`
+    pub mesh: Option<String>,
+    /// Key of the entity's material in `Assets::materials`
+    pub material: Option<String>,
+}
+
+impl Scene {
+    /// Take a snapshot of every entity in `world`. Fails if an entity has an asset handle that wasn't loaded with a key.
+    pub fn from_world(
+        world: &World,
+        assets: &Assets,
+        ambient_light: &AmbientLight,
+    ) -> Result<Self> {
+        // Sort by ID so the same world is always saved in the same order.
+        let mut entity_ids = world.iter().map(|e| e.entity()).collect::<Vec<_>>();
+        entity_ids.sort_by_key(|e| e.id());
+        let indices = entity_ids
+            .iter()
+            .enumerate()
+            .map(|(index, entity)| (*entity, index))
+            .collect::<HashMap<_, _>>();
+
+        let entities = entity_ids
+            .iter()
+            .map(|entity| {
+                let entity_ref = world.entity(*entity)?;
+                let parent = match entity_ref.get::<Parent>() {
+                    Some(parent) => Some(*indices.get(&parent.0).ok_or_else(|| {
+                        anyhow!("Entity {:?} has a parent that doesn't exist", entity)
+                    })?),
+                    None => None,
+                };
+                let mesh = entity_ref
+                    .get::<Handle<Mesh>>()
+                    .map(|h| get_key(&assets.meshes, *h))
+                    .transpose()?;
+                let material = entity_ref
+                    .get::<Handle<Material>>()
+                    .map(|h| get_key(&assets.materials, *h))
+                    .transpose()?;
+
+                Ok(SceneEntity {
+                    info: entity_ref.get::<Info>().map(|i| (*i).clone()),
+                    transform: entity_ref.get::<Transform>().map(|t| *t),
+                    parent,
+                    visible: entity_ref.has::<Visible>(),
+                    render_layer: entity_ref.get::<RenderLayer>().map(|r| *r),
+                    render_flags: entity_ref.get::<RenderFlags>().map(|r| *r),
+                    mesh,
+                    material,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            entities,
+            ambient_light: *ambient_light,
+            input_bindings: None,
+        })
+    }
+
+    /// Save the scene as JSON
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Load a scene from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Spawn the scene's entities into `world`, returning them in the same order as `Scene::entities`.
+    /// Meshes are cloned from `assets`, which shares their GPU buffers. Fails if an asset key can't be found.
+    pub fn spawn(&self, world: &mut World, assets: &Assets) -> Result<Vec<Entity>> {
+        let mut builders = self
+            .entities
+            .iter()
+            .map(|scene_entity| build_entity(scene_entity, assets))
+            .collect::<Result<Vec<_>>>()?;
+        let entities = builders
+            .iter_mut()
+            .map(|builder| world.spawn(builder.build()))
+            .collect::<Vec<_>>();
+
+        // Now that every entity exists, parents can be resolved.
+        for (scene_entity, entity) in self.entities.iter().zip(&entities) {
+            if let Some(parent) = scene_entity.parent {
+                let parent = *entities
+                    .get(parent)
+                    .ok_or_else(|| anyhow!("Invalid parent index: {}", parent))?;
+                world.insert_one(*entity, Parent(parent))?;
+            }
+        }
+
+        Ok(entities)
+    }
+}
+
+fn build_entity(scene_entity: &SceneEntity, assets: &Assets) -> Result<EntityBuilder> {
+    let mut builder = EntityBuilder::new();
+    if let Some(info) = &scene_entity.info {
+        builder.add(info.clone());
+    }
+    if let Some(transform) = scene_entity.transform {
+        builder.add(transform);
+        builder.add(TransformMatrix::default());
+    }
+    if scene_entity.visible {
+        builder.add(Visible {});
+    }
+    if let Some(render_layer) = scene_entity.render_layer {
+        builder.add(render_layer);
+    }
+    if let Some(render_flags) = scene_entity.render_flags {
+        builder.add(render_flags);
+    }
+    if let Some(key) = &scene_entity.mesh {
+        let handle = get_handle(&assets.meshes, key)?;
+        builder.add(handle);
+        builder.add(assets.meshes.get(handle).unwrap().clone());
+    }
+    if let Some(key) = &scene_entity.material {
+        builder.add(get_handle(&assets.materials, key)?);
+    }
+    Ok(builder)
+}
+
+fn get_key<T>(store: &AssetStore<T>, handle: Handle<T>) -> Result<String> {
+    store
+        .key(handle)
+        .map(|k| k.to_string())
+        .ok_or_else(|| anyhow!("Asset {:?} has no key, so it can't be saved", handle))
+}
+
+fn get_handle<T>(store: &AssetStore<T>, key: &str) -> Result<Handle<T>> {
+    store
+        .handle(key)
+        .ok_or_else(|| anyhow!("No asset has been loaded with the key {}", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::InputAction;
+    use nalgebra::{vector, UnitQuaternion};
+
+    #[test]
+    pub fn test_scene_round_trip() {
+        let mut assets = Assets::default();
+        let material = assets
+            .materials
+            .get_or_load("red", || Ok(Material::default()))
+            .unwrap();
+
+        let mut world = World::new();
+        let parent = world.spawn((
+            Info {
+                name: "Parent".to_string(),
+                node_id: 0,
+            },
+            Transform {
+                translation: vector![1., 2., 3.],
+                rotation: UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3),
+                scale: vector![2., 2., 2.],
+            },
+            TransformMatrix::default(),
+            Visible {},
+            material,
+        ));
+        world.spawn((
+            Transform::default(),
+            Parent(parent),
+            RenderLayer::UI,
+            RenderFlags { visible: false },
+        ));
+        let ambient_light = AmbientLight {
+            color: vector![1., 0.5, 0.25],
+            intensity: 0.2,
+        };
+
+        let scene = Scene::from_world(&world, &assets, &ambient_light).unwrap();
+        let json = scene.to_json().unwrap();
+        let loaded_scene = Scene::from_json(&json).unwrap();
+        assert_eq!(loaded_scene, scene);
+
+        let mut loaded_world = World::new();
+        let entities = loaded_scene.spawn(&mut loaded_world, &assets).unwrap();
+        assert_eq!(entities.len(), 2);
+
+        // Reloading the spawned world should give the same scene.
+        let reloaded_scene = Scene::from_world(&loaded_world, &assets, &ambient_light).unwrap();
+        assert_eq!(reloaded_scene, scene);
+
+        let loaded_parent = entities[0];
+        let loaded_child = entities[1];
+        assert_eq!(
+            *loaded_world.get::<Transform>(loaded_parent).unwrap(),
+            *world.get::<Transform>(parent).unwrap()
+        );
+        assert_eq!(
+            *loaded_world.get::<Handle<Material>>(loaded_parent).unwrap(),
+            material
+        );
+        assert_eq!(
+            loaded_world.get::<Parent>(loaded_child).unwrap().0,
+            loaded_parent
+        );
+        assert_eq!(
+            *loaded_world.get::<RenderLayer>(loaded_child).unwrap(),
+            RenderLayer::UI
+        );
+        assert!(loaded_world.get::<Visible>(loaded_child).is_err());
+
+        // Unknown asset keys are an error.
+        let mut bad_scene = scene.clone();
+        bad_scene.entities[0].material = Some("blue".to_string());
+        assert!(bad_scene.spawn(&mut World::new(), &assets).is_err());
+    }
+
+    #[test]
+    pub fn test_scene_input_bindings() {
+        let mut scene =
+            Scene::from_world(&World::new(), &Assets::default(), &Default::default()).unwrap();
+        assert_eq!(scene.input_bindings, None);
+
+        // Scenes saved before input bindings were stored should still load.
+        let mut json: serde_json::Value = serde_json::from_str(&scene.to_json().unwrap()).unwrap();
+        json.as_object_mut().unwrap().remove("input_bindings");
+        assert_eq!(Scene::from_json(&json.to_string()).unwrap(), scene);
+
+        let mut bindings = InputBindings::default();
+        bindings.set_paths(
+            InputAction::Grab,
+            vec!["/user/hand/left/input/trigger/value".to_string()],
+        );
+        scene.input_bindings = Some(bindings);
+        let loaded_scene = Scene::from_json(&scene.to_json().unwrap()).unwrap();
+        assert_eq!(loaded_scene, scene);
+    }
+}
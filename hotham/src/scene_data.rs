@@ -1,5 +1,5 @@
 // TODO: Should these be components?
-use nalgebra::{vector, Matrix4, Vector3, Vector4};
+use nalgebra::{vector, Matrix4, UnitQuaternion, Vector3, Vector4};
 use serde::{Deserialize, Serialize};
 
 /// Data about the current scene. Sent to the vertex and fragment shaders
@@ -12,6 +12,18 @@ pub struct SceneData {
     pub view: [Matrix4<f32>; 2],
     /// Position of the cameras (one per eye)
     pub camera_position: [Vector4<f32>; 2],
+    /// Exposure (x) and gamma (y) of the cameras (one per eye)
+    pub tonemapping: [Vector4<f32>; 2],
+    /// Colour of the ambient light, premultiplied by its intensity. See `AmbientLight`
+    pub ambient_light: Vector4<f32>,
+    /// Start (x) and end (y) distances of the near fade, and whether it's enabled (z). See `NearFade`
+    pub near_fade: Vector4<f32>,
+    /// Rotates world space directions into the space of the diffuse image based lighting map. See
+    /// `RenderContext::set_environment_rotation`
+    pub environment_rotation: Matrix4<f32>,
+    /// Rotates world space directions into the space of the specular reflection map. The identity when a
+    /// `ReflectionProbe` is used, as probes are captured in world space.
+    pub reflection_rotation: Matrix4<f32>,
 }
 
 impl Default for SceneData {
@@ -20,6 +32,86 @@ impl Default for SceneData {
             view: [Matrix4::identity(), Matrix4::identity()],
             projection: [Matrix4::identity(), Matrix4::identity()],
             camera_position: [Vector4::zeros(), Vector4::zeros()],
+            tonemapping: [vector![1., 2.2, 0., 0.], vector![1., 2.2, 0., 0.]],
+            ambient_light: AmbientLight::default().to_shader(),
+            near_fade: NearFade::default().to_shader(),
+            environment_rotation: Matrix4::identity(),
+            reflection_rotation: Matrix4::identity(),
+        }
+    }
+}
+
+/// Get the matrix that rotates a world space direction to the direction the environment maps are sampled in, so the
+/// environment appears rotated by `rotation`. Matches `pbr.frag`.
+pub(crate) fn get_environment_matrix(rotation: &UnitQuaternion<f32>) -> Matrix4<f32> {
+    rotation.inverse().to_homogeneous()
+}
+
+/// A constant light added to the diffuse term of every PBR material, independent of the directional and image
+/// based lights. Keeps unlit areas from being pure black. Set it with `RenderContext::ambient_light`.
+#[derive(Deserialize, Serialize, Clone, Debug, Copy, PartialEq)]
+pub struct AmbientLight {
+    /// Colour of the light, in linear RGB
+    pub color: Vector3<f32>,
+    /// Brightness of the light
+    pub intensity: f32,
+}
+
+impl AmbientLight {
+    pub(crate) fn to_shader(&self) -> Vector4<f32> {
+        (self.color * self.intensity).push(0.)
+    }
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        // A subtle neutral gray.
+        Self {
+            color: vector![1., 1., 1.],
+            intensity: 0.05,
+        }
+    }
+}
+
+/// Fades out geometry very close to the viewer's eyes, rather than letting the near plane clip through it, eg. when
+/// the player puts their head into a wall. Close-up geometry is uncomfortable to look at, and clipping is jarring.
+/// Set it with `RenderContext::near_fade`. Disabled by default.
+///
+/// Geometry starts to fade `start` metres in front of the eye, and is gone by `end`. The fade is dithered, discarding
+/// more of each fragment the closer it is, so it works for opaque materials as well as transparent ones. This applies
+/// to everything drawn with the PBR pipelines, including the player's own hands.
+#[derive(Deserialize, Serialize, Clone, Debug, Copy, PartialEq)]
+pub struct NearFade {
+    /// Whether geometry is faded. Defaults to `false`.
+    pub enabled: bool,
+    /// Distance from the eye, in metres, at which geometry starts to fade. Defaults to 0.3.
+    pub start: f32,
+    /// Distance from the eye, in metres, at which geometry has faded out completely. Should be less than `start`, and
+    /// more than `NEAR_PLANE`. Defaults to 0.15.
+    pub end: f32,
+}
+
+impl NearFade {
+    /// How opaque geometry `distance` metres in front of the eye is, from 0.0 (faded out) to 1.0. Matches `pbr.frag`.
+    pub fn opacity(&self, distance: f32) -> f32 {
+        if !self.enabled {
+            return 1.;
+        }
+        let t = ((distance - self.end) / (self.start - self.end).max(f32::EPSILON)).clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    }
+
+    pub(crate) fn to_shader(&self) -> Vector4<f32> {
+        vector![self.start, self.end, self.enabled as u32 as f32, 0.]
+    }
+}
+
+impl Default for NearFade {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: 0.3,
+            end: 0.15,
         }
     }
 }
@@ -65,3 +157,55 @@ impl Default for SceneParams {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    pub fn test_near_fade_opacity() {
+        let mut near_fade = NearFade::default();
+        assert_eq!(near_fade.opacity(0.1), 1.);
+
+        near_fade.enabled = true;
+        assert_eq!(near_fade.opacity(1.), 1.);
+        assert_eq!(near_fade.opacity(0.3), 1.);
+        assert_relative_eq!(near_fade.opacity(0.225), 0.5, epsilon = 0.0001);
+        assert_eq!(near_fade.opacity(0.15), 0.);
+        assert_eq!(near_fade.opacity(0.05), 0.);
+        assert_eq!(near_fade.to_shader(), vector![0.3, 0.15, 1., 0.]);
+    }
+
+    #[test]
+    pub fn test_environment_rotation() {
+        assert_eq!(
+            get_environment_matrix(&UnitQuaternion::identity()),
+            Matrix4::identity()
+        );
+
+        // Turn the environment 90 degrees to the left, so what was straight ahead (-Z) is now to the left (-X)..
+        let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2);
+        let matrix = get_environment_matrix(&rotation);
+
+        // ..so looking left samples what used to be straight ahead..
+        assert_relative_eq!(
+            matrix.transform_vector(&vector![-1., 0., 0.]),
+            vector![0., 0., -1.],
+            epsilon = 1e-6
+        );
+        // ..and looking straight ahead samples what used to be to the right.
+        assert_relative_eq!(
+            matrix.transform_vector(&vector![0., 0., -1.]),
+            vector![1., 0., 0.],
+            epsilon = 1e-6
+        );
+        // Up is still up.
+        assert_relative_eq!(
+            matrix.transform_vector(&vector![0., 1., 0.]),
+            vector![0., 1., 0.],
+            epsilon = 1e-6
+        );
+    }
+}
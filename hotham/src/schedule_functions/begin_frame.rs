@@ -1,16 +1,23 @@
+use std::time::Duration;
+
+use log::trace;
 use openxr::ActiveActionSet;
 
 use crate::{
     resources::{xr_context::XrContext, RenderContext, VulkanContext},
-    VIEW_TYPE,
+    HothamError,
 };
 
 /// Begin a frame
 /// Make sure to call this BEFORE beginning any renderpasses.
+/// If the GPU is still busy with a previous frame after `RenderContext::fence_timeout`, this frame is dropped and
+/// `shouldRender` is cleared. Panics if the device is lost, including after `RenderContext::max_fence_timeouts` frames
+/// in a row have timed out.
+/// If `RenderContext::adaptive_resolution` is enabled, the render scale is adjusted here.
 pub fn begin_frame(
     xr_context: &mut XrContext,
     vulkan_context: &VulkanContext,
-    render_context: &RenderContext,
+    render_context: &mut RenderContext,
 ) {
     let active_action_set = ActiveActionSet::new(&xr_context.action_set);
     xr_context
@@ -21,25 +28,39 @@ pub fn begin_frame(
     // Wait for a frame to become available from the runtime
     xr_context.begin_frame().unwrap();
 
-    let (view_state_flags, views) = xr_context
-        .session
-        .locate_views(
-            VIEW_TYPE,
-            xr_context.frame_state.predicted_display_time,
-            &xr_context.reference_space,
-        )
-        .unwrap();
+    let (view_state_flags, views) = xr_context.locate_views().unwrap();
     xr_context.views = views;
     xr_context.view_state_flags = view_state_flags;
 
     // If the shouldRender flag is set, start rendering
     if xr_context.frame_state.should_render {
-        render_context.begin_frame(&vulkan_context, xr_context.frame_index);
+        match render_context.begin_frame(&vulkan_context, xr_context.frame_index) {
+            Ok(()) => {
+                let display_period = xr_context.frame_state.predicted_display_period.as_nanos();
+                render_context
+                    .update_adaptive_resolution(
+                        vulkan_context,
+                        Duration::from_nanos(display_period.max(0) as u64),
+                    )
+                    .expect("[HOTHAM_BEGIN_FRAME] - Unable to change the render scale");
+            }
+            // The GPU is still busy, so drop this frame.
+            Err(HothamError::FenceTimeout { .. }) => xr_context.frame_state.should_render = false,
+            Err(e) => panic!(
+                "[HOTHAM_BEGIN_FRAME] - Fatal error beginning frame: {:?}",
+                e
+            ),
+        }
     } else {
-        println!(
-            "[HOTHAM_BEGIN_FRAME] - Session is runing but shouldRender is false - not rendering"
+        render_context.skip_frame = true;
+        trace!(
+            target: "HOTHAM_BEGIN_FRAME",
+            "Session is runing but shouldRender is false - not rendering"
         );
     }
+
+    // Time the rest of the frame, now the runtime has been waited on.
+    render_context.cpu_timer.begin_frame();
 }
 
 #[cfg(target_os = "windows")]
@@ -53,10 +74,10 @@ mod tests {
 
     pub fn test_begin_frame() {
         let (mut xr_context, vulkan_context) = XrContext::new().unwrap();
-        let render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
+        let mut render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
         xr_context.frame_index = 100;
 
-        begin_frame(&mut xr_context, &vulkan_context, &render_context);
+        begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
         assert_eq!(xr_context.frame_index, 0);
     }
 }
@@ -2,6 +2,7 @@ use crate::{
     resources::xr_context::XrContext, resources::RenderContext, resources::VulkanContext,
     util::is_view_valid,
 };
+use log::trace;
 /// Begin the PBR renderpass
 /// Evaluates the provided XrContext to ensure a valid frame state and view before initiating the
 /// Vulkan render pass.
@@ -14,8 +15,9 @@ pub fn begin_pbr_renderpass(
 ) {
     // Check if we should be rendering.
     if !xr_context.frame_state.should_render {
-        println!(
-            "[HOTHAM_BEGIN_PBR_RENDERPASS] - Session is running but shouldRender is false - not rendering"
+        trace!(
+            target: "HOTHAM_BEGIN_PBR_RENDERPASS",
+            "Session is running but shouldRender is false - not rendering"
         );
         return;
     }
@@ -26,7 +28,7 @@ pub fn begin_pbr_renderpass(
 
         // Update uniform buffers
         render_context
-            .update_scene_data(&views, &vulkan_context)
+            .update_scene_data(&views, &vulkan_context, xr_context.frame_index)
             .unwrap();
     }
 
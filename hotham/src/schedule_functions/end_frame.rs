@@ -1,23 +1,69 @@
 use crate::{resources::RenderContext, resources::VulkanContext, resources::XrContext};
+use log::{trace, warn};
+use openxr::ViewConfigurationType;
 
 /// End the current frame
 /// Make sure to ONLY call this AFTER `begin_frame` and DO NOT issue any further rendering commands this frame
+///
+/// If `XrContext::late_latching` is enabled, the views are located again just before the frame is submitted, and the
+/// view matrices the frame was recorded with are rewritten to match. The head can turn a long way in the time it
+/// takes to run a frame's systems and record it, so this cuts the latency between moving and seeing the result by
+/// most of a frame, which the compositor would otherwise have to correct by reprojecting. The same views are submitted
+/// to the compositor and left in `XrContext::views`, so rendering and anything reading them afterwards agree.
+/// If the views can't be located again, the frame is submitted with the views located in `begin_frame`.
+///
+/// NOTE: Only the views are latched. The grip and aim poses are located once, when `hands_system`,
+/// `controller_pose_system` and `pointers_system` run, and the hands and anything held in them are drawn where those
+/// systems put them: moving them here would mean re-uploading every mesh attached to them after it was recorded. So
+/// with late latching on, the hands are posed with older tracking data than the head and can trail it slightly.
+/// Anything else that ran earlier in the frame, eg. culling and `gaze_system`, also used the views located in
+/// `begin_frame`. Flat (mono) views aren't latched, as `camera_rig_system` may have moved them.
 pub fn end_frame(
     xr_context: &mut XrContext,
     vulkan_context: &VulkanContext,
     render_context: &mut RenderContext,
 ) {
+    render_context
+        .cpu_timer
+        .end_frame(xr_context.display_refresh_rate);
+
     // Check if we should be rendering.
     if xr_context.frame_state.should_render {
-        render_context.end_frame(&vulkan_context, xr_context.frame_index);
+        if xr_context.late_latching
+            && xr_context.view_type != ViewConfigurationType::PRIMARY_MONO
+            && late_latch_views(xr_context)
+        {
+            render_context
+                .update_scene_data(&xr_context.views, vulkan_context, xr_context.frame_index)
+                .expect("[HOTHAM_END_FRAME] - Unable to update scene data");
+        }
+        render_context
+            .end_frame(&vulkan_context, xr_context.frame_index)
+            .expect("[HOTHAM_END_FRAME] - Fatal error submitting frame");
     } else {
-        println!(
-            "[HOTHAM_END_FRAME] - Session is runing but shouldRender is false - not rendering"
+        trace!(
+            target: "HOTHAM_END_FRAME",
+            "Session is runing but shouldRender is false - not rendering"
         );
     }
+    xr_context.depth_resolved = render_context.has_resolved_depth();
     xr_context.end_frame().unwrap();
 }
 
+/// Locate the views again, keeping the ones from `begin_frame` if that fails. Returns whether the views were updated.
+fn late_latch_views(xr_context: &mut XrContext) -> bool {
+    match xr_context.late_latch_views() {
+        Ok(latched) => latched,
+        Err(e) => {
+            warn!(
+                target: "HOTHAM_END_FRAME",
+                "Unable to locate views again - submitting the views from begin_frame: {:?}", e
+            );
+            false
+        }
+    }
+}
+
 #[cfg(target_os = "windows")]
 #[cfg(test)]
 mod tests {
@@ -26,13 +72,74 @@ mod tests {
     use crate::{
         resources::{RenderContext, XrContext},
         schedule_functions::begin_frame,
+        util::{is_view_valid, posef_to_isometry},
     };
+    use openxr::View;
 
     #[test]
     pub fn test_end_frame() {
         let (mut xr_context, vulkan_context) = XrContext::new().unwrap();
         let mut render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
-        begin_frame(&mut xr_context, &vulkan_context, &render_context);
+        begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
+        end_frame(&mut xr_context, &vulkan_context, &mut render_context);
+    }
+
+    #[test]
+    pub fn test_end_frame_with_late_latching() {
+        let (mut xr_context, vulkan_context) = XrContext::new().unwrap();
+        let mut render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
+        xr_context.late_latching = true;
+        begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
         end_frame(&mut xr_context, &vulkan_context, &mut render_context);
+
+        // The views the frame was rendered with are the ones that were submitted.
+        if xr_context.frame_state.should_render && is_view_valid(&xr_context.view_state_flags) {
+            let poses = |views: &[View]| {
+                views
+                    .iter()
+                    .map(|v| posef_to_isometry(v.pose))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(poses(&render_context.views), poses(&xr_context.views));
+        }
+    }
+
+    #[test]
+    pub fn test_late_latching_leaves_controller_poses() {
+        use crate::{
+            components::{hand::Handedness, ControllerPoseKind, TransformMatrix},
+            systems::{
+                attach_to_controller, controller_pose_system,
+                update_parent_transform_matrix_system, update_transform_matrix_system,
+            },
+        };
+        use hecs::{PreparedQuery, World};
+        use nalgebra::Isometry3;
+
+        let (mut xr_context, vulkan_context) = XrContext::new().unwrap();
+        let mut render_context = RenderContext::new(&vulkan_context, &xr_context).unwrap();
+        xr_context.late_latching = true;
+
+        let mut world = World::new();
+        let held = attach_to_controller(
+            &mut world,
+            Handedness::Right,
+            ControllerPoseKind::Grip,
+            Isometry3::identity(),
+        );
+
+        begin_frame(&mut xr_context, &vulkan_context, &mut render_context);
+        controller_pose_system(&mut Default::default(), &mut world, &xr_context);
+        update_transform_matrix_system(&mut Default::default(), &mut world);
+        update_parent_transform_matrix_system(
+            &mut Default::default(),
+            &mut PreparedQuery::default(),
+            &mut world,
+        );
+        let before = world.get::<TransformMatrix>(held).unwrap().0;
+        end_frame(&mut xr_context, &vulkan_context, &mut render_context);
+
+        // Only the views are latched: the held entity stays where it was posed at the start of the frame.
+        assert_eq!(world.get::<TransformMatrix>(held).unwrap().0, before);
     }
 }
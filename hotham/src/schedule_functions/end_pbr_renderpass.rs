@@ -2,6 +2,7 @@ use crate::{
     resources::RenderContext,
     resources::{VulkanContext, XrContext},
 };
+use log::trace;
 /// End the PBR renderpass
 /// Evalues the provided XrContent to ensure the frame state allows for rendering before completing
 /// the Vulkan render pass.
@@ -14,8 +15,9 @@ pub fn end_pbr_renderpass(
 ) {
     // Check if we should be rendering.
     if !xr_context.frame_state.should_render {
-        println!(
-            "[HOTHAM_END_PBR_RENDERPASS] - Session is running but shouldRender is false - not rendering"
+        trace!(
+            target: "HOTHAM_END_PBR_RENDERPASS",
+            "Session is running but shouldRender is false - not rendering"
         );
         return;
     }
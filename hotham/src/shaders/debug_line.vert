@@ -0,0 +1,26 @@
+// Draws lines in world space, eg. to visualise skeletons.
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_multiview : enable
+
+layout (location = 0) in vec3 inPos;
+layout (location = 1) in vec4 inColor;
+
+layout (set = 0, binding = 0) uniform UBO  {
+	mat4 projection[2];
+	mat4 view[2];
+	vec4 camPos[2];
+} ubo;
+
+layout (location = 0) out vec4 outColor;
+
+out gl_PerVertex
+{
+	vec4 gl_Position;
+};
+
+void main() 
+{
+	outColor = inColor;
+	gl_Position = ubo.projection[gl_ViewIndex] * ubo.view[gl_ViewIndex] * vec4(inPos, 1.0);
+}
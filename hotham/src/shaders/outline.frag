@@ -0,0 +1,13 @@
+#version 450
+
+// NOTE: This must match the layout of `Highlight`
+layout (push_constant) uniform Highlight {
+	vec4 color;
+	float width;
+} highlight;
+
+layout (location = 0) out vec4 outColor;
+
+void main() {
+	outColor = highlight.color;
+}
@@ -0,0 +1,59 @@
+// Draws a mesh extruded along its normals. Used with the stencil buffer to outline highlighted objects.
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_multiview : enable
+
+layout (location = 0) in vec3 inPos;
+layout (location = 1) in vec3 inNormal;
+layout (location = 2) in vec2 inUV0;
+layout (location = 3) in vec2 inUV1;
+layout (location = 4) in vec4 inJoint0;
+layout (location = 5) in vec4 inWeight0;
+
+layout (set = 0, binding = 0) uniform UBO  {
+	mat4 projection[2];
+	mat4 view[2];
+	vec4 camPos[2];
+} ubo;
+
+layout (set = 2, binding = 0) uniform UBONode {
+	mat4 matrix;
+	float jointCount;
+} node;
+
+// Grows with the number of joints: see `Mesh::update_joint_buffer`
+layout (std430, set = 2, binding = 1) readonly buffer JointMatrices {
+	mat4 jointMatrix[];
+} joints;
+
+// NOTE: This must match the layout of `Highlight`
+layout (push_constant) uniform Highlight {
+	vec4 color;
+	float width;
+} highlight;
+
+out gl_PerVertex
+{
+	vec4 gl_Position;
+};
+
+void main() 
+{
+	mat4 model = node.matrix;
+	if (node.jointCount > 0.0) {
+		// Mesh is skinned
+		mat4 skinMat = 
+			inWeight0.x * joints.jointMatrix[int(inJoint0.x)] +
+			inWeight0.y * joints.jointMatrix[int(inJoint0.y)] +
+			inWeight0.z * joints.jointMatrix[int(inJoint0.z)] +
+			inWeight0.w * joints.jointMatrix[int(inJoint0.w)];
+		model = node.matrix * skinMat;
+	}
+
+	vec4 worldPos = model * vec4(inPos, 1.0);
+	vec3 worldNormal = normalize(transpose(inverse(mat3(model))) * inNormal);
+
+	// Push the surface out by `width` metres to form the silhouette.
+	worldPos.xyz = worldPos.xyz / worldPos.w + worldNormal * highlight.width;
+	gl_Position = ubo.projection[gl_ViewIndex] * ubo.view[gl_ViewIndex] * vec4(worldPos.xyz, 1.0);
+}
@@ -8,12 +8,23 @@ layout (location = 0) in vec3 inWorldPos;
 layout (location = 1) in vec3 inNormal;
 layout (location = 2) in vec2 inUV0;
 layout (location = 3) in vec2 inUV1;
+layout (location = 4) in vec4 inColor;
+
+// Set for the pipelines of unlit materials, so the lighting is compiled out: see `RenderContext::get_pipeline`
+layout (constant_id = 100) const bool UNLIT = false;
 
 // Scene bindings
 layout (set = 0, binding = 0) uniform UBO  {
 	mat4 projection[2];
 	mat4 view[2];
 	vec4 camPos[2];
+	vec4 tonemapping[2];
+	vec4 ambientLight;
+	// x: start distance, y: end distance, z: enabled
+	vec4 nearFade;
+	// Rotate world space directions into the space of the irradiance and prefiltered maps
+	mat4 environmentRotation;
+	mat4 reflectionRotation;
 } ubo;
 
 layout (set = 0, binding = 1) uniform UBOParams {
@@ -36,6 +47,7 @@ layout (set = 1, binding = 1) uniform sampler2D physicalDescriptorMap;
 layout (set = 1, binding = 2) uniform sampler2D normalMap;
 layout (set = 1, binding = 3) uniform sampler2D aoMap;
 layout (set = 1, binding = 4) uniform sampler2D emissiveMap;
+layout (set = 1, binding = 5) uniform sampler2D heightMap;
 
 layout (push_constant) uniform Material {
 	vec4 baseColorFactor;
@@ -52,10 +64,18 @@ layout (push_constant) uniform Material {
 	float roughnessFactor;	
 	float alphaMask;	
 	float alphaMaskCutoff;
+	float doubleSided;
+	int heightTextureSet;
+	float parallaxScale;
+	float parallaxBias;
 } material;
 
 layout (location = 0) out vec4 outColor;
 
+// Texture coordinates, offset by parallax occlusion mapping if the material has a height map
+vec2 uv0;
+vec2 uv1;
+
 // Encapsulate the various inputs used by the various functions in the shading equation
 // We store values in this struct to simplify the integration of alternative implementations
 // of the shading terms, outlined in the Readme.MD Appendix.
@@ -81,6 +101,8 @@ const float c_MinRoughness = 0.04;
 const float PBR_WORKFLOW_METALLIC_ROUGHNESS = 0.0;
 const float PBR_WORKFLOW_SPECULAR_GLOSINESS = 1.0f;
 const float PBR_WORKFLOW_UNLIT = 2.0f;
+const float PBR_WORKFLOW_UNLIT_LINEAR_TEXTURE = 3.0f;
+const float ALPHA_MASK_COVERAGE = 2.0f;
 #define MANUAL_SRGB 1
 
 vec3 Uncharted2Tonemap(vec3 color)
@@ -102,6 +124,15 @@ vec4 tonemap(vec4 color)
 	return vec4(pow(outcol, vec3(1.0f / uboParams.gamma)), color.a);
 }
 
+// Apply the camera's exposure and gamma. An SDR swapchain will then encode the result as sRGB
+// (ie. a gamma of ~2.2), so the default gamma of 2.2 leaves the colour unchanged. An HDR swapchain
+// stores it as linear scRGB, where values above 1.0 are brighter than SDR white rather than clamped.
+vec3 applyCameraTonemapping(vec3 color)
+{
+	vec2 params = ubo.tonemapping[gl_ViewIndex].xy;
+	return pow(color * params.x, vec3(2.2 / params.y));
+}
+
 vec4 SRGBtoLINEAR(vec4 srgbIn)
 {
 	#ifdef MANUAL_SRGB
@@ -117,13 +148,10 @@ vec4 SRGBtoLINEAR(vec4 srgbIn)
 	#endif //MANUAL_SRGB
 }
 
-// Find the normal for this fragment, pulling either from a predefined normal map
-// or from the interpolated mesh normal and tangent attributes.
-vec3 getNormal()
+// Build a tangent space for this fragment from the derivatives of its position and texture coordinates,
+// see http://www.thetenthplanet.de/archives/1180
+mat3 getTBN()
 {
-	// Perturb normal, see http://www.thetenthplanet.de/archives/1180
-	vec3 tangentNormal = texture(normalMap, material.normalTextureSet == 0 ? inUV0 : inUV1).xyz * 2.0 - 1.0;
-
 	vec3 q1 = dFdx(inWorldPos);
 	vec3 q2 = dFdy(inWorldPos);
 	vec2 st1 = dFdx(inUV0);
@@ -132,9 +160,53 @@ vec3 getNormal()
 	vec3 N = normalize(inNormal);
 	vec3 T = normalize(q1 * st2.t - q2 * st1.t);
 	vec3 B = -normalize(cross(N, T));
-	mat3 TBN = mat3(T, B, N);
+	return mat3(T, B, N);
+}
+
+// Find the normal for this fragment, pulling either from a predefined normal map
+// or from the interpolated mesh normal and tangent attributes.
+vec3 getNormal()
+{
+	// Perturb normal
+	vec3 tangentNormal = texture(normalMap, material.normalTextureSet == 0 ? uv0 : uv1).xyz * 2.0 - 1.0;
+	return normalize(getTBN() * tangentNormal);
+}
 
-	return normalize(TBN * tangentNormal);
+// Parallax occlusion mapping: step the view ray down through the height map, in tangent space, until it goes below
+// the surface, and return how far the texture coordinates move to get there.
+// A height of 1.0 is the top of the surface, raised parallaxBias * parallaxScale above the mesh, and 0.0 is
+// parallaxScale below that.
+vec2 getParallaxOffset()
+{
+	const float minLayers = 8.0;
+	const float maxLayers = 32.0;
+
+	vec2 uv = material.heightTextureSet == 0 ? inUV0 : inUV1;
+	vec2 dx = dFdx(uv);
+	vec2 dy = dFdy(uv);
+	vec3 v = normalize(transpose(getTBN()) * (ubo.camPos[gl_ViewIndex].xyz - inWorldPos));
+
+	// Looking straight at the surface needs fewer steps than looking across it.
+	float layerCount = mix(maxLayers, minLayers, abs(v.z));
+	float layerDepth = 1.0 / layerCount;
+	vec2 p = v.xy / max(v.z, 0.05) * material.parallaxScale;
+	vec2 deltaUV = p / layerCount;
+
+	vec2 currentUV = uv + p * material.parallaxBias;
+	float currentLayerDepth = 0.0;
+	float currentDepth = 1.0 - textureGrad(heightMap, currentUV, dx, dy).r;
+	for (int i = 0; i < int(maxLayers) && currentLayerDepth < currentDepth; i++) {
+		currentUV -= deltaUV;
+		currentDepth = 1.0 - textureGrad(heightMap, currentUV, dx, dy).r;
+		currentLayerDepth += layerDepth;
+	}
+
+	// Interpolate between the steps either side of the surface.
+	vec2 previousUV = currentUV + deltaUV;
+	float after = currentDepth - currentLayerDepth;
+	float before = 1.0 - textureGrad(heightMap, previousUV, dx, dy).r - (currentLayerDepth - layerDepth);
+	float weight = after / (after - before);
+	return mix(currentUV, previousUV, weight) - uv;
 }
 
 // Calculation of the lighting contribution from an optional Image Based Light source.
@@ -145,7 +217,7 @@ vec3 getIBLContribution(PBRInfo pbrInputs, vec3 n, vec3 reflection)
 	float lod = (pbrInputs.perceptualRoughness * uboParams.prefilteredCubeMipLevels);
 	// retrieve a scale and bias to F0. See [1], Figure 3
 	vec3 brdf = (texture(samplerBRDFLUT, vec2(pbrInputs.NdotV, 1.0 - pbrInputs.perceptualRoughness))).rgb;
-	vec3 diffuseLight = SRGBtoLINEAR(tonemap(texture(samplerIrradiance, n))).rgb;
+	vec3 diffuseLight = SRGBtoLINEAR(tonemap(texture(samplerIrradiance, mat3(ubo.environmentRotation) * n))).rgb;
 	vec3 specularLight = SRGBtoLINEAR(tonemap(textureLod(prefilteredMap, reflection, lod))).rgb;
 
 	vec3 diffuse = diffuseLight * pbrInputs.diffuseColor;
@@ -208,8 +280,41 @@ float convertMetallic(vec3 diffuse, vec3 specular, float maxSpecular) {
 	return clamp((-b + sqrt(D)) / (2.0 * a), 0.0, 1.0);
 }
 
+// The colour of an unlit material: its base colour and vertex colour, without any lighting (KHR_materials_unlit)
+vec4 getUnlitColor()
+{
+	vec4 color = material.baseColorFactor * inColor;
+	if (material.baseColorTextureSet > -1) {
+		vec4 texel = texture(colorMap, material.baseColorTextureSet == 0 ? uv0 : uv1);
+		color *= texel;
+	}
+	return color;
+}
+
+// Ordered dither threshold in (0, 1) for this pixel, from a 4x4 Bayer matrix
+float getDitherThreshold()
+{
+	const float bayer[16] = float[](
+		0.0, 8.0, 2.0, 10.0,
+		12.0, 4.0, 14.0, 6.0,
+		3.0, 11.0, 1.0, 9.0,
+		15.0, 7.0, 13.0, 5.0
+	);
+	ivec2 pixel = ivec2(gl_FragCoord.xy) % 4;
+	return (bayer[pixel.y * 4 + pixel.x] + 0.5) / 16.0;
+}
+
 void main()
 {
+	// Dither away geometry that gets close to the eyes, rather than letting the near plane clip it
+	if (ubo.nearFade.z > 0.0) {
+		float distance = -(ubo.view[gl_ViewIndex] * vec4(inWorldPos, 1.0)).z;
+		float fade = smoothstep(ubo.nearFade.y, ubo.nearFade.x, distance);
+		if (fade < getDitherThreshold()) {
+			discard;
+		}
+	}
+
 	float perceptualRoughness;
 	float metallic;
 	vec3 diffuseColor;
@@ -217,17 +322,45 @@ void main()
 
 	vec3 f0 = vec3(0.04);
 
-	if (material.alphaMask == 1.0f) {
+	uv0 = inUV0;
+	uv1 = inUV1;
+	if (material.heightTextureSet > -1) {
+		vec2 parallaxOffset = getParallaxOffset();
+		uv0 += parallaxOffset;
+		uv1 += parallaxOffset;
+	}
+
+	// Fraction of the pixel covered with alpha-to-coverage
+	float coverage = 1.0;
+	if (material.alphaMask > 0.0f) {
 		if (material.baseColorTextureSet > -1) {
-			baseColor = SRGBtoLINEAR(texture(colorMap, material.baseColorTextureSet == 0 ? inUV0 : inUV1)) * material.baseColorFactor;
+			baseColor = texture(colorMap, material.baseColorTextureSet == 0 ? uv0 : uv1) * material.baseColorFactor;
 		} else {
 			baseColor = material.baseColorFactor;
 		}
-		if (baseColor.a < material.alphaMaskCutoff) {
+		baseColor *= inColor;
+		if (material.alphaMask == ALPHA_MASK_COVERAGE) {
+			// Sharpen alpha so it goes from 0 to 1 over about a pixel around the cutoff, giving an anti-aliased edge.
+			coverage = clamp((baseColor.a - material.alphaMaskCutoff) / max(fwidth(baseColor.a), 0.0001) + 0.5, 0.0, 1.0);
+			if (coverage == 0.0) {
+				discard;
+			}
+		} else if (baseColor.a < material.alphaMaskCutoff) {
 			discard;
 		}
 	}
 
+	if (UNLIT || material.workflow >= PBR_WORKFLOW_UNLIT) {
+		outColor = getUnlitColor();
+		if (material.alphaMask == ALPHA_MASK_COVERAGE) {
+			outColor.a = coverage;
+		} else if (material.workflow == PBR_WORKFLOW_UNLIT_LINEAR_TEXTURE) {
+			// Panels are always opaque
+			outColor.a = 1.0;
+		}
+		return;
+	}
+
 	if (material.workflow == PBR_WORKFLOW_METALLIC_ROUGHNESS) {
 		// Metallic and Roughness material properties are packed together
 		// In glTF, these factors can be specified by fixed scalar values
@@ -237,7 +370,7 @@ void main()
 		if (material.physicalDescriptorTextureSet > -1) {
 			// Roughness is stored in the 'g' channel, metallic is stored in the 'b' channel.
 			// This layout intentionally reserves the 'r' channel for (optional) occlusion map data
-			vec4 mrSample = texture(physicalDescriptorMap, material.physicalDescriptorTextureSet == 0 ? inUV0 : inUV1);
+			vec4 mrSample = texture(physicalDescriptorMap, material.physicalDescriptorTextureSet == 0 ? uv0 : uv1);
 			perceptualRoughness = mrSample.g * perceptualRoughness;
 			metallic = mrSample.b * metallic;
 		} else {
@@ -249,7 +382,7 @@ void main()
 
 		// The albedo may be defined from a base texture or a flat color
 		if (material.baseColorTextureSet > -1) {
-			baseColor = SRGBtoLINEAR(texture(colorMap, material.baseColorTextureSet == 0 ? inUV0 : inUV1)) * material.baseColorFactor;
+			baseColor = texture(colorMap, material.baseColorTextureSet == 0 ? uv0 : uv1) * material.baseColorFactor;
 		} else {
 			baseColor = material.baseColorFactor;
 		}
@@ -258,15 +391,15 @@ void main()
 	if (material.workflow == PBR_WORKFLOW_SPECULAR_GLOSINESS) {
 		// Values from specular glossiness workflow are converted to metallic roughness
 		if (material.physicalDescriptorTextureSet > -1) {
-			perceptualRoughness = 1.0 - SRGBtoLINEAR(texture(physicalDescriptorMap, material.physicalDescriptorTextureSet == 0 ? inUV0 : inUV1)).a;
+			perceptualRoughness = 1.0 - texture(physicalDescriptorMap, material.physicalDescriptorTextureSet == 0 ? uv0 : uv1).a;
 		} else {
 			perceptualRoughness = 0.0;
 		}
 
 		const float epsilon = 1e-6;
 
-		vec4 diffuse = SRGBtoLINEAR(texture(colorMap, inUV0));
-		vec3 specular = SRGBtoLINEAR(texture(physicalDescriptorMap, inUV0)).rgb;
+		vec4 diffuse = texture(colorMap, uv0);
+		vec3 specular = texture(physicalDescriptorMap, uv0).rgb;
 
 		float maxSpecular = max(max(specular.r, specular.g), specular.b);
 
@@ -279,6 +412,9 @@ void main()
 
 	}
 
+	// Vertex colours are linear, and tint the base colour
+	baseColor *= inColor;
+
 	diffuseColor = baseColor.rgb * (vec3(1.0) - f0);
 	diffuseColor *= 1.0 - metallic;
 		
@@ -296,10 +432,16 @@ void main()
 	vec3 specularEnvironmentR90 = vec3(1.0, 1.0, 1.0) * reflectance90;
 
 	vec3 n = (material.normalTextureSet > -1) ? getNormal() : normalize(inNormal);
+
+	// The back faces of double sided materials should be lit as if they were facing the viewer.
+	if (material.doubleSided == 1.0 && !gl_FrontFacing) {
+		n = -n;
+	}
+
 	vec3 v = normalize(ubo.camPos[gl_ViewIndex].xyz - inWorldPos);    // Vector from surface point to camera
 	vec3 l = normalize(uboParams.lightDir.xyz);     // Vector from surface point to light
 	vec3 h = normalize(l+v);                        // Half vector between both l and v
-	vec3 reflection = -normalize(reflect(v, n));
+	vec3 reflection = mat3(ubo.reflectionRotation) * -normalize(reflect(v, n));
 	reflection.y *= -1.0f;
 
 	float NdotL = clamp(dot(n, l), 0.001, 1.0);
@@ -339,26 +481,23 @@ void main()
 	// Calculate lighting contribution from image based lighting source (IBL)
 	color += getIBLContribution(pbrInputs, n, reflection) * uboParams.scaleIBLAmbient;
 
+	// Constant ambient light, so areas the other lights don't reach aren't pure black
+	color += ubo.ambientLight.rgb * diffuseColor;
+
 	const float u_OcclusionStrength = 1.0f;
 	// Apply optional PBR terms for additional (optional) shading
 	if (material.occlusionTextureSet > -1) {
-		float ao = texture(aoMap, (material.occlusionTextureSet == 0 ? inUV0 : inUV1)).r;
+		float ao = texture(aoMap, (material.occlusionTextureSet == 0 ? uv0 : uv1)).r;
 		color = mix(color, color * ao, u_OcclusionStrength);
 	}
 
-	const float u_EmissiveFactor = 1.0f;
+	vec3 emissive = material.emissiveFactor.rgb;
 	if (material.emissiveTextureSet > -1) {
-		vec3 emissive = SRGBtoLINEAR(texture(emissiveMap, material.emissiveTextureSet == 0 ? inUV0 : inUV1)).rgb * u_EmissiveFactor;
-		color += emissive;
+		emissive *= texture(emissiveMap, material.emissiveTextureSet == 0 ? uv0 : uv1).rgb;
 	}
+	color += emissive;
 	
-	outColor = vec4(color, baseColor.a);
-
-
-	if (material.workflow == PBR_WORKFLOW_UNLIT) {
-		outColor = (texture(colorMap, material.baseColorTextureSet == 0 ? inUV0 : inUV1) * material.baseColorFactor);
-		outColor.a = 1;
-	}
+	outColor = vec4(applyCameraTonemapping(color), material.alphaMask == ALPHA_MASK_COVERAGE ? coverage : baseColor.a);
 
 	// vec3 N = normalize(inNormal);
 	// vec3 L = normalize(inLightVec);
@@ -377,25 +516,25 @@ void main()
 		int index = int(uboParams.debugViewInputs);
 		switch (index) {
 			case 1:
-				outColor.rgba = material.baseColorTextureSet > -1 ? texture(colorMap, material.baseColorTextureSet == 0 ? inUV0 : inUV1) : vec4(1.0f);
+				outColor.rgba = material.baseColorTextureSet > -1 ? texture(colorMap, material.baseColorTextureSet == 0 ? uv0 : uv1) : vec4(1.0f);
 				break;
 			case 2:
-				outColor.rgb = (material.normalTextureSet > -1) ? texture(normalMap, material.normalTextureSet == 0 ? inUV0 : inUV1).rgb : normalize(inNormal);
+				outColor.rgb = (material.normalTextureSet > -1) ? texture(normalMap, material.normalTextureSet == 0 ? uv0 : uv1).rgb : normalize(inNormal);
 				break;
 			case 3:
-				outColor.rgb = (material.occlusionTextureSet > -1) ? texture(aoMap, material.occlusionTextureSet == 0 ? inUV0 : inUV1).rrr : vec3(0.0f);
+				outColor.rgb = (material.occlusionTextureSet > -1) ? texture(aoMap, material.occlusionTextureSet == 0 ? uv0 : uv1).rrr : vec3(0.0f);
 				break;
 			case 4:
-				outColor.rgb = (material.emissiveTextureSet > -1) ? texture(emissiveMap, material.emissiveTextureSet == 0 ? inUV0 : inUV1).rgb : vec3(0.0f);
+				outColor.rgb = (material.emissiveTextureSet > -1) ? texture(emissiveMap, material.emissiveTextureSet == 0 ? uv0 : uv1).rgb : vec3(0.0f);
 				break;
 			case 5:
-				outColor.rgb = texture(physicalDescriptorMap, inUV0).bbb;
+				outColor.rgb = texture(physicalDescriptorMap, uv0).bbb;
 				break;
 			case 6:
-				outColor.rgb = texture(physicalDescriptorMap, inUV0).ggg;
+				outColor.rgb = texture(physicalDescriptorMap, uv0).ggg;
 				break;
 			case 7:
-				outColor.rgba = material.baseColorTextureSet > -1 ? texture(colorMap, material.baseColorTextureSet == 0 ? inUV0 : inUV1) * material.baseColorFactor: vec4(1.0f);
+				outColor.rgba = material.baseColorTextureSet > -1 ? texture(colorMap, material.baseColorTextureSet == 0 ? uv0 : uv1) * material.baseColorFactor: vec4(1.0f);
 				break;
 		}
 		outColor = SRGBtoLINEAR(outColor);
@@ -11,6 +11,7 @@ layout (location = 2) in vec2 inUV0;
 layout (location = 3) in vec2 inUV1;
 layout (location = 4) in vec4 inJoint0;
 layout (location = 5) in vec4 inWeight0;
+layout (location = 6) in vec4 inColor;
 
 layout (set = 0, binding = 0) uniform UBO  {
 	mat4 projection[2];
@@ -18,48 +19,59 @@ layout (set = 0, binding = 0) uniform UBO  {
 	vec4 camPos[2];
 } ubo;
 
-#define MAX_NUM_JOINTS 128
-
 layout (set = 2, binding = 0) uniform UBONode {
 	mat4 matrix;
-	mat4 jointMatrix[MAX_NUM_JOINTS];
 	float jointCount;
 } node;
 
+// Grows with the number of joints: see `Mesh::update_joint_buffer`
+layout (std430, set = 2, binding = 1) readonly buffer JointMatrices {
+	mat4 jointMatrix[];
+} joints;
+
 layout (location = 0) out vec3 outWorldPos;
 layout (location = 1) out vec3 outNormal;
 layout (location = 2) out vec2 outUV0;
 layout (location = 3) out vec2 outUV1;
+layout (location = 4) out vec4 outColor;
 
 out gl_PerVertex
 {
 	vec4 gl_Position;
 };
 
+// The inverse transpose of `m`, up to a positive scale, so normals stay perpendicular to their surface under
+// non-uniform scale. Built from the cofactors rather than `inverse`, so it's still defined for bones scaled to
+// nothing. Must match `get_normal_matrix` in `skinning.rs`: its tests check that copy, not this shader.
+mat3 getNormalMatrix(mat3 m)
+{
+	mat3 cofactor = mat3(cross(m[1], m[2]), cross(m[2], m[0]), cross(m[0], m[1]));
+	// The cofactors are the inverse transpose scaled by the determinant: keep mirrored normals pointing outwards.
+	return dot(m[0], cofactor[0]) < 0.0 ? -cofactor : cofactor;
+}
+
 void main() 
 {
-	vec4 locPos;
+	mat4 model = node.matrix;
 	if (node.jointCount > 0.0) {
 		// Mesh is skinned
 		mat4 skinMat = 
-			inWeight0.x * node.jointMatrix[int(inJoint0.x)] +
-			inWeight0.y * node.jointMatrix[int(inJoint0.y)] +
-			inWeight0.z * node.jointMatrix[int(inJoint0.z)] +
-			inWeight0.w * node.jointMatrix[int(inJoint0.w)];
-
-		locPos = node.matrix * skinMat * vec4(inPos, 1.0);
-		outNormal = normalize(transpose(inverse(mat3(node.matrix * skinMat))) * inNormal);
-	} else {
-		locPos = node.matrix * vec4(inPos, 1.0);
-		outNormal = normalize(transpose(inverse(mat3(node.matrix))) * inNormal);
+			inWeight0.x * joints.jointMatrix[int(inJoint0.x)] +
+			inWeight0.y * joints.jointMatrix[int(inJoint0.y)] +
+			inWeight0.z * joints.jointMatrix[int(inJoint0.z)] +
+			inWeight0.w * joints.jointMatrix[int(inJoint0.w)];
+		model = node.matrix * skinMat;
 	}
 
-	if (length(inNormal) == 0.0) {
-		outNormal = inNormal;
-	}
+	vec4 locPos = model * vec4(inPos, 1.0);
+
+	// Meshes without normals, and bones scaled to nothing, have no normal to normalise.
+	vec3 normal = getNormalMatrix(mat3(model)) * inNormal;
+	outNormal = dot(normal, normal) > 0.0 ? normalize(normal) : vec3(0.0);
 
 	outWorldPos = locPos.xyz / locPos.w;
 	outUV0 = inUV0;
 	outUV1 = inUV1;
+	outColor = inColor;
 	gl_Position =  ubo.projection[gl_ViewIndex] * ubo.view[gl_ViewIndex] * vec4(outWorldPos, 1.0);
 }
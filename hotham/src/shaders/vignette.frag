@@ -0,0 +1,18 @@
+#version 450
+
+// NOTE: This must match the layout of `Vignette`
+layout (push_constant) uniform Vignette {
+	float radius;
+	float intensity;
+} vignette;
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outColor;
+
+// Darken the periphery of the view, fading in just past the radius.
+void main() {
+	float distance = length(inUV - vec2(0.5)) * 2.0;
+	float alpha = smoothstep(vignette.radius, vignette.radius + 0.3, distance) * vignette.intensity;
+	outColor = vec4(0.0, 0.0, 0.0, alpha);
+}
@@ -1,15 +1,21 @@
 use anyhow::Result;
 use ash::vk::{self, Handle};
+use log::debug;
 use openxr::{Swapchain as SwapchainHandle, Vulkan};
 
 pub struct Swapchain {
     pub resolution: vk::Extent2D,
     pub images: Vec<vk::Image>,
+    pub format: vk::Format,
 }
 
 impl Swapchain {
-    pub(crate) fn new(handle: &SwapchainHandle<Vulkan>, resolution: vk::Extent2D) -> Result<Self> {
-        print!("[HOTHAM_INIT] Creating swapchain..");
+    pub(crate) fn new(
+        handle: &SwapchainHandle<Vulkan>,
+        resolution: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Self> {
+        debug!(target: "HOTHAM_INIT", "Creating swapchain..");
 
         let images = handle
             .enumerate_images()?
@@ -17,8 +23,12 @@ impl Swapchain {
             .map(vk::Image::from_raw)
             .collect::<Vec<_>>();
 
-        println!("..done!");
+        debug!(target: "HOTHAM_INIT", "..done!");
 
-        Ok(Self { resolution, images })
+        Ok(Self {
+            resolution,
+            images,
+            format,
+        })
     }
 }
@@ -1,44 +1,190 @@
-use crate::components::{animation_controller::AnimationController, AnimationTarget, Transform};
-use hecs::{PreparedQuery, World};
+use std::collections::HashSet;
+
+use crate::components::{
+    animation_controller::AnimationController, AnimationTarget, Transform, UpdateRate,
+};
+use hecs::{Entity, PreparedQuery, World};
+use nalgebra::Isometry3;
 
 /// Animation system
-/// Walks through each AnimationTarget and applies the appropriate animation
+/// Walks through each AnimationTarget and applies the appropriate animation, offset by its controller's `time_offset`
+/// If an `AnimationController` has root motion enabled, its root bone's motion is applied to the controller instead.
+/// Controllers with an `UpdateRate` only have their animations sampled on the frames it allows.
 pub fn animation_system(
     query: &mut PreparedQuery<(&mut AnimationTarget, &mut Transform)>,
     world: &mut World,
 ) {
-    for (_, (animation_target, transform)) in query.query(world).iter() {
+    apply_root_motion(world);
+
+    let held_controllers = world
+        .query_mut::<(&mut UpdateRate, &AnimationController)>()
+        .into_iter()
+        .filter_map(|(e, (update_rate, _))| if update_rate.tick() { None } else { Some(e) })
+        .collect::<HashSet<Entity>>();
+
+    for (entity, (animation_target, transform)) in query.query(world).iter() {
+        if held_controllers.contains(&animation_target.controller) {
+            continue;
+        }
+
         let controller = world
             .get::<AnimationController>(animation_target.controller)
             .unwrap();
-        let blend_from = controller.blend_from;
-        let blend_to = controller.blend_to;
-        let blend_amount = controller.blend_amount;
 
-        let transform_from = animation_target.animations[blend_from][0];
-        let transform_to = animation_target.animations[blend_to][0];
+        // The root bone's motion has already been moved to the controller, so hold it in place.
+        let keyframe = if controller.root_motion == Some(entity) {
+            0.
+        } else {
+            controller.keyframe + controller.time_offset
+        };
+
+        let sampled = sample_blended(animation_target, &controller, keyframe);
+        transform.translation = sampled.translation;
+        transform.rotation = sampled.rotation;
+        transform.scale = sampled.scale;
+    }
+}
+
+/// Move each root bone's motion since the last frame onto its controller's `Transform`.
+fn apply_root_motion(world: &mut World) {
+    let controllers = world
+        .query::<&AnimationController>()
+        .iter()
+        .filter_map(|(e, c)| c.root_motion.map(|bone| (e, bone)))
+        .collect::<Vec<(Entity, Entity)>>();
+
+    for (controller_entity, bone) in controllers {
+        let animation_target = match world.get::<AnimationTarget>(bone) {
+            Ok(animation_target) => animation_target,
+            Err(_) => continue,
+        };
+        let mut controller = world
+            .get_mut::<AnimationController>(controller_entity)
+            .unwrap();
+        let mut transform = match world.get_mut::<Transform>(controller_entity) {
+            Ok(transform) => transform,
+            Err(_) => continue,
+        };
+
+        let length = get_clip_length(animation_target.animations[controller.blend_from].len()).max(
+            get_clip_length(animation_target.animations[controller.blend_to].len()),
+        );
+        let current = wrap_keyframe(controller.keyframe + controller.time_offset, length);
+        let previous = match controller.root_motion_keyframe.replace(current) {
+            Some(previous) => previous,
+            // Nothing to extract on the first frame.
+            None => continue,
+        };
+
+        let delta =
+            get_root_motion_delta(&animation_target, &controller, previous, current, length);
+
+        // The delta is in the controller's space, so it's scaled along with everything else under it.
+        transform.translation +=
+            transform.rotation * transform.scale.component_mul(&delta.translation.vector);
+        transform.rotation *= delta.rotation;
+    }
+}
+
+/// Get the motion of the controller that makes its root bone, held at its first keyframe, end up where the animation
+/// would have moved it from `previous` to `current`. If the animation has looped in between, the motion to the end of
+/// the clip and from its start are combined.
+fn get_root_motion_delta(
+    animation_target: &AnimationTarget,
+    controller: &AnimationController,
+    previous: f32,
+    current: f32,
+    length: f32,
+) -> Isometry3<f32> {
+    let bone_at = |keyframe| sample_blended(animation_target, controller, keyframe).position();
+    let first = bone_at(0.);
+
+    let bone_motion = if current >= previous {
+        bone_at(previous).inverse() * bone_at(current)
+    } else {
+        bone_at(previous).inverse() * bone_at(length) * first.inverse() * bone_at(current)
+    };
 
-        transform.translation = transform_from
+    first * bone_motion * first.inverse()
+}
+
+/// Sample the controller's blend of `animation_target`'s animations at `keyframe`
+fn sample_blended(
+    animation_target: &AnimationTarget,
+    controller: &AnimationController,
+    keyframe: f32,
+) -> Transform {
+    let blend_amount = controller.blend_amount;
+    let transform_from = sample(
+        &animation_target.animations[controller.blend_from],
+        keyframe,
+    );
+    let transform_to = sample(&animation_target.animations[controller.blend_to], keyframe);
+
+    Transform {
+        translation: transform_from
             .translation
-            .lerp(&transform_to.translation, blend_amount);
-        transform.rotation = transform_from
+            .lerp(&transform_to.translation, blend_amount),
+        rotation: transform_from
             .rotation
-            .slerp(&transform_to.rotation, blend_amount);
-        transform.scale = transform_from.scale.lerp(&transform_to.scale, blend_amount);
+            .slerp(&transform_to.rotation, blend_amount),
+        scale: transform_from.scale.lerp(&transform_to.scale, blend_amount),
     }
 }
 
-#[cfg(target_os = "windows")]
-#[cfg(test)]
-mod tests {
-    use crate::{
-        gltf_loader::{add_model_to_world, load_models_from_glb},
-        resources::{render_context::create_descriptor_set_layouts, VulkanContext},
+/// Sample an animation at `keyframe`, interpolating between keyframes and looping at the end
+fn sample(animation: &[Transform], keyframe: f32) -> Transform {
+    let length = get_clip_length(animation.len());
+    if length == 0. {
+        return animation[0];
+    }
+
+    // Sampling exactly at the end gives the last keyframe, rather than looping back to the first.
+    let keyframe = if keyframe == length {
+        length
+    } else {
+        wrap_keyframe(keyframe, length)
     };
+    let index = (keyframe.floor() as usize).min(animation.len() - 2);
+    let amount = keyframe - index as f32;
+    let from = animation[index];
+    let to = animation[index + 1];
+
+    Transform {
+        translation: from.translation.lerp(&to.translation, amount),
+        rotation: from.rotation.slerp(&to.rotation, amount),
+        scale: from.scale.lerp(&to.scale, amount),
+    }
+}
+
+/// A clip with `keyframe_count` keyframes loops after this many keyframes
+fn get_clip_length(keyframe_count: usize) -> f32 {
+    keyframe_count.saturating_sub(1) as f32
+}
 
+fn wrap_keyframe(keyframe: f32, length: f32) -> f32 {
+    if length == 0. {
+        0.
+    } else {
+        keyframe.rem_euclid(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::vector;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[cfg(target_os = "windows")]
     #[test]
     pub fn animation_test() {
+        use crate::{
+            gltf_loader::{add_model_to_world, load_models_from_glb},
+            resources::{render_context::create_descriptor_set_layouts, VulkanContext},
+        };
+
         let vulkan_context = VulkanContext::testing().unwrap();
         let set_layouts = create_descriptor_set_layouts(&vulkan_context).unwrap();
 
@@ -84,4 +230,146 @@ mod tests {
         // Make sure our transforms have been modified!
         assert_ne!(transforms_before, transforms_after);
     }
+
+    #[test]
+    pub fn test_root_motion_moves_entity_forward() {
+        let mut world = World::new();
+        let (character, hips) = spawn_animated(&mut world, AnimationController::default(), 4);
+
+        // A walk clip: the hips bob up and down as they move forward, then loop.
+        for (n, keyframe) in world.get_mut::<AnimationTarget>(hips).unwrap().animations[0]
+            .iter_mut()
+            .enumerate()
+        {
+            keyframe.translation.y = 1. + 0.1 * (n % 2) as f32;
+        }
+        world
+            .get_mut::<AnimationController>(character)
+            .unwrap()
+            .root_motion = Some(hips);
+
+        let mut query = PreparedQuery::<(&mut AnimationTarget, &mut Transform)>::default();
+        let mut last_x = 0.;
+        for frame in 0..=12 {
+            world
+                .get_mut::<AnimationController>(character)
+                .unwrap()
+                .keyframe = frame as f32 * 0.5;
+            animation_system(&mut query, &mut world);
+
+            // The character should always be moving forward, even when the clip loops..
+            let x = world.get::<Transform>(character).unwrap().translation.x;
+            if frame > 0 {
+                assert!(
+                    x > last_x,
+                    "Frame {}: {} should be greater than {}",
+                    frame,
+                    x,
+                    last_x
+                );
+            }
+            last_x = x;
+
+            // ..while the hips stay where they started.
+            let hips_transform = *world.get::<Transform>(hips).unwrap();
+            assert_relative_eq!(hips_transform.translation, vector![0., 1., 0.]);
+        }
+
+        // 6 keyframes in, the clip has looped once and a half, so the character has walked 1.5m.
+        assert_relative_eq!(last_x, 1.5, epsilon = 1e-5);
+        let character_transform = *world.get::<Transform>(character).unwrap();
+        assert_relative_eq!(character_transform.translation.y, 0., epsilon = 1e-5);
+    }
+
+    #[test]
+    pub fn test_update_rate_samples_animation_less_often() {
+        let mut world = World::new();
+        let every_frame = spawn_animated(&mut world, AnimationController::default(), 8);
+        let every_other_frame = spawn_animated(&mut world, AnimationController::default(), 8);
+        world
+            .insert_one(every_other_frame.0, UpdateRate::new(2))
+            .unwrap();
+
+        let mut query = PreparedQuery::<(&mut AnimationTarget, &mut Transform)>::default();
+        let mut samples = [0, 0];
+        let mut last_x = [-1., -1.];
+        for frame in 0..8 {
+            for (controller, _) in [every_frame, every_other_frame] {
+                world
+                    .get_mut::<AnimationController>(controller)
+                    .unwrap()
+                    .keyframe = frame as f32;
+            }
+            animation_system(&mut query, &mut world);
+
+            for (i, (_, bone)) in [every_frame, every_other_frame].iter().enumerate() {
+                let x = world.get::<Transform>(*bone).unwrap().translation.x;
+                if x != last_x[i] {
+                    samples[i] += 1;
+                    last_x[i] = x;
+                }
+            }
+
+            // Between updates, the bone is held where it was last sampled.
+            let held_x = world
+                .get::<Transform>(every_other_frame.1)
+                .unwrap()
+                .translation
+                .x;
+            assert_eq!(held_x, 0.125 * (frame - frame % 2) as f32);
+        }
+
+        assert_eq!(samples, [8, 4]);
+    }
+
+    #[test]
+    pub fn test_time_offset_puts_entities_out_of_phase() {
+        let mut world = World::new();
+        let [in_phase, out_of_phase, wrapped] = [0., 2.5, 4.].map(|time_offset| {
+            let mut controller = AnimationController::new(0, 0, 0.).with_time_offset(time_offset);
+            controller.keyframe = 1.;
+            spawn_animated(&mut world, controller, 4).1
+        });
+
+        let mut query = PreparedQuery::<(&mut AnimationTarget, &mut Transform)>::default();
+        animation_system(&mut query, &mut world);
+
+        let x = |entity| world.get::<Transform>(entity).unwrap().translation.x;
+        assert_eq!(x(in_phase), 0.25);
+        assert_eq!(x(out_of_phase), 0.875);
+        // The offset is added before the animation loops.
+        assert_eq!(x(wrapped), 0.25);
+
+        let mut controller = AnimationController::default();
+        let mut rng = StdRng::seed_from_u64(42);
+        controller.randomize_time_offset(&mut rng, 4.);
+        assert!(controller.time_offset >= 0. && controller.time_offset < 4.);
+        controller.randomize_time_offset(&mut rng, 0.);
+        assert_eq!(controller.time_offset, 0.);
+    }
+
+    /// Spawn an entity with `controller`, and a bone it animates with a clip that moves 1m along X over
+    /// `keyframe_count` keyframes. Returns the (controller, bone) entities.
+    fn spawn_animated(
+        world: &mut World,
+        controller: AnimationController,
+        keyframe_count: usize,
+    ) -> (Entity, Entity) {
+        let clip = (0..=keyframe_count)
+            .map(|n| Transform {
+                translation: vector![n as f32 / keyframe_count as f32, 0., 0.],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let controller = world.spawn((Transform::default(), controller));
+        let bone = world.spawn((
+            Transform::default(),
+            AnimationTarget {
+                controller,
+                animations: vec![clip],
+            },
+        ));
+        (controller, bone)
+    }
 }
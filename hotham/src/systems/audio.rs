@@ -1,4 +1,5 @@
 use hecs::{PreparedQuery, World};
+use log::debug;
 use nalgebra::{Isometry3, Point3};
 
 use crate::{
@@ -39,8 +40,9 @@ pub fn audio_system(
         // Determine what we should do with the audio source
         match (sound_emitter.current_state(), &sound_emitter.next_state) {
             (SoundState::Stopped, Some(SoundState::Playing)) => {
-                println!(
-                    "[HOTHAM_AUDIO] - Playing sound effect at {:?}, {:?} from {:?}!. Original position: {:?}",
+                debug!(
+                    target: "HOTHAM_AUDIO",
+                    "Playing sound effect at {:?}, {:?} from {:?}!. Original position: {:?}",
                     position, velocity, listener_location, rigid_body.translation()
                 );
                 audio_context.play_audio(sound_emitter, position, velocity);
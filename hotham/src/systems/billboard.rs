@@ -0,0 +1,38 @@
+use hecs::{PreparedQuery, World};
+use nalgebra::Vector3;
+
+use crate::{
+    components::{billboard::get_billboard_matrix, Billboard, TransformMatrix},
+    resources::XrContext,
+    util::posef_to_isometry,
+};
+
+/// Billboard system
+/// Turns every `Billboard` to face the user's head, from where its `TransformMatrix` puts it in the world.
+/// Make sure to call this AFTER `begin_frame`, so the head pose is up to date, and AFTER
+/// `update_parent_transform_matrix_system`, so billboards parented to other entities are where they'll be drawn.
+pub fn billboard_system(
+    query: &mut PreparedQuery<(&Billboard, &mut TransformMatrix)>,
+    world: &mut World,
+    xr_context: &XrContext,
+) {
+    if xr_context.views.is_empty() {
+        return;
+    }
+
+    // The head sits between the eyes.
+    let head_position = xr_context
+        .views
+        .iter()
+        .map(|v| posef_to_isometry(v.pose).translation.vector)
+        .sum::<Vector3<f32>>()
+        / xr_context.views.len() as f32;
+
+    for (_, (billboard, transform_matrix)) in query.query_mut(world) {
+        if let Some(matrix) =
+            get_billboard_matrix(billboard.mode, &transform_matrix.0, &head_position)
+        {
+            transform_matrix.0 = matrix;
+        }
+    }
+}
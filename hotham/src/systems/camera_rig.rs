@@ -0,0 +1,71 @@
+use hecs::{PreparedQuery, World};
+use openxr::{View, ViewConfigurationType};
+
+use crate::{components::CameraRig, resources::XrContext, util::isometry_to_posef};
+
+/// Camera rig system
+/// Moves the camera of flat (handheld) views along the path of the first `CameraRig` in the world, `delta_time` seconds
+/// further than last frame. Does nothing in VR, as the camera has to follow the user's head.
+/// Make sure to call this AFTER `begin_frame`, which locates the views, and BEFORE `begin_pbr_renderpass`.
+pub fn camera_rig_system(
+    query: &mut PreparedQuery<&mut CameraRig>,
+    world: &mut World,
+    xr_context: &mut XrContext,
+    delta_time: f32,
+) {
+    if xr_context.view_type != ViewConfigurationType::PRIMARY_MONO {
+        return;
+    }
+
+    if let Some((_, rig)) = query.query_mut(world).into_iter().next() {
+        update_views(rig, &mut xr_context.views, delta_time);
+    }
+}
+
+/// Advance `rig` and move every view to its pose. A mono view is duplicated to fill both layers of the swapchain.
+fn update_views(rig: &mut CameraRig, views: &mut [View], delta_time: f32) {
+    rig.advance(delta_time);
+    if let Some(pose) = rig.pose() {
+        let pose = isometry_to_posef(pose);
+        for view in views.iter_mut() {
+            view.pose = pose;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{components::CameraKeyframe, util::posef_to_isometry};
+    use approx::assert_relative_eq;
+    use nalgebra::vector;
+    use openxr::{Fovf, Posef};
+
+    #[test]
+    pub fn test_camera_rig_system_moves_views() {
+        let mut rig = CameraRig::new(vec![
+            CameraKeyframe::new(0., vector![0., 1.6, 2.], vector![0., 1., 0.]),
+            CameraKeyframe::new(4., vector![2., 1.6, 0.], vector![0., 1., 0.]),
+        ]);
+        let view = View {
+            pose: Posef::IDENTITY,
+            fov: Fovf {
+                angle_left: -0.5,
+                angle_right: 0.5,
+                angle_up: 0.5,
+                angle_down: -0.5,
+            },
+        };
+        let mut views = vec![view, view];
+
+        // One second per frame, for four frames, should reach the last keyframe.
+        for _ in 0..4 {
+            update_views(&mut rig, &mut views, 1.);
+        }
+
+        for view in &views {
+            let pose = posef_to_isometry(view.pose);
+            assert_relative_eq!(pose.translation.vector, vector![2., 1.6, 0.]);
+        }
+    }
+}
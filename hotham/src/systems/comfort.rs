@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use log::warn;
+use nalgebra::Vector3;
+
+use crate::{
+    resources::{ComfortContext, RenderContext, VulkanContext, XrContext},
+    util::posef_to_isometry,
+};
+
+/// Comfort system
+/// Snap turns the player when the right thumbstick is pushed left or right.
+/// Make sure to call this BEFORE `begin_frame`, so that the whole frame is rendered from the new reference space.
+pub fn comfort_system(comfort_context: &mut ComfortContext, xr_context: &mut XrContext) {
+    let thumbstick = openxr::ActionInput::get(
+        &xr_context.thumbstick_action,
+        &xr_context.session,
+        xr_context.right_hand_subaction_path,
+    )
+    .unwrap();
+    if !thumbstick.is_active {
+        return;
+    }
+
+    let angle = match comfort_context.snap_turn(thumbstick.current_state.x, Instant::now()) {
+        Some(angle) => angle,
+        None => return,
+    };
+
+    // Turn around the player's head, rather than the centre of the stage.
+    let pivot = if xr_context.views.len() == 2 {
+        let left_eye = posef_to_isometry(xr_context.views[0].pose);
+        let right_eye = posef_to_isometry(xr_context.views[1].pose);
+        (left_eye.translation.vector + right_eye.translation.vector) * 0.5
+    } else {
+        Vector3::zeros()
+    };
+
+    if let Err(e) = xr_context.rotate_reference_space(angle, pivot) {
+        warn!(target: "HOTHAM_COMFORT", "Unable to snap turn: {:?}", e);
+    }
+}
+
+/// Vignette system
+/// Darkens the periphery of the player's vision while they're moving, based on `ComfortContext::player_velocity`.
+/// Make sure to call this AFTER `rendering_system` and BEFORE `end_pbr_renderpass`.
+pub fn vignette_system(
+    comfort_context: &ComfortContext,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &RenderContext,
+) {
+    if render_context.skip_frame {
+        return;
+    }
+
+    if let Some(vignette) = comfort_context.vignette() {
+        render_context.draw_vignette(vulkan_context, swapchain_image_index, &vignette);
+    }
+}
@@ -0,0 +1,161 @@
+use hecs::{Entity, PreparedQuery, World};
+use nalgebra::Isometry3;
+
+use crate::{
+    components::{
+        controller_pose::ControllerPoseKind, hand::Handedness, ControllerPose, Parent, Transform,
+        TransformMatrix,
+    },
+    resources::XrContext,
+    util::{is_space_valid, posef_to_isometry},
+};
+
+/// Controller pose system
+/// Moves each entity with a `ControllerPose` to that pose of its controller, taking anything attached to it along.
+/// Entities are left where they were while their controller isn't tracked.
+/// Make sure to call this AFTER `begin_frame` and BEFORE `update_transform_matrix_system`.
+pub fn controller_pose_system(
+    query: &mut PreparedQuery<(&ControllerPose, &mut Transform)>,
+    world: &mut World,
+    xr_context: &XrContext,
+) {
+    let time = xr_context.frame_state.predicted_display_time;
+    for (_, (controller_pose, transform)) in query.query_mut(world) {
+        let space = match (controller_pose.handedness, controller_pose.kind) {
+            (Handedness::Left, ControllerPoseKind::Grip) => &xr_context.left_hand_space,
+            (Handedness::Left, ControllerPoseKind::Aim) => &xr_context.left_pointer_space,
+            (Handedness::Right, ControllerPoseKind::Grip) => &xr_context.right_hand_space,
+            (Handedness::Right, ControllerPoseKind::Aim) => &xr_context.right_pointer_space,
+        };
+
+        let location = match space.locate(&xr_context.reference_space, time) {
+            Ok(location) => location,
+            Err(_) => continue,
+        };
+        if !is_space_valid(&location) {
+            continue;
+        }
+
+        let position = posef_to_isometry(location.pose);
+        transform.translation = position.translation.vector;
+        transform.rotation = position.rotation;
+    }
+}
+
+/// Convenience function to create an entity that's held `offset` from the `kind` pose of the `handedness`
+/// controller, eg. so a tool or weapon stays in the user's hand. `offset` is in the pose's space.
+///
+/// The entity is parented to an entity with a `ControllerPose`, which is created the first time that pose is used.
+/// Add a model to it by passing it as the parent to `add_model_to_world`:
+/// ```ignore
+/// // The sword's blade is along its +Y axis, so turn it to point forward out of the user's right fist.
+/// let offset = Isometry3::rotation(Vector3::x() * -FRAC_PI_2);
+/// let holder = attach_to_controller(world, Handedness::Right, ControllerPoseKind::Grip, offset);
+/// add_model_to_world("Sword", &models, world, Some(holder), vulkan_context, descriptor_set_layouts);
+/// ```
+pub fn attach_to_controller(
+    world: &mut World,
+    handedness: Handedness,
+    kind: ControllerPoseKind,
+    offset: Isometry3<f32>,
+) -> Entity {
+    let controller_pose = ControllerPose { handedness, kind };
+    let existing = world
+        .query::<&ControllerPose>()
+        .iter()
+        .find(|(_, c)| **c == controller_pose)
+        .map(|(e, _)| e);
+    let controller = existing.unwrap_or_else(|| {
+        world.spawn((
+            controller_pose,
+            Transform::default(),
+            TransformMatrix::default(),
+        ))
+    });
+
+    let transform = Transform {
+        translation: offset.translation.vector,
+        rotation: offset.rotation,
+        ..Default::default()
+    };
+    world.spawn((transform, TransformMatrix::default(), Parent(controller)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::{update_parent_transform_matrix_system, update_transform_matrix_system};
+    use approx::assert_relative_eq;
+    use hecs::Without;
+    use nalgebra::{vector, Point3, UnitQuaternion, Vector3};
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    pub fn test_attach_to_controller() {
+        let mut world = World::new();
+        let offset = Isometry3::translation(0., 0., -0.1);
+        let sword = attach_to_controller(
+            &mut world,
+            Handedness::Right,
+            ControllerPoseKind::Grip,
+            offset,
+        );
+        let shield = attach_to_controller(
+            &mut world,
+            Handedness::Left,
+            ControllerPoseKind::Grip,
+            offset,
+        );
+        let laser = attach_to_controller(
+            &mut world,
+            Handedness::Right,
+            ControllerPoseKind::Aim,
+            offset,
+        );
+        let dagger = attach_to_controller(
+            &mut world,
+            Handedness::Right,
+            ControllerPoseKind::Grip,
+            offset,
+        );
+
+        // Each pose is only followed by one entity.
+        let parent = |entity| world.get::<Parent>(entity).unwrap().0;
+        assert_eq!(parent(sword), parent(dagger));
+        assert_ne!(parent(sword), parent(shield));
+        assert_ne!(parent(sword), parent(laser));
+        assert_eq!(
+            *world.get::<ControllerPose>(parent(laser)).unwrap(),
+            ControllerPose {
+                handedness: Handedness::Right,
+                kind: ControllerPoseKind::Aim
+            }
+        );
+
+        // Move the right grip as `controller_pose_system` would..
+        {
+            let mut transform = world.get_mut::<Transform>(parent(sword)).unwrap();
+            transform.translation = vector![1., 1., 0.];
+            transform.rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2);
+        }
+        let mut update_transform_matrix_query = Default::default();
+        let mut parent_query = Default::default();
+        let mut roots_query = PreparedQuery::<Without<Parent, &TransformMatrix>>::default();
+        update_transform_matrix_system(&mut update_transform_matrix_query, &mut world);
+        update_parent_transform_matrix_system(&mut parent_query, &mut roots_query, &mut world);
+
+        // ..and the sword moves with it, keeping its offset in the grip's space.
+        let matrix = world.get::<TransformMatrix>(sword).unwrap().0;
+        assert_relative_eq!(
+            matrix.transform_point(&Point3::origin()),
+            Point3::new(0.9, 1., 0.),
+            epsilon = 1e-6
+        );
+        let matrix = world.get::<TransformMatrix>(shield).unwrap().0;
+        assert_relative_eq!(
+            matrix.transform_point(&Point3::origin()),
+            Point3::new(0., 0., -0.1),
+            epsilon = 1e-6
+        );
+    }
+}
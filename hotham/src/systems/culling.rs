@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+use crate::{
+    components::{
+        primitive::BoundingSphere, AnimationController, AnimationTarget, Joint, Mesh, Parent, Skin,
+        Transform, TransformMatrix,
+    },
+    scene_data::SceneData,
+};
+
+/// The planes of a view frustum in world space, with their normals pointing inwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Get the frustum of a view-projection matrix, with depth from 0 to 1 as in Vulkan
+    pub(crate) fn from_view_projection(view_projection: &Matrix4<f32>) -> Self {
+        let row = |i| view_projection.row(i).transpose();
+        let planes = [
+            row(3) + row(0), // Left
+            row(3) - row(0), // Right
+            row(3) + row(1), // Top
+            row(3) - row(1), // Bottom
+            row(2),          // Near
+            row(3) - row(2), // Far
+        ];
+
+        // Normalise the planes, so distances from them are in world units.
+        Self {
+            planes: planes.map(|p| p / p.xyz().norm()),
+        }
+    }
+
+    /// Get the frustum of each eye
+    pub(crate) fn from_scene_data(scene_data: &SceneData) -> [Frustum; 2] {
+        [0, 1].map(|i| {
+            Frustum::from_view_projection(&(scene_data.projection[i] * scene_data.view[i]))
+        })
+    }
+
+    /// Is any part of `sphere`, in world space, inside the frustum?
+    pub(crate) fn intersects(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.xyz().dot(&sphere.center) + p.w >= -sphere.radius)
+    }
+}
+
+/// Is `entity`'s `Mesh` outside all of `frustums`? Skinned meshes have their bounds expanded by their `Skin`'s margin,
+/// so that animated limbs reaching outside the bind pose aren't culled.
+pub(crate) fn is_culled(world: &World, entity: Entity, frustums: &[Frustum]) -> bool {
+    let bounding_sphere = match world.get::<Mesh>(entity).map(|m| m.bounding_sphere()) {
+        Ok(Some(bounding_sphere)) => bounding_sphere,
+        _ => return false,
+    };
+    let transform_matrix = match world.get::<TransformMatrix>(entity) {
+        Ok(transform_matrix) => transform_matrix.0,
+        Err(_) => return false,
+    };
+    let margin = world.get::<Skin>(entity).map(|s| s.margin()).unwrap_or(0.);
+
+    is_outside(&bounding_sphere, margin, &transform_matrix, frustums)
+}
+
+fn is_outside(
+    bounding_sphere: &BoundingSphere,
+    margin: f32,
+    transform_matrix: &Matrix4<f32>,
+    frustums: &[Frustum],
+) -> bool {
+    let sphere = bounding_sphere.expand(margin).transform(transform_matrix);
+    !frustums.iter().any(|f| f.intersects(&sphere))
+}
+
+/// Recalculate the culling margin of each `Skin` whose `AnimationController` has started blending between different
+/// animations since it was last calculated. `joints` maps each skinned entity to its joint entities.
+pub(crate) fn update_culling_margins(world: &mut World, joints: &HashMap<Entity, Vec<Entity>>) {
+    let stale = world
+        .query::<&Skin>()
+        .iter()
+        .filter(|(_, skin)| skin.culling_margin.is_none())
+        .filter_map(|(entity, skin)| {
+            let joints = joints.get(&entity)?;
+            let active = get_active_animations(world, joints)?;
+            if skin.animated_margin.map(|(a, _)| a) == Some(active) {
+                return None;
+            }
+            Some((
+                entity,
+                active,
+                get_animated_margin(world, entity, joints, active),
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, active, margin) in stale {
+        world.get_mut::<Skin>(entity).unwrap().animated_margin = Some((active, margin));
+    }
+}
+
+/// The (`blend_from`, `blend_to`) of the `AnimationController` animating `joints`, if they're animated
+fn get_active_animations(world: &World, joints: &[Entity]) -> Option<(usize, usize)> {
+    let controller = joints
+        .iter()
+        .find_map(|j| world.get::<AnimationTarget>(*j).ok().map(|t| t.controller))?;
+    let controller = world.get::<AnimationController>(controller).ok()?;
+    Some((controller.blend_from, controller.blend_to))
+}
+
+/// The furthest any of `joints` moves from its bind pose, in `skinned_entity`'s space, over every keyframe of the
+/// `active` animations
+fn get_animated_margin(
+    world: &World,
+    skinned_entity: Entity,
+    joints: &[Entity],
+    active: (usize, usize),
+) -> f32 {
+    let bind_positions = joints
+        .iter()
+        .filter_map(|j| {
+            let joint = world.get::<Joint>(*j).ok()?;
+            let bind_matrix = joint.inverse_bind_matrix.try_inverse()?;
+            Some((*j, bind_matrix.column(3).xyz()))
+        })
+        .collect::<Vec<(Entity, Vector3<f32>)>>();
+
+    let (blend_from, blend_to) = active;
+    let animations = if blend_from == blend_to {
+        vec![blend_from]
+    } else {
+        vec![blend_from, blend_to]
+    };
+
+    let mut margin = 0.0_f32;
+    for animation in animations {
+        let keyframe_count = joints
+            .iter()
+            .filter_map(|j| {
+                let animation_target = world.get::<AnimationTarget>(*j).ok()?;
+                Some(animation_target.animations.get(animation)?.len())
+            })
+            .max()
+            .unwrap_or(0);
+
+        for keyframe in 0..keyframe_count {
+            let skinned_matrix = get_posed_matrix(world, skinned_entity, animation, keyframe);
+            let inverse_skinned_matrix = match skinned_matrix.try_inverse() {
+                Some(m) => m,
+                None => continue,
+            };
+            for (joint, bind_position) in &bind_positions {
+                let posed_matrix =
+                    inverse_skinned_matrix * get_posed_matrix(world, *joint, animation, keyframe);
+                let displacement = (posed_matrix.column(3).xyz() - bind_position).norm();
+                margin = margin.max(displacement);
+            }
+        }
+    }
+
+    margin
+}
+
+/// `entity`'s transform relative to the root of its hierarchy, with every `AnimationTarget` posed at `keyframe` of
+/// `animation`
+fn get_posed_matrix(
+    world: &World,
+    entity: Entity,
+    animation: usize,
+    keyframe: usize,
+) -> Matrix4<f32> {
+    let mut matrix = Matrix4::identity();
+    let mut current = Some(entity);
+    while let Some(entity) = current {
+        let animated = world.get::<AnimationTarget>(entity).ok().and_then(|t| {
+            let keyframes = t.animations.get(animation)?;
+            keyframes
+                .get(keyframe)
+                .or_else(|| keyframes.last())
+                .copied()
+        });
+        let transform = animated
+            .or_else(|| world.get::<Transform>(entity).ok().map(|t| *t))
+            .unwrap_or_default();
+
+        matrix = Matrix4::new_translation(&transform.translation)
+            * Matrix4::from(transform.rotation)
+            * Matrix4::new_nonuniform_scaling(&transform.scale)
+            * matrix;
+        current = world.get::<Parent>(entity).ok().map(|p| p.0);
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::{vector, Perspective3};
+
+    #[test]
+    pub fn test_frustum() {
+        // Looking down -Z with a 90 degree field of view.
+        let projection = Perspective3::new(1., std::f32::consts::FRAC_PI_2, 0.05, 100.);
+        let frustum = Frustum::from_view_projection(&vulkan_depth(projection.to_homogeneous()));
+        let sphere = |x, y, z, radius| BoundingSphere {
+            center: vector![x, y, z],
+            radius,
+        };
+
+        assert!(frustum.intersects(&sphere(0., 0., -5., 0.5)));
+        assert!(!frustum.intersects(&sphere(0., 0., 5., 0.5)));
+        assert!(!frustum.intersects(&sphere(0., 0., -200., 0.5)));
+
+        // The edge of the view at 5m is 5m to the side, so a sphere further out than its radius is culled..
+        assert!(!frustum.intersects(&sphere(-6., 0., -5., 0.5)));
+        // ..and one that pokes inside isn't.
+        assert!(frustum.intersects(&sphere(-5.5, 0., -5., 1.)));
+    }
+
+    #[test]
+    pub fn test_animated_pose_is_not_culled() {
+        let mut world = World::new();
+
+        // A skinned mesh at the origin, with a single joint that swings 3m to the left over its animation.
+        let skinned_entity = world.spawn((Transform::default(), AnimationController::default()));
+        let joint = world.spawn((
+            Joint {
+                skeleton_root: skinned_entity,
+                inverse_bind_matrix: Matrix4::identity(),
+            },
+            Transform::default(),
+            Parent(skinned_entity),
+        ));
+        let keyframes = [0., -1.5, -3.]
+            .iter()
+            .map(|x| Transform {
+                translation: vector![*x, 0., 0.],
+                ..Default::default()
+            })
+            .collect();
+        world
+            .insert_one(
+                joint,
+                AnimationTarget {
+                    controller: skinned_entity,
+                    animations: vec![keyframes],
+                },
+            )
+            .unwrap();
+        world
+            .insert_one(skinned_entity, Skin::new(vec![0]))
+            .unwrap();
+
+        let joints = vec![(skinned_entity, vec![joint])].into_iter().collect();
+        update_culling_margins(&mut world, &joints);
+        let margin = world.get::<Skin>(skinned_entity).unwrap().margin();
+        assert_relative_eq!(margin, 3.);
+
+        // The mesh is just off to the right of a camera looking down -Z. Its rest pose is out of view, but the
+        // animated pose reaches back into it.
+        let projection = Perspective3::new(1., std::f32::consts::FRAC_PI_2, 0.05, 100.);
+        let frustums = [Frustum::from_view_projection(&vulkan_depth(
+            projection.to_homogeneous(),
+        ))];
+        let bounding_sphere = BoundingSphere {
+            center: Vector3::zeros(),
+            radius: 0.5,
+        };
+        let transform_matrix = Matrix4::new_translation(&vector![6., 0., -5.]);
+        assert!(is_outside(
+            &bounding_sphere,
+            0.,
+            &transform_matrix,
+            &frustums
+        ));
+        assert!(!is_outside(
+            &bounding_sphere,
+            margin,
+            &transform_matrix,
+            &frustums
+        ));
+
+        // A user supplied margin overrides the animated one.
+        world
+            .get_mut::<Skin>(skinned_entity)
+            .unwrap()
+            .culling_margin = Some(0.);
+        let margin = world.get::<Skin>(skinned_entity).unwrap().margin();
+        assert!(is_outside(
+            &bounding_sphere,
+            margin,
+            &transform_matrix,
+            &frustums
+        ));
+    }
+
+    /// Convert an OpenGL style projection, with depth from -1 to 1, to Vulkan's 0 to 1
+    fn vulkan_depth(projection: Matrix4<f32>) -> Matrix4<f32> {
+        Matrix4::new_translation(&vector![0., 0., 0.5])
+            * Matrix4::new_nonuniform_scaling(&vector![1., 1., 0.5])
+            * projection
+    }
+}
@@ -18,6 +18,11 @@ pub fn draw_gui_system(
     gui_context: &mut GuiContext,
     haptic_context: &mut HapticContext,
 ) {
+    // Don't record anything if this frame is being skipped.
+    if render_context.skip_frame {
+        return;
+    }
+
     // Reset hovered_this_frame
     gui_context.hovered_this_frame = false;
 
@@ -51,7 +56,7 @@ pub fn draw_gui_system(
 mod tests {
     use super::*;
     use anyhow::Result;
-    use ash::vk::{self, Handle};
+    use ash::vk;
     use egui::Pos2;
     use image::{jpeg::JpegEncoder, DynamicImage, RgbaImage};
     use nalgebra::UnitQuaternion;
@@ -209,7 +214,7 @@ mod tests {
 
         // End PBR render
         render_context.end_pbr_render_pass(vulkan_context, 0);
-        render_context.end_frame(vulkan_context, 0);
+        render_context.end_frame(vulkan_context, 0).unwrap();
     }
 
     fn begin_frame(render_context: &mut RenderContext, vulkan_context: &VulkanContext) {
@@ -234,10 +239,10 @@ mod tests {
         };
         let views = vec![view.clone(), view];
 
-        render_context.begin_frame(&vulkan_context, 0);
+        render_context.begin_frame(&vulkan_context, 0).unwrap();
 
         render_context
-            .update_scene_data(&views, &vulkan_context)
+            .update_scene_data(&views, &vulkan_context, 0)
             .unwrap();
         render_context
             .scene_params_buffer
@@ -284,6 +289,7 @@ mod tests {
             &vulkan_context,
             &image_data,
             vk::BufferUsageFlags::TRANSFER_DST,
+            "Screenshot Buffer",
         )
         .unwrap();
         vulkan_context.transition_image_layout(
@@ -331,15 +337,14 @@ mod tests {
                 vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
                 2,
                 1,
+                "Screenshot",
             )
             .unwrap();
-        vulkan_context
-            .set_debug_name(vk::ObjectType::IMAGE, image.handle.as_raw(), "Screenshot")
-            .unwrap();
 
         let swapchain = Swapchain {
             images: vec![image.handle],
             resolution,
+            format: COLOR_FORMAT,
         };
 
         let render_context =
@@ -0,0 +1,41 @@
+use crate::{
+    resources::{gaze_context::GazeSource, GazeContext, XrContext},
+    util::{is_space_valid, posef_to_isometry},
+};
+use log::warn;
+
+/// Gaze system
+/// Updates `GazeContext` with where the user is looking this frame, using eye tracking if it's enabled and available,
+/// otherwise the direction of the user's head. Both are located in `XrContext::reference_space`.
+/// Make sure to call this AFTER `begin_frame`.
+pub fn gaze_system(gaze_context: &mut GazeContext, xr_context: &XrContext) {
+    let time = xr_context.frame_state.predicted_display_time;
+
+    if gaze_context.eye_tracking_enabled {
+        if let Some((_, eye_gaze_space)) = &xr_context.eye_gaze {
+            match eye_gaze_space.locate(&xr_context.reference_space, time) {
+                Ok(location) if is_space_valid(&location) => {
+                    gaze_context.pose = Some(posef_to_isometry(location.pose));
+                    gaze_context.source = GazeSource::Eyes;
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => warn!(target: "HOTHAM_GAZE", "Unable to locate eye gaze: {:?}", e),
+            }
+        }
+    }
+
+    // Fall back to the head.
+    gaze_context.source = GazeSource::Head;
+    gaze_context.pose = match xr_context
+        .view_space
+        .locate(&xr_context.reference_space, time)
+    {
+        Ok(location) if is_space_valid(&location) => Some(posef_to_isometry(location.pose)),
+        Ok(_) => None,
+        Err(e) => {
+            warn!(target: "HOTHAM_GAZE", "Unable to locate head: {:?}", e);
+            None
+        }
+    };
+}
@@ -0,0 +1,33 @@
+use hecs::World;
+use nalgebra::Vector2;
+use openxr::ViewConfigurationType;
+
+use crate::resources::{gizmo::get_cursor_ray, Gizmo, RenderContext, XrContext};
+
+/// Gizmo system
+/// Updates `gizmo` with the mouse (or touch) `cursor`, in normalised device coordinates of the flat view, and whether
+/// its button is `pressed`, then draws the gizmo's handles. Dragging a handle edits the selected entity's `Transform`.
+/// Does nothing in VR, where there is no cursor to drag with.
+/// Make sure to call this AFTER `begin_frame`, which locates the views and clears last frame's debug lines, and BEFORE
+/// `update_transform_matrix_system`.
+pub fn gizmo_system(
+    gizmo: &mut Gizmo,
+    world: &mut World,
+    xr_context: &XrContext,
+    render_context: &mut RenderContext,
+    cursor: Vector2<f32>,
+    pressed: bool,
+) {
+    if xr_context.view_type != ViewConfigurationType::PRIMARY_MONO {
+        return;
+    }
+
+    let view = match xr_context.views.first() {
+        Some(view) => view,
+        None => return,
+    };
+
+    let (origin, direction) = get_cursor_ray(view, cursor);
+    gizmo.update(world, &origin, &direction, pressed);
+    gizmo.draw(world, render_context);
+}
@@ -0,0 +1,308 @@
+use ash::vk;
+use hecs::{Entity, PreparedQuery, World};
+use nalgebra::{Matrix4, Point3, Vector4};
+
+use crate::{
+    components::{
+        mirror::{get_oblique_projection, get_reflection_matrix},
+        Highlight, Mesh, Mirror, TransformMatrix,
+    },
+    resources::{
+        render_context::{create_push_constant, get_front_face, MIRROR_STENCIL_REFERENCE},
+        vulkan_context::has_stencil_component,
+        RenderContext, VulkanContext,
+    },
+    scene_data::SceneData,
+    systems::reflection_probe::get_captured_entities,
+};
+
+/// Mirror system
+/// Draws the reflection in the first `Mirror` in the world, in three steps:
+///
+/// 1. The mirror's mesh marks where it's visible in the stencil buffer
+/// 2. The world is drawn again from each eye's reflected view, but only where it was marked
+/// 3. The mirror's mesh tints the reflection, and replaces its depth with the mirror's own, so nothing behind the
+///    mirror is drawn over it
+///
+/// Make sure to call this AFTER `begin_pbr_renderpass` and BEFORE `rendering_system`. Leaves the default pipeline bound.
+pub fn mirror_system(
+    query: &mut PreparedQuery<(&Mirror, &TransformMatrix)>,
+    world: &mut World,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &RenderContext,
+) {
+    // Don't record anything if this frame is being skipped, or if there's no stencil buffer to mask the mirror with.
+    if render_context.skip_frame || !has_stencil_component(render_context.depth_format) {
+        return;
+    }
+
+    let (entity, plane, tint, descriptor_set) = match query.query(world).iter().next() {
+        Some((entity, (mirror, transform_matrix))) => {
+            let plane = mirror.get_plane(&transform_matrix.0);
+            let scene_data = get_reflected_scene_data(&render_context.scene_data, &plane);
+            mirror.scene_data_buffers[swapchain_image_index]
+                .update(vulkan_context, &[scene_data])
+                .unwrap();
+            let descriptor_set = mirror.scene_data_descriptor_sets[swapchain_image_index];
+            (entity, plane, mirror.tint, descriptor_set)
+        }
+        None => return,
+    };
+
+    // Nothing is reflected if neither eye is in front of the mirror.
+    let camera_position = render_context.scene_data.camera_position;
+    if camera_position
+        .iter()
+        .all(|p| plane.dot(&p.xyz().push(1.)) <= 0.)
+    {
+        return;
+    }
+
+    let device = &vulkan_context.device;
+    let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+
+    // The mirror is drawn with the outline shaders, unextruded, in its tint.
+    let push_constant = Highlight {
+        color: tint,
+        width: 0.,
+    };
+    draw_mirror(
+        entity,
+        render_context.mirror_mask_pipeline,
+        &push_constant,
+        world,
+        vulkan_context,
+        swapchain_image_index,
+        render_context,
+    );
+
+    unsafe {
+        device.cmd_set_stencil_reference(
+            command_buffer,
+            vk::StencilFaceFlags::FRONT_AND_BACK,
+            MIRROR_STENCIL_REFERENCE,
+        );
+        device.cmd_set_depth_bias(command_buffer, 0.0, 0.0, 0.0);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            render_context.pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+    }
+
+    let reflection = get_reflection_matrix(&plane);
+    for entity in get_captured_entities(world) {
+        draw_reflected_mesh(
+            entity,
+            &reflection,
+            world,
+            vulkan_context,
+            command_buffer,
+            render_context,
+        );
+    }
+
+    draw_mirror(
+        entity,
+        render_context.mirror_composite_pipeline,
+        &push_constant,
+        world,
+        vulkan_context,
+        swapchain_image_index,
+        render_context,
+    );
+
+    // Leave the default pipeline bound for anyone drawing after us.
+    unsafe {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            render_context.pipeline,
+        );
+        device.cmd_set_stencil_reference(command_buffer, vk::StencilFaceFlags::FRONT_AND_BACK, 0);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            render_context.pipeline_layout,
+            0,
+            &[render_context.scene_data_descriptor_set(swapchain_image_index)],
+            &[],
+        );
+    }
+}
+
+/// The scene data for drawing the world reflected in `plane`: each eye's view is reflected, with the near plane moved
+/// onto the mirror so nothing behind it is drawn. See `get_reflection_matrix`.
+fn get_reflected_scene_data(scene_data: &SceneData, plane: &Vector4<f32>) -> SceneData {
+    let reflection = get_reflection_matrix(plane);
+    let view = scene_data.view.map(|v| v * reflection);
+    let projection = [
+        get_oblique_projection(&scene_data.projection[0], &view[0], plane),
+        get_oblique_projection(&scene_data.projection[1], &view[1], plane),
+    ];
+    let camera_position = scene_data.camera_position.map(|p| {
+        let reflected = reflection.transform_point(&Point3::from(p.xyz()));
+        reflected.coords.push(p.w)
+    });
+
+    SceneData {
+        view,
+        projection,
+        camera_position,
+        // Mirrors are lit by the image based lighting maps, never by a `ReflectionProbe`.
+        reflection_rotation: scene_data.environment_rotation,
+        ..*scene_data
+    }
+}
+
+/// Draw the triangles in the mirror's mesh with `pipeline`, from the real eyes
+fn draw_mirror(
+    entity: Entity,
+    pipeline: vk::Pipeline,
+    push_constant: &Highlight,
+    world: &World,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &RenderContext,
+) {
+    let device = &vulkan_context.device;
+    let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+    let pipeline_layout = render_context.outline_pipeline_layout;
+    let transform_matrix = world.get::<TransformMatrix>(entity).unwrap().0;
+    let mut mesh = match world.get_mut::<Mesh>(entity) {
+        Ok(mesh) => mesh,
+        Err(_) => return,
+    };
+
+    unsafe {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+
+        // The outline pipeline layout isn't compatible with the PBR one, so the scene data must be bound again.
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[render_context.scene_data_descriptor_set(swapchain_image_index)],
+            &[],
+        );
+
+        mesh.ubo_data.transform = transform_matrix;
+        mesh.ubo_buffer
+            .update(&vulkan_context, &[mesh.ubo_data])
+            .unwrap();
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            2,
+            &mesh.descriptor_sets,
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            create_push_constant(push_constant),
+        );
+
+        for primitive in &mesh.primitives {
+            if primitive.topology != vk::PrimitiveTopology::TRIANGLE_LIST {
+                continue;
+            }
+
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[primitive.vertex_buffer.handle],
+                &[0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                primitive.index_buffer.handle(),
+                0,
+                primitive.index_buffer.index_type(),
+            );
+            primitive.draw(device, command_buffer);
+        }
+    }
+}
+
+/// Draw a mesh inside the mirror. The reflection reverses the winding of its triangles, so its front faces are found
+/// with the reflection applied.
+fn draw_reflected_mesh(
+    entity: Entity,
+    reflection: &Matrix4<f32>,
+    world: &World,
+    vulkan_context: &VulkanContext,
+    command_buffer: vk::CommandBuffer,
+    render_context: &RenderContext,
+) {
+    let device = &vulkan_context.device;
+    let transform_matrix = world.get::<TransformMatrix>(entity).unwrap().0;
+    let mut mesh = world.get_mut::<Mesh>(entity).unwrap();
+    let front_face = get_front_face(&(reflection * transform_matrix));
+
+    unsafe {
+        mesh.ubo_data.transform = transform_matrix;
+        mesh.ubo_buffer
+            .update(&vulkan_context, &[mesh.ubo_data])
+            .unwrap();
+
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            render_context.pipeline_layout,
+            2,
+            &mesh.descriptor_sets,
+            &[],
+        );
+
+        for primitive in &mesh.primitives {
+            let pipeline = render_context
+                .get_mirror_pipeline(
+                    vulkan_context,
+                    primitive.topology,
+                    front_face,
+                    primitive.material.cull_mode(),
+                )
+                .unwrap();
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            render_context.bind_line_width(vulkan_context, command_buffer, primitive.topology);
+
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[primitive.vertex_buffer.handle],
+                &[0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                primitive.index_buffer.handle(),
+                0,
+                primitive.index_buffer.index_type(),
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                render_context.pipeline_layout,
+                1,
+                &[primitive.texture_descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                render_context.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                create_push_constant(&primitive.material),
+            );
+            primitive.draw(device, command_buffer);
+        }
+    }
+}
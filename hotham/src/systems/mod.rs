@@ -1,33 +1,84 @@
+//! Hotham's built-in systems.
+//!
+//! Hotham has no scheduler: applications call systems themselves, once per frame, in their own `tick` function. Their
+//! own systems can go anywhere in that order. The built-in systems expect to be called in these stages, in this order:
+//!
+//! 1. **PreUpdate**, before `begin_frame`: `comfort_system`.
+//! 2. **Update**, after `begin_frame` has located the views and controllers: `controller_pose_system` and
+//!    `hands_system`, then
+//!    `physics_step`, `collision_system`, `grabbing_system` and `update_rigid_body_transforms_system`. Then
+//!    `pointers_system`, `pointer_ray_system`, `gaze_system`, `ui_panel_system`, `animation_system`,
+//!    `ui_overlay_system`, `gizmo_system`, `audio_system` and `camera_rig_system`. Gameplay systems usually go here.
+//! 3. **Sync point**: `EntityCommands::apply`, which spawns, despawns and re-parents the entities queued during
+//!    Update, in the order they were queued.
+//! 4. **PostUpdate**, once every `Transform` is final: `update_transform_matrix_system`, then
+//!    `update_parent_transform_matrix_system`, then `billboard_system` and `skinning_system`.
+//! 5. **PreRender**, before `begin_pbr_renderpass`: `texture_streaming_system` first, as it must run before anything
+//!    is recorded for the frame. Then `draw_gui_system` and `reflection_probe_system`. Then, inside
+//!    the PBR render pass: `mirror_system`, `rendering_system`, `skeleton_debug_system` and `vignette_system`.
+//!
+//! Systems in the same stage don't depend on each other unless noted above. A system that moves entities should run
+//! in Update, so PostUpdate picks up the change in the same frame. Systems shouldn't spawn or despawn entities
+//! themselves: queue the change on `EntityCommands` instead, so it can't invalidate another system's query.
 #![allow(missing_docs)]
 pub mod animation;
 pub mod audio;
+pub mod billboard;
+pub mod camera_rig;
 pub mod collision;
+pub mod comfort;
+pub mod controller_pose;
+pub(crate) mod culling;
 pub mod draw_gui;
+pub mod gaze;
+pub mod gizmo;
 pub mod grabbing;
 pub mod hands;
+pub mod mirror;
+pub mod pointer_ray;
 pub mod pointers;
+pub mod reflection_probe;
 pub mod rendering;
+pub mod skeleton_debug;
 pub mod skinning;
+pub mod texture_streaming;
+pub mod ui_overlay;
+pub mod ui_panel;
 pub mod update_parent_transform_matrix;
 pub mod update_rigid_body_transforms;
 pub mod update_transform_matrix;
 
 pub use animation::animation_system;
 pub use audio::audio_system;
+pub use billboard::billboard_system;
+pub use camera_rig::camera_rig_system;
 pub use collision::collision_system;
+pub use comfort::{comfort_system, vignette_system};
+pub use controller_pose::{attach_to_controller, controller_pose_system};
 pub use draw_gui::draw_gui_system;
+pub use gaze::gaze_system;
+pub use gizmo::gizmo_system;
 pub use grabbing::grabbing_system;
 pub use hands::hands_system;
+pub use mirror::mirror_system;
+pub use pointer_ray::pointer_ray_system;
 pub use pointers::pointers_system;
+pub use reflection_probe::reflection_probe_system;
 pub use rendering::rendering_system;
+pub use skeleton_debug::skeleton_debug_system;
 pub use skinning::skinning_system;
+pub use texture_streaming::texture_streaming_system;
+pub use ui_overlay::ui_overlay_system;
+pub use ui_panel::ui_panel_system;
 pub use update_parent_transform_matrix::update_parent_transform_matrix_system;
 pub use update_rigid_body_transforms::update_rigid_body_transforms_system;
 pub use update_transform_matrix::update_transform_matrix_system;
 
 use crate::components::{
-    AnimationController, AnimationTarget, Collider, Hand, Info, Joint, Mesh, Panel, Parent,
-    Pointer, RigidBody, Skin, SoundEmitter, Transform, TransformMatrix, Visible,
+    AnimationController, AnimationTarget, Billboard, CameraRig, Collider, ControllerPose, Hand,
+    Highlight, Info, Joint, Mesh, Mirror, Panel, Parent, Pointer, PointerRay, ReflectionProbe,
+    RenderFlags, RenderLayer, RigidBody, Skin, SoundEmitter, Transform, TransformMatrix, UiOverlay,
+    Visible,
 };
 use hecs::{PreparedQuery, With, Without};
 
@@ -36,16 +87,40 @@ use hecs::{PreparedQuery, With, Without};
 pub struct Queries<'a> {
     pub animation_query: PreparedQuery<(&'a mut AnimationTarget, &'a mut Transform)>,
     pub audio_query: PreparedQuery<(&'a mut SoundEmitter, &'a RigidBody)>,
+    pub billboard_query: PreparedQuery<(&'a Billboard, &'a mut TransformMatrix)>,
+    pub camera_rig_query: PreparedQuery<&'a mut CameraRig>,
     pub collision_query: PreparedQuery<&'a mut Collider>,
+    pub controller_pose_query: PreparedQuery<(&'a ControllerPose, &'a mut Transform)>,
     pub draw_gui_query: PreparedQuery<&'a mut Panel>,
     pub grabbing_query: PreparedQuery<(&'a mut Hand, &'a Collider)>,
     pub hands_query: PreparedQuery<(&'a mut Hand, &'a mut AnimationController, &'a mut RigidBody)>,
     pub joints_query: PreparedQuery<(&'a TransformMatrix, &'a Joint, &'a Info)>,
     pub meshes_query: PreparedQuery<(&'a mut Mesh, &'a Skin)>,
+    pub mirror_query: PreparedQuery<(&'a Mirror, &'a TransformMatrix)>,
     pub parent_query: PreparedQuery<&'a Parent>,
-    pub rendering_query: PreparedQuery<With<Visible, (&'a mut Mesh, &'a TransformMatrix)>>,
+    pub reflection_probe_query: PreparedQuery<(&'a ReflectionProbe, &'a TransformMatrix)>,
+    pub rendering_query: PreparedQuery<
+        With<
+            Visible,
+            With<
+                Mesh,
+                (
+                    &'a TransformMatrix,
+                    Option<&'a RenderLayer>,
+                    Option<&'a Highlight>,
+                    Option<&'a RenderFlags>,
+                ),
+            >,
+        >,
+    >,
     pub roots_query: PreparedQuery<Without<Parent, &'a TransformMatrix>>,
+    pub skeleton_debug_query: PreparedQuery<With<Joint, (&'a TransformMatrix, &'a Parent)>>,
+    pub texture_streaming_query: PreparedQuery<With<Visible, (&'a Mesh, &'a TransformMatrix)>>,
     pub update_rigid_body_transforms_query: PreparedQuery<(&'a RigidBody, &'a mut Transform)>,
     pub update_transform_matrix_query: PreparedQuery<(&'a Transform, &'a mut TransformMatrix)>,
     pub pointers_query: PreparedQuery<With<Visible, (&'a mut Pointer, &'a mut Transform)>>,
+    pub pointer_ray_query:
+        PreparedQuery<With<Visible, (&'a Pointer, &'a Transform, &'a mut PointerRay)>>,
+    pub ui_overlay_query: PreparedQuery<(&'a UiOverlay, &'a mut Transform)>,
+    pub ui_panel_query: PreparedQuery<With<Visible, (&'a Pointer, &'a Transform)>>,
 }
@@ -0,0 +1,45 @@
+use hecs::{PreparedQuery, With, World};
+use nalgebra::{vector, Point3};
+
+use crate::{
+    components::{Parent, Pointer, PointerRay, Transform, Visible},
+    raycast::raycast_filtered,
+    resources::RenderContext,
+};
+
+/// Pointer ray system
+/// Casts a ray from every visible `Pointer` with a `PointerRay`, records what it hit in `PointerRay::hit`, and draws
+/// the ray and its reticle with debug lines. The pointer's own mesh, and those of its children, are ignored.
+/// Make sure to call this AFTER `pointers_system`, which moves the pointers and reads their triggers, and BEFORE
+/// `update_transform_matrix_system`.
+pub fn pointer_ray_system(
+    query: &mut PreparedQuery<With<Visible, (&Pointer, &Transform, &mut PointerRay)>>,
+    world: &mut World,
+    render_context: &mut RenderContext,
+) {
+    let world = &*world;
+    for (entity, (pointer, transform, pointer_ray)) in query.query(world).iter() {
+        // Pointers aim along their Y axis, as in `pointers_system`.
+        let origin = transform.translation;
+        let direction = transform.rotation.transform_vector(&vector![0., 1., 0.]);
+
+        let is_pointer = |e| {
+            e == entity
+                || world
+                    .get::<Parent>(e)
+                    .map_or(false, |parent| parent.0 == entity)
+        };
+        pointer_ray.hit = raycast_filtered(
+            world,
+            &Point3::from(origin),
+            &direction,
+            pointer_ray.max_distance,
+            |e| !is_pointer(e),
+        );
+
+        let color = pointer_ray.get_color(pointer_ray.state(pointer.trigger_value));
+        for (from, to) in pointer_ray.get_lines(&origin, &direction) {
+            render_context.draw_debug_line(from, to, color);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use ash::vk;
 use egui::Pos2;
 use hecs::{PreparedQuery, With, World};
+use log::trace;
 use nalgebra::{
     point, vector, Isometry3, Orthographic3, Point3, Quaternion, Translation3, UnitQuaternion,
 };
@@ -106,7 +107,11 @@ pub fn pointers_system(
                 }
                 Err(_) => {
                     let info = world.get::<Info>(entity).map(|i| format!("{:?}", *i));
-                    println!("[HOTHAM_POINTERS] Ray collided with object that does not have a panel: {:?} - {:?}", entity, info);
+                    trace!(
+                        target: "HOTHAM_POINTERS",
+                        "Ray collided with object that does not have a panel: {:?} - {:?}",
+                        entity, info
+                    );
                 }
             }
         }
@@ -0,0 +1,459 @@
+use ash::vk;
+use hecs::{Entity, PreparedQuery, With, World};
+use nalgebra::Vector3;
+
+use crate::{
+    buffer::Buffer,
+    components::{
+        reflection_probe::{get_face_projection, get_face_views, FACE_PAIRS},
+        Mesh, ReflectionProbe, RenderFlags, RenderLayer, TransformMatrix, Visible,
+    },
+    image::Image,
+    resources::{
+        render_context::{
+            create_push_constant, get_front_face, set_viewport, DepthPass, CLEAR_VALUES,
+        },
+        RenderContext, VulkanContext,
+    },
+    scene_data::{NearFade, SceneData},
+};
+
+/// Reflection probe system
+/// Captures the first `ReflectionProbe` that is due to be captured, then uses the captured probe nearest the user for
+/// specular reflections. If there are no captured probes, the default image based lighting is used instead.
+/// At most one probe is captured each frame, so the cost of capturing doesn't add up when there are several.
+/// Make sure to call this AFTER `begin_frame` and BEFORE `begin_pbr_renderpass`.
+pub fn reflection_probe_system(
+    query: &mut PreparedQuery<(&ReflectionProbe, &TransformMatrix)>,
+    world: &mut World,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &mut RenderContext,
+) {
+    // Don't record anything if this frame is being skipped.
+    if render_context.skip_frame {
+        return;
+    }
+
+    let frame_index = render_context.frame_index;
+    let due = query
+        .query(world)
+        .iter()
+        .find(|(_, (probe, _))| probe.is_capture_due(frame_index))
+        .map(|(entity, (_, transform_matrix))| (entity, transform_matrix.0.column(3).xyz()));
+
+    if let Some((entity, position)) = due {
+        {
+            let probe = world.get::<ReflectionProbe>(entity).unwrap();
+            capture(
+                &probe,
+                &position,
+                world,
+                vulkan_context,
+                swapchain_image_index,
+                render_context,
+            );
+        }
+        world
+            .get_mut::<ReflectionProbe>(entity)
+            .unwrap()
+            .last_capture = Some(frame_index);
+    }
+
+    // Use the point between the two eyes to find the nearest probe.
+    let camera_position = render_context.scene_data.camera_position;
+    let camera_position = (camera_position[0] + camera_position[1]).xyz() * 0.5;
+
+    let nearest = query
+        .query(world)
+        .iter()
+        .filter(|(_, (probe, _))| probe.is_captured())
+        .map(|(_, (probe, transform_matrix))| {
+            let distance = (transform_matrix.0.column(3).xyz() - camera_position).norm();
+            (distance, probe.cubemap.clone())
+        })
+        .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, cubemap)| cubemap);
+
+    render_context.set_reflection_cubemap(vulkan_context, swapchain_image_index, nearest.as_ref());
+}
+
+/// Render `probe`'s surroundings into its cubemap, two faces at a time, then fill in its mip chain.
+fn capture(
+    probe: &ReflectionProbe,
+    position: &Vector3<f32>,
+    world: &World,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &RenderContext,
+) {
+    let device = &vulkan_context.device;
+    let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+    let targets = &probe.targets;
+    let views = get_face_views(position);
+    let projection = get_face_projection();
+    let entities = get_captured_entities(world);
+
+    // The scene data for each pair of faces is recorded into the command buffer rather than written from the host, as
+    // frames still in flight may be reading the last capture's.
+    let scene_data = (0..FACE_PAIRS)
+        .map(|pair| SceneData {
+            projection: [projection; 2],
+            view: [views[pair * 2], views[pair * 2 + 1]],
+            camera_position: [position.push(1.); 2],
+            near_fade: NearFade::default().to_shader(),
+            // Probes are lit by the image based lighting maps, never by another probe.
+            reflection_rotation: render_context.scene_data.environment_rotation,
+            ..render_context.scene_data
+        })
+        .collect::<Vec<_>>();
+    let scene_data_barrier = |buffer: &Buffer<SceneData>, src_access_mask, dst_access_mask| {
+        vk::BufferMemoryBarrier::builder()
+            .buffer(buffer.handle)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .build()
+    };
+
+    // Earlier frames may still be reading the scene data and sampling the cubemap. Its old contents are discarded.
+    let before_scene_data = targets
+        .scene_data_buffers
+        .iter()
+        .map(|b| {
+            scene_data_barrier(
+                b,
+                vk::AccessFlags::UNIFORM_READ,
+                vk::AccessFlags::TRANSFER_WRITE,
+            )
+        })
+        .collect::<Vec<_>>();
+    let before_cubemap = vk::ImageMemoryBarrier::builder()
+        .image(probe.cubemap.image.handle)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::SHADER_READ)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: probe.mip_levels,
+            base_array_layer: 0,
+            layer_count: 6,
+        })
+        .build();
+    let after_scene_data = targets
+        .scene_data_buffers
+        .iter()
+        .map(|b| {
+            scene_data_barrier(
+                b,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::UNIFORM_READ,
+            )
+        })
+        .collect::<Vec<_>>();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &before_scene_data,
+            &[before_cubemap],
+        );
+        for (buffer, scene_data) in targets.scene_data_buffers.iter().zip(&scene_data) {
+            device.cmd_update_buffer(
+                command_buffer,
+                buffer.handle,
+                0,
+                create_push_constant(scene_data),
+            );
+        }
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &after_scene_data,
+            &[],
+        );
+    }
+
+    for pair in 0..FACE_PAIRS {
+        let render_area = vk::Rect2D {
+            offset: Default::default(),
+            extent: probe.cubemap.image.extent,
+        };
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_context.render_pass)
+            .framebuffer(targets.framebuffers[pair])
+            .render_area(render_area)
+            .clear_values(&CLEAR_VALUES);
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            set_viewport(device, command_buffer, &render_area);
+            device.cmd_set_stencil_reference(
+                command_buffer,
+                vk::StencilFaceFlags::FRONT_AND_BACK,
+                0,
+            );
+            device.cmd_set_depth_bias(command_buffer, 0.0, 0.0, 0.0);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                render_context.pipeline_layout,
+                0,
+                &[targets.scene_data_descriptor_sets[pair]],
+                &[],
+            );
+        }
+
+        for entity in &entities {
+            draw_mesh(
+                *entity,
+                world,
+                vulkan_context,
+                command_buffer,
+                render_context,
+            );
+        }
+
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    generate_mipmaps(
+        device,
+        command_buffer,
+        &probe.cubemap.image,
+        probe.mip_levels,
+    );
+}
+
+/// Visible meshes in the opaque or background layers, in the order they should be drawn
+pub(crate) fn get_captured_entities(world: &World) -> Vec<Entity> {
+    let mut entities = world
+        .query::<With<Visible, With<Mesh, (Option<&RenderLayer>, Option<&RenderFlags>)>>>()
+        .iter()
+        .filter(|(_, (_, render_flags))| render_flags.cloned().unwrap_or_default().visible)
+        .map(|(entity, (render_layer, _))| (entity, render_layer.cloned().unwrap_or_default()))
+        .filter(|(_, render_layer)| !render_layer.is_transparent() && !render_layer.is_overlay())
+        .collect::<Vec<_>>();
+    entities.sort_by_key(|(_, render_layer)| *render_layer);
+    entities.into_iter().map(|(entity, _)| entity).collect()
+}
+
+fn draw_mesh(
+    entity: Entity,
+    world: &World,
+    vulkan_context: &VulkanContext,
+    command_buffer: vk::CommandBuffer,
+    render_context: &RenderContext,
+) {
+    let device = &vulkan_context.device;
+    let transform_matrix = world.get::<TransformMatrix>(entity).unwrap().0;
+    let mut mesh = world.get_mut::<Mesh>(entity).unwrap();
+    let front_face = get_front_face(&transform_matrix);
+
+    unsafe {
+        mesh.ubo_data.transform = transform_matrix;
+        mesh.ubo_buffer
+            .update(&vulkan_context, &[mesh.ubo_data])
+            .unwrap();
+
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            render_context.pipeline_layout,
+            2,
+            &mesh.descriptor_sets,
+            &[],
+        );
+
+        for primitive in &mesh.primitives {
+            let pipeline = render_context
+                .get_pipeline(
+                    vulkan_context,
+                    RenderLayer::OPAQUE,
+                    primitive.topology,
+                    front_face,
+                    primitive.material.cull_mode(),
+                    primitive.material.alpha_to_coverage(),
+                    primitive.material.is_unlit(),
+                    primitive.custom_shader,
+                    DepthPass::Single,
+                )
+                .unwrap();
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            render_context.bind_line_width(vulkan_context, command_buffer, primitive.topology);
+
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[primitive.vertex_buffer.handle],
+                &[0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                primitive.index_buffer.handle(),
+                0,
+                primitive.index_buffer.index_type(),
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                render_context.pipeline_layout,
+                1,
+                &[primitive.texture_descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                render_context.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                create_push_constant(&primitive.material),
+            );
+            primitive.draw(device, command_buffer);
+        }
+    }
+}
+
+/// Fill in the cubemap's mip chain from its first level, and leave it ready to be sampled. Each level is blurrier than
+/// the last, so rougher materials (which sample lower levels) get blurrier reflections.
+fn generate_mipmaps(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    cubemap: &Image,
+    mip_levels: u32,
+) {
+    let barrier = |mip_level, old_layout, new_layout, src_access_mask, dst_access_mask| {
+        vk::ImageMemoryBarrier::builder()
+            .image(cubemap.handle)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: mip_level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 6,
+            })
+            .build()
+    };
+    let subresource = |mip_level| vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level,
+        base_array_layer: 0,
+        layer_count: 6,
+    };
+    let corner = |mip_level: u32| vk::Offset3D {
+        x: (cubemap.extent.width >> mip_level).max(1) as i32,
+        y: (cubemap.extent.height >> mip_level).max(1) as i32,
+        z: 1,
+    };
+
+    unsafe {
+        // The render pass leaves the first level as a colour attachment.
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier(
+                0,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            )],
+        );
+
+        for mip_level in 1..mip_levels {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    mip_level,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+
+            let blit = vk::ImageBlit {
+                src_subresource: subresource(mip_level - 1),
+                src_offsets: [Default::default(), corner(mip_level - 1)],
+                dst_subresource: subresource(mip_level),
+                dst_offsets: [Default::default(), corner(mip_level)],
+            };
+            device.cmd_blit_image(
+                command_buffer,
+                cubemap.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                cubemap.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+
+            // This level is the source for the next one.
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    mip_level,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                )],
+            );
+        }
+
+        let mut shader_read = barrier(
+            0,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::AccessFlags::SHADER_READ,
+        );
+        shader_read.subresource_range.level_count = mip_levels;
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[shader_read],
+        );
+    }
+}
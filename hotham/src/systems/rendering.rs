@@ -1,42 +1,305 @@
 use crate::{
-    components::{Mesh, TransformMatrix, Visible},
-    resources::VulkanContext,
-    resources::{render_context::create_push_constant, RenderContext},
+    components::{
+        DepthBias, Highlight, Mesh, RenderFlags, RenderLayer, StencilTag, TransformMatrix, Visible,
+        WireframeOverlay,
+    },
+    resources::{
+        cpu_timer::CpuPhase,
+        render_context::{create_push_constant, get_front_face, DepthPass, HIGHLIGHT_STENCIL_MASK},
+        RenderContext,
+    },
+    resources::{vulkan_context::has_stencil_component, VulkanContext},
+    systems::culling::{is_culled, Frustum},
 };
 use ash::vk;
-use hecs::{PreparedQuery, With, World};
+use hecs::{Entity, PreparedQuery, With, World};
+use nalgebra::Vector3;
+use std::time::Instant;
 
 /// Rendering system
-/// Walks through each Mesh that is Visible and renders it.
+/// Walks through each Mesh that is Visible and renders it, grouped by `RenderLayer`.
+/// Meshes whose `RenderFlags` aren't `visible` or that are outside both eyes' view are skipped, and meshes with a
+/// `DepthBias` have it applied, and meshes with a `StencilTag` are tagged in the stencil buffer.
+/// Within each opaque layer, primitives are drawn grouped by pipeline and material to save on binding state.
+/// Meshes with a `Highlight` are then outlined, and meshes with a `WireframeOverlay` have their edges drawn over them.
 pub fn rendering_system(
-    query: &mut PreparedQuery<With<Visible, (&mut Mesh, &TransformMatrix)>>,
+    query: &mut PreparedQuery<
+        With<
+            Visible,
+            With<
+                Mesh,
+                (
+                    &TransformMatrix,
+                    Option<&RenderLayer>,
+                    Option<&Highlight>,
+                    Option<&RenderFlags>,
+                ),
+            >,
+        >,
+    >,
     world: &mut World,
     vulkan_context: &VulkanContext,
     swapchain_image_index: usize,
     render_context: &RenderContext,
 ) -> () {
-    for (_, (mesh, transform_matrix)) in query.query_mut(world) {
-        let device = &vulkan_context.device;
-        let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+    // Don't record anything if this frame is being skipped.
+    if render_context.skip_frame {
+        return;
+    }
+
+    // Use the point between the two eyes to sort transparent objects.
+    let camera_position = render_context.scene_data.camera_position;
+    let camera_position = (camera_position[0] + camera_position[1]).xyz() * 0.5;
+
+    let (draw_calls, primitive_draw_calls) =
+        render_context.cpu_timer.time(CpuPhase::Culling, || {
+            let mut draw_calls =
+                get_draw_calls(query.query_mut(world).into_iter(), camera_position);
+            let frustums = Frustum::from_scene_data(&render_context.scene_data);
+            draw_calls.retain(|d| !is_culled(world, d.entity, &frustums));
+            sort_draw_calls(&mut draw_calls);
+
+            let mut primitive_draw_calls =
+                get_primitive_draw_calls(&draw_calls, world, vulkan_context, render_context);
+            sort_primitive_draw_calls(&mut primitive_draw_calls);
+            (draw_calls, primitive_draw_calls)
+        });
+    let recording_start = Instant::now();
+
+    let device = &vulkan_context.device;
+    let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+    let mut current_pipeline = render_context.pipeline;
+    let mut current_entity = None;
+    let mut current_texture_descriptor_set = vk::DescriptorSet::null();
+
+    // Primitives of different meshes may be drawn in between each other, so update every mesh's transform first.
+    for draw_call in &draw_calls {
+        let transform_matrix = world.get::<TransformMatrix>(draw_call.entity).unwrap().0;
+        let mut mesh = world.get_mut::<Mesh>(draw_call.entity).unwrap();
+        mesh.ubo_data.transform = transform_matrix;
+        mesh.ubo_buffer
+            .update(&vulkan_context, &[mesh.ubo_data])
+            .unwrap();
+    }
+
+    for primitive_draw_call in &primitive_draw_calls {
+        let draw_call = &primitive_draw_call.draw_call;
+        let mesh = world.get::<Mesh>(draw_call.entity).unwrap();
+        let primitive = &mesh.primitives[primitive_draw_call.primitive_index];
 
         unsafe {
-            mesh.ubo_data.transform = transform_matrix.0.clone();
-            mesh.ubo_buffer
-                .update(&vulkan_context, &[mesh.ubo_data])
-                .unwrap();
+            if current_entity != Some(draw_call.entity) {
+                let depth_bias = world
+                    .get::<DepthBias>(draw_call.entity)
+                    .map(|d| *d)
+                    .unwrap_or_default();
+
+                // Mark highlighted objects in the stencil buffer so they can be outlined, and tag objects for
+                // later passes.
+                let stencil_tag = world.get::<StencilTag>(draw_call.entity).ok().map(|t| *t);
+                device.cmd_set_stencil_reference(
+                    command_buffer,
+                    vk::StencilFaceFlags::FRONT_AND_BACK,
+                    get_stencil_reference(draw_call.highlight.is_some(), stencil_tag),
+                );
+                device.cmd_set_depth_bias(
+                    command_buffer,
+                    depth_bias.constant_factor,
+                    0.0,
+                    depth_bias.slope_factor,
+                );
 
-            // Bind mesh descriptor sets
+                // Bind mesh descriptor sets
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    render_context.pipeline_layout,
+                    2,
+                    &mesh.descriptor_sets,
+                    &[],
+                );
+                current_entity = Some(draw_call.entity);
+            }
+
+            if primitive_draw_call.pipeline != current_pipeline {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    primitive_draw_call.pipeline,
+                );
+                render_context.bind_line_width(vulkan_context, command_buffer, primitive.topology);
+                current_pipeline = primitive_draw_call.pipeline;
+            }
+
+            // Bind vertex and index buffers
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[primitive.vertex_buffer.handle],
+                &[0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                primitive.index_buffer.handle(),
+                0,
+                primitive.index_buffer.index_type(),
+            );
+
+            // Bind texture descriptor sets
+            if primitive.texture_descriptor_set != current_texture_descriptor_set {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    render_context.pipeline_layout,
+                    1,
+                    &[primitive.texture_descriptor_set],
+                    &[],
+                );
+                current_texture_descriptor_set = primitive.texture_descriptor_set;
+            }
+
+            // Push constants
+            let material_push_constant = create_push_constant(&primitive.material);
+            device.cmd_push_constants(
+                command_buffer,
+                render_context.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                material_push_constant,
+            );
+            primitive.draw(device, command_buffer);
+        }
+    }
+
+    // Outlines rely on the stencil buffer, so they can't be drawn if the depth format has no stencil component.
+    if has_stencil_component(render_context.depth_format)
+        && draw_calls.iter().any(|d| d.highlight.is_some())
+    {
+        draw_outlines(
+            &draw_calls,
+            world,
+            vulkan_context,
+            swapchain_image_index,
+            render_context,
+        );
+        current_pipeline = vk::Pipeline::null();
+    }
+
+    if let Some(wireframe_pipeline) = render_context.wireframe_pipeline {
+        let wireframe_overlays = get_wireframe_overlays(&draw_calls, world);
+        if !wireframe_overlays.is_empty() {
+            draw_wireframe_overlays(
+                &wireframe_overlays,
+                wireframe_pipeline,
+                world,
+                vulkan_context,
+                swapchain_image_index,
+                render_context,
+            );
+            current_pipeline = vk::Pipeline::null();
+        }
+    }
+
+    unsafe {
+        // Don't leave the last entity's stencil reference or depth bias set for anyone drawing after us..
+        device.cmd_set_stencil_reference(command_buffer, vk::StencilFaceFlags::FRONT_AND_BACK, 0);
+        device.cmd_set_depth_bias(command_buffer, 0.0, 0.0, 0.0);
+    }
+
+    // ..and leave the default pipeline bound.
+    if current_pipeline != render_context.pipeline {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                render_context.pipeline,
+            );
             device.cmd_bind_descriptor_sets(
                 command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
                 render_context.pipeline_layout,
+                0,
+                &[render_context.scene_data_descriptor_set(swapchain_image_index)],
+                &[],
+            );
+        }
+    }
+
+    render_context
+        .cpu_timer
+        .record(CpuPhase::Recording, recording_start.elapsed());
+}
+
+/// The stencil reference written wherever an entity is drawn: the highlight bit, for outlines, and its tag, if any.
+fn get_stencil_reference(highlighted: bool, stencil_tag: Option<StencilTag>) -> u32 {
+    let highlight_bits = if highlighted {
+        HIGHLIGHT_STENCIL_MASK
+    } else {
+        0
+    };
+    highlight_bits | stencil_tag.map_or(0, StencilTag::stencil_bits)
+}
+
+/// Draw an extruded silhouette of each highlighted mesh wherever the mesh itself wasn't drawn.
+fn draw_outlines(
+    draw_calls: &[DrawCall],
+    world: &World,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &RenderContext,
+) {
+    let device = &vulkan_context.device;
+    let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+    let pipeline_layout = render_context.outline_pipeline_layout;
+
+    unsafe {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            render_context.outline_pipeline,
+        );
+
+        // The outline pipeline layout isn't compatible with the PBR one, so the scene data must be bound again.
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[render_context.scene_data_descriptor_set(swapchain_image_index)],
+            &[],
+        );
+    }
+
+    for draw_call in draw_calls {
+        let highlight = match &draw_call.highlight {
+            Some(highlight) => highlight,
+            None => continue,
+        };
+        let mesh = world.get::<Mesh>(draw_call.entity).unwrap();
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
                 2,
                 &mesh.descriptor_sets,
                 &[],
             );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                create_push_constant(highlight),
+            );
 
             for primitive in &mesh.primitives {
-                // Bind vertex and index buffers
+                // The outline pipeline can only extrude triangles.
+                if primitive.topology != vk::PrimitiveTopology::TRIANGLE_LIST {
+                    continue;
+                }
+
                 device.cmd_bind_vertex_buffers(
                     command_buffer,
                     0,
@@ -45,54 +308,570 @@ pub fn rendering_system(
                 );
                 device.cmd_bind_index_buffer(
                     command_buffer,
-                    primitive.index_buffer.handle,
+                    primitive.index_buffer.handle(),
                     0,
-                    vk::IndexType::UINT32,
+                    primitive.index_buffer.index_type(),
                 );
+                primitive.draw(device, command_buffer);
+            }
+        }
+    }
+}
 
-                // Bind texture descriptor sets
-                device.cmd_bind_descriptor_sets(
+/// The entities drawn this frame that have a `WireframeOverlay`, in the order they were drawn.
+fn get_wireframe_overlays(
+    draw_calls: &[DrawCall],
+    world: &World,
+) -> Vec<(Entity, WireframeOverlay)> {
+    draw_calls
+        .iter()
+        .filter_map(|d| {
+            let wireframe_overlay = world.get::<WireframeOverlay>(d.entity).ok()?;
+            Some((d.entity, *wireframe_overlay))
+        })
+        .collect()
+}
+
+/// Draw the edges of each mesh with a `WireframeOverlay` over the mesh itself, in the overlay's colour.
+fn draw_wireframe_overlays(
+    wireframe_overlays: &[(Entity, WireframeOverlay)],
+    wireframe_pipeline: vk::Pipeline,
+    world: &World,
+    vulkan_context: &VulkanContext,
+    swapchain_image_index: usize,
+    render_context: &RenderContext,
+) {
+    let device = &vulkan_context.device;
+    let command_buffer = render_context.frames[swapchain_image_index].command_buffer;
+    let pipeline_layout = render_context.outline_pipeline_layout;
+
+    unsafe {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            wireframe_pipeline,
+        );
+        render_context.bind_line_width(
+            vulkan_context,
+            command_buffer,
+            vk::PrimitiveTopology::LINE_LIST,
+        );
+
+        // The outline pipeline layout isn't compatible with the PBR one, so the scene data must be bound again.
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[render_context.scene_data_descriptor_set(swapchain_image_index)],
+            &[],
+        );
+    }
+
+    for (entity, wireframe_overlay) in wireframe_overlays {
+        let mesh = world.get::<Mesh>(*entity).unwrap();
+
+        // The wireframe is drawn with the outline shaders, unextruded, in the overlay's colour.
+        let push_constant = Highlight {
+            color: wireframe_overlay.color,
+            width: 0.,
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                2,
+                &mesh.descriptor_sets,
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                create_push_constant(&push_constant),
+            );
+
+            for primitive in &mesh.primitives {
+                // Only triangles have edges to draw.
+                if primitive.topology != vk::PrimitiveTopology::TRIANGLE_LIST {
+                    continue;
+                }
+
+                device.cmd_bind_vertex_buffers(
                     command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    render_context.pipeline_layout,
-                    1,
-                    &[primitive.texture_descriptor_set],
-                    &[],
+                    0,
+                    &[primitive.vertex_buffer.handle],
+                    &[0],
                 );
-
-                // Push constants
-                let material_push_constant = create_push_constant(&primitive.material);
-                device.cmd_push_constants(
+                device.cmd_bind_index_buffer(
                     command_buffer,
-                    render_context.pipeline_layout,
-                    vk::ShaderStageFlags::FRAGMENT,
+                    primitive.index_buffer.handle(),
                     0,
-                    material_push_constant,
+                    primitive.index_buffer.index_type(),
                 );
-                device.cmd_draw_indexed(command_buffer, primitive.indicies_count, 1, 0, 0, 1);
+                primitive.draw(device, command_buffer);
             }
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DrawCall {
+    entity: Entity,
+    render_layer: RenderLayer,
+    distance: f32,
+    highlight: Option<Highlight>,
+    front_face: vk::FrontFace,
+}
+
+/// Create a draw call for each entity that should be drawn this frame.
+fn get_draw_calls<'a>(
+    entities: impl Iterator<
+        Item = (
+            Entity,
+            (
+                &'a TransformMatrix,
+                Option<&'a RenderLayer>,
+                Option<&'a Highlight>,
+                Option<&'a RenderFlags>,
+            ),
+        ),
+    >,
+    camera_position: Vector3<f32>,
+) -> Vec<DrawCall> {
+    entities
+        .filter(|(_, (_, _, _, render_flags))| render_flags.cloned().unwrap_or_default().visible)
+        .map(|(entity, (transform_matrix, render_layer, highlight, _))| {
+            let position = transform_matrix.0.column(3).xyz();
+            DrawCall {
+                entity,
+                render_layer: render_layer.cloned().unwrap_or_default(),
+                distance: (position - camera_position).norm(),
+                highlight: highlight.cloned(),
+                front_face: get_front_face(&transform_matrix.0),
+            }
+        })
+        .collect()
+}
+
+/// Draw each layer in ascending order, with transparent layers sorted back-to-front.
+fn sort_draw_calls(draw_calls: &mut Vec<DrawCall>) {
+    draw_calls.sort_by(|a, b| {
+        a.render_layer.cmp(&b.render_layer).then_with(|| {
+            if a.render_layer.is_transparent() {
+                b.distance
+                    .partial_cmp(&a.distance)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+    });
+}
+
+/// A single primitive of a `DrawCall`, along with the state that must be bound to draw it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PrimitiveDrawCall {
+    draw_call: DrawCall,
+    primitive_index: usize,
+    pipeline: vk::Pipeline,
+    texture_descriptor_set: vk::DescriptorSet,
+}
+
+/// Split each draw call into its primitives, in the same order.
+fn get_primitive_draw_calls(
+    draw_calls: &[DrawCall],
+    world: &World,
+    vulkan_context: &VulkanContext,
+    render_context: &RenderContext,
+) -> Vec<PrimitiveDrawCall> {
+    let mut primitive_draw_calls = Vec::new();
+    for draw_call in draw_calls {
+        let mesh = world.get::<Mesh>(draw_call.entity).unwrap();
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            // Each layer has a pipeline per primitive topology, front face and cull mode
+            let depth_passes =
+                get_depth_passes(draw_call.render_layer, primitive.material.depth_prepass());
+            for depth_pass in depth_passes {
+                let pipeline = render_context
+                    .get_pipeline(
+                        vulkan_context,
+                        draw_call.render_layer,
+                        primitive.topology,
+                        draw_call.front_face,
+                        primitive.material.cull_mode(),
+                        primitive.material.alpha_to_coverage(),
+                        primitive.material.is_unlit(),
+                        primitive.custom_shader,
+                        *depth_pass,
+                    )
+                    .unwrap();
+                primitive_draw_calls.push(PrimitiveDrawCall {
+                    draw_call: *draw_call,
+                    primitive_index,
+                    pipeline,
+                    texture_descriptor_set: primitive.texture_descriptor_set,
+                });
+            }
+        }
+    }
+    primitive_draw_calls
+}
+
+/// The passes a primitive is drawn in. Blended primitives whose material has a depth prepass are drawn twice in a
+/// row, first writing their depth and then their colour: see `Material::set_depth_prepass`.
+fn get_depth_passes(render_layer: RenderLayer, depth_prepass: bool) -> &'static [DepthPass] {
+    if depth_prepass && render_layer.is_transparent() && !render_layer.is_additive() {
+        &[DepthPass::Prepass, DepthPass::Equal]
+    } else {
+        &[DepthPass::Single]
+    }
+}
+
+/// Group the primitives in opaque layers by pipeline, then by material, then by mesh so that as little state as
+/// possible is bound between them. Transparent layers are left in the back-to-front order from `sort_draw_calls`.
+fn sort_primitive_draw_calls(primitive_draw_calls: &mut Vec<PrimitiveDrawCall>) {
+    // The sort must be stable to keep the order of transparent primitives.
+    primitive_draw_calls.sort_by(|a, b| {
+        a.draw_call
+            .render_layer
+            .cmp(&b.draw_call.render_layer)
+            .then_with(|| {
+                if a.draw_call.render_layer.is_transparent() {
+                    std::cmp::Ordering::Equal
+                } else {
+                    a.pipeline
+                        .cmp(&b.pipeline)
+                        .then_with(|| a.texture_descriptor_set.cmp(&b.texture_descriptor_set))
+                        .then_with(|| a.draw_call.entity.cmp(&b.draw_call.entity))
+                }
+            })
+    });
+}
+
+#[cfg(test)]
+mod wireframe_tests {
+    use super::*;
+    use nalgebra::vector;
+
+    #[test]
+    pub fn test_toggle_wireframe_overlay() {
+        let mut world = World::new();
+        let selected = world.spawn(());
+        let other = world.spawn(());
+        let draw_calls = [selected, other]
+            .iter()
+            .map(|&entity| DrawCall {
+                entity,
+                render_layer: RenderLayer::OPAQUE,
+                distance: 0.,
+                highlight: None,
+                front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            })
+            .collect::<Vec<_>>();
+
+        // Nothing is overlaid by default..
+        assert!(get_wireframe_overlays(&draw_calls, &world).is_empty());
+
+        // ..adding the component overlays only that entity..
+        let wireframe_overlay = WireframeOverlay {
+            color: vector![0., 1., 0., 1.],
+        };
+        world.insert_one(selected, wireframe_overlay).unwrap();
+        assert_eq!(
+            get_wireframe_overlays(&draw_calls, &world),
+            vec![(selected, wireframe_overlay)]
+        );
+
+        // ..and removing it turns the overlay off again.
+        world.remove_one::<WireframeOverlay>(selected).unwrap();
+        assert!(get_wireframe_overlays(&draw_calls, &world).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod stencil_tests {
+    use super::*;
+    use crate::components::stencil_tag::STENCIL_TAG_MASK;
+
+    /// Whether a fragment passes `state`'s stencil test over a pixel holding `stored`, as in the Vulkan spec
+    fn passes(state: &vk::StencilOpState, stored: u32) -> bool {
+        let (reference, stored) = (
+            state.reference & state.compare_mask,
+            stored & state.compare_mask,
+        );
+        match state.compare_op {
+            vk::CompareOp::NEVER => false,
+            vk::CompareOp::LESS => reference < stored,
+            vk::CompareOp::EQUAL => reference == stored,
+            vk::CompareOp::LESS_OR_EQUAL => reference <= stored,
+            vk::CompareOp::GREATER => reference > stored,
+            vk::CompareOp::NOT_EQUAL => reference != stored,
+            vk::CompareOp::GREATER_OR_EQUAL => reference >= stored,
+            vk::CompareOp::ALWAYS => true,
+            op => panic!("unsupported compare op {:?}", op),
+        }
+    }
+
+    #[test]
+    pub fn test_stencil_tags_are_written() {
+        // Three pixels: one where an untagged object was drawn, one where a tagged object was drawn, and one where a
+        // highlighted, tagged object was drawn.
+        let tag = StencilTag(3);
+        let pixels = [
+            get_stencil_reference(false, None),
+            get_stencil_reference(false, Some(tag)),
+            get_stencil_reference(true, Some(tag)),
+        ];
+        assert_eq!(pixels[0], 0);
+        assert_eq!(pixels[1] & STENCIL_TAG_MASK, tag.stencil_bits());
+        assert_eq!(pixels[2], tag.stencil_bits() | 1);
+
+        // A later pass testing for the tag draws over both tagged objects, but not the untagged one..
+        let state = tag.get_stencil_test_state(vk::CompareOp::EQUAL);
+        let drawn = pixels.map(|p| passes(&state, p));
+        assert_eq!(drawn, [false, true, true]);
+        let state = StencilTag(4).get_stencil_test_state(vk::CompareOp::EQUAL);
+        assert!(!pixels.iter().any(|p| passes(&state, *p)));
+
+        // ..and outlines still only skip the highlighted object, tag or not.
+        let outline = vk::StencilOpState {
+            compare_op: vk::CompareOp::NOT_EQUAL,
+            compare_mask: HIGHLIGHT_STENCIL_MASK,
+            reference: 1,
+            ..Default::default()
+        };
+        let drawn = pixels.map(|p| passes(&outline, p));
+        assert_eq!(drawn, [true, true, false]);
+    }
+}
+
+#[cfg(test)]
+mod sort_tests {
+    use super::*;
+    use ash::vk::Handle;
+    use nalgebra::{vector, Matrix4, Point3};
+
+    #[test]
+    pub fn test_sort_draw_calls() {
+        let mut world = World::new();
+        let entities = (0..5).map(|_| world.spawn(())).collect::<Vec<_>>();
+        let draw_call = |n: usize, render_layer, distance| DrawCall {
+            entity: entities[n],
+            render_layer,
+            distance,
+            highlight: None,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        };
+
+        let mut draw_calls = vec![
+            draw_call(0, RenderLayer::UI, 1.0),
+            draw_call(1, RenderLayer::TRANSPARENT, 1.0),
+            draw_call(2, RenderLayer::TRANSPARENT, 5.0),
+            draw_call(3, RenderLayer::OPAQUE, 3.0),
+            draw_call(4, RenderLayer::BACKGROUND, 10.0),
+        ];
+        sort_draw_calls(&mut draw_calls);
+
+        let order = draw_calls.iter().map(|d| d.entity).collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec![
+                entities[4],
+                entities[3],
+                entities[2],
+                entities[1],
+                entities[0]
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_sort_primitive_draw_calls() {
+        let mut world = World::new();
+        let entities = (0..8).map(|_| world.spawn(())).collect::<Vec<_>>();
+        let primitive_draw_call =
+            |n: usize, render_layer, pipeline, texture_descriptor_set| PrimitiveDrawCall {
+                draw_call: DrawCall {
+                    entity: entities[n],
+                    render_layer,
+                    distance: 0.,
+                    highlight: None,
+                    front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                },
+                primitive_index: 0,
+                pipeline: vk::Pipeline::from_raw(pipeline),
+                texture_descriptor_set: vk::DescriptorSet::from_raw(texture_descriptor_set),
+            };
+
+        // A mixed-material scene, with two pipelines and three materials interleaved..
+        let mut primitive_draw_calls = vec![
+            primitive_draw_call(0, RenderLayer::OPAQUE, 1, 1),
+            primitive_draw_call(1, RenderLayer::OPAQUE, 2, 2),
+            primitive_draw_call(2, RenderLayer::OPAQUE, 1, 3),
+            primitive_draw_call(3, RenderLayer::OPAQUE, 2, 1),
+            primitive_draw_call(4, RenderLayer::OPAQUE, 1, 3),
+            primitive_draw_call(5, RenderLayer::OPAQUE, 2, 2),
+            // ..and two transparent primitives already sorted back-to-front.
+            primitive_draw_call(6, RenderLayer::TRANSPARENT, 3, 2),
+            primitive_draw_call(7, RenderLayer::TRANSPARENT, 3, 1),
+        ];
+        assert_eq!(count_binds(&primitive_draw_calls), (7, 7));
+
+        sort_primitive_draw_calls(&mut primitive_draw_calls);
+        assert_eq!(count_binds(&primitive_draw_calls), (3, 5));
+
+        let order = primitive_draw_calls
+            .iter()
+            .map(|d| d.draw_call.entity)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec![
+                entities[0],
+                entities[2],
+                entities[4],
+                entities[3],
+                entities[1],
+                entities[5],
+                entities[6],
+                entities[7],
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_depth_prepass_only_in_blended_layers() {
+        // Blended primitives with a depth prepass are drawn twice, depth first..
+        assert_eq!(
+            get_depth_passes(RenderLayer::TRANSPARENT, true),
+            [DepthPass::Prepass, DepthPass::Equal]
+        );
+
+        // ..but not when they're opaque, additive or overlaid, or don't have one.
+        for render_layer in [RenderLayer::OPAQUE, RenderLayer::ADDITIVE, RenderLayer::UI] {
+            assert_eq!(get_depth_passes(render_layer, true), [DepthPass::Single]);
+        }
+        assert_eq!(
+            get_depth_passes(RenderLayer::TRANSPARENT, false),
+            [DepthPass::Single]
+        );
+    }
+
+    /// Count the pipelines and texture descriptor sets `rendering_system` would bind.
+    fn count_binds(primitive_draw_calls: &[PrimitiveDrawCall]) -> (usize, usize) {
+        let changes = |f: &dyn Fn(&PrimitiveDrawCall) -> u64| {
+            primitive_draw_calls
+                .windows(2)
+                .filter(|w| f(&w[0]) != f(&w[1]))
+                .count()
+                + 1
+        };
+        (
+            changes(&|d| d.pipeline.as_raw()),
+            changes(&|d| d.texture_descriptor_set.as_raw()),
+        )
+    }
+
+    #[test]
+    pub fn test_get_draw_calls_skips_invisible() {
+        let mut world = World::new();
+        let entity = world.spawn(());
+        let transform_matrix = TransformMatrix::default();
+        let camera_position = Vector3::zeros();
+
+        let mut render_flags = RenderFlags::default();
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&transform_matrix, None, None, Some(&render_flags)))),
+            camera_position,
+        );
+        assert_eq!(draw_calls.len(), 1);
+        assert_eq!(draw_calls[0].entity, entity);
+
+        render_flags.visible = false;
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&transform_matrix, None, None, Some(&render_flags)))),
+            camera_position,
+        );
+        assert!(draw_calls.is_empty());
+
+        // No flags at all means the entity is drawn.
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&transform_matrix, None, None, None))),
+            camera_position,
+        );
+        assert_eq!(draw_calls.len(), 1);
+    }
+
+    #[test]
+    pub fn test_negative_scale_flips_front_face() {
+        let mut world = World::new();
+        let entity = world.spawn(());
+        let camera_position = Vector3::zeros();
+
+        // A counter-clockwise triangle, seen from +Z looking down -Z.
+        let triangle = [
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.),
+        ];
+        let winding = |transform: &Matrix4<f32>| {
+            let [a, b, c] = triangle.map(|p| transform.transform_point(&p));
+            (b - a).cross(&(c - a)).z
+        };
+
+        let transform_matrix = TransformMatrix::default();
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&transform_matrix, None, None, None))),
+            camera_position,
+        );
+        assert!(winding(&transform_matrix.0) > 0.);
+        assert_eq!(draw_calls[0].front_face, vk::FrontFace::COUNTER_CLOCKWISE);
+
+        // Mirroring the node reverses the winding, so clockwise triangles must now be treated as front faces.
+        let transform_matrix =
+            TransformMatrix(Matrix4::new_nonuniform_scaling(&vector![-1., 1., 1.]));
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&transform_matrix, None, None, None))),
+            camera_position,
+        );
+        assert!(winding(&transform_matrix.0) < 0.);
+        assert_eq!(draw_calls[0].front_face, vk::FrontFace::CLOCKWISE);
+
+        // Mirroring twice is just a rotation.
+        let transform_matrix =
+            TransformMatrix(Matrix4::new_nonuniform_scaling(&vector![-1., -1., 1.]));
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&transform_matrix, None, None, None))),
+            camera_position,
+        );
+        assert!(winding(&transform_matrix.0) > 0.);
+        assert_eq!(draw_calls[0].front_face, vk::FrontFace::COUNTER_CLOCKWISE);
+    }
+}
+
 #[cfg(target_os = "windows")]
 #[cfg(test)]
 mod tests {
     use std::{collections::hash_map::DefaultHasher, hash::Hasher};
 
     use super::*;
-    use ash::vk::Handle;
     use image::{jpeg::JpegEncoder, DynamicImage, RgbaImage};
-    use nalgebra::UnitQuaternion;
+    use nalgebra::{vector, Matrix4, UnitQuaternion};
     use openxr::{Fovf, Quaternionf, Vector3f};
 
     use crate::{
         buffer::Buffer,
+        components::{material::WORKFLOW_UNLIT, panel::create_quad_mesh},
         gltf_loader,
-        resources::RenderContext,
-        scene_data::SceneParams,
+        resources::{RenderContext, SpecializationConstants},
+        scene_data::{SceneData, SceneParams},
         swapchain::Swapchain,
         systems::{update_parent_transform_matrix_system, update_transform_matrix_system},
+        texture::{SamplerInfo, Texture},
         util::get_from_device_memory,
         COLOR_FORMAT,
     };
@@ -112,15 +891,14 @@ mod tests {
                 vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
                 2,
                 1,
+                "Screenshot",
             )
             .unwrap();
-        vulkan_context
-            .set_debug_name(vk::ObjectType::IMAGE, image.handle.as_raw(), "Screenshot")
-            .unwrap();
 
         let swapchain = Swapchain {
             images: vec![image.handle],
             resolution,
+            format: COLOR_FORMAT,
         };
 
         let mut render_context =
@@ -184,12 +962,40 @@ mod tests {
         image: crate::image::Image,
         name: &str,
     ) -> (u64, u64) {
+        let image_bytes = get_image_bytes(resolution, vulkan_context, &image);
+        let image_from_vulkan = DynamicImage::ImageRgba8(
+            RgbaImage::from_raw(resolution.width, resolution.height, image_bytes).unwrap(),
+        );
+        let output_path = format!("../test_assets/render_{}.jpg", name);
+        {
+            let output_path = std::path::Path::new(&output_path);
+            let mut file = std::fs::File::create(output_path).unwrap();
+            let mut jpeg_encoder = JpegEncoder::new(&mut file);
+            jpeg_encoder.encode_image(&image_from_vulkan).unwrap();
+        }
+        let output_hash = hash_file(&output_path);
+        let known_good_path = format!("../test_assets/render_{}_known_good.jpg", name);
+        let known_good_hash = hash_file(&known_good_path);
+
+        //  TODO: Fix this on non-windows platforms.
+        #[cfg(target_os = "windows")]
+        assert_eq!(output_hash, known_good_hash);
+
+        (output_hash, known_good_hash)
+    }
+
+    fn get_image_bytes(
+        resolution: vk::Extent2D,
+        vulkan_context: &VulkanContext,
+        image: &crate::image::Image,
+    ) -> Vec<u8> {
         let size = (resolution.height * resolution.width * 4) as usize;
         let image_data = vec![0; size];
         let buffer = Buffer::new(
             &vulkan_context,
             &image_data,
             vk::BufferUsageFlags::TRANSFER_DST,
+            "Screenshot Buffer",
         )
         .unwrap();
         vulkan_context.transition_image_layout(
@@ -204,26 +1010,607 @@ mod tests {
             vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
             buffer.handle,
         );
-        let image_bytes = unsafe { get_from_device_memory(&vulkan_context, &buffer) }.to_vec();
-        let image_from_vulkan = DynamicImage::ImageRgba8(
-            RgbaImage::from_raw(resolution.width, resolution.height, image_bytes).unwrap(),
+        unsafe { get_from_device_memory(&vulkan_context, &buffer) }.to_vec()
+    }
+
+    #[test]
+    pub fn test_double_sided_material_lit_from_both_sides() {
+        let (vulkan_context, mut render_context, image, resolution) = offscreen_render_context();
+
+        // A plain, lit, double-sided quad facing +Z.
+        let texture = Texture::empty(&vulkan_context).unwrap();
+        let mut mesh = create_quad_mesh(&texture, &vulkan_context, &render_context, 0.5, 0.5);
+        let primitive = &mut mesh.primitives[0];
+        primitive.material.workflow = 0.;
+        primitive.material.base_color_texture_set = -1;
+        primitive.material.double_sided = 1.;
+        for vertex in primitive.vertices.iter_mut() {
+            vertex.normal = vector![0., 0., 1.];
+        }
+        primitive
+            .vertex_buffer
+            .update(&vulkan_context, &primitive.vertices)
+            .unwrap();
+        let mut world = World::new();
+        let quad = world.spawn((mesh, TransformMatrix::default(), Visible {}));
+
+        // Look at the front of the quad..
+        render_quad(&mut render_context, &vulkan_context, &mut world);
+        let front = get_centre_pixel(resolution, &vulkan_context, &image);
+
+        // ..then turn it around and look at its back, which should be lit in exactly the same way.
+        world.get_mut::<TransformMatrix>(quad).unwrap().0 =
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::PI)
+                .to_homogeneous();
+        render_quad(&mut render_context, &vulkan_context, &mut world);
+        let back = get_centre_pixel(resolution, &vulkan_context, &image);
+
+        assert!(front[..3].iter().any(|c| *c > 0), "Quad wasn't drawn");
+        for (front, back) in front.iter().zip(&back) {
+            assert!(
+                (*front as i32 - *back as i32).abs() <= 2,
+                "Front {:?} and back {:?} are lit differently",
+                front,
+                back
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_alpha_to_coverage_smooths_cutout_edges() {
+        let (vulkan_context, mut render_context, image, resolution) = offscreen_render_context();
+
+        // A white texture that fades from transparent on the left to opaque on the right, so the cutoff runs
+        // vertically down the middle of the quad.
+        let texture = Texture::new(
+            "Alpha Gradient",
+            &vulkan_context,
+            &vec![255, 255, 255, 0, 255, 255, 255, 255],
+            2,
+            1,
+            vk::Format::R8G8B8A8_UNORM,
+            SamplerInfo::new(vk::SamplerAddressMode::CLAMP_TO_EDGE, 1),
+        )
+        .unwrap();
+        let mut mesh = create_quad_mesh(&texture, &vulkan_context, &render_context, 0.5, 0.5);
+        mesh.primitives[0].material.alpha_mask = 1.;
+        mesh.primitives[0].material.alpha_mask_cutoff = 0.5;
+        let mut world = World::new();
+        let quad = world.spawn((mesh, TransformMatrix::default(), Visible {}));
+
+        // Without alpha-to-coverage, each pixel is either discarded or drawn..
+        render_quad(&mut render_context, &vulkan_context, &mut world);
+        let hard_edge = count_centre_row_colours(resolution, &vulkan_context, &image);
+
+        // ..with it, the pixels along the cutoff are partially covered.
+        assert!(world.get_mut::<Mesh>(quad).unwrap().primitives[0]
+            .material
+            .set_alpha_to_coverage(true));
+        render_quad(&mut render_context, &vulkan_context, &mut world);
+        let smooth_edge = count_centre_row_colours(resolution, &vulkan_context, &image);
+
+        assert!(
+            smooth_edge > hard_edge,
+            "Expected partially covered pixels along the cutoff"
         );
-        let output_path = format!("../test_assets/render_{}.jpg", name);
-        {
-            let output_path = std::path::Path::new(&output_path);
-            let mut file = std::fs::File::create(output_path).unwrap();
-            let mut jpeg_encoder = JpegEncoder::new(&mut file);
-            jpeg_encoder.encode_image(&image_from_vulkan).unwrap();
+    }
+
+    #[test]
+    pub fn test_unlit_material_ignores_scene_lights() {
+        let (vulkan_context, mut render_context, image, resolution) = offscreen_render_context();
+
+        // An untextured, unlit quad, tinted by its vertex colours.
+        let texture = Texture::empty(&vulkan_context).unwrap();
+        let mut mesh = create_quad_mesh(&texture, &vulkan_context, &render_context, 0.5, 0.5);
+        let primitive = &mut mesh.primitives[0];
+        primitive.material.workflow = WORKFLOW_UNLIT;
+        primitive.material.base_color_texture_set = -1;
+        primitive.material.base_colour_factor = vector![1., 0.5, 1., 1.];
+        for vertex in primitive.vertices.iter_mut() {
+            vertex.normal = vector![0., 0., 1.];
+            vertex.color = vector![0.5, 0.5, 1., 1.];
         }
-        let output_hash = hash_file(&output_path);
-        let known_good_path = format!("../test_assets/render_{}_known_good.jpg", name);
-        let known_good_hash = hash_file(&known_good_path);
+        primitive
+            .vertex_buffer
+            .update(&vulkan_context, &primitive.vertices)
+            .unwrap();
+        let mut world = World::new();
+        let quad = world.spawn((mesh, TransformMatrix::default(), Visible {}));
 
-        //  TODO: Fix this on non-windows platforms.
-        #[cfg(target_os = "windows")]
-        assert_eq!(output_hash, known_good_hash);
+        // Draw the quad with the default lights, then with much brighter ones from a different direction.
+        let bright_lights = SceneParams {
+            light_direction: vector![0., 0., 1., 0.],
+            exposure: 10.,
+            scale_ibl_ambient: 1.,
+            ..Default::default()
+        };
+        let mut draw = |world: &mut World, scene_params: SceneParams, ambient_intensity: f32| {
+            render_context.ambient_light.intensity = ambient_intensity;
+            render_context
+                .scene_params_buffer
+                .update(&vulkan_context, &[scene_params])
+                .unwrap();
+            render_quad(&mut render_context, &vulkan_context, world);
+            get_centre_pixel(resolution, &vulkan_context, &image)
+        };
+        let unlit = draw(&mut world, Default::default(), 0.05);
+        let unlit_bright = draw(&mut world, bright_lights, 1.);
 
-        (output_hash, known_good_hash)
+        // The unlit quad is exactly its base colour times its vertex colour: linear (0.5, 0.25, 1.0), sRGB encoded.
+        assert_eq!(unlit, unlit_bright);
+        for (actual, expected) in unlit.iter().zip(&[188, 137, 255, 255]) {
+            assert!(
+                (*actual as i32 - *expected as i32).abs() <= 2,
+                "Expected {:?}, got {:?}",
+                expected,
+                unlit
+            );
+        }
+
+        // The same quad, lit, is affected by the lights.
+        world.get_mut::<Mesh>(quad).unwrap().primitives[0]
+            .material
+            .workflow = 0.;
+        let lit = draw(&mut world, Default::default(), 0.05);
+        let lit_bright = draw(&mut world, bright_lights, 1.);
+        assert_ne!(lit, lit_bright);
+    }
+
+    #[test]
+    pub fn test_overlapping_additive_quads_sum_their_colours() {
+        let (vulkan_context, mut render_context, image, resolution) = offscreen_render_context();
+
+        // An untextured, unlit, additive quad in `colour`, `z` metres in front of the origin.
+        let texture = Texture::empty(&vulkan_context).unwrap();
+        let spawn_quad = |world: &mut World, render_context: &RenderContext, colour, z| {
+            let mut mesh = create_quad_mesh(&texture, &vulkan_context, render_context, 0.5, 0.5);
+            let primitive = &mut mesh.primitives[0];
+            primitive.material.workflow = WORKFLOW_UNLIT;
+            primitive.material.base_color_texture_set = -1;
+            primitive.material.base_colour_factor = colour;
+            world.spawn((
+                mesh,
+                TransformMatrix(Matrix4::new_translation(&vector![0., 0., z])),
+                RenderLayer::ADDITIVE,
+                Visible {},
+            ))
+        };
+
+        // Two overlapping quads..
+        let mut world = World::new();
+        spawn_quad(&mut world, &render_context, vector![0.2, 0.1, 0., 1.], 0.);
+        spawn_quad(&mut world, &render_context, vector![0.2, 0.1, 0.3, 1.], 0.1);
+        render_quad(&mut render_context, &vulkan_context, &mut world);
+        let overlapping = get_centre_pixel(resolution, &vulkan_context, &image);
+
+        // ..should look exactly like a single quad in the sum of their colours.
+        let mut world = World::new();
+        spawn_quad(&mut world, &render_context, vector![0.4, 0.2, 0.3, 1.], 0.);
+        render_quad(&mut render_context, &vulkan_context, &mut world);
+        let summed = get_centre_pixel(resolution, &vulkan_context, &image);
+
+        assert!(summed[..3].iter().any(|c| *c > 0), "Quad wasn't drawn");
+        for (overlapping, summed) in overlapping[..3].iter().zip(&summed) {
+            assert!(
+                (*overlapping as i32 - *summed as i32).abs() <= 2,
+                "Expected {:?}, got {:?}",
+                summed,
+                overlapping
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_unindexed_line_strip_is_drawn_as_a_line_strip() {
+        let (vulkan_context, render_context, _, _) = offscreen_render_context();
+
+        // An unindexed glTF line strip through three points.
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "scene": 0,
+                "scenes": [{ "nodes": [0] }],
+                "nodes": [{ "name": "Strip", "mesh": 0 }],
+                "buffers": [{ "byteLength": 36 }],
+                "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+                "accessors": [{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0, 0, 0],
+                    "max": [1, 1, 0]
+                }],
+                "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "mode": 3 }] }]
+            }"#,
+        )
+        .unwrap();
+        let buffer = [[0_f32, 0., 0.], [1., 0., 0.], [1., 1., 0.]]
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut models = Default::default();
+        gltf_loader::load_models_from_gltf_data(
+            &gltf.document,
+            &buffer,
+            &Vec::new(),
+            &vulkan_context,
+            &render_context.descriptor_set_layouts,
+            &mut models,
+        )
+        .unwrap();
+        let world = models.remove("Strip").unwrap();
+        let entity = world.query::<&Mesh>().iter().next().unwrap().0;
+        let mesh = world.get::<Mesh>(entity).unwrap();
+        let transform_matrix = world.get::<TransformMatrix>(entity).unwrap();
+
+        // Every vertex is drawn, in order..
+        let primitive = &mesh.primitives[0];
+        assert_eq!(primitive.topology, vk::PrimitiveTopology::LINE_STRIP);
+        assert_eq!(primitive.indices, [0, 1, 2]);
+        assert_eq!(primitive.indicies_count, 3);
+
+        // ..with a line strip pipeline.
+        let draw_calls = get_draw_calls(
+            std::iter::once((entity, (&*transform_matrix, None, None, None))),
+            Vector3::zeros(),
+        );
+        let primitive_draw_calls =
+            get_primitive_draw_calls(&draw_calls, &world, &vulkan_context, &render_context);
+        let line_strip_pipeline = render_context
+            .get_pipeline(
+                &vulkan_context,
+                draw_calls[0].render_layer,
+                vk::PrimitiveTopology::LINE_STRIP,
+                draw_calls[0].front_face,
+                primitive.material.cull_mode(),
+                primitive.material.alpha_to_coverage(),
+                primitive.material.is_unlit(),
+                None,
+                DepthPass::Single,
+            )
+            .unwrap();
+        assert_eq!(primitive_draw_calls.len(), 1);
+        assert_eq!(primitive_draw_calls[0].pipeline, line_strip_pipeline);
+        assert_ne!(primitive_draw_calls[0].pipeline, render_context.pipeline);
+    }
+
+    #[test]
+    pub fn test_depth_bias_is_recorded_and_reset() {
+        let (vulkan_context, mut render_context, image, resolution) = offscreen_render_context();
+
+        // An untextured, unlit quad in `colour`, facing the camera at the origin.
+        let texture = Texture::empty(&vulkan_context).unwrap();
+        let spawn_quad = |world: &mut World, render_context: &RenderContext, colour| {
+            let mut mesh = create_quad_mesh(&texture, &vulkan_context, render_context, 0.5, 0.5);
+            let primitive = &mut mesh.primitives[0];
+            primitive.material.workflow = WORKFLOW_UNLIT;
+            primitive.material.base_color_texture_set = -1;
+            primitive.material.base_colour_factor = colour;
+            world.spawn((mesh, TransformMatrix(Matrix4::identity())))
+        };
+        let red = vector![1., 0., 0., 1.];
+        let green = vector![0., 1., 0., 1.];
+        // Green is the second channel whether the image is RGBA or BGRA.
+        let is_green = |pixel: &[u8]| pixel[1] > 128 && pixel[0] < 128 && pixel[2] < 128;
+
+        // A poster biased towards the camera is drawn over the coplanar wall, whichever is spawned first.
+        for poster_first in [true, false] {
+            let mut world = World::new();
+            let bias = DepthBias {
+                constant_factor: -4.,
+                slope_factor: -4.,
+            };
+            let (poster, wall) = if poster_first {
+                let poster = spawn_quad(&mut world, &render_context, green);
+                (poster, spawn_quad(&mut world, &render_context, red))
+            } else {
+                let wall = spawn_quad(&mut world, &render_context, red);
+                (spawn_quad(&mut world, &render_context, green), wall)
+            };
+            world.insert(poster, (bias, Visible {})).unwrap();
+            world.insert_one(wall, Visible {}).unwrap();
+            render_quad(&mut render_context, &vulkan_context, &mut world);
+            let pixel = get_centre_pixel(resolution, &vulkan_context, &image);
+            assert!(is_green(&pixel), "Expected the poster, got {:?}", pixel);
+        }
+
+        // A wall biased away from the camera is the last thing `rendering_system` draws. A coplanar quad drawn after
+        // it, without setting a bias of its own, is in front of it - unless the wall's bias was left set.
+        let mut world = World::new();
+        let wall = spawn_quad(&mut world, &render_context, red);
+        let bias = DepthBias {
+            constant_factor: 4.,
+            slope_factor: 4.,
+        };
+        world.insert(wall, (bias, Visible {})).unwrap();
+        let overlay = spawn_quad(&mut world, &render_context, green);
+        render_quad_then(
+            &mut render_context,
+            &vulkan_context,
+            &mut world,
+            |world, vulkan_context, render_context| {
+                draw_with_bound_state(world, overlay, vulkan_context, render_context)
+            },
+        );
+        let pixel = get_centre_pixel(resolution, &vulkan_context, &image);
+        assert!(is_green(&pixel), "Expected the overlay, got {:?}", pixel);
+    }
+
+    #[test]
+    pub fn test_min_sample_shading_recreates_pipelines() {
+        let (vulkan_context, mut render_context, _, _) = offscreen_render_context();
+        let old_pipeline = render_context.pipeline;
+
+        let min_sample_shading = render_context
+            .set_min_sample_shading(&vulkan_context, Some(2.))
+            .unwrap();
+        if vulkan_context.enabled_features.sample_rate_shading == vk::FALSE {
+            // Unsupported devices fall back to shading once per pixel.
+            assert_eq!(min_sample_shading, None);
+            assert_eq!(render_context.pipeline, old_pipeline);
+            return;
+        }
+
+        assert_eq!(min_sample_shading, Some(1.));
+        assert_ne!(render_context.pipeline, old_pipeline);
+        assert_eq!(render_context.deletion_queue.len(), 4);
+
+        // Setting the same value again shouldn't recreate anything.
+        let pipeline = render_context.pipeline;
+        render_context
+            .set_min_sample_shading(&vulkan_context, Some(1.))
+            .unwrap();
+        assert_eq!(render_context.pipeline, pipeline);
+    }
+
+    #[test]
+    pub fn test_specialized_pipelines() {
+        let (vulkan_context, render_context, _, _) = offscreen_render_context();
+
+        // Two variants of the same PBR shader modules.
+        let mut constants = SpecializationConstants::default();
+        constants
+            .set("LIGHT_COUNT", 0, 1_u32)
+            .set("USE_FOG", 1, false);
+        let first = render_context
+            .create_specialized_pipeline(&vulkan_context, RenderLayer::OPAQUE, &constants)
+            .unwrap();
+        constants
+            .set("LIGHT_COUNT", 0, 4_u32)
+            .set("USE_FOG", 1, true);
+        let second = render_context
+            .create_specialized_pipeline(&vulkan_context, RenderLayer::OPAQUE, &constants)
+            .unwrap();
+
+        assert_ne!(first, vk::Pipeline::null());
+        assert_ne!(second, vk::Pipeline::null());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    pub fn test_frames_bind_distinct_descriptor_sets() {
+        let (vulkan_context, mut render_context, _, _) = offscreen_render_context_with_images(2);
+        assert_ne!(
+            render_context.scene_data_descriptor_set(0),
+            render_context.scene_data_descriptor_set(1)
+        );
+
+        // Updating the second frame's scene data must leave the first frame's, which may still be in flight, alone.
+        let view = openxr::View {
+            pose: openxr::Posef {
+                orientation: Quaternionf {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                    w: 1.,
+                },
+                position: Vector3f {
+                    x: 1.,
+                    y: 2.,
+                    z: 3.,
+                },
+            },
+            fov: Fovf {
+                angle_up: 45.0_f32.to_radians(),
+                angle_down: -45.0_f32.to_radians(),
+                angle_left: -45.0_f32.to_radians(),
+                angle_right: 45.0_f32.to_radians(),
+            },
+        };
+        render_context
+            .update_scene_data(&vec![view.clone(), view], &vulkan_context, 1)
+            .unwrap();
+
+        let first = unsafe {
+            get_from_device_memory(&vulkan_context, &render_context.scene_data_buffers[0])
+        }[0];
+        let second = unsafe {
+            get_from_device_memory(&vulkan_context, &render_context.scene_data_buffers[1])
+        }[0];
+        assert_ne!(first.view[0], second.view[0]);
+        assert_eq!(first.view[0], SceneData::default().view[0]);
+    }
+
+    /// A `RenderContext` that renders into an offscreen, 100x100 "swapchain" image, so its pixels can be read back
+    fn offscreen_render_context() -> (
+        VulkanContext,
+        RenderContext,
+        crate::image::Image,
+        vk::Extent2D,
+    ) {
+        let (vulkan_context, render_context, mut images, resolution) =
+            offscreen_render_context_with_images(1);
+        (vulkan_context, render_context, images.remove(0), resolution)
+    }
+
+    /// As `offscreen_render_context`, with a "swapchain" of `image_count` images
+    fn offscreen_render_context_with_images(
+        image_count: usize,
+    ) -> (
+        VulkanContext,
+        RenderContext,
+        Vec<crate::image::Image>,
+        vk::Extent2D,
+    ) {
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let resolution = vk::Extent2D {
+            height: 100,
+            width: 100,
+        };
+        let images = (0..image_count)
+            .map(|i| {
+                vulkan_context
+                    .create_image(
+                        COLOR_FORMAT,
+                        &resolution,
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                        2,
+                        1,
+                        &format!("Screenshot {}", i),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let swapchain = Swapchain {
+            images: images.iter().map(|i| i.handle).collect(),
+            resolution,
+            format: COLOR_FORMAT,
+        };
+        let render_context =
+            RenderContext::new_from_swapchain(&vulkan_context, &swapchain).unwrap();
+        (vulkan_context, render_context, images, resolution)
+    }
+
+    fn get_centre_pixel(
+        resolution: vk::Extent2D,
+        vulkan_context: &VulkanContext,
+        image: &crate::image::Image,
+    ) -> Vec<u8> {
+        let image_bytes = get_image_bytes(resolution, vulkan_context, image);
+        let centre =
+            ((resolution.height / 2 * resolution.width + resolution.width / 2) * 4) as usize;
+        image_bytes[centre..centre + 4].to_vec()
+    }
+
+    /// The number of distinct colours in the middle row of the image
+    fn count_centre_row_colours(
+        resolution: vk::Extent2D,
+        vulkan_context: &VulkanContext,
+        image: &crate::image::Image,
+    ) -> usize {
+        let image_bytes = get_image_bytes(resolution, vulkan_context, image);
+        let row_length = (resolution.width * 4) as usize;
+        let start = (resolution.height / 2) as usize * row_length;
+        let mut colours = image_bytes[start..start + row_length]
+            .chunks(4)
+            .collect::<Vec<_>>();
+        colours.sort();
+        colours.dedup();
+        colours.len()
+    }
+
+    fn render_quad(
+        render_context: &mut RenderContext,
+        vulkan_context: &VulkanContext,
+        world: &mut World,
+    ) {
+        render_quad_then(render_context, vulkan_context, world, |_, _, _| {});
+    }
+
+    /// Render `world` as `render_quad` does, then call `draw_after` in the same render pass, to draw as anything
+    /// drawn after `rendering_system` would.
+    fn render_quad_then(
+        render_context: &mut RenderContext,
+        vulkan_context: &VulkanContext,
+        world: &mut World,
+        draw_after: impl FnOnce(&World, &VulkanContext, &RenderContext),
+    ) {
+        // 1m in front of the quad, looking down -Z
+        let view = openxr::View {
+            pose: openxr::Posef {
+                orientation: Quaternionf {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                    w: 1.,
+                },
+                position: Vector3f {
+                    x: 0.,
+                    y: 0.,
+                    z: 1.,
+                },
+            },
+            fov: Fovf {
+                angle_up: 45.0_f32.to_radians(),
+                angle_down: -45.0_f32.to_radians(),
+                angle_left: -45.0_f32.to_radians(),
+                angle_right: 45.0_f32.to_radians(),
+            },
+        };
+        render_context
+            .update_scene_data(&vec![view.clone(), view], &vulkan_context, 0)
+            .unwrap();
+        render_context.begin_frame(&vulkan_context, 0).unwrap();
+        render_context.begin_pbr_render_pass(&vulkan_context, 0);
+        rendering_system(
+            &mut Default::default(),
+            world,
+            vulkan_context,
+            0,
+            render_context,
+        );
+        draw_after(world, vulkan_context, render_context);
+        render_context.end_pbr_render_pass(&vulkan_context, 0);
+        render_context.end_frame(&vulkan_context, 0).unwrap();
+    }
+
+    /// Draw `entity`'s mesh with whatever pipeline and dynamic state is bound, without setting any of its own.
+    fn draw_with_bound_state(
+        world: &World,
+        entity: Entity,
+        vulkan_context: &VulkanContext,
+        render_context: &RenderContext,
+    ) {
+        let device = &vulkan_context.device;
+        let command_buffer = render_context.frames[0].command_buffer;
+        let mut mesh = world.get_mut::<Mesh>(entity).unwrap();
+        mesh.ubo_data.transform = world.get::<TransformMatrix>(entity).unwrap().0;
+        mesh.ubo_buffer
+            .update(vulkan_context, &[mesh.ubo_data])
+            .unwrap();
+        let primitive = &mesh.primitives[0];
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                render_context.pipeline_layout,
+                1,
+                &[primitive.texture_descriptor_set, mesh.descriptor_sets[0]],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[primitive.vertex_buffer.handle],
+                &[0],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                primitive.index_buffer.handle(),
+                0,
+                primitive.index_buffer.index_type(),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                render_context.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                create_push_constant(&primitive.material),
+            );
+            primitive.draw(device, command_buffer);
+        }
     }
 
     fn schedule(
@@ -263,7 +1650,7 @@ mod tests {
         };
         let views = vec![view.clone(), view];
         render_context
-            .update_scene_data(&views, &vulkan_context)
+            .update_scene_data(&views, &vulkan_context, 0)
             .unwrap();
         render_context
             .scene_params_buffer
@@ -275,7 +1662,7 @@ mod tests {
                 }],
             )
             .unwrap();
-        render_context.begin_frame(&vulkan_context, 0);
+        render_context.begin_frame(&vulkan_context, 0).unwrap();
         render_context.begin_pbr_render_pass(&vulkan_context, 0);
         update_transform_matrix_system(&mut Default::default(), world);
         update_parent_transform_matrix_system(
@@ -291,7 +1678,7 @@ mod tests {
             render_context,
         );
         render_context.end_pbr_render_pass(&vulkan_context, 0);
-        render_context.end_frame(&vulkan_context, 0);
+        render_context.end_frame(&vulkan_context, 0).unwrap();
     }
 
     fn hash_file(file_path: &str) -> u64 {
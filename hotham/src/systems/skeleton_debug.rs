@@ -0,0 +1,105 @@
+use hecs::{Entity, PreparedQuery, With, World};
+use nalgebra::{vector, Vector3, Vector4};
+
+use crate::{
+    components::{Joint, Parent, TransformMatrix},
+    resources::RenderContext,
+};
+
+/// Colours used for bones, indexed by how deep the bone is in the skeleton.
+const BONE_COLOURS: [[f32; 4]; 6] = [
+    [1.0, 0.0, 0.0, 1.0],
+    [1.0, 0.5, 0.0, 1.0],
+    [1.0, 1.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0, 1.0],
+    [0.0, 1.0, 1.0, 1.0],
+    [0.0, 0.0, 1.0, 1.0],
+];
+
+/// Skeleton debug system
+/// Draws a line from each joint to its parent joint, coloured by how deep the joint is in the skeleton.
+/// Only draws anything if `RenderContext::draw_skeletons` is set.
+/// Make sure to call this AFTER `update_parent_transform_matrix_system` and BEFORE `end_pbr_renderpass`.
+pub fn skeleton_debug_system(
+    query: &mut PreparedQuery<With<Joint, (&TransformMatrix, &Parent)>>,
+    world: &mut World,
+    render_context: &mut RenderContext,
+) {
+    if !render_context.draw_skeletons || render_context.skip_frame {
+        return;
+    }
+
+    for (from, to, depth) in get_bones(query, world) {
+        render_context.draw_debug_line(from, to, get_bone_colour(depth));
+    }
+}
+
+/// Find each bone - a joint whose parent is also a joint - returning its start, end and depth in the skeleton.
+fn get_bones(
+    query: &mut PreparedQuery<With<Joint, (&TransformMatrix, &Parent)>>,
+    world: &World,
+) -> Vec<(Vector3<f32>, Vector3<f32>, usize)> {
+    query
+        .query(world)
+        .iter()
+        .filter_map(|(entity, (transform_matrix, parent))| {
+            world.get::<Joint>(parent.0).ok()?;
+            let parent_transform_matrix = world.get::<TransformMatrix>(parent.0).ok()?;
+            let from = parent_transform_matrix.0.column(3).xyz();
+            let to = transform_matrix.0.column(3).xyz();
+            Some((from, to, get_joint_depth(entity, world)))
+        })
+        .collect()
+}
+
+/// How many of this joint's ancestors are also joints?
+fn get_joint_depth(joint: Entity, world: &World) -> usize {
+    let mut depth = 0;
+    let mut current = joint;
+    while let Ok(parent) = world.get::<Parent>(current) {
+        let parent = parent.0;
+        if world.get::<Joint>(parent).is_err() {
+            break;
+        }
+        depth += 1;
+        current = parent;
+    }
+    depth
+}
+
+fn get_bone_colour(depth: usize) -> Vector4<f32> {
+    let [r, g, b, a] = BONE_COLOURS[depth % BONE_COLOURS.len()];
+    vector![r, g, b, a]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix4, Translation3};
+
+    #[test]
+    pub fn test_get_bones() {
+        let mut world = World::new();
+        let joint = |entity| Joint {
+            skeleton_root: entity,
+            inverse_bind_matrix: Matrix4::identity(),
+        };
+        let at = |y: f32| TransformMatrix(Translation3::new(0., y, 0.).to_homogeneous());
+
+        let root = world.spawn((at(0.),));
+        let a = world.spawn((at(1.), Parent(root)));
+        world.insert_one(a, joint(root)).unwrap();
+        let b = world.spawn((at(2.), Parent(a), joint(root)));
+        let c = world.spawn((at(3.), Parent(b), joint(root)));
+
+        let mut bones = get_bones(&mut Default::default(), &world);
+        bones.sort_by_key(|(_, _, depth)| *depth);
+
+        assert_eq!(bones.len(), 2);
+        assert_eq!(bones[0], (vector![0., 1., 0.], vector![0., 2., 0.], 1));
+        assert_eq!(bones[1], (vector![0., 2., 0.], vector![0., 3., 0.], 2));
+        assert_eq!(get_joint_depth(a, &world), 0);
+        assert_eq!(get_joint_depth(c, &world), 2);
+        assert_ne!(get_bone_colour(1), get_bone_colour(2));
+    }
+}
@@ -1,18 +1,30 @@
 use hecs::{Entity, PreparedQuery, World};
 use nalgebra::Matrix4;
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
-use crate::components::{Info, Joint, Mesh, Skin, TransformMatrix};
+use crate::{
+    components::{Info, Joint, Mesh, Skin, TransformMatrix},
+    resources::{cpu_timer::CpuPhase, RenderContext, VulkanContext},
+};
+
+use super::culling::update_culling_margins;
 
 /// Skinning system
 /// Walks through each joint in the system and builds up the `joint_matrices` that will be sent to the vertex shader
+/// Each mesh's joint buffer is grown if its `Skin` now has more joints than it has room for: see
+/// `Mesh::update_joint_buffer`.
+/// Also recalculates each `Skin`'s culling margin when the animations it's blending between change
 pub fn skinning_system(
     joints_query: &mut PreparedQuery<(&TransformMatrix, &Joint, &Info)>,
     meshes_query: &mut PreparedQuery<(&mut Mesh, &Skin)>,
     world: &mut World,
+    vulkan_context: &VulkanContext,
+    render_context: &mut RenderContext,
 ) -> () {
+    let start = Instant::now();
     let mut joint_matrices: HashMap<Entity, HashMap<usize, Matrix4<f32>>> = HashMap::new();
-    for (_, (transform_matrix, joint, info)) in joints_query.query(world).iter() {
+    let mut joints: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    for (entity, (transform_matrix, joint, info)) in joints_query.query(world).iter() {
         let inverse_transform = world
             .get_mut::<TransformMatrix>(joint.skeleton_root)
             .unwrap()
@@ -26,6 +38,7 @@ pub fn skinning_system(
         let joint_matrix = inverse_transform * joint_transform * inverse_bind_matrix;
         let matrices = joint_matrices.entry(joint.skeleton_root).or_default();
         matrices.insert(id, joint_matrix);
+        joints.entry(joint.skeleton_root).or_default().push(entity);
     }
 
     for (entity, (mesh, skin)) in meshes_query.query_mut(world) {
@@ -33,11 +46,110 @@ pub fn skinning_system(
             "Unable to get joint_matrix for entity: {:?}",
             entity
         ));
-        let joint_matrices = &mut mesh.ubo_data.joint_matrices;
-        for (i, joint_id) in skin.joint_ids.iter().enumerate() {
+        mesh.joint_matrices.clear();
+        for joint_id in &skin.joint_ids {
             let joint_matrix = matrices_map.remove(&joint_id).unwrap();
-            joint_matrices[i] = joint_matrix;
+            mesh.joint_matrices.push(joint_matrix);
         }
+        mesh.ubo_data.joint_count = mesh.joint_matrices.len() as f32;
+        mesh.update_joint_buffer(vulkan_context, render_context)
+            .expect("Unable to update joint buffer");
+    }
+
+    update_culling_margins(world, &joints);
+    render_context
+        .cpu_timer
+        .record(CpuPhase::Skinning, start.elapsed());
+}
+
+/// Reference checks for the normal skinning in `pbr.vert`. These test CPU copies of the shader's maths, not the shader
+/// itself, so they only catch mistakes in the maths: a change to `pbr.vert` must be made to the copies here too.
+#[cfg(test)]
+mod normal_reference_tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::{vector, Matrix3, UnitQuaternion, Vector3, Vector4};
+
+    /// The matrix normals are transformed by under `matrix`: its inverse transpose, up to a positive scale, so they
+    /// stay perpendicular to their surface when a bone is scaled non-uniformly. Built from the cofactors of `matrix`,
+    /// so bones scaled to nothing don't give NaNs. A CPU copy of `getNormalMatrix` in `pbr.vert`.
+    fn get_normal_matrix(matrix: &Matrix3<f32>) -> Matrix3<f32> {
+        let (a, b, c) = (matrix.column(0), matrix.column(1), matrix.column(2));
+        let cofactor = Matrix3::from_columns(&[b.cross(&c), c.cross(&a), a.cross(&b)]);
+
+        // The cofactors are the inverse transpose scaled by the determinant: keep mirrored normals pointing
+        // outwards.
+        if a.dot(&cofactor.column(0)) < 0. {
+            -cofactor
+        } else {
+            cofactor
+        }
+    }
+
+    /// Skin `normal` with `joint_matrices`, blended by a vertex's `joint_indices` and `joint_weights`, as `pbr.vert`
+    /// does. The result is unit length, or zero if the vertex has no normal or its bones are scaled to nothing. A CPU
+    /// copy of `main` in `pbr.vert`.
+    fn skin_normal(
+        joint_matrices: &[Matrix4<f32>],
+        joint_indices: &Vector4<f32>,
+        joint_weights: &Vector4<f32>,
+        normal: &Vector3<f32>,
+    ) -> Vector3<f32> {
+        let skin_matrix: Matrix4<f32> = (0..4)
+            .map(|i| joint_matrices[joint_indices[i] as usize] * joint_weights[i])
+            .sum();
+        let normal =
+            get_normal_matrix(&skin_matrix.fixed_slice::<3, 3>(0, 0).clone_owned()) * normal;
+        if normal.norm_squared() > 0. {
+            normal.normalize()
+        } else {
+            Vector3::zeros()
+        }
+    }
+
+    #[test]
+    pub fn test_scaled_bone_normals() {
+        // A 45 degree slope, with its normal and a tangent along it.
+        let normal = vector![1., 1., 0.].normalize();
+        let tangent = vector![1., -1., 0.];
+        let one_bone = (vector![0., 0., 0., 0.], vector![1., 0., 0., 0.]);
+
+        // A bone stretched along X, then turned, flattens the slope: the normal must turn with it, not away from it.
+        let rotation = UnitQuaternion::from_euler_angles(0., 0., 0.3).to_homogeneous();
+        let bone = rotation * Matrix4::new_nonuniform_scaling(&vector![2., 1., 1.]);
+        let skinned = skin_normal(&[bone], &one_bone.0, &one_bone.1, &normal);
+        let skinned_tangent = bone.transform_vector(&tangent);
+        assert_relative_eq!(skinned.norm(), 1., epsilon = 1e-6);
+        assert_relative_eq!(skinned.dot(&skinned_tangent), 0., epsilon = 1e-6);
+        assert!(skinned.dot(&bone.transform_vector(&normal)) > 0.);
+
+        // Transforming the normal like a position, as before, would have sheared it off the surface.
+        let sheared = bone.transform_vector(&normal).normalize();
+        assert!(sheared.dot(&skinned_tangent).abs() > 0.1);
+
+        // Blending a scaled bone with an unscaled one still gives a unit normal, perpendicular to the blended slope.
+        let two_bones = (vector![0., 1., 0., 0.], vector![0.5, 0.5, 0., 0.]);
+        let bones = [bone, Matrix4::identity()];
+        let skinned = skin_normal(&bones, &two_bones.0, &two_bones.1, &normal);
+        let blended = (bone + Matrix4::identity()) * 0.5;
+        assert_relative_eq!(skinned.norm(), 1., epsilon = 1e-6);
+        assert_relative_eq!(
+            skinned.dot(&blended.transform_vector(&tangent)),
+            0.,
+            epsilon = 1e-6
+        );
+
+        // A mirrored bone keeps its normals pointing out of the surface..
+        let mirror = Matrix4::new_nonuniform_scaling(&vector![-1., 1., 1.]);
+        let skinned = skin_normal(&[mirror], &one_bone.0, &one_bone.1, &vector![1., 0., 0.]);
+        assert_relative_eq!(skinned, vector![-1., 0., 0.]);
+
+        // ..and one scaled to nothing, or a vertex without a normal, doesn't give NaNs.
+        let collapsed = Matrix4::new_scaling(0.);
+        let skinned = skin_normal(&[collapsed], &one_bone.0, &one_bone.1, &normal);
+        assert_eq!(skinned, Vector3::zeros());
+        let skinned = skin_normal(&[bone], &one_bone.0, &one_bone.1, &Vector3::zeros());
+        assert_eq!(skinned, Vector3::zeros());
     }
 }
 
@@ -45,14 +157,16 @@ pub fn skinning_system(
 #[cfg(test)]
 mod tests {
 
-    use std::{io::Write, marker::PhantomData};
+    use std::io::Write;
 
     use crate::{
-        buffer::Buffer,
-        components::{mesh::MeshUBO, Joint, Parent, Skin},
+        components::{panel::create_quad_mesh, Joint, Parent, Skin},
         resources::VulkanContext,
+        swapchain::Swapchain,
         systems::skinning_system,
+        texture::Texture,
         util::{get_from_device_memory, get_world_with_hands},
+        COLOR_FORMAT,
     };
 
     use super::*;
@@ -71,37 +185,20 @@ mod tests {
 
         // Create a skin
         let inverse = root_transform_matrix.try_inverse().unwrap();
-        let mut ubo_data = MeshUBO::default();
-        ubo_data.joint_matrices[0] = inverse.clone();
-        ubo_data.joint_matrices[1] = inverse.clone();
-        ubo_data.joint_count = 2.;
-        let ubo_buffer = Buffer {
-            handle: vk::Buffer::null(),
-            device_memory: vk::DeviceMemory::null(),
-            size: 0,
-            device_memory_size: 0,
-            usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
-            _phantom: PhantomData,
-        };
 
         // Store the invese matrices for our test
         let joint_matrices = vec![inverse.clone(), inverse];
 
         // Create a Mesh
-        let mesh = Mesh {
-            descriptor_sets: [vk::DescriptorSet::null()],
-            primitives: Vec::new(),
-            ubo_data,
-            ubo_buffer,
-        };
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let mut render_context = get_render_context(&vulkan_context);
+        let mesh = get_mesh(&vulkan_context, &render_context);
 
         // Now create the skin entity
         let skinned_entity = world.spawn((
             mesh,
             TransformMatrix(root_transform_matrix),
-            Skin {
-                joint_ids: vec![0, 1],
-            },
+            Skin::new(vec![0, 1]),
         ));
 
         // Create a child joint
@@ -140,10 +237,18 @@ mod tests {
             },
         ));
 
-        skinning_system(&mut Default::default(), &mut Default::default(), &mut world);
+        skinning_system(
+            &mut Default::default(),
+            &mut Default::default(),
+            &mut world,
+            &vulkan_context,
+            &mut render_context,
+        );
 
         let mesh = world.get_mut::<Mesh>(skinned_entity).unwrap();
-        let matrices_from_buffer = mesh.ubo_data.joint_matrices;
+        assert_eq!(mesh.ubo_data.joint_count, 2.);
+        let matrices_from_buffer =
+            unsafe { get_from_device_memory(&vulkan_context, &mesh.joint_buffer) };
 
         for (from_buf, joint_matrices) in matrices_from_buffer.iter().zip(joint_matrices.iter()) {
             assert_ne!(*from_buf, Matrix4::identity());
@@ -154,14 +259,16 @@ mod tests {
     #[test]
     pub fn test_hand_skinning() {
         let vulkan_context = VulkanContext::testing().unwrap();
+        let mut render_context = get_render_context(&vulkan_context);
         let mut world = get_world_with_hands();
 
-        skinning_system(&mut Default::default(), &mut Default::default(), &mut world);
-        for (_, mesh) in world.query_mut::<&Mesh>() {
-            mesh.ubo_buffer
-                .update(&vulkan_context, &[mesh.ubo_data])
-                .unwrap();
-        }
+        skinning_system(
+            &mut Default::default(),
+            &mut Default::default(),
+            &mut world,
+            &vulkan_context,
+            &mut render_context,
+        );
 
         let mut called = 0;
         for skinned_entity in world
@@ -184,8 +291,8 @@ mod tests {
                 ))
                 .unwrap()
             };
-            let ubo = unsafe { get_from_device_memory(&vulkan_context, &mesh.ubo_buffer) };
-            let matrices_from_buffer = ubo[0].joint_matrices.to_vec();
+            let matrices_from_buffer =
+                unsafe { get_from_device_memory(&vulkan_context, &mesh.joint_buffer) }.to_vec();
             for i in 0..correct_matrices.len() {
                 let expected = correct_matrices[i];
                 let actual = matrices_from_buffer[i];
@@ -207,4 +314,104 @@ mod tests {
 
         assert_ne!(called, 0);
     }
+
+    #[test]
+    pub fn test_joint_buffer_grows_with_skin() {
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let mut render_context = get_render_context(&vulkan_context);
+        let mut world = World::new();
+
+        // Quad meshes start out with room for a single joint.
+        let mesh = get_mesh(&vulkan_context, &render_context);
+        assert_eq!(mesh.joint_capacity(), 1);
+        let skinned_entity = world.spawn((
+            mesh,
+            TransformMatrix(Matrix4::identity()),
+            Skin::new(vec![0, 1]),
+        ));
+        let joint_translations = [
+            vector![1.0, 0.0, 0.0],
+            vector![0.0, 1.0, 0.0],
+            vector![0.0, 0.0, 1.0],
+        ];
+        for (node_id, translation) in joint_translations.iter().enumerate() {
+            world.spawn((
+                Joint {
+                    skeleton_root: skinned_entity,
+                    inverse_bind_matrix: Matrix4::identity(),
+                },
+                TransformMatrix(Matrix4::new_translation(translation)),
+                Info {
+                    name: node_id.to_string(),
+                    node_id,
+                },
+            ));
+        }
+        let original_descriptor_set = world.get::<Mesh>(skinned_entity).unwrap().descriptor_sets[0];
+
+        // Two joints don't fit, so the buffer and descriptor set are replaced and the old ones released..
+        skinning_system(
+            &mut Default::default(),
+            &mut Default::default(),
+            &mut world,
+            &vulkan_context,
+            &mut render_context,
+        );
+        {
+            let mesh = world.get::<Mesh>(skinned_entity).unwrap();
+            assert_eq!(mesh.joint_capacity(), 2);
+            assert_ne!(mesh.descriptor_sets[0], original_descriptor_set);
+            assert_eq!(render_context.deletion_queue.len(), 2);
+        }
+
+        // ..then swapping to a skeleton with more joints grows it to the next power of two.
+        world
+            .insert_one(skinned_entity, Skin::new(vec![0, 1, 2]))
+            .unwrap();
+        skinning_system(
+            &mut Default::default(),
+            &mut Default::default(),
+            &mut world,
+            &vulkan_context,
+            &mut render_context,
+        );
+        let mesh = world.get::<Mesh>(skinned_entity).unwrap();
+        assert_eq!(mesh.joint_capacity(), 4);
+        assert_eq!(mesh.ubo_data.joint_count, 3.);
+        assert_eq!(render_context.deletion_queue.len(), 4);
+
+        let matrices_from_buffer =
+            unsafe { get_from_device_memory(&vulkan_context, &mesh.joint_buffer) };
+        for (matrix, translation) in matrices_from_buffer.iter().zip(&joint_translations) {
+            assert_eq!(*matrix, Matrix4::new_translation(translation));
+        }
+    }
+
+    fn get_render_context(vulkan_context: &VulkanContext) -> RenderContext {
+        let resolution = vk::Extent2D {
+            height: 100,
+            width: 100,
+        };
+        let image = vulkan_context
+            .create_image(
+                COLOR_FORMAT,
+                &resolution,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                2,
+                1,
+                "Skinning Image",
+            )
+            .unwrap();
+        let swapchain = Swapchain {
+            images: vec![image.handle],
+            resolution,
+            format: COLOR_FORMAT,
+        };
+        RenderContext::new_from_swapchain(vulkan_context, &swapchain).unwrap()
+    }
+
+    fn get_mesh(vulkan_context: &VulkanContext, render_context: &RenderContext) -> Mesh {
+        let texture = Texture::empty(vulkan_context).unwrap();
+        create_quad_mesh(&texture, vulkan_context, render_context, 0.5, 0.5)
+    }
 }
@@ -0,0 +1,47 @@
+use hecs::{PreparedQuery, With, World};
+use log::error;
+
+use crate::{
+    components::{Mesh, TransformMatrix, Visible},
+    resources::{RenderContext, TextureStreaming, VulkanContext},
+};
+
+/// Texture streaming system
+/// Requests the streamed textures of every visible mesh at the distance from the user to the nearest point of each
+/// primitive's bounding sphere, then streams mips in or out with `TextureStreaming::update`. Uses the camera position
+/// from the previous frame.
+/// Make sure to call this AFTER `begin_frame` and BEFORE anything is recorded for the frame, eg. `draw_gui_system`.
+pub fn texture_streaming_system(
+    query: &mut PreparedQuery<With<Visible, (&Mesh, &TransformMatrix)>>,
+    world: &mut World,
+    vulkan_context: &VulkanContext,
+    render_context: &mut RenderContext,
+    texture_streaming: &mut TextureStreaming,
+) {
+    // Don't upload anything if this frame is being skipped.
+    if render_context.skip_frame {
+        return;
+    }
+
+    // Use the point between the two eyes.
+    let camera_position = render_context.scene_data.camera_position;
+    let camera_position = (camera_position[0] + camera_position[1]).xyz() * 0.5;
+
+    for (_, (mesh, transform_matrix)) in query.query(world).iter() {
+        for primitive in &mesh.primitives {
+            let bounding_sphere = primitive.bounding_sphere.transform(&transform_matrix.0);
+            let distance = ((bounding_sphere.center - camera_position).norm()
+                - bounding_sphere.radius)
+                .max(0.);
+            texture_streaming.request_descriptor_set(primitive.texture_descriptor_set, distance);
+        }
+    }
+
+    if let Err(e) = texture_streaming.update(
+        vulkan_context,
+        &mut render_context.deletion_queue,
+        render_context.frame_index,
+    ) {
+        error!(target: "HOTHAM_TEXTURE_STREAMING", "Unable to stream textures: {:?}", e);
+    }
+}
@@ -0,0 +1,28 @@
+use hecs::{PreparedQuery, World};
+
+use crate::{
+    components::{Transform, UiOverlay},
+    resources::XrContext,
+    util::posef_to_isometry,
+};
+
+/// UI overlay system
+/// Places every `UiOverlay` in front of the user's head, facing them. The head is midway between the eyes' views, so
+/// each eye sees the overlay from its own side and the two converge at `UiOverlay::distance`.
+/// Make sure to call this AFTER `begin_frame`, so the views are up to date, and BEFORE `update_transform_matrix_system`.
+pub fn ui_overlay_system(
+    query: &mut PreparedQuery<(&UiOverlay, &mut Transform)>,
+    world: &mut World,
+    xr_context: &XrContext,
+) {
+    if xr_context.views.is_empty() {
+        return;
+    }
+
+    let head_pose = posef_to_isometry(xr_context.head_pose());
+    for (_, (overlay, transform)) in query.query_mut(world) {
+        let pose = overlay.get_pose(&head_pose);
+        transform.translation = pose.translation.vector;
+        transform.rotation = pose.rotation;
+    }
+}
@@ -0,0 +1,234 @@
+use hecs::{PreparedQuery, With, World};
+use nalgebra::{vector, Point3, Vector2, Vector3};
+
+use crate::{
+    components::{
+        hand::Handedness,
+        ui_panel::{raycast_ui_panel, UiEvent},
+        Pointer, Transform, TransformMatrix, UiPanel, Visible,
+    },
+    resources::XrContext,
+};
+
+/// How far pointers can reach, in metres
+const MAX_DISTANCE: f32 = 40.0;
+/// How far the trigger must be pulled to click
+const TRIGGER_THRESHOLD: f32 = 0.75;
+/// Thumbstick movements smaller than this are ignored
+const THUMBSTICK_DEADZONE: f32 = 0.1;
+
+/// The state of a single pointer this frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PointerRay {
+    handedness: Handedness,
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    trigger_value: f32,
+    thumbstick: Vector2<f32>,
+}
+
+/// UI panel system
+/// Casts a ray from each `Pointer` against every visible `UiPanel` and fills the panel it hits with hover, click
+/// and scroll events.
+/// Make sure to call this AFTER `pointers_system`, so the pointers are up to date.
+pub fn ui_panel_system(
+    query: &mut PreparedQuery<With<Visible, (&Pointer, &Transform)>>,
+    world: &mut World,
+    xr_context: &XrContext,
+) {
+    let pointers = query
+        .query(world)
+        .iter()
+        .map(|(_, (pointer, transform))| {
+            let path = match pointer.handedness {
+                Handedness::Left => xr_context.left_hand_subaction_path,
+                Handedness::Right => xr_context.right_hand_subaction_path,
+            };
+            let thumbstick =
+                openxr::ActionInput::get(&xr_context.thumbstick_action, &xr_context.session, path)
+                    .map(|s| vector![s.current_state.x, s.current_state.y])
+                    .unwrap_or_else(|_| Vector2::zeros());
+
+            // Pointers point along +Y: see `pointers_system`.
+            PointerRay {
+                handedness: pointer.handedness,
+                origin: Point3::from(transform.translation),
+                direction: transform.rotation.transform_vector(&vector![0., 1., 0.]),
+                trigger_value: pointer.trigger_value,
+                thumbstick,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    update_ui_panels(world, &pointers);
+}
+
+fn update_ui_panels(world: &World, pointers: &[PointerRay]) {
+    let mut panels = world.query::<With<Visible, (&mut UiPanel, &TransformMatrix)>>();
+    let mut panels = panels.iter().collect::<Vec<_>>();
+    let mut hovered_by = vec![Vec::new(); panels.len()];
+    for (_, (panel, _)) in panels.iter_mut() {
+        panel.events.clear();
+    }
+
+    for pointer in pointers {
+        // Only the closest panel gets the pointer's events.
+        let closest = panels
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (_, (panel, transform_matrix)))| {
+                raycast_ui_panel(
+                    &panel.size,
+                    &transform_matrix.0,
+                    &pointer.origin,
+                    &pointer.direction,
+                )
+                .map(|(distance, uv)| (index, distance, uv))
+            })
+            .filter(|(_, distance, _)| *distance <= MAX_DISTANCE)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let (index, _, uv) = match closest {
+            Some(closest) => closest,
+            None => continue,
+        };
+        hovered_by[index].push(pointer.handedness);
+
+        let (_, (panel, _)) = &mut panels[index];
+        panel.events.push(UiEvent::Hover(uv));
+
+        // Each pointer clicks once per pull, whatever the other pointer is doing.
+        let pressed = panel.pressed.contains(&pointer.handedness);
+        if pointer.trigger_value >= TRIGGER_THRESHOLD {
+            if !pressed {
+                panel.events.push(UiEvent::Click(uv));
+                panel.pressed.push(pointer.handedness);
+            }
+        } else if pressed {
+            panel.pressed.retain(|h| *h != pointer.handedness);
+        }
+
+        if pointer.thumbstick.norm() > THUMBSTICK_DEADZONE {
+            panel.events.push(UiEvent::Scroll(pointer.thumbstick));
+        }
+    }
+
+    // Pointing away releases the trigger.
+    for ((_, (panel, _)), hovered_by) in panels.iter_mut().zip(hovered_by) {
+        panel.pressed.retain(|h| hovered_by.contains(h));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk;
+    use nalgebra::Isometry3;
+
+    use crate::{image::Image, texture::Texture, COLOR_FORMAT};
+
+    #[test]
+    pub fn test_ui_panel_events() {
+        let mut world = World::new();
+        let panel = world.spawn((
+            UiPanel {
+                texture: Texture {
+                    image: Image::new(
+                        vk::Image::null(),
+                        vk::ImageView::null(),
+                        vk::DeviceMemory::null(),
+                        vk::Extent2D::default(),
+                        vk::ImageUsageFlags::SAMPLED,
+                        COLOR_FORMAT,
+                        vk::ImageViewType::TYPE_2D,
+                        1,
+                    ),
+                    sampler: vk::Sampler::null(),
+                    descriptor: Default::default(),
+                },
+                size: vector![1., 1.],
+                events: Vec::new(),
+                pressed: Vec::new(),
+            },
+            TransformMatrix(Isometry3::translation(0., 0., -1.).to_homogeneous()),
+            Visible {},
+        ));
+
+        let mut pointer = PointerRay {
+            handedness: Handedness::Right,
+            origin: Point3::origin(),
+            direction: vector![0., 0., -1.],
+            trigger_value: 0.,
+            thumbstick: vector![0., 0.5],
+        };
+        let events = |world: &World| world.get::<UiPanel>(panel).unwrap().events.clone();
+
+        update_ui_panels(&world, &[pointer]);
+        assert_eq!(
+            events(&world),
+            vec![
+                UiEvent::Hover(vector![0.5, 0.5]),
+                UiEvent::Scroll(vector![0., 0.5])
+            ]
+        );
+
+        // Pulling the trigger clicks once..
+        pointer.trigger_value = 1.;
+        pointer.thumbstick = Vector2::zeros();
+        update_ui_panels(&world, &[pointer]);
+        assert_eq!(
+            events(&world),
+            vec![
+                UiEvent::Hover(vector![0.5, 0.5]),
+                UiEvent::Click(vector![0.5, 0.5])
+            ]
+        );
+
+        // ..and not again while it's held.
+        update_ui_panels(&world, &[pointer]);
+        assert_eq!(events(&world), vec![UiEvent::Hover(vector![0.5, 0.5])]);
+
+        // Holding one trigger doesn't stop the other pointer clicking..
+        let mut left = PointerRay {
+            handedness: Handedness::Left,
+            trigger_value: 1.,
+            ..pointer
+        };
+        update_ui_panels(&world, &[pointer, left]);
+        assert_eq!(
+            events(&world),
+            vec![
+                UiEvent::Hover(vector![0.5, 0.5]),
+                UiEvent::Hover(vector![0.5, 0.5]),
+                UiEvent::Click(vector![0.5, 0.5])
+            ]
+        );
+
+        // ..and releasing it doesn't release the other.
+        left.trigger_value = 0.;
+        update_ui_panels(&world, &[pointer, left]);
+        assert_eq!(
+            events(&world),
+            vec![
+                UiEvent::Hover(vector![0.5, 0.5]),
+                UiEvent::Hover(vector![0.5, 0.5])
+            ]
+        );
+
+        // Pointing away clears the events.
+        pointer.direction = vector![0., 0., 1.];
+        update_ui_panels(&world, &[pointer]);
+        assert!(events(&world).is_empty());
+
+        // ..and releases the trigger, so pointing back clicks again.
+        pointer.direction = vector![0., 0., -1.];
+        update_ui_panels(&world, &[pointer]);
+        assert_eq!(
+            events(&world),
+            vec![
+                UiEvent::Hover(vector![0.5, 0.5]),
+                UiEvent::Click(vector![0.5, 0.5])
+            ]
+        );
+    }
+}
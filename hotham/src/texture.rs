@@ -1,10 +1,16 @@
 use crate::{image::Image, resources::VulkanContext};
 use anyhow::{anyhow, Result};
 use ash::vk;
-use gltf::image::Format;
+use gltf::{
+    image::Format,
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
 use image::io::Reader as ImageReader;
 use libktx_rs::{sources::StreamSource, RustKtxStream, TextureCreateFlags, TextureSource};
+use log::{debug, error};
 use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
     io::Cursor,
     sync::{Arc, Mutex},
 };
@@ -17,6 +23,154 @@ pub struct Texture {
 }
 
 const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+/// The format of sRGB encoded textures. Sampling them decodes them to linear.
+const SRGB_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// What a texture is used for, which decides the colour space it's loaded in when it's `TextureColorSpace::Auto`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureRole {
+    /// Base colour, or the diffuse colour of the specular-glossiness workflow. sRGB encoded.
+    BaseColor,
+    /// Emitted colour. sRGB encoded.
+    Emissive,
+    /// Specular colour and glossiness of the specular-glossiness workflow. sRGB encoded, except for glossiness in
+    /// alpha, which like all alpha is linear.
+    SpecularGlossiness,
+    /// Metalness and roughness. Linear.
+    MetallicRoughness,
+    /// Tangent space normals. Linear.
+    Normal,
+    /// Ambient occlusion. Linear.
+    Occlusion,
+    /// A height map, for parallax occlusion mapping. Linear.
+    Height,
+}
+
+impl TextureRole {
+    /// Do textures with this role hold colours, which glTF stores sRGB encoded?
+    pub fn is_color(self) -> bool {
+        matches!(
+            self,
+            TextureRole::BaseColor | TextureRole::Emissive | TextureRole::SpecularGlossiness
+        )
+    }
+}
+
+/// How a texture's texels are encoded, which decides whether it's given an sRGB or a UNORM format. Shaders always
+/// sample linear values: sRGB textures are decoded by the hardware when they're sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    /// Decide from the texture's `TextureRole`, as glTF does: colours are sRGB and everything else is linear
+    Auto,
+    /// Linear, eg. data or a colour rendered by Hotham. Sampled as it's stored.
+    Linear,
+    /// sRGB encoded, as most images painted or photographed are. Decoded to linear when sampled.
+    Srgb,
+}
+
+impl Default for TextureColorSpace {
+    fn default() -> Self {
+        TextureColorSpace::Auto
+    }
+}
+
+/// The format to load an RGBA8 texture with `role` in `color_space` with
+pub fn get_texture_format(role: TextureRole, color_space: TextureColorSpace) -> vk::Format {
+    let is_srgb = match color_space {
+        TextureColorSpace::Auto => role.is_color(),
+        TextureColorSpace::Linear => false,
+        TextureColorSpace::Srgb => true,
+    };
+    if is_srgb {
+        SRGB_TEXTURE_FORMAT
+    } else {
+        TEXTURE_FORMAT
+    }
+}
+
+/// The sampler state used by a `Texture`. Textures with identical `SamplerInfo` share a `vk::Sampler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerInfo {
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    pub mip_count: u32,
+    /// Filter used when the texture is magnified. Use `NEAREST` for pixel art.
+    pub mag_filter: vk::Filter,
+    /// Filter used when the texture is minified
+    pub min_filter: vk::Filter,
+    /// How to blend between mip levels, or `None` to only ever sample the first one
+    pub mipmap_mode: Option<vk::SamplerMipmapMode>,
+}
+
+impl SamplerInfo {
+    pub fn new(address_mode: vk::SamplerAddressMode, mip_count: u32) -> Self {
+        Self {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mip_count,
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: Some(vk::SamplerMipmapMode::LINEAR),
+        }
+    }
+
+    /// Use the `wrapS` / `wrapT` modes and the filters declared by a glTF sampler. Filters that aren't declared are
+    /// linear.
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        let (min_filter, mipmap_mode) = get_min_filter(sampler.min_filter());
+        Self {
+            address_mode_u: get_address_mode(sampler.wrap_s()),
+            address_mode_v: get_address_mode(sampler.wrap_t()),
+            mag_filter: get_mag_filter(sampler.mag_filter()),
+            min_filter,
+            mipmap_mode,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        Self::new(vk::SamplerAddressMode::REPEAT, 1)
+    }
+}
+
+fn get_address_mode(wrapping_mode: WrappingMode) -> vk::SamplerAddressMode {
+    match wrapping_mode {
+        WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+    }
+}
+
+fn get_mag_filter(mag_filter: Option<MagFilter>) -> vk::Filter {
+    match mag_filter {
+        Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+        Some(MagFilter::Linear) | None => vk::Filter::LINEAR,
+    }
+}
+
+/// glTF combines the minification filter and mipmap mode, as OpenGL does
+fn get_min_filter(min_filter: Option<MinFilter>) -> (vk::Filter, Option<vk::SamplerMipmapMode>) {
+    match min_filter {
+        Some(MinFilter::Nearest) => (vk::Filter::NEAREST, None),
+        Some(MinFilter::Linear) => (vk::Filter::LINEAR, None),
+        Some(MinFilter::NearestMipmapNearest) => {
+            (vk::Filter::NEAREST, Some(vk::SamplerMipmapMode::NEAREST))
+        }
+        Some(MinFilter::LinearMipmapNearest) => {
+            (vk::Filter::LINEAR, Some(vk::SamplerMipmapMode::NEAREST))
+        }
+        Some(MinFilter::NearestMipmapLinear) => {
+            (vk::Filter::NEAREST, Some(vk::SamplerMipmapMode::LINEAR))
+        }
+        Some(MinFilter::LinearMipmapLinear) | None => {
+            (vk::Filter::LINEAR, Some(vk::SamplerMipmapMode::LINEAR))
+        }
+    }
+}
 
 impl Texture {
     pub fn new(
@@ -26,8 +180,9 @@ impl Texture {
         width: u32,
         height: u32,
         format: vk::Format,
+        sampler_info: SamplerInfo,
     ) -> Result<Self> {
-        let (image, sampler) = vulkan_context.create_texture_image(
+        let image = vulkan_context.create_texture_image(
             name,
             image_buf,
             width,
@@ -37,6 +192,7 @@ impl Texture {
             1,
             vec![0],
         )?;
+        let sampler = vulkan_context.get_texture_sampler(&sampler_info)?;
         let descriptor = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .image_view(image.view)
@@ -50,17 +206,22 @@ impl Texture {
         })
     }
 
+    /// Load a texture from a glTF document, used as `role`. Its format is chosen by `get_texture_format`.
     pub fn load(
         mesh_name: &str,
         texture: gltf::texture::Texture,
         vulkan_context: &VulkanContext,
         images: &Vec<gltf::image::Data>,
+        role: TextureRole,
+        color_space: TextureColorSpace,
     ) -> Option<Self> {
         let texture_name = &format!(
             "Texture {} for mesh {}",
             texture.name().unwrap_or(""),
             mesh_name
         );
+        let sampler_info = SamplerInfo::from_gltf(&texture.sampler());
+        let format = get_texture_format(role, color_space);
         match texture.source().source() {
             gltf::image::Source::Uri { uri, .. } => {
                 let (buf, width, height) =
@@ -72,7 +233,8 @@ impl Texture {
                         &buf,
                         width,
                         height,
-                        TEXTURE_FORMAT,
+                        format,
+                        sampler_info,
                     )
                     .unwrap(),
                 )
@@ -89,7 +251,8 @@ impl Texture {
                         &pixels,
                         image.width,
                         image.height,
-                        TEXTURE_FORMAT,
+                        format,
+                        sampler_info,
                     )
                 } else {
                     Texture::new(
@@ -98,17 +261,81 @@ impl Texture {
                         &image.pixels,
                         image.width,
                         image.height,
-                        TEXTURE_FORMAT,
+                        format,
+                        sampler_info,
                     )
                 };
 
                 texture
-                    .map_err(|e| eprintln!("Failed to load texture {} - {:?}", index, e))
+                    .map_err(|e| error!("Failed to load texture {} - {:?}", index, e))
                     .ok()
             }
         }
     }
 
+    /// The key `texture` is stored under in an `AssetStore` when it's loaded from a glTF document as `role`. Images
+    /// embedded in the document have no path, so they're keyed by their pixels: the same image used by several
+    /// materials, or loaded again from the same file, gets the same key.
+    pub(crate) fn get_gltf_key(
+        texture: &gltf::texture::Texture,
+        images: &[gltf::image::Data],
+        role: TextureRole,
+        color_space: TextureColorSpace,
+    ) -> String {
+        let source = match texture.source().source() {
+            gltf::image::Source::Uri { uri, .. } => uri.to_string(),
+            gltf::image::Source::View { .. } => {
+                let image = &images[texture.source().index()];
+                let mut hasher = DefaultHasher::new();
+                image.pixels.hash(&mut hasher);
+                format!(
+                    "glTF image {:016x} {}x{} {:?}",
+                    hasher.finish(),
+                    image.width,
+                    image.height,
+                    image.format
+                )
+            }
+        };
+        format!(
+            "{}#{:?}#{:?}",
+            source,
+            get_texture_format(role, color_space),
+            SamplerInfo::from_gltf(&texture.sampler())
+        )
+    }
+
+    /// Load an image file (eg. a PNG or JPEG) from the assets directory. It's treated as an sRGB encoded colour: use
+    /// `from_path_with_color_space` for anything else, eg. a normal map.
+    pub fn from_path(path: &str, vulkan_context: &VulkanContext) -> Result<Self> {
+        Self::from_path_with_color_space(
+            path,
+            vulkan_context,
+            TextureRole::BaseColor,
+            TextureColorSpace::Auto,
+        )
+    }
+
+    /// Load an image file (eg. a PNG or JPEG) from the assets directory, used as `role`. Its format is chosen by
+    /// `get_texture_format`, so `color_space` can force it to be treated as linear or sRGB whatever its role.
+    pub fn from_path_with_color_space(
+        path: &str,
+        vulkan_context: &VulkanContext,
+        role: TextureRole,
+        color_space: TextureColorSpace,
+    ) -> Result<Self> {
+        let (buf, width, height) = parse_image(path)?;
+        Self::new(
+            path,
+            vulkan_context,
+            &buf,
+            width,
+            height,
+            get_texture_format(role, color_space),
+            Default::default(),
+        )
+    }
+
     pub fn empty(vulkan_context: &VulkanContext) -> Result<Self> {
         Self::new(
             "Empty Texture",
@@ -117,6 +344,7 @@ impl Texture {
             1,
             1,
             TEXTURE_FORMAT,
+            Default::default(),
         )
     }
 
@@ -124,12 +352,12 @@ impl Texture {
         let buf = Cursor::new(buf.to_vec());
         let buf = Box::new(buf);
         let (buf, width, height, format, array_layers, mip_levels, offsets) = parse_ktx(buf)?;
-        println!(
+        debug!(
             "Creating texture image with format {:?}, array layers {} and mip_levels {}",
             format, array_layers, mip_levels
         );
 
-        let (image, sampler) = vulkan_context.create_texture_image(
+        let image = vulkan_context.create_texture_image(
             name,
             &buf,
             width,
@@ -139,6 +367,15 @@ impl Texture {
             mip_levels,
             offsets,
         )?;
+
+        // Lookup tables and cubemaps shouldn't wrap.
+        let address_mode = if format == vk::Format::R16G16_SFLOAT || array_layers == 6 {
+            vk::SamplerAddressMode::CLAMP_TO_EDGE
+        } else {
+            vk::SamplerAddressMode::REPEAT
+        };
+        let sampler =
+            vulkan_context.get_texture_sampler(&SamplerInfo::new(address_mode, mip_levels))?;
         let descriptor = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
             .image_view(image.view)
@@ -254,3 +491,124 @@ const EMPTY_KTX: [u8; 104] = [
     0x6F, 0x6E, 0x00, 0x53, 0x3D, 0x72, 0x2C, 0x54, 0x3D, 0x64, 0x2C, 0x52, 0x3D, 0x69, 0x00, 0x00,
     0x04, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_sampler_info_from_gltf() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "images": [{ "uri": "test.png" }],
+            "samplers": [{ "wrapS": 33071, "wrapT": 33648 }],
+            "textures": [{ "source": 0, "sampler": 0 }, { "source": 0 }]
+        }"#;
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let mut textures = gltf.textures();
+
+        let clamped = SamplerInfo::from_gltf(&textures.next().unwrap().sampler());
+        assert_eq!(
+            clamped.address_mode_u,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE
+        );
+        assert_eq!(
+            clamped.address_mode_v,
+            vk::SamplerAddressMode::MIRRORED_REPEAT
+        );
+
+        // Textures without a sampler should use the glTF default of REPEAT.
+        let default = SamplerInfo::from_gltf(&textures.next().unwrap().sampler());
+        assert_eq!(default, SamplerInfo::default());
+    }
+
+    #[test]
+    pub fn test_nearest_sampler_from_gltf() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "images": [{ "uri": "test.png" }],
+            "samplers": [
+                { "magFilter": 9728, "minFilter": 9728 },
+                { "magFilter": 9729, "minFilter": 9986 }
+            ],
+            "textures": [{ "source": 0, "sampler": 0 }, { "source": 0, "sampler": 1 }]
+        }"#;
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let mut textures = gltf.textures();
+
+        // Pixel art: nearest filtering, without mipmaps.
+        let nearest = SamplerInfo::from_gltf(&textures.next().unwrap().sampler());
+        assert_eq!(nearest.mag_filter, vk::Filter::NEAREST);
+        assert_eq!(nearest.min_filter, vk::Filter::NEAREST);
+        assert_eq!(nearest.mipmap_mode, None);
+        assert_ne!(nearest, SamplerInfo::default());
+
+        // NEAREST_MIPMAP_LINEAR
+        let mipmapped = SamplerInfo::from_gltf(&textures.next().unwrap().sampler());
+        assert_eq!(mipmapped.mag_filter, vk::Filter::LINEAR);
+        assert_eq!(mipmapped.min_filter, vk::Filter::NEAREST);
+        assert_eq!(mipmapped.mipmap_mode, Some(vk::SamplerMipmapMode::LINEAR));
+    }
+
+    #[test]
+    pub fn test_texture_color_space_override() {
+        // By default, colours are sRGB and data is linear..
+        assert_eq!(
+            get_texture_format(TextureRole::BaseColor, TextureColorSpace::Auto),
+            vk::Format::R8G8B8A8_SRGB
+        );
+        assert_eq!(
+            get_texture_format(TextureRole::Emissive, TextureColorSpace::Auto),
+            vk::Format::R8G8B8A8_SRGB
+        );
+        assert_eq!(
+            get_texture_format(TextureRole::Normal, TextureColorSpace::Auto),
+            vk::Format::R8G8B8A8_UNORM
+        );
+        assert_eq!(
+            get_texture_format(TextureRole::MetallicRoughness, TextureColorSpace::Auto),
+            vk::Format::R8G8B8A8_UNORM
+        );
+
+        // ..but forcing linear always selects a UNORM format, and forcing sRGB an SRGB one, whatever the role.
+        assert_eq!(
+            get_texture_format(TextureRole::BaseColor, TextureColorSpace::Linear),
+            vk::Format::R8G8B8A8_UNORM
+        );
+        assert_eq!(
+            get_texture_format(TextureRole::Occlusion, TextureColorSpace::Srgb),
+            vk::Format::R8G8B8A8_SRGB
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    pub fn test_samplers_are_shared() {
+        let vulkan_context = VulkanContext::testing().unwrap();
+        let clamped = SamplerInfo::new(vk::SamplerAddressMode::CLAMP_TO_EDGE, 1);
+        let a = Texture::new(
+            "Texture A",
+            &vulkan_context,
+            &EMPTY_KTX.to_vec(),
+            1,
+            1,
+            TEXTURE_FORMAT,
+            clamped,
+        )
+        .unwrap();
+        let b = Texture::new(
+            "Texture B",
+            &vulkan_context,
+            &EMPTY_KTX.to_vec(),
+            1,
+            1,
+            TEXTURE_FORMAT,
+            clamped,
+        )
+        .unwrap();
+
+        assert_eq!(a.sampler, b.sampler);
+        assert_ne!(a.image.handle, b.image.handle);
+        assert_eq!(vulkan_context.samplers.lock().unwrap().len(), 1);
+    }
+}
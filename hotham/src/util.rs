@@ -98,6 +98,17 @@ pub fn posef_to_isometry(pose: Posef) -> Isometry3<f32> {
     }
 }
 
+/// Convert a `nalgebra::Isometry3` into a `Posef` for OpenXR
+pub fn isometry_to_posef(isometry: Isometry3<f32>) -> Posef {
+    let position: mint::Vector3<f32> = isometry.translation.vector.into();
+    let orientation: mint::Quaternion<f32> = isometry.rotation.into();
+
+    Posef {
+        position: position.into(),
+        orientation: orientation.into(),
+    }
+}
+
 #[cfg(test)]
 use crate::buffer::Buffer;
 #[cfg(test)]
@@ -2,7 +2,7 @@ use ash::vk;
 use nalgebra::{Vector2, Vector3, Vector4};
 
 #[repr(C)]
-#[derive(Clone, Debug, Copy, PartialEq, Default)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Vertex {
     pub position: Vector3<f32>,
     pub normal: Vector3<f32>,
@@ -10,6 +10,22 @@ pub struct Vertex {
     pub texture_coords_1: Vector2<f32>,
     pub joint_indices: Vector4<f32>,
     pub joint_weights: Vector4<f32>,
+    /// Linear RGBA colour, multiplied with the material's base colour. Defaults to white.
+    pub color: Vector4<f32>,
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zeros(),
+            normal: Vector3::zeros(),
+            texture_coords_0: Vector2::zeros(),
+            texture_coords_1: Vector2::zeros(),
+            joint_indices: Vector4::zeros(),
+            joint_weights: Vector4::zeros(),
+            color: Vector4::repeat(1.),
+        }
+    }
 }
 
 impl Vertex {
@@ -28,6 +44,7 @@ impl Vertex {
             normal,
             joint_indices,
             joint_weights,
+            color: Vector4::repeat(1.),
         }
     }
 
@@ -89,6 +106,13 @@ impl Vertex {
             .offset(memoffset::offset_of!(Vertex, joint_weights) as _)
             .build();
 
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(6)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(memoffset::offset_of!(Vertex, color) as _)
+            .build();
+
         vec![
             position,
             normal,
@@ -96,6 +120,7 @@ impl Vertex {
             texture_coords_1,
             joint_indices,
             joint_weights,
+            color,
         ]
     }
 }